@@ -0,0 +1,94 @@
+#ifndef BGZF_RS_H
+#define BGZF_RS_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A file opened with `bgzf_rs_open`
+ *
+ * Fields description:
+ *
+ * reader: reader of the file,
+ * last_error: message of the last failure of a call with the handle, None
+ * once a call succeeds,
+ */
+typedef struct BgzfHandle BgzfHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the bgzf file at `path`, a NUL-terminated string, and returns a
+ * handle to read it, to free with `bgzf_rs_close`. Returns NULL when the
+ * file can not be opened, `bgzf_rs_last_error(NULL)` then giving why.
+ *
+ * # Safety
+ * `path` must be NULL or point to a NUL-terminated string.
+ */
+struct BgzfHandle *bgzf_rs_open(const char *path);
+
+/**
+ * Sets the position of `handle` to the uncompressed offset `pos`, returns 0,
+ * or -1 for a NULL handle. A position past the end of the data is allowed,
+ * reads from it returning 0, but those ending past the largest offset,
+ * 2^64 - 1, return -1.
+ *
+ * # Safety
+ * `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+ */
+int bgzf_rs_seek(struct BgzfHandle *handle, uint64_t pos);
+
+/**
+ * Reads up to `len` bytes from the position of `handle` into `buf` and
+ * moves the position past them. Returns the number of bytes read, 0 at the
+ * end of the data or when `len` is 0, or -1 on failure.
+ *
+ * # Safety
+ * `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed, and
+ * `buf` must point to `len` writable bytes unless `len` is 0, which need not
+ * be initialized.
+ */
+int64_t bgzf_rs_read(struct BgzfHandle *handle, uint8_t *buf, size_t len);
+
+/**
+ * Returns the uncompressed length of the file of `handle`, 0 for a NULL
+ * handle.
+ *
+ * # Safety
+ * `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+ */
+uint64_t bgzf_rs_length(struct BgzfHandle *handle);
+
+/**
+ * Returns the message of the last call with `handle` which failed, or of
+ * the last `bgzf_rs_open` of the thread which failed for a NULL handle. NULL
+ * when the last call succeeded. The string belongs to the library: it must
+ * not be freed, and is valid until the next call with the handle, or the
+ * next `bgzf_rs_open` of the thread for a NULL handle.
+ *
+ * # Safety
+ * `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+ */
+const char *bgzf_rs_last_error(const struct BgzfHandle *handle);
+
+/**
+ * Closes `handle` and frees it, with its last error. NULL is ignored.
+ *
+ * # Safety
+ * `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed, which
+ * must not be used after.
+ */
+void bgzf_rs_close(struct BgzfHandle *handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BGZF_RS_H */
@@ -0,0 +1,586 @@
+use crate::source::Source;
+use crate::{truncation_at, BgzfBlock};
+use std::error::Error;
+use std::fmt;
+
+/// How strictly a file is held to the BGZF spec, set with
+/// `BgzfReaderBuilder::parse_mode`, for the anomalies of `AnomalyKind`.
+/// Damaged files fail the same way in every mode, except truncated ones,
+/// which `Permissive` opens.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+  /// Every anomaly fails with a `BgzfError::Anomaly` error, for validators
+  Strict,
+  /// Anomalies are read through and collected, `BgzfReader::anomalies`
+  /// returns them
+  #[default]
+  Default,
+  /// Anomalies are read through without notice, and truncated files open
+  /// as with `allow_truncated`, to recover what can be read
+  Permissive,
+}
+
+/// Ways a file departs from the BGZF spec while still being readable
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnomalyKind {
+  /// The file does not end with the EOF marker
+  MissingEofMarker,
+  /// An empty block, ISIZE 0, is followed by more blocks, like the EOF
+  /// markers left in the middle of files joined with `cat`
+  EmptyBlock,
+  /// Data that is not a block follows the EOF marker
+  TrailingData,
+  /// The BC subfield is not the first subfield of the extra field
+  BcNotFirst,
+  /// The header has other gzip flags than FEXTRA set, such as FNAME or
+  /// FHCRC
+  HeaderFlags,
+  /// The header leaves no room for deflate data while the footer gives the
+  /// block data, skipped as an empty block. Only found in permissive mode,
+  /// the other modes fail with `BgzfError::InvalidBlock`, so only
+  /// `open_warnings` tells about it.
+  InvalidBlock,
+}
+
+/// Anomaly found in a file:
+///
+/// kind: what departs from the spec,
+/// offset: offset in the file of the block or data it is about, the length
+/// of the file for a missing EOF marker,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Anomaly {
+  pub kind: AnomalyKind,
+  pub offset: u64,
+}
+
+impl Error for Anomaly {}
+
+impl fmt::Display for Anomaly {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.kind {
+      AnomalyKind::MissingEofMarker => write!(f, "The file does not end with the EOF marker"),
+      AnomalyKind::EmptyBlock => write!(
+        f,
+        "The empty block at offset {} is followed by more blocks",
+        self.offset
+      ),
+      AnomalyKind::TrailingData => write!(
+        f,
+        "The data at offset {} after the EOF marker is not a block",
+        self.offset
+      ),
+      AnomalyKind::BcNotFirst => write!(
+        f,
+        "The BC subfield of the block at offset {} is not the first one",
+        self.offset
+      ),
+      AnomalyKind::HeaderFlags => write!(
+        f,
+        "The block at offset {} has other gzip flags than FEXTRA set",
+        self.offset
+      ),
+      AnomalyKind::InvalidBlock => write!(
+        f,
+        "The block at offset {} has no deflate data for the data of its footer, it was skipped",
+        self.offset
+      ),
+    }
+  }
+}
+
+/// Data blocks holding less uncompressed data than this, other than the
+/// last one, are worth a warning: bgzip fills blocks with 65280 bytes, and
+/// files of many small blocks are slow to index and compress badly
+pub(crate) const SMALL_BLOCK_LENGTH: u32 = 1024;
+
+/// Something imperfect about a file opening found without failing, returned
+/// by `BgzfReader::open_warnings`, with the offset in the file of the block
+/// or data it is about
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpenWarning {
+  /// The file does not end with the EOF marker, the offset is the length of
+  /// the file
+  MissingEofMarker { offset: u64 },
+  /// Data that is not a block follows the EOF marker
+  TrailingData { offset: u64 },
+  /// An empty block, skipped, is followed by more blocks
+  EmptyBlock { offset: u64 },
+  /// A data block other than the last one holds less than 1024 bytes of
+  /// uncompressed data
+  SmallBlock {
+    offset: u64,
+    uncompressed_length: u32,
+  },
+  /// The BC subfield is not the first subfield of the extra field
+  BcNotFirst { offset: u64 },
+  /// The header has other gzip flags than FEXTRA set
+  HeaderFlags { offset: u64 },
+  /// The header leaves no room for deflate data while the footer gives the
+  /// block data, skipped in permissive mode
+  InvalidBlock { offset: u64 },
+}
+
+impl OpenWarning {
+  /// Offset in the file of the block or data the warning is about
+  pub fn offset(&self) -> u64 {
+    match *self {
+      OpenWarning::MissingEofMarker { offset }
+      | OpenWarning::TrailingData { offset }
+      | OpenWarning::EmptyBlock { offset }
+      | OpenWarning::SmallBlock { offset, .. }
+      | OpenWarning::BcNotFirst { offset }
+      | OpenWarning::HeaderFlags { offset }
+      | OpenWarning::InvalidBlock { offset } => offset,
+    }
+  }
+}
+
+impl From<Anomaly> for OpenWarning {
+  fn from(anomaly: Anomaly) -> OpenWarning {
+    let offset = anomaly.offset;
+    match anomaly.kind {
+      AnomalyKind::MissingEofMarker => OpenWarning::MissingEofMarker { offset },
+      AnomalyKind::EmptyBlock => OpenWarning::EmptyBlock { offset },
+      AnomalyKind::TrailingData => OpenWarning::TrailingData { offset },
+      AnomalyKind::BcNotFirst => OpenWarning::BcNotFirst { offset },
+      AnomalyKind::HeaderFlags => OpenWarning::HeaderFlags { offset },
+      AnomalyKind::InvalidBlock => OpenWarning::InvalidBlock { offset },
+    }
+  }
+}
+
+impl fmt::Display for OpenWarning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let kind = match *self {
+      OpenWarning::SmallBlock {
+        offset,
+        uncompressed_length,
+      } => {
+        return write!(
+          f,
+          "The block at offset {} holds only {} bytes",
+          offset, uncompressed_length
+        )
+      }
+      OpenWarning::MissingEofMarker { .. } => AnomalyKind::MissingEofMarker,
+      OpenWarning::TrailingData { .. } => AnomalyKind::TrailingData,
+      OpenWarning::EmptyBlock { .. } => AnomalyKind::EmptyBlock,
+      OpenWarning::BcNotFirst { .. } => AnomalyKind::BcNotFirst,
+      OpenWarning::HeaderFlags { .. } => AnomalyKind::HeaderFlags,
+      OpenWarning::InvalidBlock { .. } => AnomalyKind::InvalidBlock,
+    };
+    Anomaly {
+      kind,
+      offset: self.offset(),
+    }
+    .fmt(f)
+  }
+}
+
+/// Returns the warnings about a file of the `anomalies` found and of its
+/// indexed `blocks`, in file order
+pub(crate) fn open_warnings(anomalies: &[Anomaly], blocks: &[BgzfBlock]) -> Vec<OpenWarning> {
+  let mut warnings: Vec<OpenWarning> = anomalies.iter().map(|&anomaly| anomaly.into()).collect();
+  let small_blocks = blocks
+    .iter()
+    .take(blocks.len().saturating_sub(1))
+    .filter(|block| block.input_length < SMALL_BLOCK_LENGTH)
+    .map(|block| OpenWarning::SmallBlock {
+      offset: block.block_offset,
+      uncompressed_length: block.input_length,
+    });
+  warnings.extend(small_blocks);
+  warnings.sort_by_key(OpenWarning::offset);
+  warnings
+}
+
+/// Finds the anomalies of the blocks of a file read one after the other
+///
+/// Fields description:
+///
+/// empty_block: offset of the last block read when it is empty,
+/// found: anomalies found so far, in file order,
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AnomalyScan {
+  empty_block: Option<u64>,
+  pub(crate) found: Vec<Anomaly>,
+}
+
+impl AnomalyScan {
+  /// Records the anomalies of `block`, which follows the blocks scanned so
+  /// far, its header having the anomaly `header` if any
+  pub(crate) fn block(&mut self, block: &BgzfBlock, header: Option<AnomalyKind>) {
+    if let Some(offset) = self.empty_block.take() {
+      self.found.push(Anomaly {
+        kind: AnomalyKind::EmptyBlock,
+        offset,
+      });
+    }
+    if let Some(kind) = header {
+      self.found.push(Anomaly {
+        kind,
+        offset: block.block_offset,
+      });
+    }
+    //a skipped block is no empty one
+    if block.input_length == 0 && header != Some(AnomalyKind::InvalidBlock) {
+      self.empty_block = Some(block.block_offset);
+    }
+  }
+
+  /// Records trailing data when the blocks of `file` stop being readable at
+  /// `scan_end`, after an EOF marker and before the end of the file
+  pub(crate) fn end(&mut self, file: &Source, scan_end: u64) {
+    let before_file_end = file.len().is_ok_and(|length| length > scan_end);
+    if before_file_end && truncation_at(file, scan_end).is_none() {
+      self.found.push(Anomaly {
+        kind: AnomalyKind::TrailingData,
+        offset: scan_end,
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::executor::Executor;
+  use crate::fixtures::{
+    make_bgzf, make_truncated, make_with_anomaly, make_with_header_fields, write_temp,
+  };
+  use crate::writer::EOF_MARKER;
+  use crate::{scan_blocks_parallel, BgzfError, BgzfReader, BgzfReaderBuilder, Truncated};
+  use std::fs::{self, File};
+
+  const KINDS: [AnomalyKind; 5] = [
+    AnomalyKind::MissingEofMarker,
+    AnomalyKind::EmptyBlock,
+    AnomalyKind::TrailingData,
+    AnomalyKind::BcNotFirst,
+    AnomalyKind::HeaderFlags,
+  ];
+
+  fn data() -> Vec<u8> {
+    (0..1000u32).map(|n| (n % 251) as u8).collect()
+  }
+
+  /// The anomalies the fixture of `kind` has, where they are
+  fn expected(kind: AnomalyKind, bgzf: &[u8]) -> Vec<Anomaly> {
+    let data = data();
+    let good = make_bgzf(&data, 100);
+    let offset = match kind {
+      AnomalyKind::MissingEofMarker => bgzf.len(),
+      AnomalyKind::EmptyBlock => make_bgzf(&data[..500], 100).len() - EOF_MARKER.len(),
+      AnomalyKind::TrailingData => good.len(),
+      //the second block
+      _ => usize::from(u16::from_le_bytes([good[16], good[17]])) + 1,
+    };
+    let mut anomalies = vec![Anomaly {
+      kind,
+      offset: offset as u64,
+    }];
+    //with data after it, the EOF marker is not at the end either
+    if kind == AnomalyKind::TrailingData {
+      anomalies.push(Anomaly {
+        kind: AnomalyKind::MissingEofMarker,
+        offset: bgzf.len() as u64,
+      });
+    }
+    anomalies
+  }
+
+  /// Opens the fixture of `kind` with `builder` and reads it whole
+  fn read_fixture(
+    kind: AnomalyKind,
+    builder: BgzfReaderBuilder,
+  ) -> Result<Vec<Anomaly>, BgzfError> {
+    let bgzf = make_with_anomaly(&data(), 100, kind);
+    let path = write_temp(&format!("anomaly_{:?}", kind), &bgzf).unwrap();
+    let read = builder.open(path.clone()).and_then(|reader| {
+      let mut all = vec![0; 2000];
+      let mut length = 0;
+      loop {
+        match reader.read(&mut all, length, 2000 - length)? {
+          0 => break,
+          read => length += read,
+        }
+      }
+      assert_eq!(data(), &all[..length]);
+      Ok(reader.anomalies())
+    });
+    fs::remove_file(path).unwrap();
+    read
+  }
+
+  #[test]
+  fn test_default_mode_collects_anomalies() {
+    for &kind in &KINDS {
+      let bgzf = make_with_anomaly(&data(), 100, kind);
+      let anomalies = read_fixture(kind, BgzfReader::builder()).unwrap();
+      assert_eq!(expected(kind, &bgzf), anomalies, "{:?}", kind);
+      let lazy = read_fixture(kind, BgzfReader::builder().lazy(true)).unwrap();
+      assert_eq!(anomalies, lazy);
+      let background = read_fixture(kind, BgzfReader::builder().background_indexing(true));
+      assert_eq!(anomalies, background.unwrap());
+
+      //the scan of large files, on several threads
+      let path = write_temp("anomaly_parallel", &bgzf).unwrap();
+      let (_blocks, input_length, _truncation, anomaly_scan) = scan_blocks_parallel(
+        &Source::File(File::open(&path).unwrap()),
+        3,
+        ParseMode::Default,
+        &Executor::default(),
+      )
+      .unwrap();
+      assert_eq!(1000, input_length);
+      let mut found = anomaly_scan.found;
+      if !bgzf.ends_with(&EOF_MARKER) {
+        found.push(expected(AnomalyKind::MissingEofMarker, &bgzf)[0]);
+      }
+      assert_eq!(anomalies, found);
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_strict_mode_fails() {
+    for &kind in &KINDS {
+      let bgzf = make_with_anomaly(&data(), 100, kind);
+      let strict = || BgzfReader::builder().parse_mode(ParseMode::Strict);
+      match read_fixture(kind, strict()) {
+        Err(BgzfError::Anomaly(anomaly)) => assert_eq!(expected(kind, &bgzf)[0], anomaly),
+        other => panic!("{:?} gave {:?}", kind, other),
+      }
+      for builder in [strict().lazy(true), strict().background_indexing(true)] {
+        match read_fixture(kind, builder) {
+          //lazily, a missing EOF marker is known before the rest
+          Err(BgzfError::Anomaly(anomaly)) => assert!(expected(kind, &bgzf).contains(&anomaly)),
+          other => panic!("{:?} gave {:?}", kind, other),
+        }
+      }
+    }
+    let error = BgzfError::from(expected(AnomalyKind::TrailingData, &[])[0]);
+    assert_eq!(
+      format!(
+        "The data at offset {} after the EOF marker is not a block",
+        make_bgzf(&data(), 100).len()
+      ),
+      error.to_string()
+    );
+  }
+
+  #[test]
+  fn test_permissive_mode_reads_through() {
+    for &kind in &KINDS {
+      let builder = BgzfReader::builder().parse_mode(ParseMode::Permissive);
+      assert_eq!(Vec::<Anomaly>::new(), read_fixture(kind, builder).unwrap());
+    }
+    //and truncated files
+    let path = write_temp("anomaly_truncated", &make_truncated(&data(), 100, 10)).unwrap();
+    assert!(matches!(
+      BgzfReader::new(path.clone()).err().unwrap(),
+      BgzfError::Truncated(_)
+    ));
+    let reader = BgzfReader::builder()
+      .parse_mode(ParseMode::Permissive)
+      .open(path.clone())
+      .unwrap();
+    assert!(reader.is_truncated());
+    assert_eq!(900, reader.total_uncompressed_length());
+    assert!(reader.anomalies().is_empty());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_open_warnings() {
+    let data: Vec<u8> = (0..8000u32).map(|n| (n % 251) as u8).collect();
+    //a small block, ending the first of two files joined with cat, then
+    //blocks naming the file and data after the last EOF marker
+    let mut bgzf = make_bgzf(&data[..4500], 2000);
+    let marker = bgzf.len() - EOF_MARKER.len();
+    bgzf.extend(make_with_header_fields(&data[4500..], 2000, 8));
+    let joined = bgzf.len();
+    bgzf.extend_from_slice(b"trailing data\n");
+    let path = write_temp("open_warnings", &bgzf).unwrap();
+
+    for mode in [ParseMode::Default, ParseMode::Permissive] {
+      let reader = BgzfReader::builder()
+        .parse_mode(mode)
+        .open(path.clone())
+        .unwrap();
+      let offsets: Vec<u64> = reader
+        .block_offsets()
+        .map(|block| block.compressed_offset)
+        .collect();
+      let expected = [
+        OpenWarning::SmallBlock {
+          offset: offsets[2],
+          uncompressed_length: 500,
+        },
+        OpenWarning::EmptyBlock {
+          offset: marker as u64,
+        },
+        OpenWarning::HeaderFlags { offset: offsets[3] },
+        OpenWarning::HeaderFlags { offset: offsets[4] },
+        OpenWarning::TrailingData {
+          offset: joined as u64,
+        },
+        OpenWarning::MissingEofMarker {
+          offset: bgzf.len() as u64,
+        },
+      ];
+      assert_eq!(&expected, reader.open_warnings());
+      //with an index only the blocks it gives are known
+      let indexed = BgzfReader::open_with_index(path.clone(), &reader.index().unwrap()).unwrap();
+      assert_eq!(
+        &[expected[0], expected[5]],
+        indexed.open_warnings(),
+        "{:?}",
+        mode
+      );
+    }
+    let lazy = BgzfReader::builder().lazy(true).open(path.clone()).unwrap();
+    assert_eq!(
+      &[OpenWarning::MissingEofMarker {
+        offset: bgzf.len() as u64
+      }],
+      lazy.open_warnings()
+    );
+    //strict mode fails as it did
+    let strict = BgzfReader::builder().parse_mode(ParseMode::Strict);
+    match strict.open(path.clone()) {
+      Err(BgzfError::Anomaly(anomaly)) => assert_eq!(
+        Anomaly {
+          kind: AnomalyKind::EmptyBlock,
+          offset: marker as u64
+        },
+        anomaly
+      ),
+      other => panic!("{:?}", other.map(|reader| reader.open_warnings().to_vec())),
+    }
+    fs::remove_file(path).unwrap();
+
+    let warning = OpenWarning::SmallBlock {
+      offset: 6,
+      uncompressed_length: 500,
+    };
+    assert_eq!(
+      "The block at offset 6 holds only 500 bytes",
+      warning.to_string()
+    );
+    let warning = OpenWarning::TrailingData { offset: 6 };
+    assert_eq!(
+      "The data at offset 6 after the EOF marker is not a block",
+      warning.to_string()
+    );
+  }
+
+  #[test]
+  fn test_invalid_block() {
+    let kind = AnomalyKind::InvalidBlock;
+    let bgzf = make_with_anomaly(&data(), 100, kind);
+    let offset = expected(kind, &bgzf)[0].offset;
+    let path = write_temp("anomaly_invalid_block", &bgzf).unwrap();
+    //the blocks of 100 bytes are small ones
+    let warnings = |reader: &BgzfReader| {
+      reader
+        .open_warnings()
+        .iter()
+        .copied()
+        .filter(|warning| !matches!(warning, OpenWarning::SmallBlock { .. }))
+        .collect::<Vec<_>>()
+    };
+    let invalid = |result: Result<Vec<Anomaly>, BgzfError>| match result {
+      Err(BgzfError::InvalidBlock {
+        offset: at,
+        data_length: 0,
+        uncompressed_length: 10,
+      }) => at == offset,
+      _ => false,
+    };
+    //refused in the other modes, at open or once the reads get there
+    for mode in [ParseMode::Default, ParseMode::Strict] {
+      let builder = || BgzfReader::builder().parse_mode(mode);
+      assert!(invalid(read_fixture(kind, builder())));
+      assert!(invalid(read_fixture(kind, builder().lazy(true))));
+      assert!(invalid(read_fixture(
+        kind,
+        builder().background_indexing(true)
+      )));
+    }
+    assert_eq!(
+      format!(
+        "The block at offset {} has 0 bytes of deflate data for 10 bytes of data",
+        offset
+      ),
+      BgzfReader::new(path.clone()).err().unwrap().to_string()
+    );
+    //skipped with a warning in permissive mode
+    let permissive = || BgzfReader::builder().parse_mode(ParseMode::Permissive);
+    for builder in [
+      permissive(),
+      permissive().lazy(true),
+      permissive().background_indexing(true),
+    ] {
+      assert_eq!(Vec::<Anomaly>::new(), read_fixture(kind, builder).unwrap());
+    }
+    let reader = permissive().open(path.clone()).unwrap();
+    assert!(!reader.is_truncated());
+    assert_eq!(
+      vec![OpenWarning::InvalidBlock { offset }],
+      warnings(&reader)
+    );
+    assert_eq!(
+      format!(
+        "The block at offset {} has no deflate data for the data of its footer, it was skipped",
+        offset
+      ),
+      warnings(&reader)[0].to_string()
+    );
+    let file = Source::File(File::open(&path).unwrap());
+    let (_blocks, input_length, truncation, anomaly_scan) =
+      scan_blocks_parallel(&file, 3, ParseMode::Permissive, &Executor::default()).unwrap();
+    assert_eq!((1000, None), (input_length, truncation));
+    assert_eq!(vec![Anomaly { kind, offset }], anomaly_scan.found);
+    let (_blocks, input_length, truncation, _anomaly_scan) =
+      scan_blocks_parallel(&file, 3, ParseMode::Default, &Executor::default()).unwrap();
+    assert_eq!(
+      (
+        100,
+        Some(Truncated {
+          block_offset: offset
+        })
+      ),
+      (input_length, truncation)
+    );
+    fs::remove_file(path).unwrap();
+
+    //an empty block without deflate data is one more empty block, followed
+    //by the rest
+    let mut bgzf = bgzf;
+    bgzf[offset as usize + 22] = 0;
+    let path = write_temp("anomaly_empty_without_data", &bgzf).unwrap();
+    for mode in [ParseMode::Default, ParseMode::Permissive] {
+      let reader = BgzfReader::builder()
+        .parse_mode(mode)
+        .open(path.clone())
+        .unwrap();
+      assert_eq!(1000, reader.total_uncompressed_length());
+      assert_eq!(vec![OpenWarning::EmptyBlock { offset }], warnings(&reader));
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_well_formed_file_has_no_anomalies() {
+    let path = write_temp("anomaly_none", &make_bgzf(&data(), 100)).unwrap();
+    for mode in [ParseMode::Strict, ParseMode::Default, ParseMode::Permissive] {
+      let reader = BgzfReader::builder()
+        .parse_mode(mode)
+        .open(path.clone())
+        .unwrap();
+      assert!(reader.anomalies().is_empty());
+    }
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,213 @@
+use crate::block_cache::BlockCache;
+use crate::decompressor_pool::DecompressorPool;
+use crate::{inflate_block, BgzfBlock, BgzfError};
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+use std::sync::MutexGuard;
+use std::task::{ready, Context, Poll};
+
+/// What fetching a block gives back: its compressed bytes, to decompress
+/// inline, or its decompressed bytes when it was decompressed elsewhere
+pub(crate) enum Fetched {
+  Compressed(Vec<u8>),
+  //only the blocks of the tokio reader are decompressed on other threads
+  #[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+  Decompressed(Vec<u8>),
+}
+
+/// Where the blocks read by an `AsyncCore` come from, which is all that
+/// differs between the async readers: a sync reader whose blocks are read on
+/// tokio's blocking thread pool, or an async source of compressed data
+pub(crate) trait BlockFetcher {
+  /// Returns the block holding `pos`, None at the end of the data, or the
+  /// error reading further would give, like for a truncated file
+  fn block_at(&self, pos: u64) -> Result<Option<BgzfBlock>, BgzfError>;
+
+  /// Returns the uncompressed length of the data
+  fn length(&self) -> u64;
+
+  /// Returns the cache of decompressed blocks
+  fn cache(&self) -> MutexGuard<'_, BlockCache>;
+
+  /// Returns all the data when it was loaded in memory
+  fn loaded(&self) -> Option<&[u8]> {
+    None
+  }
+
+  /// Fetches `block`, which the first call starts and the following ones
+  /// poll, all of them for the same block until it is ready
+  fn poll_fetch(
+    &mut self,
+    cx: &mut Context<'_>,
+    block: &BgzfBlock,
+  ) -> Poll<Result<Fetched, BgzfError>>;
+
+  /// Decompresses `block_data`, the whole compressed `block`
+  fn inflate(&self, block: &BgzfBlock, block_data: &[u8]) -> Result<Vec<u8>, BgzfError>;
+
+  /// Adds to `error` what is known of the block it happened in
+  fn locate(&self, error: BgzfError) -> BgzfError {
+    error
+  }
+}
+
+/// The polling of an async bgzf reader, apart from the traits of any
+/// runtime: reads copy from the cached blocks, and fetch the others a block
+/// at a time through the `BlockFetcher`
+///
+/// Fields description:
+///
+/// fetcher: where the blocks come from,
+/// pos: uncompressed offset the next read starts at,
+/// fetching: block being fetched, if any,
+/// seeking: position a seek started with `start_seek` moves to,
+pub(crate) struct AsyncCore<F> {
+  pub(crate) fetcher: F,
+  pos: u64,
+  fetching: Option<BgzfBlock>,
+  seeking: Option<u64>,
+}
+
+impl<F: BlockFetcher> AsyncCore<F> {
+  pub(crate) fn new(fetcher: F) -> AsyncCore<F> {
+    AsyncCore {
+      fetcher,
+      pos: 0,
+      fetching: None,
+      seeking: None,
+    }
+  }
+
+  /// Returns the position the next read starts at
+  pub(crate) fn position(&self) -> u64 {
+    self.pos
+  }
+
+  /// Reads from the position to `buf`, at most up to the end of a block, and
+  /// returns the number of bytes read, 0 at the end of the data
+  pub(crate) fn poll_read(
+    &mut self,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    self
+      .poll_fill(cx, buf)
+      .map_err(|error| io::Error::from(self.fetcher.locate(error)))
+  }
+
+  /// Starts moving the position within the uncompressed data. Seeking past
+  /// the end is allowed, reads from there return no bytes, and seeking
+  /// before the start fails with an `InvalidInput` error.
+  pub(crate) fn start_seek(&mut self, position: SeekFrom) -> io::Result<()> {
+    let (base, offset) = match position {
+      SeekFrom::Start(offset) => (offset, 0),
+      SeekFrom::End(offset) => (self.fetcher.length(), offset),
+      SeekFrom::Current(offset) => (self.pos, offset),
+    };
+    let target = base.checked_add_signed(offset).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Seek to a negative or overflowing position",
+      )
+    })?;
+    self.seeking = Some(target);
+    Ok(())
+  }
+
+  /// Completes the seek started last, if any, and returns the position
+  pub(crate) fn complete_seek(&mut self) -> u64 {
+    //a block still being fetched is kept, the next read adds it to the cache
+    if let Some(target) = self.seeking.take() {
+      self.pos = target;
+    }
+    self.pos
+  }
+
+  /// Copies from the position to `buf` when `block` is in the cache, or all
+  /// the data is loaded, and returns the number of bytes copied then
+  fn copy_cached(&mut self, block: &BgzfBlock, buf: &mut [u8]) -> Option<usize> {
+    if let Some(loaded) = self.fetcher.loaded() {
+      return Some(copy_from(&mut self.pos, 0, loaded, buf));
+    }
+    let mut cache = self.fetcher.cache();
+    let cached = cache.get(block.input_offset)?;
+    Some(copy_from(&mut self.pos, block.input_offset, cached, buf))
+  }
+
+  /// Adds the fetched `block` to the cache, decompressing it first when it
+  /// was only read, and copies from it to `buf` when it holds the position,
+  /// which a seek made meanwhile can have moved elsewhere. Returns the number
+  /// of bytes copied then.
+  fn store(
+    &mut self,
+    block: &BgzfBlock,
+    fetched: Fetched,
+    buf: &mut [u8],
+  ) -> Result<Option<usize>, BgzfError> {
+    let uncompressed = match fetched {
+      Fetched::Decompressed(uncompressed) => uncompressed,
+      Fetched::Compressed(block_data) => self.fetcher.inflate(block, &block_data)?,
+    };
+    let mut cache = self.fetcher.cache();
+    let uncompressed = cache.insert(block.input_offset, uncompressed);
+    let holds_pos =
+      (block.input_offset..block.input_offset + u64::from(block.input_length)).contains(&self.pos);
+    let pos = &mut self.pos;
+    Ok(holds_pos.then(|| copy_from(pos, block.input_offset, uncompressed, buf)))
+  }
+
+  /// `poll_read` with the errors of the sync reader
+  fn poll_fill(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, BgzfError>> {
+    if buf.is_empty() {
+      return Poll::Ready(Ok(0));
+    }
+    loop {
+      if let Some(block) = self.fetching {
+        let fetched = ready!(self.fetcher.poll_fetch(cx, &block));
+        self.fetching = None;
+        if let Some(read) = self.store(&block, fetched?, buf)? {
+          return Poll::Ready(Ok(read));
+        }
+      }
+      let block = match self.fetcher.block_at(self.pos)? {
+        Some(block) => block,
+        None => return Poll::Ready(Ok(0)),
+      };
+      if let Some(read) = self.copy_cached(&block, buf) {
+        return Poll::Ready(Ok(read));
+      }
+      self.fetching = Some(block);
+    }
+  }
+}
+
+/// Copies the bytes of `data`, which starts at the uncompressed offset
+/// `start`, from `pos` to `buf`, as many as fit, moves `pos` past them and
+/// returns their number
+fn copy_from(pos: &mut u64, start: u64, data: &[u8], buf: &mut [u8]) -> usize {
+  //the position is within the data, the distance fits in a usize
+  let copy_start = (*pos - start) as usize;
+  let length = min(data.len() - copy_start, buf.len());
+  buf[..length].copy_from_slice(&data[copy_start..copy_start + length]);
+  *pos += length as u64;
+  length
+}
+
+/// Decompresses `block_data`, the whole compressed `block`, with a
+/// decompressor of `decompressors`, the `inflate` of the fetchers
+pub(crate) fn inflate_whole(
+  decompressors: &DecompressorPool,
+  verify_crc: bool,
+  block: &BgzfBlock,
+  block_data: &[u8],
+) -> Result<Vec<u8>, BgzfError> {
+  let mut uncompressed = vec![0; block.input_length as usize];
+  inflate_block(
+    &mut decompressors.acquire(),
+    block,
+    verify_crc,
+    block_data,
+    &mut uncompressed,
+  )?;
+  Ok(uncompressed)
+}
@@ -0,0 +1,566 @@
+use crate::async_core::{inflate_whole, AsyncCore, BlockFetcher, Fetched};
+use crate::block_cache::BlockCache;
+use crate::shared_state::{self, lock};
+use crate::{BgzfBlock, BgzfError, BgzfReader, BlockContext, BlockOperation};
+use bytes::Bytes;
+use futures_core::Stream;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, MutexGuard};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::task::JoinHandle;
+
+/// Fetches the blocks of a sync reader on tokio's blocking thread pool
+///
+/// Fields description:
+///
+/// reader: the reader of the file, whose index and block cache are used,
+/// spawn_blocking: true to decompress blocks on the blocking thread pool
+/// too, false to decompress them inline,
+/// fetching: block being read on the blocking thread pool, if any,
+struct ReaderFetcher {
+  reader: Arc<BgzfReader>,
+  spawn_blocking: bool,
+  fetching: Option<JoinHandle<Result<Fetched, BgzfError>>>,
+}
+
+impl ReaderFetcher {
+  /// Starts reading `block` on the blocking thread pool
+  fn spawn(&self, block: BgzfBlock) -> JoinHandle<Result<Fetched, BgzfError>> {
+    let reader = Arc::clone(&self.reader);
+    let spawn_blocking = self.spawn_blocking;
+    tokio::task::spawn_blocking(move || {
+      if spawn_blocking {
+        return reader.decompress_block(&block).map(Fetched::Decompressed);
+      }
+      let mut block_data = vec![0; block.block_size as usize];
+      reader
+        .block_source()
+        .read_exact_at(block.block_offset, &mut block_data)
+        .map_err(|source| BgzfError::ReadFailed {
+          block: BlockContext::new(&block, BlockOperation::Reading),
+          source,
+        })?;
+      if let Some(cache) = &reader.compressed_cache {
+        lock(cache).insert(block.block_offset, block_data.clone());
+      }
+      Ok(Fetched::Compressed(block_data))
+    })
+  }
+}
+
+impl BlockFetcher for ReaderFetcher {
+  fn block_at(&self, pos: u64) -> Result<Option<BgzfBlock>, BgzfError> {
+    match self.reader.block_containing(pos) {
+      Some((index, _info)) => Ok(Some(shared_state::read(&self.reader.blocks)[index])),
+      None => {
+        self.reader.check_truncation()?;
+        Ok(None)
+      }
+    }
+  }
+
+  fn length(&self) -> u64 {
+    self.reader.total_uncompressed_length()
+  }
+
+  fn cache(&self) -> MutexGuard<'_, BlockCache> {
+    lock(&self.reader.cache)
+  }
+
+  fn loaded(&self) -> Option<&[u8]> {
+    self.reader.loaded.get().map(Vec::as_slice)
+  }
+
+  /// A block in the compressed cache is ready at once
+  fn poll_fetch(
+    &mut self,
+    cx: &mut Context<'_>,
+    block: &BgzfBlock,
+  ) -> Poll<Result<Fetched, BgzfError>> {
+    let fetching = match self.fetching.take() {
+      Some(fetching) => fetching,
+      None => {
+        if !self.spawn_blocking {
+          let compressed = self
+            .reader
+            .compressed_cache
+            .as_ref()
+            .and_then(|cache| lock(cache).get(block.block_offset).map(<[u8]>::to_vec));
+          if let Some(compressed) = compressed {
+            return Poll::Ready(Ok(Fetched::Compressed(compressed)));
+          }
+        }
+        self.spawn(*block)
+      }
+    };
+    let fetching = self.fetching.insert(fetching);
+    let fetched = ready!(Pin::new(fetching).poll(cx));
+    self.fetching = None;
+    Poll::Ready(fetched.map_err(io::Error::other)?)
+  }
+
+  fn inflate(&self, block: &BgzfBlock, block_data: &[u8]) -> Result<Vec<u8>, BgzfError> {
+    inflate_whole(
+      &self.reader.decompressors,
+      self.reader.verify_crc,
+      block,
+      block_data,
+    )
+  }
+
+  fn locate(&self, error: BgzfError) -> BgzfError {
+    self.reader.record_gap(self.reader.locate_block(error))
+  }
+}
+
+/// Struct to read bgzf data as a tokio `AsyncRead` and `AsyncSeek`, and
+/// with the `futures-io` feature as a `futures::io::AsyncRead` and
+/// `AsyncSeek` too, which still need a tokio runtime
+///
+/// Fields description:
+///
+/// core: the polling shared with the other async readers, fetching blocks
+/// of the sync reader on the blocking thread pool,
+pub struct AsyncBgzfReader {
+  core: AsyncCore<ReaderFetcher>,
+}
+
+/// Below are the steps to use the async bgzf Reader,
+/// open the file with `AsyncBgzfReader::open` (or
+/// `BgzfReader::builder().open_async` for other options), which indexes it
+/// on tokio's blocking thread pool, then read and seek with the
+/// `AsyncReadExt` and `AsyncSeekExt` methods.
+///
+/// Reads go through the block cache of the sync reader: a block already in
+/// it is copied inline, the others are read from the file on the blocking
+/// thread pool, a block at a time, and decompressed inline in `poll_read`,
+/// at most 64 KiB, unless the builder asked for `spawn_blocking`. The
+/// decompressed block is then added to the cache, so the following reads
+/// within it are copies, and `cache_bytes` keeps more blocks around as it
+/// does for the sync reader.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::AsyncBgzfReader;
+/// use std::io::SeekFrom;
+/// use tokio::io::{AsyncReadExt, AsyncSeekExt};
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// let word = runtime.block_on(async {
+///   let mut reader = AsyncBgzfReader::open(String::from("bgzf_test.bgz")).await.unwrap();
+///   reader.seek(SeekFrom::Start(8)).await.unwrap();
+///   let mut word = [0; 4];
+///   reader.read_exact(&mut word).await.unwrap();
+///   word
+/// });
+/// assert_eq!(b"just", &word);
+/// ```
+impl AsyncBgzfReader {
+  /// Opens the bgzf file at `file_path` with the default options, see
+  /// `BgzfReaderBuilder::open_async`
+  pub async fn open(file_path: String) -> Result<AsyncBgzfReader, BgzfError> {
+    BgzfReader::builder().open_async(file_path).await
+  }
+
+  /// Returns an async reader of `reader`, from the start of the data, which
+  /// decompresses blocks inline
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{AsyncBgzfReader, BgzfReader};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let reader = AsyncBgzfReader::new(reader);
+  /// assert_eq!(280, reader.get_ref().total_uncompressed_length());
+  /// ```
+  pub fn new(reader: BgzfReader) -> AsyncBgzfReader {
+    AsyncBgzfReader::from_parts(reader, false)
+  }
+
+  pub(crate) fn from_parts(reader: BgzfReader, spawn_blocking: bool) -> AsyncBgzfReader {
+    AsyncBgzfReader {
+      core: AsyncCore::new(ReaderFetcher {
+        reader: Arc::new(reader),
+        spawn_blocking,
+        fetching: None,
+      }),
+    }
+  }
+
+  /// Returns a reference to the sync reader, for its index and cache stats.
+  /// Its own position is not the one of the async reader.
+  pub fn get_ref(&self) -> &BgzfReader {
+    &self.core.fetcher.reader
+  }
+
+  /// Returns the position the next read starts at
+  pub fn position(&self) -> u64 {
+    self.core.position()
+  }
+
+  /// Returns a stream of the data from the position, in chunks of
+  /// `chunk_size` bytes (at least 1) but the last, shorter one. Blocks are
+  /// read and decompressed as the stream is polled, no further than the
+  /// chunk being filled, so a slow consumer holds back the reading, and
+  /// dropping the stream drops the reader with it. The chunks are not
+  /// aligned on blocks. The stream can be the body of an HTTP response, like
+  /// with `axum::body::Body::from_stream`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::AsyncBgzfReader;
+  /// use futures::StreamExt;
+  ///
+  /// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  /// let chunks = runtime.block_on(async {
+  ///   let reader = AsyncBgzfReader::open(String::from("bgzf_test.bgz")).await.unwrap();
+  ///   let stream = reader.into_chunk_stream(100);
+  ///   stream.map(|chunk| chunk.unwrap().len()).collect::<Vec<_>>().await
+  /// });
+  /// assert_eq!(vec![100, 100, 80], chunks);
+  /// ```
+  pub fn into_chunk_stream(self, chunk_size: usize) -> impl Stream<Item = io::Result<Bytes>> {
+    ChunkStream {
+      core: self.core,
+      chunk_size: chunk_size.max(1),
+      chunk: Vec::new(),
+      filled: 0,
+      done: false,
+    }
+  }
+}
+
+/// The stream of `AsyncBgzfReader::into_chunk_stream`
+///
+/// Fields description:
+///
+/// core: the polling of the async reader the stream was made of,
+/// chunk_size: length of the chunks but the last,
+/// chunk: chunk being filled, allocated when the filling starts,
+/// filled: number of bytes of `chunk` filled,
+/// done: true once the end of the data or an error was reached,
+struct ChunkStream {
+  core: AsyncCore<ReaderFetcher>,
+  chunk_size: usize,
+  chunk: Vec<u8>,
+  filled: usize,
+  done: bool,
+}
+
+impl ChunkStream {
+  /// Returns the bytes filled, leaving an empty chunk to fill
+  fn take_chunk(&mut self) -> Bytes {
+    let mut chunk = std::mem::take(&mut self.chunk);
+    chunk.truncate(self.filled);
+    self.filled = 0;
+    Bytes::from(chunk)
+  }
+}
+
+/// Fills a chunk a read at a time, keeping what was filled when a block is
+/// not ready yet. After an error the stream ends.
+impl Stream for ChunkStream {
+  type Item = io::Result<Bytes>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = &mut *self;
+    while !this.done && this.filled < this.chunk_size {
+      if this.chunk.is_empty() {
+        this.chunk = vec![0; this.chunk_size];
+      }
+      match ready!(this.core.poll_read(cx, &mut this.chunk[this.filled..])) {
+        Ok(0) => this.done = true,
+        Ok(read) => this.filled += read,
+        Err(error) => {
+          this.done = true;
+          this.chunk = Vec::new();
+          this.filled = 0;
+          return Poll::Ready(Some(Err(error)));
+        }
+      }
+    }
+    match this.filled {
+      0 => Poll::Ready(None),
+      _ => Poll::Ready(Some(Ok(this.take_chunk()))),
+    }
+  }
+}
+
+impl AsyncRead for AsyncBgzfReader {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let read = ready!(self.core.poll_read(cx, buf.initialize_unfilled()))?;
+    buf.advance(read);
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// Seeks within the uncompressed data. Seeking past the end is allowed,
+/// reads from there return no bytes, and seeking before the start fails
+/// with an `InvalidInput` error. Seeking from the end indexes the whole file
+/// when opened lazily.
+impl AsyncSeek for AsyncBgzfReader {
+  fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+    self.core.start_seek(position)
+  }
+
+  fn poll_complete(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+    Poll::Ready(Ok(self.core.complete_seek()))
+  }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for AsyncBgzfReader {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    self.core.poll_read(cx, buf)
+  }
+}
+
+/// Seeks like the tokio `AsyncSeek`
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncSeek for AsyncBgzfReader {
+  fn poll_seek(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    position: SeekFrom,
+  ) -> Poll<io::Result<u64>> {
+    self.core.start_seek(position)?;
+    Poll::Ready(Ok(self.core.complete_seek()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_truncated, write_temp};
+  use std::fs;
+  use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+  fn test_content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..6000 {
+      content.extend_from_slice(format!("{}\tchr{}\tasync read\n", i, i % 23).as_bytes());
+    }
+    content
+  }
+
+  fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+      .build()
+      .unwrap()
+      .block_on(future)
+  }
+
+  #[test]
+  fn test_seek_and_read_across_blocks() {
+    let content = test_content();
+    let path = write_temp("async_read", &make_bgzf(&content, 1000)).unwrap();
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().spawn_blocking(true),
+      BgzfReader::builder().cache_bytes(1 << 20),
+      BgzfReader::builder().compressed_cache_bytes(1 << 20),
+      BgzfReader::builder().lazy(true),
+    ];
+    for builder in builders {
+      block_on(async {
+        let mut reader = builder.open_async(path.clone()).await.unwrap();
+        //a read spanning three blocks
+        assert_eq!(950, reader.seek(SeekFrom::Start(950)).await.unwrap());
+        let mut buf = vec![0; 2100];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&content[950..3050], &buf[..]);
+        assert_eq!(3050, reader.position());
+
+        assert_eq!(3000, reader.seek(SeekFrom::Current(-50)).await.unwrap());
+        let mut buf = vec![0; 10];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&content[3000..3010], &buf[..]);
+
+        let end = content.len() as u64;
+        assert_eq!(end - 5, reader.seek(SeekFrom::End(-5)).await.unwrap());
+        let mut tail = Vec::new();
+        assert_eq!(5, reader.read_to_end(&mut tail).await.unwrap());
+        assert_eq!(&content[content.len() - 5..], &tail[..]);
+        assert_eq!(0, reader.read(&mut buf).await.unwrap());
+
+        //reads past the end return no bytes, seeks before the start fail
+        reader.seek(SeekFrom::Start(end + 100)).await.unwrap();
+        assert_eq!(0, reader.read(&mut buf).await.unwrap());
+        let error = reader.seek(SeekFrom::Current(-(end as i64) - 200)).await;
+        assert_eq!(io::ErrorKind::InvalidInput, error.unwrap_err().kind());
+        assert_eq!(end + 100, reader.position());
+
+        reader.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).await.unwrap();
+        assert_eq!(content, all);
+      });
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_reads_use_the_cache() {
+    let content = test_content();
+    let path = write_temp("async_read_cache", &make_bgzf(&content, 1000)).unwrap();
+    let reader = BgzfReader::builder()
+      .cache_bytes(1 << 20)
+      .open(path.clone())
+      .unwrap();
+    let mut reader = AsyncBgzfReader::new(reader);
+    block_on(async {
+      let mut buf = [0; 100];
+      for _pass in 0..2 {
+        reader.seek(SeekFrom::Start(0)).await.unwrap();
+        for _read in 0..30 {
+          reader.read_exact(&mut buf).await.unwrap();
+        }
+      }
+    });
+    let stats = reader.get_ref().cache_stats();
+    //3 blocks decompressed once, the other reads copied from the cache
+    assert_eq!((3, 3, 57), (stats.blocks, stats.misses, stats.hits));
+
+    let loaded = BgzfReader::new(path.clone()).unwrap();
+    loaded.load_all().unwrap();
+    let mut reader = AsyncBgzfReader::new(loaded);
+    let mut all = Vec::new();
+    block_on(reader.read_to_end(&mut all)).unwrap();
+    assert_eq!(content, all);
+    fs::remove_file(path).unwrap();
+  }
+
+  /// Collects the stream of `reader` in chunks of `chunk_size` bytes
+  fn collect_chunks(reader: AsyncBgzfReader, chunk_size: usize) -> Vec<io::Result<Bytes>> {
+    use futures::StreamExt;
+
+    block_on(reader.into_chunk_stream(chunk_size).collect())
+  }
+
+  #[test]
+  fn test_chunk_stream() {
+    let content = test_content();
+    let path = write_temp("async_chunk_stream", &make_bgzf(&content, 1000)).unwrap();
+    let all = BgzfReader::new(path.clone())
+      .unwrap()
+      .decompress_all_parallel(1)
+      .unwrap();
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().spawn_blocking(true),
+      BgzfReader::builder().lazy(true),
+    ];
+    for builder in builders {
+      for chunk_size in [1, 333, 1000, 4096, content.len(), content.len() + 1] {
+        let reader = block_on(builder.clone().open_async(path.clone())).unwrap();
+        let chunks = collect_chunks(reader, chunk_size)
+          .into_iter()
+          .collect::<io::Result<Vec<_>>>()
+          .unwrap();
+        assert_eq!(all, chunks.concat());
+        let (last, full) = chunks.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == chunk_size));
+        assert!(!last.is_empty() && last.len() <= chunk_size);
+      }
+    }
+
+    //from the position, the stream can be dropped before its end
+    let mut reader = block_on(AsyncBgzfReader::open(path.clone())).unwrap();
+    block_on(reader.seek(SeekFrom::Start(content.len() as u64 - 10))).unwrap();
+    let chunks = collect_chunks(reader, 0);
+    assert_eq!(10, chunks.len());
+    block_on(async {
+      use futures::StreamExt;
+
+      let reader = BgzfReader::builder()
+        .spawn_blocking(true)
+        .open_async(path.clone())
+        .await
+        .unwrap();
+      let mut stream = Box::pin(reader.into_chunk_stream(1500));
+      let first = stream.next().await.unwrap().unwrap();
+      assert_eq!(&content[..1500], &first[..]);
+    });
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_chunk_stream_errors() {
+    let content = test_content();
+    let path = write_temp("async_chunk_truncated", &make_truncated(&content, 1000, 10)).unwrap();
+    let reader = block_on(BgzfReader::builder().lazy(true).open_async(path.clone())).unwrap();
+    let mut chunks = collect_chunks(reader, 2500);
+    let error = chunks.pop().unwrap().unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    let read = chunks
+      .into_iter()
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap()
+      .concat();
+    assert!(!read.is_empty() && content.starts_with(&read));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[cfg(feature = "futures-io")]
+  #[test]
+  fn test_futures_io_traits() {
+    use futures::io::{AsyncReadExt as FuturesReadExt, AsyncSeekExt as FuturesSeekExt};
+
+    let content = test_content();
+    let path = write_temp("async_read_futures", &make_bgzf(&content, 1000)).unwrap();
+    block_on(async {
+      let mut reader = AsyncBgzfReader::open(path.clone()).await.unwrap();
+      assert_eq!(
+        950,
+        FuturesSeekExt::seek(&mut reader, SeekFrom::Start(950))
+          .await
+          .unwrap()
+      );
+      let mut buf = vec![0; 2100];
+      FuturesReadExt::read_exact(&mut reader, &mut buf)
+        .await
+        .unwrap();
+      assert_eq!(&content[950..3050], &buf[..]);
+      let error = FuturesSeekExt::seek(&mut reader, SeekFrom::Current(-4000)).await;
+      assert_eq!(io::ErrorKind::InvalidInput, error.unwrap_err().kind());
+      let mut tail = Vec::new();
+      FuturesReadExt::read_to_end(&mut reader, &mut tail)
+        .await
+        .unwrap();
+      assert_eq!(&content[3050..], &tail[..]);
+    });
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_errors() {
+    let content = test_content();
+    let truncated = make_truncated(&content, 1000, 10);
+    let path = write_temp("async_read_truncated", &truncated).unwrap();
+    let error = block_on(AsyncBgzfReader::open(path.clone())).err().unwrap();
+    assert!(matches!(error, BgzfError::Truncated(_)), "{:?}", error);
+
+    //opened lazily, the read reaching the cut block fails
+    let mut reader = block_on(BgzfReader::builder().lazy(true).open_async(path.clone())).unwrap();
+    let mut all = Vec::new();
+    let error = block_on(reader.read_to_end(&mut all)).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    fs::remove_file(path).unwrap();
+
+    let error = block_on(AsyncBgzfReader::open(String::from("/nonexistent/file.bgz")))
+      .err()
+      .unwrap();
+    assert!(matches!(error, BgzfError::Io(_)));
+  }
+}
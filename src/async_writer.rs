@@ -0,0 +1,414 @@
+use crate::writer::{BgzfWriterBuilder, BlockCompressor, BlockFormat, EOF_MARKER};
+use std::cmp::min;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::task::JoinHandle;
+
+/// What a block compressed on the blocking thread pool gives back: the
+/// compressor, the emptied uncompressed buffer and the compressed block, all
+/// reused for the next block
+type CompressedBlock = (BlockCompressor, Vec<u8>, Vec<u8>, io::Result<()>);
+
+/// Struct to write bgzf data to a tokio `AsyncWrite` sink
+///
+/// Fields description:
+///
+/// inner: sink the compressed blocks are written to,
+/// compressor: deflate state reused for every block, None while it is
+/// compressing on the blocking thread pool,
+/// block_size: number of uncompressed bytes put in each block,
+/// uncompressed_data: data buffered for the block currently being filled,
+/// compressed: block (or EOF marker) waiting to be written to the sink,
+/// compressed_written: number of bytes of `compressed` already written,
+/// compressing: block being compressed on the blocking thread pool,
+/// spawn_blocking: true to compress with `spawn_blocking`, false for inline,
+/// finished: true once the last block and the EOF marker are queued,
+pub struct AsyncBgzfWriter<W: AsyncWrite + Unpin> {
+  inner: W,
+  compressor: Option<BlockCompressor>,
+  block_size: usize,
+  uncompressed_data: Vec<u8>,
+  compressed: Vec<u8>,
+  compressed_written: usize,
+  compressing: Option<JoinHandle<CompressedBlock>>,
+  spawn_blocking: bool,
+  finished: bool,
+}
+
+/// Below are the steps to use the async bgzf Writer,
+/// create it over any tokio `AsyncWrite` sink with `new` (or
+/// `BgzfWriter::builder().build_async` for other options), write with the
+/// `AsyncWriteExt` methods and call `shutdown`, which writes the last block
+/// and the EOF marker. Full blocks are compressed inline in `poll_write`,
+/// at most 64 KiB at a time, unless the builder asked for `spawn_blocking`.
+///
+/// `poll_write` returns `Pending` while the sink does, once a block is ready
+/// no more data is taken until the block has been written out.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::AsyncBgzfWriter;
+/// use tokio::io::AsyncWriteExt;
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// let compressed = runtime.block_on(async {
+///   let mut writer = AsyncBgzfWriter::new(Vec::new());
+///   writer.write_all(b"This is just a bgzf test").await.unwrap();
+///   writer.shutdown().await.unwrap();
+///   writer.into_inner()
+/// });
+/// assert_eq!(&[31, 139, 8, 4], &compressed[..4]);
+/// ```
+impl<W: AsyncWrite + Unpin> AsyncBgzfWriter<W> {
+  /// Returns an async writer with the default options, see `BgzfWriter::new`
+  pub fn new(inner: W) -> AsyncBgzfWriter<W> {
+    BgzfWriterBuilder::default()
+      .build_async(inner)
+      .expect("Default writer options are valid")
+  }
+
+  pub(crate) fn from_parts(
+    inner: W,
+    block_format: BlockFormat,
+    block_size: usize,
+    spawn_blocking: bool,
+  ) -> AsyncBgzfWriter<W> {
+    let mut compressor = BlockCompressor::new(block_format);
+    let compressed = Vec::with_capacity(compressor.max_block_length(block_size));
+    AsyncBgzfWriter {
+      inner,
+      compressor: Some(compressor),
+      block_size,
+      uncompressed_data: Vec::with_capacity(block_size),
+      compressed,
+      compressed_written: 0,
+      compressing: None,
+      spawn_blocking,
+      finished: false,
+    }
+  }
+
+  /// Returns a reference to the underlying writer
+  pub fn get_ref(&self) -> &W {
+    &self.inner
+  }
+
+  /// Returns a mutable reference to the underlying writer, writing to it
+  /// directly corrupts the bgzf output
+  pub fn get_mut(&mut self) -> &mut W {
+    &mut self.inner
+  }
+
+  /// Returns the underlying writer, anything not written yet is lost so it
+  /// should only be called after `shutdown`
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+
+  /// Hands the buffered data over to be compressed, the previous block must
+  /// be completely written
+  fn start_block(&mut self) -> io::Result<()> {
+    //only missing when compressing on the blocking thread pool panicked
+    let mut compressor = self
+      .compressor
+      .take()
+      .ok_or_else(|| io::Error::other("Compression of a previous block failed"))?;
+    self.compressed_written = 0;
+    if self.spawn_blocking {
+      let mut uncompressed_data = mem::take(&mut self.uncompressed_data);
+      let mut block = mem::take(&mut self.compressed);
+      self.compressing = Some(tokio::task::spawn_blocking(move || {
+        let result = compressor.compress(&uncompressed_data, &mut block);
+        uncompressed_data.clear();
+        (compressor, uncompressed_data, block, result)
+      }));
+      Ok(())
+    } else {
+      let result = compressor.compress(&self.uncompressed_data, &mut self.compressed);
+      self.compressor = Some(compressor);
+      if result.is_err() {
+        self.compressed.clear();
+      }
+      self.uncompressed_data.clear();
+      result
+    }
+  }
+
+  /// Waits for the block being compressed, if any, and writes out what is
+  /// left of the last compressed block
+  fn poll_write_compressed(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    if let Some(compressing) = self.compressing.as_mut() {
+      let result = ready!(Pin::new(compressing).poll(cx));
+      self.compressing = None;
+      let (compressor, uncompressed_data, block, result) = result.map_err(io::Error::other)?;
+      self.compressor = Some(compressor);
+      self.uncompressed_data = uncompressed_data;
+      self.compressed = block;
+      if let Err(e) = result {
+        self.compressed.clear();
+        return Poll::Ready(Err(e));
+      }
+    }
+    while self.compressed_written < self.compressed.len() {
+      let written = ready!(
+        Pin::new(&mut self.inner).poll_write(cx, &self.compressed[self.compressed_written..])
+      )?;
+      if written == 0 {
+        return Poll::Ready(Err(io::Error::new(
+          io::ErrorKind::WriteZero,
+          "Failed to write the compressed block",
+        )));
+      }
+      self.compressed_written += written;
+    }
+    self.compressed.clear();
+    self.compressed_written = 0;
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncBgzfWriter<W> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    if this.finished {
+      return Poll::Ready(Err(io::Error::other("Writer is already finished")));
+    }
+    ready!(this.poll_write_compressed(cx))?;
+    if this.uncompressed_data.len() == this.block_size {
+      this.start_block()?;
+      ready!(this.poll_write_compressed(cx))?;
+    }
+    let copy_length = min(this.block_size - this.uncompressed_data.len(), buf.len());
+    this
+      .uncompressed_data
+      .extend_from_slice(&buf[..copy_length]);
+    Poll::Ready(Ok(copy_length))
+  }
+
+  /// Writes the buffered block if it is full, a partially filled block stays
+  /// buffered and the EOF marker is not written, like `BgzfWriter::flush`
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    ready!(this.poll_write_compressed(cx))?;
+    if !this.finished && this.uncompressed_data.len() == this.block_size {
+      this.start_block()?;
+      ready!(this.poll_write_compressed(cx))?;
+    }
+    Pin::new(&mut this.inner).poll_flush(cx)
+  }
+
+  /// Writes the buffered data as a last block followed by the EOF marker,
+  /// then shuts the underlying writer down
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    ready!(this.poll_write_compressed(cx))?;
+    if !this.finished {
+      if !this.uncompressed_data.is_empty() {
+        this.start_block()?;
+        ready!(this.poll_write_compressed(cx))?;
+      }
+      this.compressed.extend_from_slice(&EOF_MARKER);
+      this.finished = true;
+      ready!(this.poll_write_compressed(cx))?;
+    }
+    ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+    Pin::new(&mut this.inner).poll_shutdown(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::write_temp;
+  use crate::{BgzfReader, BgzfWriter};
+  use std::fs;
+  use std::future::poll_fn;
+  use std::io::Write;
+  use tokio::io::AsyncWriteExt;
+
+  fn test_content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..6000 {
+      content.extend_from_slice(
+        format!("{}\tchr{}\tsome fairly repetitive text\n", i, i % 23).as_bytes(),
+      );
+    }
+    content
+  }
+
+  fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+      .build()
+      .unwrap()
+      .block_on(future)
+  }
+
+  fn read_back(name: &str, compressed: &[u8], length: usize) -> Vec<u8> {
+    let path = write_temp(name, compressed).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(length as u64, reader.total_uncompressed_length());
+    let mut content = Vec::new();
+    for block in reader.block_offsets().collect::<Vec<_>>() {
+      let mut chunk = vec![0; block.uncompressed_length as usize];
+      reader.read_to(&mut chunk).unwrap();
+      content.extend_from_slice(&chunk);
+    }
+    fs::remove_file(path).unwrap();
+    content
+  }
+
+  /// Sink accepting at most 100 bytes per call, returning Pending every
+  /// other call or always while it is blocked
+  struct SlowSink {
+    data: Vec<u8>,
+    blocked: bool,
+    ready: bool,
+    pending_calls: usize,
+  }
+
+  impl AsyncWrite for SlowSink {
+    fn poll_write(
+      mut self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      if self.blocked || !self.ready {
+        self.ready = true;
+        self.pending_calls += 1;
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+      }
+      self.ready = false;
+      let length = min(100, buf.len());
+      self.data.extend_from_slice(&buf[..length]);
+      Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let content = test_content();
+    let mut sync_writer = BgzfWriter::builder()
+      .block_size(10_000)
+      .build(Vec::new())
+      .unwrap();
+    sync_writer.write_all(&content).unwrap();
+    let expected = sync_writer.finish().unwrap();
+
+    for &spawn_blocking in &[false, true] {
+      let compressed = block_on(async {
+        let mut writer = BgzfWriter::builder()
+          .block_size(10_000)
+          .spawn_blocking(spawn_blocking)
+          .build_async(Vec::new())
+          .unwrap();
+        for chunk in content.chunks(777) {
+          writer.write_all(chunk).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+      });
+      assert_eq!(expected, compressed);
+      assert_eq!(
+        content,
+        read_back("async_round_trip", &compressed, content.len())
+      );
+    }
+  }
+
+  #[test]
+  fn test_empty() {
+    let compressed = block_on(async {
+      let mut writer = AsyncBgzfWriter::new(Vec::new());
+      writer.shutdown().await.unwrap();
+      writer.into_inner()
+    });
+    assert_eq!(EOF_MARKER.to_vec(), compressed);
+  }
+
+  #[test]
+  fn test_slow_sink() {
+    let content = test_content();
+    for &spawn_blocking in &[false, true] {
+      let sink = block_on(async {
+        let mut writer = BgzfWriter::builder()
+          .block_size(5000)
+          .spawn_blocking(spawn_blocking)
+          .build_async(SlowSink {
+            data: Vec::new(),
+            blocked: false,
+            ready: false,
+            pending_calls: 0,
+          })
+          .unwrap();
+        writer.write_all(&content).await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+      });
+      assert!(sink.pending_calls > 0);
+      assert_eq!(
+        content,
+        read_back("async_slow_sink", &sink.data, content.len())
+      );
+    }
+  }
+
+  #[test]
+  fn test_backpressure() {
+    let content = test_content();
+    block_on(async {
+      let mut writer = BgzfWriter::builder()
+        .block_size(1000)
+        .build_async(SlowSink {
+          data: Vec::new(),
+          blocked: true,
+          ready: true,
+          pending_calls: 0,
+        })
+        .unwrap();
+      //the first block is buffered, the next write needs it written out
+      writer.write_all(&content[..1000]).await.unwrap();
+      let poll =
+        poll_fn(|cx| Poll::Ready(Pin::new(&mut writer).poll_write(cx, &content[1000..]))).await;
+      assert!(poll.is_pending());
+      assert!(writer.get_ref().data.is_empty());
+
+      writer.get_mut().blocked = false;
+      writer.write_all(&content[1000..]).await.unwrap();
+      writer.shutdown().await.unwrap();
+      let written = writer.into_inner().data;
+      assert_eq!(
+        content,
+        read_back("async_backpressure", &written, content.len())
+      );
+    });
+  }
+
+  #[test]
+  fn test_write_after_shutdown() {
+    block_on(async {
+      let mut writer = AsyncBgzfWriter::new(Vec::new());
+      writer.write_all(b"some data").await.unwrap();
+      writer.shutdown().await.unwrap();
+      assert!(writer.write_all(b"more data").await.is_err());
+      writer.shutdown().await.unwrap();
+      assert_eq!(
+        b"some data".to_vec(),
+        read_back("async_after_shutdown", writer.get_ref(), 9)
+      );
+    });
+  }
+}
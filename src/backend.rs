@@ -0,0 +1,275 @@
+//! Deflate implementations, libdeflate by default or miniz_oxide with the
+//! `rust-backend` feature. The rest of the crate only uses the traits and
+//! types below so it does not depend on which backend is compiled in.
+
+use std::io;
+
+#[cfg(not(any(feature = "libdeflate", feature = "rust-backend")))]
+compile_error!("Either the \"libdeflate\" or the \"rust-backend\" feature must be enabled");
+
+#[cfg(all(feature = "libdeflate", not(feature = "rust-backend")))]
+pub(crate) use self::libdeflate::{crc32, Compressor, Decompressor};
+#[cfg(feature = "rust-backend")]
+pub(crate) use self::rust::{crc32, Compressor, Decompressor};
+
+/// Deflate level, from 1 (fastest) to 12 (best) like libdeflate, level 0 is
+/// handled by the writer with stored blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeflateLevel(i32);
+
+impl DeflateLevel {
+  pub(crate) fn new(level: i32) -> Option<DeflateLevel> {
+    if (1..=12).contains(&level) {
+      Some(DeflateLevel(level))
+    } else {
+      None
+    }
+  }
+
+  pub(crate) fn get(self) -> i32 {
+    self.0
+  }
+}
+
+/// Why raw deflate data could not be decompressed, with the backend's error
+#[derive(Debug)]
+pub(crate) enum InflateError {
+  /// The data is not valid deflate data, or stops before the end of it
+  BadData(io::Error),
+  /// The data decompresses to more bytes than the output holds
+  InsufficientSpace(io::Error),
+}
+
+/// Decompresses the raw deflate data of a block
+pub(crate) trait Inflate {
+  fn new() -> Self;
+
+  /// Decompresses all of `compressed` into `uncompressed` and returns the
+  /// number of bytes written, data that does not fit is an error
+  fn inflate(&mut self, compressed: &[u8], uncompressed: &mut [u8]) -> Result<usize, InflateError>;
+}
+
+/// Compresses the data of a block into raw deflate data, the same compressor
+/// is reused for every block
+pub(crate) trait Deflate {
+  fn new(level: DeflateLevel) -> Self;
+
+  /// Maximum length of the compressed data for `length` bytes of input
+  fn compress_bound(&mut self, length: usize) -> usize;
+
+  /// Appends the compressed `data` to `out`, without allocating when `out`
+  /// has room for `compress_bound` more bytes
+  fn deflate(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+#[cfg(all(feature = "libdeflate", not(feature = "rust-backend")))]
+mod libdeflate {
+  use super::{Deflate, DeflateLevel, Inflate, InflateError};
+  use libdeflater::{CompressionLvl, DecompressionError};
+  use std::io;
+
+  pub(crate) use libdeflater::crc32;
+
+  pub(crate) struct Decompressor(libdeflater::Decompressor);
+
+  impl Inflate for Decompressor {
+    fn new() -> Decompressor {
+      Decompressor(libdeflater::Decompressor::new())
+    }
+
+    fn inflate(
+      &mut self,
+      compressed: &[u8],
+      uncompressed: &mut [u8],
+    ) -> Result<usize, InflateError> {
+      self
+        .0
+        .deflate_decompress(compressed, uncompressed)
+        .map_err(|error| {
+          let insufficient_space = error == DecompressionError::InsufficientSpace;
+          let source = io::Error::new(io::ErrorKind::InvalidData, error);
+          if insufficient_space {
+            InflateError::InsufficientSpace(source)
+          } else {
+            InflateError::BadData(source)
+          }
+        })
+    }
+  }
+
+  pub(crate) struct Compressor(libdeflater::Compressor);
+
+  impl Deflate for Compressor {
+    fn new(level: DeflateLevel) -> Compressor {
+      let level = CompressionLvl::new(level.get()).expect("DeflateLevel is in libdeflate's range");
+      Compressor(libdeflater::Compressor::new(level))
+    }
+
+    fn compress_bound(&mut self, length: usize) -> usize {
+      self.0.deflate_compress_bound(length)
+    }
+
+    fn deflate(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+      let start = out.len();
+      let bound = self.compress_bound(data.len());
+      out.resize(start + bound, 0);
+      let compressed_length = self
+        .0
+        .deflate_compress(data, &mut out[start..])
+        .map_err(io::Error::other)?;
+      out.truncate(start + compressed_length);
+      Ok(())
+    }
+  }
+}
+
+#[cfg(feature = "rust-backend")]
+mod rust {
+  use super::{Deflate, DeflateLevel, Inflate, InflateError};
+  use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+  };
+  use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+  use miniz_oxide::inflate::TINFLStatus;
+  use std::cmp::{max, min};
+  use std::io;
+
+  pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+  }
+
+  pub(crate) struct Decompressor(Box<DecompressorOxide>);
+
+  impl Inflate for Decompressor {
+    fn new() -> Decompressor {
+      Decompressor(Box::default())
+    }
+
+    fn inflate(
+      &mut self,
+      compressed: &[u8],
+      uncompressed: &mut [u8],
+    ) -> Result<usize, InflateError> {
+      self.0.init();
+      let (status, _bytes_read, bytes_written) = decompress(
+        &mut self.0,
+        compressed,
+        uncompressed,
+        0,
+        inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+      );
+      let source = || {
+        io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Deflate data could not be decompressed: {:?}", status),
+        )
+      };
+      match status {
+        TINFLStatus::Done => Ok(bytes_written),
+        TINFLStatus::HasMoreOutput => Err(InflateError::InsufficientSpace(source())),
+        _ => Err(InflateError::BadData(source())),
+      }
+    }
+  }
+
+  pub(crate) struct Compressor(Box<CompressorOxide>);
+
+  impl Deflate for Compressor {
+    fn new(level: DeflateLevel) -> Compressor {
+      //miniz levels stop at 10, window bits 0 gives raw deflate
+      let flags = create_comp_flags_from_zip_params(min(level.get(), 10), 0, 0);
+      Compressor(Box::new(CompressorOxide::new(flags)))
+    }
+
+    fn compress_bound(&mut self, length: usize) -> usize {
+      //same as miniz's mz_compressBound
+      max(
+        128 + length * 110 / 100,
+        128 + length + (length / (31 * 1024) + 1) * 5,
+      )
+    }
+
+    fn deflate(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+      let start = out.len();
+      let bound = self.compress_bound(data.len());
+      out.resize(start + bound, 0);
+      self.0.reset();
+      let (status, bytes_read, bytes_written) =
+        compress(&mut self.0, data, &mut out[start..], TDEFLFlush::Finish);
+      if status != TDEFLStatus::Done || bytes_read != data.len() {
+        return Err(io::Error::other(format!(
+          "Data could not be deflated: {:?}",
+          status
+        )));
+      }
+      out.truncate(start + bytes_written);
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_deflate_round_trip() {
+    let data = b"bgzf bgzf bgzf bgzf bgzf bgzf bgzf bgzf".repeat(100);
+    let mut compressed = vec![1, 2, 3];
+    Compressor::new(DeflateLevel::new(6).unwrap())
+      .deflate(&data, &mut compressed)
+      .unwrap();
+    assert_eq!(&[1, 2, 3], &compressed[..3]);
+    assert!(compressed.len() < data.len());
+
+    let mut uncompressed = vec![0; data.len()];
+    let mut decompressor = Decompressor::new();
+    assert_eq!(
+      data.len(),
+      decompressor
+        .inflate(&compressed[3..], &mut uncompressed)
+        .unwrap()
+    );
+    assert_eq!(data, uncompressed);
+    //the decompressor is reusable and rejects data that does not fit
+    let mut too_short = vec![0; data.len() - 1];
+    assert!(matches!(
+      decompressor.inflate(&compressed[3..], &mut too_short),
+      Err(InflateError::InsufficientSpace(_))
+    ));
+    //nor data that is not deflate data
+    assert!(matches!(
+      decompressor.inflate(&[0xff; 20], &mut uncompressed),
+      Err(InflateError::BadData(_))
+    ));
+  }
+
+  #[test]
+  fn test_compressor_reuse() {
+    let mut compressor = Compressor::new(DeflateLevel::new(6).unwrap());
+    let data = b"0123456789 bgzf bgzf bgzf".repeat(2000);
+    let mut first = Vec::new();
+    compressor.deflate(&data, &mut first).unwrap();
+    assert!(first.len() <= compressor.compress_bound(data.len()));
+    let mut other = Vec::new();
+    compressor.deflate(b"something else", &mut other).unwrap();
+    let mut again = Vec::with_capacity(compressor.compress_bound(data.len()));
+    let capacity = again.capacity();
+    compressor.deflate(&data, &mut again).unwrap();
+    assert_eq!(first, again);
+    assert_eq!(capacity, again.capacity());
+  }
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(0, crc32(b""));
+    assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+  }
+
+  #[test]
+  fn test_deflate_level_range() {
+    assert_eq!(None, DeflateLevel::new(0));
+    assert_eq!(None, DeflateLevel::new(13));
+    assert_eq!(Some(12), DeflateLevel::new(12).map(DeflateLevel::get));
+  }
+}
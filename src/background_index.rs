@@ -0,0 +1,340 @@
+use crate::anomaly::AnomalyScan;
+use crate::scan_buffer::ScanBuffer;
+use crate::source::Source;
+use crate::{read_block_with_anomaly, truncation_at, Anomaly, BgzfBlock, ParseMode, Truncated};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// What the indexing thread has found so far
+///
+/// Fields description:
+///
+/// blocks: indexed blocks the reader has not taken yet,
+/// anomalies: anomalies found the reader has not taken yet,
+/// indexed_length: uncompressed length of all the blocks indexed so far,
+/// done: whether the thread has stopped,
+/// truncation: where the blocks stopped being readable, once done,
+#[derive(Default)]
+struct IndexedBlocks {
+  blocks: Vec<BgzfBlock>,
+  anomalies: Vec<Anomaly>,
+  indexed_length: u64,
+  done: bool,
+  truncation: Option<Truncated>,
+}
+
+/// Indexed blocks shared with the thread, and the condition variable it
+/// signals whenever it adds some
+type Shared = Arc<(Mutex<IndexedBlocks>, Condvar)>;
+
+/// Thread indexing the blocks of a file while a reader uses the ones already
+/// indexed. Dropping it stops the thread after the block it is reading.
+///
+/// Fields description:
+///
+/// shared: blocks found by the thread,
+/// stop: asks the thread to stop,
+/// thread: handle of the thread, joined on drop,
+pub(crate) struct BackgroundIndexer {
+  shared: Shared,
+  stop: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundIndexer {
+  /// Starts indexing `bgzf_file` from the block at `block_offset`, the blocks
+  /// before it holding `indexed_length` uncompressed bytes, in `parse_mode`
+  pub(crate) fn spawn(
+    bgzf_file: Source,
+    block_offset: u64,
+    indexed_length: u64,
+    parse_mode: ParseMode,
+  ) -> io::Result<BackgroundIndexer> {
+    let shared: Shared = Arc::new((
+      Mutex::new(IndexedBlocks {
+        indexed_length,
+        ..IndexedBlocks::default()
+      }),
+      Condvar::new(),
+    ));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut scan = BackgroundScan {
+      bgzf_file,
+      block_offset,
+      shared: shared.clone(),
+      stop: stop.clone(),
+      anomaly_scan: AnomalyScan::default(),
+      parse_mode,
+    };
+    let thread = thread::Builder::new()
+      .name(String::from("bgzf-indexer"))
+      .spawn(move || scan.run())?;
+    Ok(BackgroundIndexer {
+      shared,
+      stop,
+      thread: Some(thread),
+    })
+  }
+
+  /// Waits until the indexed blocks cover the uncompressed data up to `end`
+  /// or the thread is done, and moves the blocks and anomalies the reader
+  /// has not taken yet to the end of `blocks` and `anomalies`. Returns the
+  /// uncompressed length indexed so far and whether the thread is done.
+  pub(crate) fn wait_for(
+    &self,
+    end: u64,
+    blocks: &mut Vec<BgzfBlock>,
+    anomalies: &mut Vec<Anomaly>,
+  ) -> (u64, bool) {
+    let (lock, indexed) = &*self.shared;
+    let mut state = indexed
+      .wait_while(lock_state(lock), |state| {
+        !state.done && state.indexed_length < end
+      })
+      .unwrap_or_else(PoisonError::into_inner);
+    blocks.append(&mut state.blocks);
+    anomalies.append(&mut state.anomalies);
+    (state.indexed_length, state.done)
+  }
+
+  /// Asks the thread to stop after the block it is reading, for a reader that
+  /// wants no more blocks
+  pub(crate) fn stop(&self) {
+    self.stop.store(true, Ordering::Relaxed);
+  }
+
+  /// Where the blocks stopped being readable, None until the thread is done
+  pub(crate) fn truncation(&self) -> Option<Truncated> {
+    lock_state(&self.shared.0).truncation
+  }
+}
+
+impl Drop for BackgroundIndexer {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::Relaxed);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// The indexed blocks stay consistent even when the other side panicked,
+/// every change to them is made in one go
+fn lock_state(lock: &Mutex<IndexedBlocks>) -> MutexGuard<'_, IndexedBlocks> {
+  lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Work of the indexing thread
+///
+/// Fields description:
+///
+/// bgzf_file: the file, shared with the reader,
+/// block_offset: where the next block to index starts,
+/// shared: blocks found so far,
+/// stop: set when the reader is dropped,
+/// anomaly_scan: anomalies found, moved to the shared ones as they are,
+/// parse_mode: the reader's, in which blocks can be skipped,
+struct BackgroundScan {
+  bgzf_file: Source,
+  block_offset: u64,
+  shared: Shared,
+  stop: Arc<AtomicBool>,
+  anomaly_scan: AnomalyScan,
+  parse_mode: ParseMode,
+}
+
+impl BackgroundScan {
+  /// Indexes blocks like `BgzfReader::index_up_to` until one can not be read
+  fn run(&mut self) {
+    let scan = ScanBuffer::new(&self.bgzf_file);
+    while !self.stop.load(Ordering::Relaxed) {
+      let block = match read_block_with_anomaly(&scan, self.block_offset, self.parse_mode) {
+        Ok((block, header_anomaly)) => {
+          self.anomaly_scan.block(&block, header_anomaly);
+          block
+        }
+        Err(_) => return,
+      };
+      if block.input_length > 0 || !self.anomaly_scan.found.is_empty() {
+        let (lock, indexed) = &*self.shared;
+        let mut state = lock_state(lock);
+        state.anomalies.append(&mut self.anomaly_scan.found);
+        let input_offset = state.indexed_length;
+        if block.input_length > 0 {
+          state.blocks.push(BgzfBlock {
+            input_offset,
+            ..block
+          });
+        }
+        state.indexed_length += u64::from(block.input_length);
+        drop(state);
+        indexed.notify_all();
+      }
+      self.block_offset += u64::from(block.block_size);
+    }
+  }
+}
+
+impl Drop for BackgroundScan {
+  //also reached when reading a block panics, so that a reader waiting for
+  //more blocks never waits forever
+  fn drop(&mut self) {
+    let truncation = if self.stop.load(Ordering::Relaxed) {
+      None
+    } else {
+      self.anomaly_scan.end(&self.bgzf_file, self.block_offset);
+      truncation_at(&self.bgzf_file, self.block_offset)
+    };
+    let (lock, indexed) = &*self.shared;
+    let mut state = lock_state(lock);
+    state.anomalies.append(&mut self.anomaly_scan.found);
+    state.truncation = truncation;
+    state.done = true;
+    drop(state);
+    indexed.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_truncated, write_temp};
+  use crate::writer::EOF_MARKER;
+  use crate::{BgzfError, BgzfReader};
+  use std::fs::{self, File};
+
+  fn numbers(count: u32) -> Vec<u8> {
+    (0..count).flat_map(|n| n.to_le_bytes()).collect()
+  }
+
+  fn background_reader(path: &str) -> BgzfReader {
+    BgzfReader::builder()
+      .background_indexing(true)
+      .open(String::from(path))
+      .unwrap()
+  }
+
+  #[test]
+  fn test_head_now_and_tail_after_indexing() {
+    let data = numbers(40_000);
+    let path = write_temp("background_head_tail", &make_bgzf(&data, 16)).unwrap();
+    let reader = background_reader(&path);
+    let mut head = vec![0; 16];
+    assert_eq!(16, reader.read_to(&mut head).unwrap());
+    assert_eq!(&data[..16], &head[..]);
+
+    assert_eq!(data.len() as u64, reader.total_uncompressed_length());
+    assert!(reader.background.is_some());
+    assert_eq!(None, reader.next_block_offset.get());
+    assert_eq!(10_000, reader.num_blocks());
+    let serial = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(
+      serial.block_offsets().collect::<Vec<_>>(),
+      reader.block_offsets().collect::<Vec<_>>()
+    );
+    //the whole content, read block by block from the start
+    reader.seek(0);
+    let mut block = vec![0; 16];
+    for expected in data.chunks(16) {
+      assert_eq!(16, reader.read_to(&mut block).unwrap());
+      assert_eq!(expected, &block[..]);
+    }
+    assert_eq!(0, reader.read_to(&mut block).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_reads_wait_for_the_indexer() {
+    let data = numbers(40_000);
+    let path = write_temp("background_wait", &make_bgzf(&data, 16)).unwrap();
+    let reader = background_reader(&path);
+    let tail = data.len() as u64 - 16;
+    let (_index, block) = reader.block_containing(tail).unwrap();
+    assert_eq!(tail, block.uncompressed_offset);
+    reader.seek(tail);
+    let mut last_block = vec![0; 16];
+    assert_eq!(16, reader.read_to(&mut last_block).unwrap());
+    assert_eq!(&data[data.len() - 16..], &last_block[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_drop_stops_the_indexer() {
+    let data = numbers(20_000);
+    let path = write_temp("background_drop", &make_bgzf(&data, 4)).unwrap();
+    let file = Source::File(File::open(&path).unwrap());
+    let indexer = BackgroundIndexer::spawn(file, 0, 0, ParseMode::Default).unwrap();
+    drop(indexer);
+
+    //a reader dropped right after opening does not wait for the whole index
+    drop(background_reader(&path));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_wait_for() {
+    let data = numbers(1000);
+    let path = write_temp("background_wait_for", &make_bgzf(&data, 400)).unwrap();
+    let indexer = BackgroundIndexer::spawn(
+      Source::File(File::open(&path).unwrap()),
+      0,
+      0,
+      ParseMode::Default,
+    )
+    .unwrap();
+    let mut blocks = Vec::new();
+    let mut anomalies = Vec::new();
+    let (indexed_length, _done) = indexer.wait_for(1, &mut blocks, &mut anomalies);
+    assert!(indexed_length >= 400);
+    assert_eq!(indexed_length, blocks.len() as u64 * 400);
+    assert_eq!(
+      (4000, true),
+      indexer.wait_for(u64::MAX, &mut blocks, &mut anomalies)
+    );
+    assert!(anomalies.is_empty());
+    assert_eq!(
+      vec![0, 400, 800, 1200, 1600, 2000, 2400, 2800, 3200, 3600],
+      blocks
+        .iter()
+        .map(|block| block.input_offset)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(None, indexer.truncation());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_truncated_file_in_background() {
+    let data = numbers(1000);
+    let path = write_temp("background_truncated", &make_truncated(&data, 400, 10)).unwrap();
+    let reader = background_reader(&path);
+    let mut block = vec![0; 400];
+    for expected in data[..3600].chunks(400) {
+      assert_eq!(400, reader.read_to(&mut block).unwrap());
+      assert_eq!(expected, &block[..]);
+    }
+    let error = reader.read_to(&mut block).err().unwrap();
+    assert!(matches!(error, BgzfError::Truncated(_)));
+    assert!(reader.is_truncated());
+
+    let reader = BgzfReader::builder()
+      .background_indexing(true)
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    assert_eq!(3600, reader.total_uncompressed_length());
+    assert!(reader.is_truncated());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_empty_file_needs_no_thread() {
+    let path = write_temp("background_empty", &EOF_MARKER).unwrap();
+    let reader = background_reader(&path);
+    assert!(reader.background.is_none());
+    assert_eq!(0, reader.total_uncompressed_length());
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,213 @@
+//! Command line tool compressing, decompressing and checking bgzf files,
+//! built with `--features cli`
+
+use bgzf_rust_reader::{BgzfReader, BgzfStreamReader, BgzfWriter};
+use clap::{Parser, Subcommand};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+
+#[derive(Parser)]
+#[command(
+  name = "bgzf",
+  version,
+  about = "Compress, decompress and check BGZF files"
+)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Compress INPUT (or stdin) as bgzf to OUTPUT (or stdout)
+  Compress {
+    input: Option<String>,
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Compression level, 0 (stored blocks) to 12
+    #[arg(short, long, default_value_t = bgzf_rust_reader::DEFAULT_COMPRESSION_LEVEL)]
+    level: i32,
+    /// Number of compression threads
+    #[arg(short = '@', long, default_value_t = 1)]
+    threads: usize,
+    /// Also write OUTPUT.gzi, needs --output
+    #[arg(short, long)]
+    index: bool,
+  },
+  /// Decompress INPUT (or stdin) to OUTPUT (or stdout)
+  Decompress {
+    input: Option<String>,
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Write the .gzi index of INPUT to OUTPUT (INPUT.gzi by default)
+  Index {
+    input: String,
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Print the offsets and sizes of every block of INPUT
+  Inspect { input: String },
+  /// Check the CRC32 of every block of INPUT and its EOF marker
+  Test {
+    input: String,
+    /// Number of threads decompressing the blocks
+    #[arg(short = '@', long, default_value_t = 1)]
+    threads: usize,
+  },
+}
+
+fn main() {
+  let cli = Cli::parse();
+  let result = match cli.command {
+    Command::Compress {
+      input,
+      output,
+      level,
+      threads,
+      index,
+    } => compress(input, output, level, threads, index),
+    Command::Decompress { input, output } => decompress(input, output),
+    Command::Index { input, output } => index(input, output),
+    Command::Inspect { input } => inspect(input),
+    Command::Test { input, threads } => test(input, threads),
+  };
+  if let Err(e) = result {
+    //the causes of the error follow it, like "block failed: bad data"
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+      message.push_str(&format!(": {}", cause));
+      source = cause.source();
+    }
+    eprintln!("bgzf: {}", message);
+    process::exit(1);
+  }
+}
+
+fn compress(
+  input: Option<String>,
+  output: Option<String>,
+  level: i32,
+  threads: usize,
+  index: bool,
+) -> Result<(), Box<dyn Error>> {
+  let mut options = BgzfWriter::builder()
+    .compression_level(level)
+    .threads(threads);
+  if index {
+    match output.as_ref() {
+      Some(output) => options = options.gzi(format!("{}.gzi", output)),
+      None => return Err("--index needs --output".into()),
+    }
+  }
+  let mut src: Box<dyn Read> = match input {
+    Some(input) => Box::new(File::open(input)?),
+    None => Box::new(io::stdin()),
+  };
+  match output {
+    Some(output) => BgzfWriter::compress_from_reader(&mut src, File::create(output)?, options)?,
+    None => BgzfWriter::compress_from_reader(&mut src, io::stdout().lock(), options)?,
+  };
+  Ok(())
+}
+
+fn decompress(input: Option<String>, output: Option<String>) -> Result<(), Box<dyn Error>> {
+  let mut out: Box<dyn Write> = match output {
+    Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+    None => Box::new(BufWriter::new(io::stdout().lock())),
+  };
+  match input {
+    Some(input) => decompress_file(&input, &mut out)?,
+    //stdin can not be read at positions, its blocks are read as they come
+    None => {
+      io::copy(&mut BgzfStreamReader::new(io::stdin().lock()), &mut out)?;
+    }
+  }
+  out.flush()?;
+  Ok(())
+}
+
+fn decompress_file(path: &str, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+  let reader = BgzfReader::new(path.to_string())?;
+  let blocks = reader.block_offsets().collect::<Vec<_>>();
+  let mut buffer = Vec::new();
+  for block in blocks {
+    buffer.resize(block.uncompressed_length as usize, 0);
+    reader.seek(block.uncompressed_offset);
+    reader.read_to(&mut buffer)?;
+    out.write_all(&buffer)?;
+  }
+  Ok(())
+}
+
+fn index(input: String, output: Option<String>) -> Result<(), Box<dyn Error>> {
+  let output = output.unwrap_or_else(|| format!("{}.gzi", input));
+  BgzfReader::new(input)?.save_gzi(output)?;
+  Ok(())
+}
+
+fn inspect(input: String) -> Result<(), Box<dyn Error>> {
+  let reader = BgzfReader::new(input)?;
+  let stdout = io::stdout();
+  let mut out = BufWriter::new(stdout.lock());
+  writeln!(
+    out,
+    "block\tcompressed_offset\tuncompressed_offset\tblock_size\tuncompressed_length"
+  )?;
+  for (number, block) in reader.block_offsets().enumerate() {
+    writeln!(
+      out,
+      "{}\t{}\t{}\t{}\t{}",
+      number,
+      block.compressed_offset,
+      block.uncompressed_offset,
+      block.block_size,
+      block.uncompressed_length
+    )?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+fn test(input: String, threads: usize) -> Result<(), Box<dyn Error>> {
+  //a truncated file is checked too, and reported with the rest
+  let reader = BgzfReader::builder()
+    .allow_truncated(true)
+    .open(input.clone())?;
+  let report = reader.verify(threads)?;
+  for failure in &report.failures {
+    eprintln!("bgzf: {}", failure);
+  }
+  if !report.failures.is_empty() {
+    return Err(
+      format!(
+        "CRC32 mismatch or damage in {} of {} blocks",
+        report.failures.len(),
+        report.blocks
+      )
+      .into(),
+    );
+  }
+  if let Some(truncation) = report.truncation {
+    return Err(
+      format!(
+        "Invalid or truncated block at offset {}",
+        truncation.block_offset
+      )
+      .into(),
+    );
+  }
+  if report.trailing_bytes > 0 {
+    //where the last data block ends
+    let data_end = report.compressed_bytes - report.trailing_bytes;
+    return Err(format!("Invalid or truncated block at offset {}", data_end).into());
+  }
+  if !report.has_eof_marker {
+    return Err("File does not end with the BGZF EOF marker".into());
+  }
+  println!("{}: {} blocks OK", input, report.blocks);
+  Ok(())
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Use of the block cache of a reader, as returned by
+/// `BgzfReader::cache_stats`:
+///
+/// blocks: number of decompressed blocks held,
+/// bytes: uncompressed bytes held by those blocks,
+/// budget: bytes the cache may hold, the last block used is kept even when
+/// it alone is larger,
+/// hits: number of times a read found the block it needed in the cache,
+/// misses: number of times a read did not find its block in the cache,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+  pub blocks: u64,
+  pub bytes: u64,
+  pub budget: u64,
+  pub hits: u64,
+  pub misses: u64,
+}
+
+/// A decompressed block and when it was last used
+struct CachedBlock {
+  uncompressed_data: Vec<u8>,
+  last_use: u64,
+}
+
+/// Decompressed blocks by uncompressed offset, evicting the least recently
+/// used ones once they hold more bytes than the budget. The compressed cache
+/// of a reader is one too, holding compressed blocks by compressed offset.
+///
+/// Fields description:
+///
+/// name: what the cache holds, "blocks", "compressed" or "chunks", naming it
+/// in the tracing events of its hits and misses,
+/// blocks: cached blocks by the uncompressed offset they start at,
+/// by_last_use: uncompressed offset of every cached block by its last use,
+/// least recently used first,
+/// uses: number of uses so far, giving every use its own number,
+/// stats: sizes and counters reported by `stats`,
+pub(crate) struct BlockCache {
+  #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+  name: &'static str,
+  blocks: HashMap<u64, CachedBlock>,
+  by_last_use: BTreeMap<u64, u64>,
+  uses: u64,
+  stats: CacheStats,
+}
+
+impl BlockCache {
+  pub(crate) fn new(name: &'static str, budget: u64) -> BlockCache {
+    BlockCache {
+      name,
+      blocks: HashMap::new(),
+      by_last_use: BTreeMap::new(),
+      uses: 0,
+      stats: CacheStats {
+        budget,
+        ..CacheStats::default()
+      },
+    }
+  }
+
+  /// Whether the block starting at `uoffset` is cached
+  pub(crate) fn contains(&self, uoffset: u64) -> bool {
+    self.blocks.contains_key(&uoffset)
+  }
+
+  /// Returns the cached block starting at `uoffset` and makes it the most
+  /// recently used one
+  pub(crate) fn get(&mut self, uoffset: u64) -> Option<&[u8]> {
+    let block = self.blocks.get_mut(&uoffset)?;
+    #[cfg(feature = "tracing")]
+    tracing::trace!(cache = self.name, offset = uoffset, "cache hit");
+    self.by_last_use.remove(&block.last_use);
+    self.uses += 1;
+    block.last_use = self.uses;
+    self.by_last_use.insert(block.last_use, uoffset);
+    self.stats.hits += 1;
+    Some(&block.uncompressed_data)
+  }
+
+  /// Returns the uncompressed offset and data of the most recently used block
+  pub(crate) fn most_recent(&self) -> Option<(u64, &[u8])> {
+    let (_last_use, &uoffset) = self.by_last_use.iter().next_back()?;
+    Some((uoffset, &self.blocks[&uoffset].uncompressed_data))
+  }
+
+  /// Counts a miss of the block starting at `uoffset`, which a read got
+  /// elsewhere and did not cache
+  #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+  pub(crate) fn record_miss(&mut self, uoffset: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(cache = self.name, offset = uoffset, "cache miss");
+    self.stats.misses += 1;
+  }
+
+  /// Adds the block starting at `uoffset`, just decompressed, as the most
+  /// recently used one, and evicts the least recently used blocks until the
+  /// cache fits the budget or only holds the new block. Returns the data of
+  /// the new block.
+  pub(crate) fn insert(&mut self, uoffset: u64, uncompressed_data: Vec<u8>) -> &[u8] {
+    self.record_miss(uoffset);
+    self.uses += 1;
+    self.stats.bytes += uncompressed_data.len() as u64;
+    let replaced = self.blocks.insert(
+      uoffset,
+      CachedBlock {
+        uncompressed_data,
+        last_use: self.uses,
+      },
+    );
+    if let Some(replaced) = replaced {
+      self.by_last_use.remove(&replaced.last_use);
+      self.stats.bytes -= replaced.uncompressed_data.len() as u64;
+    }
+    self.by_last_use.insert(self.uses, uoffset);
+    while self.stats.bytes > self.stats.budget && self.by_last_use.len() > 1 {
+      let (_last_use, evicted) = self
+        .by_last_use
+        .pop_first()
+        .expect("The cache holds more than one block");
+      let evicted = self
+        .blocks
+        .remove(&evicted)
+        .expect("Used blocks are cached");
+      self.stats.bytes -= evicted.uncompressed_data.len() as u64;
+    }
+    &self.blocks[&uoffset].uncompressed_data
+  }
+
+  pub(crate) fn stats(&self) -> CacheStats {
+    CacheStats {
+      blocks: self.blocks.len() as u64,
+      ..self.stats
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cached_offsets(cache: &BlockCache) -> Vec<u64> {
+    cache.by_last_use.values().copied().collect()
+  }
+
+  #[test]
+  fn test_evicts_least_recently_used() {
+    let mut cache = BlockCache::new("blocks", 300);
+    cache.insert(0, vec![0; 100]);
+    cache.insert(100, vec![1; 100]);
+    cache.insert(200, vec![2; 100]);
+    assert_eq!(vec![0, 100, 200], cached_offsets(&cache));
+    //using the first block makes the second one the least recently used
+    assert_eq!(Some(&[0u8; 100][..]), cache.get(0));
+    cache.insert(300, vec![3; 100]);
+    assert_eq!(vec![200, 0, 300], cached_offsets(&cache));
+    assert_eq!(None, cache.get(100));
+    assert_eq!(Some((300, &[3u8; 100][..])), cache.most_recent());
+    assert_eq!(
+      CacheStats {
+        blocks: 3,
+        bytes: 300,
+        budget: 300,
+        hits: 1,
+        misses: 4,
+      },
+      cache.stats()
+    );
+
+    //a larger block evicts as many as it needs
+    assert_eq!(&[4u8; 250][..], cache.insert(400, vec![4; 250]));
+    assert_eq!(vec![400], cached_offsets(&cache));
+    assert_eq!(250, cache.stats().bytes);
+  }
+
+  #[test]
+  fn test_keeps_the_last_block_over_budget() {
+    let mut cache = BlockCache::new("blocks", 0);
+    assert_eq!(None, cache.most_recent());
+    cache.insert(0, vec![0; 100]);
+    assert_eq!(Some((0, &[0u8; 100][..])), cache.most_recent());
+    cache.insert(100, vec![1; 100]);
+    assert_eq!(vec![100], cached_offsets(&cache));
+    assert_eq!(100, cache.stats().bytes);
+    assert_eq!(1, cache.stats().blocks);
+  }
+
+  #[test]
+  fn test_insert_again() {
+    let mut cache = BlockCache::new("blocks", 1000);
+    cache.insert(0, vec![0; 100]);
+    cache.insert(100, vec![1; 100]);
+    cache.insert(0, vec![0; 100]);
+    assert_eq!(vec![100, 0], cached_offsets(&cache));
+    assert_eq!(200, cache.stats().bytes);
+  }
+}
@@ -0,0 +1,304 @@
+use crate::BgzfBlock;
+use positioned_io::ReadAt;
+use crate::shared_state::lock;
+use std::io;
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
+
+/// Most compressed bytes read at once for blocks following each other
+const COALESCED_READ_MAX_LENGTH: u64 = 1 << 20;
+
+/// Compressed bytes of blocks following each other in the file, read with a
+/// single read rather than one per block
+///
+/// Fields description:
+///
+/// start: offset in the file of the first byte held,
+/// data: compressed bytes held from start, its capacity grows to the longest
+/// run read so far, shared with the blocks returned that are still in use,
+/// None while a read fills it,
+pub(crate) struct CompressedRun {
+  start: u64,
+  data: Option<Arc<Vec<u8>>>,
+}
+
+impl Default for CompressedRun {
+  fn default() -> CompressedRun {
+    //made with the reader rather than with its first read
+    CompressedRun {
+      start: 0,
+      data: Some(Arc::default()),
+    }
+  }
+}
+
+/// Compressed bytes of a block, part of the run read with it, that can be
+/// inflated once the run is released
+pub(crate) struct CompressedBlock {
+  run: Arc<Vec<u8>>,
+  range: Range<usize>,
+}
+
+impl Deref for CompressedBlock {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.run[self.range.clone()]
+  }
+}
+
+impl CompressedRun {
+  /// Returns the whole compressed block `blocks[0]` from `run`. When its bytes
+  /// are not held yet they are read along with those of the next of `blocks`
+  /// that follow it in the file and are `wanted`, up to 1MiB. When that read
+  /// fails the block is read alone, so it fails like it would have by itself.
+  /// `run` is only locked to look the block up and to keep the bytes read,
+  /// reads sharing it read the file at the same time.
+  pub(crate) fn block<R: ReadAt + ?Sized>(
+    run: &Mutex<CompressedRun>,
+    source: &R,
+    blocks: &[BgzfBlock],
+    wanted: impl Fn(&BgzfBlock) -> bool,
+  ) -> io::Result<CompressedBlock> {
+    let block = &blocks[0];
+    let mut data = {
+      let mut held = lock(run);
+      if let Some(found) = held.get(block) {
+        return Ok(found);
+      }
+      held.take_data()
+    };
+
+    let block_end = block.block_offset + u64::from(block.block_size);
+    let mut run_end = block_end;
+    for next in &blocks[1..] {
+      let next_end = next.block_offset + u64::from(next.block_size);
+      if next.block_offset != run_end
+        || next_end - block.block_offset > COALESCED_READ_MAX_LENGTH
+        || !wanted(next)
+      {
+        break;
+      }
+      run_end = next_end;
+    }
+    let buffer = Arc::get_mut(&mut data).expect("The bytes taken are not shared");
+    let read = fill(source, buffer, block.block_offset, run_end);
+    if read.is_err() && run_end != block_end {
+      fill(source, buffer, block.block_offset, block_end)?;
+    } else {
+      read?;
+    }
+
+    let mut held = lock(run);
+    held.start = block.block_offset;
+    held.data = Some(data);
+    Ok(held.get(block).expect("The block was just read"))
+  }
+
+  /// Returns `block` when its bytes are held
+  fn get(&self, block: &BgzfBlock) -> Option<CompressedBlock> {
+    let data = self.data.as_ref()?;
+    let block_end = block.block_offset + u64::from(block.block_size);
+    if block.block_offset < self.start || block_end > self.start + data.len() as u64 {
+      return None;
+    }
+    let start = (block.block_offset - self.start) as usize;
+    Some(CompressedBlock {
+      run: Arc::clone(data),
+      range: start..start + block.block_size as usize,
+    })
+  }
+
+  /// Takes the bytes held to read others in their place, unless blocks still
+  /// being inflated use them, which keep them
+  fn take_data(&mut self) -> Arc<Vec<u8>> {
+    let mut data = self.data.take().unwrap_or_default();
+    if Arc::get_mut(&mut data).is_none() {
+      data = Arc::default();
+    }
+    data
+  }
+}
+
+/// Reads the bytes from `start` to `end` of `source` into `data`, leaving it
+/// empty when that fails
+fn fill<R: ReadAt + ?Sized>(source: &R, data: &mut Vec<u8>, start: u64, end: u64) -> io::Result<()> {
+  data.resize((end - start) as usize, 0);
+  source.read_exact_at(start, data).inspect_err(|_| data.clear())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::backend::{Decompressor, Inflate};
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::{inflate_block, BgzfReader};
+  use std::cell::Cell;
+  use std::fs;
+
+  const BLOCK_LENGTH: usize = 1000;
+
+  /// In-memory file counting the reads made
+  pub(crate) struct CountingSource {
+    pub(crate) data: Vec<u8>,
+    pub(crate) reads: Cell<usize>,
+  }
+
+  impl CountingSource {
+    pub(crate) fn new(data: Vec<u8>) -> CountingSource {
+      CountingSource {
+        data,
+        reads: Cell::new(0),
+      }
+    }
+  }
+
+  impl ReadAt for CountingSource {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+      self.reads.set(self.reads.get() + 1);
+      self.data.read_at(pos, buf)
+    }
+  }
+
+  fn numbers(count: u32) -> Vec<u8> {
+    (0..count).flat_map(|n| n.to_le_bytes()).collect()
+  }
+
+  fn source_and_blocks(name: &str, data: &[u8]) -> (CountingSource, Vec<BgzfBlock>) {
+    let bgzf = make_bgzf(data, BLOCK_LENGTH);
+    let path = write_temp(name, &bgzf).unwrap();
+    let blocks = BgzfReader::new(path.clone()).unwrap().blocks().clone();
+    fs::remove_file(path).unwrap();
+    (CountingSource::new(bgzf), blocks)
+  }
+
+  /// Decompresses the `wanted` blocks through `run`, like a read skipping the
+  /// cached blocks
+  fn decompress(
+    run: &Mutex<CompressedRun>,
+    source: &CountingSource,
+    blocks: &[BgzfBlock],
+    wanted: impl Fn(&BgzfBlock) -> bool,
+  ) -> Vec<u8> {
+    let mut un_compressor = Decompressor::new();
+    let mut data = Vec::new();
+    for (number, block) in blocks.iter().enumerate() {
+      if !wanted(block) {
+        continue;
+      }
+      let mut uncompressed = vec![0; block.input_length as usize];
+      let block_data = CompressedRun::block(run, source, &blocks[number..], &wanted).unwrap();
+      inflate_block(
+        &mut un_compressor,
+        block,
+        false,
+        &block_data,
+        &mut uncompressed,
+      )
+      .unwrap();
+      data.extend(uncompressed);
+    }
+    data
+  }
+
+  #[test]
+  fn test_one_read_for_consecutive_blocks() {
+    let data = numbers(10_000);
+    let (source, blocks) = source_and_blocks("compressed_run_consecutive", &data);
+    let run = Mutex::new(CompressedRun::default());
+    assert_eq!(data, decompress(&run, &source, &blocks, |_| true));
+    assert_eq!(1, source.reads.get());
+
+    //blocks still held are not read again
+    CompressedRun::block(&run, &source, &blocks[3..], |_| true).unwrap();
+    assert_eq!(1, source.reads.get());
+
+    //blocks not wanted are not read with the others, ending the run
+    let run = Mutex::new(CompressedRun::default());
+    source.reads.set(0);
+    let skipped = blocks[10];
+    let decompressed = decompress(&run, &source, &blocks, |block| {
+      block.input_offset != skipped.input_offset
+    });
+    let skipped_end = (skipped.input_offset + u64::from(skipped.input_length)) as usize;
+    assert_eq!(
+      [&data[..skipped.input_offset as usize], &data[skipped_end..]].concat(),
+      decompressed
+    );
+    assert_eq!(2, source.reads.get());
+  }
+
+  #[test]
+  fn test_longest_read() {
+    //data that does not compress, so that the blocks take more than 1MiB
+    let mut state = 1u32;
+    let data = (0..1_500_000)
+      .map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 24) as u8
+      })
+      .collect::<Vec<_>>();
+    let (source, blocks) = source_and_blocks("compressed_run_longest", &data);
+    let run = Mutex::new(CompressedRun::default());
+    assert_eq!(data, decompress(&run, &source, &blocks, |_| true));
+    assert_eq!(2, source.reads.get());
+  }
+
+  #[test]
+  fn test_run_past_the_end() {
+    let data = numbers(1000);
+    let (source, mut blocks) = source_and_blocks("compressed_run_past_end", &data);
+    let last = *blocks.last().unwrap();
+    //like a block of a stale index describing a longer file
+    blocks.push(BgzfBlock {
+      input_offset: last.input_offset + u64::from(last.input_length),
+      block_offset: last.block_offset + u64::from(last.block_size),
+      input_length: 100,
+      block_size: 200,
+    });
+
+    //the blocks before it are still read, one by one once reading all failed
+    let run = Mutex::new(CompressedRun::default());
+    let blocks_before = blocks.len() - 1;
+    let mut un_compressor = Decompressor::new();
+    let mut uncompressed = vec![0; BLOCK_LENGTH];
+    for (number, block) in blocks[..blocks_before].iter().enumerate() {
+      let block_data = CompressedRun::block(&run, &source, &blocks[number..], |_| true).unwrap();
+      let length = block.input_length as usize;
+      inflate_block(
+        &mut un_compressor,
+        block,
+        false,
+        &block_data,
+        &mut uncompressed[..length],
+      )
+      .unwrap();
+    }
+    let error = CompressedRun::block(&run, &source, &blocks[blocks_before..], |_| true)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+  }
+
+  #[test]
+  fn test_block_outlives_the_run() {
+    let data = numbers(10_000);
+    let (source, blocks) = source_and_blocks("compressed_run_outlives", &data);
+    let run = Mutex::new(CompressedRun::default());
+    let first = CompressedRun::block(&run, &source, &blocks, |_| false).unwrap();
+    let first_bytes = first.to_vec();
+    //a block still in use keeps its bytes when others are read
+    let last = CompressedRun::block(&run, &source, &blocks[blocks.len() - 1..], |_| false).unwrap();
+    assert_eq!(first_bytes, &first[..]);
+    assert_eq!(2, source.reads.get());
+    assert!(!Arc::ptr_eq(&first.run, &last.run));
+
+    //once the blocks are dropped the next bytes are read in place
+    drop((first, last));
+    let held = Arc::as_ptr(lock(&run).data.as_ref().unwrap());
+    let again = CompressedRun::block(&run, &source, &blocks, |_| false).unwrap();
+    assert_eq!(first_bytes, &again[..]);
+    assert_eq!(3, source.reads.get());
+    assert_eq!(held, Arc::as_ptr(&again.run));
+  }
+}
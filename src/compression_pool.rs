@@ -0,0 +1,250 @@
+use crate::executor::Executor;
+use crate::writer::{BlockCompressor, BlockFormat};
+use std::collections::BTreeMap;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+#[cfg(feature = "rayon")]
+use std::sync::mpsc::{Sender, TryRecvError};
+#[cfg(feature = "rayon")]
+use std::sync::PoisonError;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Function compressing the uncompressed data of one block into a whole block,
+/// each worker keeps its own compressor
+pub(crate) type CompressFn = fn(&mut BlockCompressor, &[u8]) -> io::Result<Vec<u8>>;
+
+/// Pool of worker threads compressing blocks in parallel
+///
+/// Fields description:
+///
+/// workers: where the blocks are compressed,
+/// receiver: compressed blocks coming back from the workers, in any order,
+/// completed: blocks that came back before the ones preceding them,
+/// submitted: sequence number the next submitted job will get,
+/// next: sequence number of the next block to hand out in order,
+/// max_in_flight: number of blocks allowed to be queued or compressing,
+pub(crate) struct CompressionPool {
+  workers: Workers,
+  receiver: Receiver<(u64, io::Result<Vec<u8>>)>,
+  completed: BTreeMap<u64, io::Result<Vec<u8>>>,
+  submitted: u64,
+  next: u64,
+  max_in_flight: usize,
+}
+
+/// Threads of the pool's own taking the jobs from a queue, or jobs spawned
+/// on a rayon pool
+///
+/// Fields description:
+///
+/// sender: queue of (sequence number, uncompressed data) jobs,
+/// handles: handles of the worker threads, joined on drop,
+/// pool: the rayon pool running the jobs,
+/// compressors: the compressors of the jobs done, each job taking one,
+/// format: how the blocks are compressed, for the compressors made,
+/// compress: the function compressing a block,
+/// results: sends the compressed blocks back,
+enum Workers {
+  Threads {
+    sender: Option<SyncSender<(u64, Vec<u8>)>>,
+    handles: Vec<JoinHandle<()>>,
+  },
+  #[cfg(feature = "rayon")]
+  Pool {
+    pool: Arc<rayon::ThreadPool>,
+    compressors: Arc<Mutex<Vec<BlockCompressor>>>,
+    format: Arc<BlockFormat>,
+    compress: CompressFn,
+    results: Sender<(u64, io::Result<Vec<u8>>)>,
+  },
+}
+
+/// Compresses `data` with `compressor`, a panic becoming an error
+fn compress_job(
+  compress: CompressFn,
+  compressor: &mut BlockCompressor,
+  data: &[u8],
+) -> io::Result<Vec<u8>> {
+  panic::catch_unwind(AssertUnwindSafe(|| compress(compressor, data)))
+    .unwrap_or_else(|_e| Err(io::Error::other("Compression worker panicked")))
+}
+
+impl CompressionPool {
+  /// A pool compressing `threads` blocks at once with `compress`, on threads
+  /// of its own, or with as many jobs at once on the rayon pool of `executor`
+  pub(crate) fn new(
+    threads: usize,
+    format: BlockFormat,
+    compress: CompressFn,
+    executor: &Executor,
+  ) -> CompressionPool {
+    let max_in_flight = threads * 2;
+    let (results, receiver) = channel();
+    let workers = match executor {
+      #[cfg(feature = "rayon")]
+      Executor::Pool(pool) => Workers::Pool {
+        pool: Arc::clone(pool),
+        compressors: Arc::new(Mutex::new(Vec::new())),
+        format: Arc::new(format),
+        compress,
+        results,
+      },
+      Executor::Threads => {
+        let (sender, jobs) = sync_channel::<(u64, Vec<u8>)>(max_in_flight);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let handles = (0..threads)
+          .map(|_| {
+            let jobs = Arc::clone(&jobs);
+            let mut compressor = BlockCompressor::new(format.clone());
+            let results = results.clone();
+            thread::spawn(move || loop {
+              let job = match jobs.lock() {
+                Ok(jobs) => jobs.recv(),
+                Err(_e) => break,
+              };
+              let (sequence, data) = match job {
+                Ok(job) => job,
+                Err(_e) => break,
+              };
+              let block = compress_job(compress, &mut compressor, &data);
+              if results.send((sequence, block)).is_err() {
+                break;
+              }
+            })
+          })
+          .collect();
+        Workers::Threads {
+          sender: Some(sender),
+          handles,
+        }
+      }
+    };
+    CompressionPool {
+      workers,
+      receiver,
+      completed: BTreeMap::new(),
+      submitted: 0,
+      next: 0,
+      max_in_flight,
+    }
+  }
+
+  /// Number of blocks submitted but not handed out yet
+  pub(crate) fn in_flight(&self) -> usize {
+    (self.submitted - self.next) as usize
+  }
+
+  /// Returns true when no more blocks should be submitted before taking some out
+  pub(crate) fn is_full(&self) -> bool {
+    self.in_flight() >= self.max_in_flight
+  }
+
+  pub(crate) fn submit(&mut self, data: Vec<u8>) -> io::Result<()> {
+    let sequence = self.submitted;
+    match &self.workers {
+      Workers::Threads { sender, .. } => {
+        let sender = sender.as_ref().expect("Sender lives as long as the pool");
+        sender
+          .send((sequence, data))
+          .map_err(|_e| io::Error::other("Compression workers have stopped"))?;
+      }
+      #[cfg(feature = "rayon")]
+      Workers::Pool {
+        pool,
+        compressors,
+        format,
+        compress,
+        results,
+      } => {
+        let (compressors, format, compress, results) = (
+          Arc::clone(compressors),
+          Arc::clone(format),
+          *compress,
+          results.clone(),
+        );
+        pool.spawn(move || {
+          let idle = compressors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+          let mut compressor = idle.unwrap_or_else(|| BlockCompressor::new((*format).clone()));
+          let block = compress_job(compress, &mut compressor, &data);
+          compressors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(compressor);
+          let _sent = results.send((sequence, block));
+        });
+      }
+    }
+    self.submitted += 1;
+    Ok(())
+  }
+
+  /// Waits for a compressed block to come back. On a thread of the rayon
+  /// pool, whose jobs may be queued behind the one waiting, the thread runs
+  /// them meanwhile.
+  fn receive(&self) -> io::Result<(u64, io::Result<Vec<u8>>)> {
+    #[cfg(feature = "rayon")]
+    if let Workers::Pool { pool, .. } = &self.workers {
+      if pool.current_thread_index().is_some() {
+        loop {
+          match self.receiver.try_recv() {
+            Ok(result) => return Ok(result),
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {
+              if pool.yield_now() != Some(rayon::Yield::Executed) {
+                thread::yield_now();
+              }
+            }
+          }
+        }
+      }
+    }
+    self
+      .receiver
+      .recv()
+      .map_err(|_e| io::Error::other("Compression workers have stopped"))
+  }
+
+  /// Returns the next compressed block in submission order, if `wait` is false
+  /// and that block is not compressed yet, None is returned. None is also
+  /// returned when there is nothing in flight.
+  pub(crate) fn next_block(&mut self, wait: bool) -> io::Result<Option<Vec<u8>>> {
+    while self.next < self.submitted {
+      if let Some(block) = self.completed.remove(&self.next) {
+        self.next += 1;
+        return block.map(Some);
+      }
+      let (sequence, block) = if wait {
+        self.receive()?
+      } else {
+        match self.receiver.try_recv() {
+          Ok(result) => result,
+          Err(_e) => return Ok(None),
+        }
+      };
+      self.completed.insert(sequence, block);
+    }
+    Ok(None)
+  }
+}
+
+impl Drop for CompressionPool {
+  fn drop(&mut self) {
+    match &mut self.workers {
+      //closing the job queue lets the workers run out of work and exit
+      Workers::Threads { sender, handles } => {
+        *sender = None;
+        for worker in handles.drain(..) {
+          let _result = worker.join();
+        }
+      }
+      //the jobs spawned finish on their own
+      #[cfg(feature = "rayon")]
+      Workers::Pool { .. } => {}
+    }
+  }
+}
@@ -0,0 +1,94 @@
+//! Hash of the uncompressed data, see `BgzfReader::digest`.
+
+use digest::Digest;
+use std::io::{self, Write};
+
+/// Feeds the data written to it to a hasher, for
+/// `parallel_decompress::decompress_to_writer` to write the blocks to in
+/// order
+pub(crate) struct HashingWriter<D: Digest>(pub(crate) D);
+
+impl<D: Digest> Write for HashingWriter<D> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.update(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Number of threads decompressing the blocks while the data is hashed
+pub(crate) fn threads() -> usize {
+  std::thread::available_parallelism().map_or(1, |cpus| cpus.get())
+}
+
+/// `bytes` as lowercase hexadecimal, as md5sum and sha256sum print them
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_with_bad_crc};
+  use crate::{BgzfError, BgzfReader};
+  use sha2::Sha256;
+
+  #[test]
+  fn test_known_hashes() {
+    //the hashes md5sum and sha256sum give the decompressed files
+    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    assert_eq!(
+      "4f21e461da8577fe57cc534b8d22840c",
+      reader.md5_hex().unwrap()
+    );
+    assert_eq!(
+      "d8f5c8aeefc97960f2de520ac7da66f116ad09cb9de16235294904f59dd75056",
+      reader.sha256_hex().unwrap()
+    );
+    let reader = BgzfReader::new(String::from("tests/data/htslib.vcf.gz")).unwrap();
+    assert_eq!(
+      "605e38c07316d2e46414012cdb7e577a",
+      reader.md5_hex().unwrap()
+    );
+    assert_eq!(
+      "0d2c1ecfc774a259a78808fa3d0efbd0dd38357e9d870ae3fad19968356fa818",
+      to_hex(&reader.digest_verifying_crc::<Sha256>().unwrap())
+    );
+  }
+
+  #[test]
+  fn test_hashes_the_data_in_order() {
+    let content = (0..300_000u32)
+      .map(|i| (i * 7919 % 251) as u8)
+      .collect::<Vec<_>>();
+    let reader = BgzfReader::from_bytes(make_bgzf(&content, 1000)).unwrap();
+    assert_eq!(Sha256::digest(&content), reader.digest::<Sha256>().unwrap());
+    let empty = BgzfReader::from_bytes(make_bgzf(b"", 1000)).unwrap();
+    assert_eq!(
+      "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+      empty.sha256_hex().unwrap()
+    );
+  }
+
+  #[test]
+  fn test_crc_checked_in_the_same_pass() {
+    let reader = BgzfReader::from_bytes(make_with_bad_crc(b"some data to hash", 5, 2)).unwrap();
+    //the data decompresses, only its CRC32 is wrong
+    assert!(reader.md5_hex().is_ok());
+    assert!(matches!(
+      reader.digest_verifying_crc::<Sha256>(),
+      Err(BgzfError::ChecksumMismatch { .. })
+    ));
+    let reader = BgzfReader::builder()
+      .verify_crc(true)
+      .open_bytes(make_with_bad_crc(b"some data to hash", 5, 2))
+      .unwrap();
+    assert!(matches!(
+      reader.sha256_hex(),
+      Err(BgzfError::ChecksumMismatch { .. })
+    ));
+  }
+}
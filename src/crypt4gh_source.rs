@@ -0,0 +1,435 @@
+use crate::block_cache::BlockCache;
+use crate::shared_state::lock;
+use crypt4gh::header::{deconstruct_header_body, DecryptedHeaderPackets};
+use crypt4gh::Keys;
+use positioned_io::ReadAt;
+use sodiumoxide::crypto::aead::chacha20poly1305_ietf::{self, Key, Nonce};
+use std::cmp::min;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Length of the plaintext of every segment but the last one
+const SEGMENT_SIZE: u64 = 65536;
+
+/// Length a segment takes in the file: its nonce, its encrypted data and the
+/// MAC following it
+const CIPHER_SEGMENT_SIZE: u64 = 12 + SEGMENT_SIZE + 16;
+
+/// Magic number, version and number of header packets
+const HEADER_INFO_LENGTH: usize = 16;
+
+/// Packet length, encryption method, writer's public key, nonce and MAC of a
+/// header packet encrypted with X25519 and ChaCha20-Poly1305, the only method
+/// the specification and the crypt4gh crate implement
+const MIN_PACKET_LENGTH: u32 = 4 + 4 + 32 + 12 + 16;
+
+/// Decrypted segments kept, so that the reads of a block across two segments
+/// and of the blocks next to it decrypt each segment once
+const CACHE_BYTES: u64 = 4 * SEGMENT_SIZE;
+
+/// The plaintext of a Crypt4GH file, decrypting the 64 KiB segments the reads
+/// need, each one on its own, the source of a reader opened with
+/// `BgzfReaderBuilder::open_crypt4gh`
+///
+/// Fields description:
+///
+/// file: the encrypted file,
+/// data_start: offset of the first segment, after the header,
+/// length: length of the plaintext,
+/// session_keys: keys of the header packets, each segment being encrypted
+/// with one of them,
+/// segments: decrypted segments, by offset in the plaintext,
+pub(crate) struct Crypt4ghSource<R: ReadAt> {
+  file: R,
+  data_start: u64,
+  length: u64,
+  session_keys: Vec<Key>,
+  segments: Mutex<BlockCache>,
+}
+
+impl<R: ReadAt> Crypt4ghSource<R> {
+  /// Returns the source of the plaintext of `file`, of `file_length` bytes,
+  /// whose header is decrypted with `secret_key`, the recipient's X25519
+  /// secret key of which only the first 32 bytes are used
+  pub(crate) fn new(file: R, file_length: u64, secret_key: &[u8]) -> io::Result<Crypt4ghSource<R>> {
+    if secret_key.len() < 32 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "A crypt4gh secret key holds 32 bytes, not {}",
+          secret_key.len()
+        ),
+      ));
+    }
+    sodiumoxide::init().map_err(|()| io::Error::other("libsodium can not be initialized"))?;
+    let (packets, data_start) = read_header_packets(&file, file_length)?;
+    let keys = [Keys {
+      method: 0,
+      privkey: secret_key[..32].to_vec(),
+      recipient_pubkey: Vec::new(),
+    }];
+    //the crypt4gh crate panics on some malformed packets, which are still
+    //bad data here
+    let header = panic::catch_unwind(AssertUnwindSafe(|| {
+      deconstruct_header_body(packets, &keys, &None)
+    }))
+    .map_err(|_| invalid_data("The crypt4gh header packets are malformed".to_string()))?
+    .map_err(|e| {
+      invalid_data(format!(
+        "The crypt4gh header can not be decrypted with this key: {}",
+        e
+      ))
+    })?;
+    let DecryptedHeaderPackets {
+      data_enc_packets,
+      edit_list_packet,
+    } = header;
+    //an edit list skips parts of the plaintext, whose offsets would then not
+    //be those of the segments
+    if edit_list_packet.is_some() {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "crypt4gh files with an edit list can not be read at random",
+      ));
+    }
+    let session_keys = data_enc_packets
+      .iter()
+      .map(|session_key| {
+        Key::from_slice(session_key).ok_or_else(|| {
+          invalid_data(format!(
+            "A crypt4gh session key of {} bytes instead of 32",
+            session_key.len()
+          ))
+        })
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+    let encrypted_length = file_length - data_start;
+    let last_segment_length = encrypted_length % CIPHER_SEGMENT_SIZE;
+    //the last segment holds at least a byte
+    if last_segment_length > 0 && last_segment_length <= CIPHER_SEGMENT_SIZE - SEGMENT_SIZE {
+      return Err(invalid_data(format!(
+        "The last crypt4gh segment is {} bytes long, too short for any data",
+        last_segment_length
+      )));
+    }
+    let length = encrypted_length / CIPHER_SEGMENT_SIZE * SEGMENT_SIZE
+      + last_segment_length.saturating_sub(CIPHER_SEGMENT_SIZE - SEGMENT_SIZE);
+    Ok(Crypt4ghSource {
+      file,
+      data_start,
+      length,
+      session_keys,
+      segments: Mutex::new(BlockCache::new("segments", CACHE_BYTES)),
+    })
+  }
+
+  /// Returns the length of the plaintext
+  pub(crate) fn len(&self) -> u64 {
+    self.length
+  }
+
+  /// Reads and decrypts the segment holding the plaintext at `segment_start`
+  fn decrypt_segment(&self, segment_start: u64) -> io::Result<Vec<u8>> {
+    let segment_number = segment_start / SEGMENT_SIZE;
+    let plaintext_length = min(SEGMENT_SIZE, self.length - segment_start);
+    let mut segment = vec![0; (plaintext_length + CIPHER_SEGMENT_SIZE - SEGMENT_SIZE) as usize];
+    self.file.read_exact_at(
+      self.data_start + segment_number * CIPHER_SEGMENT_SIZE,
+      &mut segment,
+    )?;
+    let (nonce, encrypted) = segment.split_at(12);
+    let nonce = Nonce::from_slice(nonce).expect("A nonce is 12 bytes");
+    self
+      .session_keys
+      .iter()
+      .find_map(|key| chacha20poly1305_ietf::open(encrypted, None, &nonce, key).ok())
+      .ok_or_else(|| {
+        invalid_data(format!(
+          "The crypt4gh segment {} can not be decrypted, it is damaged",
+          segment_number
+        ))
+      })
+  }
+}
+
+/// Reads from one segment at a time, at most up to its end
+impl<R: ReadAt> ReadAt for Crypt4ghSource<R> {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    if pos >= self.length || buf.is_empty() {
+      return Ok(0);
+    }
+    let segment_start = pos - pos % SEGMENT_SIZE;
+    //within a segment, the distance fits in a usize
+    let copy_start = (pos - segment_start) as usize;
+    let copy = |segment: &[u8], buf: &mut [u8]| {
+      let length = min(segment.len() - copy_start, buf.len());
+      buf[..length].copy_from_slice(&segment[copy_start..copy_start + length]);
+      length
+    };
+    if let Some(segment) = lock(&self.segments).get(segment_start) {
+      return Ok(copy(segment, buf));
+    }
+    //decrypted without holding the lock, the reads of cached segments go on
+    let segment = self.decrypt_segment(segment_start)?;
+    let mut segments = lock(&self.segments);
+    let segment = segments.insert(segment_start, segment);
+    Ok(copy(segment, buf))
+  }
+}
+
+/// Reads the header of the crypt4gh file `file`, of `file_length` bytes, and
+/// returns its packets, without their length, and where the segments start
+fn read_header_packets<R: ReadAt>(file: &R, file_length: u64) -> io::Result<(Vec<Vec<u8>>, u64)> {
+  let mut header_info = [0; HEADER_INFO_LENGTH];
+  file
+    .read_exact_at(0, &mut header_info)
+    .map_err(|_| invalid_data("The file is too short for a crypt4gh header".to_string()))?;
+  if &header_info[..8] != b"crypt4gh" {
+    return Err(invalid_data("The file is not a crypt4gh file".to_string()));
+  }
+  let version = u32::from_le_bytes([
+    header_info[8],
+    header_info[9],
+    header_info[10],
+    header_info[11],
+  ]);
+  if version != 1 {
+    return Err(invalid_data(format!(
+      "crypt4gh version {} is not supported, only version 1",
+      version
+    )));
+  }
+  let packets_count = u32::from_le_bytes([
+    header_info[12],
+    header_info[13],
+    header_info[14],
+    header_info[15],
+  ]);
+  let mut position = HEADER_INFO_LENGTH as u64;
+  let mut packets = Vec::new();
+  for _ in 0..packets_count {
+    let mut packet_length = [0; 4];
+    file.read_exact_at(position, &mut packet_length)?;
+    let packet_length = u32::from_le_bytes(packet_length);
+    if packet_length < 4 || position + u64::from(packet_length) > file_length {
+      return Err(invalid_data(format!(
+        "The crypt4gh header packet at offset {} is {} bytes long",
+        position, packet_length
+      )));
+    }
+    let mut packet = vec![0; packet_length as usize - 4];
+    file.read_exact_at(position + 4, &mut packet)?;
+    position += u64::from(packet_length);
+    //only packets of the X25519 method can be ours, and others of it too
+    //short for its fields would make the crypt4gh crate panic
+    if packet_length >= MIN_PACKET_LENGTH && packet[..4] == [0, 0, 0, 0] {
+      packets.push(packet);
+    }
+  }
+  Ok((packets, position))
+}
+
+/// Reads the crypt4gh secret key file at `key_path`, in the Crypt4GH format
+/// or an OpenSSH ed25519 key, decrypting it with `passphrase` when it is
+/// encrypted, and returns the secret key `BgzfReaderBuilder::open_crypt4gh`
+/// takes. Only with the `crypt4gh` feature.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{crypt4gh_secret_key, BgzfReader};
+///
+/// let secret_key = crypt4gh_secret_key(
+///   String::from("tests/data/crypt4gh_recipient.sec"),
+///   "bgzf test",
+/// )
+/// .unwrap();
+/// let reader = BgzfReader::builder()
+///   .open_crypt4gh(String::from("tests/data/htslib.vcf.gz.c4gh"), &secret_key)
+///   .unwrap();
+/// let mut start = vec![0; 16];
+/// reader.read_to(&mut start).unwrap();
+/// assert_eq!(b"##fileformat=VCF", &start[..]);
+/// ```
+pub fn crypt4gh_secret_key(key_path: String, passphrase: &str) -> io::Result<Vec<u8>> {
+  let passphrase = passphrase.to_string();
+  let key = panic::catch_unwind(AssertUnwindSafe(|| {
+    crypt4gh::keys::get_private_key(Path::new(&key_path), || Ok(passphrase.clone()))
+  }))
+  .map_err(|_| invalid_data(format!("{} is a malformed crypt4gh key", key_path)))?
+  .map_err(|e| invalid_data(format!("{}: {}", key_path, e)))?;
+  if key.len() < 32 {
+    return Err(invalid_data(format!(
+      "{} is a malformed crypt4gh key",
+      key_path
+    )));
+  }
+  Ok(key)
+}
+
+fn invalid_data(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crypt4gh::header;
+  use crypt4gh::keys::get_public_key_from_private_key;
+  use std::collections::HashSet;
+
+  fn data(length: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..length)
+      .map(|_| {
+        state = state
+          .wrapping_mul(6_364_136_223_846_793_005)
+          .wrapping_add(1);
+        (state >> 56) as u8
+      })
+      .collect()
+  }
+
+  /// Encrypts `plaintext` for the recipient of `secret_key` with the crypt4gh
+  /// crate
+  fn encrypt(plaintext: &[u8], secret_key: &[u8]) -> Vec<u8> {
+    let recipients = HashSet::from([Keys {
+      method: 0,
+      privkey: data(32),
+      recipient_pubkey: get_public_key_from_private_key(secret_key).unwrap(),
+    }]);
+    let mut encrypted = Vec::new();
+    crypt4gh::encrypt(&recipients, &mut &plaintext[..], &mut encrypted, 0, None).unwrap();
+    encrypted
+  }
+
+  #[test]
+  fn test_reads_across_segments() {
+    let secret_key = vec![7; 32];
+    for &length in &[1, 1000, 65536, 65537, 3 * 65536 + 500] {
+      let plaintext = data(length);
+      let encrypted = encrypt(&plaintext, &secret_key);
+      let source =
+        Crypt4ghSource::new(&encrypted[..], encrypted.len() as u64, &secret_key).unwrap();
+      assert_eq!(length as u64, source.len());
+      for &(pos, read_length) in &[
+        (0, length),
+        (length / 2, 70_000),
+        (65530, 20),
+        (length - 1, 5),
+      ] {
+        let pos = min(pos, length - 1);
+        let end = min(pos + read_length, length);
+        let mut buf = vec![0; end - pos];
+        source.read_exact_at(pos as u64, &mut buf).unwrap();
+        assert_eq!(&plaintext[pos..end], &buf[..], "{} at {}", length, pos);
+      }
+      let mut buf = [0; 4];
+      assert_eq!(0, source.read_at(length as u64, &mut buf).unwrap());
+    }
+  }
+
+  #[test]
+  fn test_rejects_wrong_key_and_damage() {
+    let secret_key = vec![7; 32];
+    let plaintext = data(100_000);
+    let encrypted = encrypt(&plaintext, &secret_key);
+    let header_length = encrypted.len() - (plaintext.len() + 2 * 28);
+
+    let error = Crypt4ghSource::new(&encrypted[..], encrypted.len() as u64, &[8; 32])
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    assert!(Crypt4ghSource::new(&encrypted[..], encrypted.len() as u64, &[7; 16]).is_err());
+    assert!(Crypt4ghSource::new(&b"not crypt4gh data"[..], 17, &secret_key).is_err());
+    //the header cut short
+    let error = Crypt4ghSource::new(&encrypted[..40], 40, &secret_key)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    //a last segment too short to hold any data
+    let cut = encrypted.len() - (100_000 - 65536) - 10;
+    let error = Crypt4ghSource::new(&encrypted[..cut], cut as u64, &secret_key)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+    //a damaged second segment fails its reads only
+    let mut damaged = encrypted.clone();
+    damaged[header_length + CIPHER_SEGMENT_SIZE as usize + 100] ^= 1;
+    let source = Crypt4ghSource::new(&damaged[..], damaged.len() as u64, &secret_key).unwrap();
+    let mut buf = vec![0; 100];
+    source.read_exact_at(1000, &mut buf).unwrap();
+    assert_eq!(&plaintext[1000..1100], &buf[..]);
+    let error = source.read_exact_at(70_000, &mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+  }
+
+  #[test]
+  fn test_rejects_edit_lists() {
+    let secret_key = vec![7; 32];
+    let recipients = HashSet::from([Keys {
+      method: 0,
+      privkey: data(32),
+      recipient_pubkey: get_public_key_from_private_key(&secret_key).unwrap(),
+    }]);
+    let session_key = [3; 32];
+    let plaintext = data(1000);
+    let segment = crypt4gh::encrypt_segment(
+      &plaintext,
+      Nonce::from_slice(&[1; 12]).unwrap(),
+      &Key(session_key),
+    );
+    let encrypted = |packets: Vec<Vec<u8>>| {
+      let mut encrypted_packets = Vec::new();
+      for packet in packets {
+        encrypted_packets.extend(header::encrypt(&packet, &recipients).unwrap());
+      }
+      let mut encrypted = header::serialize(encrypted_packets);
+      encrypted.extend_from_slice(&segment);
+      encrypted
+    };
+
+    let whole = encrypted(vec![header::make_packet_data_enc(0, &session_key)]);
+    let source = Crypt4ghSource::new(&whole[..], whole.len() as u64, &secret_key).unwrap();
+    let mut buf = vec![0; 1000];
+    source.read_exact_at(0, &mut buf).unwrap();
+    assert_eq!(plaintext, buf);
+
+    let edited = encrypted(vec![
+      header::make_packet_data_enc(0, &session_key),
+      header::make_packet_data_edit_list(vec![10, 500]),
+    ]);
+    let error = Crypt4ghSource::new(&edited[..], edited.len() as u64, &secret_key)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::Unsupported, error.kind());
+  }
+
+  #[test]
+  fn test_rejects_malformed_header_packets() {
+    let secret_key = vec![7; 32];
+    let mut header = b"crypt4gh".to_vec();
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(&2u32.to_le_bytes());
+    //a packet of the X25519 method too short for its fields, and one of an
+    //unknown method
+    header.extend_from_slice(&12u32.to_le_bytes());
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&10u32.to_le_bytes());
+    header.extend_from_slice(&[1, 0, 0, 0, 0, 0]);
+    let error = Crypt4ghSource::new(&header[..], header.len() as u64, &secret_key)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+    //a packet longer than the file
+    let mut header = header[..HEADER_INFO_LENGTH].to_vec();
+    header.extend_from_slice(&1000u32.to_le_bytes());
+    assert!(Crypt4ghSource::new(&header[..], header.len() as u64, &secret_key).is_err());
+    let mut version_2 = header.clone();
+    version_2[8] = 2;
+    assert!(Crypt4ghSource::new(&version_2[..], version_2.len() as u64, &secret_key).is_err());
+  }
+}
@@ -0,0 +1,110 @@
+use crate::source::Source;
+use crate::{BgzfBlock, Truncated};
+use positioned_io::ReadAt;
+use std::io::{self, BufWriter, Write};
+
+/// Writes the layout of `blocks` as the JSON described in
+/// `BgzfReader::write_debug_json`, one block per line. The gzip header of
+/// every block is read from `file` for the offset of its compressed data.
+pub(crate) fn write_debug_json<W: Write>(
+  file: &Source,
+  blocks: &[BgzfBlock],
+  has_eof_marker: bool,
+  truncation: Option<Truncated>,
+  out: W,
+) -> io::Result<()> {
+  let mut out = BufWriter::new(out);
+  writeln!(out, "{{")?;
+  writeln!(out, "  \"blocks\": [")?;
+  let mut compressed_length = 0;
+  let mut uncompressed_length = 0;
+  for (number, block) in blocks.iter().enumerate() {
+    let mut header = [0u8; 12];
+    file.read_exact_at(block.block_offset, &mut header)?;
+    let header_length = 12 + u64::from(u16::from_le_bytes([header[10], header[11]]));
+    //the 8 byte footer holds the CRC32 and ISIZE
+    let data_length = u64::from(block.block_size).saturating_sub(header_length + 8);
+    writeln!(
+      out,
+      "    {{\"compressed_offset\": {}, \"compressed_size\": {}, \"data_offset\": {}, \"data_length\": {}, \"uncompressed_offset\": {}, \"uncompressed_length\": {}}}{}",
+      block.block_offset,
+      block.block_size,
+      block.block_offset + header_length,
+      data_length,
+      block.input_offset,
+      block.input_length,
+      if number + 1 < blocks.len() { "," } else { "" }
+    )?;
+    compressed_length += u64::from(block.block_size);
+    uncompressed_length += u64::from(block.input_length);
+  }
+  writeln!(out, "  ],")?;
+  writeln!(out, "  \"block_count\": {},", blocks.len())?;
+  writeln!(out, "  \"compressed_length\": {},", compressed_length)?;
+  writeln!(out, "  \"uncompressed_length\": {},", uncompressed_length)?;
+  writeln!(out, "  \"has_eof_marker\": {},", has_eof_marker)?;
+  match truncation {
+    Some(truncation) => writeln!(out, "  \"truncated_at\": {}", truncation.block_offset)?,
+    None => writeln!(out, "  \"truncated_at\": null")?,
+  }
+  writeln!(out, "}}")?;
+  out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::fixtures::{make_truncated, write_temp};
+  use crate::BgzfReader;
+  use std::fs;
+
+  const SNAPSHOT: &str = r#"{
+  "blocks": [
+    {"compressed_offset": 0, "compressed_size": 211, "data_offset": 18, "data_length": 185, "uncompressed_offset": 0, "uncompressed_length": 280}
+  ],
+  "block_count": 1,
+  "compressed_length": 211,
+  "uncompressed_length": 280,
+  "has_eof_marker": true,
+  "truncated_at": null
+}
+"#;
+
+  #[test]
+  fn test_snapshot() {
+    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    assert_eq!(SNAPSHOT, reader.to_debug_json().unwrap());
+  }
+
+  #[test]
+  fn test_valid_json_of_damaged_file() {
+    let data = b"This is just a bgzf test,lets see how it reacts. :).".repeat(10);
+    let path = write_temp("debug_json", &make_truncated(&data, 100, 5)).unwrap();
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    let mut streamed = Vec::new();
+    reader.write_debug_json(&mut streamed).unwrap();
+    assert_eq!(reader.to_debug_json().unwrap().as_bytes(), &streamed[..]);
+
+    let json: serde_json::Value = serde_json::from_slice(&streamed).unwrap();
+    let blocks = json["blocks"].as_array().unwrap();
+    assert_eq!(5, blocks.len());
+    assert_eq!(5, json["block_count"]);
+    assert_eq!(500, json["uncompressed_length"]);
+    assert_eq!(false, json["has_eof_marker"]);
+    for (block, info) in blocks.iter().zip(reader.block_offsets()) {
+      assert_eq!(info.compressed_offset, block["compressed_offset"]);
+      assert_eq!(info.block_size, block["compressed_size"]);
+      assert_eq!(info.compressed_offset + 18, block["data_offset"]);
+      assert_eq!(info.block_size - 26, block["data_length"]);
+      assert_eq!(info.uncompressed_offset, block["uncompressed_offset"]);
+      assert_eq!(100, block["uncompressed_length"]);
+    }
+    assert_eq!(
+      reader.truncation().unwrap().block_offset,
+      json["truncated_at"]
+    );
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,215 @@
+use crate::backend::{Decompressor, Inflate};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Decompressors shared by everything decompressing the blocks of a reader:
+/// its reads, its prefetching thread and its parallel decompression. They
+/// are made when needed, up to `max`, then a thread needing one waits for
+/// another to give one back.
+///
+/// Fields description:
+///
+/// state: the idle decompressors and how many were made,
+/// returned: notified when a decompressor is given back,
+/// max: most decompressors made,
+pub(crate) struct DecompressorPool {
+  state: Mutex<PoolState>,
+  returned: Condvar,
+  max: usize,
+}
+
+/// idle: decompressors given back and not taken again,
+/// created: number of decompressors made so far, idle or not,
+struct PoolState {
+  idle: Vec<Decompressor>,
+  created: usize,
+}
+
+impl DecompressorPool {
+  /// A pool of at most `max` decompressors, at least one
+  pub(crate) fn new(max: usize) -> DecompressorPool {
+    DecompressorPool {
+      state: Mutex::new(PoolState {
+        idle: Vec::new(),
+        created: 0,
+      }),
+      returned: Condvar::new(),
+      max: max.max(1),
+    }
+  }
+
+  /// Default number of decompressors, one per CPU but at least two so that
+  /// prefetching does not wait for reads
+  pub(crate) fn default_max() -> usize {
+    thread::available_parallelism().map_or(2, |cpus| cpus.get().max(2))
+  }
+
+  /// Takes an idle decompressor, or makes one when all are in use and fewer
+  /// than `max` were made, otherwise waits for one to be given back. It goes
+  /// back to the pool when dropped. A thread must not take a second one
+  /// while holding one, as it could wait for itself.
+  pub(crate) fn acquire(&self) -> PooledDecompressor<'_> {
+    let mut state = self
+      .returned
+      .wait_while(self.lock_state(), |state| {
+        state.idle.is_empty() && state.created >= self.max
+      })
+      .unwrap_or_else(PoisonError::into_inner);
+    let decompressor = match state.idle.pop() {
+      Some(decompressor) => decompressor,
+      None => {
+        state.created += 1;
+        drop(state);
+        Decompressor::new()
+      }
+    };
+    PooledDecompressor {
+      pool: self,
+      decompressor: Some(decompressor),
+    }
+  }
+
+  /// Number of decompressors made so far
+  #[cfg(test)]
+  pub(crate) fn created(&self) -> usize {
+    self.lock_state().created
+  }
+
+  fn lock_state(&self) -> MutexGuard<'_, PoolState> {
+    self.state.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+/// A decompressor taken from a `DecompressorPool`, given back when dropped.
+/// The decompressor keeps no state from one block to the next, even when
+/// decompressing one failed, so any of them does for any block.
+pub(crate) struct PooledDecompressor<'a> {
+  pool: &'a DecompressorPool,
+  decompressor: Option<Decompressor>,
+}
+
+impl Deref for PooledDecompressor<'_> {
+  type Target = Decompressor;
+
+  fn deref(&self) -> &Decompressor {
+    self.decompressor.as_ref().expect("Held until dropped")
+  }
+}
+
+impl DerefMut for PooledDecompressor<'_> {
+  fn deref_mut(&mut self) -> &mut Decompressor {
+    self.decompressor.as_mut().expect("Held until dropped")
+  }
+}
+
+impl Drop for PooledDecompressor<'_> {
+  fn drop(&mut self) {
+    if let Some(decompressor) = self.decompressor.take() {
+      self.pool.lock_state().idle.push(decompressor);
+      self.pool.returned.notify_one();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::BgzfReader;
+  use std::fs;
+  use std::sync::Arc;
+
+  #[test]
+  fn test_made_when_needed() {
+    let pool = DecompressorPool::new(2);
+    assert_eq!(0, pool.created());
+    let first = pool.acquire();
+    drop(first);
+    let first = pool.acquire();
+    assert_eq!(1, pool.created());
+    let second = pool.acquire();
+    assert_eq!(2, pool.created());
+    drop((first, second));
+    assert_eq!(2, pool.lock_state().idle.len());
+    assert_eq!(1, DecompressorPool::new(0).max);
+  }
+
+  #[test]
+  fn test_many_threads() {
+    let mut content = Vec::new();
+    for i in 0..5_000 {
+      content.extend_from_slice(format!("{}\tchr{}\tpool\n", i, i % 7).as_bytes());
+    }
+    let bgzf = make_bgzf(&content, 1000);
+    let path = write_temp("decompressor_pool_threads", &bgzf).unwrap();
+    let blocks = BgzfReader::new(path.clone()).unwrap().blocks().clone();
+    fs::remove_file(path).unwrap();
+
+    let pool = Arc::new(DecompressorPool::new(3));
+    let (bgzf, blocks, content) = (Arc::new(bgzf), Arc::new(blocks), Arc::new(content));
+    let threads = (0..16)
+      .map(|thread| {
+        let (pool, bgzf, blocks, content) = (
+          Arc::clone(&pool),
+          Arc::clone(&bgzf),
+          Arc::clone(&blocks),
+          Arc::clone(&content),
+        );
+        thread::spawn(move || {
+          let mut block_data = Vec::new();
+          for number in 0..200 {
+            let block = &blocks[(thread * 31 + number * 7) % blocks.len()];
+            let mut uncompressed = vec![0; block.input_length as usize];
+            let mut decompressor = pool.acquire();
+            crate::decompress_block_at(
+              &*bgzf,
+              &mut decompressor,
+              block,
+              false,
+              &mut block_data,
+              &mut uncompressed,
+            )
+            .unwrap();
+            drop(decompressor);
+            assert_eq!(
+              &content[block.input_offset as usize..][..uncompressed.len()],
+              &uncompressed[..]
+            );
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+    assert!(pool.created() <= 3);
+  }
+
+  #[test]
+  fn test_reader_with_one_decompressor() {
+    let content = (0..50_000u32)
+      .flat_map(|n| n.to_le_bytes())
+      .collect::<Vec<_>>();
+    let path = write_temp("decompressor_pool_reader", &make_bgzf(&content, 1000)).unwrap();
+    let reader = BgzfReader::builder()
+      .decompressors(1)
+      .prefetch(4)
+      .open(path.clone())
+      .unwrap();
+    //reads, prefetching and parallel decompression wait for each other
+    let mut data = vec![0; content.len()];
+    for chunk in data.chunks_mut(3000) {
+      reader.read_to(chunk).unwrap();
+    }
+    assert_eq!(content, data);
+    assert_eq!(content, reader.decompress_all_parallel(8).unwrap());
+    let mut out = Vec::new();
+    reader
+      .decompress_to_writer_parallel(8, 16, &mut out)
+      .unwrap();
+    assert_eq!(content, out);
+    assert_eq!(1, reader.decompressors.created());
+    fs::remove_file(path).unwrap();
+  }
+}
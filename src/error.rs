@@ -0,0 +1,516 @@
+#[cfg(feature = "gzip")]
+use crate::GzipError;
+#[cfg(feature = "tabix")]
+use crate::TabixError;
+use crate::{
+  Anomaly, BgzfBlock, DataGap, FaiError, GziError, IndexError, IndexingCancelled, LineIndexError,
+  Truncated,
+};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Limits on what a file may take, set on `BgzfReaderBuilder` for files from
+/// untrusted sources
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceLimit {
+  /// `max_blocks`, the blocks with data indexed
+  Blocks,
+  /// `max_uncompressed_length`, the uncompressed length of the blocks
+  /// indexed
+  UncompressedLength,
+  /// `max_allocation`, the bytes allocated at once for the data
+  Allocation,
+}
+
+/// What was being done with a block when it failed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockOperation {
+  /// Reading its header or compressed bytes from the file
+  Reading,
+  /// Decompressing its deflate data
+  Decompressing,
+  /// Checking the CRC32 of its data against its footer
+  CheckingCrc,
+}
+
+/// Where the block a failure is about is, to find it in the file:
+///
+/// index: number of the block among the blocks with data in file order,
+/// from 0, like with `BgzfReader::block_metadata`,
+/// compressed_offset: offset in the file where the block starts,
+/// uncompressed_offset: offset in the data where its data starts,
+/// operation: what was being done with the block,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockContext {
+  pub index: u64,
+  pub compressed_offset: u64,
+  pub uncompressed_offset: u64,
+  pub operation: BlockOperation,
+}
+
+impl BlockContext {
+  /// Context of `block` for the failure of `operation`, the reader sets the
+  /// index, as it knows the blocks before it
+  pub(crate) fn new(block: &BgzfBlock, operation: BlockOperation) -> BlockContext {
+    BlockContext {
+      index: 0,
+      compressed_offset: block.block_offset,
+      uncompressed_offset: block.input_offset,
+      operation,
+    }
+  }
+}
+
+impl fmt::Display for BlockContext {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "block {} at compressed offset {} (uncompressed offset {})",
+      self.index, self.compressed_offset, self.uncompressed_offset
+    )
+  }
+}
+
+/// Reasons reading a bgzf file fails. Offsets in the file are compressed
+/// offsets, where a block starts, and offsets in the data uncompressed ones.
+#[derive(Debug)]
+pub enum BgzfError {
+  /// Reading the file failed, outside of reading a block
+  Io(io::Error),
+  /// Reading the block failed
+  ReadFailed {
+    block: BlockContext,
+    source: io::Error,
+  },
+  /// The file does not start with a BGZF block, though it may be gzip
+  /// compressed, without the extra field giving the size of each block
+  NotBgzf { looks_like_gzip: bool },
+  /// The block at the offset does not start with a BGZF gzip header
+  InvalidHeader { offset: u64 },
+  /// The extra field of the block at the offset holds no valid BC subfield,
+  /// or a subfield longer than the field
+  BadSubfield { offset: u64 },
+  /// The footer of the block at the offset gives it an uncompressed length
+  /// it can not have, over the 65536 bytes a block holds or more than its
+  /// deflate data can decompress to
+  InvalidFooter {
+    offset: u64,
+    uncompressed_length: u32,
+  },
+  /// The header of the block at `offset` leaves no room for deflate data,
+  /// `data_length` being 0, while its footer gives it `uncompressed_length`
+  /// bytes of data
+  InvalidBlock {
+    offset: u64,
+    data_length: u32,
+    uncompressed_length: u32,
+  },
+  /// The header of the block does not have the size or uncompressed length
+  /// the index gives it, or is no longer a BGZF header
+  IndexMismatch { block: BlockContext },
+  /// The deflate data of the block is not valid deflate data, stops before
+  /// its end, or decompresses to more than the 65536 bytes a block holds:
+  /// the file is corrupt
+  CorruptDeflateStream {
+    block: BlockContext,
+    source: io::Error,
+  },
+  /// The deflate data of the block decompresses to `actual` bytes rather
+  /// than the `expected` ones its footer gives it: the file is corrupt, its
+  /// footer or its data
+  SizeMismatch {
+    block: BlockContext,
+    expected: u64,
+    actual: u64,
+    source: io::Error,
+  },
+  /// The buffer the block was to be decompressed into does not hold its
+  /// uncompressed length: a bug of this crate, the file is not to blame
+  InternalBufferError {
+    block: BlockContext,
+    source: io::Error,
+  },
+  /// The CRC32 of the data of the block is `actual` rather than the
+  /// `expected` one of its footer
+  ChecksumMismatch {
+    block: BlockContext,
+    expected: u32,
+    actual: u32,
+  },
+  /// The data ended after `read` of the `requested` bytes a read needing
+  /// all of them asked for, the data being sound
+  UnexpectedEof { requested: u64, read: u64 },
+  /// The offset is past the `len` bytes it is an offset in, the data, a
+  /// block, a buffer or the blocks of the file
+  OutOfRange { pos: u64, len: u64 },
+  /// A read of `len` bytes from `pos` ends past the largest offset a u64
+  /// holds
+  PositionOverflow { pos: u64, len: u64 },
+  /// No block starts at the offset of a virtual offset
+  NoBlockAt { offset: u64 },
+  /// The file does not end with the EOF marker, when it is required
+  MissingEofMarker,
+  /// The uncompressed data is longer than the limit set for loading it, or
+  /// than a buffer can be on the platform, `usize::MAX`
+  TooLarge { length: u64, limit: u64 },
+  /// The file goes past one of the limits set on the builder, whose value
+  /// is `max`
+  LimitExceeded { limit: ResourceLimit, max: u64 },
+  /// The blocks of the file end with one that can not be read
+  Truncated(Truncated),
+  /// The size in the header of the block at `offset` leads to bytes that are
+  /// not a block, nor the end of the file, while a block starts a few bytes
+  /// away, the size being wrong rather than the next block damaged
+  BlockChainBroken { offset: u64 },
+  /// The file departs from the spec, in strict mode
+  Anomaly(Anomaly),
+  /// The read goes across data lost to damage, when opened with `recover`
+  DataGap(DataGap),
+  /// The progress callback stopped the open
+  Cancelled(IndexingCancelled),
+  /// The index given can not be the file's
+  Index(IndexError),
+  /// The .gzi index given can not be used for the file
+  Gzi(GziError),
+  /// The .fai index can not be used, or the region fetched is not in it
+  Fai(FaiError),
+  /// The line index can not be used for the file, or the line is not in it
+  LineIndex(LineIndexError),
+  /// The tabix index can not be used, or the query or a line it reads is
+  /// not valid
+  #[cfg(feature = "tabix")]
+  Tabix(TabixError),
+  /// The gzip file read by `GzipRandomReader` is corrupt, or its index can
+  /// not be used for it
+  #[cfg(feature = "gzip")]
+  Gzip(GzipError),
+}
+
+/// The io and deflate errors behind a failure are its source, while its
+/// message tells which block it was. `Io` and the errors of their own type
+/// are only wrapped, so their source is their own.
+impl Error for BgzfError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      BgzfError::Io(error) => error.source(),
+      BgzfError::ReadFailed { source, .. }
+      | BgzfError::CorruptDeflateStream { source, .. }
+      | BgzfError::SizeMismatch { source, .. }
+      | BgzfError::InternalBufferError { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for BgzfError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      BgzfError::Io(error) => write!(f, "{}", error),
+      BgzfError::ReadFailed { block, .. } => write!(f, "Reading {} failed", block),
+      BgzfError::NotBgzf {
+        looks_like_gzip: true,
+      } => write!(
+        f,
+        "The file is gzip but not BGZF compressed, recompress it with bgzip"
+      ),
+      BgzfError::NotBgzf {
+        looks_like_gzip: false,
+      } => write!(f, "The file is not BGZF compressed"),
+      BgzfError::InvalidHeader { offset } => write!(
+        f,
+        "The block at offset {} does not start with a BGZF header",
+        offset
+      ),
+      BgzfError::BadSubfield { offset } => write!(
+        f,
+        "The block at offset {} has a bad subfield in its extra field",
+        offset
+      ),
+      BgzfError::InvalidFooter {
+        offset,
+        uncompressed_length,
+      } => write!(
+        f,
+        "The footer of the block at offset {} gives it an impossible uncompressed length of {}",
+        offset, uncompressed_length
+      ),
+      BgzfError::InvalidBlock {
+        offset,
+        data_length,
+        uncompressed_length,
+      } => write!(
+        f,
+        "The block at offset {} has {} bytes of deflate data for {} bytes of data",
+        offset, data_length, uncompressed_length
+      ),
+      BgzfError::IndexMismatch { block } => {
+        write!(f, "The header of {} does not match the index", block)
+      }
+      BgzfError::CorruptDeflateStream { block, .. } => {
+        write!(f, "The deflate data of {} is corrupt", block)
+      }
+      BgzfError::SizeMismatch {
+        block,
+        expected,
+        actual,
+        ..
+      } => write!(
+        f,
+        "The deflate data of {} decompresses to {} bytes instead of the {} of its footer",
+        block, actual, expected
+      ),
+      BgzfError::InternalBufferError { block, .. } => write!(
+        f,
+        "The buffer for decompressing {} does not fit its data, this is a bug",
+        block
+      ),
+      BgzfError::ChecksumMismatch {
+        block,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "The CRC32 of {} is {:08x} instead of {:08x}",
+        block, actual, expected
+      ),
+      BgzfError::UnexpectedEof { requested, read } => write!(
+        f,
+        "The data ended after {} of the {} bytes asked for",
+        read, requested
+      ),
+      BgzfError::OutOfRange { pos, len } => {
+        write!(f, "Offset {} is past the {} bytes available", pos, len)
+      }
+      BgzfError::PositionOverflow { pos, len } => write!(
+        f,
+        "A read of {} bytes from offset {} goes past the largest offset",
+        len, pos
+      ),
+      BgzfError::NoBlockAt { offset } => {
+        write!(f, "No block starts at compressed offset {}", offset)
+      }
+      BgzfError::MissingEofMarker => write!(f, "File does not end with the BGZF EOF marker"),
+      BgzfError::TooLarge { length, limit } => write!(
+        f,
+        "The {} bytes of uncompressed data are more than the limit of {}",
+        length, limit
+      ),
+      BgzfError::LimitExceeded { limit, max } => {
+        let what = match limit {
+          ResourceLimit::Blocks => "blocks",
+          ResourceLimit::UncompressedLength => "bytes of uncompressed data",
+          ResourceLimit::Allocation => "bytes allocated at once",
+        };
+        write!(f, "The file goes past the limit of {} {}", max, what)
+      }
+      BgzfError::Truncated(error) => write!(f, "{}", error),
+      BgzfError::BlockChainBroken { offset } => write!(
+        f,
+        "The size of the block at offset {} does not lead to another block",
+        offset
+      ),
+      BgzfError::Anomaly(error) => write!(f, "{}", error),
+      BgzfError::DataGap(error) => write!(f, "{}", error),
+      BgzfError::Cancelled(error) => write!(f, "{}", error),
+      BgzfError::Index(error) => write!(f, "{}", error),
+      BgzfError::Gzi(error) => write!(f, "{}", error),
+      BgzfError::Fai(error) => write!(f, "{}", error),
+      BgzfError::LineIndex(error) => write!(f, "{}", error),
+      #[cfg(feature = "tabix")]
+      BgzfError::Tabix(error) => write!(f, "{}", error),
+      #[cfg(feature = "gzip")]
+      BgzfError::Gzip(error) => write!(f, "{}", error),
+    }
+  }
+}
+
+impl BgzfError {
+  /// Returns the block the failure is about and what was being done with it,
+  /// for the failures of reading and decompressing a block
+  pub fn block_context(&self) -> Option<&BlockContext> {
+    match self {
+      BgzfError::ReadFailed { block, .. }
+      | BgzfError::IndexMismatch { block }
+      | BgzfError::CorruptDeflateStream { block, .. }
+      | BgzfError::SizeMismatch { block, .. }
+      | BgzfError::InternalBufferError { block, .. }
+      | BgzfError::ChecksumMismatch { block, .. } => Some(block),
+      _ => None,
+    }
+  }
+
+  /// Sets the index of the block the failure is about, if any
+  pub(crate) fn with_block_index(mut self, index: impl Fn(u64) -> u64) -> BgzfError {
+    match &mut self {
+      BgzfError::ReadFailed { block, .. }
+      | BgzfError::IndexMismatch { block }
+      | BgzfError::CorruptDeflateStream { block, .. }
+      | BgzfError::SizeMismatch { block, .. }
+      | BgzfError::InternalBufferError { block, .. }
+      | BgzfError::ChecksumMismatch { block, .. } => {
+        block.index = index(block.compressed_offset);
+      }
+      _ => {}
+    }
+    self
+  }
+}
+
+impl From<io::Error> for BgzfError {
+  fn from(error: io::Error) -> BgzfError {
+    BgzfError::Io(error)
+  }
+}
+
+impl From<Truncated> for BgzfError {
+  fn from(error: Truncated) -> BgzfError {
+    BgzfError::Truncated(error)
+  }
+}
+
+impl From<Anomaly> for BgzfError {
+  fn from(error: Anomaly) -> BgzfError {
+    BgzfError::Anomaly(error)
+  }
+}
+
+impl From<DataGap> for BgzfError {
+  fn from(error: DataGap) -> BgzfError {
+    BgzfError::DataGap(error)
+  }
+}
+
+impl From<IndexingCancelled> for BgzfError {
+  fn from(error: IndexingCancelled) -> BgzfError {
+    BgzfError::Cancelled(error)
+  }
+}
+
+impl From<IndexError> for BgzfError {
+  fn from(error: IndexError) -> BgzfError {
+    BgzfError::Index(error)
+  }
+}
+
+impl From<GziError> for BgzfError {
+  fn from(error: GziError) -> BgzfError {
+    BgzfError::Gzi(error)
+  }
+}
+
+impl From<FaiError> for BgzfError {
+  fn from(error: FaiError) -> BgzfError {
+    BgzfError::Fai(error)
+  }
+}
+
+impl From<LineIndexError> for BgzfError {
+  fn from(error: LineIndexError) -> BgzfError {
+    BgzfError::LineIndex(error)
+  }
+}
+
+#[cfg(feature = "tabix")]
+impl From<TabixError> for BgzfError {
+  fn from(error: TabixError) -> BgzfError {
+    BgzfError::Tabix(error)
+  }
+}
+
+#[cfg(feature = "gzip")]
+impl From<GzipError> for BgzfError {
+  fn from(error: GzipError) -> BgzfError {
+    BgzfError::Gzip(error)
+  }
+}
+
+/// Errors a read turns into for the std `Read` implementation, io errors
+/// are kept as they are, the failed reads of a block keep their kind, data
+/// ending early, short of what was asked for or within a block cut off, is
+/// `UnexpectedEof` and the others are invalid data
+impl From<BgzfError> for io::Error {
+  fn from(error: BgzfError) -> io::Error {
+    match error {
+      BgzfError::Io(error) => error,
+      BgzfError::ReadFailed { ref source, .. } => io::Error::new(source.kind(), error),
+      BgzfError::UnexpectedEof { .. } | BgzfError::Truncated(_) => {
+        io::Error::new(io::ErrorKind::UnexpectedEof, error)
+      }
+      BgzfError::PositionOverflow { .. } => io::Error::new(io::ErrorKind::InvalidInput, error),
+      BgzfError::InternalBufferError { .. } => io::Error::other(error),
+      error => io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_io_error() {
+    let error = BgzfError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "short"));
+    assert_eq!("short", error.to_string());
+    assert_eq!(io::ErrorKind::UnexpectedEof, io::Error::from(error).kind());
+
+    let error = io::Error::from(BgzfError::InvalidHeader { offset: 28 });
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    assert_eq!(
+      "The block at offset 28 does not start with a BGZF header",
+      error.to_string()
+    );
+    assert!(matches!(
+      error
+        .into_inner()
+        .unwrap()
+        .downcast::<BgzfError>()
+        .as_deref(),
+      Ok(BgzfError::InvalidHeader { offset: 28 })
+    ));
+  }
+
+  #[test]
+  fn test_sources() {
+    let block = BlockContext {
+      index: 1,
+      compressed_offset: 20_000,
+      uncompressed_offset: 65280,
+      operation: BlockOperation::Decompressing,
+    };
+    let error = BgzfError::CorruptDeflateStream {
+      block,
+      source: io::Error::new(io::ErrorKind::InvalidData, "bad deflate data"),
+    };
+    assert_eq!(
+      "The deflate data of block 1 at compressed offset 20000 (uncompressed offset 65280) is corrupt",
+      error.to_string()
+    );
+    assert_eq!(Some(&block), error.block_context());
+    let source = error.source().unwrap().downcast_ref::<io::Error>().unwrap();
+    assert_eq!("bad deflate data", source.to_string());
+
+    let error = BgzfError::ReadFailed {
+      block: BlockContext {
+        index: 0,
+        compressed_offset: 1000,
+        uncompressed_offset: 0,
+        operation: BlockOperation::Reading,
+      },
+      source: io::Error::new(io::ErrorKind::UnexpectedEof, "short read"),
+    };
+    assert!(error.source().unwrap().is::<io::Error>());
+    let error = io::Error::from(error);
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    assert_eq!(
+      "Reading block 0 at compressed offset 1000 (uncompressed offset 0) failed",
+      error.to_string()
+    );
+
+    //wrapped errors are their own source
+    let error = BgzfError::from(io::Error::other("disk gone"));
+    assert!(error.source().is_none());
+    assert!(BgzfError::MissingEofMarker.source().is_none());
+    assert!(BgzfError::MissingEofMarker.block_context().is_none());
+  }
+}
@@ -0,0 +1,364 @@
+//! Where the parallel work of a reader or a writer runs, see
+//! `BgzfReaderBuilder::thread_pool` and `BgzfWriterBuilder::thread_pool`.
+
+#[cfg(feature = "rayon")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "rayon")]
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// Runs the tasks every parallel code path splits its work into
+#[derive(Clone, Debug, Default)]
+pub(crate) enum Executor {
+  /// Scoped threads started by every call, one per task, and the global
+  /// rayon pool for the reads decompressing blocks in parallel
+  #[default]
+  Threads,
+  /// The threads of a rayon pool given to the builder, which runs as many
+  /// tasks at once as it has threads
+  #[cfg(feature = "rayon")]
+  Pool(Arc<rayon::ThreadPool>),
+}
+
+impl Executor {
+  /// Runs `task` with every number from 0 to `tasks` excluded in parallel,
+  /// and returns once every task is done with the result of each in order,
+  /// or the payload of its panic. Called from a thread of the pool, that
+  /// thread runs tasks too while it waits.
+  pub(crate) fn run<T: Send>(
+    &self,
+    tasks: usize,
+    task: impl Fn(usize) -> T + Sync,
+  ) -> Vec<thread::Result<T>> {
+    match self {
+      #[cfg(feature = "rayon")]
+      Executor::Pool(pool) => in_pool(pool, tasks, &task, || ()).1,
+      Executor::Threads => in_threads(tasks, &task, || ()).1,
+    }
+  }
+
+  /// Same as `run`, with `main` running on the calling thread meanwhile,
+  /// whose result is returned with the tasks' ones. Called from a thread of
+  /// the pool, where the tasks waiting for `main` could hold the only threads
+  /// free to run them, the tasks run on scoped threads.
+  pub(crate) fn run_beside<T: Send, R>(
+    &self,
+    tasks: usize,
+    task: impl Fn(usize) -> T + Sync,
+    main: impl FnOnce() -> R,
+  ) -> (R, Vec<thread::Result<T>>) {
+    match self {
+      #[cfg(feature = "rayon")]
+      Executor::Pool(pool) if pool.current_thread_index().is_none() => {
+        in_pool(pool, tasks, &task, main)
+      }
+      _ => in_threads(tasks, &task, main),
+    }
+  }
+
+  /// Runs `op`, which uses rayon's parallel iterators, in the pool, or in the
+  /// global rayon pool by default
+  #[cfg(feature = "rayon")]
+  pub(crate) fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+    match self {
+      Executor::Threads => op(),
+      Executor::Pool(pool) => pool.install(op),
+    }
+  }
+}
+
+fn in_threads<T: Send, R>(
+  tasks: usize,
+  task: &(impl Fn(usize) -> T + Sync),
+  main: impl FnOnce() -> R,
+) -> (R, Vec<thread::Result<T>>) {
+  thread::scope(|scope| {
+    let handles = (0..tasks)
+      .map(|number| scope.spawn(move || task(number)))
+      .collect::<Vec<_>>();
+    let main_result = main();
+    (
+      main_result,
+      handles.into_iter().map(|handle| handle.join()).collect(),
+    )
+  })
+}
+
+#[cfg(feature = "rayon")]
+fn in_pool<T: Send, R>(
+  pool: &rayon::ThreadPool,
+  tasks: usize,
+  task: &(impl Fn(usize) -> T + Sync),
+  main: impl FnOnce() -> R,
+) -> (R, Vec<thread::Result<T>>) {
+  let results = (0..tasks).map(|_| Mutex::new(None)).collect::<Vec<_>>();
+  let main_result = pool.in_place_scope(|scope| {
+    for (number, result) in results.iter().enumerate() {
+      scope.spawn(move |_scope| {
+        let task_result = panic::catch_unwind(AssertUnwindSafe(|| task(number)));
+        *result.lock().unwrap_or_else(PoisonError::into_inner) = Some(task_result);
+      });
+    }
+    main()
+  });
+  let results = results
+    .into_iter()
+    .map(|result| {
+      result
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .expect("Every task ran before the scope ended")
+    })
+    .collect();
+  (main_result, results)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_threads() {
+    let results = Executor::Threads.run(4, |number| number * 2);
+    let results = results
+      .into_iter()
+      .map(|result| result.unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(vec![0, 2, 4, 6], results);
+    let (main_result, results) = Executor::Threads.run_beside(0, |number| number, || "main");
+    assert_eq!(("main", 0), (main_result, results.len()));
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_pool() {
+    let pool = rayon::ThreadPoolBuilder::new()
+      .num_threads(1)
+      .thread_name(|_index| String::from("executor-test"))
+      .build()
+      .unwrap();
+    let pool = Arc::new(pool);
+    let executor = Executor::Pool(Arc::clone(&pool));
+    let (main_thread, results) = executor.run_beside(
+      3,
+      |number| {
+        if number == 1 {
+          panic!("task 1");
+        }
+        thread::current().name().map(String::from)
+      },
+      || thread::current().id(),
+    );
+    assert_eq!(thread::current().id(), main_thread);
+    assert_eq!(3, results.len());
+    assert!(results[1].is_err());
+    for number in [0, 2] {
+      assert_eq!(
+        Some("executor-test"),
+        results[number].as_ref().unwrap().as_deref()
+      );
+    }
+
+    //from the pool's only thread, which runs the tasks itself
+    let names =
+      pool.install(|| executor.run(2, |_number| thread::current().name().map(String::from)));
+    assert!(names
+      .into_iter()
+      .all(|name| name.unwrap().as_deref() == Some("executor-test")));
+    //while it waits for the tasks, they do not wait for it
+    let (received, results) = pool.install(|| {
+      let (sender, receiver) = std::sync::mpsc::channel();
+      executor.run_beside(
+        2,
+        move |number| sender.send(number).unwrap(),
+        || receiver.iter().take(2).sum::<usize>(),
+      )
+    });
+    assert_eq!(1, received);
+    assert!(results.iter().all(Result::is_ok));
+  }
+
+  /// A 1-thread pool whose thread is held until something is sent to the
+  /// sender returned
+  #[cfg(feature = "rayon")]
+  fn held_pool() -> (Arc<rayon::ThreadPool>, std::sync::mpsc::Sender<()>) {
+    let pool = rayon::ThreadPoolBuilder::new()
+      .num_threads(1)
+      .thread_name(|_index| String::from("bgzf-test-pool"))
+      .build()
+      .unwrap();
+    let (release, held) = std::sync::mpsc::channel::<()>();
+    pool.spawn(move || held.recv().unwrap());
+    (Arc::new(pool), release)
+  }
+
+  /// Runs `f`, which can not finish while the pool's thread is held when it
+  /// runs on the pool, then lets the pool go on and returns what `f` returns
+  #[cfg(feature = "rayon")]
+  fn on_held_pool<T: Send>(
+    release: &std::sync::mpsc::Sender<()>,
+    f: impl FnOnce() -> T + Send,
+  ) -> T {
+    thread::scope(|scope| {
+      let handle = scope.spawn(f);
+      thread::sleep(std::time::Duration::from_millis(50));
+      assert!(!handle.is_finished(), "ran without the pool");
+      release.send(()).unwrap();
+      handle.join().unwrap()
+    })
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_reader_on_pool() {
+    use crate::fixtures::{make_bgzf, write_temp};
+    use crate::BgzfReader;
+
+    //data that barely compresses, so that the file is large enough to be
+    //indexed in parallel
+    let mut state = 12345u32;
+    let content = (0..1_500_000)
+      .map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 24) as u8
+      })
+      .collect::<Vec<_>>();
+    let path = write_temp("executor_reader", &make_bgzf(&content, 60_000)).unwrap();
+
+    let (pool, release) = held_pool();
+    let reader = on_held_pool(&release, || {
+      BgzfReader::builder()
+        .threads(4)
+        .thread_pool(Arc::clone(&pool))
+        .open(path.clone())
+        .unwrap()
+    });
+    assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+
+    let (pool, release) = held_pool();
+    let reader = BgzfReader::builder()
+      .thread_pool(Arc::clone(&pool))
+      .open(path.clone())
+      .unwrap();
+    let data = on_held_pool(&release, || reader.decompress_all_parallel(4).unwrap());
+    assert_eq!(content, data);
+
+    let (pool, release) = held_pool();
+    let reader = BgzfReader::builder()
+      .thread_pool(Arc::clone(&pool))
+      .open(path.clone())
+      .unwrap();
+    let out = on_held_pool(&release, || {
+      let mut out = Vec::new();
+      reader
+        .decompress_to_writer_parallel(4, 2, &mut out)
+        .unwrap();
+      out
+    });
+    assert_eq!(content, out);
+
+    let (pool, release) = held_pool();
+    let reader = BgzfReader::builder()
+      .thread_pool(Arc::clone(&pool))
+      .open(path.clone())
+      .unwrap();
+    let report = on_held_pool(&release, || reader.verify(4).unwrap());
+    assert!(report.is_sound());
+
+    //a read of many whole blocks decompresses them in the pool
+    let (pool, release) = held_pool();
+    let reader = BgzfReader::builder()
+      .thread_pool(Arc::clone(&pool))
+      .open(path.clone())
+      .unwrap();
+    let data = on_held_pool(&release, || {
+      let mut data = vec![0; content.len()];
+      reader.read_to(&mut data).unwrap();
+      data
+    });
+    assert_eq!(content, data);
+
+    //the blocks are handed to the callback on the pool's thread
+    let names = std::sync::Mutex::new(std::collections::BTreeSet::new());
+    let length = std::sync::atomic::AtomicUsize::new(0);
+    reader
+      .for_each_block_parallel(4, |_offset, data| {
+        let name = thread::current().name().map(String::from);
+        names.lock().unwrap().insert(name);
+        length.fetch_add(data.len(), std::sync::atomic::Ordering::SeqCst);
+      })
+      .unwrap();
+    assert_eq!(content.len(), length.into_inner());
+    assert_eq!(
+      vec![Some(String::from("bgzf-test-pool"))],
+      names.into_inner().unwrap().into_iter().collect::<Vec<_>>()
+    );
+
+    //called from the pool's only thread, the work does not wait for it
+    let data = pool.install(|| reader.decompress_all_parallel(4).unwrap());
+    assert_eq!(content, data);
+    let mut out = Vec::new();
+    pool.install(|| {
+      reader
+        .decompress_to_writer_parallel(4, 2, &mut out)
+        .unwrap()
+    });
+    assert_eq!(content, out);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[cfg(all(feature = "rayon", feature = "digest"))]
+  #[test]
+  fn test_digest_on_pool() {
+    use crate::BgzfReader;
+
+    let (pool, release) = held_pool();
+    let reader = BgzfReader::builder()
+      .thread_pool(pool)
+      .open(String::from("bgzf_test.bgz"))
+      .unwrap();
+    let md5 = on_held_pool(&release, || reader.md5_hex().unwrap());
+    assert_eq!("4f21e461da8577fe57cc534b8d22840c", md5);
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_writer_on_pool() {
+    use crate::{BgzfReader, BgzfWriter};
+    use std::io::Write;
+
+    let content = (0..400_000u32)
+      .map(|i| (i * 7919 % 251) as u8)
+      .collect::<Vec<_>>();
+    let serial = {
+      let mut writer = BgzfWriter::builder().build(Vec::new()).unwrap();
+      writer.write_all(&content).unwrap();
+      writer.finish().unwrap()
+    };
+    let (pool, release) = held_pool();
+    let compressed = on_held_pool(&release, || {
+      let mut writer = BgzfWriter::builder()
+        .threads(4)
+        .thread_pool(Arc::clone(&pool))
+        .build(Vec::new())
+        .unwrap();
+      writer.write_all(&content).unwrap();
+      writer.finish().unwrap()
+    });
+    assert_eq!(serial, compressed);
+
+    //writing from the pool's only thread, which compresses the blocks itself
+    let compressed = pool.install(|| {
+      let mut writer = BgzfWriter::builder()
+        .threads(4)
+        .thread_pool(Arc::clone(&pool))
+        .build(Vec::new())
+        .unwrap();
+      writer.write_all(&content).unwrap();
+      writer.finish().unwrap()
+    });
+    assert_eq!(serial, compressed);
+    let reader = BgzfReader::from_bytes(compressed).unwrap();
+    assert_eq!(content, reader.decompress_all_parallel(2).unwrap());
+  }
+}
@@ -0,0 +1,281 @@
+//! Region fetches of a bgzipped FASTA file with its .fai index, see
+//! `FaiReader`.
+//!
+//! A .fai index, as `samtools faidx` writes it, has a tab-separated line for
+//! every sequence: its name, length, offset of its first base in the
+//! uncompressed file, number of bases on each line and number of bytes of
+//! each line, the newline included. A FASTQ index has the offset of the
+//! qualities in a sixth column, which is left out.
+
+use crate::{buffer_length, BgzfError, BgzfReader};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+/// Reasons a .fai index or a fetch with it can not be used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FaiError {
+  /// Line `line` (from 1) of the index does not have 5 or 6 columns of a
+  /// name and numbers, or its line lengths do not fit its sequence
+  Malformed { line: usize },
+  /// The index has no sequence `name`
+  UnknownSequence { name: String },
+  /// The 1-based, inclusive `start..=end` is not within the `length` bases
+  /// of the sequence, or starts after its end
+  OutOfRange {
+    name: String,
+    start: u64,
+    end: u64,
+    length: u64,
+  },
+  /// The bases read for `name` do not have the line lengths of the index
+  LineMismatch { name: String },
+}
+
+impl Error for FaiError {}
+
+impl fmt::Display for FaiError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FaiError::Malformed { line } => write!(f, "Line {} of the .fai index is malformed", line),
+      FaiError::UnknownSequence { name } => {
+        write!(f, "The .fai index has no sequence {}", name)
+      }
+      FaiError::OutOfRange {
+        name,
+        start,
+        end,
+        length,
+      } => write!(
+        f,
+        "{}:{}-{} is not within the {} bases of {}",
+        name, start, end, length, name
+      ),
+      FaiError::LineMismatch { name } => write!(
+        f,
+        "The lines of {} do not have the lengths of the .fai index",
+        name
+      ),
+    }
+  }
+}
+
+/// A sequence of the .fai index
+///
+/// Fields description:
+///
+/// length: number of bases,
+/// offset: uncompressed offset of the first base,
+/// line_bases: number of bases on each line, the last one can have fewer,
+/// line_width: number of bytes of each line, the newline included,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FaiEntry {
+  length: u64,
+  offset: u64,
+  line_bases: u64,
+  line_width: u64,
+}
+
+impl FaiEntry {
+  /// Returns the uncompressed offset of the 0-based `base`, u64::MAX when
+  /// it would be past it
+  fn base_offset(&self, base: u64) -> u64 {
+    (base / self.line_bases)
+      .saturating_mul(self.line_width)
+      .saturating_add(self.offset)
+      .saturating_add(base % self.line_bases)
+  }
+}
+
+/// Parses the content of a .fai index into its sequences by name, with
+/// their names in file order
+fn read_fai(fai: &str) -> Result<(Vec<String>, HashMap<String, FaiEntry>), FaiError> {
+  let mut names = Vec::new();
+  let mut entries = HashMap::new();
+  for (index, line) in fai.lines().enumerate() {
+    let malformed = FaiError::Malformed { line: index + 1 };
+    let columns = line.split('\t').collect::<Vec<_>>();
+    if columns.len() != 5 && columns.len() != 6 || columns[0].is_empty() {
+      return Err(malformed);
+    }
+    let mut numbers = columns[1..5].iter().map(|column| column.parse::<u64>());
+    let mut number = || numbers.next().unwrap().map_err(|_error| malformed.clone());
+    let entry = FaiEntry {
+      length: number()?,
+      offset: number()?,
+      line_bases: number()?,
+      line_width: number()?,
+    };
+    //only an empty sequence has no bases per line
+    if entry.line_bases == 0 && entry.length > 0 || entry.line_width < entry.line_bases {
+      return Err(malformed);
+    }
+    if entries.insert(columns[0].to_string(), entry).is_some() {
+      return Err(malformed);
+    }
+    names.push(columns[0].to_string());
+  }
+  Ok((names, entries))
+}
+
+/// A bgzipped FASTA file with its .fai index, to fetch the bases of a region
+///
+/// Fields description:
+///
+/// reader: reader of the FASTA file,
+/// names: names of the sequences, in index order,
+/// entries: sequences of the index by name,
+pub struct FaiReader {
+  reader: BgzfReader,
+  names: Vec<String>,
+  entries: HashMap<String, FaiEntry>,
+}
+
+/// Below are the steps to fetch regions of a bgzipped FASTA file, open it
+/// with a `BgzfReader`, give the reader and the path of the .fai index to
+/// `new`, then call `fetch` with 1-based, inclusive coordinates. The reader
+/// is only read at the offsets `fetch` computes, its position is not used.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{BgzfReader, FaiReader};
+///
+/// let reader = BgzfReader::new(String::from("tests/data/sequences.fa.gz")).unwrap();
+/// let fasta = FaiReader::new(reader, String::from("tests/data/sequences.fa.gz.fai")).unwrap();
+/// assert_eq!(Some(250), fasta.sequence_length("chr1"));
+/// let bases = fasta.fetch("chr1", 59, 62).unwrap();
+/// assert_eq!(4, bases.len());
+/// ```
+impl FaiReader {
+  /// Reads the .fai index at `fai_path` of the FASTA file `reader` reads
+  pub fn new(reader: BgzfReader, fai_path: String) -> Result<FaiReader, BgzfError> {
+    let (names, entries) = read_fai(&fs::read_to_string(fai_path)?)?;
+    Ok(FaiReader {
+      reader,
+      names,
+      entries,
+    })
+  }
+
+  /// Returns the names of the sequences, in index order
+  pub fn sequence_names(&self) -> &[String] {
+    &self.names
+  }
+
+  /// Returns the number of bases of sequence `name`, None when the index
+  /// does not have it
+  pub fn sequence_length(&self, name: &str) -> Option<u64> {
+    self.entries.get(name).map(|entry| entry.length)
+  }
+
+  /// Returns the bases `start` to `end` of sequence `name`, 1-based and
+  /// inclusive, without the newlines between lines. Both must be within the
+  /// sequence, `start` no further than `end`, else `FaiError::OutOfRange`,
+  /// and a whole sequence is `1` to its length. A region running to the end
+  /// of the last line, shorter than the others, reads like any other.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader, FaiError, FaiReader};
+  ///
+  /// let reader = BgzfReader::new(String::from("tests/data/sequences.fa.gz")).unwrap();
+  /// let fasta = FaiReader::new(reader, String::from("tests/data/sequences.fa.gz.fai")).unwrap();
+  /// assert_eq!(7, fasta.fetch("mito", 1, 7).unwrap().len());
+  /// assert!(matches!(
+  ///   fasta.fetch("mito", 1, 8),
+  ///   Err(BgzfError::Fai(FaiError::OutOfRange { length: 7, .. }))
+  /// ));
+  /// ```
+  pub fn fetch(&self, name: &str, start: u64, end: u64) -> Result<Vec<u8>, BgzfError> {
+    let entry = self
+      .entries
+      .get(name)
+      .ok_or_else(|| FaiError::UnknownSequence {
+        name: name.to_string(),
+      })?;
+    if start == 0 || start > end || end > entry.length {
+      return Err(BgzfError::from(FaiError::OutOfRange {
+        name: name.to_string(),
+        start,
+        end,
+        length: entry.length,
+      }));
+    }
+
+    let first = entry.base_offset(start - 1);
+    let length = entry.base_offset(end - 1) + 1 - first;
+    //an index of another file can point far past the data
+    let available = self
+      .reader
+      .total_uncompressed_length()
+      .saturating_sub(first);
+    if length > available {
+      return Err(BgzfError::UnexpectedEof {
+        requested: length,
+        read: available,
+      });
+    }
+    let mut data = vec![0; buffer_length(length)?];
+    let mut read = 0;
+    while read < data.len() {
+      match self
+        .reader
+        .read_at(first + read as u64, &mut data[read..])?
+      {
+        0 => {
+          return Err(BgzfError::UnexpectedEof {
+            requested: length,
+            read: read as u64,
+          })
+        }
+        bytes => read += bytes,
+      }
+    }
+
+    data.retain(|&byte| byte != b'\n' && byte != b'\r');
+    if data.len() as u64 != end - start + 1 {
+      return Err(BgzfError::from(FaiError::LineMismatch {
+        name: name.to_string(),
+      }));
+    }
+    Ok(data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_read_fai() {
+    let (names, entries) =
+      read_fai("chr1\t250\t6\t60\t61\nread1\t8\t7\t8\t9\t17\nempty\t0\t30\t0\t0\n").unwrap();
+    assert_eq!(["chr1", "read1", "empty"], &names[..]);
+    let chr1 = entries["chr1"];
+    assert_eq!(
+      FaiEntry {
+        length: 250,
+        offset: 6,
+        line_bases: 60,
+        line_width: 61
+      },
+      chr1
+    );
+    assert_eq!(6, chr1.base_offset(0));
+    assert_eq!(65, chr1.base_offset(59));
+    assert_eq!(67, chr1.base_offset(60));
+    assert_eq!(6 + 4 * 61 + 9, chr1.base_offset(249));
+
+    for (fai, line) in [
+      ("chr1\t250\t6\t60\n", 1),
+      ("chr1\t250\t6\t60\t61\nchr2\t-1\t6\t60\t61\n", 2),
+      ("\t250\t6\t60\t61\n", 1),
+      ("chr1\t250\t6\t0\t0\n", 1),
+      ("chr1\t250\t6\t60\t59\n", 1),
+      ("chr1\t250\t6\t60\t61\nchr1\t250\t6\t60\t61\n", 2),
+    ] {
+      assert_eq!(Err(FaiError::Malformed { line }), read_fai(fai));
+    }
+  }
+}
@@ -0,0 +1,285 @@
+//! C API of the reader, for tools written in other languages. Only with the
+//! `ffi` feature, the library then being built as a cdylib and a staticlib
+//! too, and `include/bgzf_rs.h` declaring the functions.
+//!
+//! A file is opened with `bgzf_rs_open`, which returns a handle the other
+//! functions take, until `bgzf_rs_close` frees it. A handle reads from its
+//! own position and must not be used by two threads at once, while separate
+//! handles can be used from any thread.
+//!
+//! Failures return NULL or -1, and `bgzf_rs_last_error` then gives the
+//! message. The message belongs to the handle, the caller must not free it,
+//! and it stays valid until the next call with the handle or its close. A
+//! panic inside the library is caught and reported as a failure rather than
+//! unwinding into the caller.
+
+use crate::BgzfReader;
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// A file opened with `bgzf_rs_open`
+///
+/// Fields description:
+///
+/// reader: reader of the file,
+/// last_error: message of the last failure of a call with the handle, None
+/// once a call succeeds,
+pub struct BgzfHandle {
+  reader: BgzfReader,
+  last_error: Option<CString>,
+}
+
+thread_local! {
+  /// Message of the last `bgzf_rs_open` of the thread which failed, no
+  /// handle being there to hold it
+  static OPEN_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Turns `message` into a C string, its NUL bytes, if any, replaced
+fn c_message(message: String) -> CString {
+  CString::new(message.replace('\0', "\\0")).unwrap_or_default()
+}
+
+/// Returns the message of a caught panic
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+  let reason = match (
+    payload.downcast_ref::<&str>(),
+    payload.downcast_ref::<String>(),
+  ) {
+    (Some(reason), _) => reason.to_string(),
+    (_, Some(reason)) => reason.clone(),
+    _ => String::from("unknown reason"),
+  };
+  format!("The bgzf library panicked: {}", reason)
+}
+
+/// Runs `call` with the reader of `handle`, records its error as the
+/// handle's last error and returns `failed` for it, or for a NULL handle or
+/// a panic. `handle` must be NULL or a handle of `bgzf_rs_open` not yet
+/// closed.
+unsafe fn with_handle<T, F>(handle: *mut BgzfHandle, failed: T, call: F) -> T
+where
+  F: FnOnce(&BgzfReader) -> Result<T, String>,
+{
+  let handle = match handle.as_mut() {
+    Some(handle) => handle,
+    None => return failed,
+  };
+  let result = panic::catch_unwind(AssertUnwindSafe(|| call(&handle.reader)))
+    .unwrap_or_else(|payload| Err(panic_message(payload)));
+  match result {
+    Ok(value) => {
+      handle.last_error = None;
+      value
+    }
+    Err(message) => {
+      handle.last_error = Some(c_message(message));
+      failed
+    }
+  }
+}
+
+/// Opens the bgzf file at `path`, a NUL-terminated string, and returns a
+/// handle to read it, to free with `bgzf_rs_close`. Returns NULL when the
+/// file can not be opened, `bgzf_rs_last_error(NULL)` then giving why.
+///
+/// # Safety
+/// `path` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_open(path: *const c_char) -> *mut BgzfHandle {
+  let opened = panic::catch_unwind(|| {
+    if path.is_null() {
+      return Err(String::from("The path is NULL"));
+    }
+    let path = CStr::from_ptr(path)
+      .to_str()
+      .map_err(|_error| String::from("The path is not valid UTF-8"))?;
+    BgzfReader::new(path.to_string()).map_err(|error| error.to_string())
+  })
+  .unwrap_or_else(|payload| Err(panic_message(payload)));
+  match opened {
+    Ok(reader) => {
+      OPEN_ERROR.with(|error| error.borrow_mut().take());
+      Box::into_raw(Box::new(BgzfHandle {
+        reader,
+        last_error: None,
+      }))
+    }
+    Err(message) => {
+      OPEN_ERROR.with(|error| *error.borrow_mut() = Some(c_message(message)));
+      ptr::null_mut()
+    }
+  }
+}
+
+/// Sets the position of `handle` to the uncompressed offset `pos`, returns 0,
+/// or -1 for a NULL handle. A position past the end of the data is allowed,
+/// reads from it returning 0, but those ending past the largest offset,
+/// 2^64 - 1, return -1.
+///
+/// # Safety
+/// `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_seek(handle: *mut BgzfHandle, pos: u64) -> c_int {
+  with_handle(handle, -1, |reader| {
+    reader.seek(pos);
+    Ok(0)
+  })
+}
+
+/// Reads up to `len` bytes from the position of `handle` into `buf` and
+/// moves the position past them. Returns the number of bytes read, 0 at the
+/// end of the data or when `len` is 0, or -1 on failure.
+///
+/// # Safety
+/// `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed, and
+/// `buf` must point to `len` writable bytes unless `len` is 0, which need not
+/// be initialized.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_read(handle: *mut BgzfHandle, buf: *mut u8, len: usize) -> i64 {
+  with_handle(handle, -1, |reader| {
+    if len == 0 {
+      return Ok(0);
+    }
+    if buf.is_null() {
+      return Err(String::from("The buffer is NULL"));
+    }
+    //the count returned must fit in an i64
+    let len = len.min(i64::MAX as usize);
+    //C callers hand over memory they have not initialized
+    let buf = slice::from_raw_parts_mut(buf.cast::<MaybeUninit<u8>>(), len);
+    match reader.read_into_uninit(buf) {
+      Ok(read) => Ok(read as i64),
+      Err(error) => Err(error.to_string()),
+    }
+  })
+}
+
+/// Returns the uncompressed length of the file of `handle`, 0 for a NULL
+/// handle.
+///
+/// # Safety
+/// `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_length(handle: *mut BgzfHandle) -> u64 {
+  with_handle(handle, 0, |reader| Ok(reader.total_uncompressed_length()))
+}
+
+/// Returns the message of the last call with `handle` which failed, or of
+/// the last `bgzf_rs_open` of the thread which failed for a NULL handle. NULL
+/// when the last call succeeded. The string belongs to the library: it must
+/// not be freed, and is valid until the next call with the handle, or the
+/// next `bgzf_rs_open` of the thread for a NULL handle.
+///
+/// # Safety
+/// `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_last_error(handle: *const BgzfHandle) -> *const c_char {
+  let message =
+    |error: &Option<CString>| error.as_ref().map_or(ptr::null(), |error| error.as_ptr());
+  match handle.as_ref() {
+    Some(handle) => message(&handle.last_error),
+    None => OPEN_ERROR.with(|error| message(&error.borrow())),
+  }
+}
+
+/// Closes `handle` and frees it, with its last error. NULL is ignored.
+///
+/// # Safety
+/// `handle` must be NULL or a handle of `bgzf_rs_open` not yet closed, which
+/// must not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn bgzf_rs_close(handle: *mut BgzfHandle) {
+  if !handle.is_null() {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, write_temp};
+  use std::fs;
+
+  fn last_error(handle: *const BgzfHandle) -> Option<String> {
+    let error = unsafe { bgzf_rs_last_error(handle) };
+    if error.is_null() {
+      return None;
+    }
+    Some(
+      unsafe { CStr::from_ptr(error) }
+        .to_string_lossy()
+        .into_owned(),
+    )
+  }
+
+  #[test]
+  fn test_read_through_the_c_api() {
+    let data = (0..1000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let path = write_temp("ffi_read", &make_bgzf(&data, 300)).unwrap();
+    let c_path = CString::new(path.clone()).unwrap();
+    unsafe {
+      let handle = bgzf_rs_open(c_path.as_ptr());
+      assert!(!handle.is_null());
+      assert_eq!(1000, bgzf_rs_length(handle));
+      assert_eq!(0, bgzf_rs_seek(handle, 250));
+      let mut buf = vec![0; 100];
+      assert_eq!(100, bgzf_rs_read(handle, buf.as_mut_ptr(), buf.len()));
+      assert_eq!(&data[250..350], &buf[..]);
+      //into memory never initialized, like a buffer from malloc
+      let mut uninit = Vec::<u8>::with_capacity(100);
+      assert_eq!(100, bgzf_rs_read(handle, uninit.as_mut_ptr(), 100));
+      uninit.set_len(100);
+      assert_eq!(&data[350..450], &uninit[..]);
+      assert_eq!(0, bgzf_rs_read(handle, ptr::null_mut(), 0));
+      assert_eq!(-1, bgzf_rs_read(handle, ptr::null_mut(), 10));
+      assert_eq!(Some(String::from("The buffer is NULL")), last_error(handle));
+      assert_eq!(0, bgzf_rs_seek(handle, 2000));
+      assert_eq!(None, last_error(handle));
+      assert_eq!(0, bgzf_rs_read(handle, buf.as_mut_ptr(), buf.len()));
+      bgzf_rs_close(handle);
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_failures_through_the_c_api() {
+    let missing = CString::new("/nonexistent/file.bgz").unwrap();
+    unsafe {
+      assert!(bgzf_rs_open(missing.as_ptr()).is_null());
+      assert!(last_error(ptr::null()).is_some());
+      assert!(bgzf_rs_open(ptr::null()).is_null());
+      assert_eq!(
+        Some(String::from("The path is NULL")),
+        last_error(ptr::null())
+      );
+
+      assert_eq!(-1, bgzf_rs_seek(ptr::null_mut(), 0));
+      assert_eq!(-1, bgzf_rs_read(ptr::null_mut(), ptr::null_mut(), 0));
+      assert_eq!(0, bgzf_rs_length(ptr::null_mut()));
+      bgzf_rs_close(ptr::null_mut());
+    }
+
+    let mut handle = BgzfHandle {
+      reader: BgzfReader::new(String::from("bgzf_test.bgz")).unwrap(),
+      last_error: None,
+    };
+    let failed = unsafe {
+      with_handle(&mut handle, -1, |_reader| -> Result<i32, String> {
+        panic!("broken")
+      })
+    };
+    assert_eq!(-1, failed);
+    assert_eq!(
+      Some(String::from("The bgzf library panicked: broken")),
+      last_error(&handle)
+    );
+    assert_eq!("a\\0b", c_message(String::from("a\0b")).to_str().unwrap());
+  }
+}
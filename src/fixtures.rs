@@ -0,0 +1,565 @@
+//! Functions generating BGZF data to test readers with, valid multi-block
+//! files as well as the usual kinds of damaged ones. They are built on
+//! `BgzfWriter`, and panic when the block size is not between 1 and 65280.
+//!
+//! # Example
+//! ```
+//! use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+//! use bgzf_rust_reader::BgzfReader;
+//!
+//! let bgzf = make_bgzf(b"This is just a bgzf test", 10);
+//! let path = write_temp("doc_example", &bgzf).unwrap();
+//! let reader = BgzfReader::new(path.clone()).unwrap();
+//! assert_eq!(24, reader.total_uncompressed_length());
+//! std::fs::remove_file(path).unwrap();
+//! ```
+
+use crate::backend::crc32;
+use crate::writer::EOF_MARKER;
+use crate::{AnomalyKind, BgzfWriter};
+use std::fs;
+use std::io::{self, Write};
+
+/// Returns `data` compressed as bgzf with `block_size` uncompressed bytes in
+/// each block, followed by the EOF marker
+pub fn make_bgzf(data: &[u8], block_size: usize) -> Vec<u8> {
+  let mut writer = BgzfWriter::builder()
+    .block_size(block_size)
+    .build(Vec::new())
+    .expect("Fixture block size must be between 1 and 65280");
+  writer
+    .write_all(data)
+    .expect("Writing to a Vec does not fail");
+  writer.finish().expect("Writing to a Vec does not fail")
+}
+
+/// Same as `make_bgzf` without the EOF marker, like a file whose writer was
+/// interrupted right after its last block
+pub fn make_without_eof(data: &[u8], block_size: usize) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  bgzf.truncate(bgzf.len() - EOF_MARKER.len());
+  bgzf
+}
+
+/// Same as `make_without_eof` with the last `missing_bytes` bytes of the last
+/// block cut off too, like a file whose download was interrupted mid-block.
+///
+/// # Panics
+/// When `missing_bytes` is 0 or not smaller than the last block
+pub fn make_truncated(data: &[u8], block_size: usize, missing_bytes: usize) -> Vec<u8> {
+  let mut bgzf = make_without_eof(data, block_size);
+  let last_block = *block_starts(&bgzf)
+    .last()
+    .expect("Fixture has no data block");
+  assert!(
+    missing_bytes > 0 && missing_bytes < bgzf.len() - last_block,
+    "missing_bytes must cut into the last block without removing all of it"
+  );
+  bgzf.truncate(bgzf.len() - missing_bytes);
+  bgzf
+}
+
+/// Same as `make_bgzf` with the CRC32 in the footer of block number
+/// `block_index` (starting from 0) flipped, the block itself still
+/// decompresses fine.
+///
+/// # Panics
+/// When there is no block number `block_index`
+pub fn make_with_bad_crc(data: &[u8], block_size: usize, block_index: usize) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let starts = block_starts(&bgzf);
+  let next_block = *starts
+    .get(block_index + 1)
+    .unwrap_or_else(|| panic!("Fixture has no block {}", block_index));
+  //the CRC32 is the first half of the 8 byte footer
+  for byte in &mut bgzf[next_block - 8..next_block - 4] {
+    *byte = !*byte;
+  }
+  bgzf
+}
+
+/// Same as `make_bgzf` with the extra field holding the size of every block
+/// taken out, leaving gzip data in several members that is not BGZF, which
+/// `gzip -d` decompresses but the reader refuses
+pub fn make_plain_gzip(data: &[u8], block_size: usize) -> Vec<u8> {
+  let bgzf = make_bgzf(data, block_size);
+  let mut gzip = Vec::with_capacity(bgzf.len());
+  let starts = block_starts(&bgzf);
+  for (number, &start) in starts.iter().enumerate() {
+    let end = starts.get(number + 1).copied().unwrap_or(bgzf.len());
+    //the header without FEXTRA nor XLEN, then the block past its extra field
+    let mut header = [0u8; 10];
+    header.copy_from_slice(&bgzf[start..start + 10]);
+    header[3] = 0;
+    gzip.extend_from_slice(&header);
+    gzip.extend_from_slice(&bgzf[start + 18..end]);
+  }
+  gzip
+}
+
+/// Same as `make_bgzf` with the one anomaly `kind`, which readers read
+/// through unless in strict mode: the EOF marker taken out, `data` written
+/// as two files joined with `cat`, data after the EOF marker, or the header
+/// of the second block (the EOF marker when there is a single data block)
+/// given a subfield before BC or the FNAME flag and a name. For
+/// `InvalidBlock`, only read through in permissive mode, a block of a
+/// header and a footer giving it 10 bytes of data goes before the second
+/// block.
+///
+/// # Panics
+/// When `data` is empty, for the anomalies of the second block
+pub fn make_with_anomaly(data: &[u8], block_size: usize, kind: AnomalyKind) -> Vec<u8> {
+  match kind {
+    AnomalyKind::MissingEofMarker => make_without_eof(data, block_size),
+    AnomalyKind::EmptyBlock => {
+      let half = data.len() / 2;
+      let mut joined = make_bgzf(&data[..half], block_size);
+      joined.extend(make_bgzf(&data[half..], block_size));
+      joined
+    }
+    AnomalyKind::TrailingData => {
+      let mut bgzf = make_bgzf(data, block_size);
+      bgzf.extend_from_slice(b"trailing data\n");
+      bgzf
+    }
+    AnomalyKind::BcNotFirst => {
+      let mut bgzf = make_bgzf(data, block_size);
+      let start = second_block(&bgzf);
+      grow(&mut bgzf[start + 16..start + 18], 6);
+      grow(&mut bgzf[start + 10..start + 12], 6);
+      bgzf.splice(
+        start + 12..start + 12,
+        b"XY\x02\x00\x00\x00".iter().copied(),
+      );
+      bgzf
+    }
+    AnomalyKind::HeaderFlags => {
+      let mut bgzf = make_bgzf(data, block_size);
+      let start = second_block(&bgzf);
+      insert_header_fields(&mut bgzf, start, 8);
+      bgzf
+    }
+    AnomalyKind::InvalidBlock => {
+      //a block of a header and a footer alone, before the second block
+      let mut bgzf = make_bgzf(data, block_size);
+      let start = second_block(&bgzf);
+      //the header of the EOF marker then a CRC32 of 0 and an ISIZE of 10
+      let mut block = EOF_MARKER[..18].to_vec();
+      block.extend_from_slice(&[0, 0, 0, 0, 10, 0, 0, 0]);
+      block[16] = 25;
+      bgzf.splice(start..start, block);
+      bgzf
+    }
+  }
+}
+
+/// Same as `make_bgzf` with the gzip flags `flags`, any of FHCRC (2), FNAME
+/// (8) and FCOMMENT (16), set in the header of every data block, followed by
+/// the fields they add: the name "name" and the comment "comment", zero
+/// terminated, then the CRC16 of the header. The EOF marker is left as is.
+pub fn make_with_header_fields(data: &[u8], block_size: usize, flags: u8) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let starts = block_starts(&bgzf);
+  //from the last data block, for the offsets of the others to stay right
+  for &start in starts[..starts.len() - 1].iter().rev() {
+    insert_header_fields(&mut bgzf, start, flags);
+  }
+  bgzf
+}
+
+/// Same as `make_bgzf` with `length` bytes from the start of the block
+/// `block_index` overwritten with garbage, like a bad sector, the blocks
+/// after it staying where they were. The garbage has no gzip magic bytes.
+///
+/// # Panics
+/// When there are not that many blocks, or the garbage goes past the file
+pub fn make_with_garbage(
+  data: &[u8],
+  block_size: usize,
+  block_index: usize,
+  length: usize,
+) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let start = *block_starts(&bgzf)
+    .get(block_index)
+    .expect("Fixture has not that many blocks");
+  for (number, byte) in bgzf[start..start + length].iter_mut().enumerate() {
+    //with the high bit set, none is the 31 starting a header
+    *byte = 0x80 | (number * 37 % 128) as u8;
+  }
+  bgzf
+}
+
+/// Same as `make_bgzf` with a subfield "XY" holding 2 bytes added to the
+/// extra field of every data block, after BC with `bc_first`, before it
+/// otherwise. Both are valid BGZF.
+pub fn make_with_extra_subfield(data: &[u8], block_size: usize, bc_first: bool) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let starts = block_starts(&bgzf);
+  for &start in starts[..starts.len() - 1].iter().rev() {
+    grow(&mut bgzf[start + 16..start + 18], 6);
+    grow(&mut bgzf[start + 10..start + 12], 6);
+    let at = if bc_first { start + 18 } else { start + 12 };
+    bgzf.splice(at..at, b"XY\x02\x00\x01\x02".iter().copied());
+  }
+  bgzf
+}
+
+/// Same as `make_bgzf` with the BC subfield of the block `block_index`
+/// renamed XC, leaving the block without its size
+///
+/// # Panics
+/// When there are not that many blocks
+pub fn make_without_bc_subfield(data: &[u8], block_size: usize, block_index: usize) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let start = *block_starts(&bgzf)
+    .get(block_index)
+    .expect("Fixture has not that many blocks");
+  bgzf[start + 12] = b'X';
+  bgzf
+}
+
+/// Same as `make_bgzf` with the BSIZE in the header of block number
+/// `block_index` (starting from 0) off by `delta`, the block itself left as
+/// it is, like a writer getting the size of the block wrong. A block of
+/// less than 256 bytes of data claiming a byte less than it holds still
+/// reads, its footer taken from a byte earlier, but its size leads to the
+/// last byte of its footer rather than to the next block.
+///
+/// # Panics
+/// When there is no block number `block_index`
+pub fn make_with_bad_bsize(
+  data: &[u8],
+  block_size: usize,
+  block_index: usize,
+  delta: i16,
+) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let start = *block_starts(&bgzf)
+    .get(block_index)
+    .unwrap_or_else(|| panic!("Fixture has no block {}", block_index));
+  let bsize = u16::from_le_bytes([bgzf[start + 16], bgzf[start + 17]]);
+  let bad_bsize = (i32::from(bsize) + i32::from(delta)) as u16;
+  bgzf[start + 16..start + 18].copy_from_slice(&bad_bsize.to_le_bytes());
+  bgzf
+}
+
+/// Same as `make_bgzf` with the ISIZE in the footer of block number
+/// `block_index` (starting from 0) set to `isize`, the block itself left as
+/// it is, so that its data decompresses to another length than its footer
+/// gives it.
+///
+/// # Panics
+/// When there is no block number `block_index`
+pub fn make_with_bad_isize(
+  data: &[u8],
+  block_size: usize,
+  block_index: usize,
+  isize: u32,
+) -> Vec<u8> {
+  let mut bgzf = make_bgzf(data, block_size);
+  let next_block = *block_starts(&bgzf)
+    .get(block_index + 1)
+    .unwrap_or_else(|| panic!("Fixture has no block {}", block_index));
+  //the ISIZE is the second half of the 8 byte footer
+  bgzf[next_block - 4..next_block].copy_from_slice(&isize.to_le_bytes());
+  bgzf
+}
+
+/// Sets `flags` in the header of the block at `start` of `bgzf` and inserts
+/// the fields they add after its extra field, see `make_with_header_fields`
+fn insert_header_fields(bgzf: &mut Vec<u8>, start: usize, flags: u8) {
+  let xlen = usize::from(u16::from_le_bytes([bgzf[start + 10], bgzf[start + 11]]));
+  let mut fields = Vec::new();
+  if flags & 8 != 0 {
+    fields.extend_from_slice(b"name\0");
+  }
+  if flags & 16 != 0 {
+    fields.extend_from_slice(b"comment\0");
+  }
+  let crc16_length = if flags & 2 != 0 { 2 } else { 0 };
+  let mut header = bgzf[start..start + 12 + xlen].to_vec();
+  header[3] |= flags;
+  grow(&mut header[16..18], fields.len() + crc16_length);
+  header.extend(fields);
+  if flags & 2 != 0 {
+    let crc16 = crc32(&header) as u16;
+    header.extend_from_slice(&crc16.to_le_bytes());
+  }
+  bgzf.splice(start..start + 12 + xlen, header);
+}
+
+/// Offset of the second block of `bgzf`, the EOF marker when there is a
+/// single data block
+fn second_block(bgzf: &[u8]) -> usize {
+  *block_starts(bgzf)
+    .get(1)
+    .expect("Fixture has no second block")
+}
+
+/// Adds `length` to the little endian u16 `bytes`
+fn grow(bytes: &mut [u8], length: usize) {
+  let grown = u16::from_le_bytes([bytes[0], bytes[1]]) + length as u16;
+  bytes.copy_from_slice(&grown.to_le_bytes());
+}
+
+/// Returns the header of a BAM file holding no records, compressed like
+/// `make_bgzf`: the magic "BAM\1", the SAM text of an @HD line and an @SQ
+/// line per reference, and the name and length of each of `references`
+pub fn make_bam_header(references: &[(&str, u32)], block_size: usize) -> Vec<u8> {
+  let mut text = String::from("@HD\tVN:1.6\tSO:coordinate\n");
+  for (name, length) in references {
+    text.push_str(&format!("@SQ\tSN:{}\tLN:{}\n", name, length));
+  }
+  let mut bam = b"BAM\x01".to_vec();
+  bam.extend_from_slice(&(text.len() as u32).to_le_bytes());
+  bam.extend_from_slice(text.as_bytes());
+  bam.extend_from_slice(&(references.len() as u32).to_le_bytes());
+  for (name, length) in references {
+    //names are zero terminated
+    bam.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+    bam.extend_from_slice(name.as_bytes());
+    bam.push(0);
+    bam.extend_from_slice(&length.to_le_bytes());
+  }
+  make_bgzf(&bam, block_size)
+}
+
+/// Returns a VCF compressed like `make_bgzf`: its fileformat line, a contig
+/// line, the header line of the columns and `records` records on chr1
+pub fn make_vcf(records: usize, block_size: usize) -> Vec<u8> {
+  let mut vcf = String::from("##fileformat=VCFv4.3\n##contig=<ID=chr1,length=1000000>\n");
+  vcf.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+  for record in 0..records {
+    vcf.push_str(&format!(
+      "chr1\t{}\t.\tA\tG\t50\tPASS\t.\n",
+      100 * (record + 1)
+    ));
+  }
+  make_bgzf(vcf.as_bytes(), block_size)
+}
+
+/// Fills `out` with the data at uncompressed offset `start` of a file whose
+/// every 8 byte word, from offset 0, is its own offset in little endian, so
+/// that a read far into a large file tells where it read from
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::fixtures::offset_pattern;
+///
+/// let mut data = vec![0; 6];
+/// offset_pattern((1 << 32) + 2, &mut data);
+/// assert_eq!([0, 0, 1, 0, 0, 0], &data[..]);
+/// ```
+pub fn offset_pattern(start: u64, out: &mut [u8]) {
+  for (offset, byte) in (start..).zip(out.iter_mut()) {
+    *byte = (offset & !7).to_le_bytes()[(offset & 7) as usize];
+  }
+}
+
+/// Writes `bgzf` to a file in the temporary directory and returns its path,
+/// `name` and the process id make the file name unique. Removing the file is
+/// left to the caller.
+pub fn write_temp(name: &str, bgzf: &[u8]) -> io::Result<String> {
+  let path = std::env::temp_dir().join(format!(
+    "bgzf_rust_reader_fixture_{}_{}.bgz",
+    name,
+    std::process::id()
+  ));
+  fs::write(&path, bgzf)?;
+  path
+    .into_os_string()
+    .into_string()
+    .map_err(|_e| io::Error::new(io::ErrorKind::InvalidInput, "Temporary path is not UTF-8"))
+}
+
+/// Offsets of every block, the EOF marker included, in data written by
+/// `BgzfWriter` (whose only extra subfield is BC)
+fn block_starts(bgzf: &[u8]) -> Vec<usize> {
+  let mut starts = Vec::new();
+  let mut offset = 0;
+  while offset < bgzf.len() {
+    starts.push(offset);
+    let bsize = u16::from_le_bytes([bgzf[offset + 16], bgzf[offset + 17]]);
+    offset += usize::from(bsize) + 1;
+  }
+  starts
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::scan_block_chain;
+  use crate::source::Source;
+  use crate::BgzfReader;
+  use std::fs::File;
+
+  const DATA: &[u8] = b"This is just a bgzf test,lets see how it reacts. :).";
+
+  #[test]
+  fn test_make_bgzf_blocks() {
+    let bgzf = make_bgzf(DATA, 10);
+    assert_eq!(7, block_starts(&bgzf).len());
+    assert_eq!(&EOF_MARKER[..], &bgzf[bgzf.len() - EOF_MARKER.len()..]);
+
+    let path = write_temp("make_bgzf", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(6, reader.blocks().len());
+    let mut content = vec![0; 10];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(&DATA[..10], &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_make_without_eof() {
+    let bgzf = make_without_eof(DATA, 10);
+    assert_eq!(make_bgzf(DATA, 10).len() - EOF_MARKER.len(), bgzf.len());
+    let path = write_temp("without_eof", &bgzf).unwrap();
+    let block_chain = scan_block_chain(&Source::File(File::open(&path).unwrap())).unwrap();
+    assert!(!block_chain.has_eof_marker);
+    assert_eq!(DATA.len() as u64, block_chain.uncompressed_length);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_make_truncated() {
+    let bgzf = make_truncated(DATA, 10, 3);
+    assert_eq!(make_without_eof(DATA, 10).len() - 3, bgzf.len());
+    let path = write_temp("truncated", &bgzf).unwrap();
+    assert!(scan_block_chain(&Source::File(File::open(&path).unwrap())).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_make_plain_gzip() {
+    let gzip = make_plain_gzip(DATA, 10);
+    //8 bytes less for each of the 6 blocks and the EOF marker
+    assert_eq!(make_bgzf(DATA, 10).len() - 7 * 8, gzip.len());
+    assert_eq!(&[31, 139, 8, 0], &gzip[..4]);
+    //the deflate data right after the header
+    let bgzf = make_bgzf(DATA, 10);
+    assert_eq!(&bgzf[18..24], &gzip[10..16]);
+  }
+
+  #[test]
+  fn test_make_with_anomaly() {
+    let good = make_bgzf(DATA, 10);
+    let second = block_starts(&good)[1];
+    let bc_not_first = make_with_anomaly(DATA, 10, AnomalyKind::BcNotFirst);
+    assert_eq!(good.len() + 6, bc_not_first.len());
+    assert_eq!(b"XY", &bc_not_first[second + 12..second + 14]);
+    assert_eq!(b"BC", &bc_not_first[second + 18..second + 20]);
+    let header_flags = make_with_anomaly(DATA, 10, AnomalyKind::HeaderFlags);
+    assert_eq!(4 | 8, header_flags[second + 3]);
+    assert_eq!(b"name\0", &header_flags[second + 18..second + 23]);
+    //the rest of the block and the blocks after it as they were
+    assert_eq!(&good[second + 18..], &header_flags[second + 23..]);
+    assert!(make_with_anomaly(DATA, 10, AnomalyKind::TrailingData).starts_with(&good));
+    let invalid_block = make_with_anomaly(DATA, 10, AnomalyKind::InvalidBlock);
+    assert_eq!(&good[..second + 4], &invalid_block[..second + 4]);
+    assert_eq!(&[25, 0], &invalid_block[second + 16..second + 18]);
+    assert_eq!(
+      &[0, 0, 0, 0, 10, 0, 0, 0],
+      &invalid_block[second + 18..second + 26]
+    );
+    assert_eq!(&good[second..], &invalid_block[second + 26..]);
+  }
+
+  #[test]
+  fn test_make_with_garbage() {
+    let good = make_bgzf(DATA, 10);
+    let bgzf = make_with_garbage(DATA, 10, 2, 15);
+    let third = block_starts(&good)[2];
+    assert_eq!(good.len(), bgzf.len());
+    assert_eq!(&good[..third], &bgzf[..third]);
+    assert_eq!(&good[third + 15..], &bgzf[third + 15..]);
+    assert!(bgzf[third..third + 15].iter().all(|&byte| byte >= 0x80));
+  }
+
+  #[test]
+  fn test_make_with_bad_bsize() {
+    let good = make_bgzf(DATA, 10);
+    let second = block_starts(&good)[1];
+    for delta in [-1, 2] {
+      let bgzf = make_with_bad_bsize(DATA, 10, 1, delta);
+      let bsize =
+        |bgzf: &[u8]| i32::from(u16::from_le_bytes([bgzf[second + 16], bgzf[second + 17]]));
+      assert_eq!(bsize(&good) + i32::from(delta), bsize(&bgzf));
+      assert_eq!(&good[..second + 16], &bgzf[..second + 16]);
+      assert_eq!(&good[second + 18..], &bgzf[second + 18..]);
+    }
+  }
+
+  #[test]
+  fn test_make_with_bad_isize() {
+    let good = make_bgzf(DATA, 10);
+    let bgzf = make_with_bad_isize(DATA, 10, 1, 7);
+    let third = block_starts(&good)[2];
+    assert_eq!(&[10, 0, 0, 0], &good[third - 4..third]);
+    assert_eq!(&[7, 0, 0, 0], &bgzf[third - 4..third]);
+    assert_eq!(&good[..third - 4], &bgzf[..third - 4]);
+    assert_eq!(&good[third..], &bgzf[third..]);
+  }
+
+  #[test]
+  fn test_make_with_extra_subfield() {
+    let good = make_bgzf(DATA, 10);
+    let bc_first = make_with_extra_subfield(DATA, 10, true);
+    let bc_second = make_with_extra_subfield(DATA, 10, false);
+    //6 bytes more in each of the 6 data blocks
+    assert_eq!(good.len() + 6 * 6, bc_first.len());
+    assert_eq!(bc_first.len(), bc_second.len());
+    assert_eq!(b"BC\x02\x00", &bc_first[12..16]);
+    assert_eq!(b"XY\x02\x00", &bc_first[18..22]);
+    assert_eq!(b"XY\x02\x00", &bc_second[12..16]);
+    assert_eq!(b"BC\x02\x00", &bc_second[18..22]);
+    assert_eq!(
+      &good[18..block_starts(&good)[1]],
+      &bc_first[24..block_starts(&bc_first)[1]]
+    );
+    assert!(bc_second.ends_with(&EOF_MARKER));
+
+    let without_bc = make_without_bc_subfield(DATA, 10, 1);
+    let second = block_starts(&good)[1];
+    assert_eq!(b"XC", &without_bc[second + 12..second + 14]);
+    assert_eq!(&good[..second], &without_bc[..second]);
+  }
+
+  #[test]
+  fn test_make_with_header_fields() {
+    let good = make_bgzf(DATA, 10);
+    let bgzf = make_with_header_fields(DATA, 10, 2 | 8 | 16);
+    //5 + 8 + 2 bytes more in each of the 6 data blocks
+    assert_eq!(good.len() + 6 * 15, bgzf.len());
+    assert_eq!(4 | 2 | 8 | 16, bgzf[3]);
+    assert_eq!(b"name\0comment\0", &bgzf[18..31]);
+    assert_eq!((crc32(&bgzf[..31]) as u16).to_le_bytes(), bgzf[31..33]);
+    assert_eq!(
+      &good[18..block_starts(&good)[1]],
+      &bgzf[33..block_starts(&bgzf)[1]]
+    );
+    assert!(bgzf.ends_with(&EOF_MARKER));
+    let comment = make_with_header_fields(DATA, 10, 16);
+    assert_eq!(4 | 16, comment[3]);
+    assert_eq!(b"comment\0", &comment[18..26]);
+  }
+
+  #[test]
+  fn test_make_with_bad_crc() {
+    let good = make_bgzf(DATA, 10);
+    let bad = make_with_bad_crc(DATA, 10, 1);
+    let next_block = block_starts(&good)[2];
+    let differences = good
+      .iter()
+      .zip(bad.iter())
+      .enumerate()
+      .filter(|(_index, (good, bad))| good != bad)
+      .map(|(index, _bytes)| index)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      (next_block - 8..next_block - 4).collect::<Vec<_>>(),
+      differences
+    );
+  }
+}
@@ -0,0 +1,582 @@
+use crate::async_core::{inflate_whole, AsyncCore, BlockFetcher, Fetched};
+use crate::block_cache::BlockCache;
+use crate::decompressor_pool::DecompressorPool;
+use crate::shared_state::lock;
+use crate::stream_reader::Window;
+use crate::writer::{EOF_MARKER, MAX_BLOCK_SIZE};
+use crate::{
+  check_bgzf_start, floor_block, locate_block_in, read_block, BgzfBlock, BgzfError, BgzfIndex,
+  BgzfReader, BgzfReaderBuilder, BlockContext, BlockOperation, Truncated,
+};
+use futures_io::{AsyncRead, AsyncSeek};
+use std::future::poll_fn;
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{ready, Context, Poll};
+
+/// A block of the source being read:
+///
+/// sought: whether the source was moved to the block yet,
+/// block_data: the bytes of the block,
+/// filled: number of those bytes read so far,
+struct Reading {
+  sought: bool,
+  block_data: Vec<u8>,
+  filled: usize,
+}
+
+/// Fetches the blocks of an async source of compressed data, reading them
+/// with its `AsyncRead` and `AsyncSeek`
+///
+/// Fields description:
+///
+/// source: the compressed data,
+/// blocks: index of the blocks holding data,
+/// input_length: uncompressed length of the data,
+/// cache: decompressed blocks, holding at least the one read last,
+/// decompressors: where the decompressor of every block is taken from,
+/// verify_crc: true to check the CRC32 of every block decompressed,
+/// reading: block being read from the source, if any,
+struct SourceFetcher<S> {
+  source: S,
+  blocks: Vec<BgzfBlock>,
+  input_length: u64,
+  cache: Mutex<BlockCache>,
+  decompressors: DecompressorPool,
+  verify_crc: bool,
+  reading: Option<Reading>,
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin> SourceFetcher<S> {
+  /// Moves the source to `block` and reads it into `reading`
+  fn poll_read_block(
+    source: &mut S,
+    cx: &mut Context<'_>,
+    block: &BgzfBlock,
+    reading: &mut Reading,
+  ) -> Poll<io::Result<()>> {
+    if !reading.sought {
+      ready!(Pin::new(&mut *source).poll_seek(cx, SeekFrom::Start(block.block_offset)))?;
+      reading.sought = true;
+    }
+    while reading.filled < reading.block_data.len() {
+      match ready!(Pin::new(&mut *source).poll_read(cx, &mut reading.block_data[reading.filled..]))
+      {
+        Ok(0) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+        Ok(read) => reading.filled += read,
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+        Err(error) => return Poll::Ready(Err(error)),
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin> BlockFetcher for SourceFetcher<S> {
+  fn block_at(&self, pos: u64) -> Result<Option<BgzfBlock>, BgzfError> {
+    Ok(
+      floor_block(&self.blocks, pos)
+        .map(|index| self.blocks[index])
+        .filter(|block| pos < block.input_offset + u64::from(block.input_length)),
+    )
+  }
+
+  fn length(&self) -> u64 {
+    self.input_length
+  }
+
+  fn cache(&self) -> MutexGuard<'_, BlockCache> {
+    lock(&self.cache)
+  }
+
+  fn poll_fetch(
+    &mut self,
+    cx: &mut Context<'_>,
+    block: &BgzfBlock,
+  ) -> Poll<Result<Fetched, BgzfError>> {
+    let reading = self.reading.get_or_insert_with(|| Reading {
+      sought: false,
+      block_data: vec![0; block.block_size as usize],
+      filled: 0,
+    });
+    let read = ready!(SourceFetcher::poll_read_block(
+      &mut self.source,
+      cx,
+      block,
+      reading
+    ));
+    let block_data = mem::take(&mut reading.block_data);
+    self.reading = None;
+    Poll::Ready(match read {
+      Ok(()) => Ok(Fetched::Compressed(block_data)),
+      Err(source) => Err(BgzfError::ReadFailed {
+        block: BlockContext::new(block, BlockOperation::Reading),
+        source,
+      }),
+    })
+  }
+
+  fn inflate(&self, block: &BgzfBlock, block_data: &[u8]) -> Result<Vec<u8>, BgzfError> {
+    inflate_whole(&self.decompressors, self.verify_crc, block, block_data)
+  }
+
+  fn locate(&self, error: BgzfError) -> BgzfError {
+    locate_block_in(&self.blocks, error)
+  }
+}
+
+/// Struct to read bgzf data from any `futures::io::AsyncRead` and
+/// `AsyncSeek` source of compressed data, like the files of smol or
+/// async-std, as a `futures::io::AsyncRead` and `AsyncSeek` itself, on any
+/// runtime
+///
+/// Fields description:
+///
+/// core: the polling shared with the other async readers, fetching blocks
+/// from the source,
+pub struct FuturesBgzfReader<S> {
+  core: AsyncCore<SourceFetcher<S>>,
+}
+
+/// Below are the steps to use the bgzf Reader of an async source,
+/// open it with `FuturesBgzfReader::open` (or
+/// `BgzfReader::builder().open_async_source` for other options), which
+/// indexes it reading it through once, or give it a saved index with
+/// `with_index`, then read and seek with the `AsyncReadExt` and
+/// `AsyncSeekExt` methods of the futures crate.
+///
+/// Nothing runs on another thread: the blocks are read from the source a
+/// block at a time, with its own polling, and decompressed inline in
+/// `poll_read`, at most 64 KiB. The decompressed block is then added to the
+/// cache, so the following reads within it are copies, and `cache_bytes`
+/// keeps more blocks around as it does for the sync reader.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::FuturesBgzfReader;
+/// use futures::io::{AsyncReadExt, AsyncSeekExt, Cursor};
+/// use std::io::SeekFrom;
+///
+/// let data = std::fs::read("bgzf_test.bgz").unwrap();
+/// let word = futures::executor::block_on(async {
+///   let mut reader = FuturesBgzfReader::open(Cursor::new(data)).await.unwrap();
+///   reader.seek(SeekFrom::Start(8)).await.unwrap();
+///   let mut word = [0; 4];
+///   reader.read_exact(&mut word).await.unwrap();
+///   word
+/// });
+/// assert_eq!(b"just", &word);
+/// ```
+impl<S: AsyncRead + AsyncSeek + Unpin> FuturesBgzfReader<S> {
+  /// Opens the bgzf data of `source` with the default options, see
+  /// `BgzfReaderBuilder::open_async_source`
+  pub async fn open(source: S) -> Result<FuturesBgzfReader<S>, BgzfError> {
+    BgzfReader::builder().open_async_source(source).await
+  }
+
+  /// Returns a reader of the bgzf data of `source` whose blocks are those of
+  /// `index`, saved from `BgzfReader::index`, without reading the source to
+  /// index it. A stale index fails the reads of the blocks it got wrong.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, FuturesBgzfReader};
+  /// use futures::io::Cursor;
+  ///
+  /// let index = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap().index().unwrap();
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let reader = FuturesBgzfReader::with_index(Cursor::new(data), &index);
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// ```
+  pub fn with_index(source: S, index: &BgzfIndex) -> FuturesBgzfReader<S> {
+    BgzfReader::builder().open_async_source_with_index(source, index)
+  }
+
+  pub(crate) fn from_parts(
+    source: S,
+    blocks: Vec<BgzfBlock>,
+    builder: &BgzfReaderBuilder,
+  ) -> FuturesBgzfReader<S> {
+    let input_length = blocks.last().map_or(0, |block| {
+      block.input_offset + u64::from(block.input_length)
+    });
+    FuturesBgzfReader {
+      core: AsyncCore::new(SourceFetcher {
+        source,
+        blocks,
+        input_length,
+        cache: Mutex::new(BlockCache::new("blocks", builder.cache_bytes as u64)),
+        decompressors: DecompressorPool::new(1),
+        verify_crc: builder.verify_crc,
+        reading: None,
+      }),
+    }
+  }
+
+  /// Returns the uncompressed length of the data
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.core.fetcher.length()
+  }
+
+  /// Returns the number of blocks holding data
+  pub fn num_blocks(&self) -> usize {
+    self.core.fetcher.blocks.len()
+  }
+
+  /// Returns the position the next read starts at
+  pub fn position(&self) -> u64 {
+    self.core.position()
+  }
+
+  /// Returns a reference to the source
+  pub fn get_ref(&self) -> &S {
+    &self.core.fetcher.source
+  }
+
+  /// Returns the source, whose position is wherever the last block read left
+  /// it
+  pub fn into_inner(self) -> S {
+    self.core.fetcher.source
+  }
+}
+
+/// Reads the whole of `source` from its start and returns the blocks holding
+/// data. A block cut off by the end of the source fails with `Truncated`,
+/// and anything after an EOF marker that is not a block is ignored, as by
+/// the sync reader. With `require_eof_marker` the data must end with one.
+pub(crate) async fn index_source<S: AsyncRead + AsyncSeek + Unpin>(
+  source: &mut S,
+  require_eof_marker: bool,
+) -> Result<Vec<BgzfBlock>, BgzfError> {
+  poll_fn(|cx| Pin::new(&mut *source).poll_seek(cx, SeekFrom::Start(0))).await?;
+  let mut blocks = Vec::new();
+  //bytes read and not indexed yet, the first filled of pending, from the
+  //compressed offset block_offset, a whole block being at most its length
+  let mut pending = vec![0; MAX_BLOCK_SIZE];
+  let mut filled = 0;
+  let mut block_offset = 0;
+  let mut input_offset = 0;
+  let mut source_end = false;
+  let mut after_eof_marker = false;
+  loop {
+    while !source_end && filled < pending.len() {
+      let read = loop {
+        match poll_fn(|cx| Pin::new(&mut *source).poll_read(cx, &mut pending[filled..])).await {
+          Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+          read => break read?,
+        }
+      };
+      filled += read;
+      source_end = read == 0;
+    }
+    let window = Window {
+      start: block_offset,
+      data: &pending[..filled],
+    };
+    if block_offset == 0 {
+      check_bgzf_start(&window)?;
+    }
+    if filled == 0 {
+      break;
+    }
+    match read_block(&window, block_offset) {
+      Ok(block) => {
+        if block.input_length > 0 {
+          blocks.push(BgzfBlock {
+            input_offset,
+            ..block
+          });
+          input_offset += u64::from(block.input_length);
+        }
+        let block_size = block.block_size as usize;
+        after_eof_marker = pending[..block_size] == EOF_MARKER;
+        pending.copy_within(block_size..filled, 0);
+        filled -= block_size;
+        block_offset += u64::from(block.block_size);
+      }
+      Err(_error) if after_eof_marker => break,
+      Err(BgzfError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof => {
+        return Err(Truncated { block_offset }.into())
+      }
+      Err(error) => return Err(error),
+    }
+  }
+  if require_eof_marker && !after_eof_marker {
+    return Err(BgzfError::MissingEofMarker);
+  }
+  Ok(blocks)
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin> AsyncRead for FuturesBgzfReader<S> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    self.core.poll_read(cx, buf)
+  }
+}
+
+/// Seeks within the uncompressed data. Seeking past the end is allowed,
+/// reads from there return no bytes, and seeking before the start fails
+/// with an `InvalidInput` error. The source is only moved by reads.
+impl<S: AsyncRead + AsyncSeek + Unpin> AsyncSeek for FuturesBgzfReader<S> {
+  fn poll_seek(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    position: SeekFrom,
+  ) -> Poll<io::Result<u64>> {
+    self.core.start_seek(position)?;
+    Poll::Ready(Ok(self.core.complete_seek()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{
+    make_bgzf, make_plain_gzip, make_truncated, make_with_bad_crc, make_with_extra_subfield,
+    make_without_eof,
+  };
+  use crate::BlockInfo;
+  use futures::executor::block_on;
+  use futures::io::{AsyncReadExt, AsyncSeekExt, Cursor};
+
+  fn test_content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..6000 {
+      content.extend_from_slice(format!("{}\tchr{}\tfutures read\n", i, i % 23).as_bytes());
+    }
+    content
+  }
+
+  /// A source returning `Pending` before every read and seek, and reading at
+  /// most 100 bytes at a time, or failing from `fail_at` on
+  struct Trickle {
+    data: Cursor<Vec<u8>>,
+    ready: bool,
+    fail_at: u64,
+  }
+
+  impl Trickle {
+    fn new(data: Vec<u8>) -> Trickle {
+      Trickle {
+        data: Cursor::new(data),
+        ready: false,
+        fail_at: u64::MAX,
+      }
+    }
+
+    /// Returns whether to go on, after waking the task when not
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> bool {
+      self.ready = !self.ready;
+      if !self.ready {
+        cx.waker().wake_by_ref();
+      }
+      !self.ready
+    }
+  }
+
+  impl AsyncRead for Trickle {
+    fn poll_read(
+      mut self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+      if self.poll_ready(cx) {
+        return Poll::Pending;
+      }
+      if self.data.position() >= self.fail_at {
+        return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+      }
+      let length = buf.len().min(100);
+      Pin::new(&mut self.data).poll_read(cx, &mut buf[..length])
+    }
+  }
+
+  impl AsyncSeek for Trickle {
+    fn poll_seek(
+      mut self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      position: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+      if self.poll_ready(cx) {
+        return Poll::Pending;
+      }
+      Pin::new(&mut self.data).poll_seek(cx, position)
+    }
+  }
+
+  async fn check_reads<S: AsyncRead + AsyncSeek + Unpin>(
+    mut reader: FuturesBgzfReader<S>,
+    content: &[u8],
+  ) {
+    //a read spanning three blocks
+    assert_eq!(950, reader.seek(SeekFrom::Start(950)).await.unwrap());
+    let mut buf = vec![0; 2100];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&content[950..3050], &buf[..]);
+    assert_eq!(3050, reader.position());
+
+    assert_eq!(3000, reader.seek(SeekFrom::Current(-50)).await.unwrap());
+    let mut buf = vec![0; 10];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&content[3000..3010], &buf[..]);
+
+    let end = content.len() as u64;
+    assert_eq!(end - 5, reader.seek(SeekFrom::End(-5)).await.unwrap());
+    let mut tail = Vec::new();
+    assert_eq!(5, reader.read_to_end(&mut tail).await.unwrap());
+    assert_eq!(&content[content.len() - 5..], &tail[..]);
+    assert_eq!(0, reader.read(&mut buf).await.unwrap());
+
+    //reads past the end return no bytes, seeks before the start fail
+    reader.seek(SeekFrom::Start(end + 100)).await.unwrap();
+    assert_eq!(0, reader.read(&mut buf).await.unwrap());
+    let error = reader.seek(SeekFrom::Current(-(end as i64) - 200)).await;
+    assert_eq!(io::ErrorKind::InvalidInput, error.unwrap_err().kind());
+    assert_eq!(end + 100, reader.position());
+
+    reader.seek(SeekFrom::Start(0)).await.unwrap();
+    let mut all = Vec::new();
+    reader.read_to_end(&mut all).await.unwrap();
+    assert_eq!(content, &all[..]);
+  }
+
+  #[test]
+  fn test_seek_and_read_across_blocks() {
+    let content = test_content();
+    let bgzf = make_bgzf(&content, 1000);
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().cache_bytes(1 << 20),
+      BgzfReader::builder().verify_crc(true),
+    ];
+    for builder in builders {
+      block_on(async {
+        let cursor = builder.clone().open_async_source(Cursor::new(bgzf.clone()));
+        check_reads(cursor.await.unwrap(), &content).await;
+        let trickle = builder.open_async_source(Trickle::new(bgzf.clone()));
+        check_reads(trickle.await.unwrap(), &content).await;
+      });
+    }
+
+    let index = BgzfReader::from_bytes(bgzf.clone()).unwrap().index().unwrap();
+    let reader = FuturesBgzfReader::with_index(Trickle::new(bgzf), &index);
+    assert_eq!(index.blocks().len(), reader.num_blocks());
+    block_on(check_reads(reader, &content));
+  }
+
+  #[test]
+  fn test_index_matches_sync_reader() {
+    let content = test_content();
+    let files = [
+      make_bgzf(&content, 1000),
+      make_without_eof(&content, 1000),
+      make_with_extra_subfield(&content, 1000, false),
+      make_bgzf(&content, 65280),
+      make_bgzf(b"", 1000),
+      Vec::new(),
+    ];
+    for bgzf in files {
+      let sync = BgzfReader::from_bytes(bgzf.clone()).unwrap();
+      let reader = block_on(FuturesBgzfReader::open(Trickle::new(bgzf))).unwrap();
+      let blocks: Vec<BlockInfo> = reader
+        .core
+        .fetcher
+        .blocks
+        .iter()
+        .map(BgzfBlock::info)
+        .collect();
+      assert_eq!(sync.block_offsets().collect::<Vec<_>>(), blocks);
+      assert_eq!(
+        sync.total_uncompressed_length(),
+        reader.total_uncompressed_length()
+      );
+    }
+  }
+
+  #[test]
+  fn test_open_errors() {
+    let content = test_content();
+    let truncated = make_truncated(&content, 1000, 10);
+    let expected = BgzfReader::from_bytes(truncated.clone()).err().unwrap();
+    let error = block_on(FuturesBgzfReader::open(Trickle::new(truncated)))
+      .err()
+      .unwrap();
+    assert!(
+      matches!((&error, &expected), (BgzfError::Truncated(t), BgzfError::Truncated(e)) if t == e),
+      "{:?}",
+      error
+    );
+    //cut within the first header
+    let error = block_on(FuturesBgzfReader::open(Cursor::new(
+      make_bgzf(&content, 1000)[..10].to_vec(),
+    )))
+    .err()
+    .unwrap();
+    assert!(matches!(
+      error,
+      BgzfError::Truncated(Truncated { block_offset: 0 })
+    ));
+
+    let error = block_on(FuturesBgzfReader::open(Cursor::new(make_plain_gzip(
+      &content, 1000,
+    ))))
+    .err()
+    .unwrap();
+    assert!(
+      matches!(
+        error,
+        BgzfError::NotBgzf {
+          looks_like_gzip: true
+        }
+      ),
+      "{:?}",
+      error
+    );
+
+    let builder = BgzfReader::builder().require_eof_marker(true);
+    let without_eof = Cursor::new(make_without_eof(&content, 1000));
+    let error = block_on(builder.clone().open_async_source(without_eof))
+      .err()
+      .unwrap();
+    assert!(matches!(error, BgzfError::MissingEofMarker), "{:?}", error);
+    let with_eof = Cursor::new(make_bgzf(&content, 1000));
+    assert!(block_on(builder.open_async_source(with_eof)).is_ok());
+  }
+
+  #[test]
+  fn test_read_errors() {
+    let content = test_content();
+    let bad_crc = make_with_bad_crc(&content, 1000, 1);
+    let builder = BgzfReader::builder().verify_crc(true);
+    let mut reader = block_on(builder.open_async_source(Cursor::new(bad_crc))).unwrap();
+    let mut all = Vec::new();
+    let error = block_on(reader.read_to_end(&mut all)).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    let error = error.into_inner().unwrap().downcast::<BgzfError>().unwrap();
+    assert!(
+      matches!(*error, BgzfError::ChecksumMismatch { block, .. } if block.index == 1),
+      "{:?}",
+      error
+    );
+    assert_eq!(1000, all.len());
+
+    //the errors of the source keep their kind, the next reads try again
+    let bgzf = make_bgzf(&content, 1000);
+    let index = BgzfReader::from_bytes(bgzf.clone()).unwrap().index().unwrap();
+    let mut source = Trickle::new(bgzf);
+    source.fail_at = index.blocks()[2].compressed_offset + 10;
+    let mut reader = FuturesBgzfReader::with_index(source, &index);
+    let mut all = Vec::new();
+    let error = block_on(reader.read_to_end(&mut all)).unwrap_err();
+    assert_eq!(io::ErrorKind::ConnectionReset, error.kind());
+    assert_eq!(&content[..2000], &all[..]);
+    reader.core.fetcher.source.fail_at = u64::MAX;
+    block_on(reader.read_to_end(&mut all)).unwrap();
+    assert_eq!(content, all);
+  }
+}
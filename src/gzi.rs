@@ -0,0 +1,166 @@
+use crate::writer::MAX_BLOCK_SIZE;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Reasons a .gzi index can not be used for a file. An entry is numbered by
+/// its block: entry 0 is the first block, which the .gzi leaves out, so entry
+/// n is the n-th offset pair of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GziError {
+  /// The file is not an entry count followed by that many offset pairs
+  Malformed { length: u64 },
+  /// The entry does not start after the block before it
+  NotMonotonic { entry: usize },
+  /// The entry is further from the previous one than a BGZF block can be
+  BlockTooLarge { entry: usize },
+  /// The entry points past the end of the data file
+  OffsetBeyondEnd {
+    entry: usize,
+    compressed_offset: u64,
+    file_length: u64,
+  },
+  /// There is no valid block where the entry points
+  InvalidBlock {
+    entry: usize,
+    compressed_offset: u64,
+  },
+}
+
+impl Error for GziError {}
+
+impl fmt::Display for GziError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GziError::Malformed { length } => write!(f, "Malformed .gzi index of {} bytes", length),
+      GziError::NotMonotonic { entry } => write!(
+        f,
+        "Entry {} of the .gzi index does not come after the previous one",
+        entry
+      ),
+      GziError::BlockTooLarge { entry } => write!(
+        f,
+        "Entry {} of the .gzi index gives a block larger than BGZF allows",
+        entry
+      ),
+      GziError::OffsetBeyondEnd {
+        entry,
+        compressed_offset,
+        file_length,
+      } => write!(
+        f,
+        "Entry {} of the .gzi index points at offset {} past the end of the {} byte file",
+        entry, compressed_offset, file_length
+      ),
+      GziError::InvalidBlock {
+        entry,
+        compressed_offset,
+      } => write!(
+        f,
+        "Entry {} of the .gzi index points at offset {} where there is no valid block",
+        entry, compressed_offset
+      ),
+    }
+  }
+}
+
+/// Writes a .gzi index in the format of `bgzip -r`: the number of entries as
+/// a little-endian u64, followed by a (compressed offset, uncompressed offset)
+/// u64 pair for the start of every data block. The first block always starts
+/// at (0, 0) so like htslib it is left out.
+pub(crate) fn write_gzi<W: Write>(block_offsets: &[(u64, u64)], mut out: W) -> io::Result<()> {
+  let entries = block_offsets
+    .iter()
+    .filter(|&&offsets| offsets != (0, 0))
+    .collect::<Vec<_>>();
+  let mut gzi = Vec::with_capacity(8 + entries.len() * 16);
+  gzi.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+  for (compressed_offset, uncompressed_offset) in entries {
+    gzi.extend_from_slice(&compressed_offset.to_le_bytes());
+    gzi.extend_from_slice(&uncompressed_offset.to_le_bytes());
+  }
+  out.write_all(&gzi)?;
+  out.flush()
+}
+
+/// Parses the content of a .gzi index into the (compressed offset,
+/// uncompressed offset) start of every block, the first block at (0, 0)
+/// included. Both offsets must grow from block to block by no more than a
+/// BGZF block holds.
+pub(crate) fn read_gzi(gzi: &[u8]) -> Result<Vec<(u64, u64)>, GziError> {
+  let malformed = GziError::Malformed {
+    length: gzi.len() as u64,
+  };
+  if gzi.len() < 8 || !(gzi.len() - 8).is_multiple_of(16) {
+    return Err(malformed);
+  }
+  let read_u64 = |at: usize| u64::from_le_bytes(gzi[at..at + 8].try_into().unwrap());
+  if read_u64(0) != ((gzi.len() - 8) / 16) as u64 {
+    return Err(malformed);
+  }
+
+  let mut block_offsets = vec![(0, 0)];
+  for (index, pair) in (8..gzi.len()).step_by(16).enumerate() {
+    let entry = index + 1;
+    let (compressed_offset, uncompressed_offset) = (read_u64(pair), read_u64(pair + 8));
+    let (previous_compressed, previous_uncompressed) = block_offsets[index];
+    if compressed_offset <= previous_compressed || uncompressed_offset <= previous_uncompressed {
+      return Err(GziError::NotMonotonic { entry });
+    }
+    if compressed_offset - previous_compressed > MAX_BLOCK_SIZE as u64
+      || uncompressed_offset - previous_uncompressed > MAX_BLOCK_SIZE as u64
+    {
+      return Err(GziError::BlockTooLarge { entry });
+    }
+    block_offsets.push((compressed_offset, uncompressed_offset));
+  }
+  Ok(block_offsets)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_read_gzi_round_trip() {
+    let block_offsets = vec![(0, 0), (1031, 1000), (2062, 2000)];
+    let mut gzi = Vec::new();
+    write_gzi(&block_offsets, &mut gzi).unwrap();
+    assert_eq!(block_offsets, read_gzi(&gzi).unwrap());
+    assert_eq!(vec![(0, 0)], read_gzi(&[0; 8]).unwrap());
+  }
+
+  #[test]
+  fn test_read_gzi_errors() {
+    let gzi = |pairs: &[(u64, u64)]| {
+      let mut gzi = Vec::new();
+      write_gzi(pairs, &mut gzi).unwrap();
+      gzi
+    };
+    let valid = gzi(&[(1031, 1000), (2062, 2000)]);
+    assert_eq!(
+      Err(GziError::Malformed { length: 39 }),
+      read_gzi(&valid[..39])
+    );
+    assert_eq!(Err(GziError::Malformed { length: 4 }), read_gzi(&[0; 4]));
+    let mut wrong_count = valid.clone();
+    wrong_count[0] = 3;
+    assert_eq!(
+      Err(GziError::Malformed { length: 40 }),
+      read_gzi(&wrong_count)
+    );
+    assert_eq!(
+      Err(GziError::NotMonotonic { entry: 2 }),
+      read_gzi(&gzi(&[(2062, 2000), (1031, 3000)]))
+    );
+    assert_eq!(
+      Err(GziError::NotMonotonic { entry: 2 }),
+      read_gzi(&gzi(&[(1031, 1000), (2062, 1000)]))
+    );
+    assert_eq!(
+      Err(GziError::BlockTooLarge { entry: 1 }),
+      read_gzi(&gzi(&[(100_000, 1000)]))
+    );
+  }
+}
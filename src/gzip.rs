@@ -0,0 +1,1127 @@
+//! Random access to plain gzip files, which are not BGZF: one or more gzip
+//! members, as gzip and pigz write them or `cat` joins them, with no block
+//! sizes to index. A full decompression pass builds an index of
+//! checkpoints, places the data can be decompressed from, and reads then
+//! decompress forward from the checkpoint before them.
+//!
+//! Checkpoints are the start of every member, and every
+//! `checkpoint_spacing` uncompressed bytes the next boundary between deflate
+//! blocks, like the flush points pigz leaves every 128 KiB. Those hold the
+//! 32 KiB of data before them, which the data after them refers back to, and
+//! the bits of the byte the deflate block starts in.
+//!
+//! The binary format of `GzipIndex`, all integers little-endian:
+//!
+//! magic: the 4 bytes `GZRI`,
+//! version: u32, currently 1,
+//! checkpoint count: u64,
+//! uncompressed length: u64,
+//! compressed length: u64, the length of the file indexed,
+//! then for every checkpoint: compressed offset (u64), uncompressed offset
+//! (u64), member start (u8, 1 or 0), bit count (u8) and bits (u8) of the
+//! byte before the compressed offset the deflate block starts in, window
+//! length (u32) and the window.
+
+use crate::index_cache;
+use crate::shared_state::{lock, Position};
+use crate::source::Source;
+use crate::BgzfError;
+use miniz_oxide::inflate::core::inflate_flags::{
+  TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY,
+};
+use miniz_oxide::inflate::core::{
+  decompress, BlockBoundaryState, DecompressorOxide, TINFL_LZ_DICT_SIZE,
+};
+use miniz_oxide::inflate::TINFLStatus;
+use positioned_io::ReadAt;
+use std::cmp::min;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+const INDEX_MAGIC: [u8; 4] = *b"GZRI";
+
+const INDEX_VERSION: u32 = 1;
+
+/// Length of a checkpoint record before its window
+const RECORD_LENGTH: usize = 23;
+
+/// Default of `GzipRandomReaderBuilder::checkpoint_spacing`
+pub const DEFAULT_CHECKPOINT_SPACING: u64 = 1024 * 1024;
+
+/// Bytes of the file read at once
+const INPUT_CHUNK: usize = 64 * 1024;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Reasons a gzip file can not be read, or its index not used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GzipError {
+  /// No gzip member header starts at the offset, or it is cut short
+  InvalidHeader { offset: u64 },
+  /// The deflate data of the member starting at `member_offset` is not
+  /// valid deflate data, or stops before its end
+  CorruptDeflateStream { member_offset: u64 },
+  /// The trailer of the member starting at `member_offset` is cut short, or
+  /// gives another CRC32 or length than those of its data
+  TrailerMismatch { member_offset: u64 },
+  /// The index was built from a file of `indexed_length` bytes, not of the
+  /// `file_length` ones of this file
+  IndexMismatch {
+    indexed_length: u64,
+    file_length: u64,
+  },
+}
+
+impl Error for GzipError {}
+
+impl fmt::Display for GzipError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GzipError::InvalidHeader { offset } => {
+        write!(f, "No valid gzip member header at offset {}", offset)
+      }
+      GzipError::CorruptDeflateStream { member_offset } => write!(
+        f,
+        "The deflate data of the gzip member at offset {} is corrupt",
+        member_offset
+      ),
+      GzipError::TrailerMismatch { member_offset } => write!(
+        f,
+        "The trailer of the gzip member at offset {} does not match its data",
+        member_offset
+      ),
+      GzipError::IndexMismatch {
+        indexed_length,
+        file_length,
+      } => write!(
+        f,
+        "The gzip index is of a file of {} bytes, not of this one of {} bytes",
+        indexed_length, file_length
+      ),
+    }
+  }
+}
+
+/// A place in a gzip file reads can decompress from
+///
+/// Fields description:
+///
+/// compressed_offset: offset in the file of the member header, or of the
+/// byte after the one the deflate block starts in,
+/// uncompressed_offset: offset in the data,
+/// member_start: true at the start of a member,
+/// bit_count: number of bits of the byte before `compressed_offset` that
+/// belong to the deflate block, from 0 to 7,
+/// bits: those bits, in the low bits,
+/// window: the data of the member before the checkpoint, its last 32 KiB,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GzipCheckpoint {
+  pub compressed_offset: u64,
+  pub uncompressed_offset: u64,
+  pub member_start: bool,
+  bit_count: u8,
+  bits: u8,
+  window: Vec<u8>,
+}
+
+impl GzipCheckpoint {
+  fn member(compressed_offset: u64, uncompressed_offset: u64) -> GzipCheckpoint {
+    GzipCheckpoint {
+      compressed_offset,
+      uncompressed_offset,
+      member_start: true,
+      bit_count: 0,
+      bits: 0,
+      window: Vec::new(),
+    }
+  }
+
+  /// Returns the length of the window the checkpoint holds
+  pub fn window_length(&self) -> usize {
+    self.window.len()
+  }
+}
+
+/// The checkpoints of a gzip file, as returned by `GzipRandomReader::index`
+///
+/// Fields description:
+///
+/// checkpoints: the checkpoints in file order, the first at the start of
+/// the file,
+/// input_length: total uncompressed length of the members,
+/// compressed_length: length of the file indexed,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GzipIndex {
+  checkpoints: Vec<GzipCheckpoint>,
+  input_length: u64,
+  compressed_length: u64,
+}
+
+impl GzipIndex {
+  /// Builds the index of `source` with a full decompression pass, checking
+  /// the trailer of every member
+  pub(crate) fn build(source: &Source, spacing: u64) -> Result<GzipIndex, BgzfError> {
+    let compressed_length = source.len()?;
+    let mut checkpoints = Vec::new();
+    let mut member_offset = 0;
+    let mut input_length = 0;
+    //an empty file is no gzip file either
+    while member_offset < compressed_length || checkpoints.is_empty() {
+      let mut inflater = Inflater::at_member(source, member_offset, input_length)?;
+      checkpoints.push(GzipCheckpoint::member(member_offset, input_length));
+      let mut last_checkpoint = input_length;
+      let mut crc = crc32fast::Hasher::new();
+      //stopping at every block boundary, which the data of a call can go past
+      while !inflater.ended {
+        inflater.inflate_more(source, true)?;
+        crc.update(inflater.pending());
+        inflater.consume(inflater.pending_length);
+        if inflater.boundary && inflater.uncompressed_offset - last_checkpoint >= spacing {
+          checkpoints.push(inflater.checkpoint());
+          last_checkpoint = inflater.uncompressed_offset;
+        }
+      }
+      let member_length = inflater.uncompressed_offset - input_length;
+      member_offset = inflater.check_trailer(source, crc.finalize(), member_length)?;
+      input_length = inflater.uncompressed_offset;
+    }
+    Ok(GzipIndex {
+      checkpoints,
+      input_length,
+      compressed_length,
+    })
+  }
+
+  /// The checkpoints in file order
+  pub fn checkpoints(&self) -> &[GzipCheckpoint] {
+    &self.checkpoints
+  }
+
+  /// Total length of the uncompressed data
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.input_length
+  }
+
+  /// Returns the index of the last checkpoint at or before `uoffset`
+  fn checkpoint_before(&self, uoffset: u64) -> usize {
+    self
+      .checkpoints
+      .partition_point(|checkpoint| checkpoint.uncompressed_offset <= uoffset)
+      .saturating_sub(1)
+  }
+
+  /// Writes the index in the binary format described in the module docs
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{GzipIndex, GzipRandomReader};
+  ///
+  /// let path = String::from("tests/data/multi_member.txt.gz");
+  /// let reader = GzipRandomReader::open(path.clone()).unwrap();
+  /// let mut saved = Vec::new();
+  /// reader.index().save(&mut saved).unwrap();
+  ///
+  /// let index = GzipIndex::load(&saved[..]).unwrap();
+  /// let reader = GzipRandomReader::open_with_index(path, &index).unwrap();
+  /// assert_eq!(414339, reader.total_uncompressed_length());
+  /// ```
+  pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+    let mut saved = Vec::new();
+    saved.extend_from_slice(&INDEX_MAGIC);
+    saved.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+    saved.extend_from_slice(&(self.checkpoints.len() as u64).to_le_bytes());
+    saved.extend_from_slice(&self.input_length.to_le_bytes());
+    saved.extend_from_slice(&self.compressed_length.to_le_bytes());
+    for checkpoint in &self.checkpoints {
+      saved.extend_from_slice(&checkpoint.compressed_offset.to_le_bytes());
+      saved.extend_from_slice(&checkpoint.uncompressed_offset.to_le_bytes());
+      saved.push(u8::from(checkpoint.member_start));
+      saved.push(checkpoint.bit_count);
+      saved.push(checkpoint.bits);
+      saved.extend_from_slice(&(checkpoint.window.len() as u32).to_le_bytes());
+      saved.extend_from_slice(&checkpoint.window);
+    }
+    out.write_all(&saved)?;
+    out.flush()
+  }
+
+  /// Reads an index written by `save`. Another magic or version, records
+  /// that do not match the checkpoint count, and checkpoints that do not
+  /// follow each other or could not be decompressed from are `InvalidData`
+  /// errors.
+  pub fn load<R: Read>(mut input: R) -> io::Result<GzipIndex> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0u8; 32];
+    input.read_exact(&mut header)?;
+    if header[..4] != INDEX_MAGIC {
+      return Err(invalid("Not a gzip index, the magic number is wrong"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != INDEX_VERSION {
+      return Err(invalid(&format!(
+        "Unsupported gzip index version {}",
+        version
+      )));
+    }
+    let count = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let input_length = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let compressed_length = u64::from_le_bytes(header[24..32].try_into().unwrap());
+
+    //read to the end rather than allocating for a count that may be garbage
+    let mut records = Vec::new();
+    input.read_to_end(&mut records)?;
+    let mut checkpoints = Vec::new();
+    let mut rest = &records[..];
+    while rest.len() >= RECORD_LENGTH {
+      let window_length = u32::from_le_bytes(rest[19..23].try_into().unwrap()) as usize;
+      if window_length > TINFL_LZ_DICT_SIZE || rest.len() - RECORD_LENGTH < window_length {
+        return Err(invalid("Gzip index checkpoint window is too long"));
+      }
+      checkpoints.push(GzipCheckpoint {
+        compressed_offset: u64::from_le_bytes(rest[..8].try_into().unwrap()),
+        uncompressed_offset: u64::from_le_bytes(rest[8..16].try_into().unwrap()),
+        member_start: rest[16] != 0,
+        bit_count: rest[17],
+        bits: rest[18],
+        window: rest[RECORD_LENGTH..RECORD_LENGTH + window_length].to_vec(),
+      });
+      rest = &rest[RECORD_LENGTH + window_length..];
+    }
+    if !rest.is_empty() || checkpoints.len() as u64 != count {
+      return Err(invalid(
+        "Gzip index checkpoint count does not match its records",
+      ));
+    }
+    let index = GzipIndex {
+      checkpoints,
+      input_length,
+      compressed_length,
+    };
+    if !index.is_consistent() {
+      return Err(invalid("Gzip index checkpoints do not follow each other"));
+    }
+    Ok(index)
+  }
+
+  /// Returns true when the first checkpoint starts the file, each one comes
+  /// after the one before it within the file and the data, and those within
+  /// a member have a window and at most 7 bits
+  fn is_consistent(&self) -> bool {
+    let first = match self.checkpoints.first() {
+      Some(first) => first,
+      None => return false,
+    };
+    let follow = self.checkpoints.windows(2).all(|pair| {
+      pair[0].compressed_offset < pair[1].compressed_offset
+        && pair[0].uncompressed_offset <= pair[1].uncompressed_offset
+    });
+    let resumable = self.checkpoints.iter().all(|checkpoint| {
+      checkpoint.member_start || (checkpoint.bit_count < 8 && !checkpoint.window.is_empty())
+    });
+    let last = &self.checkpoints[self.checkpoints.len() - 1];
+    first.member_start
+      && (first.compressed_offset, first.uncompressed_offset) == (0, 0)
+      && follow
+      && resumable
+      && last.compressed_offset < self.compressed_length
+      && last.uncompressed_offset <= self.input_length
+  }
+}
+
+/// Reads `buf.len()` bytes of `source` from `pos`, fewer at its end, and
+/// returns their number
+fn read_fully(source: &Source, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+  let mut read = 0;
+  while read < buf.len() {
+    match source.read_at(pos + read as u64, &mut buf[read..]) {
+      Ok(0) => break,
+      Ok(length) => read += length,
+      Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+      Err(error) => return Err(error),
+    }
+  }
+  Ok(read)
+}
+
+/// Decompresses a member of a gzip file forward from a checkpoint, for the
+/// full pass as well as for reads
+///
+/// Fields description:
+///
+/// decompressor: state of the deflate data,
+/// file_length: length of the file,
+/// member_offset: offset in the file of the member,
+/// input: bytes of the file from `input_offset`, consumed up to `input_pos`,
+/// input_offset: offset in the file of the first byte of `input`,
+/// input_pos: offset in `input` of the next byte to decompress,
+/// ring: the last 32 KiB of the member, which deflate data refers back to,
+/// ring_pos: offset in `ring` the next data goes to,
+/// history: number of bytes of the member in `ring`, up to its length,
+/// pending_start: offset in `ring` of the data not consumed yet,
+/// pending_length: length of that data,
+/// uncompressed_offset: offset in the data of the first byte not consumed,
+/// boundary: true when decompression stopped at a deflate block boundary,
+/// ended: true once the deflate data of the member ended,
+struct Inflater {
+  decompressor: Box<DecompressorOxide>,
+  file_length: u64,
+  member_offset: u64,
+  input: Vec<u8>,
+  input_offset: u64,
+  input_pos: usize,
+  ring: Vec<u8>,
+  ring_pos: usize,
+  history: usize,
+  pending_start: usize,
+  pending_length: usize,
+  uncompressed_offset: u64,
+  boundary: bool,
+  ended: bool,
+}
+
+impl Inflater {
+  fn new(
+    source: &Source,
+    decompressor: DecompressorOxide,
+    member_offset: u64,
+    compressed_offset: u64,
+    uncompressed_offset: u64,
+  ) -> io::Result<Inflater> {
+    Ok(Inflater {
+      decompressor: Box::new(decompressor),
+      file_length: source.len()?,
+      member_offset,
+      input: Vec::new(),
+      input_offset: compressed_offset,
+      input_pos: 0,
+      ring: vec![0; TINFL_LZ_DICT_SIZE],
+      ring_pos: 0,
+      history: 0,
+      pending_start: 0,
+      pending_length: 0,
+      uncompressed_offset,
+      boundary: false,
+      ended: false,
+    })
+  }
+
+  /// Returns an inflater of the member at `offset`, past its header, whose
+  /// data starts at `uncompressed_offset`
+  fn at_member(
+    source: &Source,
+    offset: u64,
+    uncompressed_offset: u64,
+  ) -> Result<Inflater, BgzfError> {
+    let mut inflater = Inflater::new(
+      source,
+      DecompressorOxide::new(),
+      offset,
+      offset,
+      uncompressed_offset,
+    )?;
+    inflater.skip_header(source)?;
+    Ok(inflater)
+  }
+
+  /// Returns an inflater from checkpoint `checkpoint` of `index`
+  fn at_checkpoint(
+    source: &Source,
+    index: &GzipIndex,
+    checkpoint: usize,
+  ) -> Result<Inflater, BgzfError> {
+    let at = &index.checkpoints[checkpoint];
+    if at.member_start {
+      return Inflater::at_member(source, at.compressed_offset, at.uncompressed_offset);
+    }
+    let member_offset = index.checkpoints[..checkpoint]
+      .iter()
+      .rev()
+      .find(|checkpoint| checkpoint.member_start)
+      .map_or(0, |member| member.compressed_offset);
+    let state = BlockBoundaryState {
+      num_bits: at.bit_count,
+      bit_buf: at.bits,
+      ..BlockBoundaryState::default()
+    };
+    let mut inflater = Inflater::new(
+      source,
+      DecompressorOxide::from_block_boundary_state(&state),
+      member_offset,
+      at.compressed_offset,
+      at.uncompressed_offset,
+    )?;
+    //the window ends where the data goes on, the ring wrapping around it
+    inflater.ring[..at.window.len()].copy_from_slice(&at.window);
+    inflater.ring_pos = at.window.len() % TINFL_LZ_DICT_SIZE;
+    inflater.history = at.window.len();
+    Ok(inflater)
+  }
+
+  fn corrupt(&self) -> BgzfError {
+    GzipError::CorruptDeflateStream {
+      member_offset: self.member_offset,
+    }
+    .into()
+  }
+
+  /// Reads more of the file into `input`, dropping what was consumed, and
+  /// returns the number of bytes read, 0 at the end of the file
+  fn fill_input(&mut self, source: &Source) -> io::Result<usize> {
+    self.input.drain(..self.input_pos);
+    self.input_offset += self.input_pos as u64;
+    self.input_pos = 0;
+    let start = self.input.len();
+    self.input.resize(start + INPUT_CHUNK, 0);
+    let read = read_fully(
+      source,
+      self.input_offset + start as u64,
+      &mut self.input[start..],
+    )?;
+    self.input.truncate(start + read);
+    Ok(read)
+  }
+
+  /// Returns the next byte of the file, None at its end
+  fn next_byte(&mut self, source: &Source) -> io::Result<Option<u8>> {
+    if self.input_pos == self.input.len() && self.fill_input(source)? == 0 {
+      return Ok(None);
+    }
+    self.input_pos += 1;
+    Ok(Some(self.input[self.input_pos - 1]))
+  }
+
+  /// Skips the gzip header of the member, checking it
+  fn skip_header(&mut self, source: &Source) -> Result<(), BgzfError> {
+    let invalid = GzipError::InvalidHeader {
+      offset: self.member_offset,
+    };
+    let mut fixed = [0u8; 10];
+    for byte in fixed.iter_mut() {
+      *byte = self.next_byte(source)?.ok_or_else(|| invalid.clone())?;
+    }
+    let flags = fixed[3];
+    //the reserved flags must be 0
+    if fixed[..3] != [0x1f, 0x8b, 8] || flags & 0xe0 != 0 {
+      return Err(invalid.into());
+    }
+    if flags & FEXTRA != 0 {
+      let low = self.next_byte(source)?.ok_or_else(|| invalid.clone())?;
+      let high = self.next_byte(source)?.ok_or_else(|| invalid.clone())?;
+      for _byte in 0..u16::from_le_bytes([low, high]) {
+        self.next_byte(source)?.ok_or_else(|| invalid.clone())?;
+      }
+    }
+    //the file name and the comment end with a zero byte
+    for flag in [FNAME, FCOMMENT] {
+      if flags & flag != 0 {
+        while self.next_byte(source)?.ok_or_else(|| invalid.clone())? != 0 {}
+      }
+    }
+    if flags & FHCRC != 0 {
+      for _byte in 0..2 {
+        self.next_byte(source)?.ok_or_else(|| invalid.clone())?;
+      }
+    }
+    Ok(())
+  }
+
+  /// The data decompressed and not consumed yet
+  fn pending(&self) -> &[u8] {
+    &self.ring[self.pending_start..self.pending_start + self.pending_length]
+  }
+
+  /// Consumes `length` bytes of the pending data
+  fn consume(&mut self, length: usize) {
+    self.pending_start += length;
+    self.pending_length -= length;
+    self.uncompressed_offset += length as u64;
+  }
+
+  /// Decompresses more of the member, once the pending data is consumed,
+  /// up to the end of the ring, of a deflate block when `stop_at_boundary`,
+  /// or of the member
+  fn inflate_more(&mut self, source: &Source, stop_at_boundary: bool) -> Result<(), BgzfError> {
+    self.boundary = false;
+    loop {
+      let mut flags = 0;
+      if self.input_offset + (self.input.len() as u64) < self.file_length {
+        flags |= TINFL_FLAG_HAS_MORE_INPUT;
+      }
+      if stop_at_boundary {
+        flags |= TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY;
+      }
+      let (status, read, written) = decompress(
+        &mut self.decompressor,
+        &self.input[self.input_pos..],
+        &mut self.ring,
+        self.ring_pos,
+        flags,
+      );
+      self.input_pos += read;
+      self.pending_start = self.ring_pos;
+      self.pending_length = written;
+      self.ring_pos = (self.ring_pos + written) % TINFL_LZ_DICT_SIZE;
+      self.history = min(self.history + written, TINFL_LZ_DICT_SIZE);
+      match status {
+        TINFLStatus::Done => self.ended = true,
+        TINFLStatus::BlockBoundary => self.boundary = true,
+        TINFLStatus::NeedsMoreInput => {
+          self.fill_input(source)?;
+        }
+        TINFLStatus::HasMoreOutput => {}
+        _ => return Err(self.corrupt()),
+      }
+      if written > 0 || self.ended || self.boundary {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Reads into `buf` from `pos`, at or after where the inflater is, up to
+  /// the end of the member, and returns the number of bytes read, 0 at the
+  /// end of the member
+  fn read_at(&mut self, source: &Source, pos: u64, buf: &mut [u8]) -> Result<usize, BgzfError> {
+    loop {
+      if self.pending_length == 0 {
+        if self.ended {
+          return Ok(0);
+        }
+        self.inflate_more(source, false)?;
+        continue;
+      }
+      //the distance is within the pending data, it fits in a usize
+      let skip = min(pos - self.uncompressed_offset, self.pending_length as u64) as usize;
+      self.consume(skip);
+      if self.pending_length > 0 {
+        let length = min(self.pending_length, buf.len());
+        buf[..length].copy_from_slice(&self.pending()[..length]);
+        self.consume(length);
+        return Ok(length);
+      }
+    }
+  }
+
+  /// Returns the checkpoint of where decompression stopped, at a deflate
+  /// block boundary with the pending data consumed
+  fn checkpoint(&self) -> GzipCheckpoint {
+    let state = self
+      .decompressor
+      .block_boundary_state()
+      .expect("Decompression stopped at a block boundary");
+    let mut window = Vec::with_capacity(self.history);
+    if self.history > self.ring_pos {
+      window.extend_from_slice(&self.ring[TINFL_LZ_DICT_SIZE - (self.history - self.ring_pos)..]);
+    }
+    window.extend_from_slice(&self.ring[self.ring_pos.saturating_sub(self.history)..self.ring_pos]);
+    GzipCheckpoint {
+      compressed_offset: self.input_offset + self.input_pos as u64,
+      uncompressed_offset: self.uncompressed_offset,
+      member_start: false,
+      bit_count: state.num_bits,
+      bits: state.bit_buf,
+      window,
+    }
+  }
+
+  /// Checks the trailer of the member, once its deflate data ended, against
+  /// `crc` and `length` of its data, and returns the offset right after it
+  fn check_trailer(&mut self, source: &Source, crc: u32, length: u64) -> Result<u64, BgzfError> {
+    let mismatch = GzipError::TrailerMismatch {
+      member_offset: self.member_offset,
+    };
+    let mut trailer = [0u8; 8];
+    for byte in trailer.iter_mut() {
+      *byte = self.next_byte(source)?.ok_or_else(|| mismatch.clone())?;
+    }
+    let expected_crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let expected_length = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    //the length is that of the data of the member, modulo 2^32
+    if (expected_crc, expected_length) != (crc, length as u32) {
+      return Err(mismatch.into());
+    }
+    Ok(self.input_offset + self.input_pos as u64)
+  }
+}
+
+/// Struct to read a plain gzip file with random access, through an index of
+/// checkpoints built by a full decompression pass. It is slower than
+/// `BgzfReader`: reads decompress from the checkpoint before them, up to
+/// `checkpoint_spacing` bytes plus the length of the deflate block, unless
+/// they go on from where the last read stopped. Only with the `gzip`
+/// feature.
+///
+/// Fields description:
+///
+/// gzip_file: the file read,
+/// index: the checkpoints of the file,
+/// pos: position the next read from the position starts at,
+/// inflater: where the last read stopped, which the next one goes on from
+/// when it starts after it and before the next checkpoint,
+pub struct GzipRandomReader {
+  gzip_file: Source,
+  index: GzipIndex,
+  pos: Position,
+  inflater: Mutex<Option<Inflater>>,
+}
+
+/// Options of a `GzipRandomReader`, from `GzipRandomReader::builder`
+///
+/// Fields description:
+///
+/// checkpoint_spacing: uncompressed bytes between checkpoints within a
+/// member, at least,
+/// cache_index: true to keep the index in a file next to the gzip file,
+#[derive(Clone, Debug)]
+pub struct GzipRandomReaderBuilder {
+  checkpoint_spacing: u64,
+  cache_index: bool,
+}
+
+impl Default for GzipRandomReaderBuilder {
+  fn default() -> GzipRandomReaderBuilder {
+    GzipRandomReaderBuilder {
+      checkpoint_spacing: DEFAULT_CHECKPOINT_SPACING,
+      cache_index: false,
+    }
+  }
+}
+
+impl GzipRandomReaderBuilder {
+  /// Sets the uncompressed bytes after which the next deflate block boundary
+  /// is a checkpoint, `DEFAULT_CHECKPOINT_SPACING` by default, at least 1.
+  /// Closer checkpoints make reads faster and the index larger, each
+  /// holding 32 KiB of data.
+  pub fn checkpoint_spacing(mut self, bytes: u64) -> GzipRandomReaderBuilder {
+    self.checkpoint_spacing = bytes.max(1);
+    self
+  }
+
+  /// Keeps the index in `<file>.gzridx` next to the file, in the format of
+  /// `GzipIndex::save` after the length and modification time of the file,
+  /// like `BgzfReaderBuilder::cache_index`. An up to date cache spares the
+  /// full decompression pass, a stale one is rebuilt.
+  pub fn cache_index(mut self, cache: bool) -> GzipRandomReaderBuilder {
+    self.cache_index = cache;
+    self
+  }
+
+  /// Opens the gzip file at `file_path`, building its index with a full
+  /// decompression pass, unless `cache_index` finds it cached
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::GzipRandomReader;
+  ///
+  /// let reader = GzipRandomReader::builder()
+  ///   .checkpoint_spacing(64 * 1024)
+  ///   .open(String::from("tests/data/multi_member.txt.gz"))
+  ///   .unwrap();
+  /// assert!(reader.index().checkpoints().len() > 4);
+  /// let mut line = [0; 14];
+  /// reader.read_at(300021, &mut line).unwrap();
+  /// assert_eq!(b"8740\tchr0\t9984", &line);
+  /// ```
+  pub fn open(self, file_path: String) -> Result<GzipRandomReader, BgzfError> {
+    let gzip_file = File::open(&file_path)?;
+    let metadata = gzip_file.metadata()?;
+    let gzip_file = Source::File(gzip_file);
+    if !self.cache_index {
+      let index = GzipIndex::build(&gzip_file, self.checkpoint_spacing)?;
+      return Ok(GzipRandomReader::with_index(gzip_file, index));
+    }
+    let cache_path = format!("{}.gzridx", file_path);
+    let cached = index_cache::load_stamped(&cache_path, &metadata)
+      .and_then(|cached| GzipIndex::load(&cached[..]).ok())
+      .filter(|index| index.compressed_length == metadata.len());
+    let index = match cached {
+      Some(index) => index,
+      None => {
+        let index = GzipIndex::build(&gzip_file, self.checkpoint_spacing)?;
+        let _ = index_cache::save_with(&cache_path, &metadata, |file| index.save(file));
+        index
+      }
+    };
+    Ok(GzipRandomReader::with_index(gzip_file, index))
+  }
+}
+
+/// Below are the steps to read a gzip file with random access,
+/// open it with `GzipRandomReader::open` (or `GzipRandomReader::builder()`
+/// for other options), then read with `read_at`, or with `Read` from the
+/// position set with `seek`.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::GzipRandomReader;
+/// use std::io::Read;
+///
+/// let reader = GzipRandomReader::open(String::from("tests/data/multi_member.txt.gz")).unwrap();
+/// reader.seek(100006);
+/// let mut line = String::new();
+/// (&reader).take(16).read_to_string(&mut line).unwrap();
+/// assert_eq!("2935\tchr14\t41569", line);
+/// assert_eq!(100022, reader.position());
+/// ```
+impl GzipRandomReader {
+  /// Opens the gzip file at `file_path` with the default options, see
+  /// `GzipRandomReaderBuilder::open`
+  pub fn open(file_path: String) -> Result<GzipRandomReader, BgzfError> {
+    GzipRandomReader::builder().open(file_path)
+  }
+
+  /// Returns a builder to open a file with other options than `open`
+  pub fn builder() -> GzipRandomReaderBuilder {
+    GzipRandomReaderBuilder::default()
+  }
+
+  /// Opens the gzip file at `file_path` with the checkpoints of `index`,
+  /// saved from an earlier `GzipRandomReader::index` of the same file,
+  /// without decompressing it. An index of a file of another length is
+  /// rejected with `GzipError::IndexMismatch`.
+  pub fn open_with_index(
+    file_path: String,
+    index: &GzipIndex,
+  ) -> Result<GzipRandomReader, BgzfError> {
+    let gzip_file = File::open(file_path)?;
+    let file_length = gzip_file.metadata()?.len();
+    if index.compressed_length != file_length {
+      return Err(
+        GzipError::IndexMismatch {
+          indexed_length: index.compressed_length,
+          file_length,
+        }
+        .into(),
+      );
+    }
+    Ok(GzipRandomReader::with_index(
+      Source::File(gzip_file),
+      index.clone(),
+    ))
+  }
+
+  fn with_index(gzip_file: Source, index: GzipIndex) -> GzipRandomReader {
+    GzipRandomReader {
+      gzip_file,
+      index,
+      pos: Position::new(0),
+      inflater: Mutex::new(None),
+    }
+  }
+
+  /// Returns the checkpoints of the file, to save with `GzipIndex::save`
+  pub fn index(&self) -> &GzipIndex {
+    &self.index
+  }
+
+  /// Returns the uncompressed length of the data
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.index.input_length
+  }
+
+  /// Sets the position the next read from the position starts at
+  pub fn seek(&self, pos: u64) {
+    self.pos.set(pos);
+  }
+
+  /// Returns the position the next read from the position starts at
+  pub fn position(&self) -> u64 {
+    self.pos.get()
+  }
+
+  /// Reads into `buf` from `pos`, without touching the position, and
+  /// returns the number of bytes read, fewer than `buf.len()` only at the
+  /// end of the data. Reads are made one at a time.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::GzipRandomReader;
+  ///
+  /// let reader = GzipRandomReader::open(String::from("tests/data/multi_member.txt.gz")).unwrap();
+  /// let mut start = [0; 8];
+  /// assert_eq!(8, reader.read_at(0, &mut start).unwrap());
+  /// assert_eq!(b"0\tchr0\t0", &start);
+  /// assert_eq!(0, reader.read_at(414339, &mut start).unwrap());
+  /// ```
+  pub fn read_at(&self, pos: u64, buf: &mut [u8]) -> Result<usize, BgzfError> {
+    let mut inflater = lock(&self.inflater);
+    let mut read = 0;
+    while read < buf.len() && pos.saturating_add(read as u64) < self.index.input_length {
+      let at = pos + read as u64;
+      let checkpoint = self.index.checkpoint_before(at);
+      let start = self.index.checkpoints[checkpoint].uncompressed_offset;
+      //the last read stopped between the checkpoint and `at`
+      let goes_on = inflater.as_ref().is_some_and(|inflater| {
+        (start..=at).contains(&inflater.uncompressed_offset)
+          && !(inflater.ended && inflater.pending_length == 0)
+      });
+      if !goes_on {
+        *inflater = Some(Inflater::at_checkpoint(
+          &self.gzip_file,
+          &self.index,
+          checkpoint,
+        )?);
+      }
+      let current = inflater.as_mut().unwrap();
+      match current.read_at(&self.gzip_file, at, &mut buf[read..])? {
+        //the member ended before the index says the data does
+        0 => return Err(current.corrupt()),
+        length => read += length,
+      }
+    }
+    Ok(read)
+  }
+}
+
+/// Reads from the position with `read_at`, 0 at the end of the data
+impl io::Read for &GzipRandomReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let pos = self.pos.get();
+    let read = self.read_at(pos, buf)?;
+    self.pos.set(pos + read as u64);
+    Ok(read)
+  }
+}
+
+impl io::Read for GzipRandomReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    io::Read::read(&mut &*self, buf)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::write_temp;
+  use miniz_oxide::deflate::compress_to_vec;
+  use std::fs;
+
+  /// Lines of varied text, compressing into several deflate blocks
+  fn test_content(lines: u32) -> Vec<u8> {
+    let mut content = Vec::new();
+    let mut state: u32 = 7;
+    for i in 0..lines {
+      state = state.wrapping_mul(1103515245).wrapping_add(12345);
+      content
+        .extend_from_slice(format!("{}\tchr{}\t{}\tgzip\n", i, i % 23, state >> 20).as_bytes());
+    }
+    content
+  }
+
+  /// Returns `data` as a gzip member with the header fields of `flags`
+  fn gzip_member(data: &[u8], flags: u8) -> Vec<u8> {
+    let mut member = vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 3];
+    if flags & FEXTRA != 0 {
+      member.extend_from_slice(&[6, 0, b'A', b'B', 2, 0, 1, 2]);
+    }
+    if flags & FNAME != 0 {
+      member.extend_from_slice(b"name.txt\0");
+    }
+    if flags & FCOMMENT != 0 {
+      member.extend_from_slice(b"a comment\0");
+    }
+    if flags & FHCRC != 0 {
+      member.extend_from_slice(&[0, 0]);
+    }
+    member.extend_from_slice(&compress_to_vec(data, 6));
+    member.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    member.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    member
+  }
+
+  fn open_bytes(
+    name: &str,
+    gzip: &[u8],
+    spacing: u64,
+  ) -> (String, Result<GzipRandomReader, BgzfError>) {
+    let path = write_temp(name, gzip).unwrap();
+    let reader = GzipRandomReader::builder()
+      .checkpoint_spacing(spacing)
+      .open(path.clone());
+    (path, reader)
+  }
+
+  #[test]
+  fn test_header_fields() {
+    let content = test_content(2000);
+    let mut gzip = Vec::new();
+    for flags in [
+      0,
+      FEXTRA,
+      FNAME | FCOMMENT,
+      FEXTRA | FNAME | FCOMMENT | FHCRC,
+    ] {
+      gzip.extend_from_slice(&gzip_member(&content, flags));
+    }
+    let (path, reader) = open_bytes("gzip_header_fields", &gzip, DEFAULT_CHECKPOINT_SPACING);
+    let reader = reader.unwrap();
+    assert_eq!(4 * content.len() as u64, reader.total_uncompressed_length());
+    let mut all = Vec::new();
+    io::Read::read_to_end(&mut &reader, &mut all).unwrap();
+    assert_eq!(content.repeat(4), all);
+    assert_eq!(4, reader.index().checkpoints().len());
+    assert!(reader.index().checkpoints().iter().all(|c| c.member_start));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_checkpoints_within_members() {
+    let content = test_content(40000);
+    let gzip = gzip_member(&content, 0);
+    let (path, reader) = open_bytes("gzip_checkpoints", &gzip, 100_000);
+    let reader = reader.unwrap();
+    let checkpoints = reader.index().checkpoints();
+    assert!(checkpoints.len() > 5, "{}", checkpoints.len());
+    let mut previous = 0;
+    for checkpoint in &checkpoints[1..] {
+      assert!(!checkpoint.member_start);
+      assert!(checkpoint.uncompressed_offset - previous >= 100_000);
+      assert_eq!(TINFL_LZ_DICT_SIZE, checkpoint.window_length());
+      assert!(checkpoint.bit_count < 8);
+      previous = checkpoint.uncompressed_offset;
+    }
+
+    //from before, at and after every checkpoint, backwards so that no read
+    //goes on from the one before
+    let mut buf = vec![0; 5000];
+    for checkpoint in checkpoints.iter().rev() {
+      let at = checkpoint.uncompressed_offset;
+      for pos in [at.saturating_sub(1), at, at + 1] {
+        let read = reader.read_at(pos, &mut buf).unwrap();
+        assert_eq!(&content[pos as usize..pos as usize + read], &buf[..read]);
+        assert_eq!(min(5000, content.len() - pos as usize), read);
+      }
+    }
+    //past the end
+    assert_eq!(0, reader.read_at(content.len() as u64, &mut buf).unwrap());
+    assert_eq!(0, reader.read_at(u64::MAX, &mut buf).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_gzip_errors() {
+    let content = test_content(3000);
+    let member = gzip_member(&content, FNAME);
+    let (path, reader) = open_bytes("gzip_error", b"", DEFAULT_CHECKPOINT_SPACING);
+    assert!(matches!(
+      reader,
+      Err(BgzfError::Gzip(GzipError::InvalidHeader { offset: 0 }))
+    ));
+    fs::remove_file(path).unwrap();
+
+    //a second member cut in its header, then bytes that are no member
+    for tail in [&member[..5], b"not gzip data"] {
+      let mut gzip = member.clone();
+      gzip.extend_from_slice(tail);
+      let (path, reader) = open_bytes("gzip_error", &gzip, DEFAULT_CHECKPOINT_SPACING);
+      let offset = member.len() as u64;
+      assert!(matches!(
+        reader,
+        Err(BgzfError::Gzip(GzipError::InvalidHeader { offset: at })) if at == offset
+      ));
+      fs::remove_file(path).unwrap();
+    }
+
+    let mut wrong_crc = member.clone();
+    let crc_at = wrong_crc.len() - 8;
+    wrong_crc[crc_at] ^= 1;
+    let mut corrupt = member.clone();
+    corrupt[40] ^= 0xff;
+    for gzip in [wrong_crc, member[..member.len() - 4].to_vec()] {
+      let (path, reader) = open_bytes("gzip_error", &gzip, DEFAULT_CHECKPOINT_SPACING);
+      assert!(matches!(
+        reader,
+        Err(BgzfError::Gzip(GzipError::TrailerMismatch {
+          member_offset: 0
+        }))
+      ));
+      fs::remove_file(path).unwrap();
+    }
+    for gzip in [corrupt, member[..member.len() / 2].to_vec()] {
+      let (path, reader) = open_bytes("gzip_error", &gzip, DEFAULT_CHECKPOINT_SPACING);
+      assert!(
+        matches!(
+          reader,
+          Err(BgzfError::Gzip(GzipError::CorruptDeflateStream {
+            member_offset: 0
+          }))
+            | Err(BgzfError::Gzip(GzipError::TrailerMismatch {
+              member_offset: 0
+            }))
+        ),
+        "{:?}",
+        reader.err()
+      );
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_index_save_and_load() {
+    let content = test_content(20000);
+    let mut gzip = gzip_member(&content, 0);
+    gzip.extend_from_slice(&gzip_member(b"second member", FNAME));
+    let (path, reader) = open_bytes("gzip_index", &gzip, 50_000);
+    let reader = reader.unwrap();
+    let mut saved = Vec::new();
+    reader.index().save(&mut saved).unwrap();
+    let index = GzipIndex::load(&saved[..]).unwrap();
+    assert_eq!(reader.index(), &index);
+
+    let reopened = GzipRandomReader::open_with_index(path.clone(), &index).unwrap();
+    let mut buf = vec![0; 100];
+    let pos = index.checkpoints()[2].uncompressed_offset + 10;
+    reopened.read_at(pos, &mut buf).unwrap();
+    assert_eq!(&content[pos as usize..pos as usize + 100], &buf[..]);
+    let end = content.len() as u64 + 7;
+    assert_eq!(6, reopened.read_at(end, &mut buf).unwrap());
+    assert_eq!(b"member", &buf[..6]);
+
+    //an index of another file, and indexes that are not
+    let other = write_temp("gzip_index_other", &gzip[..gzip.len() - 1]).unwrap();
+    assert!(matches!(
+      GzipRandomReader::open_with_index(other.clone(), &index),
+      Err(BgzfError::Gzip(GzipError::IndexMismatch { .. }))
+    ));
+    for garbage in [
+      &saved[..saved.len() - 1],
+      &saved[..20],
+      b"BGZI and more bytes of garbage",
+    ] {
+      let error = GzipIndex::load(garbage).unwrap_err();
+      assert!(
+        matches!(
+          error.kind(),
+          io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+        ),
+        "{:?}",
+        error
+      );
+    }
+    fs::remove_file(path).unwrap();
+    fs::remove_file(other).unwrap();
+  }
+
+  #[test]
+  fn test_cache_index() {
+    let content = test_content(20000);
+    let path = write_temp("gzip_cache", &gzip_member(&content, 0)).unwrap();
+    let cache_path = format!("{}.gzridx", path);
+    let builder = GzipRandomReader::builder()
+      .checkpoint_spacing(50_000)
+      .cache_index(true);
+    let built = builder.clone().open(path.clone()).unwrap();
+    assert!(fs::metadata(&cache_path).is_ok());
+    //the cached index is used whatever the spacing asked
+    let cached = builder.checkpoint_spacing(1).open(path.clone()).unwrap();
+    assert_eq!(built.index(), cached.index());
+    fs::remove_file(cache_path).unwrap();
+    fs::remove_file(path).unwrap();
+  }
+}
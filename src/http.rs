@@ -0,0 +1,109 @@
+use crate::RangeTransport;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// Transport making HTTP range requests for the file at a URL, which the
+/// server must support, as object stores and most static file servers do
+///
+/// Fields description:
+///
+/// agent: the HTTP client, keeping connections alive between requests,
+/// url: where the file is,
+pub struct HttpTransport {
+  agent: ureq::Agent,
+  url: String,
+}
+
+impl HttpTransport {
+  /// Returns a transport for the file at `url`, http or https
+  pub fn new(url: &str) -> HttpTransport {
+    HttpTransport::with_agent(ureq::Agent::new(), url)
+  }
+
+  /// Returns a transport for the file at `url` making its requests with
+  /// `agent`, for timeouts, proxies or TLS settings of its own
+  pub fn with_agent(agent: ureq::Agent, url: &str) -> HttpTransport {
+    HttpTransport {
+      agent,
+      url: url.to_string(),
+    }
+  }
+
+  /// Requests `first..=last`, the bytes of an HTTP range. The 416 status of
+  /// a range past the end is an answer too, giving the length of the file.
+  fn request(&self, first: u64, last: u64) -> io::Result<ureq::Response> {
+    let request = self.agent.get(&self.url);
+    match request
+      .set("Range", &format!("bytes={}-{}", first, last))
+      .call()
+    {
+      Ok(response) | Err(ureq::Error::Status(416, response)) => Ok(response),
+      Err(error) => Err(request_error(&self.url, error)),
+    }
+  }
+}
+
+impl RangeTransport for HttpTransport {
+  /// Asks for the first byte, the length being after the slash of the
+  /// Content-Range of the answer, which also tells that the server supports
+  /// range requests. An empty file has no first byte and is answered with a
+  /// 416 status giving its length the same way.
+  fn length(&self) -> io::Result<u64> {
+    let response = self.request(0, 0)?;
+    let content_range = response.header("Content-Range").unwrap_or("");
+    content_range
+      .rsplit_once('/')
+      .and_then(|(_range, length)| length.trim().parse().ok())
+      .ok_or_else(|| no_ranges(&self.url))
+  }
+
+  fn fetch(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+    if range.is_empty() {
+      return Ok(Vec::new());
+    }
+    let response = self.request(range.start, range.end - 1)?;
+    //a server ignoring the range sends the whole file with a 200 status
+    if response.status() != 206 {
+      return Err(no_ranges(&self.url));
+    }
+    let length = range.end - range.start;
+    let mut data = Vec::with_capacity(length as usize);
+    response.into_reader().take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+          "{} bytes of {} received from {}",
+          data.len(),
+          length,
+          self.url
+        ),
+      ));
+    }
+    Ok(data)
+  }
+}
+
+/// The error of a request failing for the file at `url`, a status like 404
+/// keeping the kind of the file errors it stands for
+fn request_error(url: &str, error: ureq::Error) -> io::Error {
+  match error {
+    ureq::Error::Status(status, _response) => {
+      let kind = match status {
+        404 | 410 => io::ErrorKind::NotFound,
+        401 | 403 => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+      };
+      io::Error::new(kind, format!("{} answered with status {}", url, status))
+    }
+    ureq::Error::Transport(transport) => io::Error::other(format!("{}: {}", url, transport)),
+  }
+}
+
+/// The error of a server not supporting range requests for `url`
+fn no_ranges(url: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::Unsupported,
+    format!("{} does not support range requests", url),
+  )
+}
@@ -0,0 +1,1014 @@
+//! Native block index of a bgzf file, to build once and open the file later
+//! without reading any block header.
+//!
+//! The binary format, all integers little-endian:
+//!
+//! magic: the 4 bytes `BGZI`,
+//! version: u32, currently 1,
+//! block count: u64,
+//! uncompressed length: u64, total of the blocks,
+//! then for every block a 24 byte record: compressed offset (u64),
+//! uncompressed offset (u64), block size (u32), uncompressed length (u32).
+//!
+//! With the `serde` feature `BgzfIndex` and `BlockInfo` implement `Serialize`
+//! and `Deserialize` as sequences rather than maps: an index is
+//! `[version, uncompressed length, [block, ...]]` and a block is
+//! `[compressed offset, uncompressed offset, block size, uncompressed length]`,
+//! version being the one of the binary format. This representation only
+//! changes along with that version, and indexes of an older version keep
+//! loading.
+
+use crate::writer::{EOF_MARKER, MAX_BLOCK_SIZE};
+use crate::{BlockInfo, ParseMode};
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const INDEX_MAGIC: [u8; 4] = *b"BGZI";
+
+const INDEX_VERSION: u32 = 1;
+
+const RECORD_LENGTH: usize = 24;
+
+/// Reasons an index can not describe a bgzf file, as found by
+/// `BgzfIndex::validate`. Blocks are numbered from 0 in index order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IndexError {
+  /// The block starts before the end of the block before it
+  Overlap {
+    block: usize,
+    compressed_offset: u64,
+    previous_end: u64,
+  },
+  /// Between the block and the one before it is a gap too small to hold even
+  /// an empty block
+  Gap {
+    block: usize,
+    compressed_offset: u64,
+    previous_end: u64,
+  },
+  /// The block does not start at the total uncompressed length of the blocks
+  /// before it, overlapping them or leaving a gap in the data
+  UncompressedOffsetMismatch {
+    block: usize,
+    uncompressed_offset: u64,
+    expected: u64,
+  },
+  /// The block holds no uncompressed data, so the block after it starts at
+  /// the same uncompressed offset, the index only holds data blocks
+  EmptyBlock {
+    block: usize,
+    uncompressed_offset: u64,
+  },
+  /// The block holds more uncompressed bytes than a BGZF block can
+  BlockTooLarge {
+    block: usize,
+    uncompressed_length: u32,
+  },
+  /// The block is smaller than the 28 bytes of an empty BGZF block
+  BlockSizeTooSmall { block: usize, block_size: u32 },
+  /// The block is larger than the 65536 bytes a BGZF block can be
+  BlockSizeTooLarge { block: usize, block_size: u32 },
+  /// The block ends past the largest offset a file can have
+  OffsetOverflow {
+    block: usize,
+    compressed_offset: u64,
+    block_size: u32,
+  },
+  /// The block ends past the end of the file
+  PastEndOfFile {
+    block: usize,
+    block_end: u64,
+    file_length: u64,
+  },
+}
+
+impl Error for IndexError {}
+
+impl fmt::Display for IndexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      IndexError::Overlap {
+        block,
+        compressed_offset,
+        previous_end,
+      } => write!(
+        f,
+        "Index block {} at offset {} overlaps the previous block ending at {}",
+        block, compressed_offset, previous_end
+      ),
+      IndexError::Gap {
+        block,
+        compressed_offset,
+        previous_end,
+      } => write!(
+        f,
+        "Index block {} at offset {} leaves a gap no block fits in after the previous block ending at {}",
+        block, compressed_offset, previous_end
+      ),
+      IndexError::UncompressedOffsetMismatch {
+        block,
+        uncompressed_offset,
+        expected,
+      } => write!(
+        f,
+        "Index block {} starts at uncompressed offset {} instead of {}",
+        block, uncompressed_offset, expected
+      ),
+      IndexError::EmptyBlock {
+        block,
+        uncompressed_offset,
+      } => write!(
+        f,
+        "Index block {} holds no data, at uncompressed offset {} like the next block",
+        block, uncompressed_offset
+      ),
+      IndexError::BlockTooLarge {
+        block,
+        uncompressed_length,
+      } => write!(
+        f,
+        "Index block {} holds {} uncompressed bytes, more than BGZF allows",
+        block, uncompressed_length
+      ),
+      IndexError::BlockSizeTooSmall { block, block_size } => write!(
+        f,
+        "Index block {} is {} bytes long, less than an empty BGZF block",
+        block, block_size
+      ),
+      IndexError::BlockSizeTooLarge { block, block_size } => write!(
+        f,
+        "Index block {} is {} bytes long, more than BGZF allows",
+        block, block_size
+      ),
+      IndexError::OffsetOverflow {
+        block,
+        compressed_offset,
+        block_size,
+      } => write!(
+        f,
+        "Index block {} at offset {} is {} bytes long, past the largest file offset",
+        block, compressed_offset, block_size
+      ),
+      IndexError::PastEndOfFile {
+        block,
+        block_end,
+        file_length,
+      } => write!(
+        f,
+        "Index block {} ends at offset {} past the end of the {} byte file",
+        block, block_end, file_length
+      ),
+    }
+  }
+}
+
+/// Every block of a bgzf file and the total uncompressed length, as returned
+/// by `BgzfReader::index`
+///
+/// Fields description:
+///
+/// blocks: the data blocks in file order, one after the other in the data, and
+/// in the file where only empty blocks can be between them,
+/// input_length: total uncompressed length of the blocks,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BgzfIndex {
+  blocks: Vec<BlockInfo>,
+  input_length: u64,
+}
+
+impl BgzfIndex {
+  /// Returns the index of `blocks`, which must pass the checks of `validate`
+  /// that do not need the file
+  pub(crate) fn new(blocks: Vec<BlockInfo>) -> Result<BgzfIndex, IndexError> {
+    let input_length = check_blocks(&blocks)?;
+    Ok(BgzfIndex {
+      blocks,
+      input_length,
+    })
+  }
+
+  /// Checks that the index can describe a file of `file_length` bytes: the
+  /// blocks follow each other in the file without overlapping, with only gaps
+  /// large enough for the empty blocks that are not indexed, they follow each
+  /// other in the uncompressed data from offset 0, each starting where the
+  /// one before it ends, none is empty or holds more than the 65536
+  /// uncompressed bytes of a BGZF block, each is between 28 and 65536 bytes
+  /// long like a BGZF block, and the last one ends within the file. This
+  /// catches an index of another file before its blocks are read.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, IndexError};
+  ///
+  /// let index = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap().index().unwrap();
+  /// assert_eq!(Ok(()), index.validate(239));
+  /// assert!(matches!(
+  ///   index.validate(200),
+  ///   Err(IndexError::PastEndOfFile { block: 0, .. })
+  /// ));
+  /// ```
+  pub fn validate(&self, file_length: u64) -> Result<(), IndexError> {
+    check_blocks(&self.blocks)?;
+    let block_end = self.data_end();
+    if block_end > file_length {
+      return Err(IndexError::PastEndOfFile {
+        block: self.blocks.len() - 1,
+        block_end,
+        file_length,
+      });
+    }
+    Ok(())
+  }
+
+  /// The data blocks of the file in increasing offset order
+  pub fn blocks(&self) -> &[BlockInfo] {
+    &self.blocks
+  }
+
+  /// Total length of the uncompressed data
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.input_length
+  }
+
+  /// Returns the (offset, length) spans of the file holding the blocks with
+  /// any of the uncompressed range `ustart..uend`, in file order, blocks
+  /// next to each other in the file making a single span. Fetching them is
+  /// enough to read the range, for example with HTTP range requests planned
+  /// from the index before the file is opened. Empty when the range is, or
+  /// starts past the end of the data.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let index = BgzfReader::from_bytes(make_bgzf(&[7; 300], 100)).unwrap().index().unwrap();
+  /// let blocks = index.blocks();
+  /// let spans = index.compressed_ranges_for(150, 250);
+  /// let length = u64::from(blocks[1].block_size + blocks[2].block_size);
+  /// assert_eq!(vec![(blocks[1].compressed_offset, length)], spans);
+  /// assert!(index.compressed_ranges_for(300, 400).is_empty());
+  /// ```
+  pub fn compressed_ranges_for(&self, ustart: u64, uend: u64) -> Vec<(u64, u64)> {
+    let first_block = self.blocks.partition_point(|block| {
+      block.uncompressed_offset + u64::from(block.uncompressed_length) <= ustart
+    });
+    coalesce_blocks(
+      self.blocks[first_block..]
+        .iter()
+        .take_while(|block| block.uncompressed_offset < uend && ustart < uend)
+        .copied(),
+    )
+  }
+
+  /// Offset right after the last data block in the file
+  pub(crate) fn data_end(&self) -> u64 {
+    self.blocks.last().map_or(0, |block| {
+      block.compressed_offset + u64::from(block.block_size)
+    })
+  }
+
+  /// Writes the index in the binary format described in the module docs
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfIndex, BgzfReader};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut saved = Vec::new();
+  /// reader.index().unwrap().save(&mut saved).unwrap();
+  ///
+  /// let index = BgzfIndex::load(&saved[..]).unwrap();
+  /// let reader = BgzfReader::open_with_index(String::from("bgzf_test.bgz"), &index).unwrap();
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// ```
+  pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+    let mut saved = Vec::with_capacity(24 + self.blocks.len() * RECORD_LENGTH);
+    saved.extend_from_slice(&INDEX_MAGIC);
+    saved.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+    saved.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+    saved.extend_from_slice(&self.input_length.to_le_bytes());
+    for block in &self.blocks {
+      saved.extend_from_slice(&block.compressed_offset.to_le_bytes());
+      saved.extend_from_slice(&block.uncompressed_offset.to_le_bytes());
+      saved.extend_from_slice(&block.block_size.to_le_bytes());
+      saved.extend_from_slice(&block.uncompressed_length.to_le_bytes());
+    }
+    out.write_all(&saved)?;
+    out.flush()
+  }
+
+  /// Reads an index written by `save`. Another magic or version, a record
+  /// count that does not match the data and blocks that do not follow each
+  /// other are `InvalidData` errors.
+  pub fn load<R: Read>(input: R) -> io::Result<BgzfIndex> {
+    BgzfIndex::load_with_mode(input, ParseMode::Default).map(|(index, _repairs)| index)
+  }
+
+  /// Same as `load`, with the blocks repaired in `ParseMode::Permissive` when
+  /// their uncompressed offsets are inconsistent, as left by a buggy writer:
+  /// empty blocks, whose offset is also the next block's, are dropped, and
+  /// the offsets of the others are set to the total length of the blocks
+  /// before them. The errors repaired are returned along with the index, as
+  /// warnings, numbering the blocks in the order they were saved in. The
+  /// other modes fail on them, with an `IndexError` inside the `InvalidData`
+  /// error, as do all modes on blocks that do not follow each other in the
+  /// file.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfIndex, BgzfReader, IndexError, ParseMode};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut saved = Vec::new();
+  /// reader.index().unwrap().save(&mut saved).unwrap();
+  ///
+  /// let (index, repairs) = BgzfIndex::load_with_mode(&saved[..], ParseMode::Permissive).unwrap();
+  /// assert_eq!(reader.index().unwrap(), index);
+  /// assert_eq!(Vec::<IndexError>::new(), repairs);
+  /// ```
+  pub fn load_with_mode<R: Read>(
+    mut input: R,
+    mode: ParseMode,
+  ) -> io::Result<(BgzfIndex, Vec<IndexError>)> {
+    let mut header = [0u8; 24];
+    input.read_exact(&mut header)?;
+    if header[..4] != INDEX_MAGIC {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Not a bgzf index, the magic number is wrong",
+      ));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != INDEX_VERSION {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unsupported bgzf index version {}", version),
+      ));
+    }
+    let block_count = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let input_length = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+    //read to the end rather than allocating for a count that may be garbage
+    let mut records = Vec::new();
+    input.read_to_end(&mut records)?;
+    if records.len() as u64 != block_count.saturating_mul(RECORD_LENGTH as u64) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Bgzf index block count does not match its records",
+      ));
+    }
+    let blocks = records
+      .chunks(RECORD_LENGTH)
+      .map(|record| BlockInfo {
+        compressed_offset: u64::from_le_bytes(record[..8].try_into().unwrap()),
+        uncompressed_offset: u64::from_le_bytes(record[8..16].try_into().unwrap()),
+        block_size: u32::from_le_bytes(record[16..20].try_into().unwrap()),
+        uncompressed_length: u32::from_le_bytes(record[20..24].try_into().unwrap()),
+      })
+      .collect();
+    let (blocks, repairs) = match mode {
+      ParseMode::Permissive => repair_blocks(blocks),
+      ParseMode::Strict | ParseMode::Default => (blocks, Vec::new()),
+    };
+    let index = BgzfIndex::new(blocks)
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    if index.input_length != input_length {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Bgzf index length does not match its blocks",
+      ));
+    }
+    Ok((index, repairs))
+  }
+}
+
+/// Checks everything `BgzfIndex::validate` does but the file length, and
+/// returns the total uncompressed length of the blocks
+fn check_blocks(blocks: &[BlockInfo]) -> Result<u64, IndexError> {
+  let mut previous_end = 0;
+  let mut input_length = 0;
+  for (index, block) in blocks.iter().enumerate() {
+    if block.compressed_offset < previous_end {
+      return Err(IndexError::Overlap {
+        block: index,
+        compressed_offset: block.compressed_offset,
+        previous_end,
+      });
+    }
+    //the smallest block there can be between two data blocks is an empty one
+    //like the EOF marker
+    if block.compressed_offset > previous_end
+      && block.compressed_offset - previous_end < EOF_MARKER.len() as u64
+    {
+      return Err(IndexError::Gap {
+        block: index,
+        compressed_offset: block.compressed_offset,
+        previous_end,
+      });
+    }
+    if block.uncompressed_length == 0 {
+      return Err(IndexError::EmptyBlock {
+        block: index,
+        uncompressed_offset: block.uncompressed_offset,
+      });
+    }
+    if block.uncompressed_offset != input_length {
+      return Err(IndexError::UncompressedOffsetMismatch {
+        block: index,
+        uncompressed_offset: block.uncompressed_offset,
+        expected: input_length,
+      });
+    }
+    if block.uncompressed_length as usize > MAX_BLOCK_SIZE {
+      return Err(IndexError::BlockTooLarge {
+        block: index,
+        uncompressed_length: block.uncompressed_length,
+      });
+    }
+    if (block.block_size as usize) < EOF_MARKER.len() {
+      return Err(IndexError::BlockSizeTooSmall {
+        block: index,
+        block_size: block.block_size,
+      });
+    }
+    if block.block_size as usize > MAX_BLOCK_SIZE {
+      return Err(IndexError::BlockSizeTooLarge {
+        block: index,
+        block_size: block.block_size,
+      });
+    }
+    previous_end = block
+      .compressed_offset
+      .checked_add(u64::from(block.block_size))
+      .ok_or(IndexError::OffsetOverflow {
+        block: index,
+        compressed_offset: block.compressed_offset,
+        block_size: block.block_size,
+      })?;
+    input_length += u64::from(block.uncompressed_length);
+  }
+  Ok(input_length)
+}
+
+/// Drops the empty `blocks` and sets the uncompressed offset of the others
+/// to the total length of the blocks before them, and returns them with the
+/// errors of `check_blocks` this repaired
+fn repair_blocks(blocks: Vec<BlockInfo>) -> (Vec<BlockInfo>, Vec<IndexError>) {
+  let mut repairs = Vec::new();
+  let mut input_length = 0;
+  let mut repaired = Vec::with_capacity(blocks.len());
+  for (index, block) in blocks.into_iter().enumerate() {
+    if block.uncompressed_length == 0 {
+      repairs.push(IndexError::EmptyBlock {
+        block: index,
+        uncompressed_offset: block.uncompressed_offset,
+      });
+      continue;
+    }
+    if block.uncompressed_offset != input_length {
+      repairs.push(IndexError::UncompressedOffsetMismatch {
+        block: index,
+        uncompressed_offset: block.uncompressed_offset,
+        expected: input_length,
+      });
+    }
+    repaired.push(BlockInfo {
+      uncompressed_offset: input_length,
+      ..block
+    });
+    input_length += u64::from(block.uncompressed_length);
+  }
+  (repaired, repairs)
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+  use super::{BgzfIndex, INDEX_VERSION};
+  use crate::BlockInfo;
+  use serde::de::{Deserialize, Deserializer, Error};
+  use serde::ser::{Serialize, Serializer};
+
+  impl Serialize for BlockInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+      (
+        self.compressed_offset,
+        self.uncompressed_offset,
+        self.block_size,
+        self.uncompressed_length,
+      )
+        .serialize(serializer)
+    }
+  }
+
+  impl<'de> Deserialize<'de> for BlockInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+      let (compressed_offset, uncompressed_offset, block_size, uncompressed_length) =
+        <(u64, u64, u32, u32)>::deserialize(deserializer)?;
+      Ok(BlockInfo {
+        uncompressed_offset,
+        compressed_offset,
+        block_size,
+        uncompressed_length,
+      })
+    }
+  }
+
+  impl Serialize for BgzfIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+      (INDEX_VERSION, self.input_length, &self.blocks).serialize(serializer)
+    }
+  }
+
+  //same checks as BgzfIndex::load
+  impl<'de> Deserialize<'de> for BgzfIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+      let (version, input_length, blocks) =
+        <(u32, u64, Vec<BlockInfo>)>::deserialize(deserializer)?;
+      if version != INDEX_VERSION {
+        return Err(D::Error::custom(format!(
+          "Unsupported bgzf index version {}",
+          version
+        )));
+      }
+      let index = BgzfIndex::new(blocks).map_err(D::Error::custom)?;
+      if index.input_length != input_length {
+        return Err(D::Error::custom(
+          "Bgzf index length does not match its blocks",
+        ));
+      }
+      Ok(index)
+    }
+  }
+}
+
+/// Returns the (offset, length) spans of the file holding `blocks`, given in
+/// file order, those next to each other making a single span
+pub(crate) fn coalesce_blocks(blocks: impl Iterator<Item = BlockInfo>) -> Vec<(u64, u64)> {
+  let mut spans: Vec<(u64, u64)> = Vec::new();
+  for block in blocks {
+    match spans.last_mut() {
+      Some((offset, length)) if *offset + *length == block.compressed_offset => {
+        *length += u64::from(block.block_size)
+      }
+      _ => spans.push((block.compressed_offset, u64::from(block.block_size))),
+    }
+  }
+  spans
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_with_anomaly, write_temp};
+  use crate::{AnomalyKind, BgzfError, BgzfReader};
+  use std::fs;
+
+  const TEST_CONTENT: &[u8] = b"This is just a bgzf test,lets see how it reacts. :). I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarantee that it will work. So I am just trying to test bgzf with this text file. Have a great day software lovers.\n";
+
+  fn saved_index(block_size: usize) -> Vec<u8> {
+    let path = write_temp("index_saved", &make_bgzf(TEST_CONTENT, block_size)).unwrap();
+    let mut saved = Vec::new();
+    BgzfReader::new(path.clone())
+      .unwrap()
+      .index()
+      .unwrap()
+      .save(&mut saved)
+      .unwrap();
+    fs::remove_file(path).unwrap();
+    saved
+  }
+
+  fn load_error(saved: &[u8]) -> String {
+    BgzfIndex::load(saved).unwrap_err().to_string()
+  }
+
+  #[test]
+  fn test_save_load_round_trip() {
+    let path = write_temp("index_round_trip", &make_bgzf(TEST_CONTENT, 100)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let index = reader.index().unwrap();
+    assert_eq!(3, index.blocks().len());
+    assert_eq!(280, index.total_uncompressed_length());
+
+    let mut saved = Vec::new();
+    index.save(&mut saved).unwrap();
+    assert_eq!(24 + 3 * RECORD_LENGTH, saved.len());
+    assert_eq!(b"BGZI", &saved[..4]);
+    let loaded = BgzfIndex::load(&saved[..]).unwrap();
+    assert_eq!(index, loaded);
+
+    let indexed = BgzfReader::open_with_index(path.clone(), &loaded).unwrap();
+    assert_eq!(
+      reader.block_offsets().collect::<Vec<_>>(),
+      indexed.block_offsets().collect::<Vec<_>>()
+    );
+    indexed.seek(120);
+    let mut content = vec![0; 20];
+    indexed.read_to(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[120..140], &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_load_rejects_invalid_indexes() {
+    let saved = saved_index(100);
+    let mut wrong_magic = saved.clone();
+    wrong_magic[0] = b'X';
+    assert_eq!(
+      "Not a bgzf index, the magic number is wrong",
+      load_error(&wrong_magic)
+    );
+
+    let mut wrong_version = saved.clone();
+    wrong_version[4] = 2;
+    assert_eq!(
+      "Unsupported bgzf index version 2",
+      load_error(&wrong_version)
+    );
+
+    assert_eq!(
+      "Bgzf index block count does not match its records",
+      load_error(&saved[..saved.len() - 1])
+    );
+    assert_eq!(
+      io::ErrorKind::UnexpectedEof,
+      BgzfIndex::load(&saved[..10]).unwrap_err().kind()
+    );
+
+    let mut wrong_length = saved.clone();
+    wrong_length[16] += 1;
+    assert_eq!(
+      "Bgzf index length does not match its blocks",
+      load_error(&wrong_length)
+    );
+
+    //second record's compressed offset moved back into the first block
+    let mut overlap = saved.clone();
+    overlap[24 + RECORD_LENGTH] -= 1;
+    assert!(load_error(&overlap).contains("overlaps the previous block"));
+  }
+
+  fn block(compressed_offset: u64, uncompressed_offset: u64) -> BlockInfo {
+    BlockInfo {
+      uncompressed_offset,
+      compressed_offset,
+      block_size: 100,
+      uncompressed_length: 1000,
+    }
+  }
+
+  /// Index of `blocks` without the checks of `new`
+  fn unchecked_index(blocks: Vec<BlockInfo>) -> BgzfIndex {
+    let input_length = blocks
+      .iter()
+      .map(|block| u64::from(block.uncompressed_length))
+      .sum();
+    BgzfIndex {
+      blocks,
+      input_length,
+    }
+  }
+
+  #[test]
+  fn test_validate() {
+    let valid = unchecked_index(vec![block(0, 0), block(100, 1000), block(200, 2000)]);
+    assert_eq!(Ok(()), valid.validate(300));
+    assert_eq!(Ok(()), valid.validate(328));
+    //an empty block between data blocks, like in files joined with cat
+    let with_empty_block = unchecked_index(vec![block(0, 0), block(128, 1000)]);
+    assert_eq!(Ok(()), with_empty_block.validate(228));
+    assert_eq!(Ok(()), unchecked_index(Vec::new()).validate(0));
+
+    assert_eq!(
+      Err(IndexError::PastEndOfFile {
+        block: 2,
+        block_end: 300,
+        file_length: 299
+      }),
+      valid.validate(299)
+    );
+    assert_eq!(
+      Err(IndexError::Overlap {
+        block: 1,
+        compressed_offset: 99,
+        previous_end: 100
+      }),
+      unchecked_index(vec![block(0, 0), block(99, 1000)]).validate(1000)
+    );
+    //the same block twice is not increasing
+    assert_eq!(
+      Err(IndexError::Overlap {
+        block: 1,
+        compressed_offset: 0,
+        previous_end: 100
+      }),
+      unchecked_index(vec![block(0, 0), block(0, 1000)]).validate(1000)
+    );
+    assert_eq!(
+      Err(IndexError::Gap {
+        block: 1,
+        compressed_offset: 127,
+        previous_end: 100
+      }),
+      unchecked_index(vec![block(0, 0), block(127, 1000)]).validate(1000)
+    );
+    assert_eq!(
+      Err(IndexError::UncompressedOffsetMismatch {
+        block: 1,
+        uncompressed_offset: 999,
+        expected: 1000
+      }),
+      unchecked_index(vec![block(0, 0), block(100, 999)]).validate(1000)
+    );
+    let mut too_large = block(0, 0);
+    too_large.uncompressed_length = 65537;
+    assert_eq!(
+      Err(IndexError::BlockTooLarge {
+        block: 0,
+        uncompressed_length: 65537
+      }),
+      unchecked_index(vec![too_large]).validate(1000)
+    );
+    too_large.uncompressed_length = 65536;
+    assert_eq!(Ok(()), unchecked_index(vec![too_large]).validate(1000));
+  }
+
+  #[test]
+  fn test_validate_block_sizes() {
+    //a block of 0 bytes would share its offset with the next one
+    let mut too_small = block(0, 0);
+    too_small.block_size = 0;
+    assert_eq!(
+      Err(IndexError::BlockSizeTooSmall {
+        block: 0,
+        block_size: 0
+      }),
+      unchecked_index(vec![too_small, block(0, 1000)]).validate(1000)
+    );
+    too_small.block_size = 27;
+    assert_eq!(
+      Err(IndexError::BlockSizeTooSmall {
+        block: 0,
+        block_size: 27
+      }),
+      unchecked_index(vec![too_small]).validate(1000)
+    );
+    too_small.block_size = 28;
+    assert_eq!(Ok(()), unchecked_index(vec![too_small]).validate(1000));
+
+    let mut too_large = block(0, 0);
+    too_large.block_size = u32::MAX;
+    assert_eq!(
+      Err(IndexError::BlockSizeTooLarge {
+        block: 0,
+        block_size: u32::MAX
+      }),
+      unchecked_index(vec![too_large]).validate(u64::MAX)
+    );
+    too_large.block_size = 65537;
+    assert_eq!(
+      Err(IndexError::BlockSizeTooLarge {
+        block: 0,
+        block_size: 65537
+      }),
+      unchecked_index(vec![too_large]).validate(u64::MAX)
+    );
+    too_large.block_size = 65536;
+    assert_eq!(Ok(()), unchecked_index(vec![too_large]).validate(65536));
+  }
+
+  #[test]
+  fn test_validate_offset_overflow() {
+    let past_the_end = block(u64::MAX - 50, 0);
+    assert_eq!(
+      Err(IndexError::OffsetOverflow {
+        block: 0,
+        compressed_offset: u64::MAX - 50,
+        block_size: 100
+      }),
+      unchecked_index(vec![past_the_end]).validate(u64::MAX)
+    );
+    //a loaded index is checked the same way
+    let mut saved = saved_index(100);
+    saved[24..32].copy_from_slice(&(u64::MAX - 50).to_le_bytes());
+    assert!(load_error(&saved).contains("past the largest file offset"));
+  }
+
+  #[test]
+  fn test_inconsistent_uncompressed_offsets() {
+    //two files joined with cat, the EOF marker of the first between them
+    let bgzf = make_with_anomaly(TEST_CONTENT, 100, AnomalyKind::EmptyBlock);
+    let path = write_temp("index_inconsistent", &bgzf).unwrap();
+    let index = BgzfReader::new(path.clone()).unwrap().index().unwrap();
+    let blocks = index.blocks();
+    let marker = blocks
+      .windows(2)
+      .position(|pair| pair[1].compressed_offset - pair[0].compressed_offset > 100)
+      .unwrap()
+      + 1;
+    let previous = blocks[marker - 1];
+    let data_end = previous.compressed_offset + u64::from(previous.block_size);
+
+    //an index the marker is in too, at the offset of the next block
+    let mut with_marker = blocks.to_vec();
+    with_marker.insert(
+      marker,
+      BlockInfo {
+        compressed_offset: data_end,
+        uncompressed_offset: blocks[marker].uncompressed_offset,
+        block_size: 28,
+        uncompressed_length: 0,
+      },
+    );
+    //and one whose second block overlaps the first in the data
+    let mut overlapping = blocks.to_vec();
+    overlapping[1].uncompressed_offset -= 30;
+    let empty_block = IndexError::EmptyBlock {
+      block: marker,
+      uncompressed_offset: blocks[marker].uncompressed_offset,
+    };
+    let overlap = IndexError::UncompressedOffsetMismatch {
+      block: 1,
+      uncompressed_offset: blocks[1].uncompressed_offset - 30,
+      expected: blocks[1].uncompressed_offset,
+    };
+
+    for (blocks, error) in [(with_marker, empty_block), (overlapping, overlap)] {
+      let mut saved = Vec::new();
+      unchecked_index(blocks).save(&mut saved).unwrap();
+      for mode in [ParseMode::Strict, ParseMode::Default] {
+        let failed = BgzfIndex::load_with_mode(&saved[..], mode).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, failed.kind());
+        assert_eq!(
+          Some(&error),
+          failed.get_ref().unwrap().downcast_ref::<IndexError>()
+        );
+      }
+      assert_eq!(error.to_string(), load_error(&saved));
+
+      let (repaired, repairs) =
+        BgzfIndex::load_with_mode(&saved[..], ParseMode::Permissive).unwrap();
+      assert_eq!(vec![error], repairs);
+      assert_eq!(index, repaired);
+      //reads find the data where it is
+      let reader = BgzfReader::open_with_index(path.clone(), &repaired).unwrap();
+      for block in repaired.blocks() {
+        let mut content = vec![0; 10];
+        reader.seek(block.uncompressed_offset);
+        assert_eq!(10, reader.read_to(&mut content).unwrap());
+        let start = block.uncompressed_offset as usize;
+        assert_eq!(&TEST_CONTENT[start..start + 10], &content[..]);
+      }
+    }
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(
+      Err(IndexError::EmptyBlock {
+        block: 0,
+        uncompressed_offset: 0
+      }),
+      unchecked_index(vec![BlockInfo {
+        uncompressed_length: 0,
+        ..block(0, 0)
+      }])
+      .validate(1000)
+    );
+  }
+
+  #[cfg(feature = "serde")]
+  fn read_with_index(path: &str, index: &BgzfIndex) -> Vec<u8> {
+    let reader = BgzfReader::open_with_index(String::from(path), index).unwrap();
+    let mut content = Vec::new();
+    for block in reader.block_offsets() {
+      let mut data = vec![0; block.uncompressed_length as usize];
+      reader.read_to(&mut data).unwrap();
+      content.extend_from_slice(&data);
+    }
+    content
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip() {
+    let path = write_temp("index_serde", &make_bgzf(TEST_CONTENT, 100)).unwrap();
+    let index = BgzfReader::new(path.clone()).unwrap().index().unwrap();
+
+    //the block sizes depend on the compression backend
+    let json = serde_json::to_string(&index).unwrap();
+    let blocks = index
+      .blocks()
+      .iter()
+      .map(|block| {
+        format!(
+          "[{},{},{},{}]",
+          block.compressed_offset,
+          block.uncompressed_offset,
+          block.block_size,
+          block.uncompressed_length
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(format!("[1,280,[{}]]", blocks.join(",")), json);
+    assert_eq!(
+      vec![(0, 100), (100, 100), (200, 80)],
+      index
+        .blocks()
+        .iter()
+        .map(|block| (block.uncompressed_offset, block.uncompressed_length))
+        .collect::<Vec<_>>()
+    );
+    let from_json: BgzfIndex = serde_json::from_str(&json).unwrap();
+    assert_eq!(index, from_json);
+    assert_eq!(TEST_CONTENT, &read_with_index(&path, &from_json)[..]);
+
+    let encoded = bincode::serialize(&index).unwrap();
+    let from_bincode: BgzfIndex = bincode::deserialize(&encoded).unwrap();
+    assert_eq!(index, from_bincode);
+    assert_eq!(TEST_CONTENT, &read_with_index(&path, &from_bincode)[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_rejects_invalid_indexes() {
+    let error = |json: &str| {
+      serde_json::from_str::<BgzfIndex>(json)
+        .unwrap_err()
+        .to_string()
+    };
+    assert!(error("[2,100,[[0,0,111,100]]]").contains("Unsupported bgzf index version 2"));
+    assert!(
+      error("[1,101,[[0,0,111,100]]]").contains("Bgzf index length does not match its blocks")
+    );
+    assert!(
+      error("[1,200,[[0,0,111,100],[110,100,111,100]]]").contains("overlaps the previous block")
+    );
+    assert!(serde_json::from_str::<BgzfIndex>("{\"blocks\":[]}").is_err());
+  }
+
+  #[test]
+  fn test_compressed_ranges_for() {
+    let index = BgzfReader::from_bytes(make_bgzf(TEST_CONTENT, 100))
+      .unwrap()
+      .index()
+      .unwrap();
+    let blocks = index.blocks();
+    let span = |first: usize, last: usize| {
+      let length = blocks[first..=last]
+        .iter()
+        .map(|block| u64::from(block.block_size))
+        .sum::<u64>();
+      (blocks[first].compressed_offset, length)
+    };
+    //inside one block, and on its edges
+    assert_eq!(vec![span(1, 1)], index.compressed_ranges_for(120, 180));
+    assert_eq!(vec![span(1, 1)], index.compressed_ranges_for(100, 200));
+    assert_eq!(vec![span(0, 0)], index.compressed_ranges_for(99, 100));
+    //a run of blocks, coalesced
+    assert_eq!(vec![span(0, 2)], index.compressed_ranges_for(0, 280));
+    assert_eq!(vec![span(0, 1)], index.compressed_ranges_for(50, 101));
+    //touching the last block, and past the end of the data
+    assert_eq!(vec![span(1, 2)], index.compressed_ranges_for(199, 280));
+    assert_eq!(vec![span(2, 2)], index.compressed_ranges_for(279, 1000));
+    assert_eq!(vec![span(2, 2)], index.compressed_ranges_for(200, u64::MAX));
+    assert!(index.compressed_ranges_for(280, 1000).is_empty());
+    //empty ranges
+    assert!(index.compressed_ranges_for(150, 150).is_empty());
+    assert!(index.compressed_ranges_for(200, 100).is_empty());
+  }
+
+  #[test]
+  fn test_coalesce_blocks_splits_at_gaps() {
+    let block = |compressed_offset, block_size| BlockInfo {
+      uncompressed_offset: 0,
+      compressed_offset,
+      block_size,
+      uncompressed_length: 10,
+    };
+    assert!(coalesce_blocks(std::iter::empty()).is_empty());
+    assert_eq!(
+      vec![(0, 50), (78, 40)],
+      coalesce_blocks(vec![block(0, 20), block(20, 30), block(78, 15), block(93, 25)].into_iter())
+    );
+  }
+
+  #[test]
+  fn test_open_with_index_of_longer_file() {
+    let long_path = write_temp("index_long", &make_bgzf(TEST_CONTENT, 100)).unwrap();
+    let short_path = write_temp("index_short", &make_bgzf(&TEST_CONTENT[..100], 100)).unwrap();
+    let index = BgzfReader::new(long_path.clone()).unwrap().index().unwrap();
+    let error = BgzfReader::open_with_index(short_path.clone(), &index)
+      .err()
+      .unwrap();
+    assert!(error.to_string().contains("past the end of the"));
+    assert!(matches!(
+      error,
+      BgzfError::Index(IndexError::PastEndOfFile { block: 2, .. })
+    ));
+    fs::remove_file(long_path).unwrap();
+    fs::remove_file(short_path).unwrap();
+  }
+}
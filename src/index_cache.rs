@@ -0,0 +1,97 @@
+//! Sidecar cache of the block index next to a bgzf file, read and written by
+//! `BgzfReaderBuilder::cache_index`, and of the checkpoints of a gzip file,
+//! by `GzipRandomReaderBuilder::cache_index` with the `gzip` feature.
+//!
+//! A cache file starts with the length of the data file it was built from
+//! (u64) and its modification time in seconds (u64) and nanoseconds (u32)
+//! since the epoch, all little-endian, followed by the index in the
+//! `BgzfIndex::save` or `GzipIndex::save` format.
+
+use crate::BgzfIndex;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+const STAMP_LENGTH: usize = 20;
+
+//tells apart the temporary files of caches written at the same time
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Path of the index cache of the bgzf file at `file_path`
+pub(crate) fn cache_path(file_path: &str) -> String {
+  format!("{}.bgzidx", file_path)
+}
+
+/// Length and modification time of a data file as stored in the cache, None
+/// when the filesystem has no modification time
+fn stamp(metadata: &Metadata) -> Option<[u8; STAMP_LENGTH]> {
+  let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+  let mut stamp = [0u8; STAMP_LENGTH];
+  stamp[..8].copy_from_slice(&metadata.len().to_le_bytes());
+  stamp[8..16].copy_from_slice(&mtime.as_secs().to_le_bytes());
+  stamp[16..].copy_from_slice(&mtime.subsec_nanos().to_le_bytes());
+  Some(stamp)
+}
+
+/// Returns the index cached at `cache_path` if it was built from a data file
+/// of the length and modification time in `metadata`. A missing, stale or
+/// corrupt cache gives None.
+pub(crate) fn load(cache_path: &str, metadata: &Metadata) -> Option<BgzfIndex> {
+  BgzfIndex::load(&load_stamped(cache_path, metadata)?[..]).ok()
+}
+
+/// Returns what follows the stamp of the cache at `cache_path` if it was
+/// built from a data file of the length and modification time in
+/// `metadata`, for the indexes of other kinds of files
+pub(crate) fn load_stamped(cache_path: &str, metadata: &Metadata) -> Option<Vec<u8>> {
+  let stamp = stamp(metadata)?;
+  let mut cached = fs::read(cache_path).ok()?;
+  if cached.len() < STAMP_LENGTH || cached[..STAMP_LENGTH] != stamp {
+    return None;
+  }
+  cached.drain(..STAMP_LENGTH);
+  Some(cached)
+}
+
+/// Writes `index` of the data file with `metadata` to `cache_path`. The cache
+/// is written to a temporary file renamed over the old one, so that readers
+/// never see a partly written cache.
+pub(crate) fn save(cache_path: &str, metadata: &Metadata, index: &BgzfIndex) -> io::Result<()> {
+  save_with(cache_path, metadata, |file| index.save(file))
+}
+
+/// Same as `save` for an index written by `write`
+pub(crate) fn save_with(
+  cache_path: &str,
+  metadata: &Metadata,
+  write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+  let stamp = stamp(metadata).ok_or_else(|| {
+    io::Error::other("The file system has no modification time to check a cache against")
+  })?;
+  let temp_path = format!(
+    "{}.{}.{}.tmp",
+    cache_path,
+    process::id(),
+    TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+  );
+  let written =
+    write_cache(&temp_path, &stamp, write).and_then(|_| fs::rename(&temp_path, cache_path));
+  if written.is_err() {
+    let _ = fs::remove_file(&temp_path);
+  }
+  written
+}
+
+fn write_cache(
+  path: &str,
+  stamp: &[u8],
+  write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+  let mut file = File::create(path)?;
+  file.write_all(stamp)?;
+  write(&mut file)?;
+  file.sync_all()
+}
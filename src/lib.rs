@@ -1,492 +1,8693 @@
-use libdeflater::Decompressor;
+use backend::{crc32, Decompressor, Inflate, InflateError};
 use positioned_io::ReadAt;
-use std::cell::Cell;
-use std::cell::RefCell;
 use std::cmp::min;
-use std::collections::BTreeMap;
-use std::error;
-use std::fs::File;
-use std::ops::Bound::{Excluded, Included};
-use std::str;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, IoSliceMut};
+use std::mem::MaybeUninit;
+use std::ops::{ControlFlow, Range};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard};
 use std::{error::Error, fmt};
 
+mod anomaly;
+#[cfg(any(feature = "async-tokio", feature = "futures-io"))]
+mod async_core;
+#[cfg(feature = "async-tokio")]
+mod async_reader;
+#[cfg(feature = "async-tokio")]
+mod async_writer;
+mod backend;
+mod background_index;
+mod block_cache;
+mod compressed_run;
+mod compression_pool;
+#[cfg(feature = "digest")]
+mod content_digest;
+#[cfg(feature = "crypt4gh")]
+mod crypt4gh_source;
+mod debug_json;
+mod decompressor_pool;
+mod error;
+mod executor;
+mod fai;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixtures;
+#[cfg(feature = "futures-io")]
+mod futures_reader;
+mod gzi;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "http")]
+mod http;
+mod index;
+mod index_cache;
+mod line_index;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "rayon")]
+mod par_lines;
+mod parallel_decompress;
+mod payload;
+mod pipelined;
+mod prefetch;
+#[cfg(feature = "python")]
+mod python;
+mod recovery;
+#[cfg(feature = "remote")]
+mod remote;
+mod report;
+mod scan_buffer;
+mod seek_read;
+mod shared_state;
+mod source;
+#[cfg(feature = "object-store")]
+mod store;
+mod stream_reader;
+#[cfg(feature = "tabix")]
+mod tabix;
+mod tools;
+mod verify;
+mod writer;
+
+use anomaly::AnomalyScan;
+use background_index::BackgroundIndexer;
+use block_cache::BlockCache;
+use compressed_run::CompressedRun;
+#[cfg(feature = "crypt4gh")]
+use crypt4gh_source::Crypt4ghSource;
+use decompressor_pool::DecompressorPool;
+use executor::Executor;
+#[cfg(feature = "mmap")]
+use mmap::MappedFile;
+use prefetch::Prefetcher;
+#[cfg(feature = "remote")]
+use remote::RemoteSource;
+use scan_buffer::ScanBuffer;
+use shared_state::{lock, SharedCell};
+use source::Source;
+use writer::{EOF_MARKER, MAX_BLOCK_SIZE};
+
+pub use anomaly::{Anomaly, AnomalyKind, OpenWarning, ParseMode};
+#[cfg(feature = "async-tokio")]
+pub use async_reader::AsyncBgzfReader;
+#[cfg(feature = "async-tokio")]
+pub use async_writer::AsyncBgzfWriter;
+pub use block_cache::CacheStats;
+#[cfg(feature = "crypt4gh")]
+pub use crypt4gh_source::crypt4gh_secret_key;
+pub use error::{BgzfError, BlockContext, BlockOperation, ResourceLimit};
+pub use fai::{FaiError, FaiReader};
+#[cfg(feature = "futures-io")]
+pub use futures_reader::FuturesBgzfReader;
+pub use gzi::GziError;
+#[cfg(feature = "gzip")]
+pub use gzip::{
+  GzipCheckpoint, GzipError, GzipIndex, GzipRandomReader, GzipRandomReaderBuilder,
+  DEFAULT_CHECKPOINT_SPACING,
+};
+#[cfg(feature = "http")]
+pub use http::HttpTransport;
+pub use index::{BgzfIndex, IndexError};
+pub use line_index::{LineIndex, LineIndexError, DEFAULT_LINE_SPACING};
+pub use payload::PayloadKind;
+pub use pipelined::PipelinedReader;
+pub use recovery::DataGap;
+#[cfg(feature = "remote")]
+pub use remote::RangeTransport;
+pub use report::{CompressionReport, HistogramBucket, ReportOptions};
+pub use seek_read::SeekReadSource;
+pub use shared_state::Position;
+#[cfg(feature = "object-store")]
+pub use store::ObjectStoreTransport;
+pub use stream_reader::BgzfStreamReader;
+#[cfg(feature = "tabix")]
+pub use tabix::{TabixError, TabixHeader, TabixIndex, TabixLines, TabixReader};
+pub use tools::{
+  cat, defragment, has_eof_marker, repair_eof, slice_blocks, transcode, BlockStats,
+  DefragmentStats, SliceInfo, TranscodeStats,
+};
+pub use verify::VerifyReport;
+pub use writer::{
+  BgzfWriter, BgzfWriterBuilder, BlockFlushInfo, CompressStats, WriterStats,
+  DEFAULT_COMPRESSION_LEVEL,
+};
+
 /// Struct to hold the block information:
 ///
-/// data_offset: pointer of file where real data is located,
-/// data_length: total length of data i.e (block - header - footer,
+/// input_offset: offset of the block's first byte in the uncompressed data,
+/// block_offset: pointer of file where the block header starts,
 /// input_length: uncompressed length of the data,
 /// block_size: length of the block,
 #[derive(Copy, Clone)]
 struct BgzfBlock {
-  data_offset: u64,
-  data_length: u32,
+  input_offset: u64,
+  block_offset: u64,
   input_length: u32,
   block_size: u32,
 }
 
-///Cache struct to cache uncompressed data of a whole block
-#[derive(Clone)]
-struct Cache {
-  pos: u64,
-  uncompressed_data: Vec<u8>,
+impl BgzfBlock {
+  /// Public description of the block
+  fn info(&self) -> BlockInfo {
+    BlockInfo {
+      uncompressed_offset: self.input_offset,
+      compressed_offset: self.block_offset,
+      block_size: self.block_size,
+      uncompressed_length: self.input_length,
+    }
+  }
+}
+
+/// Public description of where a block is, in the file and in the data:
+///
+/// uncompressed_offset: offset of the block's first byte in the uncompressed data,
+/// compressed_offset: offset of the block header in the file,
+/// block_size: length of the whole block in the file (header and footer included),
+/// uncompressed_length: number of uncompressed bytes in the block,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+  pub uncompressed_offset: u64,
+  pub compressed_offset: u64,
+  pub block_size: u32,
+  pub uncompressed_length: u32,
+}
+
+/// Smallest, largest and mean sizes of the data blocks of a file, all 0 when
+/// it has no data block:
+///
+/// blocks: number of data blocks, the EOF marker not included,
+/// min_block_size, max_block_size, mean_block_size: size of the blocks in the
+/// file, gzip header and footer included,
+/// min_uncompressed_length, max_uncompressed_length, mean_uncompressed_length:
+/// number of uncompressed bytes in the blocks,
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BlockSizeSummary {
+  pub blocks: u64,
+  pub min_block_size: u32,
+  pub max_block_size: u32,
+  pub mean_block_size: f64,
+  pub min_uncompressed_length: u32,
+  pub max_uncompressed_length: u32,
+  pub mean_uncompressed_length: f64,
+}
+
+/// How far indexing has gone when opening with
+/// `BgzfReaderBuilder::open_with_progress`:
+///
+/// blocks: number of data blocks indexed so far,
+/// compressed_bytes: size of those blocks in the file, from its start,
+/// uncompressed_bytes: uncompressed length of those blocks,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexProgress {
+  pub blocks: u64,
+  pub compressed_bytes: u64,
+  pub uncompressed_bytes: u64,
+}
+
+/// Error returned when the progress callback of
+/// `BgzfReaderBuilder::open_with_progress` stops the open, holding the
+/// progress reported to the callback that stopped it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IndexingCancelled {
+  pub progress: IndexProgress,
+}
+
+impl Error for IndexingCancelled {}
+
+impl fmt::Display for IndexingCancelled {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "Opening was cancelled after indexing {} blocks",
+      self.progress.blocks
+    )
+  }
+}
+
+/// Error returned when the blocks of a file end with one that is cut off or
+/// damaged, instead of at the end of the file or after an EOF marker, which
+/// usually means the file was truncated:
+///
+/// block_offset: offset in the file of the first block that can not be read,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Truncated {
+  pub block_offset: u64,
+}
+
+impl Error for Truncated {}
+
+impl fmt::Display for Truncated {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "The file is truncated or damaged, the block at offset {} can not be read",
+      self.block_offset
+    )
+  }
+}
+
+/// One subfield of the gzip extra field (FEXTRA) of a block header, BGZF
+/// blocks always have a BC subfield holding the block size and may have
+/// others
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraSubfield {
+  pub id: [u8; 2],
+  pub data: Vec<u8>,
+}
+
+/// Gzip header fields of a block:
+///
+/// mtime: modification time, 0 in files written by bgzip and `BgzfWriter`,
+/// xfl: extra flags,
+/// os: operating system the block was written on, 255 for unknown,
+/// extra_subfields: every subfield of the extra field in file order, BC included,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+  pub mtime: u32,
+  pub xfl: u8,
+  pub os: u8,
+  pub extra_subfields: Vec<ExtraSubfield>,
 }
 
 /// Struct to read bgzf file
 ///
+/// A reader is `Sync`, it can be shared between threads behind an `Arc`:
+/// `read_at` reads from any position without the reader's position, and
+/// the reads from the position (`read`, `read_to`, `read_exact`, ...) are
+/// made one at a time, each reading on from where the last one ended.
+///
 /// Fields description:
 ///
-/// input_length: total length of the uncompressed version,
-/// current_read_position: current position of the compressed file,
+/// blocks: indexed blocks in file order, which is also the order of their
+/// uncompressed offsets, so both can be binary searched,
+/// next_block_offset: where the next block to index starts in the file, None
+/// once the whole file is indexed,
+/// indexed_length: uncompressed length of the indexed blocks,
+/// truncation: where indexing stopped at a block that can not be read,
+/// allow_truncated: whether reading the blocks before it is fine,
+/// has_eof_marker: whether the file ends with the EOF marker,
+/// background: thread indexing the file when opened with
+/// `background_indexing`, until it is done,
+/// decompressors: decompressors shared by the reads, the prefetching thread
+/// and the parallel decompression,
+/// compressed_run: compressed bytes of the last blocks read, the blocks of a
+/// read following each other in the file are read at once,
+/// mapping: memory mapping the blocks are read from, when opened with `mmap`,
+/// cache: decompressed blocks kept for the next reads,
+/// compressed_cache: compressed bytes of the blocks kept for the next reads,
+/// when opened with `compressed_cache_bytes`,
+/// prefetcher: thread decompressing the next blocks of sequential reads,
+/// when opened with `prefetch`,
+/// prefetch_depth: number of blocks it decompresses ahead,
+/// loaded: the whole uncompressed data once `load_all` loaded it, reads
+/// then copy from it,
+/// load_all_limit: longest uncompressed data `load_all` loads,
+/// verify_crc: whether the CRC32 of every block decompressed is checked
+/// against its footer,
+/// limits: most blocks, uncompressed data and bytes allocated at once the
+/// file may take,
+/// limit_hit: the limit indexing stopped at, if any,
+/// parse_mode: how strictly the file is held to the spec,
+/// anomaly_scan: anomalies found in the blocks indexed so far,
+/// open_warnings: what opening found imperfect in the file,
+/// recover: whether indexing skips over damaged blocks,
+/// gaps: the damage skipped so far, in file order,
+/// last_read_end: where the last read ended, a read starting there continues
+/// a sequential scan,
+/// sequential_read: held by the reads from pos, which are made one at a time,
+/// executor: runs the tasks of the parallel decompression,
+/// input_length: total length of the uncompressed version, when opened lazily
+/// only the length of the first block (see `total_uncompressed_length`),
+/// current_read_position: always 0, deprecated for `compressed_position`,
 /// pos: current position of the uncompressed file,
 pub struct BgzfReader {
-  bgzf_file: File,
-  block_tree: BTreeMap<u64, BgzfBlock>,
-  cache: RefCell<Option<Cache>>,
+  bgzf_file: Source,
+  blocks: RwLock<Vec<BgzfBlock>>,
+  next_block_offset: SharedCell<Option<u64>>,
+  indexed_length: Position,
+  truncation: SharedCell<Option<Truncated>>,
+  allow_truncated: bool,
+  has_eof_marker: bool,
+  background: Option<BackgroundIndexer>,
+  decompressors: Arc<DecompressorPool>,
+  compressed_run: Mutex<CompressedRun>,
+  #[cfg(feature = "mmap")]
+  mapping: Option<MappedFile>,
+  cache: Mutex<BlockCache>,
+  compressed_cache: Option<Mutex<BlockCache>>,
+  prefetcher: Option<Prefetcher>,
+  prefetch_depth: usize,
+  loaded: OnceLock<Vec<u8>>,
+  load_all_limit: u64,
+  verify_crc: bool,
+  limits: Limits,
+  limit_hit: SharedCell<Option<ResourceLimit>>,
+  parse_mode: ParseMode,
+  anomaly_scan: Mutex<AnomalyScan>,
+  open_warnings: Vec<OpenWarning>,
+  recover: bool,
+  gaps: Mutex<Vec<DataGap>>,
+  last_read_end: Position,
+  sequential_read: Mutex<()>,
+  executor: Executor,
   pub input_length: u64,
-  pub current_read_position: Cell<u64>,
-  pub pos: Cell<u64>,
+  #[deprecated(since = "0.3.0", note = "never updated, use `compressed_position`")]
+  pub current_read_position: Position,
+  pub pos: Position,
 }
 
-/// Below are the steps to use the bgzf Reader,
-/// 1st step is to create a BGZF instance with a new function
-/// after that read, and seek method can be used respectively.
+/// Builder to configure a bgzf Reader before opening a file
 ///
 /// # Example
 /// ```
 /// use bgzf_rust_reader::BgzfReader;
-/// use std::str;
-///
-///  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-///  let mut vec = vec![0; 52];
-///  let data_read = reader.read_to(&mut vec);
-///  assert_eq!(data_read.unwrap(), 52);
-///  assert_eq!(
-///    "This is just a bgzf test,lets see how it reacts. :).",
-///    str::from_utf8(&vec).unwrap()
-///  );
 ///
+/// let reader = BgzfReader::builder()
+///   .lazy(true)
+///   .open(String::from("bgzf_test.bgz"))
+///   .unwrap();
+/// let mut first_bytes = vec![0; 4];
+/// reader.read_to(&mut first_bytes).unwrap();
+/// assert_eq!(b"This", &first_bytes[..]);
 /// ```
-impl BgzfReader {
-  pub fn new(file_path: String) -> Result<BgzfReader, Box<dyn error::Error>> {
-    let mut b_tree = BTreeMap::new();
-    let bgzf_file = File::open(file_path)?;
-    let mut input_offset: u64 = 0;
-    let mut current_file_position = 0;
-    loop {
-      match read_block(&bgzf_file, current_file_position) {
-        Ok(option_block) => match option_block {
-          Some(block) => {
-            let input_length_block = block.input_length;
-            let block_size_block = block.block_size;
-            b_tree.insert(input_offset, block);
-            input_offset += u64::from(input_length_block);
-            current_file_position += u64::from(block_size_block);
-          }
-          None => break,
-        },
-        Err(_e) => break,
-      }
+#[derive(Clone, Debug)]
+pub struct BgzfReaderBuilder {
+  lazy: bool,
+  threads: usize,
+  cache_index: bool,
+  allow_truncated: bool,
+  require_eof_marker: bool,
+  background_indexing: bool,
+  cache_bytes: usize,
+  compressed_cache_bytes: usize,
+  prefetch: usize,
+  decompressors: usize,
+  load_all_limit: u64,
+  verify_crc: bool,
+  limits: Limits,
+  parse_mode: ParseMode,
+  recover: bool,
+  executor: Executor,
+  #[cfg(feature = "mmap")]
+  mmap: bool,
+  #[cfg(feature = "async-tokio")]
+  spawn_blocking: bool,
+  #[cfg(feature = "remote")]
+  fetch_size: u64,
+  #[cfg(feature = "remote")]
+  remote_cache_bytes: u64,
+}
+
+impl Default for BgzfReaderBuilder {
+  fn default() -> BgzfReaderBuilder {
+    BgzfReaderBuilder {
+      lazy: false,
+      threads: 1,
+      cache_index: false,
+      allow_truncated: false,
+      require_eof_marker: false,
+      background_indexing: false,
+      cache_bytes: 0,
+      compressed_cache_bytes: 0,
+      prefetch: 0,
+      decompressors: DecompressorPool::default_max(),
+      load_all_limit: DEFAULT_LOAD_ALL_LIMIT,
+      verify_crc: false,
+      limits: Limits::default(),
+      parse_mode: ParseMode::Default,
+      recover: false,
+      executor: Executor::default(),
+      #[cfg(feature = "mmap")]
+      mmap: false,
+      #[cfg(feature = "async-tokio")]
+      spawn_blocking: false,
+      #[cfg(feature = "remote")]
+      fetch_size: remote::DEFAULT_FETCH_SIZE,
+      #[cfg(feature = "remote")]
+      remote_cache_bytes: remote::DEFAULT_CACHE_BYTES,
     }
-    let reader = BgzfReader {
-      bgzf_file,
-      block_tree: b_tree,
-      input_length: input_offset,
-      current_read_position: Cell::new(0),
-      pos: Cell::new(0),
-      cache: RefCell::new(None),
-    };
-    Ok(reader)
+  }
+}
+
+impl BgzfReaderBuilder {
+  /// With `true` opening the file only reads the header of the first block,
+  /// the other blocks are indexed when a read needs them. This saves reading
+  /// every block header of a large file for a sequential read or a peek at
+  /// its start. `total_uncompressed_length`, `block_offsets` and the tools
+  /// working on whole files index the rest of the file first.
+  pub fn lazy(mut self, lazy: bool) -> BgzfReaderBuilder {
+    self.lazy = lazy;
+    self
   }
 
-  /// This method can set the file position relative to uncompressed data
+  /// With `true` opening only reads the header of the first block, like
+  /// `lazy`, and a thread indexes the rest of the file while the reader is
+  /// used. A read or seek needing blocks the thread has not got to waits for
+  /// it, as do `total_uncompressed_length` and the other methods needing the
+  /// whole index. Dropping the reader stops the thread. This overrides `lazy`
+  /// and `threads`.
   ///
   /// # Example
   /// ```
   /// use bgzf_rust_reader::BgzfReader;
   ///
-  ///let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-  ///reader.seek(33);
-  /// assert_eq!(0, reader.current_read_position.get());
-  ///assert_eq!(33, reader.pos.get());
-  ///
+  /// let reader = BgzfReader::builder()
+  ///   .background_indexing(true)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// assert_eq!(280, reader.total_uncompressed_length());
   /// ```
-  pub fn seek(&self, pos: u64) {
-    self.pos.set(pos);
+  pub fn background_indexing(mut self, background_indexing: bool) -> BgzfReaderBuilder {
+    self.background_indexing = background_indexing;
+    self
   }
 
-  /// This method calculates total uncompressed length
-  pub fn total_uncompressed_length(&self) -> u64 {
-    self.input_length
+  /// Sets the number of threads reading the block headers when opening, 1 by
+  /// default. The block starts are first chained from the block sizes reading
+  /// the file in large chunks, then the headers are read and checked in
+  /// parallel, which helps on network filesystems where every read waits.
+  /// Files under 1 MiB and lazy readers are always indexed by one thread.
+  pub fn threads(mut self, threads: usize) -> BgzfReaderBuilder {
+    self.threads = threads;
+    self
   }
 
-  /// this method reads data to the slice passed
+  /// Runs the parallel work of the reader on the threads of `pool` rather
+  /// than on threads of its own: reading the block headers when opening,
+  /// `decompress_all_parallel`, `decompress_to_writer_parallel`,
+  /// `for_each_block_parallel`, `verify`, `digest` and the reads decompressing
+  /// whole blocks in parallel. The `threads` those are given become tasks,
+  /// of which the pool runs as many at once as it has threads, so the reader
+  /// never uses more threads than the pool. `par_lines` runs in the pool its
+  /// iterator is driven from, see `rayon::ThreadPool::install`. The
+  /// prefetching and background indexing threads stay threads of their own,
+  /// as they wait on the reader for as long as it is open.
+  ///
+  /// By default every call starts its own scoped threads, as many as it is
+  /// given, and reads decompress blocks in the global rayon pool.
   ///
   /// # Example
   /// ```
   /// use bgzf_rust_reader::BgzfReader;
-  /// use std::str;
-  ///
-  ///  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-  ///  let mut vec = vec![0; 52];
-  ///  let data_read = reader.read_to(&mut vec);
-  ///  assert_eq!(data_read.unwrap(), 52);
-  ///  assert_eq!(
-  ///    "This is just a bgzf test,lets see how it reacts. :).",
-  ///    str::from_utf8(&vec).unwrap()
-  ///  );
+  /// use std::sync::Arc;
   ///
+  /// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+  /// let reader = BgzfReader::builder()
+  ///   .thread_pool(Arc::new(pool))
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let data = reader.decompress_all_parallel(8).unwrap();
+  /// assert_eq!(280, data.len());
   /// ```
-  pub fn read_to(&self, b: &mut Vec<u8>) -> Result<i32, Box<dyn error::Error>> {
-    self.read(b, 0, b.len())
+  #[cfg(feature = "rayon")]
+  pub fn thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> BgzfReaderBuilder {
+    self.executor = Executor::Pool(pool);
+    self
   }
 
-  /// this method reads data to the slice from offset position,
-  /// up to the len position
+  /// With `true` the block index is kept in a `<file>.bgzidx` file next to
+  /// the bgzf file. Opening uses it when it was saved for a file of the same
+  /// length and modification time and the first and last blocks it describes
+  /// are in the file, otherwise the file is indexed as usual and the cache
+  /// written again. A cache that can not be written does not fail the open.
+  /// Lazy and background indexing are off when the cache is on, as the cache
+  /// needs every block.
+  pub fn cache_index(mut self, cache_index: bool) -> BgzfReaderBuilder {
+    self.cache_index = cache_index;
+    self
+  }
+
+  /// With `true` a file whose blocks end with one that is cut off or damaged
+  /// opens with the blocks before it, and `is_truncated` tells so. By default
+  /// opening such a file fails with a `BgzfError::Truncated` error, as the
+  /// data read from it would be silently shorter than what was written. A
+  /// lazy reader only gets to the bad block when a read needs it, so the
+  /// error then comes from that read.
+  pub fn allow_truncated(mut self, allow_truncated: bool) -> BgzfReaderBuilder {
+    self.allow_truncated = allow_truncated;
+    self
+  }
+
+  /// With `true` opening fails unless the file ends with the EOF marker,
+  /// which tells a complete file from one whose writer did not finish, like
+  /// htslib does. Off by default, as files without it are otherwise readable.
+  pub fn require_eof_marker(mut self, require_eof_marker: bool) -> BgzfReaderBuilder {
+    self.require_eof_marker = require_eof_marker;
+    self
+  }
+
+  /// Sets how many bytes of decompressed blocks the reader keeps, so that
+  /// reading a block again does not decompress it again. The least recently
+  /// used blocks are dropped first, but the last block used is always kept,
+  /// even when larger than the budget. 0 by default, which keeps that block
+  /// only. `BgzfReader::cache_stats` tells how much the cache holds.
   ///
   /// # Example
   /// ```
   /// use bgzf_rust_reader::BgzfReader;
-  /// use std::str;
-  ///
-  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-  /// let mut content = vec![0; 10];
-  /// match reader.read(&mut content, 0, 10) {
-  ///  Ok(val) => {
-  ///   assert_eq!(10, val);
-  ///  }
-  ///  Err(e) => {
-  ///    assert!(false);
-  ///  }
-  /// };
-  ///let file_content = str::from_utf8(&content).unwrap();
-  ///  assert_eq!("This is ju", file_content);
   ///
+  /// let reader = BgzfReader::builder()
+  ///   .cache_bytes(8 * 1024 * 1024)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(280, reader.cache_stats().bytes);
   /// ```
-  pub fn read(
-    &self,
-    b: &mut Vec<u8>,
-    off: usize,
-    len: usize,
-  ) -> Result<i32, Box<dyn error::Error>> {
-    if b.len() == 0 {
-      return Err(BGZFError::new("Buffer size needs to be greater than 0").into());
-    }
-    if len > b.len() - off {
-      return Err(BGZFError::new("Index out of bound exception").into());
-    }
-    if len == 0 {
-      return Ok(0);
-    }
-    if self.pos.get() >= self.input_length {
-      return Ok(-1);
-    }
-
-    let mut off = off;
-    let mut len = len;
-    let mut cb: i32 = 0;
-
-    match self.cache.borrow().as_ref() {
-      Some(cache) => {
-        if self.pos.get() >= cache.pos {
-          let bytes_available_in_cache =
-            cache.pos as usize + cache.uncompressed_data.len() - self.pos.get() as usize;
-          if bytes_available_in_cache > 0 {
-            let copy_start = (self.pos.get() - cache.pos) as usize;
-            let copy_length = min(bytes_available_in_cache, len);
-            let end_index = copy_start + copy_length;
-            b[off..]
-              .copy_from_slice(&cache.uncompressed_data[copy_start as usize..end_index as usize]);
-            cb += copy_length as i32;
-            off += copy_length;
-            len -= copy_length;
-            self.pos.set(self.pos.get() + copy_length as u64);
-            if len == 0 {
-              return Ok(cb);
-            }
-          }
-        }
-      }
-      None => {
-        //If there is no cache available lets move forward
-      }
-    }
-
-    let mut un_compressor = Decompressor::new();
-
-    #[derive(Copy, Clone)]
-    struct Entry {
-      key: u64,
-      value: BgzfBlock,
-    }
-
-    let mut entry_vector: Vec<Entry> = Vec::new();
-
-    if !self.block_tree.contains_key(&self.pos.get()) {
-      let floored_value = self.block_tree.range(..self.pos.get()).next_back().unwrap();
-      //Getting a floored value if we do not find pos in the tree.
-      entry_vector.push(Entry {
-        key: *floored_value.0,
-        value: *floored_value.1,
-      });
-    }
-    //Get all the blocks from the block tree that is within the range of
-    //pos and length of the buffer passed
-    let pos_and_len_combined = self.pos.get() + len as u64;
-    for (&key, &value) in self
-      .block_tree
-      .range((Included(self.pos.get()), Excluded(pos_and_len_combined)))
-    {
-      entry_vector.push(Entry { key, value });
-    }
-
-    for entry in entry_vector {
-      let block = entry.value;
-      let input_offset = entry.key;
-
-      //Reading compressed data from the block
-      let mut compressed = vec![0u8; block.data_length as usize];
-      self
-        .bgzf_file
-        .read_exact_at(block.data_offset, &mut compressed)?;
-
-      //now it's time to de-compress the read value obtained.
-      let mut uncompressed = vec![0u8; block.input_length as usize];
-      let bytes_decompressed =
-        un_compressor.deflate_decompress(&mut compressed, &mut uncompressed)?;
-
-      if bytes_decompressed == 0 || bytes_decompressed != block.input_length as usize {
-        return Err(BGZFError::new("Did not fully de-compress").into());
-      }
-
-      self.cache.replace(Some(Cache {
-        pos: input_offset,
-        uncompressed_data: uncompressed.clone(),
-      }));
-
-      let mut copy_start: u64 = 0;
-      //total uncompressed size is input_length
-      let mut copy_length = block.input_length;
-      if input_offset < self.pos.get() {
-        let copy_skip = self.pos.get() - input_offset;
-        copy_start += copy_skip;
-        copy_length -= copy_skip as u32;
-      }
-
-      if copy_length > len as u32 {
-        copy_length = len as u32;
-      }
-      let end_index = copy_start + u64::from(copy_length);
-      b[off..].copy_from_slice(&uncompressed[copy_start as usize..end_index as usize]);
-      len -= copy_length as usize;
-      self.pos.set(self.pos.get() + u64::from(copy_length));
-      off += copy_length as usize;
-      cb += copy_length as i32;
-    }
-    Ok(cb)
+  pub fn cache_bytes(mut self, cache_bytes: usize) -> BgzfReaderBuilder {
+    self.cache_bytes = cache_bytes;
+    self
   }
-}
-
-fn read_block(
-  file: &File,
-  current_file_position: u64,
-) -> Result<Option<BgzfBlock>, Box<dyn error::Error>> {
-  let mut current_file_position = current_file_position;
-
-  let mut buf = [0; 12];
-  file.read_exact_at(current_file_position, &mut buf)?;
-  current_file_position += buf.len() as u64;
 
-  if buf[0] != 31 || buf[1] != 139 || buf[2] != 8 || buf[3] != 4 {
-    return Err(BGZFError::new("Incorrect header").into());
+  /// With more than 0 the reader also keeps the compressed bytes of the
+  /// blocks it reads, up to `compressed_cache_bytes`, so that a block whose
+  /// decompressed data left the cache is decompressed again without reading
+  /// the file. Compressed blocks are several times smaller than decompressed
+  /// ones, which helps when reading the file is slow, like over a network
+  /// filesystem. The least recently used blocks are dropped first. Reads
+  /// using it do not decompress blocks in parallel, and prefetched blocks do
+  /// not go through it. 0 by default, which keeps none, as local files gain
+  /// nothing from it. `BgzfReader::compressed_cache_stats` tells how much it
+  /// holds.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .compressed_cache_bytes(1024 * 1024)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(211, reader.compressed_cache_stats().bytes);
+  /// ```
+  pub fn compressed_cache_bytes(mut self, compressed_cache_bytes: usize) -> BgzfReaderBuilder {
+    self.compressed_cache_bytes = compressed_cache_bytes;
+    self
   }
 
-  let xlen: u16 = (buf[10] as u16) | ((buf[11] as u16) << 8);
-
-  let mut buf_xlen = vec![0u8; usize::from(xlen)];
+  /// With more than 0 a thread reads and decompresses the `prefetch` blocks
+  /// following the last one read while the caller works on its data, as long
+  /// as every read starts where the previous one ended. A read going
+  /// elsewhere drops the blocks prefetched, and prefetching starts again with
+  /// the next read following it. A block the thread could not decompress
+  /// fails the read needing it, with the error that read would have got.
+  /// Only indexed blocks are prefetched, so with `lazy` the blocks past the
+  /// last read are not. 0 by default.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .prefetch(4)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// ```
+  pub fn prefetch(mut self, prefetch: usize) -> BgzfReaderBuilder {
+    self.prefetch = prefetch;
+    self
+  }
 
-  file.read_exact_at(current_file_position, &mut buf_xlen)?;
-  current_file_position += buf_xlen.len() as u64;
+  /// Sets the most decompressors the reader makes, shared by its reads, its
+  /// prefetching thread and `decompress_all_parallel` and
+  /// `decompress_to_writer_parallel`. They are made as needed, and once
+  /// `decompressors` are in use the next block waits for one of them, so
+  /// this also bounds the blocks decompressed at once. One per CPU by
+  /// default, and at least 1.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .decompressors(2)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// let data = reader.decompress_all_parallel(8).unwrap();
+  /// assert_eq!(280, data.len());
+  /// ```
+  pub fn decompressors(mut self, decompressors: usize) -> BgzfReaderBuilder {
+    self.decompressors = decompressors;
+    self
+  }
 
-  if buf_xlen[0] != 66 || buf_xlen[1] != 67 {
-    return Err(BGZFError::new("Bad subfield Identifier").into());
+  /// Sets the longest uncompressed data `BgzfReader::load_all` loads, so
+  /// that loading a file much larger than expected fails rather than
+  /// allocating all of it. 1GiB by default.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .load_all_limit(100)
+  ///   .open(String::from("bgzf_test.bgz"))
+  ///   .unwrap();
+  /// assert!(reader.load_all().is_err());
+  /// ```
+  pub fn load_all_limit(mut self, load_all_limit: u64) -> BgzfReaderBuilder {
+    self.load_all_limit = load_all_limit;
+    self
   }
 
-  if ((buf_xlen[2] as u16) | ((buf_xlen[3] as u16) << 8)) != 2 {
-    return Err(BGZFError::new("Bad subfield Length").into());
+  /// With `true` the CRC32 of every block decompressed, by reads, the
+  /// prefetching thread, `load_all` and the parallel decompression, is
+  /// checked against the one in the block's footer, and a block whose data
+  /// does not match fails with `BgzfError::ChecksumMismatch`. Off by default,
+  /// as computing the CRC32 costs a little on top of decompressing.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_with_bad_crc, write_temp};
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader};
+  ///
+  /// let path = write_temp("verify_crc_doc", &make_with_bad_crc(b"bgzf data", 4, 1)).unwrap();
+  /// let reader = BgzfReader::builder()
+  ///   .verify_crc(true)
+  ///   .open(path.clone())
+  ///   .unwrap();
+  /// let mut data = vec![0; 9];
+  /// let error = reader.read_to(&mut data).err().unwrap();
+  /// assert!(matches!(error, BgzfError::ChecksumMismatch { .. }));
+  /// let block = error.block_context().unwrap();
+  /// assert_eq!((1, 4), (block.index, block.uncompressed_offset));
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn verify_crc(mut self, verify_crc: bool) -> BgzfReaderBuilder {
+    self.verify_crc = verify_crc;
+    self
   }
 
-  let bsize = (buf_xlen[4] as u16) | ((buf_xlen[5] as u16) << 8);
-  let block_size = u32::from(bsize) + 1;
-  let data_length = bsize - xlen - 19;
-  let data_offset = current_file_position;
+  /// Sets the most blocks with data the file may have, for files from
+  /// untrusted sources whose block index would otherwise grow with every
+  /// block. Checked while indexing, so opening fails with
+  /// `BgzfError::LimitExceeded` once the blocks go past it, or with `lazy`
+  /// and `background_indexing` the read needing the blocks past it does.
+  /// `allow_truncated` does not allow it. Unlimited by default.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader, ResourceLimit};
+  ///
+  /// let path = write_temp("max_blocks_doc", &make_bgzf(b"bgzf data", 1)).unwrap();
+  /// let error = BgzfReader::builder()
+  ///   .max_blocks(8)
+  ///   .open(path.clone())
+  ///   .err()
+  ///   .unwrap();
+  /// assert!(matches!(
+  ///   error,
+  ///   BgzfError::LimitExceeded { limit: ResourceLimit::Blocks, max: 8 }
+  /// ));
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn max_blocks(mut self, max_blocks: u64) -> BgzfReaderBuilder {
+    self.limits.max_blocks = max_blocks;
+    self
+  }
 
-  //Skip data block
-  current_file_position += u64::from(data_length) + 4;
+  /// Sets the longest uncompressed data the file may hold, checked against
+  /// the lengths the block footers claim while indexing, like `max_blocks`.
+  /// Unlimited by default.
+  pub fn max_uncompressed_length(mut self, max_uncompressed_length: u64) -> BgzfReaderBuilder {
+    self.limits.max_uncompressed_length = max_uncompressed_length;
+    self
+  }
 
-  let mut buf_isize = [0; 4];
-  file.read_exact_at(current_file_position, &mut buf_isize)?;
+  /// Sets the most bytes allocated at once for the file's data, checked
+  /// when reading rather than opening: `decompress_all_parallel` and
+  /// `load_all`, which hold all of it, fail with `BgzfError::LimitExceeded`
+  /// past it. Reads only allocate a block at a time, 64KiB at most.
+  /// Unlimited by default.
+  pub fn max_allocation(mut self, max_allocation: u64) -> BgzfReaderBuilder {
+    self.limits.max_allocation = max_allocation;
+    self
+  }
 
-  let i_size: u32 = (buf_isize[0] as u32)
-    | ((buf_isize[1] as u32) << 8)
-    | ((buf_isize[2] as u32) << 16)
-    | ((buf_isize[3] as u32) << 24);
+  /// Sets how strictly the file is held to the BGZF spec: with
+  /// `ParseMode::Strict` its anomalies, such as a missing EOF marker or data
+  /// after it, fail with a `BgzfError::Anomaly` error, when opening or with
+  /// `lazy` and `background_indexing` when a read gets to them. With
+  /// `ParseMode::Default` they are read through and
+  /// `BgzfReader::anomalies` returns them, and with `ParseMode::Permissive`
+  /// they are ignored and truncated files open as with `allow_truncated`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_without_eof, write_temp};
+  /// use bgzf_rust_reader::{AnomalyKind, BgzfError, BgzfReader, ParseMode};
+  ///
+  /// let path = write_temp("parse_mode_doc", &make_without_eof(b"bgzf data", 4)).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// assert_eq!(AnomalyKind::MissingEofMarker, reader.anomalies()[0].kind);
+  ///
+  /// let error = BgzfReader::builder()
+  ///   .parse_mode(ParseMode::Strict)
+  ///   .open(path.clone())
+  ///   .err()
+  ///   .unwrap();
+  /// assert!(matches!(error, BgzfError::Anomaly(_)));
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn parse_mode(mut self, parse_mode: ParseMode) -> BgzfReaderBuilder {
+    self.parse_mode = parse_mode;
+    self
+  }
 
-  if i_size == 0 {
-    return Ok(None);
+  /// With `true` damage in the middle of the file is skipped over rather
+  /// than ending its blocks: when a block header can not be read, indexing
+  /// goes on from the next block that can, found by searching the file for
+  /// a gzip header whose BC subfield chains to another block. The bytes
+  /// skipped are recorded as a `DataGap` (see `BgzfReader::gaps`), whose
+  /// data is lost and whose uncompressed range is empty, as its length is
+  /// unknown. A block that fails to decompress when read is recorded too,
+  /// with its uncompressed range. Reads going across a gap fail with a
+  /// `BgzfError::DataGap` error, the others read as ever, so a read can go
+  /// on after seeking to the end of the gap. Damage nothing follows is a
+  /// truncation, which is allowed as with `allow_truncated`. The file is
+  /// indexed by the calling thread whatever the `threads` and
+  /// `background_indexing` options are, and without the index cache. The
+  /// methods decompressing the whole file read the blocks there are.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_with_garbage, write_temp};
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader};
+  ///
+  /// let data = vec![b'x'; 3000];
+  /// let path = write_temp("recover_doc", &make_with_garbage(&data, 1000, 1, 10)).unwrap();
+  /// assert!(BgzfReader::new(path.clone()).is_err());
+  ///
+  /// let reader = BgzfReader::builder().recover(true).open(path.clone()).unwrap();
+  /// assert_eq!(2000, reader.total_uncompressed_length());
+  /// assert_eq!(1000..1000, reader.gaps()[0].uoffset_range);
+  /// let mut content = vec![0; 2000];
+  /// assert!(matches!(reader.read_to(&mut content), Err(BgzfError::DataGap(_))));
+  /// assert_eq!(1000, reader.read_to(&mut content[..1000]).unwrap());
+  /// assert_eq!(1000, reader.read_to(&mut content[..1000]).unwrap());
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn recover(mut self, recover: bool) -> BgzfReaderBuilder {
+    self.recover = recover;
+    self
   }
 
-  let block = BgzfBlock {
-    data_offset,
-    data_length: u32::from(data_length),
-    input_length: u32::from(i_size),
-    block_size,
-  };
-  Ok(Some(block))
-}
+  /// With `true` the blocks are read from a memory mapping of the file
+  /// instead of with a read per block, see `BgzfReader::open_mmap`. Only with
+  /// the `mmap` feature.
+  #[cfg(feature = "mmap")]
+  pub fn mmap(mut self, mmap: bool) -> BgzfReaderBuilder {
+    self.mmap = mmap;
+    self
+  }
 
-#[derive(Debug)]
-struct BGZFError {
-  msg: String,
-}
+  /// Sets the least number of bytes a remote file opened with `open_remote`
+  /// or `open_url` fetches at once: it is read in aligned chunks of
+  /// `fetch_size` bytes, so that the reads of neighbouring blocks, like
+  /// those of indexing, make a single request. 256 KiB by default, larger
+  /// chunks making fewer requests of more latency bound storage. Only with
+  /// the `remote` feature.
+  #[cfg(feature = "remote")]
+  pub fn fetch_size(mut self, fetch_size: u64) -> BgzfReaderBuilder {
+    self.fetch_size = fetch_size;
+    self
+  }
 
-impl BGZFError {
-  fn new(msg: &str) -> BGZFError {
-    BGZFError {
-      msg: msg.to_string(),
-    }
+  /// Sets how many bytes of the chunks fetched from a remote file the reader
+  /// keeps, so that reading a block again does not fetch it again. The least
+  /// recently used chunks are dropped first, the last one used is always
+  /// kept. 16 MiB by default. `BgzfReader::fetch_stats` tells how much it
+  /// holds and how many requests were made. Only with the `remote` feature.
+  #[cfg(feature = "remote")]
+  pub fn remote_cache_bytes(mut self, remote_cache_bytes: u64) -> BgzfReaderBuilder {
+    self.remote_cache_bytes = remote_cache_bytes;
+    self
   }
-}
 
-impl Error for BGZFError {
-  fn description(&self) -> &str {
-    &self.msg
+  /// Makes the async reader decompress blocks on tokio's blocking thread
+  /// pool with `spawn_blocking`, along with reading them, instead of inline
+  /// in `poll_read`, which keeps the runtime's worker threads free. Only used
+  /// by `open_async`.
+  #[cfg(feature = "async-tokio")]
+  pub fn spawn_blocking(mut self, spawn_blocking: bool) -> BgzfReaderBuilder {
+    self.spawn_blocking = spawn_blocking;
+    self
   }
-}
 
-impl fmt::Display for BGZFError {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{}", self.msg)
+  /// Opens the bgzf file at `file_path` like `open`, on tokio's blocking
+  /// thread pool so that indexing a large file does not block the runtime,
+  /// and returns an async reader of it
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use tokio::io::AsyncReadExt;
+  ///
+  /// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  /// let data = runtime.block_on(async {
+  ///   let mut reader = BgzfReader::builder()
+  ///     .cache_bytes(1 << 20)
+  ///     .spawn_blocking(true)
+  ///     .open_async(String::from("bgzf_test.bgz"))
+  ///     .await
+  ///     .unwrap();
+  ///   let mut data = Vec::new();
+  ///   reader.read_to_end(&mut data).await.unwrap();
+  ///   data
+  /// });
+  /// assert_eq!(280, data.len());
+  /// ```
+  #[cfg(feature = "async-tokio")]
+  pub async fn open_async(self, file_path: String) -> Result<AsyncBgzfReader, BgzfError> {
+    let spawn_blocking = self.spawn_blocking;
+    let reader = tokio::task::spawn_blocking(move || self.open(file_path))
+      .await
+      .map_err(io::Error::other)??;
+    Ok(AsyncBgzfReader::from_parts(reader, spawn_blocking))
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  /// Opens the bgzf data of `source`, any `futures::io::AsyncRead` and
+  /// `AsyncSeek` like the files of smol or async-std, indexing it by reading
+  /// it through once, and returns an async reader of it that needs no
+  /// runtime of its own. Only `cache_bytes`, `verify_crc` and
+  /// `require_eof_marker` are used, the other options are those of files.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use futures::io::{AsyncReadExt, Cursor};
+  ///
+  /// let compressed = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let data = futures::executor::block_on(async {
+  ///   let mut reader = BgzfReader::builder()
+  ///     .cache_bytes(1 << 20)
+  ///     .verify_crc(true)
+  ///     .open_async_source(Cursor::new(compressed))
+  ///     .await
+  ///     .unwrap();
+  ///   let mut data = Vec::new();
+  ///   reader.read_to_end(&mut data).await.unwrap();
+  ///   data
+  /// });
+  /// assert_eq!(280, data.len());
+  /// ```
+  #[cfg(feature = "futures-io")]
+  pub async fn open_async_source<S>(self, mut source: S) -> Result<FuturesBgzfReader<S>, BgzfError>
+  where
+    S: futures_io::AsyncRead + futures_io::AsyncSeek + Unpin,
+  {
+    let blocks = futures_reader::index_source(&mut source, self.require_eof_marker).await?;
+    Ok(FuturesBgzfReader::from_parts(source, blocks, &self))
+  }
 
-  #[test]
-  fn test_read_block_func() {
-    let bgzf_file = File::open("bgzf_test.bgz").unwrap();
-    match read_block(&bgzf_file, 0) {
-      Ok(option_block) => match option_block {
-        Some(block) => {
-          assert_eq!(block.block_size, 211);
-          assert_eq!(block.data_length, 185);
-          assert_eq!(block.data_offset, 18);
-          assert_eq!(block.input_length, 280);
-        }
-        None => assert!(false),
-      },
-      Err(_e) => assert!(false),
-    }
+  /// Returns an async reader of the bgzf data of `source` like
+  /// `open_async_source`, whose blocks are those of `index` rather than
+  /// read from the source, see `FuturesBgzfReader::with_index`
+  #[cfg(feature = "futures-io")]
+  pub fn open_async_source_with_index<S>(self, source: S, index: &BgzfIndex) -> FuturesBgzfReader<S>
+  where
+    S: futures_io::AsyncRead + futures_io::AsyncSeek + Unpin,
+  {
+    FuturesBgzfReader::from_parts(source, blocks_of_index(index), &self)
   }
 
-  #[test]
-  fn test_bgzf_reader_new_func() {
-    let bgzf_reader = BgzfReader::new(String::from("bgzf_test.bgz"));
-    match bgzf_reader {
-      Ok(reader) => {
-        let expected_uncompressed_length = 280;
-        assert_eq!(1, reader.block_tree.len());
-        assert_eq!(expected_uncompressed_length, reader.input_length);
-        assert_eq!(0, reader.current_read_position.get());
-
-        let block = reader.block_tree.get(&0);
-        match block {
-          Some(block) => {
-            assert_eq!(block.block_size, 211);
-            assert_eq!(block.data_length, 185);
-            assert_eq!(block.data_offset, 18);
-            assert_eq!(block.input_length, 280);
-          }
-          None => assert!(false),
-        }
+  /// Opens the bgzf file at `file_path`
+  pub fn open(self, file_path: String) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = File::open(&file_path)?;
+    let metadata = bgzf_file.metadata()?;
+    let bgzf_file = Source::File(bgzf_file);
+    self.check_eof_marker(&bgzf_file)?;
+    if !self.cache_index || self.recover {
+      return self.open_file(bgzf_file);
+    }
+    let cache_path = index_cache::cache_path(&file_path);
+    if let Some(index) = index_cache::load(&cache_path, &metadata) {
+      if index_fits_file(&bgzf_file, &index) {
+        return self.open_indexed(bgzf_file, &index);
       }
-      Err(_e) => assert!(false),
     }
+    let reader = self
+      .lazy(false)
+      .background_indexing(false)
+      .open_file(bgzf_file)?;
+    //an index read from the cache would not know about the truncation, nor
+    //the anomalies of the blocks
+    if reader.truncation.get().is_none() && lock(&reader.anomaly_scan).found.is_empty() {
+      if let Ok(index) = reader.index() {
+        let _ = index_cache::save(&cache_path, &metadata, &index);
+      }
+    }
+    Ok(reader)
   }
 
-  #[test]
+  /// Opens the bgzf stream embedded in the file at `file_path` from byte
+  /// `offset`, like in a tar archive or a container format, see
+  /// `open_file_at`
+  pub fn open_at(
+    self,
+    file_path: String,
+    offset: u64,
+    length: Option<u64>,
+  ) -> Result<BgzfReader, BgzfError> {
+    self.open_file_at(File::open(file_path)?, offset, length)
+  }
+
+  /// Opens the bgzf stream of `length` bytes embedded in `bgzf_file` from
+  /// byte `offset`, up to the end of the file when None, and reads it as if
+  /// it were a file of its own. The bytes before `offset` and past `length`,
+  /// like the padding of a tar archive, are never read, so bounding the
+  /// stream keeps what follows it from being taken for blocks.
+  ///
+  /// Compressed offsets, and so the ones of virtual offsets, block offsets,
+  /// indexes and errors, are relative to the start of the stream: .gzi and
+  /// .tbi indexes of the stream written on its own apply to it as they are.
+  /// `cache_index` and `mmap` work with whole files and are ignored.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::fs::File;
+  ///
+  /// let mut container = vec![0; 512];
+  /// let bgzf = std::fs::read("bgzf_test.bgz").unwrap();
+  /// container.extend_from_slice(&bgzf);
+  /// container.extend_from_slice(&[0; 1024]);
+  /// let path = std::env::temp_dir().join("bgzf_open_at_doc_example.tar");
+  /// std::fs::write(&path, &container).unwrap();
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .open_file_at(File::open(&path).unwrap(), 512, Some(bgzf.len() as u64))
+  ///   .unwrap();
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// assert_eq!(0, reader.block_offsets().next().unwrap().compressed_offset);
+  /// std::fs::remove_file(&path).unwrap();
+  /// ```
+  pub fn open_file_at(
+    self,
+    bgzf_file: File,
+    offset: u64,
+    length: Option<u64>,
+  ) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = Source::embedded(bgzf_file, offset, length)?;
+    self.check_eof_marker(&bgzf_file)?;
+    self.open_file(bgzf_file)
+  }
+
+  /// Opens the bgzf data `data`, the bytes of a whole bgzf file, like `open`
+  /// opens a file, without any filesystem access: it works where there are
+  /// no files, like in a browser on wasm32. `cache_index` and `mmap` have no
+  /// file to work with and are ignored.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let reader = BgzfReader::builder().verify_crc(true).open_bytes(data).unwrap();
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// ```
+  pub fn open_bytes(self, data: Vec<u8>) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = Source::Bytes(Arc::new(data));
+    self.check_eof_marker(&bgzf_file)?;
+    self.open_file(bgzf_file)
+  }
+
+  /// Opens the `length` bytes of bgzf data of `source`, any type reading at
+  /// an offset through positioned-io's `ReadAt`, like `open` opens a file.
+  /// `SeekReadSource` adapts sources that can only `Read` and `Seek`. The
+  /// reads of the reader, of its clones and of the parallel methods are
+  /// made from many threads at once, which the source has to support, and
+  /// a read returning less than asked is followed by another one for the
+  /// rest. `cache_index` and `mmap` have no local file to work with and are
+  /// ignored.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let length = data.len() as u64;
+  /// let reader = BgzfReader::builder().open_read_at(data, length).unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// ```
+  pub fn open_read_at<R: ReadAt + Send + Sync + 'static>(
+    self,
+    source: R,
+    length: u64,
+  ) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = Source::Custom {
+      source: Arc::new(source),
+      length,
+    };
+    self.check_eof_marker(&bgzf_file)?;
+    self.open_file(bgzf_file)
+  }
+
+  /// Opens the bgzf file at `url` with HTTP range requests, see
+  /// `open_remote`. Only with the `http` feature.
+  #[cfg(feature = "http")]
+  pub fn open_url(self, url: &str) -> Result<BgzfReader, BgzfError> {
+    self.open_remote(HttpTransport::new(url))
+  }
+
+  /// Opens the remote bgzf file of `transport` like `open` opens a file,
+  /// reading it in chunks of `fetch_size` bytes kept up to
+  /// `remote_cache_bytes`. Indexing reads the whole file, unless `lazy` only
+  /// indexes what the reads need; `open_remote_with_index` and
+  /// `open_remote_with_gzi` take a saved index instead. `cache_index` and
+  /// `mmap` have no local file to work with and are ignored. Only with the
+  /// `remote` feature.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, RangeTransport};
+  /// use std::io;
+  /// use std::ops::Range;
+  ///
+  /// struct InMemory(Vec<u8>);
+  ///
+  /// impl RangeTransport for InMemory {
+  ///   fn length(&self) -> io::Result<u64> {
+  ///     Ok(self.0.len() as u64)
+  ///   }
+  ///
+  ///   fn fetch(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+  ///     Ok(self.0[range.start as usize..range.end as usize].to_vec())
+  ///   }
+  /// }
+  ///
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let reader = BgzfReader::builder()
+  ///   .fetch_size(64 * 1024)
+  ///   .open_remote(InMemory(data))
+  ///   .unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// assert_eq!(1, reader.fetch_stats().misses);
+  /// ```
+  #[cfg(feature = "remote")]
+  pub fn open_remote<T: RangeTransport + 'static>(
+    self,
+    transport: T,
+  ) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = self.remote_source(transport)?;
+    self.check_eof_marker(&bgzf_file)?;
+    self.open_file(bgzf_file)
+  }
+
+  /// Opens the remote bgzf file of `transport` like `open_remote`, with the
+  /// blocks of `index`, saved from an earlier `BgzfReader::index` of the
+  /// same file, so that opening fetches nothing but the end of the file. An
+  /// index that can not be the file's is rejected with a `BgzfError::Index`,
+  /// one that only looks right is caught when a block it describes is read.
+  /// Only with the `remote` feature.
+  #[cfg(feature = "remote")]
+  pub fn open_remote_with_index<T: RangeTransport + 'static>(
+    self,
+    transport: T,
+    index: &BgzfIndex,
+  ) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = self.remote_source(transport)?;
+    index.validate(bgzf_file.len()?)?;
+    self.open_indexed(bgzf_file, index)
+  }
+
+  /// Opens the remote bgzf file of `transport` like `open_remote`, with the
+  /// block offsets of `gzi`, the bytes of its .gzi index, like
+  /// `BgzfReader::open_with_gzi` does for files. Opening only fetches the
+  /// last block and the end of the file. Only with the `remote` feature.
+  #[cfg(feature = "remote")]
+  pub fn open_remote_with_gzi<T: RangeTransport + 'static>(
+    self,
+    transport: T,
+    gzi: &[u8],
+  ) -> Result<BgzfReader, BgzfError> {
+    let mut reader = BgzfReader::from_gzi(self.remote_source(transport)?, gzi)?;
+    self.set_up_reads(&mut reader)?;
+    Ok(reader)
+  }
+
+  /// Opens the bgzf data encrypted with Crypt4GH in the file at `file_path`
+  /// like `open` opens a file, without decrypting it to disk: the header is
+  /// decrypted with `secret_key`, the recipient's secret key as returned by
+  /// `crypt4gh_secret_key`, then each read decrypts the 64 KiB segments it
+  /// needs, which keeps random access. The last segments decrypted are kept
+  /// so that reading the blocks next to each other decrypts each segment
+  /// once. Files with an edit list, which drops parts of the plaintext, are
+  /// refused as `Unsupported`, and a damaged segment fails the reads of its
+  /// blocks. `cache_index` and `mmap` have no plaintext file to work with and
+  /// are ignored. Only with the `crypt4gh` feature.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{crypt4gh_secret_key, BgzfReader};
+  ///
+  /// let secret_key = crypt4gh_secret_key(
+  ///   String::from("tests/data/crypt4gh_recipient.sec"),
+  ///   "bgzf test",
+  /// )
+  /// .unwrap();
+  /// let reader = BgzfReader::builder()
+  ///   .open_crypt4gh(String::from("tests/data/htslib.vcf.gz.c4gh"), &secret_key)
+  ///   .unwrap();
+  /// assert_eq!(4, reader.num_blocks());
+  /// ```
+  #[cfg(feature = "crypt4gh")]
+  pub fn open_crypt4gh(
+    self,
+    file_path: String,
+    secret_key: &[u8],
+  ) -> Result<BgzfReader, BgzfError> {
+    let file = Source::File(File::open(file_path)?);
+    let file_length = file.len()?;
+    let bgzf_file = Source::Crypt4gh(Arc::new(Crypt4ghSource::new(
+      file,
+      file_length,
+      secret_key,
+    )?));
+    self.check_eof_marker(&bgzf_file)?;
+    self.open_file(bgzf_file)
+  }
+
+  /// Returns the source of the remote file of `transport`
+  #[cfg(feature = "remote")]
+  fn remote_source<T: RangeTransport + 'static>(&self, transport: T) -> io::Result<Source> {
+    let remote = RemoteSource::new(
+      Box::new(transport),
+      self.fetch_size,
+      self.remote_cache_bytes,
+    )?;
+    Ok(Source::Remote(Arc::new(remote)))
+  }
+
+  /// Opens the bgzf file at `file_path` like `open`, calling `progress` with
+  /// how far indexing has gone every `every_blocks` blocks (every block for
+  /// 0) and once the whole file is indexed. Returning `ControlFlow::Break`
+  /// stops indexing right away, and the open fails with a
+  /// `BgzfError::Cancelled` error. The whole file is indexed by the calling
+  /// thread whatever the `lazy`, `threads` and `background_indexing` options
+  /// are.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader};
+  /// use std::ops::ControlFlow;
+  ///
+  /// let mut blocks = 0;
+  /// let reader = BgzfReader::builder()
+  ///   .open_with_progress(String::from("bgzf_test.bgz"), 100, |progress| {
+  ///     blocks = progress.blocks;
+  ///     ControlFlow::Continue(())
+  ///   })
+  ///   .unwrap();
+  /// assert_eq!(1, blocks);
+  ///
+  /// let error = BgzfReader::builder()
+  ///   .open_with_progress(String::from("bgzf_test.bgz"), 1, |_progress| ControlFlow::Break(()))
+  ///   .err()
+  ///   .unwrap();
+  /// assert!(matches!(error, BgzfError::Cancelled(_)));
+  /// ```
+  pub fn open_with_progress<F>(
+    self,
+    file_path: String,
+    every_blocks: usize,
+    mut progress: F,
+  ) -> Result<BgzfReader, BgzfError>
+  where
+    F: FnMut(IndexProgress) -> ControlFlow<()>,
+  {
+    let every_blocks = every_blocks.max(1);
+    let bgzf_file = Source::File(File::open(file_path)?);
+    self.check_eof_marker(&bgzf_file)?;
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!("index_scan", blocks = tracing::field::Empty).entered();
+    let mut reader = BgzfReader::with_blocks(bgzf_file, Vec::new(), 0, Some(0));
+    reader.limits = self.limits;
+    reader.parse_mode = self.parse_mode;
+    reader.recover = self.recover;
+    let mut reported = None;
+    loop {
+      reader.index_up_to(reader.indexed_length.get() + 1);
+      let done = reader.next_block_offset.get().is_none();
+      let blocks = reader
+        .blocks
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner);
+      if reported != Some(blocks.len()) && (done || blocks.len().is_multiple_of(every_blocks)) {
+        reported = Some(blocks.len());
+        //the callback only gets a copy of the counts, never the reader
+        let indexed = IndexProgress {
+          blocks: blocks.len() as u64,
+          compressed_bytes: blocks
+            .last()
+            .map_or(0, |block| block.block_offset + u64::from(block.block_size)),
+          uncompressed_bytes: reader.indexed_length.get(),
+        };
+        if progress(indexed).is_break() {
+          return Err(IndexingCancelled { progress: indexed }.into());
+        }
+      }
+      if done {
+        break;
+      }
+    }
+    reader
+      .blocks
+      .get_mut()
+      .unwrap_or_else(PoisonError::into_inner)
+      .shrink_to_fit();
+    reader.input_length = reader.indexed_length.get();
+    #[cfg(feature = "tracing")]
+    span.record("blocks", shared_state::read(&reader.blocks).len());
+    reader.allow_truncated = self.allows_truncated();
+    self.set_up_reads(&mut reader)?;
+    reader.check_truncation()?;
+    reader.record_open_warnings();
+    Ok(reader)
+  }
+
+  /// Opens `bgzf_file` with the blocks of `index`, which fits it
+  fn open_indexed(&self, bgzf_file: Source, index: &BgzfIndex) -> Result<BgzfReader, BgzfError> {
+    let mut reader = BgzfReader::from_index(bgzf_file, index);
+    reader.limits = self.limits;
+    reader.parse_mode = self.parse_mode;
+    reader.enforce_limits(&mut shared_state::write(&reader.blocks));
+    self.set_up_reads(&mut reader)?;
+    reader.check_truncation()?;
+    reader.record_open_warnings();
+    Ok(reader)
+  }
+
+  /// Whether truncated files open, as they do in permissive and recovery
+  /// modes
+  fn allows_truncated(&self) -> bool {
+    self.allow_truncated || self.parse_mode == ParseMode::Permissive || self.recover
+  }
+
+  /// Fails when `bgzf_file` does not start with a BGZF block, or the EOF
+  /// marker is required and it does not end with it
+  fn check_eof_marker(&self, bgzf_file: &Source) -> Result<(), BgzfError> {
+    check_bgzf_start(bgzf_file)?;
+    if self.require_eof_marker && !ends_with_eof_marker(bgzf_file)? {
+      return Err(BgzfError::MissingEofMarker);
+    }
+    Ok(())
+  }
+
+  /// Sets up the cache, prefetching and parallel decompression of `reader`
+  fn set_up_reads(&self, reader: &mut BgzfReader) -> io::Result<()> {
+    reader.executor = self.executor.clone();
+    reader.cache = Mutex::new(BlockCache::new("blocks", self.cache_bytes as u64));
+    if self.compressed_cache_bytes > 0 {
+      reader.compressed_cache = Some(Mutex::new(BlockCache::new(
+        "compressed",
+        self.compressed_cache_bytes as u64,
+      )));
+    }
+    reader.decompressors = Arc::new(DecompressorPool::new(self.decompressors));
+    reader.load_all_limit = self.load_all_limit;
+    reader.verify_crc = self.verify_crc;
+    #[cfg(feature = "mmap")]
+    if self.mmap {
+      //only whole files are mapped
+      if let Some(file) = reader.bgzf_file.file() {
+        reader.mapping = Some(MappedFile::new(file)?);
+      }
+    }
+    if self.prefetch > 0 {
+      reader.prefetcher = Some(reader.spawn_prefetcher()?);
+      reader.prefetch_depth = self.prefetch;
+    }
+    Ok(())
+  }
+
+  fn open_file(self, bgzf_file: Source) -> Result<BgzfReader, BgzfError> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!("index_scan", blocks = tracing::field::Empty).entered();
+    //recovering from damage is done serially
+    let background_indexing = self.background_indexing && !self.recover;
+    let mut reader = if !self.lazy
+      && !background_indexing
+      && !self.recover
+      && self.threads > 1
+      && bgzf_file.len()? >= PARALLEL_SCAN_MIN_LENGTH
+    {
+      let (blocks, input_length, truncation, anomaly_scan) =
+        scan_blocks_parallel(&bgzf_file, self.threads, self.parse_mode, &self.executor)?;
+      let mut reader = BgzfReader::with_blocks(bgzf_file, blocks, input_length, None);
+      reader.truncation.set(truncation);
+      *reader
+        .anomaly_scan
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner) = anomaly_scan;
+      reader.limits = self.limits;
+      reader.parse_mode = self.parse_mode;
+      reader.enforce_limits(&mut shared_state::write(&reader.blocks));
+      reader
+    } else {
+      let mut reader = BgzfReader::with_blocks(bgzf_file, Vec::new(), 0, Some(0));
+      reader.limits = self.limits;
+      reader.parse_mode = self.parse_mode;
+      reader.recover = self.recover;
+      let first_block_only = self.lazy || background_indexing;
+      reader.index_up_to(if first_block_only { 1 } else { u64::MAX });
+      reader
+        .blocks
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .shrink_to_fit();
+      reader.input_length = reader.indexed_length.get();
+      reader
+    };
+    #[cfg(feature = "tracing")]
+    span.record("blocks", shared_state::read(&reader.blocks).len());
+    reader.allow_truncated = self.allows_truncated();
+    self.set_up_reads(&mut reader)?;
+    reader.check_truncation()?;
+    reader.record_open_warnings();
+    if let (true, Some(block_offset)) = (background_indexing, reader.next_block_offset.get()) {
+      reader.background = Some(BackgroundIndexer::spawn(
+        reader.bgzf_file.try_clone()?,
+        block_offset,
+        reader.indexed_length.get(),
+        reader.parse_mode,
+      )?);
+    }
+    Ok(reader)
+  }
+}
+
+/// Below are the steps to use the bgzf Reader,
+/// 1st step is to create a BGZF instance with a new function
+/// after that read, and seek method can be used respectively.
+///
+/// A file of the EOF marker alone, which bgzip writes for empty data, and a
+/// file of no bytes at all both open as a reader of no data, whose reads
+/// return 0 from any position.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::BgzfReader;
+/// use std::str;
+///
+///  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+///  let mut vec = vec![0; 52];
+///  let data_read = reader.read_to(&mut vec);
+///  assert_eq!(data_read.unwrap(), 52);
+///  assert_eq!(
+///    "This is just a bgzf test,lets see how it reacts. :).",
+///    str::from_utf8(&vec).unwrap()
+///  );
+///
+/// ```
+impl BgzfReader {
+  pub fn new(file_path: String) -> Result<BgzfReader, BgzfError> {
+    BgzfReader::builder().open(file_path)
+  }
+
+  /// Opens the bgzf data `data` held in memory, see
+  /// `BgzfReaderBuilder::open_bytes`
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let reader = BgzfReader::from_bytes(data).unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// ```
+  pub fn from_bytes(data: Vec<u8>) -> Result<BgzfReader, BgzfError> {
+    BgzfReader::builder().open_bytes(data)
+  }
+
+  /// Opens the bgzf stream embedded in the file at `file_path` from byte
+  /// `offset`, of `length` bytes or up to the end of the file, see
+  /// `BgzfReaderBuilder::open_file_at`. Its compressed and virtual offsets
+  /// are relative to `offset`.
+  pub fn open_at(
+    file_path: String,
+    offset: u64,
+    length: Option<u64>,
+  ) -> Result<BgzfReader, BgzfError> {
+    BgzfReader::builder().open_at(file_path, offset, length)
+  }
+
+  /// Returns a builder to open a file with other options than `new`
+  pub fn builder() -> BgzfReaderBuilder {
+    BgzfReaderBuilder::default()
+  }
+
+  /// Opens the bgzf file at `file_path` like `new`, but reads the blocks from
+  /// a memory mapping of the file rather than with a read per block, letting
+  /// the OS cache the compressed pages. Only with the `mmap` feature.
+  ///
+  /// The file must not be truncated or changed while the reader is open: on
+  /// most systems reading a page cut off from a mapped file kills the process
+  /// with SIGBUS, and changed pages change the data read. Blocks past the end
+  /// of the mapping, like those of a stale index, fail to read like with the
+  /// file.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::open_mmap(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// ```
+  #[cfg(feature = "mmap")]
+  pub fn open_mmap(file_path: String) -> Result<BgzfReader, BgzfError> {
+    BgzfReader::builder().mmap(true).open(file_path)
+  }
+
+  /// Where the blocks are read from to be decompressed, the mapping when
+  /// opened with `mmap`, the file otherwise
+  fn block_source(&self) -> &(dyn ReadAt + Sync) {
+    #[cfg(feature = "mmap")]
+    if let Some(mapping) = &self.mapping {
+      return mapping;
+    }
+    &self.bgzf_file
+  }
+
+  #[allow(deprecated)]
+  fn with_blocks(
+    bgzf_file: Source,
+    blocks: Vec<BgzfBlock>,
+    indexed_length: u64,
+    next_block_offset: Option<u64>,
+  ) -> BgzfReader {
+    //a file whose end can not be read has no marker to speak of
+    let has_eof_marker = ends_with_eof_marker(&bgzf_file).unwrap_or(false);
+    BgzfReader {
+      bgzf_file,
+      blocks: RwLock::new(blocks),
+      next_block_offset: SharedCell::new(next_block_offset),
+      indexed_length: Position::new(indexed_length),
+      truncation: SharedCell::new(None),
+      allow_truncated: false,
+      has_eof_marker,
+      background: None,
+      decompressors: Arc::new(DecompressorPool::new(DecompressorPool::default_max())),
+      compressed_run: Mutex::new(CompressedRun::default()),
+      #[cfg(feature = "mmap")]
+      mapping: None,
+      input_length: indexed_length,
+      current_read_position: Position::new(0),
+      pos: Position::new(0),
+      cache: Mutex::new(BlockCache::new("blocks", 0)),
+      compressed_cache: None,
+      prefetcher: None,
+      prefetch_depth: 0,
+      loaded: OnceLock::new(),
+      load_all_limit: DEFAULT_LOAD_ALL_LIMIT,
+      verify_crc: false,
+      limits: Limits::default(),
+      limit_hit: SharedCell::new(None),
+      parse_mode: ParseMode::Default,
+      anomaly_scan: Mutex::new(AnomalyScan::default()),
+      open_warnings: Vec::new(),
+      recover: false,
+      gaps: Mutex::new(Vec::new()),
+      last_read_end: Position::new(0),
+      sequential_read: Mutex::new(()),
+      executor: Executor::default(),
+    }
+  }
+
+  /// Indexes the blocks following the last indexed one until the blocks
+  /// cover the uncompressed data up to `end`, or the file is fully indexed.
+  /// Empty blocks, like the EOF markers left in the middle of files joined
+  /// with `cat`, are skipped. Indexing stops at the first block that can not
+  /// be read, which is recorded as a truncation unless it is the end of the
+  /// file or follows an EOF marker. With a background indexer it waits for
+  /// the indexer's blocks instead of reading any. Indexing also stops at the
+  /// limits set on the builder, and at the first anomaly in strict mode.
+  fn index_up_to(&self, end: u64) {
+    //most reads need no more blocks, and do not wait for reads holding them
+    if self.indexed_length.get() >= end || self.next_block_offset.get().is_none() {
+      return;
+    }
+    let mut blocks = shared_state::write(&self.blocks);
+    let mut anomaly_scan = lock(&self.anomaly_scan);
+    let strict = self.parse_mode == ParseMode::Strict;
+    if let Some(indexer) = &self.background {
+      if self.next_block_offset.get().is_some() && self.indexed_length.get() < end {
+        let (indexed_length, done) = indexer.wait_for(end, &mut blocks, &mut anomaly_scan.found);
+        self.indexed_length.set(indexed_length);
+        if done {
+          self.truncation.set(indexer.truncation());
+          self.next_block_offset.set(None);
+        }
+        let stopped_by_anomaly = strict && !anomaly_scan.found.is_empty();
+        if self.enforce_limits(&mut blocks) || stopped_by_anomaly {
+          self.next_block_offset.set(None);
+          indexer.stop();
+        }
+      }
+      return;
+    }
+    let scan = ScanBuffer::new(&self.bgzf_file);
+    while self.indexed_length.get() < end {
+      let block_offset = match self.next_block_offset.get() {
+        Some(block_offset) => block_offset,
+        None => return,
+      };
+      match read_block_with_anomaly(&scan, block_offset, self.parse_mode) {
+        Ok((block, header_anomaly)) => {
+          anomaly_scan.block(&block, header_anomaly);
+          if strict && !anomaly_scan.found.is_empty() {
+            self.next_block_offset.set(None);
+            return;
+          }
+          if block.input_length > 0 {
+            blocks.push(BgzfBlock {
+              input_offset: self.indexed_length.get(),
+              ..block
+            });
+            self
+              .indexed_length
+              .set(self.indexed_length.get() + u64::from(block.input_length));
+            if self.enforce_limits(&mut blocks) {
+              return;
+            }
+          }
+          self
+            .next_block_offset
+            .set(Some(block_offset + u64::from(block.block_size)));
+        }
+        _ => {
+          let truncation = truncation_at(&self.bgzf_file, block_offset);
+          //a block whose size leads elsewhere than to the next one is
+          //skipped along with what follows it up to the next block
+          let damage_start = match (self.recover, truncation) {
+            (true, Some(_)) => {
+              Some(broken_chain(&self.bgzf_file, &blocks, block_offset).unwrap_or(block_offset))
+            }
+            _ => None,
+          };
+          if let Some((damage_start, next_block_offset)) = damage_start.and_then(|damage_start| {
+            self
+              .resync(damage_start)
+              .map(|next_block_offset| (damage_start, next_block_offset))
+          }) {
+            if damage_start != block_offset {
+              let suspect = blocks.pop().unwrap();
+              self.indexed_length.set(suspect.input_offset);
+            }
+            let uoffset = self.indexed_length.get();
+            lock(&self.gaps).push(DataGap {
+              compressed_range: damage_start..next_block_offset,
+              uoffset_range: uoffset..uoffset,
+            });
+            self.next_block_offset.set(Some(next_block_offset));
+            continue;
+          }
+          self.truncation.set(truncation);
+          anomaly_scan.end(&self.bgzf_file, block_offset);
+          self.next_block_offset.set(None);
+        }
+      }
+    }
+  }
+
+  /// Returns the offset of the next block that can be read after the
+  /// damaged one at `block_offset`, if any
+  fn resync(&self, block_offset: u64) -> Option<u64> {
+    let file_length = self.bgzf_file.len().ok()?;
+    recovery::resync(&self.bgzf_file, block_offset, file_length)
+  }
+
+  /// Returns the damage skipped over in the file, in file order, when opened
+  /// with `recover`: the damaged block headers, once the rest of the file is
+  /// indexed, and the blocks that failed to decompress so far
+  pub fn gaps(&self) -> Vec<DataGap> {
+    self.index_up_to(u64::MAX);
+    lock(&self.gaps).clone()
+  }
+
+  /// Drops the indexed `blocks` past the limits and stops indexing there,
+  /// recording the limit hit. Returns whether one was.
+  fn enforce_limits(&self, blocks: &mut Vec<BgzfBlock>) -> bool {
+    let limits = self.limits;
+    let within_length = blocks.partition_point(|block| {
+      block.input_offset + u64::from(block.input_length) <= limits.max_uncompressed_length
+    });
+    let (kept, limit) = if blocks.len() as u64 > limits.max_blocks {
+      (limits.max_blocks as usize, ResourceLimit::Blocks)
+    } else if within_length < blocks.len() {
+      (within_length, ResourceLimit::UncompressedLength)
+    } else {
+      return false;
+    };
+    blocks.truncate(kept);
+    self.indexed_length.set(blocks.last().map_or(0, |block| {
+      block.input_offset + u64::from(block.input_length)
+    }));
+    self.next_block_offset.set(None);
+    self.limit_hit.set(Some(limit));
+    true
+  }
+
+  /// Fails with a `BgzfError::LimitExceeded` error when indexing stopped at
+  /// one of the limits, then with a `BgzfError::Truncated` error when it
+  /// stopped at a block that can not be read, or `InvalidFooter` when that
+  /// block is whole but gives a length no block has, unless opened with
+  /// `allow_truncated`, then in strict mode with a `BgzfError::Anomaly`
+  /// error for the first anomaly found
+  fn check_truncation(&self) -> Result<(), BgzfError> {
+    if let Some(limit) = self.limit_hit.get() {
+      return Err(BgzfError::LimitExceeded {
+        limit,
+        max: self.limits.max(limit),
+      });
+    }
+    match self.truncation.get() {
+      //a whole block breaking the limits of the format is no truncation, nor
+      //a block whose size leads elsewhere than to the next one
+      Some(truncation) if !self.allow_truncated => {
+        let blocks = shared_state::read(&self.blocks);
+        if let Some(offset) = broken_chain(&self.bgzf_file, &blocks, truncation.block_offset) {
+          return Err(BgzfError::BlockChainBroken { offset });
+        }
+        return match read_block(&self.bgzf_file, truncation.block_offset) {
+          Err(error @ BgzfError::InvalidFooter { .. })
+          | Err(error @ BgzfError::InvalidBlock { .. }) => Err(error),
+          _ => Err(truncation.into()),
+        };
+      }
+      _ => {}
+    }
+    match self.anomalies().first() {
+      Some(&anomaly) if self.parse_mode == ParseMode::Strict => Err(anomaly.into()),
+      _ => Ok(()),
+    }
+  }
+
+  /// Returns the anomalies found in the file so far, where it departs from
+  /// the BGZF spec while still being readable, in file order. When opened
+  /// lazily or with `background_indexing`, only those of the blocks indexed
+  /// yet are known. Always empty with `ParseMode::Permissive`, while with
+  /// `ParseMode::Strict` opening or reading fails at the first one.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+  /// use bgzf_rust_reader::{AnomalyKind, BgzfReader};
+  ///
+  /// //two files joined with cat, the EOF marker of the first left in between
+  /// let mut joined = make_bgzf(b"first", 8);
+  /// let marker = joined.len() - 28;
+  /// joined.extend(make_bgzf(b"second", 8));
+  /// let path = write_temp("anomalies_doc", &joined).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// let anomalies = reader.anomalies();
+  /// assert_eq!(1, anomalies.len());
+  /// assert_eq!(AnomalyKind::EmptyBlock, anomalies[0].kind);
+  /// assert_eq!(marker as u64, anomalies[0].offset);
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn anomalies(&self) -> Vec<Anomaly> {
+    if self.parse_mode == ParseMode::Permissive {
+      return Vec::new();
+    }
+    let mut anomalies = lock(&self.anomaly_scan).found.clone();
+    anomalies.extend(self.missing_eof_marker());
+    anomalies
+  }
+
+  /// The anomaly of a file not ending with the EOF marker, data after the
+  /// marker making it missing from the end too
+  fn missing_eof_marker(&self) -> Option<Anomaly> {
+    if self.has_eof_marker {
+      return None;
+    }
+    Some(Anomaly {
+      kind: AnomalyKind::MissingEofMarker,
+      offset: self.bgzf_file.len().unwrap_or(0),
+    })
+  }
+
+  /// Returns what opening found imperfect in the file without failing, in
+  /// file order and in every parse mode, for logs: the anomalies `anomalies`
+  /// returns, and the data blocks other than the last one holding less than
+  /// 1024 bytes. Opened lazily or with background indexing, only the blocks
+  /// read at open are covered, and opened with an index, only the blocks it
+  /// gives and the EOF marker. Strict mode fails on anomalies as ever, small
+  /// blocks do not fail.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_without_eof, write_temp};
+  /// use bgzf_rust_reader::{BgzfReader, OpenWarning};
+  ///
+  /// let bgzf = make_without_eof(&[7; 3000], 2000);
+  /// let path = write_temp("open_warnings_doc", &bgzf).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// assert_eq!(
+  ///   &[OpenWarning::MissingEofMarker {
+  ///     offset: bgzf.len() as u64
+  ///   }],
+  ///   reader.open_warnings()
+  /// );
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn open_warnings(&self) -> &[OpenWarning] {
+    &self.open_warnings
+  }
+
+  /// Sets the open warnings from the anomalies and blocks found so far
+  fn record_open_warnings(&mut self) {
+    let mut anomalies = self
+      .anomaly_scan
+      .get_mut()
+      .unwrap_or_else(PoisonError::into_inner)
+      .found
+      .clone();
+    anomalies.extend(self.missing_eof_marker());
+    self.open_warnings = anomaly::open_warnings(
+      &anomalies,
+      self
+        .blocks
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner),
+    );
+  }
+
+  /// Tells whether the blocks of the file end with one that is cut off or
+  /// damaged. When opened lazily it indexes the rest of the file to know it.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_truncated, write_temp};
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader};
+  ///
+  /// let path = write_temp("is_truncated_doc", &make_truncated(b"bgzf data", 4, 10)).unwrap();
+  /// let error = BgzfReader::new(path.clone()).err().unwrap();
+  /// assert!(matches!(error, BgzfError::Truncated(_)));
+  ///
+  /// let reader = BgzfReader::builder()
+  ///   .allow_truncated(true)
+  ///   .open(path.clone())
+  ///   .unwrap();
+  /// assert!(reader.is_truncated());
+  /// assert_eq!(8, reader.total_uncompressed_length());
+  /// std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn is_truncated(&self) -> bool {
+    self.truncation().is_some()
+  }
+
+  /// Tells whether the file ends with the 28 bytes of the BGZF EOF marker,
+  /// which writers add once every block is written. A last block that is
+  /// merely empty without being the exact marker does not count.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_without_eof, write_temp};
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert!(reader.has_eof_marker());
+  ///
+  /// let path = write_temp("has_eof_marker_doc", &make_without_eof(b"bgzf", 4)).unwrap();
+  /// assert!(!BgzfReader::new(path.clone()).unwrap().has_eof_marker());
+  /// assert!(BgzfReader::builder()
+  ///   .require_eof_marker(true)
+  ///   .open(path.clone())
+  ///   .is_err());
+  /// std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn has_eof_marker(&self) -> bool {
+    self.has_eof_marker
+  }
+
+  /// Returns how many decompressed blocks and bytes the reader keeps, within
+  /// the budget set with `BgzfReaderBuilder::cache_bytes`, and how often
+  /// reads found their blocks there
+  pub fn cache_stats(&self) -> CacheStats {
+    lock(&self.cache).stats()
+  }
+
+  /// Returns how many compressed blocks and bytes the reader keeps, within
+  /// the budget set with `BgzfReaderBuilder::compressed_cache_bytes`. Its
+  /// hits are the blocks decompressed again without reading the file, and its
+  /// misses the blocks read from the file. All 0 when it is off.
+  pub fn compressed_cache_stats(&self) -> CacheStats {
+    self
+      .compressed_cache
+      .as_ref()
+      .map_or_else(CacheStats::default, |cache| lock(cache).stats())
+  }
+
+  /// Returns how many chunks of a remote file opened with
+  /// `BgzfReaderBuilder::open_remote` the reader keeps, within the budget set
+  /// with `remote_cache_bytes`. Its misses are the requests made, its hits
+  /// the reads of chunks fetched before. All 0 for other files. Only with the
+  /// `remote` feature.
+  #[cfg(feature = "remote")]
+  pub fn fetch_stats(&self) -> CacheStats {
+    match &self.bgzf_file {
+      Source::Remote(remote) => remote.stats(),
+      _ => CacheStats::default(),
+    }
+  }
+
+  /// Returns where the blocks of the file stop being readable, None when
+  /// they end normally (see `is_truncated`)
+  pub fn truncation(&self) -> Option<Truncated> {
+    self.index_up_to(u64::MAX);
+    self.truncation.get()
+  }
+
+  /// Returns the blocks of the whole file, indexing what is left of it first
+  /// when opened lazily
+  pub(crate) fn blocks(&self) -> RwLockReadGuard<'_, Vec<BgzfBlock>> {
+    self.index_up_to(u64::MAX);
+    shared_state::read(&self.blocks)
+  }
+
+  /// Opens the bgzf file at `file_path` with the block offsets of the .gzi
+  /// index at `gzi_path` (see `save_gzi`), instead of reading the header of
+  /// every block like `new` does. Only the last block is read, the others
+  /// get their sizes from the offsets around them. An index that can not be
+  /// the file's is reported as a `BgzfError::Gzi`, and one that only looks
+  /// right is caught when a block it describes is read.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let gzi_path = std::env::temp_dir().join("bgzf_test_open_doc.gzi");
+  /// let gzi_path = gzi_path.to_string_lossy().into_owned();
+  /// reader.save_gzi(gzi_path.clone()).unwrap();
+  ///
+  /// let indexed = BgzfReader::open_with_gzi(String::from("bgzf_test.bgz"), gzi_path.clone()).unwrap();
+  /// assert_eq!(280, indexed.total_uncompressed_length());
+  /// std::fs::remove_file(gzi_path).unwrap();
+  /// ```
+  pub fn open_with_gzi(file_path: String, gzi_path: String) -> Result<BgzfReader, BgzfError> {
+    let bgzf_file = Source::File(File::open(file_path)?);
+    BgzfReader::from_gzi(bgzf_file, &fs::read(gzi_path)?)
+  }
+
+  /// Same as `open_with_gzi` with `gzi`, the bytes of the .gzi index
+  fn from_gzi(bgzf_file: Source, gzi: &[u8]) -> Result<BgzfReader, BgzfError> {
+    let file_length = bgzf_file.len()?;
+    let block_offsets = gzi::read_gzi(gzi)?;
+    for (entry, &(compressed_offset, _uncompressed_offset)) in block_offsets.iter().enumerate() {
+      if compressed_offset >= file_length {
+        return Err(
+          GziError::OffsetBeyondEnd {
+            entry,
+            compressed_offset,
+            file_length,
+          }
+          .into(),
+        );
+      }
+    }
+
+    let mut blocks: Vec<BgzfBlock> = block_offsets
+      .windows(2)
+      .map(|pair| {
+        let ((block_offset, input_offset), (next_block_offset, next_input_offset)) =
+          (pair[0], pair[1]);
+        BgzfBlock {
+          input_offset,
+          block_offset,
+          input_length: (next_input_offset - input_offset) as u32,
+          block_size: (next_block_offset - block_offset) as u32,
+        }
+      })
+      .collect();
+    let entry = block_offsets.len() - 1;
+    let (compressed_offset, mut input_offset) = block_offsets[entry];
+    match read_block(&bgzf_file, compressed_offset) {
+      Ok(block) if block.input_length > 0 => {
+        blocks.push(BgzfBlock {
+          input_offset,
+          ..block
+        });
+        input_offset += u64::from(block.input_length);
+      }
+      //the index of a file holding no data points at the EOF marker
+      Ok(_empty_block) if entry == 0 => {}
+      _ => {
+        return Err(
+          GziError::InvalidBlock {
+            entry,
+            compressed_offset,
+          }
+          .into(),
+        )
+      }
+    }
+    let mut reader = BgzfReader::with_blocks(bgzf_file, blocks, input_offset, None);
+    reader.record_open_warnings();
+    Ok(reader)
+  }
+
+  /// Opens the bgzf file at `file_path` with the blocks of `index`, saved
+  /// from an earlier `BgzfReader::index` of the same file, without reading
+  /// any block header. An index that can not be the file's is rejected with
+  /// a `BgzfError::Index` holding the error of `BgzfIndex::validate`, one
+  /// that only looks right is caught when a block it describes is read.
+  pub fn open_with_index(file_path: String, index: &BgzfIndex) -> Result<BgzfReader, BgzfError> {
+    BgzfReader::from_file_with_index(File::open(file_path)?, index)
+  }
+
+  /// Same as `open_with_index` for a bgzf file that is already open, such as
+  /// one opened with other options or handed over by another process
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfIndex, BgzfReader};
+  /// use std::fs::File;
+  ///
+  /// let mut saved = Vec::new();
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.index().unwrap().save(&mut saved).unwrap();
+  ///
+  /// let index = BgzfIndex::load(&saved[..]).unwrap();
+  /// let file = File::open("bgzf_test.bgz").unwrap();
+  /// let reader = BgzfReader::from_file_with_index(file, &index).unwrap();
+  /// let mut first_bytes = vec![0; 4];
+  /// reader.read_to(&mut first_bytes).unwrap();
+  /// assert_eq!(b"This", &first_bytes[..]);
+  /// ```
+  pub fn from_file_with_index(bgzf_file: File, index: &BgzfIndex) -> Result<BgzfReader, BgzfError> {
+    index.validate(bgzf_file.metadata()?.len())?;
+    let mut reader = BgzfReader::from_index(Source::File(bgzf_file), index);
+    reader.record_open_warnings();
+    Ok(reader)
+  }
+
+  /// Starts a thread decompressing the next blocks of sequential reads,
+  /// reading them from the mapping when there is one
+  fn spawn_prefetcher(&self) -> io::Result<Prefetcher> {
+    #[cfg(feature = "mmap")]
+    if let Some(mapping) = &self.mapping {
+      return Prefetcher::spawn(
+        mapping.clone(),
+        Arc::clone(&self.decompressors),
+        self.verify_crc,
+      );
+    }
+    Prefetcher::spawn(
+      self.bgzf_file.try_clone()?,
+      Arc::clone(&self.decompressors),
+      self.verify_crc,
+    )
+  }
+
+  fn from_index(bgzf_file: Source, index: &BgzfIndex) -> BgzfReader {
+    BgzfReader::with_blocks(
+      bgzf_file,
+      blocks_of_index(index),
+      index.total_uncompressed_length(),
+      None,
+    )
+  }
+
+  /// Returns the index of every block of the file, to save and give to
+  /// `open_with_index` later. When opened lazily the rest of the file is
+  /// indexed first.
+  ///
+  /// Fails with `IndexError::Gap` when opened with `recover` and damage
+  /// shorter than the 28 bytes of an empty block was skipped, which an index
+  /// can not hold
+  pub fn index(&self) -> Result<BgzfIndex, IndexError> {
+    BgzfIndex::new(self.block_offsets().collect())
+  }
+
+  /// Returns another reader of the same data, reading the file through a
+  /// duplicate of its handle (`File::try_clone`) rather than opening its
+  /// path again, which may no longer exist. The new reader has a copy of the
+  /// blocks indexed so far and the options of this one, its position at 0
+  /// and its caches empty, and is then independent of this one: seeks, reads
+  /// and caches of one never affect the other. Bytes and remote files are
+  /// shared rather than copied.
+  ///
+  /// Neither reader indexes the file again, so blocks appended to the file
+  /// once it was indexed are seen by neither, reopen it to read them. When
+  /// this reader has only indexed part of the file so far, opened `lazy` or
+  /// with `background_indexing`, the new reader indexes the rest itself,
+  /// seeing the file as it is then.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.seek(33);
+  /// let other = reader.try_clone().unwrap();
+  /// assert_eq!(0, other.pos.get());
+  /// let (mut first, mut second) = (vec![0; 8], vec![0; 8]);
+  /// other.read_to(&mut first).unwrap();
+  /// reader.read_to(&mut second).unwrap();
+  /// assert_eq!(b"This is ", &first[..]);
+  /// assert_eq!(b" how it ", &second[..]);
+  /// ```
+  #[allow(deprecated)]
+  pub fn try_clone(&self) -> Result<BgzfReader, BgzfError> {
+    let blocks = shared_state::read(&self.blocks).clone();
+    //the blocks of a background indexer are received without moving where
+    //indexing goes on, the new reader indexes on from the last one itself
+    let next_block_offset = match (
+      &self.background,
+      self.next_block_offset.get(),
+      blocks.last(),
+    ) {
+      (Some(_indexer), Some(_next_block_offset), Some(last)) => {
+        Some(last.block_offset + u64::from(last.block_size))
+      }
+      (_, next_block_offset, _) => next_block_offset,
+    };
+    let mut reader = BgzfReader {
+      bgzf_file: self.bgzf_file.try_clone()?,
+      blocks: RwLock::new(blocks),
+      next_block_offset: SharedCell::new(next_block_offset),
+      indexed_length: Position::new(self.indexed_length.get()),
+      truncation: SharedCell::new(self.truncation.get()),
+      allow_truncated: self.allow_truncated,
+      has_eof_marker: self.has_eof_marker,
+      background: None,
+      decompressors: Arc::clone(&self.decompressors),
+      compressed_run: Mutex::new(CompressedRun::default()),
+      #[cfg(feature = "mmap")]
+      mapping: self.mapping.clone(),
+      cache: Mutex::new(BlockCache::new("blocks", lock(&self.cache).stats().budget)),
+      compressed_cache: self
+        .compressed_cache
+        .as_ref()
+        .map(|cache| Mutex::new(BlockCache::new("compressed", lock(cache).stats().budget))),
+      prefetcher: None,
+      prefetch_depth: self.prefetch_depth,
+      loaded: OnceLock::new(),
+      load_all_limit: self.load_all_limit,
+      verify_crc: self.verify_crc,
+      limits: self.limits,
+      limit_hit: SharedCell::new(self.limit_hit.get()),
+      parse_mode: self.parse_mode,
+      anomaly_scan: Mutex::new(lock(&self.anomaly_scan).clone()),
+      open_warnings: self.open_warnings.clone(),
+      recover: self.recover,
+      gaps: Mutex::new(lock(&self.gaps).clone()),
+      last_read_end: Position::new(0),
+      sequential_read: Mutex::new(()),
+      executor: self.executor.clone(),
+      input_length: self.input_length,
+      current_read_position: Position::new(0),
+      pos: Position::new(0),
+    };
+    if self.prefetcher.is_some() {
+      reader.prefetcher = Some(reader.spawn_prefetcher()?);
+    }
+    Ok(reader)
+  }
+
+  /// This method can set the file position relative to uncompressed data.
+  /// Any position can be set, positions past the end of the data too: reads
+  /// from them return 0 like reads at the end, after the reader checks the
+  /// file is not truncated as they do, and `tell_virtual` gives the end of
+  /// the data. Seeking back makes the data readable again. A read whose end
+  /// would be past `u64::MAX` fails with `BgzfError::PositionOverflow`
+  /// instead, without moving the position.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  ///let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  ///reader.seek(33);
+  ///assert_eq!(33, reader.pos.get());
+  ///
+  /// ```
+  pub fn seek(&self, pos: u64) {
+    self.pos.set(pos);
+  }
+
+  /// Returns the virtual offset of the current position, the compressed
+  /// offset of the block holding it shifted 16 bits left plus the offset in
+  /// the uncompressed block, as used by BAI, TBI and CSI indexes. At the end
+  /// of the data it is the offset right after the last block.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.seek(33);
+  /// assert_eq!(33, reader.tell_virtual());
+  /// ```
+  pub fn tell_virtual(&self) -> u64 {
+    self.virtual_offset(self.pos.get())
+  }
+
+  /// Returns the offset in the file of the block holding the current
+  /// position, the offset right after the last block at the end of the data,
+  /// for progress reports of how much of the file was read. It is the
+  /// compressed offset of `tell_virtual`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// //a single block of 211 bytes holds the 280 bytes of data
+  /// let mut content = vec![0; 200];
+  /// reader.read_to(&mut content).unwrap();
+  /// assert_eq!(0, reader.compressed_position());
+  /// reader.read_to(&mut content).unwrap();
+  /// assert_eq!(211, reader.compressed_position());
+  /// assert_eq!(reader.compressed_length(), reader.compressed_position());
+  /// ```
+  pub fn compressed_position(&self) -> u64 {
+    self.tell_virtual() >> 16
+  }
+
+  fn virtual_offset(&self, uoffset: u64) -> u64 {
+    self.index_up_to(uoffset.saturating_add(1));
+    let blocks = shared_state::read(&self.blocks);
+    match floor_block(&blocks, uoffset).map(|index| &blocks[index]) {
+      Some(block) if uoffset - block.input_offset < u64::from(block.input_length) => {
+        block.block_offset << 16 | (uoffset - block.input_offset)
+      }
+      //past the last block
+      Some(block) => (block.block_offset + u64::from(block.block_size)) << 16,
+      None => 0,
+    }
+  }
+
+  /// Sets the position to the virtual offset `voffset` (see `tell_virtual`).
+  /// A block must start at its compressed offset, `voffset >> 16`, and the
+  /// offset within the block can not be past the block's uncompressed data.
+  /// The offset right after the last block is the end of the data, and so is
+  /// the offset right after the EOF marker.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.seek_virtual(52).unwrap();
+  /// let mut content = vec![0; 8];
+  /// reader.read_to(&mut content).unwrap();
+  /// assert_eq!(b" I think", &content[..]);
+  /// assert!(reader.seek_virtual(1 << 16).is_err());
+  /// ```
+  pub fn seek_virtual(&self, voffset: u64) -> Result<(), BgzfError> {
+    self.pos.set(self.voffset_to_uoffset(voffset)?);
+    Ok(())
+  }
+
+  /// Same as `tell_virtual`, returning a noodles `VirtualPosition`. Only with
+  /// the `noodles` feature.
+  ///
+  /// The virtual offsets of this crate are the `u64` noodles converts from
+  /// and to with `From`: `VirtualPosition::from(reader.tell_virtual())` and
+  /// `u64::from(position)` never fail, the compressed offset of a `u64`
+  /// shifted 16 bits right always fitting in the 48 bits of a
+  /// `VirtualPosition`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.seek(33);
+  /// let position = reader.tell_virtual_position();
+  /// assert_eq!((0, 33), (position.compressed(), position.uncompressed()));
+  /// ```
+  #[cfg(feature = "noodles")]
+  pub fn tell_virtual_position(&self) -> noodles_bgzf::VirtualPosition {
+    noodles_bgzf::VirtualPosition::from(self.tell_virtual())
+  }
+
+  /// Same as `seek_virtual`, taking a noodles `VirtualPosition`, like those
+  /// of the chunks of a noodles-csi index. Only with the `noodles` feature.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use noodles_bgzf::VirtualPosition;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.seek_virtual_position(VirtualPosition::from(52)).unwrap();
+  /// let mut content = vec![0; 8];
+  /// reader.read_to(&mut content).unwrap();
+  /// assert_eq!(b" I think", &content[..]);
+  /// ```
+  #[cfg(feature = "noodles")]
+  pub fn seek_virtual_position(
+    &self,
+    position: noodles_bgzf::VirtualPosition,
+  ) -> Result<(), BgzfError> {
+    self.seek_virtual(u64::from(position))
+  }
+
+  /// Returns the virtual offset of the uncompressed offset `uoffset`, which
+  /// can be the total uncompressed length for the end of the data, without
+  /// moving the position (see `tell_virtual`)
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(279, reader.uoffset_to_voffset(279).unwrap());
+  /// assert!(reader.uoffset_to_voffset(281).is_err());
+  /// ```
+  pub fn uoffset_to_voffset(&self, uoffset: u64) -> Result<u64, BgzfError> {
+    let voffset = self.virtual_offset(uoffset);
+    if uoffset > self.indexed_length.get() {
+      return Err(BgzfError::OutOfRange {
+        pos: uoffset,
+        len: self.indexed_length.get(),
+      });
+    }
+    Ok(voffset)
+  }
+
+  /// Returns the uncompressed offset of the virtual offset `voffset`, with the
+  /// same checks as `seek_virtual` but without moving the position
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(279, reader.voffset_to_uoffset(279).unwrap());
+  /// assert!(reader.voffset_to_uoffset(281).is_err());
+  /// ```
+  pub fn voffset_to_uoffset(&self, voffset: u64) -> Result<u64, BgzfError> {
+    let compressed_offset = voffset >> 16;
+    let within_block = voffset & 0xffff;
+    self.index_through(compressed_offset);
+
+    let block = {
+      let blocks = shared_state::read(&self.blocks);
+      blocks
+        .binary_search_by_key(&compressed_offset, |block| block.block_offset)
+        .ok()
+        .map(|index| blocks[index])
+    };
+    match block {
+      Some(block) => {
+        let input_length = block.input_length;
+        if within_block > u64::from(input_length) {
+          return Err(BgzfError::OutOfRange {
+            pos: within_block,
+            len: u64::from(input_length),
+          });
+        }
+        Ok(block.input_offset + within_block)
+      }
+      //the EOF marker is an empty block, htslib and noodles reach the end
+      //of the data at its start or right after it
+      None
+        if within_block == 0
+          && (compressed_offset == self.data_end()
+            || compressed_offset == self.compressed_length_with_eof()?) =>
+      {
+        Ok(self.indexed_length.get())
+      }
+      None => Err(BgzfError::NoBlockAt {
+        offset: compressed_offset,
+      }),
+    }
+  }
+
+  /// Whether `seek_virtual` accepts `voffset`: a block starts at its
+  /// compressed offset and the offset within the block is at most the
+  /// block's uncompressed length, as htslib reads them. An offset right after
+  /// the last byte of a block, which htslib and tabix indexes give for lines
+  /// ending a block, is the start of the next block, and the offsets of the
+  /// EOF marker and right after it are the end of the data.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert!(reader.is_valid_voffset(280));
+  /// assert!(!reader.is_valid_voffset(281));
+  /// assert!(!reader.is_valid_voffset(1 << 16));
+  /// ```
+  pub fn is_valid_voffset(&self, voffset: u64) -> bool {
+    self.voffset_to_uoffset(voffset).is_ok()
+  }
+
+  /// Returns the valid virtual offset (see `is_valid_voffset`) closest to
+  /// `voffset` going forward: `voffset` itself when valid, the end of its
+  /// block when the offset within the block is past its uncompressed length,
+  /// the start of the next block when no block starts at its compressed
+  /// offset, and the offset right after the last block past the end of the
+  /// data
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(33, reader.clamp_voffset(33));
+  /// assert_eq!(280, reader.clamp_voffset(1000));
+  /// //a single block of 211 bytes holds the data
+  /// assert_eq!(211 << 16, reader.clamp_voffset(5 << 16));
+  /// ```
+  pub fn clamp_voffset(&self, voffset: u64) -> u64 {
+    if self.is_valid_voffset(voffset) {
+      return voffset;
+    }
+    let compressed_offset = voffset >> 16;
+    self.index_through(compressed_offset);
+    //the block after the offset too, for offsets within a block
+    if let Some(next_block_offset) = self.next_block_offset.get() {
+      self.index_through(next_block_offset);
+    }
+    let blocks = shared_state::read(&self.blocks);
+    let next = blocks.partition_point(|block| block.block_offset < compressed_offset);
+    match blocks.get(next) {
+      Some(block) if block.block_offset == compressed_offset => {
+        compressed_offset << 16 | min(voffset & 0xffff, u64::from(block.input_length))
+      }
+      Some(block) => block.block_offset << 16,
+      None => {
+        blocks
+          .last()
+          .map_or(0, |block| block.block_offset + u64::from(block.block_size))
+          << 16
+      }
+    }
+  }
+
+  /// Indexes lazily until every block starting at or before
+  /// `compressed_offset` is, or can not be, indexed
+  fn index_through(&self, compressed_offset: u64) {
+    while self
+      .next_block_offset
+      .get()
+      .is_some_and(|next_block_offset| next_block_offset <= compressed_offset)
+    {
+      self.index_up_to(self.indexed_length.get() + 1);
+    }
+  }
+
+  /// Offset right after the last block in the file, once it is fully indexed
+  fn data_end(&self) -> u64 {
+    self
+      .blocks()
+      .last()
+      .map_or(0, |block| block.block_offset + u64::from(block.block_size))
+  }
+
+  /// Returns the data blocks of the file in increasing offset order, without
+  /// reading or decompressing anything
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let block = reader.block_offsets().next().unwrap();
+  /// assert_eq!(0, block.compressed_offset);
+  /// assert_eq!(280, block.uncompressed_length);
+  /// ```
+  pub fn block_offsets(&self) -> impl Iterator<Item = BlockInfo> + '_ {
+    self
+      .blocks()
+      .iter()
+      .map(BgzfBlock::info)
+      .collect::<Vec<_>>()
+      .into_iter()
+  }
+
+  /// Returns the number of data blocks in the file, the EOF marker not
+  /// included. When opened lazily the rest of the file is indexed first.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(1, reader.num_blocks());
+  /// ```
+  pub fn num_blocks(&self) -> usize {
+    self.blocks().len()
+  }
+
+  /// Returns the total size of the data blocks in the file, their gzip
+  /// headers and footers included and the EOF marker not included
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(211, reader.compressed_length());
+  /// ```
+  pub fn compressed_length(&self) -> u64 {
+    self
+      .blocks()
+      .iter()
+      .map(|block| u64::from(block.block_size))
+      .sum()
+  }
+
+  /// Same as `compressed_length` plus the 28 bytes of the EOF marker when
+  /// one follows the last data block
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(239, reader.compressed_length_with_eof().unwrap());
+  /// ```
+  pub fn compressed_length_with_eof(&self) -> Result<u64, BgzfError> {
+    let data_end = self.data_end();
+    let mut marker = [0u8; 28];
+    let has_marker = match self.bgzf_file.read_exact_at(data_end, &mut marker) {
+      Ok(()) => marker == EOF_MARKER,
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+      Err(e) => return Err(e.into()),
+    };
+    Ok(data_end + if has_marker { marker.len() as u64 } else { 0 })
+  }
+
+  /// Number of uncompressed bytes per byte of data blocks in the file, the
+  /// EOF marker not counted, 0 when there is no data block
+  pub fn compression_ratio(&self) -> f64 {
+    let compressed_length = self.compressed_length();
+    if compressed_length == 0 {
+      return 0.0;
+    }
+    self.total_uncompressed_length() as f64 / compressed_length as f64
+  }
+
+  /// Returns the smallest, largest and mean sizes of the data blocks, in the
+  /// file and uncompressed
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let path = write_temp("block_size_summary_doc", &make_bgzf(&[b'a'; 250], 100)).unwrap();
+  /// let summary = BgzfReader::new(path.clone()).unwrap().block_size_summary();
+  /// assert_eq!(3, summary.blocks);
+  /// assert_eq!(50, summary.min_uncompressed_length);
+  /// assert_eq!(100, summary.max_uncompressed_length);
+  /// std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn block_size_summary(&self) -> BlockSizeSummary {
+    let indexed_blocks = self.blocks();
+    let blocks = indexed_blocks.len() as u64;
+    if blocks == 0 {
+      return BlockSizeSummary::default();
+    }
+    let block_sizes = || indexed_blocks.iter().map(|block| block.block_size);
+    let uncompressed_lengths = || indexed_blocks.iter().map(|block| block.input_length);
+    BlockSizeSummary {
+      blocks,
+      min_block_size: block_sizes().min().unwrap_or(0),
+      max_block_size: block_sizes().max().unwrap_or(0),
+      mean_block_size: block_sizes().map(u64::from).sum::<u64>() as f64 / blocks as f64,
+      min_uncompressed_length: uncompressed_lengths().min().unwrap_or(0),
+      max_uncompressed_length: uncompressed_lengths().max().unwrap_or(0),
+      mean_uncompressed_length: uncompressed_lengths().map(u64::from).sum::<u64>() as f64
+        / blocks as f64,
+    }
+  }
+
+  /// Reports how well the blocks are compressed, with histograms of their
+  /// sizes and ratios, the blocks under the thresholds of `options` and the
+  /// blocks compressed worst, to find files written with tiny blocks or
+  /// holding incompressible data. Only the block sizes are used, nothing is
+  /// decompressed. When opened lazily the rest of the file is indexed first.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, ReportOptions};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let report = reader.compression_report(ReportOptions::default());
+  /// assert_eq!(1, report.blocks);
+  /// assert_eq!(1, report.small_blocks);
+  /// assert_eq!(0, report.worst_blocks[0].0);
+  /// ```
+  pub fn compression_report(&self, options: ReportOptions) -> CompressionReport {
+    report::compression_report(&self.blocks(), options)
+  }
+
+  /// Splits the data into up to `n` ranges of uncompressed offsets, as
+  /// `(start, end)` with `end` excluded, for workers to process in parallel
+  /// with a reader each. The ranges start and end at block boundaries, follow
+  /// each other from 0 to the total uncompressed length and are as even in
+  /// length as the blocks allow. A file with fewer than `n` blocks gets a
+  /// range per block, an empty file or `n` of 0 no range at all.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let path = write_temp("partition_doc", &make_bgzf(&[b'a'; 1000], 100)).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// assert_eq!(vec![(0, 300), (300, 500), (500, 800), (800, 1000)], reader.partition(4));
+  /// std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn partition(&self, n: usize) -> Vec<(u64, u64)> {
+    let blocks = self.blocks();
+    let parts = min(n, blocks.len());
+    if parts == 0 {
+      return Vec::new();
+    }
+    let total_length = self.indexed_length.get();
+    //block starts but the first one, where ranges can begin
+    let starts: Vec<u64> = blocks[1..].iter().map(|block| block.input_offset).collect();
+    let mut bounds = vec![0];
+    let mut next_start = 0;
+    for part in 1..parts {
+      //every range left needs a block of its own
+      let last_start = starts.len() - (parts - part);
+      let target = (u128::from(total_length) * part as u128 / parts as u128) as u64;
+      let after =
+        next_start + starts[next_start..=last_start].partition_point(|&start| start < target);
+      let cut = match after {
+        after if after > last_start => last_start,
+        after if after > next_start && target - starts[after - 1] < starts[after] - target => {
+          after - 1
+        }
+        after => after,
+      };
+      bounds.push(starts[cut]);
+      next_start = cut + 1;
+    }
+    bounds.push(total_length);
+    bounds.windows(2).map(|pair| (pair[0], pair[1])).collect()
+  }
+
+  /// Returns the block at `index` in file order, None past the last block
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(280, reader.block_metadata(0).unwrap().uncompressed_length);
+  /// assert_eq!(None, reader.block_metadata(1));
+  /// ```
+  pub fn block_metadata(&self, index: usize) -> Option<BlockInfo> {
+    while shared_state::read(&self.blocks).len() <= index && self.next_block_offset.get().is_some()
+    {
+      self.index_up_to(self.indexed_length.get() + 1);
+    }
+    shared_state::read(&self.blocks)
+      .get(index)
+      .map(BgzfBlock::info)
+  }
+
+  /// Returns the compressed bytes of the block at `block_index` in file
+  /// order, header, deflate data and footer as they are in the file, to
+  /// pass on without decompressing them, such as to serve bgzf slices to
+  /// htslib clients. The bytes are checked to still be the block the index
+  /// describes, see `raw_block_range`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let block = reader.raw_block(0).unwrap();
+  /// assert_eq!(reader.block_metadata(0).unwrap().block_size as usize, block.len());
+  /// assert_eq!(&[31, 139, 8, 4], &block[..4]);
+  /// ```
+  pub fn raw_block(&self, block_index: usize) -> Result<Vec<u8>, BgzfError> {
+    let mut raw = Vec::new();
+    self.raw_block_range(block_index, block_index, &mut raw)?;
+    Ok(raw)
+  }
+
+  /// Writes the compressed bytes of the blocks `first` to `last` included,
+  /// in file order, to `out` as they are in the file, and returns the
+  /// number of bytes written. Each block is read from the offset and with
+  /// the size the index gives it, and checked to have the BGZF header, size
+  /// and uncompressed length of the index, failing with `IndexMismatch` when
+  /// the file changed. Empty blocks between them, which are not indexed, are
+  /// left out. Nothing is written when `first` is past `last`, and a `last`
+  /// past the last block fails with `OutOfRange` before anything is written.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(b"0123456789", 4)).unwrap();
+  /// let mut slice = Vec::new();
+  /// reader.raw_block_range(1, 2, &mut slice).unwrap();
+  /// let slice = BgzfReader::from_bytes(slice).unwrap();
+  /// assert_eq!(b"456789".to_vec(), slice.decompress_all_parallel(1).unwrap());
+  /// ```
+  pub fn raw_block_range<W: io::Write>(
+    &self,
+    first: usize,
+    last: usize,
+    out: &mut W,
+  ) -> Result<u64, BgzfError> {
+    if first > last {
+      return Ok(0);
+    }
+    if self.block_metadata(last).is_none() {
+      return Err(BgzfError::OutOfRange {
+        pos: last as u64,
+        len: self.blocks().len() as u64,
+      });
+    }
+    let blocks = shared_state::read(&self.blocks);
+    let mut raw = Vec::new();
+    let mut written = 0;
+    for block in &blocks[first..=last] {
+      raw.resize(block.block_size as usize, 0);
+      self
+        .block_source()
+        .read_exact_at(block.block_offset, &mut raw)
+        .map_err(|source| BgzfError::ReadFailed {
+          block: BlockContext::new(block, BlockOperation::Reading),
+          source,
+        })
+        .and_then(|_| deflate_data(&raw, block).map(|_deflate_data| ()))
+        .map_err(|error| locate_block_in(&blocks, error))?;
+      out.write_all(&raw)?;
+      written += raw.len() as u64;
+    }
+    Ok(written)
+  }
+
+  /// Returns the block holding the uncompressed offset `uoffset` and its
+  /// index in file order, None when `uoffset` is not before the end of the
+  /// data. An offset at a block boundary is in the block starting there.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let (index, block) = reader.block_containing(279).unwrap();
+  /// assert_eq!(0, index);
+  /// assert_eq!(0, block.compressed_offset);
+  /// assert_eq!(None, reader.block_containing(280));
+  /// ```
+  pub fn block_containing(&self, uoffset: u64) -> Option<(usize, BlockInfo)> {
+    self.index_up_to(uoffset.saturating_add(1));
+    let blocks = shared_state::read(&self.blocks);
+    let index = floor_block(&blocks, uoffset)?;
+    let block = blocks[index];
+    if uoffset - block.input_offset >= u64::from(block.input_length) {
+      return None;
+    }
+    Some((index, block.info()))
+  }
+
+  /// Returns the data block whose bytes in the file, from its header to its
+  /// footer, hold the compressed offset `coffset`, like the block damaged by
+  /// a bad sector. None when `coffset` is in no data block: in the EOF
+  /// marker or an empty block, between blocks or past the last one. When
+  /// opened lazily the file is indexed up to `coffset`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(&[7; 300], 100)).unwrap();
+  /// let second = reader.block_metadata(1).unwrap();
+  /// let block = reader
+  ///   .block_at_compressed_offset(second.compressed_offset + 20)
+  ///   .unwrap();
+  /// assert_eq!(100, block.uncompressed_offset);
+  /// //the EOF marker
+  /// assert_eq!(None, reader.block_at_compressed_offset(reader.compressed_length()));
+  /// ```
+  pub fn block_at_compressed_offset(&self, coffset: u64) -> Option<BlockInfo> {
+    let indexed_end = || {
+      shared_state::read(&self.blocks)
+        .last()
+        .map_or(0, |block| block.block_offset + u64::from(block.block_size))
+    };
+    while indexed_end() <= coffset && self.next_block_offset.get().is_some() {
+      self.index_up_to(self.indexed_length.get() + 1);
+    }
+    let blocks = shared_state::read(&self.blocks);
+    let index = blocks
+      .partition_point(|block| block.block_offset <= coffset)
+      .checked_sub(1)?;
+    let block = blocks[index];
+    if coffset - block.block_offset >= u64::from(block.block_size) {
+      return None;
+    }
+    Some(block.info())
+  }
+
+  /// Returns the (offset, length) spans of the file holding the blocks with
+  /// any of the uncompressed range `ustart..uend`, blocks next to each other
+  /// in the file making a single span, see `BgzfIndex::compressed_ranges_for`.
+  /// When opened lazily the file is indexed up to `uend`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!(vec![(0, 211)], reader.compressed_ranges_for(10, 20));
+  /// ```
+  pub fn compressed_ranges_for(&self, ustart: u64, uend: u64) -> Vec<(u64, u64)> {
+    if ustart >= uend {
+      return Vec::new();
+    }
+    self.index_up_to(uend);
+    let blocks = shared_state::read(&self.blocks);
+    let first_block =
+      blocks.partition_point(|block| block.input_offset + u64::from(block.input_length) <= ustart);
+    index::coalesce_blocks(
+      blocks[first_block..]
+        .iter()
+        .take_while(|block| block.input_offset < uend)
+        .map(BgzfBlock::info),
+    )
+  }
+
+  /// Saves the block index to `gzi_path` as a .gzi file, the format written
+  /// by `bgzip -i` and `bgzip -r`: the number of entries as a little-endian
+  /// u64 followed by a (compressed offset, uncompressed offset) u64 pair for
+  /// every block but the first one, which always starts at (0, 0)
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let gzi_path = std::env::temp_dir().join("bgzf_test_doc.gzi");
+  /// reader
+  ///   .save_gzi(gzi_path.to_string_lossy().into_owned())
+  ///   .unwrap();
+  /// //a single block leaves no entry
+  /// assert_eq!(vec![0; 8], std::fs::read(&gzi_path).unwrap());
+  /// std::fs::remove_file(gzi_path).unwrap();
+  /// ```
+  pub fn save_gzi(&self, gzi_path: String) -> io::Result<()> {
+    self.write_gzi(File::create(gzi_path)?)
+  }
+
+  /// Writes the .gzi index described in `save_gzi` to any `std::io::Write`
+  /// sink
+  pub fn write_gzi<W: io::Write>(&self, out: W) -> io::Result<()> {
+    let block_offsets = self
+      .block_offsets()
+      .map(|block| (block.compressed_offset, block.uncompressed_offset))
+      .collect::<Vec<_>>();
+    gzi::write_gzi(&block_offsets, out)
+  }
+
+  /// Writes the layout of the file as JSON, for bug reports: for every data
+  /// block its compressed offset and size, the offset and length of its
+  /// compressed data within the file and its uncompressed offset and length,
+  /// then the totals, whether the file ends with the EOF marker and where
+  /// its blocks stop being readable if they do (see `truncation`). The
+  /// output is meant for people and may change between versions. Blocks are
+  /// written as they are gone through, one per line, so the output of a huge
+  /// file is never held in memory. When opened lazily the rest of the file is
+  /// indexed first.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let json = reader.to_debug_json().unwrap();
+  /// assert!(json.contains("\"block_count\": 1,"));
+  /// assert!(json.contains("\"has_eof_marker\": true,"));
+  /// ```
+  pub fn write_debug_json<W: io::Write>(&self, out: W) -> io::Result<()> {
+    let truncation = self.truncation();
+    debug_json::write_debug_json(
+      &self.bgzf_file,
+      &self.blocks(),
+      self.has_eof_marker,
+      truncation,
+      out,
+    )
+  }
+
+  /// Returns the JSON written by `write_debug_json`, for files small enough
+  /// to hold it in memory
+  pub fn to_debug_json(&self) -> io::Result<String> {
+    let mut json = Vec::new();
+    self.write_debug_json(&mut json)?;
+    Ok(String::from_utf8(json).expect("Debug JSON is ASCII"))
+  }
+
+  /// Reads the gzip header of `block`, one returned by `block_offsets`
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let block = reader.block_offsets().next().unwrap();
+  /// let header = reader.block_header(&block).unwrap();
+  /// assert_eq!(*b"BC", header.extra_subfields[0].id);
+  /// ```
+  pub fn block_header(&self, block: &BlockInfo) -> Result<BlockHeader, BgzfError> {
+    let read_failed = |source| {
+      self.locate_block(BgzfError::ReadFailed {
+        block: BlockContext {
+          index: 0,
+          compressed_offset: block.compressed_offset,
+          uncompressed_offset: block.uncompressed_offset,
+          operation: BlockOperation::Reading,
+        },
+        source,
+      })
+    };
+    let mut buf = [0u8; 12];
+    self
+      .bgzf_file
+      .read_exact_at(block.compressed_offset, &mut buf)
+      .map_err(read_failed)?;
+    let xlen = u16::from_le_bytes([buf[10], buf[11]]);
+    let mut extra = vec![0u8; usize::from(xlen)];
+    self
+      .bgzf_file
+      .read_exact_at(block.compressed_offset + buf.len() as u64, &mut extra)
+      .map_err(read_failed)?;
+
+    let mut extra_subfields = Vec::new();
+    let mut rest = &extra[..];
+    while !rest.is_empty() {
+      if rest.len() < 4 {
+        return Err(BgzfError::BadSubfield {
+          offset: block.compressed_offset,
+        });
+      }
+      let length = usize::from(u16::from_le_bytes([rest[2], rest[3]]));
+      if rest.len() < 4 + length {
+        return Err(BgzfError::BadSubfield {
+          offset: block.compressed_offset,
+        });
+      }
+      extra_subfields.push(ExtraSubfield {
+        id: [rest[0], rest[1]],
+        data: rest[4..4 + length].to_vec(),
+      });
+      rest = &rest[4 + length..];
+    }
+    Ok(BlockHeader {
+      mtime: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+      xfl: buf[8],
+      os: buf[9],
+      extra_subfields,
+    })
+  }
+
+  /// This method calculates total uncompressed length, when opened lazily
+  /// it indexes the rest of the file to know it
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.index_up_to(u64::MAX);
+    self.indexed_length.get()
+  }
+
+  /// Decompresses the whole file with `threads` threads, each decompressing
+  /// a run of blocks straight into its part of the result, which is the data
+  /// reading the whole file would give. The position is left as it is. A
+  /// truncated file fails like a read reaching its end unless it was opened
+  /// with `allow_truncated`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let data = reader.decompress_all_parallel(4).unwrap();
+  /// assert_eq!(280, data.len());
+  /// assert!(data.starts_with(b"This is just a bgzf test"));
+  /// ```
+  pub fn decompress_all_parallel(&self, threads: usize) -> Result<Vec<u8>, BgzfError> {
+    self.index_up_to(u64::MAX);
+    self.check_truncation()?;
+    let blocks = self.blocks();
+    if self.indexed_length.get() > self.limits.max_allocation {
+      return Err(BgzfError::LimitExceeded {
+        limit: ResourceLimit::Allocation,
+        max: self.limits.max_allocation,
+      });
+    }
+    let mut data = vec![0u8; buffer_length(self.indexed_length.get())?];
+    parallel_decompress::decompress_all(
+      self.block_source(),
+      &self.decompressors,
+      self.verify_crc,
+      &blocks,
+      &self.executor,
+      threads,
+      &mut data,
+    )
+    .map_err(|error| locate_block_in(&blocks, error))?;
+    Ok(data)
+  }
+
+  /// Decompresses the whole file with `threads` threads and writes the data
+  /// to `out` in order, returning the number of bytes written. At most
+  /// `in_flight` blocks are decompressed ahead of the one being written, which
+  /// bounds the memory used to `in_flight` times 64 KiB. On an error the data
+  /// of the blocks before the failing one has been written. The position is
+  /// left as it is.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut out = Vec::new();
+  /// assert_eq!(280, reader.decompress_to_writer_parallel(4, 16, &mut out).unwrap());
+  /// assert!(out.starts_with(b"This is just a bgzf test"));
+  /// ```
+  pub fn decompress_to_writer_parallel<W: io::Write>(
+    &self,
+    threads: usize,
+    in_flight: usize,
+    out: W,
+  ) -> Result<u64, BgzfError> {
+    self.index_up_to(u64::MAX);
+    self.check_truncation()?;
+    let blocks = self.blocks();
+    parallel_decompress::decompress_to_writer(
+      self.block_source(),
+      &self.decompressors,
+      self.verify_crc,
+      &blocks,
+      &self.executor,
+      threads,
+      in_flight,
+      out,
+    )
+    .map_err(|error| locate_block_in(&blocks, error))
+  }
+
+  /// Hashes the uncompressed data with `D`, a RustCrypto hash such as
+  /// `sha2::Sha256`, as recorded for archived files, without holding more
+  /// than a few blocks of it: the blocks are decompressed in parallel and
+  /// fed to the hasher in order. Their CRC32 is checked when opened with
+  /// `verify_crc`, see `digest_verifying_crc` to check it anyway. The
+  /// position and the caches are left as they are.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use sha2::{Digest, Sha256};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let data = reader.decompress_all_parallel(1).unwrap();
+  /// assert_eq!(Sha256::digest(&data), reader.digest::<Sha256>().unwrap());
+  /// ```
+  #[cfg(feature = "digest")]
+  pub fn digest<D: digest::Digest>(&self) -> Result<digest::Output<D>, BgzfError> {
+    self.digest_checking_crc::<D>(self.verify_crc)
+  }
+
+  /// Same as `digest`, checking the CRC32 of every block in the same pass
+  /// whether the reader was opened with `verify_crc` or not, the data being
+  /// at hand. A mismatch fails with `ChecksumMismatch`.
+  #[cfg(feature = "digest")]
+  pub fn digest_verifying_crc<D: digest::Digest>(&self) -> Result<digest::Output<D>, BgzfError> {
+    self.digest_checking_crc::<D>(true)
+  }
+
+  /// The MD5 of the uncompressed data in lowercase hexadecimal, as `md5sum`
+  /// prints it, see `digest`
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// assert_eq!("4f21e461da8577fe57cc534b8d22840c", reader.md5_hex().unwrap());
+  /// ```
+  #[cfg(feature = "digest")]
+  pub fn md5_hex(&self) -> Result<String, BgzfError> {
+    Ok(content_digest::to_hex(&self.digest::<md5::Md5>()?))
+  }
+
+  /// The SHA-256 of the uncompressed data in lowercase hexadecimal, as
+  /// `sha256sum` prints it, see `digest`
+  #[cfg(feature = "digest")]
+  pub fn sha256_hex(&self) -> Result<String, BgzfError> {
+    Ok(content_digest::to_hex(&self.digest::<sha2::Sha256>()?))
+  }
+
+  #[cfg(feature = "digest")]
+  fn digest_checking_crc<D: digest::Digest>(
+    &self,
+    verify_crc: bool,
+  ) -> Result<digest::Output<D>, BgzfError> {
+    self.index_up_to(u64::MAX);
+    self.check_truncation()?;
+    let blocks = self.blocks();
+    let threads = content_digest::threads();
+    let mut hashing = content_digest::HashingWriter(D::new());
+    parallel_decompress::decompress_to_writer(
+      self.block_source(),
+      &self.decompressors,
+      verify_crc,
+      &blocks,
+      &self.executor,
+      threads,
+      2 * threads,
+      &mut hashing,
+    )
+    .map_err(|error| locate_block_in(&blocks, error))?;
+    Ok(hashing.0.finalize())
+  }
+
+  /// Decompresses every block once with `threads` threads and calls `f`
+  /// with the uncompressed offset and the data of each, for scans of the
+  /// whole file like counting records. `f` runs on the thread that
+  /// decompressed the block, only once its data is whole, and the blocks
+  /// come in no particular order, several at a time. On an error the threads
+  /// start no more blocks and the error of the first failing block in the
+  /// file is returned; a panic of `f` likewise stops them and is resumed
+  /// here. The position is left as it is.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::sync::atomic::{AtomicU64, Ordering};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let spaces = AtomicU64::new(0);
+  /// reader
+  ///   .for_each_block_parallel(4, |_offset, data| {
+  ///     let count = data.iter().filter(|&&byte| byte == b' ').count();
+  ///     spaces.fetch_add(count as u64, Ordering::Relaxed);
+  ///   })
+  ///   .unwrap();
+  /// assert_eq!(56, spaces.into_inner());
+  /// ```
+  pub fn for_each_block_parallel(
+    &self,
+    threads: usize,
+    f: impl Fn(u64, &[u8]) + Sync,
+  ) -> Result<(), BgzfError> {
+    self.index_up_to(u64::MAX);
+    self.check_truncation()?;
+    let blocks = self.blocks();
+    parallel_decompress::for_each_block(
+      self.block_source(),
+      &self.decompressors,
+      self.verify_crc,
+      &blocks,
+      &self.executor,
+      threads,
+      &f,
+    )
+    .map_err(|error| locate_block_in(&blocks, error))
+  }
+
+  /// Returns the lines of the data as a rayon `ParallelIterator`, without
+  /// their line ending, for scans of large text files like counting or
+  /// filtering lines. The blocks are split into runs of 64, whose lines
+  /// rayon workers read at the same time, each line once whichever blocks it
+  /// spans: a worker skips the end of the line its run starts within and
+  /// reads past the end of its run for the last line starting in it. Lines
+  /// are those of `BufRead::lines`, see `LineIndex`. They come to
+  /// `for_each` and the like in no particular order, while `collect` keeps
+  /// them in file order. The blocks are decompressed aside from the caches and
+  /// the position. A block failing to decompress ends the lines of its
+  /// worker with the error, and a truncated file yields its error alone
+  /// unless opened with `allow_truncated`. The workers are those of the pool
+  /// the iterator is driven from, the builder's `thread_pool` being for the
+  /// work the reader runs itself.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use rayon::prelude::*;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(b"chr1\t5\nchr2\t7\nchr1\t9\n", 8)).unwrap();
+  /// let chr1 = reader
+  ///   .par_lines()
+  ///   .filter(|line| match line {
+  ///     Ok(line) => line.starts_with(b"chr1\t"),
+  ///     Err(_) => true,
+  ///   })
+  ///   .collect::<Result<Vec<_>, _>>()
+  ///   .unwrap();
+  /// assert_eq!(2, chr1.len());
+  /// ```
+  #[cfg(feature = "rayon")]
+  pub fn par_lines(
+    &self,
+  ) -> impl rayon::iter::ParallelIterator<Item = Result<Vec<u8>, BgzfError>> + '_ {
+    use rayon::prelude::*;
+
+    self.index_up_to(u64::MAX);
+    let truncation = self.check_truncation().err();
+    let blocks = match truncation {
+      Some(_) => Arc::new(Vec::new()),
+      None => Arc::new(self.blocks().clone()),
+    };
+    let partitions = par_lines::partitions(&blocks, par_lines::PARTITION_BLOCKS);
+    truncation
+      .map(Err)
+      .into_par_iter()
+      .chain(partitions.into_par_iter().flat_map_iter(move |partition| {
+        par_lines::PartitionLines::new(self, blocks.clone(), partition)
+      }))
+  }
+
+  /// Scans the data once and returns the index of its lines, with the
+  /// offset of every 1000th line (`DEFAULT_LINE_SPACING`), for `seek_line`
+  /// and `LineIndex::line_count`. Lines are those of `BufRead::lines`, see
+  /// `LineIndex`. The position and the caches are left as they are.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(b"one\r\ntwo\r\nthree", 5)).unwrap();
+  /// let index = reader.build_line_index().unwrap();
+  /// assert_eq!(3, index.line_count());
+  /// ```
+  pub fn build_line_index(&self) -> Result<LineIndex, BgzfError> {
+    self.build_line_index_with_spacing(DEFAULT_LINE_SPACING)
+  }
+
+  /// Same as `build_line_index` with the offset of every `spacing`-th line,
+  /// `spacing` being at least 1. A smaller spacing makes a larger index and
+  /// `seek_line` scan fewer lines.
+  pub fn build_line_index_with_spacing(&self, spacing: u64) -> Result<LineIndex, BgzfError> {
+    let mut scan = line_index::LineScan::new(spacing);
+    self.decompress_to_writer_parallel(1, 1, &mut scan)?;
+    Ok(scan.finish())
+  }
+
+  /// Seeks to the start of line `line`, counted from 0, with the `index`
+  /// built by `build_line_index`: from the checkpoint before the line, the
+  /// data is read up to at most `index.spacing()` lines further. Fails with
+  /// `LineIndexError::LineOutOfRange` past the last line, and with the other
+  /// `LineIndexError`s when the index was built from other data, leaving the
+  /// position as it was.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::io::BufRead;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(b"one\r\ntwo\r\nthree", 5)).unwrap();
+  /// let index = reader.build_line_index_with_spacing(2).unwrap();
+  /// reader.seek_line(1, &index).unwrap();
+  /// let mut line = String::new();
+  /// std::io::BufReader::new(&reader).read_line(&mut line).unwrap();
+  /// assert_eq!("two\r\n", line);
+  /// assert!(reader.seek_line(3, &index).is_err());
+  /// ```
+  pub fn seek_line(&self, line: u64, index: &LineIndex) -> Result<(), BgzfError> {
+    let data_length = self.total_uncompressed_length();
+    if index.total_uncompressed_length() != data_length {
+      return Err(
+        LineIndexError::LengthMismatch {
+          index_length: index.total_uncompressed_length(),
+          data_length,
+        }
+        .into(),
+      );
+    }
+    let (checkpoint, mut newlines) = index.checkpoint_before(line)?;
+    let not_a_line_start = LineIndexError::NotALineStart { offset: checkpoint };
+    //the byte before the checkpoint is read too, to check it is a newline
+    let mut pos = checkpoint.saturating_sub(1);
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+      let length = self.read_at(pos, &mut chunk)?;
+      let mut data = &chunk[..length];
+      if pos < checkpoint {
+        if data.first() != Some(&b'\n') {
+          return Err(not_a_line_start.into());
+        }
+        data = &data[1..];
+        pos += 1;
+      }
+      if newlines == 0 {
+        break;
+      }
+      if data.is_empty() {
+        return Err(not_a_line_start.into());
+      }
+      for (i, _) in data.iter().enumerate().filter(|&(_, &byte)| byte == b'\n') {
+        newlines -= 1;
+        if newlines == 0 {
+          self.seek(pos + i as u64 + 1);
+          return Ok(());
+        }
+      }
+      pos += data.len() as u64;
+    }
+    self.seek(pos);
+    Ok(())
+  }
+
+  /// Decompresses the blocks numbered `blocks`, in file order like with
+  /// `block_metadata`, and checks that the CRC32 and the length of their
+  /// data match their footer, without moving the position or caching them.
+  /// The CRC32 is computed by the deflate backend, with libdeflate's
+  /// vectorized implementation by default or crc32fast with `rust-backend`,
+  /// so checking costs little next to decompressing. Fails at the first
+  /// block that does not match, or when `blocks` goes past the last block.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_bgzf, make_with_bad_crc, write_temp};
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let path = write_temp("verify_blocks_doc", &make_bgzf(b"bgzf data", 4)).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// assert!(reader.verify_blocks(0..3).is_ok());
+  /// assert!(reader.verify_blocks(0..4).is_err());
+  ///
+  /// std::fs::write(&path, make_with_bad_crc(b"bgzf data", 4, 1)).unwrap();
+  /// let reader = BgzfReader::new(path.clone()).unwrap();
+  /// assert!(reader.verify_blocks(0..1).is_ok());
+  /// assert!(reader.verify_blocks(1..3).is_err());
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn verify_blocks(&self, blocks: Range<usize>) -> Result<(), BgzfError> {
+    let all_blocks = self.blocks();
+    let blocks = all_blocks
+      .get(blocks.clone())
+      .ok_or(BgzfError::OutOfRange {
+        pos: blocks.end as u64,
+        len: all_blocks.len() as u64,
+      })?;
+    let mut un_compressor = self.decompressors.acquire();
+    let (mut block_data, mut uncompressed) = (Vec::new(), Vec::new());
+    for block in blocks {
+      uncompressed.resize(block.input_length as usize, 0);
+      decompress_block_at(
+        self.block_source(),
+        &mut un_compressor,
+        block,
+        true,
+        &mut block_data,
+        &mut uncompressed,
+      )
+      .map_err(|error| locate_block_in(&all_blocks, error))?;
+    }
+    Ok(())
+  }
+
+  /// Tells what the data of the file is, BAM, VCF, FASTA or something else,
+  /// from the signature it starts with, decompressing only its first block,
+  /// or the first few when they are shorter than 512 bytes. The blocks are
+  /// decompressed aside, leaving the position and the caches as they were.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_vcf;
+  /// use bgzf_rust_reader::{BgzfReader, PayloadKind};
+  ///
+  /// let reader = BgzfReader::from_bytes(make_vcf(10, 65280)).unwrap();
+  /// assert_eq!(PayloadKind::Vcf, reader.payload_kind().unwrap());
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// match reader.payload_kind().unwrap() {
+  ///   PayloadKind::Unknown(head) => assert!(head.starts_with(b"This is just")),
+  ///   kind => panic!("{:?}", kind),
+  /// }
+  /// ```
+  pub fn payload_kind(&self) -> Result<PayloadKind, BgzfError> {
+    if let Some(loaded) = self.loaded.get() {
+      return Ok(PayloadKind::of(loaded));
+    }
+    self.index_up_to(payload::HEAD_LENGTH as u64);
+    if self.indexed_length.get() < payload::HEAD_LENGTH as u64 {
+      self.check_truncation()?;
+    }
+    let mut head = Vec::new();
+    let (mut block_data, mut uncompressed) = (Vec::new(), Vec::new());
+    let blocks = shared_state::read(&self.blocks);
+    for block in blocks
+      .iter()
+      .take_while(|block| block.input_offset < payload::HEAD_LENGTH as u64)
+    {
+      uncompressed.resize(block.input_length as usize, 0);
+      decompress_block_at(
+        self.block_source(),
+        &mut self.decompressors.acquire(),
+        block,
+        self.verify_crc,
+        &mut block_data,
+        &mut uncompressed,
+      )?;
+      head.extend_from_slice(&uncompressed);
+    }
+    Ok(PayloadKind::of(&head))
+  }
+
+  /// Checks the whole file, for when it has to be known completely sound,
+  /// such as before deleting its source: every block is decompressed with
+  /// `threads` threads, and its data checked against the CRC32 and length in
+  /// its footer. Blocks chaining from the start of the file to the EOF marker
+  /// ending it, with nothing after it, is checked too. Unlike `verify_blocks`
+  /// it goes on after a block fails, and reports every failure with the
+  /// totals. Only the blocks of a file opened with an index are checked, and
+  /// a truncated file must be opened with `allow_truncated` to be checked.
+  /// Fails when a resource limit stopped indexing, the blocks past it being
+  /// unknown.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::{make_with_bad_crc, write_temp};
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let report = reader.verify(2).unwrap();
+  /// assert!(report.is_sound());
+  /// assert_eq!(280, report.uncompressed_bytes);
+  ///
+  /// let path = write_temp("verify_doc", &make_with_bad_crc(b"bgzf data", 4, 1)).unwrap();
+  /// let report = BgzfReader::new(path.clone()).unwrap().verify(2).unwrap();
+  /// assert!(!report.is_sound());
+  /// assert_eq!(1, report.failures[0].block_context().unwrap().index);
+  /// # std::fs::remove_file(path).unwrap();
+  /// ```
+  pub fn verify(&self, threads: usize) -> Result<VerifyReport, BgzfError> {
+    let blocks = self.blocks();
+    if let Some(limit) = self.limit_hit.get() {
+      return Err(BgzfError::LimitExceeded {
+        limit,
+        max: self.limits.max(limit),
+      });
+    }
+    let failures = verify::check_blocks(
+      self.block_source(),
+      &self.decompressors,
+      &blocks,
+      &self.executor,
+      threads,
+    )
+    .into_iter()
+    .map(|error| locate_block_in(&blocks, error))
+    .collect();
+    let compressed_bytes = self.bgzf_file.len()?;
+    let data_end = blocks
+      .last()
+      .map_or(0, |block| block.block_offset + u64::from(block.block_size));
+    let eof_marker_length = if self.has_eof_marker {
+      EOF_MARKER.len() as u64
+    } else {
+      0
+    };
+    Ok(VerifyReport {
+      blocks: blocks.len() as u64,
+      compressed_bytes,
+      uncompressed_bytes: self.indexed_length.get(),
+      failures,
+      has_eof_marker: self.has_eof_marker,
+      truncation: self.truncation.get(),
+      trailing_bytes: compressed_bytes.saturating_sub(data_end + eof_marker_length),
+    })
+  }
+
+  /// Decompresses the whole file into memory, in parallel, after which reads
+  /// copy from it instead of finding and decompressing blocks, and the file
+  /// is no longer read. This suits small files read at random places many
+  /// times. Fails when the uncompressed data is longer than the limit set
+  /// with `BgzfReaderBuilder::load_all_limit`, 1GiB by default, or when a
+  /// block can not be decompressed, in which case the reads go on as before.
+  /// Loading again does nothing.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// reader.load_all().unwrap();
+  /// reader.seek(5);
+  /// let mut bytes = vec![0; 7];
+  /// reader.read_to(&mut bytes).unwrap();
+  /// assert_eq!(b"is just", &bytes[..]);
+  /// ```
+  pub fn load_all(&self) -> Result<(), BgzfError> {
+    if self.loaded.get().is_some() {
+      return Ok(());
+    }
+    let length = self.total_uncompressed_length();
+    if length > self.load_all_limit {
+      return Err(BgzfError::TooLarge {
+        length,
+        limit: self.load_all_limit,
+      });
+    }
+    let data = self.decompress_all_parallel(DecompressorPool::default_max())?;
+    self.loaded.get_or_init(|| data);
+    Ok(())
+  }
+
+  /// this method reads data to the slice passed
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::str;
+  ///
+  ///  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  ///  let mut vec = vec![0; 52];
+  ///  let data_read = reader.read_to(&mut vec);
+  ///  assert_eq!(data_read.unwrap(), 52);
+  ///  assert_eq!(
+  ///    "This is just a bgzf test,lets see how it reacts. :).",
+  ///    str::from_utf8(&vec).unwrap()
+  ///  );
+  ///
+  /// ```
+  pub fn read_to(&self, b: &mut [u8]) -> Result<usize, BgzfError> {
+    self.read(b, 0, b.len())
+  }
+
+  /// Fills `b` from the current position, failing with
+  /// `BgzfError::UnexpectedEof`, which tells how many bytes were read, when
+  /// the data ends before it is full. The position is then at the end of the
+  /// data, past the bytes read into the start of `b`. An empty `b` reads
+  /// nothing.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfError, BgzfReader};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut start = [0; 4];
+  /// reader.read_exact(&mut start).unwrap();
+  /// assert_eq!(b"This", &start);
+  ///
+  /// let mut rest = vec![0; 300];
+  /// match reader.read_exact(&mut rest) {
+  ///   Err(BgzfError::UnexpectedEof { requested, read }) => {
+  ///     assert_eq!((300, 276), (requested, read));
+  ///   }
+  ///   other => panic!("{:?}", other),
+  /// }
+  /// ```
+  pub fn read_exact(&self, b: &mut [u8]) -> Result<(), BgzfError> {
+    let mut read = 0;
+    while read < b.len() {
+      match self.read_to(&mut b[read..])? {
+        0 => {
+          return Err(BgzfError::UnexpectedEof {
+            requested: b.len() as u64,
+            read: read as u64,
+          })
+        }
+        n => read += n,
+      }
+    }
+    Ok(())
+  }
+
+  /// this method reads data to the slice from offset position,
+  /// up to the len position, and returns the number of bytes read, 0 at the
+  /// end of the data.
+  ///
+  /// The bytes read go to `b[off..off + len]`:
+  /// - `off` can be up to `b.len()`, past it the read fails with
+  ///   `BgzfError::OutOfRange { pos: off + len, len: b.len() }`, whatever
+  ///   `len` is,
+  /// - `off + len` past `b.len()` fails the same way, so does any `len` but
+  ///   0 with an empty `b`,
+  /// - a `len` of 0 otherwise returns 0 without reading, like the std `Read`
+  ///   does, with `off` equal to `b.len()` or `b` empty too. The position
+  ///   does not move, even at the end of the data or in a gap of a recovered
+  ///   file.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::str;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut content = vec![0; 10];
+  /// match reader.read(&mut content, 0, 10) {
+  ///  Ok(val) => {
+  ///   assert_eq!(10, val);
+  ///  }
+  ///  Err(e) => {
+  ///    assert!(false);
+  ///  }
+  /// };
+  ///let file_content = str::from_utf8(&content).unwrap();
+  ///  assert_eq!("This is ju", file_content);
+  ///
+  /// let mut rest = vec![0; 300];
+  /// assert_eq!(270, reader.read(&mut rest, 0, 300).unwrap());
+  /// assert_eq!(0, reader.read(&mut rest, 0, 300).unwrap());
+  /// ```
+  pub fn read(&self, b: &mut [u8], off: usize, len: usize) -> Result<usize, BgzfError> {
+    check_read_bounds(b, off, len)?;
+    let mut target = [IoSliceMut::new(&mut b[off..off + len])];
+    Ok(
+      self
+        .read_from_pos(&mut ReadTarget::new(&mut target))?
+        .unwrap_or(0),
+    )
+  }
+
+  /// Fills `b` from `pos` of the uncompressed data, and returns the number
+  /// of bytes read, 0 at the end of the data, like `read` from `pos`, without
+  /// using or moving the reader's position. Threads sharing the reader can
+  /// read from where each of them is at the same time. Fails with
+  /// `BgzfError::PositionOverflow` when `pos` plus the length of `b` is past
+  /// `u64::MAX`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::sync::Arc;
+  /// use std::thread;
+  ///
+  /// let reader = Arc::new(BgzfReader::new(String::from("bgzf_test.bgz")).unwrap());
+  /// let shared = Arc::clone(&reader);
+  /// let word = thread::spawn(move || {
+  ///   let mut word = [0; 4];
+  ///   shared.read_at(8, &mut word).unwrap();
+  ///   word
+  /// });
+  /// let mut start = [0; 4];
+  /// assert_eq!(4, reader.read_at(0, &mut start).unwrap());
+  /// assert_eq!(b"This", &start);
+  /// assert_eq!(b"just", &word.join().unwrap());
+  /// assert_eq!(0, reader.pos.get());
+  /// ```
+  pub fn read_at(&self, pos: u64, b: &mut [u8]) -> Result<usize, BgzfError> {
+    check_read_bounds(b, 0, b.len())?;
+    let mut target = [IoSliceMut::new(b)];
+    let mut pos = pos;
+    Ok(
+      self
+        .read_from(&mut pos, &mut ReadTarget::new(&mut target))?
+        .unwrap_or(0),
+    )
+  }
+
+  /// Reads from the current position into `buf`, whose bytes need not be
+  /// initialized, like the spare capacity of a `Vec`, and returns the number
+  /// of bytes read, 0 at the end of the data, which are the bytes at the
+  /// start of `buf` that are now initialized. It reads what `read_to` would,
+  /// without the buffer being zeroed first: the bytes of cached blocks, of
+  /// loaded data and of blocks read in part are copied in, while a whole
+  /// block decompressed in place, which the deflate backends only do into
+  /// initialized memory, has its own stretch zeroed right before, while it is
+  /// in the CPU cache.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut data = Vec::with_capacity(300);
+  /// let read = reader.read_into_uninit(data.spare_capacity_mut()).unwrap();
+  /// // SAFETY: read_into_uninit initialized the first `read` bytes
+  /// unsafe { data.set_len(read) };
+  /// assert_eq!(280, data.len());
+  /// assert!(data.starts_with(b"This is just a bgzf test"));
+  /// ```
+  pub fn read_into_uninit(&self, buf: &mut [MaybeUninit<u8>]) -> Result<usize, BgzfError> {
+    Ok(
+      self
+        .read_from_pos(&mut ReadTarget::uninit(buf))?
+        .unwrap_or(0),
+    )
+  }
+
+  /// Fills `bufs` one after the other from the current position, like
+  /// reading into all of them joined, and returns the number of bytes read,
+  /// 0 at the end of the data. The blocks covering the read are looked up
+  /// once, a block going over the end of a buffer is decompressed once and
+  /// its bytes carry on into the next buffer. Empty buffers are skipped, and
+  /// at the end of the data the buffers are filled up to it. The std `Read`
+  /// implementation's `read_vectored` reads with it.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::io::IoSliceMut;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let (mut first, mut second) = ([0; 4], [0; 8]);
+  /// let mut bufs = [
+  ///   IoSliceMut::new(&mut first),
+  ///   IoSliceMut::new(&mut []),
+  ///   IoSliceMut::new(&mut second),
+  /// ];
+  /// assert_eq!(12, reader.read_vectored(&mut bufs).unwrap());
+  /// assert_eq!(b"This", &first);
+  /// assert_eq!(b" is just", &second);
+  /// ```
+  pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize, BgzfError> {
+    Ok(self.read_from_pos(&mut ReadTarget::new(bufs))?.unwrap_or(0))
+  }
+
+  /// Turns the reader into a `Read` of its data from its position, for
+  /// sequential reads like `io::copy`: a thread decompresses the next chunks
+  /// of 64KiB while the caller reads the current one, keeping at most
+  /// `depth` of them ready, at least one. Besides those, one chunk is being
+  /// read and one decompressed, so it holds `depth + 2` chunks at most. An
+  /// error stops the thread, and is returned once the chunks before it were
+  /// read, as are the reads following it. Dropping it stops the thread.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfReader;
+  /// use std::io::Read;
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut data = String::new();
+  /// reader
+  ///   .into_pipelined(2)
+  ///   .unwrap()
+  ///   .read_to_string(&mut data)
+  ///   .unwrap();
+  /// assert!(data.starts_with("This is just"));
+  /// ```
+  pub fn into_pipelined(self, depth: usize) -> io::Result<PipelinedReader> {
+    PipelinedReader::spawn(self, depth)
+  }
+
+  /// Fills `target` from pos and returns the number of bytes read, or None
+  /// when pos is at the end of the data. Reads from pos are made one at a
+  /// time, each moving pos past the bytes it read.
+  fn read_from_pos(&self, target: &mut ReadTarget) -> Result<Option<usize>, BgzfError> {
+    let _reading = lock(&self.sequential_read);
+    let mut pos = self.pos.get();
+    let read = self.read_from(&mut pos, target);
+    self.pos.set(pos);
+    read
+  }
+
+  /// Fills `target` from `pos`, moving it past the bytes read, and returns
+  /// their number, or None when `pos` is at the end of the data
+  fn read_from(&self, pos: &mut u64, target: &mut ReadTarget) -> Result<Option<usize>, BgzfError> {
+    let len = target.remaining;
+    if len == 0 {
+      return Ok(Some(0));
+    }
+    //the end of the read is checked once, the offsets worked out from pos
+    //below then stay before it
+    let end = read_end(*pos, len)?;
+    if self.recover {
+      self.index_up_to(end);
+      if let Some(gap) = lock(&self.gaps)
+        .iter()
+        .find(|gap| gap.touched_by(*pos, len))
+      {
+        return Err(gap.clone().into());
+      }
+    }
+    self
+      .fill_from(pos, target)
+      .map_err(|error| self.locate_block(error))
+      .map_err(|error| self.record_gap(error))
+  }
+
+  /// Turns `error`, when it is about a block that can not be decompressed,
+  /// into the gap the block leaves in the data when opened with `recover`,
+  /// and records it
+  fn record_gap(&self, error: BgzfError) -> BgzfError {
+    let block = match (&error, self.recover) {
+      (
+        BgzfError::IndexMismatch { block }
+        | BgzfError::CorruptDeflateStream { block, .. }
+        | BgzfError::SizeMismatch { block, .. }
+        | BgzfError::ChecksumMismatch { block, .. },
+        true,
+      ) => shared_state::read(&self.blocks)[block.index as usize],
+      _ => return error,
+    };
+    let gap = DataGap {
+      compressed_range: block.block_offset..block.block_offset + u64::from(block.block_size),
+      uoffset_range: block.input_offset..block.input_offset + u64::from(block.input_length),
+    };
+    let mut gaps = lock(&self.gaps);
+    let at = gaps.partition_point(|other| other.uoffset_range.start < gap.uoffset_range.start);
+    gaps.insert(at, gap.clone());
+    gap.into()
+  }
+
+  /// Sets the index of the block `error` is about, if any, which is its
+  /// number among the blocks indexed
+  fn locate_block(&self, error: BgzfError) -> BgzfError {
+    locate_block_in(&shared_state::read(&self.blocks), error)
+  }
+
+  /// `read_from` without the index of the block in its errors
+  fn fill_from(&self, pos: &mut u64, target: &mut ReadTarget) -> Result<Option<usize>, BgzfError> {
+    if let Some(loaded) = self.loaded.get() {
+      if *pos >= loaded.len() as u64 {
+        return Ok(None);
+      }
+      let start = *pos as usize;
+      let copy_length = min(loaded.len() - start, target.remaining);
+      target.write(&loaded[start..start + copy_length]);
+      *pos += copy_length as u64;
+      return Ok(Some(copy_length));
+    }
+    let end = read_end(*pos, target.remaining)?;
+    self.index_up_to(end);
+    if *pos >= self.indexed_length.get() {
+      self.check_truncation()?;
+      return Ok(None);
+    }
+
+    let mut cb: usize = 0;
+
+    //a read going elsewhere than where the last one ended stops prefetching
+    let sequential = *pos == self.last_read_end.get();
+    if let (Some(prefetcher), false) = (&self.prefetcher, sequential) {
+      prefetcher.request(&[]);
+    }
+
+    //the cache is only locked to look blocks up and add them, blocks are read
+    //and decompressed with it released so that reads sharing the reader run
+    //at the same time
+    let mut cache = lock(&self.cache);
+    match cache
+      .most_recent()
+      .map(|(cache_pos, cached)| (cache_pos, cached.len()))
+    {
+      //blocks read straight into the buffer leave an older block in the
+      //cache, pos can be past it
+      Some((cache_pos, cached_length))
+        if *pos >= cache_pos && *pos < cache_pos + cached_length as u64 =>
+      {
+        //the offsets go past usize on 32-bit targets, not the distances
+        //within the cached block
+        let bytes_available_in_cache = (cache_pos + cached_length as u64 - *pos) as usize;
+        let cached = cache.get(cache_pos).unwrap();
+        let copy_start = (*pos - cache_pos) as usize;
+        let copy_length = min(bytes_available_in_cache, target.remaining);
+        let end_index = copy_start + copy_length;
+        target.write(&cached[copy_start..end_index]);
+        cb += copy_length;
+        *pos += copy_length as u64;
+        if target.remaining == 0 {
+          self.last_read_end.set(*pos);
+          return Ok(Some(cb));
+        }
+      }
+      _ => {
+        //If there is no cache available lets move forward
+      }
+    }
+    drop(cache);
+    let uncached = |block: &BgzfBlock| !lock(&self.cache).contains(block.input_offset);
+
+    let blocks = shared_state::read(&self.blocks);
+    //Getting the floored block holding pos, then all the blocks starting
+    //within the range of pos and length of the buffers passed
+    let first_block = floor_block(&blocks, *pos).ok_or(BgzfError::OutOfRange {
+      pos: *pos,
+      len: self.indexed_length.get(),
+    })?;
+    let end_block =
+      first_block + blocks[first_block..].partition_point(|block| block.input_offset < end);
+
+    for (number, &block) in blocks.iter().enumerate().take(end_block).skip(first_block) {
+      let input_offset = block.input_offset;
+      //the blocks left to read, whose compressed bytes are read at once with
+      //this one's when they follow it and are not cached
+      let run = &blocks[number..end_block];
+
+      #[cfg(feature = "rayon")]
+      {
+        if input_offset + u64::from(block.input_length) <= *pos {
+          //already decompressed with the blocks before it
+          continue;
+        }
+        //many whole blocks the buffer being filled has room for are
+        //decompressed in parallel right into it
+        let pos_and_len_combined = pos.saturating_add(target.current_len() as u64);
+        let whole_blocks = &run[..run.partition_point(|other| {
+          other.input_offset + u64::from(other.input_length) <= pos_and_len_combined
+        })];
+        if input_offset == *pos
+          && whole_blocks.len() >= PARALLEL_READ_MIN_BLOCKS
+          && self.compressed_cache.is_none()
+        {
+          let whole_length = whole_blocks
+            .iter()
+            .map(|block| block.input_length as usize)
+            .sum();
+          let length = decompress_blocks_parallel(
+            self.block_source(),
+            &self.decompressors,
+            self.verify_crc,
+            whole_blocks,
+            &self.executor,
+            target.current(whole_length),
+          )?;
+          let mut cache = lock(&self.cache);
+          for block in whole_blocks {
+            cache.record_miss(block.input_offset);
+          }
+          target.advance(length);
+          *pos += length as u64;
+          cb += length;
+          continue;
+        }
+      }
+
+      let cached = !uncached(&block);
+      let prefetched = match &self.prefetcher {
+        Some(prefetcher) if !cached => prefetcher.take(input_offset),
+        _ => None,
+      };
+
+      //a whole block the buffer being filled has room for is decompressed
+      //right into it, or copied there when prefetched, without going through
+      //the cache
+      let block_length = block.input_length as usize;
+      if input_offset == *pos && target.current_len() >= block_length && !cached {
+        lock(&self.cache).record_miss(input_offset);
+        let out = target.current(block_length);
+        match prefetched {
+          Some(prefetched) => out.copy_from_slice(&prefetched?),
+          None => self.decompress_block_in_run(run, uncached, out)?,
+        }
+        target.advance(block_length);
+        *pos += block_length as u64;
+        cb += block_length;
+        continue;
+      }
+
+      //blocks still in the cache are not decompressed again, the others are
+      //moved to the cache and copied to the buffer from there
+      let prefetched = prefetched.transpose()?;
+      let mut cache = lock(&self.cache);
+      let uncompressed = match prefetched {
+        Some(prefetched) => cache.insert(input_offset, prefetched),
+        None if cache.contains(input_offset) => cache.get(input_offset).unwrap(),
+        None => {
+          drop(cache);
+          let mut decompressed = vec![0u8; block_length];
+          self.decompress_block_in_run(run, uncached, &mut decompressed)?;
+          cache = lock(&self.cache);
+          cache.insert(input_offset, decompressed)
+        }
+      };
+
+      let (copy_start, copy_length) = block_copy_range(&block, *pos, target.remaining);
+      target.write(&uncompressed[copy_start..copy_start + copy_length]);
+      *pos += copy_length as u64;
+      cb += copy_length;
+    }
+    self.last_read_end.set(*pos);
+
+    //the next blocks are decompressed while the caller uses these ones
+    if let (Some(prefetcher), true) = (&self.prefetcher, sequential) {
+      let next_blocks = &blocks[end_block..min(end_block + self.prefetch_depth, blocks.len())];
+      let cache = lock(&self.cache);
+      let uncached = next_blocks
+        .iter()
+        .filter(|block| !cache.contains(block.input_offset))
+        .copied()
+        .collect::<Vec<_>>();
+      drop(cache);
+      prefetcher.request(&uncached);
+    }
+    Ok(Some(cb))
+  }
+
+  /// Reads the compressed data of a block and de-compresses all of it
+  fn decompress_block(&self, block: &BgzfBlock) -> Result<Vec<u8>, BgzfError> {
+    let mut uncompressed = vec![0u8; block.input_length as usize];
+    self.decompress_block_into(block, &mut uncompressed)?;
+    Ok(uncompressed)
+  }
+
+  /// De-compresses all of a block into `uncompressed`, which holds exactly
+  /// its uncompressed length
+  fn decompress_block_into(
+    &self,
+    block: &BgzfBlock,
+    uncompressed: &mut [u8],
+  ) -> Result<(), BgzfError> {
+    self.decompress_block_in_run(std::slice::from_ref(block), |_| false, uncompressed)
+  }
+
+  /// De-compresses all of `blocks[0]` into `uncompressed`, reading its
+  /// compressed bytes along with those of the next of `blocks` following it
+  /// in the file that are `wanted`, see `CompressedRun::block`. Blocks in the
+  /// compressed cache are not read, the others are added to it. The block is
+  /// inflated with the caches and the compressed run released.
+  fn decompress_block_in_run(
+    &self,
+    blocks: &[BgzfBlock],
+    wanted: impl Fn(&BgzfBlock) -> bool,
+    uncompressed: &mut [u8],
+  ) -> Result<(), BgzfError> {
+    let block = &blocks[0];
+    let cached = self
+      .compressed_cache
+      .as_ref()
+      .and_then(|cache| lock(cache).get(block.block_offset).map(<[u8]>::to_vec));
+    if let Some(block_data) = cached {
+      return inflate_block(
+        &mut self.decompressors.acquire(),
+        block,
+        self.verify_crc,
+        &block_data,
+        uncompressed,
+      );
+    }
+
+    //blocks in the compressed cache are not read again with this one
+    let wanted = |next: &BgzfBlock| {
+      wanted(next)
+        && self
+          .compressed_cache
+          .as_ref()
+          .is_none_or(|cache| !lock(cache).contains(next.block_offset))
+    };
+    let block_data =
+      CompressedRun::block(&self.compressed_run, self.block_source(), blocks, wanted).map_err(
+        |source| BgzfError::ReadFailed {
+          block: BlockContext::new(block, BlockOperation::Reading),
+          source,
+        },
+      )?;
+    if let Some(cache) = &self.compressed_cache {
+      lock(cache).insert(block.block_offset, block_data.to_vec());
+    }
+    inflate_block(
+      &mut self.decompressors.acquire(),
+      &blocks[0],
+      self.verify_crc,
+      &block_data,
+      uncompressed,
+    )
+  }
+}
+
+/// Reads from the current position with `read`, 0 at the end of the data
+impl io::Read for &BgzfReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    BgzfReader::read(self, buf, 0, buf.len()).map_err(io::Error::from)
+  }
+
+  fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+    BgzfReader::read_vectored(self, bufs).map_err(io::Error::from)
+  }
+}
+
+impl io::Read for BgzfReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    io::Read::read(&mut &*self, buf)
+  }
+
+  fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+    io::Read::read_vectored(&mut &*self, bufs)
+  }
+}
+
+/// What a read fills: buffers of initialized bytes, or the buffer of
+/// `read_into_uninit`, whose bytes may not be initialized
+enum Buffers<'a, 'b> {
+  Init(&'a mut [IoSliceMut<'b>]),
+  Uninit(&'a mut [MaybeUninit<u8>]),
+}
+
+/// The buffers a read fills, one after the other
+///
+/// Fields description:
+///
+/// bufs: the buffers, the empty ones are skipped,
+/// index: index of the buffer being filled,
+/// filled: number of bytes of it already filled,
+/// remaining: number of bytes left to fill in all of them,
+struct ReadTarget<'a, 'b> {
+  bufs: Buffers<'a, 'b>,
+  index: usize,
+  filled: usize,
+  remaining: usize,
+}
+
+impl<'a, 'b> ReadTarget<'a, 'b> {
+  fn new(bufs: &'a mut [IoSliceMut<'b>]) -> ReadTarget<'a, 'b> {
+    let remaining = bufs.iter().map(|buf| buf.len()).sum();
+    ReadTarget::of(Buffers::Init(bufs), remaining)
+  }
+
+  fn uninit(buf: &'a mut [MaybeUninit<u8>]) -> ReadTarget<'a, 'b> {
+    let remaining = buf.len();
+    ReadTarget::of(Buffers::Uninit(buf), remaining)
+  }
+
+  fn of(bufs: Buffers<'a, 'b>, remaining: usize) -> ReadTarget<'a, 'b> {
+    let mut target = ReadTarget {
+      bufs,
+      index: 0,
+      filled: 0,
+      remaining,
+    };
+    target.advance(0);
+    target
+  }
+
+  /// Length of the buffer numbered `index`, 0 past the last one
+  fn buf_len(&self, index: usize) -> usize {
+    match &self.bufs {
+      Buffers::Init(bufs) => bufs.get(index).map_or(0, |buf| buf.len()),
+      Buffers::Uninit(buf) if index == 0 => buf.len(),
+      Buffers::Uninit(_) => 0,
+    }
+  }
+
+  /// Number of bytes of the buffer being filled that are not filled yet, 0
+  /// once all of them are
+  fn current_len(&self) -> usize {
+    self.buf_len(self.index).saturating_sub(self.filled)
+  }
+
+  /// The next `length` bytes of the buffer being filled, at most
+  /// `current_len`, for the caller to fill all of them and `advance` past
+  /// them. Bytes that may not be initialized are zeroed first, the deflate
+  /// backends only write to initialized memory.
+  fn current(&mut self, length: usize) -> &mut [u8] {
+    let range = self.filled..self.filled + length;
+    match &mut self.bufs {
+      Buffers::Init(bufs) => &mut bufs[self.index][range],
+      Buffers::Uninit(buf) => {
+        let out = &mut buf[range];
+        for byte in out.iter_mut() {
+          byte.write(0);
+        }
+        // SAFETY: every byte of `out` was written just above
+        unsafe { out.assume_init_mut() }
+      }
+    }
+  }
+
+  /// Counts `length` more bytes of the buffer being filled as filled and
+  /// moves on to the next buffer that is not empty when it is full
+  fn advance(&mut self, length: usize) {
+    self.filled += length;
+    self.remaining -= length;
+    while self.filled == self.buf_len(self.index) && self.remaining > 0 {
+      self.index += 1;
+      self.filled = 0;
+    }
+  }
+
+  /// Copies `data`, which is not longer than what is left to fill, going on
+  /// into the next buffers when the one being filled is full. Bytes that may
+  /// not be initialized are written without being zeroed first.
+  fn write(&mut self, mut data: &[u8]) {
+    while !data.is_empty() {
+      let length = min(self.current_len(), data.len());
+      let range = self.filled..self.filled + length;
+      match &mut self.bufs {
+        Buffers::Init(bufs) => bufs[self.index][range].copy_from_slice(&data[..length]),
+        Buffers::Uninit(buf) => {
+          buf[range].write_copy_of_slice(&data[..length]);
+        }
+      }
+      data = &data[length..];
+      self.advance(length);
+    }
+  }
+}
+
+/// Longest uncompressed data `load_all` loads by default
+const DEFAULT_LOAD_ALL_LIMIT: u64 = 1 << 30;
+
+/// Limits on what a file may take, set on the builder, none by default:
+///
+/// max_blocks: most blocks with data indexed,
+/// max_uncompressed_length: longest uncompressed data indexed,
+/// max_allocation: most bytes allocated at once for the data,
+#[derive(Copy, Clone, Debug)]
+struct Limits {
+  max_blocks: u64,
+  max_uncompressed_length: u64,
+  max_allocation: u64,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      max_blocks: u64::MAX,
+      max_uncompressed_length: u64::MAX,
+      max_allocation: u64::MAX,
+    }
+  }
+}
+
+impl Limits {
+  fn max(&self, limit: ResourceLimit) -> u64 {
+    match limit {
+      ResourceLimit::Blocks => self.max_blocks,
+      ResourceLimit::UncompressedLength => self.max_uncompressed_length,
+      ResourceLimit::Allocation => self.max_allocation,
+    }
+  }
+}
+
+/// Number of whole blocks from which a read decompresses them in parallel
+#[cfg(feature = "rayon")]
+const PARALLEL_READ_MIN_BLOCKS: usize = 8;
+
+/// Decompresses `blocks`, which follow each other, in parallel into the start
+/// of `out` and returns their total uncompressed length. Every rayon worker
+/// of the pool of `executor` has its own buffer for the compressed bytes, and
+/// takes a decompressor from `decompressors` for every block.
+#[cfg(feature = "rayon")]
+fn decompress_blocks_parallel(
+  source: &(dyn ReadAt + Sync),
+  decompressors: &DecompressorPool,
+  verify_crc: bool,
+  blocks: &[BgzfBlock],
+  executor: &Executor,
+  out: &mut [u8],
+) -> Result<usize, BgzfError> {
+  use rayon::prelude::*;
+
+  let mut segments = Vec::with_capacity(blocks.len());
+  let mut rest = out;
+  let mut length = 0;
+  for block in blocks {
+    let (segment, tail) = std::mem::take(&mut rest).split_at_mut(block.input_length as usize);
+    segments.push((block, segment));
+    rest = tail;
+    length += block.input_length as usize;
+  }
+  executor.install(|| {
+    segments
+      .into_par_iter()
+      .try_for_each_init(Vec::new, |block_data, (block, segment)| {
+        let mut un_compressor = decompressors.acquire();
+        decompress_block_at(
+          source,
+          &mut un_compressor,
+          block,
+          verify_crc,
+          block_data,
+          segment,
+        )
+      })
+  })?;
+  Ok(length)
+}
+
+/// Reads `block` from `source` into `block_data`, grown as needed, and
+/// de-compresses all of it into `uncompressed`, which holds exactly its
+/// uncompressed length, checking its CRC32 with `verify_crc`
+fn decompress_block_at<R: ReadAt + ?Sized>(
+  source: &R,
+  un_compressor: &mut Decompressor,
+  block: &BgzfBlock,
+  verify_crc: bool,
+  block_data: &mut Vec<u8>,
+  uncompressed: &mut [u8],
+) -> Result<(), BgzfError> {
+  //Reading the whole block, the header tells where the compressed data is
+  block_data.resize(block.block_size as usize, 0);
+  source
+    .read_exact_at(block.block_offset, block_data)
+    .map_err(|source| BgzfError::ReadFailed {
+      block: BlockContext::new(block, BlockOperation::Reading),
+      source,
+    })?;
+  inflate_block(un_compressor, block, verify_crc, block_data, uncompressed)
+}
+
+/// De-compresses all of the whole compressed block `block_data` into
+/// `uncompressed`, which holds exactly its uncompressed length, and with
+/// `verify_crc` checks its CRC32 against the footer
+fn inflate_block(
+  un_compressor: &mut Decompressor,
+  block: &BgzfBlock,
+  verify_crc: bool,
+  block_data: &[u8],
+  uncompressed: &mut [u8],
+) -> Result<(), BgzfError> {
+  //wasm32 has no clock, its blocks take no time
+  #[cfg(feature = "tracing")]
+  let started = (!cfg!(target_arch = "wasm32")).then(std::time::Instant::now);
+  let compressed = deflate_data(block_data, block)?;
+  let context = BlockContext::new(block, BlockOperation::Decompressing);
+  let expected = u64::from(block.input_length);
+  let size_mismatch = |actual: usize, source| BgzfError::SizeMismatch {
+    block: context,
+    expected,
+    actual: actual as u64,
+    source,
+  };
+  if uncompressed.len() as u64 != expected {
+    return Err(BgzfError::InternalBufferError {
+      block: context,
+      source: io::Error::other(format!(
+        "{} bytes of buffer for {} bytes of data",
+        uncompressed.len(),
+        expected
+      )),
+    });
+  }
+
+  //now it's time to de-compress the read value obtained.
+  let bytes_decompressed = match un_compressor.inflate(compressed, uncompressed) {
+    Ok(bytes_decompressed) => bytes_decompressed,
+    Err(InflateError::BadData(source)) => {
+      return Err(BgzfError::CorruptDeflateStream {
+        block: context,
+        source,
+      })
+    }
+    //the buffer holds what the footer gives, the footer lied, how much the
+    //data holds is found decompressing it again with room for a whole block
+    Err(InflateError::InsufficientSpace(source)) => {
+      let mut whole_block = vec![0; MAX_BLOCK_SIZE];
+      return Err(match un_compressor.inflate(compressed, &mut whole_block) {
+        Ok(actual) => size_mismatch(actual, source),
+        Err(_error) => BgzfError::CorruptDeflateStream {
+          block: context,
+          source,
+        },
+      });
+    }
+  };
+
+  if bytes_decompressed as u64 != expected {
+    return Err(size_mismatch(
+      bytes_decompressed,
+      io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("The deflate data ended after {} bytes", bytes_decompressed),
+      ),
+    ));
+  }
+  if verify_crc {
+    check_footer(block, block_data, uncompressed)?;
+  }
+  #[cfg(feature = "tracing")]
+  tracing::trace!(
+    block_offset = block.block_offset,
+    compressed_size = block.block_size,
+    uncompressed_size = block.input_length,
+    duration_us = started.map_or(0, |started| started.elapsed().as_micros() as u64),
+    "block decompressed"
+  );
+  Ok(())
+}
+
+/// Fails unless the footer of the whole compressed `block_data` of `block`
+/// holds the CRC32 of its decompressed data `uncompressed`, its length was
+/// checked against ISIZE when decompressing it
+fn check_footer(
+  block: &BgzfBlock,
+  block_data: &[u8],
+  uncompressed: &[u8],
+) -> Result<(), BgzfError> {
+  //the 8 byte footer holds the CRC32 and ISIZE
+  let footer = &block_data[block_data.len() - 8..];
+  let expected = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+  let actual = crc32(uncompressed);
+  if actual != expected {
+    return Err(BgzfError::ChecksumMismatch {
+      block: BlockContext::new(block, BlockOperation::CheckingCrc),
+      expected,
+      actual,
+    });
+  }
+  Ok(())
+}
+
+/// Fails unless `len` bytes from `off` fit in `b`
+fn check_read_bounds(b: &[u8], off: usize, len: usize) -> Result<(), BgzfError> {
+  if off > b.len() || len > b.len() - off {
+    return Err(BgzfError::OutOfRange {
+      pos: off.saturating_add(len) as u64,
+      len: b.len() as u64,
+    });
+  }
+  Ok(())
+}
+
+/// Returns the offset right after a read of `len` bytes from `pos`, failing
+/// with `BgzfError::PositionOverflow` when it does not fit in a u64
+fn read_end(pos: u64, len: usize) -> Result<u64, BgzfError> {
+  pos
+    .checked_add(len as u64)
+    .ok_or(BgzfError::PositionOverflow {
+      pos,
+      len: len as u64,
+    })
+}
+
+/// Returns where the bytes of `block` from `pos` start in its uncompressed
+/// data, and how many of them a read of `len` more bytes takes
+fn block_copy_range(block: &BgzfBlock, pos: u64, len: usize) -> (usize, usize) {
+  let input_length = u64::from(block.input_length);
+  let copy_start = min(pos.saturating_sub(block.input_offset), input_length);
+  let available = (input_length - copy_start) as usize;
+  (copy_start as usize, min(available, len))
+}
+
+/// Length of a buffer holding `length` bytes of uncompressed data, which can
+/// be too long for the platform, on 32-bit targets
+fn buffer_length(length: u64) -> Result<usize, BgzfError> {
+  usize::try_from(length).map_err(|_error| BgzfError::TooLarge {
+    length,
+    limit: usize::MAX as u64,
+  })
+}
+
+/// `BgzfReader::locate_block` among the indexed `blocks`, for callers
+/// already holding them
+fn locate_block_in(blocks: &[BgzfBlock], error: BgzfError) -> BgzfError {
+  error.with_block_index(|block_offset| {
+    blocks.partition_point(|block| block.block_offset < block_offset) as u64
+  })
+}
+
+/// Returns the blocks of `index`
+fn blocks_of_index(index: &BgzfIndex) -> Vec<BgzfBlock> {
+  index
+    .blocks()
+    .iter()
+    .map(|block| BgzfBlock {
+      input_offset: block.uncompressed_offset,
+      block_offset: block.compressed_offset,
+      input_length: block.uncompressed_length,
+      block_size: block.block_size,
+    })
+    .collect()
+}
+
+/// Returns the index of the last of `blocks` starting at or before `uoffset`,
+/// the one holding it unless it is past the end of the data
+fn floor_block(blocks: &[BgzfBlock], uoffset: u64) -> Option<usize> {
+  blocks
+    .partition_point(|block| block.input_offset <= uoffset)
+    .checked_sub(1)
+}
+
+/// Layout of a file checked by `scan_block_chain`
+///
+/// data_end: offset where the data blocks end,
+/// uncompressed_length: total uncompressed length of the data blocks,
+/// has_eof_marker: whether the EOF marker follows the data blocks,
+/// block_offsets: (compressed, uncompressed) offset of every data block start,
+pub(crate) struct BlockChain {
+  pub(crate) data_end: u64,
+  pub(crate) uncompressed_length: u64,
+  pub(crate) has_eof_marker: bool,
+  pub(crate) block_offsets: Vec<(u64, u64)>,
+}
+
+/// Checks that the whole file is a chain of valid blocks, optionally ended by
+/// the EOF marker, and returns where the data blocks are
+pub(crate) fn scan_block_chain(file: &Source) -> io::Result<BlockChain> {
+  let file_length = file.len()?;
+  let mut data_end = file_length;
+  if file_length >= EOF_MARKER.len() as u64 {
+    let mut tail = [0u8; 28];
+    file.read_exact_at(file_length - tail.len() as u64, &mut tail)?;
+    if tail == EOF_MARKER {
+      data_end -= tail.len() as u64;
+    }
+  }
+
+  let mut uncompressed_length = 0;
+  let mut block_offsets = Vec::new();
+  let mut current_file_position = 0;
+  let scan = ScanBuffer::new(file);
+  while current_file_position < data_end {
+    match read_block(&scan, current_file_position) {
+      Ok(block) => {
+        if block.input_length > 0 {
+          block_offsets.push((current_file_position, uncompressed_length));
+          uncompressed_length += u64::from(block.input_length);
+        }
+        current_file_position += u64::from(block.block_size);
+      }
+      Err(e) => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Invalid block at offset {}: {}", current_file_position, e),
+        ))
+      }
+    }
+  }
+  if current_file_position != data_end {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Last block extends past the end of the file",
+    ));
+  }
+  Ok(BlockChain {
+    data_end,
+    uncompressed_length,
+    has_eof_marker: data_end != file_length,
+    block_offsets,
+  })
+}
+
+/// Returns the compressed data of a whole block read from the file, checking
+/// that its header and footer match what the block tree says about it, and
+/// are still those of a BGZF block
+fn deflate_data<'a>(block_data: &'a [u8], block: &BgzfBlock) -> Result<&'a [u8], BgzfError> {
+  let invalid_header = BgzfError::IndexMismatch {
+    block: BlockContext::new(block, BlockOperation::Reading),
+  };
+  if block_data.len() < 26 || block_data[..3] != [31, 139, 8] || !valid_flags(block_data[3]) {
+    return Err(invalid_header);
+  }
+  let xlen = usize::from(u16::from_le_bytes([block_data[10], block_data[11]]));
+  let bsize = match block_data.get(12..12 + xlen).and_then(find_bsize) {
+    Some((_position, bsize)) if 12 + xlen + 8 <= block_data.len() => u32::from(bsize),
+    _ => return Err(invalid_header),
+  };
+  let footer = block_data.len() - 8;
+  let mut i_size = [0u8; 4];
+  i_size.copy_from_slice(&block_data[footer + 4..]);
+  if bsize + 1 != block.block_size || u32::from_le_bytes(i_size) != block.input_length {
+    return Err(invalid_header);
+  }
+  let data_start = 12 + xlen;
+  match header_fields_length(block_data[3], &block_data[data_start..footer]) {
+    Some(fields_length) => Ok(&block_data[data_start + fields_length..footer]),
+    None => Err(invalid_header),
+  }
+}
+
+/// Size under which a file is always indexed by a single thread
+const PARALLEL_SCAN_MIN_LENGTH: u64 = 1 << 20;
+
+/// Number of bytes read at once when chaining the block starts
+const SCAN_CHUNK_LENGTH: usize = 4 << 20;
+
+/// Returns where every block starts, following the BSIZE of each header from
+/// the start of the file, and stops at the first header that is not a BGZF
+/// one. The file is read in large chunks rather than block by block.
+fn chain_block_starts(file: &Source) -> io::Result<Vec<u64>> {
+  let file_length = file.len()?;
+  let mut block_starts = Vec::new();
+  let mut chunk = vec![0u8; SCAN_CHUNK_LENGTH];
+  let mut chunk_start = 0;
+  let mut chunk_end = 0;
+  let mut block_start = 0;
+  while block_start + 18 <= file_length {
+    if block_start + 18 > chunk_end {
+      let chunk_length = min(SCAN_CHUNK_LENGTH as u64, file_length - block_start) as usize;
+      file.read_exact_at(block_start, &mut chunk[..chunk_length])?;
+      chunk_start = block_start;
+      chunk_end = block_start + chunk_length as u64;
+    }
+    let header_start = (block_start - chunk_start) as usize;
+    let header = &chunk[header_start..header_start + 18];
+    let block_size = if header[..4] == [31, 139, 8, 4] && header[12..16] == [66, 67, 2, 0] {
+      u64::from(u16::from_le_bytes([header[16], header[17]])) + 1
+    } else {
+      //headers laid out otherwise are read in full
+      match read_block(file, block_start) {
+        Ok(block) => u64::from(block.block_size),
+        Err(_) => break,
+      }
+    };
+    block_starts.push(block_start);
+    block_start += block_size;
+  }
+  Ok(block_starts)
+}
+
+/// Builds the same blocks as reading them one after the other, with
+/// `threads` tasks of `executor` reading the headers of the chained block
+/// starts. Returns the blocks, the total uncompressed length and the
+/// truncation the serial scan would find.
+fn scan_blocks_parallel(
+  file: &Source,
+  threads: usize,
+  parse_mode: ParseMode,
+  executor: &Executor,
+) -> io::Result<(Vec<BgzfBlock>, u64, Option<Truncated>, AnomalyScan)> {
+  let block_starts = chain_block_starts(file)?;
+  let per_thread = block_starts.len().div_ceil(threads).max(1);
+  let runs = block_starts.chunks(per_thread).collect::<Vec<_>>();
+  let scanned = executor.run(runs.len(), |run| {
+    let scan = ScanBuffer::new(file);
+    runs[run]
+      .iter()
+      .map(|&block_start| read_block_with_anomaly(&scan, block_start, parse_mode).ok())
+      .collect::<Vec<_>>()
+  });
+  let blocks = scanned
+    .into_iter()
+    .flat_map(|blocks| blocks.expect("Block scanning thread panicked"))
+    .collect::<Vec<_>>();
+
+  //like the serial scan, the blocks end at the first one that is not valid
+  //and empty ones are skipped
+  let scan_end = match blocks.iter().position(Option::is_none) {
+    Some(invalid) => block_starts[invalid],
+    None => blocks
+      .last()
+      .copied()
+      .flatten()
+      .map_or(0, |(block, _anomaly)| {
+        block.block_offset + u64::from(block.block_size)
+      }),
+  };
+  let mut anomaly_scan = AnomalyScan::default();
+  let mut input_offset = 0;
+  let blocks = blocks
+    .into_iter()
+    .map_while(|block| block)
+    .map(|(block, header_anomaly)| {
+      anomaly_scan.block(&block, header_anomaly);
+      block
+    })
+    .filter(|block| block.input_length > 0)
+    .map(|block| {
+      let block = BgzfBlock {
+        input_offset,
+        ..block
+      };
+      input_offset += u64::from(block.input_length);
+      block
+    })
+    .collect();
+  anomaly_scan.end(file, scan_end);
+  Ok((
+    blocks,
+    input_offset,
+    truncation_at(file, scan_end),
+    anomaly_scan,
+  ))
+}
+
+/// Fails with `NotBgzf` unless `file` starts with the header of a BGZF block,
+/// or a part of it for a file cut within it, which indexing reports as
+/// truncated. An empty file is fine.
+fn check_bgzf_start<R: ReadAt + ?Sized>(file: &R) -> Result<(), BgzfError> {
+  //the gzip magic number, deflate, FEXTRA, then the BC subfield of 2 bytes
+  let mut header = [0u8; 16];
+  let mut length = 0;
+  while length < header.len() {
+    match file.read_at(length as u64, &mut header[length..])? {
+      0 => break,
+      read => length += read,
+    }
+  }
+  let differs = |range: Range<usize>, expected: &[u8]| {
+    let available = range.start..range.end.min(length);
+    !available.is_empty() && header[available.clone()] != expected[..available.len()]
+  };
+  if differs(0..2, &[31, 139]) {
+    return Err(BgzfError::NotBgzf {
+      looks_like_gzip: false,
+    });
+  }
+  let not_bgzf = Err(BgzfError::NotBgzf {
+    looks_like_gzip: true,
+  });
+  if differs(2..3, &[8]) || (length > 3 && !valid_flags(header[3])) {
+    return not_bgzf;
+  }
+  if differs(12..16, &[66, 67, 2, 0]) {
+    //the BC subfield may follow others, unless the file is cut before it
+    let xlen = usize::from(u16::from_le_bytes([header[10], header[11]]));
+    let mut extra = vec![0u8; xlen];
+    if file.read_exact_at(12, &mut extra).is_ok() && find_bsize(&extra).is_none() {
+      return not_bgzf;
+    }
+  }
+  Ok(())
+}
+
+/// Checks whether the last 28 bytes of `file` are the EOF marker
+pub(crate) fn ends_with_eof_marker(file: &Source) -> io::Result<bool> {
+  let file_length = file.len()?;
+  if file_length < EOF_MARKER.len() as u64 {
+    return Ok(false);
+  }
+  let mut tail = [0u8; 28];
+  file.read_exact_at(file_length - tail.len() as u64, &mut tail)?;
+  Ok(tail == EOF_MARKER)
+}
+
+/// Returns the truncation of a file whose blocks stop being readable at
+/// `block_offset`, None when that is the end of the file or follows an EOF
+/// marker, after which anything that is not a block is ignored
+fn truncation_at(file: &Source, block_offset: u64) -> Option<Truncated> {
+  let at_file_end = file.len().is_ok_and(|length| length == block_offset);
+  let mut previous_block = [0u8; 28];
+  let after_eof_marker = block_offset >= previous_block.len() as u64
+    && file
+      .read_exact_at(
+        block_offset - previous_block.len() as u64,
+        &mut previous_block,
+      )
+      .is_ok()
+    && previous_block == EOF_MARKER;
+  if at_file_end || after_eof_marker {
+    None
+  } else {
+    Some(Truncated { block_offset })
+  }
+}
+
+/// Most bytes the size in a block header is taken to be off by, looking for
+/// the block it should have led to
+const MAX_BLOCK_SIZE_SLIP: u64 = 16;
+
+/// Returns the offset of the last of `blocks` when the blocks of `file` stop
+/// at `block_offset` because that block's size leads there, where no block
+/// starts, while a block can be read a few bytes before or after, rather
+/// than to a damaged block. A last block that decompresses to the length and
+/// CRC32 of its footer is whole, what follows it is junk and not its fault.
+fn broken_chain(file: &Source, blocks: &[BgzfBlock], block_offset: u64) -> Option<u64> {
+  let last = blocks.last()?;
+  if last.block_offset + u64::from(last.block_size) != block_offset
+    || read_block(file, block_offset).is_ok()
+  {
+    return None;
+  }
+  let slipped = (block_offset.saturating_sub(MAX_BLOCK_SIZE_SLIP)
+    ..=block_offset + MAX_BLOCK_SIZE_SLIP)
+    .filter(|&offset| offset > last.block_offset && offset != block_offset)
+    .any(|offset| read_block(file, offset).is_ok());
+  if !slipped {
+    return None;
+  }
+  let mut uncompressed = vec![0; last.input_length as usize];
+  let whole = decompress_block_at(
+    file,
+    &mut Decompressor::new(),
+    last,
+    true,
+    &mut Vec::new(),
+    &mut uncompressed,
+  )
+  .is_ok();
+  (!whole).then_some(last.block_offset)
+}
+
+/// Checks that `index` can be the index of `file`: it passes `validate` for
+/// the file's length and its first and last blocks are there with the sizes
+/// it gives them
+fn index_fits_file(file: &Source, index: &BgzfIndex) -> bool {
+  match file.len() {
+    Ok(length) if index.validate(length).is_ok() => {}
+    _ => return false,
+  }
+  let blocks = index.blocks();
+  blocks
+    .first()
+    .into_iter()
+    .chain(blocks.last())
+    .all(|block| match read_block(file, block.compressed_offset) {
+      Ok(read) => {
+        read.block_size == block.block_size && read.input_length == block.uncompressed_length
+      }
+      _ => false,
+    })
+}
+
+/// Most bytes a byte of deflate data decompresses to, reached by matches of
+/// 258 bytes coded in 2 bits
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// Reads the header and footer of the block at `block_offset`, an empty block
+/// (ISIZE 0) is returned with an `input_length` of 0. That is three reads,
+/// scans of many blocks read through a `ScanBuffer`.
+fn read_block<R: ReadAt + ?Sized>(file: &R, block_offset: u64) -> Result<BgzfBlock, BgzfError> {
+  read_block_with_anomaly(file, block_offset, ParseMode::Default).map(|(block, _anomaly)| block)
+}
+
+/// Same as `read_block`, also returning the anomaly of the block's header,
+/// other gzip flags than FEXTRA or a BC subfield that is not the first one.
+/// In `parse_mode` permissive, a block failing with `InvalidBlock` is
+/// returned as an empty block with that anomaly instead.
+fn read_block_with_anomaly<R: ReadAt + ?Sized>(
+  file: &R,
+  block_offset: u64,
+  parse_mode: ParseMode,
+) -> Result<(BgzfBlock, Option<AnomalyKind>), BgzfError> {
+  let mut current_file_position = block_offset;
+
+  let mut buf = [0; 12];
+  file.read_exact_at(current_file_position, &mut buf)?;
+  current_file_position += buf.len() as u64;
+
+  if buf[0] != 31 || buf[1] != 139 || buf[2] != 8 || !valid_flags(buf[3]) {
+    return Err(BgzfError::InvalidHeader {
+      offset: block_offset,
+    });
+  }
+
+  let xlen: u16 = (buf[10] as u16) | ((buf[11] as u16) << 8);
+  //too short for the BC subfield
+  if xlen < 6 {
+    return Err(BgzfError::BadSubfield {
+      offset: block_offset,
+    });
+  }
+
+  let mut buf_xlen = vec![0u8; usize::from(xlen)];
+
+  file.read_exact_at(current_file_position, &mut buf_xlen)?;
+  current_file_position += buf_xlen.len() as u64;
+
+  let (bc_position, bsize) = match find_bsize(&buf_xlen) {
+    Some(bc_subfield) => bc_subfield,
+    None => {
+      return Err(BgzfError::BadSubfield {
+        offset: block_offset,
+      })
+    }
+  };
+  let anomaly = if buf[3] != 4 {
+    Some(AnomalyKind::HeaderFlags)
+  } else if bc_position != 0 {
+    Some(AnomalyKind::BcNotFirst)
+  } else {
+    None
+  };
+  let block_size = u32::from(bsize) + 1;
+  //BSIZE counts the header, extra field and footer too, a block can not be
+  //shorter than them
+  let mut data_length = match u32::from(bsize).checked_sub(u32::from(xlen) + 19) {
+    Some(data_length) => data_length,
+    None => {
+      return Err(BgzfError::InvalidHeader {
+        offset: block_offset,
+      })
+    }
+  };
+  //nor than the fields more flags add after the extra field, which are read
+  //to know their length
+  if buf[3] & (2 | 8 | 16) != 0 {
+    let mut after_extra = vec![0u8; data_length as usize];
+    file.read_exact_at(current_file_position, &mut after_extra)?;
+    match header_fields_length(buf[3], &after_extra) {
+      Some(fields_length) => data_length -= fields_length as u32,
+      None => {
+        return Err(BgzfError::InvalidHeader {
+          offset: block_offset,
+        })
+      }
+    }
+    current_file_position += after_extra.len() as u64 - u64::from(data_length);
+  }
+
+  //Skip data block
+  current_file_position += u64::from(data_length) + 4;
+
+  let mut buf_isize = [0; 4];
+  file.read_exact_at(current_file_position, &mut buf_isize)?;
+
+  let i_size: u32 = (buf_isize[0] as u32)
+    | ((buf_isize[1] as u32) << 8)
+    | ((buf_isize[2] as u32) << 16)
+    | ((buf_isize[3] as u32) << 24);
+  //no deflate data at all holds nothing, not even the empty data of the EOF
+  //marker, which takes 2 bytes
+  if data_length == 0 && i_size > 0 {
+    if parse_mode == ParseMode::Permissive {
+      let block = BgzfBlock {
+        input_offset: 0,
+        block_offset,
+        input_length: 0,
+        block_size,
+      };
+      return Ok((block, Some(AnomalyKind::InvalidBlock)));
+    }
+    return Err(BgzfError::InvalidBlock {
+      offset: block_offset,
+      data_length,
+      uncompressed_length: i_size,
+    });
+  }
+  //a block holds 64KiB at most, and deflate data can not hold more than
+  //MAX_DEFLATE_RATIO times its length
+  if i_size as usize > MAX_BLOCK_SIZE
+    || u64::from(i_size) > u64::from(data_length) * MAX_DEFLATE_RATIO
+  {
+    return Err(BgzfError::InvalidFooter {
+      offset: block_offset,
+      uncompressed_length: i_size,
+    });
+  }
+
+  //the uncompressed offset is only known to the caller
+  let block = BgzfBlock {
+    input_offset: 0,
+    block_offset,
+    input_length: i_size,
+    block_size,
+  };
+  Ok((block, anomaly))
+}
+
+/// Returns the length of the header fields the gzip flags `flags` add after
+/// the extra field, FNAME and FCOMMENT, zero terminated, then the 2 bytes of
+/// FHCRC, given the bytes of the block following the extra field. None when
+/// they do not fit in them.
+fn header_fields_length(flags: u8, after_extra: &[u8]) -> Option<usize> {
+  let mut length = 0;
+  for &flag in &[8, 16] {
+    if flags & flag != 0 {
+      length += after_extra[length..].iter().position(|&byte| byte == 0)? + 1;
+    }
+  }
+  if flags & 2 != 0 {
+    length += 2;
+  }
+  if length > after_extra.len() {
+    return None;
+  }
+  Some(length)
+}
+
+/// Tells whether the gzip flags `flags` of a block header set FEXTRA, which
+/// holds the block size, and none of the reserved bits
+fn valid_flags(flags: u8) -> bool {
+  flags & 4 != 0 && flags & 0xe0 == 0
+}
+
+/// Finds the BC subfield in the `extra` field of a block header. Returns its
+/// position in the field and the BSIZE it holds, None when there is none, it
+/// is not 2 bytes long or the subfields before it run past the field.
+fn find_bsize(extra: &[u8]) -> Option<(usize, u16)> {
+  let mut position = 0;
+  while position + 4 <= extra.len() {
+    let length = usize::from(u16::from_le_bytes([
+      extra[position + 2],
+      extra[position + 3],
+    ]));
+    if extra[position..position + 2] == [66, 67] {
+      if length != 2 || position + 6 > extra.len() {
+        return None;
+      }
+      let bsize = u16::from_le_bytes([extra[position + 4], extra[position + 5]]);
+      return Some((position, bsize));
+    }
+    position += 4 + length;
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use positioned_io::WriteAt;
+  use std::fs;
+  use std::io::Write;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Condvar;
+  use std::thread;
+  use std::time::{Duration, Instant};
+
+  /// Same text as in bgzf_test.bgz
+  const TEST_CONTENT: &[u8] = b"This is just a bgzf test,lets see how it reacts. :). I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarantee that it will work. So I am just trying to test bgzf with this text file. Have a great day software lovers.\n";
+
+  /// Writes the test content as blocks of `block_size` bytes to a temporary file
+  fn test_fixture(name: &str, block_size: usize) -> String {
+    fixtures::write_temp(name, &fixtures::make_bgzf(TEST_CONTENT, block_size)).unwrap()
+  }
+
+  #[test]
+  fn test_read_block_func() {
+    let path = test_fixture("read_block", 100);
+    let bgzf_file = File::open(&path).unwrap();
+    match read_block(&bgzf_file, 0) {
+      Ok(block) => {
+        assert_eq!(block.block_offset, 0);
+        let header = fs::read(&path).unwrap();
+        let bsize = u16::from_le_bytes([header[16], header[17]]);
+        assert_eq!(block.block_size, u32::from(bsize) + 1);
+        assert_eq!(block.input_length, 100);
+      }
+      Err(_e) => panic!(),
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_block_bad_lengths() {
+    let bgzf = fs::read("bgzf_test.bgz").unwrap();
+    let damaged = |at: usize, value: u16| {
+      let mut damaged = bgzf.clone();
+      damaged[at..at + 2].copy_from_slice(&value.to_le_bytes());
+      read_block(&damaged, 0)
+    };
+    //BSIZE shorter than the header, extra field and footer
+    for &bsize in &[24, 6, 0] {
+      assert!(matches!(
+        damaged(16, bsize),
+        Err(BgzfError::InvalidHeader { offset: 0 })
+      ));
+    }
+    //an extra field too short for the BC subfield
+    for &xlen in &[4, 0] {
+      assert!(matches!(
+        damaged(10, xlen),
+        Err(BgzfError::BadSubfield { offset: 0 })
+      ));
+    }
+    //the smallest BSIZE, the EOF marker with its deflate data cut to an
+    //empty one
+    let mut eof_marker = EOF_MARKER.to_vec();
+    eof_marker[16..18].copy_from_slice(&25u16.to_le_bytes());
+    assert_eq!(26, read_block(&eof_marker, 0).unwrap().block_size);
+    //ISIZE past the 64KiB a block holds, or than its deflate data can hold
+    let footer = bgzf.len() - 28 - 4;
+    assert!(matches!(
+      damaged(footer + 2, 1),
+      Err(BgzfError::InvalidFooter {
+        offset: 0,
+        uncompressed_length: 65_816
+      })
+    ));
+    eof_marker = EOF_MARKER.to_vec();
+    eof_marker[24..26].copy_from_slice(&2065u16.to_le_bytes());
+    assert!(matches!(
+      read_block(&eof_marker, 0),
+      Err(BgzfError::InvalidFooter {
+        offset: 0,
+        uncompressed_length: 2065
+      })
+    ));
+    eof_marker[24..26].copy_from_slice(&2064u16.to_le_bytes());
+    assert_eq!(2064, read_block(&eof_marker, 0).unwrap().input_length);
+  }
+
+  #[test]
+  fn test_open_with_bad_isize() {
+    //the footer of the fourth block claims 4GiB, which is never allocated
+    let mut bgzf = fixtures::make_bgzf(TEST_CONTENT, 50);
+    let path = fixtures::write_temp("bad_isize", &bgzf).unwrap();
+    let fourth = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(3)
+      .unwrap();
+    let next = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(4)
+      .unwrap();
+    let footer = next.compressed_offset as usize - 4;
+    bgzf[footer..footer + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&path, &bgzf).unwrap();
+    let is_bad_isize = |error: BgzfError| {
+      matches!(error, BgzfError::InvalidFooter {
+        offset,
+        uncompressed_length: u32::MAX
+      } if offset == fourth.compressed_offset)
+    };
+    assert!(is_bad_isize(BgzfReader::new(path.clone()).err().unwrap()));
+    let reader = BgzfReader::builder().lazy(true).open(path.clone()).unwrap();
+    let mut all = vec![0; 280];
+    assert_eq!(150, reader.read_to(&mut all).unwrap());
+    assert!(is_bad_isize(reader.read_to(&mut all).err().unwrap()));
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    assert_eq!(150, reader.total_uncompressed_length());
+    assert_eq!(150, reader.read_to(&mut all).unwrap());
+    assert_eq!(&TEST_CONTENT[..150], &all[..150]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_max_blocks() {
+    //six blocks, of 50 bytes but the last
+    let path = test_fixture("max_blocks", 50);
+    let limit = |error: BgzfError| {
+      matches!(
+        error,
+        BgzfError::LimitExceeded {
+          limit: ResourceLimit::Blocks,
+          max: 4
+        }
+      )
+    };
+    let open = |builder: BgzfReaderBuilder| builder.max_blocks(4).open(path.clone());
+    assert!(limit(open(BgzfReader::builder()).err().unwrap()));
+    assert!(limit(
+      open(BgzfReader::builder().allow_truncated(true))
+        .err()
+        .unwrap()
+    ));
+    assert_eq!(
+      6,
+      BgzfReader::builder()
+        .max_blocks(6)
+        .open(path.clone())
+        .unwrap()
+        .num_blocks()
+    );
+
+    //opened lazily or indexed in the background, the reads past the limit
+    //fail
+    for builder in [
+      BgzfReader::builder().lazy(true),
+      BgzfReader::builder().background_indexing(true),
+    ] {
+      let reader = open(builder).unwrap();
+      let mut all = vec![0; 280];
+      assert_eq!(200, reader.read_to(&mut all).unwrap());
+      assert_eq!(&TEST_CONTENT[..200], &all[..200]);
+      assert!(limit(reader.read_to(&mut all).err().unwrap()));
+      assert_eq!(4, reader.num_blocks());
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_max_uncompressed_length() {
+    let path = test_fixture("max_uncompressed_length", 50);
+    let open = |max: u64| {
+      BgzfReader::builder()
+        .max_uncompressed_length(max)
+        .open(path.clone())
+    };
+    assert_eq!(280, open(280).unwrap().total_uncompressed_length());
+    //a block going past the limit is not indexed
+    let error = open(120).err().unwrap();
+    assert!(matches!(
+      error,
+      BgzfError::LimitExceeded {
+        limit: ResourceLimit::UncompressedLength,
+        max: 120
+      }
+    ));
+    assert_eq!(
+      "The file goes past the limit of 120 bytes of uncompressed data",
+      error.to_string()
+    );
+    let reader = BgzfReader::builder()
+      .lazy(true)
+      .max_uncompressed_length(120)
+      .open(path.clone())
+      .unwrap();
+    let mut all = vec![0; 280];
+    assert_eq!(100, reader.read_to(&mut all).unwrap());
+    assert!(reader.read_to(&mut all).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_max_allocation() {
+    let path = test_fixture("max_allocation", 50);
+    let reader = BgzfReader::builder()
+      .max_allocation(279)
+      .open(path.clone())
+      .unwrap();
+    let limit = |error: BgzfError| {
+      matches!(
+        error,
+        BgzfError::LimitExceeded {
+          limit: ResourceLimit::Allocation,
+          max: 279
+        }
+      )
+    };
+    assert!(limit(reader.decompress_all_parallel(2).err().unwrap()));
+    assert!(limit(reader.load_all().err().unwrap()));
+    //reads allocate a block at a time
+    let mut all = vec![0; 280];
+    assert_eq!(280, reader.read_to(&mut all).unwrap());
+    assert_eq!(TEST_CONTENT, &all[..]);
+    let reader = BgzfReader::builder()
+      .max_allocation(280)
+      .open(path.clone())
+      .unwrap();
+    assert_eq!(
+      TEST_CONTENT,
+      &reader.decompress_all_parallel(2).unwrap()[..]
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_bgzf_reader_new_func() {
+    let path = test_fixture("reader_new", 100);
+    let bgzf_reader = BgzfReader::new(path.clone());
+    match bgzf_reader {
+      Ok(reader) => {
+        let expected_uncompressed_length = 280;
+        assert_eq!(3, reader.blocks().len());
+        assert_eq!(expected_uncompressed_length, reader.input_length);
+        assert_eq!(0, reader.compressed_position());
+
+        let mut next_block_offset = 0;
+        for block in reader.blocks().iter() {
+          assert_eq!(next_block_offset, block.block_offset);
+          next_block_offset += u64::from(block.block_size);
+          assert_eq!(
+            min(100, 280 - block.input_offset),
+            u64::from(block.input_length)
+          );
+        }
+        assert_eq!(
+          vec![0, 100, 200],
+          reader
+            .blocks()
+            .iter()
+            .map(|block| block.input_offset)
+            .collect::<Vec<_>>()
+        );
+      }
+      Err(_e) => panic!(),
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
   fn test_bgzf_read_method() {
+    let path = test_fixture("read_method", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut content = vec![0; 10];
+    match reader.read(&mut content, 0, 10) {
+      Ok(val) => {
+        assert_eq!(10, val);
+      }
+      Err(_e) => {
+        panic!();
+      }
+    };
+    let file_content = str::from_utf8(&content).unwrap();
+    assert_eq!("This is ju", file_content);
+
+    reader.seek(20);
+    let mut content_two = vec![0; 32];
+    match reader.read(&mut content_two, 0, 32) {
+      Ok(val) => {
+        assert_eq!(32, val);
+      }
+      Err(_e) => {
+        panic!();
+      }
+    };
+    let file_content_two = str::from_utf8(&content_two).unwrap();
+    assert_eq!("test,lets see how it reacts. :).", file_content_two);
+
+    //a block further down the file, on a fresh reader
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(245);
+    let mut content_three = vec![0; 35];
+    assert_eq!(35, reader.read(&mut content_three, 0, 35).unwrap());
+    assert_eq!(
+      "Have a great day software lovers.\n",
+      str::from_utf8(&content_three[1..]).unwrap()
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_seek_method() {
+    let path = test_fixture("seek", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(33);
+    assert_eq!(33, reader.pos.get());
+    assert_eq!(0, reader.compressed_position());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_chain_broken() {
+    //the second block claims a byte less than it holds, it still reads but
+    //leads into its own footer
+    let bgzf = fixtures::make_with_bad_bsize(TEST_CONTENT, 100, 1, -1);
+    let path = fixtures::write_temp("chain_broken", &bgzf).unwrap();
+    let file = File::open(&path).unwrap();
+    let second = read_block(&file, 0).unwrap().block_size;
+    let third =
+      u64::from(second) + u64::from(read_block(&file, u64::from(second)).unwrap().block_size);
+    assert!(read_block(&file, third).is_err());
+    assert!(read_block(&file, third + 1).is_ok());
+    let second_offset = u64::from(second);
+    let broken = |error: &BgzfError| matches!(error, &BgzfError::BlockChainBroken { offset } if offset == second_offset);
+    for threads in [1, 4] {
+      let error = BgzfReader::builder()
+        .threads(threads)
+        .open(path.clone())
+        .err()
+        .unwrap();
+      assert!(broken(&error), "{:?}", error);
+    }
+    assert_eq!(
+      format!(
+        "The size of the block at offset {} does not lead to another block",
+        second
+      ),
+      BgzfReader::new(path.clone()).err().unwrap().to_string()
+    );
+    //lazily, once the index gets past the block, whose footer gives it far
+    //more data than it holds
+    let reader = BgzfReader::builder().lazy(true).open(path.clone()).unwrap();
+    let mut content = vec![0; 100];
+    assert_eq!(100, reader.read_to(&mut content).unwrap());
+    reader.seek(1 << 20);
+    let error = reader.read_to(&mut content).unwrap_err();
+    assert!(broken(&error), "{:?}", error);
+
+    //recovering, the block is skipped up to the next one
+    let reader = BgzfReader::builder()
+      .recover(true)
+      .open(path.clone())
+      .unwrap();
+    assert_eq!(
+      vec![DataGap {
+        compressed_range: u64::from(second)..third + 1,
+        uoffset_range: 100..100
+      }],
+      reader.gaps()
+    );
+    assert_eq!(180, reader.total_uncompressed_length());
+    let mut content = [0; 180];
+    reader.read_exact(&mut content[..100]).unwrap();
+    reader.read_exact(&mut content[100..]).unwrap();
+    assert_eq!(&TEST_CONTENT[..100], &content[..100]);
+    assert_eq!(&TEST_CONTENT[200..], &content[100..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_junk_after_whole_block() {
+    //a few bytes of junk after the first block, which is whole, end close
+    //enough to the second block to look like a block size slip
+    let mut bgzf = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let first_end = read_block(&bgzf, 0).unwrap().block_size as usize;
+    bgzf.splice(first_end..first_end, [0x55; 5]);
+    let path = fixtures::write_temp("junk_after_whole_block", &bgzf).unwrap();
+    let error = BgzfReader::new(path.clone()).err().unwrap();
+    assert!(
+      !matches!(error, BgzfError::BlockChainBroken { .. }),
+      "{:?}",
+      error
+    );
+
+    //recovering, the junk is the gap and the first block is kept
+    let reader = BgzfReader::builder()
+      .recover(true)
+      .open(path.clone())
+      .unwrap();
+    let first_end = first_end as u64;
+    assert_eq!(
+      vec![DataGap {
+        compressed_range: first_end..first_end + 5,
+        uoffset_range: 100..100
+      }],
+      reader.gaps()
+    );
+    assert_eq!(
+      TEST_CONTENT.len() as u64,
+      reader.total_uncompressed_length()
+    );
+    //a read stops at the gap, the next one goes on after it
+    let mut content = vec![0; TEST_CONTENT.len()];
+    reader.read_exact(&mut content[..100]).unwrap();
+    reader.read_exact(&mut content[100..]).unwrap();
+    assert_eq!(TEST_CONTENT, &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_index_of_recovered_file() {
+    let bgzf = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let first_end = read_block(&bgzf, 0).unwrap().block_size as usize;
+    let recovered = |junk_length: usize| {
+      let mut damaged = bgzf.clone();
+      damaged.splice(first_end..first_end, vec![0x55; junk_length]);
+      BgzfReader::builder()
+        .recover(true)
+        .open_bytes(damaged)
+        .unwrap()
+    };
+
+    //damage shorter than an empty block leaves a gap no index can hold
+    let reader = recovered(20);
+    assert_eq!(1, reader.gaps().len());
+    assert_eq!(
+      Err(IndexError::Gap {
+        block: 1,
+        compressed_offset: first_end as u64 + 20,
+        previous_end: first_end as u64
+      }),
+      reader.index()
+    );
+
+    //longer damage fits, like a skipped empty block
+    let reader = recovered(40);
+    assert_eq!(1, reader.gaps().len());
+    assert_eq!(3, reader.index().unwrap().blocks().len());
+  }
+
+  #[test]
+  fn test_compressed_position() {
+    let path = test_fixture("compressed_position", 100);
+    let data_end = fs::metadata(&path).unwrap().len() - EOF_MARKER.len() as u64;
+    for lazy in [false, true] {
+      let reader = BgzfReader::builder().lazy(lazy).open(path.clone()).unwrap();
+      let offsets = BgzfReader::new(path.clone())
+        .unwrap()
+        .block_offsets()
+        .map(|block| block.compressed_offset)
+        .collect::<Vec<_>>();
+      //sequential reads move it block by block
+      let mut content = vec![0; 60];
+      let mut positions = Vec::new();
+      while reader.read_to(&mut content).unwrap() > 0 {
+        positions.push((reader.pos.get(), reader.compressed_position()));
+      }
+      assert_eq!(
+        vec![
+          (60, 0),
+          (120, offsets[1]),
+          (180, offsets[1]),
+          (240, offsets[2]),
+          (280, data_end)
+        ],
+        positions
+      );
+      //and seeks anywhere, past the end too
+      reader.seek(99);
+      assert_eq!(0, reader.compressed_position());
+      reader.seek(100);
+      assert_eq!(offsets[1], reader.compressed_position());
+      reader.seek(1000);
+      assert_eq!(data_end, reader.compressed_position());
+      assert_eq!(reader.compressed_length(), reader.compressed_position());
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_to() {
+    let path = test_fixture("read_to", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut vec = vec![0; 52];
+    let data_read = reader.read_to(&mut vec);
+    assert_eq!(data_read.unwrap(), 52);
+    assert_eq!(
+      "This is just a bgzf test,lets see how it reacts. :).",
+      str::from_utf8(&vec).unwrap()
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_exact() {
+    let path = test_fixture("read_exact", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    //across blocks
+    let mut content = vec![0; 250];
+    reader.seek(20);
+    reader.read_exact(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[20..270], &content[..]);
+    reader.read_exact(&mut []).unwrap();
+    //10 bytes are left
+    match reader.read_exact(&mut content) {
+      Err(BgzfError::UnexpectedEof { requested, read }) => assert_eq!((250, 10), (requested, read)),
+      other => panic!("{:?}", other),
+    }
+    assert_eq!(&TEST_CONTENT[270..], &content[..10]);
+    assert_eq!(280, reader.pos.get());
+    let error = reader.read_exact(&mut content[..1]).unwrap_err();
+    assert_eq!(
+      "The data ended after 0 of the 1 bytes asked for",
+      error.to_string()
+    );
+    assert_eq!(io::ErrorKind::UnexpectedEof, io::Error::from(error).kind());
+    //and the std one gives the same kind
+    reader.seek(270);
+    let error = io::Read::read_exact(&mut &reader, &mut content).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    fs::remove_file(path).unwrap();
+
+    //a block cut off is an early end of the file, and allowed, the data ends
+    //before it
+    let bgzf = fixtures::make_truncated(TEST_CONTENT, 100, 10);
+    let path = fixtures::write_temp("read_exact_truncated", &bgzf).unwrap();
+    let error = BgzfReader::new(path.clone()).err().unwrap();
+    assert!(matches!(error, BgzfError::Truncated(_)), "{:?}", error);
+    assert_eq!(io::ErrorKind::UnexpectedEof, io::Error::from(error).kind());
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    assert!(matches!(
+      reader.read_exact(&mut content),
+      Err(BgzfError::UnexpectedEof {
+        requested: 250,
+        read: 200
+      })
+    ));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_parameters() {
+    let path = test_fixture("read_parameters", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(10);
+    let mut content = [0; 10];
+    //no bytes asked for, with room for them or not
+    assert_eq!(0, reader.read(&mut [], 0, 0).unwrap());
+    assert_eq!(0, reader.read_to(&mut []).unwrap());
+    assert_eq!(0, reader.read(&mut content, 0, 0).unwrap());
+    assert_eq!(0, reader.read(&mut content, 10, 0).unwrap());
+    assert_eq!(0, reader.read_at(0, &mut []).unwrap());
+    assert_eq!(0, reader.read_vectored(&mut []).unwrap());
+    assert_eq!(0, io::Read::read(&mut &reader, &mut []).unwrap());
+    assert_eq!(10, reader.pos.get());
+    //offsets and lengths going past the buffer
+    for (buffer_length, off, len) in [(10, 11, 0), (10, 5, 6), (10, 10, 1), (0, 0, 1), (0, 1, 0)] {
+      let mut content = vec![0; buffer_length];
+      match reader.read(&mut content, off, len) {
+        Err(BgzfError::OutOfRange {
+          pos,
+          len: available,
+        }) => {
+          assert_eq!(
+            (off + len, buffer_length),
+            (pos as usize, available as usize)
+          )
+        }
+        other => panic!("{:?}", other),
+      }
+    }
+    assert_eq!(10, reader.pos.get());
+    //the end of the buffer is where it should be
+    assert_eq!(4, reader.read(&mut content, 6, 4).unwrap());
+    assert_eq!(&TEST_CONTENT[10..14], &content[6..]);
+    //no bytes at the end of the data
+    reader.seek(280);
+    assert_eq!(0, reader.read(&mut content, 0, 0).unwrap());
+    fs::remove_file(path).unwrap();
+
+    //nor in a gap
+    let bgzf = fixtures::make_with_bad_crc(TEST_CONTENT, 100, 1);
+    let path = fixtures::write_temp("read_parameters_gap", &bgzf).unwrap();
+    let reader = BgzfReader::builder()
+      .recover(true)
+      .verify_crc(true)
+      .open(path.clone())
+      .unwrap();
+    reader.seek(150);
+    assert!(reader.read_to(&mut content).is_err());
+    assert_eq!(0, reader.read(&mut content, 0, 0).unwrap());
+    assert_eq!(150, reader.pos.get());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_past_largest_offset() {
+    let path = test_fixture("largest_offset", 100);
+    for (lazy, recover) in [(false, false), (true, false), (false, true)] {
+      let reader = BgzfReader::builder()
+        .lazy(lazy)
+        .recover(recover)
+        .open(path.clone())
+        .unwrap();
+      let mut content = vec![0; 10];
+      //a read ending past u64::MAX fails rather than wrapping around to the
+      //start of the data, without moving the position
+      reader.seek(u64::MAX - 4);
+      assert!(matches!(
+        reader.read_to(&mut content),
+        Err(BgzfError::PositionOverflow {
+          pos,
+          len: 10
+        }) if pos == u64::MAX - 4
+      ));
+      assert_eq!(u64::MAX - 4, reader.pos.get());
+      assert!(matches!(
+        reader.read_at(u64::MAX, &mut content[..1]),
+        Err(BgzfError::PositionOverflow { .. })
+      ));
+      //one ending right at it is past the end of the data
+      assert_eq!(0, reader.read_at(u64::MAX - 10, &mut content).unwrap());
+      //a length past the buffer is refused before that
+      assert!(matches!(
+        reader.read(&mut content, 0, usize::MAX),
+        Err(BgzfError::OutOfRange { .. })
+      ));
+      assert!(matches!(
+        reader.read(&mut content, usize::MAX, 1),
+        Err(BgzfError::OutOfRange { .. })
+      ));
+      //the data still reads
+      reader.seek(270);
+      assert_eq!(10, reader.read_to(&mut content).unwrap());
+      assert_eq!(&TEST_CONTENT[270..], &content[..]);
+    }
+    let error = BgzfError::PositionOverflow {
+      pos: u64::MAX,
+      len: 1,
+    };
+    assert_eq!(
+      format!(
+        "A read of 1 bytes from offset {} goes past the largest offset",
+        u64::MAX
+      ),
+      error.to_string()
+    );
+    assert_eq!(io::ErrorKind::InvalidInput, io::Error::from(error).kind());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_offsets() {
+    let path = test_fixture("block_offsets", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    assert_eq!(3, blocks.len());
+    assert_eq!(3, reader.num_blocks());
+    let mut compressed_offset = 0;
+    for (index, block) in blocks.iter().enumerate() {
+      assert_eq!(index as u64 * 100, block.uncompressed_offset);
+      assert_eq!(compressed_offset, block.compressed_offset);
+      compressed_offset += u64::from(block.block_size);
+    }
+    assert_eq!(80, blocks[2].uncompressed_length);
+    assert_eq!(
+      fs::metadata(&path).unwrap().len(),
+      compressed_offset + EOF_MARKER.len() as u64
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compression_statistics() {
+    //stored blocks are 18 header bytes, 5 stored block header bytes, the data
+    //and 8 footer bytes
+    let mut writer = BgzfWriter::builder()
+      .compression_level(0)
+      .block_size(100)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(TEST_CONTENT).unwrap();
+    let bgzf = writer.finish().unwrap();
+    let path = fixtures::write_temp("compression_statistics", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(131 + 131 + 111, reader.compressed_length());
+    assert_eq!(373 + 28, reader.compressed_length_with_eof().unwrap());
+    assert_eq!(280.0 / 373.0, reader.compression_ratio());
+    assert_eq!(
+      BlockSizeSummary {
+        blocks: 3,
+        min_block_size: 111,
+        max_block_size: 131,
+        mean_block_size: 373.0 / 3.0,
+        min_uncompressed_length: 80,
+        max_uncompressed_length: 100,
+        mean_uncompressed_length: 280.0 / 3.0,
+      },
+      reader.block_size_summary()
+    );
+    fs::remove_file(path).unwrap();
+
+    let path = fixtures::write_temp(
+      "compression_statistics_no_eof",
+      &bgzf[..bgzf.len() - EOF_MARKER.len()],
+    )
+    .unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(373, reader.compressed_length());
+    assert_eq!(373, reader.compressed_length_with_eof().unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compression_statistics_of_empty_file() {
+    let path = fixtures::write_temp("compression_statistics_empty", &EOF_MARKER).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(0, reader.compressed_length());
+    assert_eq!(28, reader.compressed_length_with_eof().unwrap());
+    assert_eq!(0.0, reader.compression_ratio());
+    assert_eq!(BlockSizeSummary::default(), reader.block_size_summary());
+    fs::remove_file(path).unwrap();
+  }
+
+  /// Reads `start..end` with a reader of its own, a block at a time
+  fn read_range(path: &str, start: u64, end: u64) -> Vec<u8> {
+    let reader = BgzfReader::new(String::from(path)).unwrap();
+    let mut content = Vec::new();
+    reader.seek(start);
+    while reader.pos.get() < end {
+      let (_index, block) = reader.block_containing(reader.pos.get()).unwrap();
+      let mut data = vec![0; block.uncompressed_length as usize];
+      reader.read_to(&mut data).unwrap();
+      content.extend_from_slice(&data);
+    }
+    content
+  }
+
+  #[test]
+  fn test_partition_in_parallel() {
+    let data: Vec<u8> = (0..20_000u32).flat_map(|n| n.to_le_bytes()).collect();
+    let path = fixtures::write_temp("partition", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let ranges = reader.partition(7);
+    assert_eq!(7, ranges.len());
+    assert_eq!(0, ranges[0].0);
+    assert_eq!(80_000, ranges[6].1);
+    for (range, next) in ranges.iter().zip(ranges.iter().skip(1)) {
+      assert_eq!(range.1, next.0);
+      assert_eq!(0, range.1 % 1000);
+      //80 blocks over 7 ranges: 11 or 12 blocks each
+      assert!(range.1 - range.0 == 11_000 || range.1 - range.0 == 12_000);
+    }
+
+    let parts: Vec<Vec<u8>> = thread::scope(|scope| {
+      let workers: Vec<_> = ranges
+        .iter()
+        .map(|&(start, end)| {
+          let path = &path;
+          scope.spawn(move || read_range(path, start, end))
+        })
+        .collect();
+      workers
+        .into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect()
+    });
+    assert_eq!(data, parts.concat());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_partition_degenerate_cases() {
+    let path = test_fixture("partition_few_blocks", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(vec![(0, 100), (100, 200), (200, 280)], reader.partition(10));
+    assert_eq!(vec![(0, 280)], reader.partition(1));
+    assert!(reader.partition(0).is_empty());
+    fs::remove_file(path).unwrap();
+
+    let path = test_fixture("partition_one_block", 65280);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(vec![(0, 280)], reader.partition(4));
+    fs::remove_file(path).unwrap();
+
+    let path = fixtures::write_temp("partition_empty", &EOF_MARKER).unwrap();
+    assert!(BgzfReader::new(path.clone())
+      .unwrap()
+      .partition(4)
+      .is_empty());
+    fs::remove_file(path).unwrap();
+
+    //uneven blocks: the big block stays alone
+    let mut bgzf = fixtures::make_without_eof(&[b'a'; 10], 10);
+    bgzf.extend(fixtures::make_without_eof(&[b'b'; 1000], 1000));
+    bgzf.extend(fixtures::make_bgzf(&[b'c'; 30], 10));
+    let path = fixtures::write_temp("partition_uneven", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(vec![(0, 10), (10, 1010), (1010, 1040)], reader.partition(3));
+    assert_eq!(vec![(0, 1010), (1010, 1040)], reader.partition(2));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_many_blocks() {
+    let data: Vec<u8> = (0..10_000u32).flat_map(|n| n.to_le_bytes()).collect();
+    let path = fixtures::write_temp("many_blocks", &fixtures::make_bgzf(&data, 8)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(5000, reader.num_blocks());
+    //backwards, as seeking forward past the cached block is not supported
+    for pos in (0..40_000u64).rev().step_by(37) {
+      reader.seek(pos);
+      let mut byte = [0; 1];
+      assert_eq!(1, reader.read_to(&mut byte).unwrap());
+      assert_eq!(data[pos as usize], byte[0]);
+
+      let (index, block) = reader.block_containing(pos).unwrap();
+      assert_eq!(pos as usize / 8, index);
+      assert_eq!(pos / 8 * 8, block.uncompressed_offset);
+      let voffset = reader.uoffset_to_voffset(pos).unwrap();
+      assert_eq!(block.compressed_offset, voffset >> 16);
+      assert_eq!(pos, reader.voffset_to_uoffset(voffset).unwrap());
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_open_with_progress() {
+    let path = test_fixture("open_progress", 10);
+    let sizes = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .map(|block| u64::from(block.block_size))
+      .collect::<Vec<_>>();
+    let mut reports = Vec::new();
+    let reader = BgzfReader::builder()
+      .open_with_progress(path.clone(), 10, |progress| {
+        reports.push(progress);
+        ControlFlow::Continue(())
+      })
+      .unwrap();
+    //every 10 blocks, then once at the end of the 28 blocks
+    assert_eq!(
+      vec![10, 20, 28],
+      reports
+        .iter()
+        .map(|progress| progress.blocks)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(sizes[..10].iter().sum::<u64>(), reports[0].compressed_bytes);
+    assert_eq!(100, reports[0].uncompressed_bytes);
+    assert_eq!(sizes.iter().sum::<u64>(), reports[2].compressed_bytes);
+    assert_eq!(280, reports[2].uncompressed_bytes);
+    assert_eq!(280, reader.input_length);
+    let mut content = vec![0; 10];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[..10], &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_open_with_progress_cancelled() {
+    let path = test_fixture("open_progress_cancelled", 10);
+    let mut calls = 0;
+    let result = BgzfReader::builder().open_with_progress(path.clone(), 1, |progress| {
+      calls += 1;
+      if progress.blocks == 5 {
+        ControlFlow::Break(())
+      } else {
+        ControlFlow::Continue(())
+      }
+    });
+    assert_eq!(5, calls);
+    let error = result.err().unwrap();
+    let cancelled = match error {
+      BgzfError::Cancelled(cancelled) => cancelled,
+      error => panic!("{}", error),
+    };
+    assert_eq!(5, cancelled.progress.blocks);
+    assert_eq!(50, cancelled.progress.uncompressed_bytes);
+    assert_eq!(
+      "Opening was cancelled after indexing 5 blocks",
+      error.to_string()
+    );
+    fs::remove_file(path).unwrap();
+
+    let path = fixtures::write_temp("open_progress_empty", &EOF_MARKER).unwrap();
+    let mut reports = Vec::new();
+    BgzfReader::builder()
+      .open_with_progress(path.clone(), 0, |progress| {
+        reports.push(progress);
+        ControlFlow::Continue(())
+      })
+      .unwrap();
+    assert_eq!(vec![IndexProgress::default()], reports);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_concatenated_files() {
+    //two files joined with cat, the EOF marker of the first is in the middle
+    let bgzf = [
+      fixtures::make_bgzf(TEST_CONTENT, 100),
+      fixtures::make_bgzf(&TEST_CONTENT[..50], 30),
+    ]
+    .concat();
+    let expected = [TEST_CONTENT, &TEST_CONTENT[..50]].concat();
+    let path = fixtures::write_temp("concatenated", &bgzf).unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(330, reader.input_length);
+    assert_eq!(5, reader.num_blocks());
+    assert_eq!(expected, read_all(&reader).unwrap());
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    assert_eq!(
+      blocks[2].compressed_offset + u64::from(blocks[2].block_size) + EOF_MARKER.len() as u64,
+      blocks[3].compressed_offset
+    );
+    assert_eq!(
+      bgzf.len() as u64,
+      reader.compressed_length_with_eof().unwrap()
+    );
+
+    let lazy = lazy_reader(&path);
+    assert_eq!(expected, read_all(&lazy).unwrap());
+    let (parallel_blocks, input_length, truncation, _anomaly_scan) = scan_blocks_parallel(
+      &Source::File(File::open(&path).unwrap()),
+      3,
+      ParseMode::Default,
+      &Executor::default(),
+    )
+    .unwrap();
+    assert_eq!(None, truncation);
+    assert_eq!(330, input_length);
+    assert_eq!(
+      blocks,
+      parallel_blocks
+        .iter()
+        .map(BgzfBlock::info)
+        .collect::<Vec<_>>()
+    );
+    let block_chain = scan_block_chain(&Source::File(File::open(&path).unwrap())).unwrap();
+    assert_eq!(5, block_chain.block_offsets.len());
+    assert!(block_chain.has_eof_marker);
+
+    let indexed = BgzfReader::open_with_index(path.clone(), &reader.index().unwrap()).unwrap();
+    assert_eq!(expected, read_all(&indexed).unwrap());
+    fs::remove_file(path).unwrap();
+
+    //a file starting with an empty block
+    let bgzf = [&EOF_MARKER[..], &fixtures::make_bgzf(TEST_CONTENT, 100)].concat();
+    let path = fixtures::write_temp("leading_empty_block", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(28, reader.block_metadata(0).unwrap().compressed_offset);
+    assert_eq!(TEST_CONTENT, &read_all(&reader).unwrap()[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_truncated_files() {
+    let content = TEST_CONTENT.repeat(4);
+    let complete = fixtures::make_without_eof(&content, 100);
+    let path = fixtures::write_temp("truncated_layout", &complete).unwrap();
+    let last_block = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_metadata(11)
+      .unwrap()
+      .compressed_offset;
+    fs::remove_file(path).unwrap();
+    let last_block_size = complete.len() - last_block as usize;
+    //cut in the header, in the compressed data and in the footer
+    for &missing_bytes in &[last_block_size - 5, last_block_size / 2, 6, 1] {
+      let bgzf = fixtures::make_truncated(&content, 100, missing_bytes);
+      let path = fixtures::write_temp("truncated", &bgzf).unwrap();
+      let truncation = Truncated {
+        block_offset: last_block,
+      };
+
+      let error = BgzfReader::new(path.clone()).err().unwrap();
+      assert!(matches!(error, BgzfError::Truncated(t) if t == truncation));
+      assert!(error.to_string().contains("truncated"));
+      let error = BgzfReader::builder()
+        .open_with_progress(path.clone(), 1, |_progress| ControlFlow::Continue(()))
+        .err()
+        .unwrap();
+      assert!(matches!(error, BgzfError::Truncated(t) if t == truncation));
+      let (blocks, _input_length, parallel_truncation, _anomaly_scan) = scan_blocks_parallel(
+        &Source::File(File::open(&path).unwrap()),
+        2,
+        ParseMode::Default,
+        &Executor::default(),
+      )
+      .unwrap();
+      assert_eq!(11, blocks.len());
+      assert_eq!(Some(truncation), parallel_truncation);
+
+      //a lazy reader fails when a read gets to the missing block
+      let lazy = lazy_reader(&path);
+      let mut block = vec![0; 100];
+      for expected in content[..1100].chunks(100) {
+        assert_eq!(100, lazy.read_to(&mut block).unwrap());
+        assert_eq!(expected, &block[..]);
+      }
+      let error = lazy.read_to(&mut block).err().unwrap();
+      assert!(matches!(error, BgzfError::Truncated(t) if t == truncation));
+      assert!(lazy.is_truncated());
+
+      let reader = BgzfReader::builder()
+        .allow_truncated(true)
+        .open(path.clone())
+        .unwrap();
+      assert!(reader.is_truncated());
+      assert_eq!(Some(truncation), reader.truncation());
+      assert_eq!(1100, reader.total_uncompressed_length());
+      assert_eq!(&content[..1100], &read_all(&reader).unwrap()[..]);
+      assert_eq!(0, reader.read_to(&mut block).unwrap());
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_clean_ends_are_not_truncations() {
+    //without an EOF marker, and with bytes that are not a block after one
+    let without_eof = fixtures::make_without_eof(TEST_CONTENT, 100);
+    let mut trailing_bytes = fixtures::make_bgzf(TEST_CONTENT, 100);
+    trailing_bytes.extend_from_slice(b"not a block");
+    for (name, bgzf) in &[
+      ("without_eof", without_eof),
+      ("trailing_bytes", trailing_bytes),
+    ] {
+      let path = fixtures::write_temp(name, bgzf).unwrap();
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      assert!(!reader.is_truncated());
+      assert_eq!(TEST_CONTENT, &read_all(&reader).unwrap()[..]);
+      let (_blocks, _input_length, truncation, _anomaly_scan) = scan_blocks_parallel(
+        &Source::File(File::open(&path).unwrap()),
+        2,
+        ParseMode::Default,
+        &Executor::default(),
+      )
+      .unwrap();
+      assert_eq!(None, truncation);
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_cache_index_of_truncated_file() {
+    let bgzf = fixtures::make_truncated(TEST_CONTENT, 100, 10);
+    let path = fixtures::write_temp("cache_index_truncated", &bgzf).unwrap();
+    assert!(BgzfReader::builder()
+      .cache_index(true)
+      .open(path.clone())
+      .is_err());
+    let reader = BgzfReader::builder()
+      .cache_index(true)
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    assert!(reader.is_truncated());
+    assert!(fs::metadata(index_cache::cache_path(&path)).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_has_eof_marker() {
+    //an empty last block that is not the exact marker, its mtime is set
+    let mut empty_block = EOF_MARKER;
+    empty_block[4] = 1;
+    let with_eof = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let cases = vec![
+      ("eof_present", with_eof.clone(), true),
+      (
+        "eof_absent",
+        fixtures::make_without_eof(TEST_CONTENT, 100),
+        false,
+      ),
+      (
+        "eof_duplicated",
+        [&with_eof[..], &EOF_MARKER[..]].concat(),
+        true,
+      ),
+      (
+        "eof_empty_block",
+        [
+          &fixtures::make_without_eof(TEST_CONTENT, 100)[..],
+          &empty_block[..],
+        ]
+        .concat(),
+        false,
+      ),
+    ];
+    for (name, bgzf, has_eof_marker) in cases {
+      let path = fixtures::write_temp(name, &bgzf).unwrap();
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      assert_eq!(has_eof_marker, reader.has_eof_marker(), "{}", name);
+      assert_eq!(TEST_CONTENT, &read_all(&reader).unwrap()[..]);
+      assert_eq!(has_eof_marker, lazy_reader(&path).has_eof_marker());
+      let indexed = BgzfReader::open_with_index(path.clone(), &reader.index().unwrap()).unwrap();
+      assert_eq!(has_eof_marker, indexed.has_eof_marker());
+
+      let strict = BgzfReader::builder()
+        .require_eof_marker(true)
+        .open(path.clone());
+      assert_eq!(has_eof_marker, strict.is_ok(), "{}", name);
+      let strict = BgzfReader::builder()
+        .require_eof_marker(true)
+        .open_with_progress(path.clone(), 1, |_progress| ControlFlow::Continue(()));
+      assert_eq!(has_eof_marker, strict.is_ok(), "{}", name);
+      if !has_eof_marker {
+        assert!(strict.err().unwrap().to_string().contains("EOF marker"));
+      }
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_precomputed_index_reads_the_same() {
+    let mut content = Vec::new();
+    for i in 0..5_000 {
+      content.extend_from_slice(format!("{}\tprecomputed index\n", i).as_bytes());
+    }
+    let path =
+      fixtures::write_temp("precomputed_index", &fixtures::make_bgzf(&content, 997)).unwrap();
+    let scanned = BgzfReader::new(path.clone()).unwrap();
+    let mut saved = Vec::new();
+    scanned.index().unwrap().save(&mut saved).unwrap();
+    let index = BgzfIndex::load(&saved[..]).unwrap();
+
+    let opened = BgzfReader::open_with_index(path.clone(), &index).unwrap();
+    let from_file = BgzfReader::from_file_with_index(File::open(&path).unwrap(), &index).unwrap();
+    let scanned_content = read_all(&scanned).unwrap();
+    assert_eq!(content, scanned_content);
+    assert_eq!(scanned_content, read_all(&opened).unwrap());
+    assert_eq!(scanned_content, read_all(&from_file).unwrap());
+    assert_eq!(
+      scanned.block_offsets().collect::<Vec<_>>(),
+      from_file.block_offsets().collect::<Vec<_>>()
+    );
+    //positioned reads within blocks, walking backwards
+    for position in (0..content.len() as u64 - 10).rev().step_by(3001) {
+      let mut expected = vec![0; 10];
+      let mut read = vec![0; 10];
+      let block_end = scanned
+        .block_containing(position)
+        .map(|(_index, block)| block.uncompressed_offset + u64::from(block.uncompressed_length))
+        .unwrap();
+      let length = min(10, block_end - position) as usize;
+      scanned.seek(position);
+      from_file.seek(position);
+      scanned.read_to(&mut expected[..length]).unwrap();
+      from_file.read_to(&mut read[..length]).unwrap();
+      assert_eq!(expected, read);
+    }
+
+    let other_path = test_fixture("precomputed_index_other", 100);
+    let error = BgzfReader::from_file_with_index(File::open(&other_path).unwrap(), &index)
+      .err()
+      .unwrap();
+    assert!(matches!(error, BgzfError::Index(_)));
+    fs::remove_file(path).unwrap();
+    fs::remove_file(other_path).unwrap();
+  }
+
+  #[test]
+  fn test_reads_after_a_failed_decompression() {
+    let mut bgzf = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let path = fixtures::write_temp("failed_decompression", &bgzf).unwrap();
+    let second_block = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_metadata(1)
+      .unwrap()
+      .compressed_offset as usize;
+    //reserved deflate block type in the first compressed byte
+    bgzf[second_block + 18] = 0xff;
+    fs::write(&path, &bgzf).unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut block = vec![0; 100];
+    reader.seek(100);
+    assert!(reader.read_to(&mut block).is_err());
+    //the same decompressor goes on with the blocks around it
+    reader.seek(200);
+    assert_eq!(80, reader.read_to(&mut block[..80]).unwrap());
+    assert_eq!(&TEST_CONTENT[200..], &block[..80]);
+    reader.seek(0);
+    assert_eq!(100, reader.read_to(&mut block).unwrap());
+    assert_eq!(&TEST_CONTENT[..100], &block[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_lookups() {
+    let path = test_fixture("block_lookups", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    for (index, block) in blocks.iter().enumerate() {
+      assert_eq!(Some(*block), reader.block_metadata(index));
+    }
+    assert_eq!(None, reader.block_metadata(3));
+
+    let containing = |uoffset| reader.block_containing(uoffset);
+    assert_eq!(Some((0, blocks[0])), containing(0));
+    assert_eq!(Some((0, blocks[0])), containing(99));
+    assert_eq!(Some((1, blocks[1])), containing(100));
+    assert_eq!(Some((2, blocks[2])), containing(200));
+    assert_eq!(Some((2, blocks[2])), containing(279));
+    assert_eq!(None, containing(280));
+    assert_eq!(None, containing(u64::MAX));
+
+    //lazily opened, the lookups index what they need
+    let lazy = lazy_reader(&path);
+    assert_eq!(Some((1, blocks[1])), lazy.block_containing(150));
+    assert_eq!(Some(blocks[2]), lazy_reader(&path).block_metadata(2));
+    assert_eq!(None, lazy_reader(&path).block_metadata(3));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_at_compressed_offset() {
+    let path = test_fixture("block_at_compressed_offset", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    let at = |coffset| reader.block_at_compressed_offset(coffset);
+    for block in &blocks {
+      let start = block.compressed_offset;
+      let end = start + u64::from(block.block_size);
+      //the header, its BSIZE, the deflate data, the CRC32 and ISIZE
+      for coffset in [start, start + 1, start + 17, start + 18, end - 8, end - 1] {
+        assert_eq!(Some(*block), at(coffset), "{}", coffset);
+      }
+    }
+    //the EOF marker and past it
+    let data_end = reader.compressed_length();
+    assert_eq!(
+      blocks[2].compressed_offset + u64::from(blocks[2].block_size),
+      data_end
+    );
+    assert_eq!(None, at(data_end));
+    assert_eq!(None, at(data_end + 27));
+    assert_eq!(None, at(data_end + 28));
+    assert_eq!(None, at(u64::MAX));
+
+    //lazily opened, the lookup indexes up to the offset
+    let lazy = lazy_reader(&path);
+    assert_eq!(
+      Some(blocks[1]),
+      lazy.block_at_compressed_offset(blocks[1].compressed_offset + 30)
+    );
+    assert_eq!(
+      None,
+      lazy_reader(&path).block_at_compressed_offset(data_end + 1)
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_at_compressed_offset_skips_empty_blocks() {
+    let mut bgzf = fixtures::make_bgzf(b"first", 5);
+    bgzf.truncate(bgzf.len() - EOF_MARKER.len());
+    let empty_block = bgzf.len() as u64;
+    bgzf.extend_from_slice(&EOF_MARKER);
+    bgzf.extend(fixtures::make_bgzf(b"second", 6));
+    let reader = BgzfReader::from_bytes(bgzf).unwrap();
+    let second = reader.block_metadata(1).unwrap();
+    assert_eq!(empty_block + 28, second.compressed_offset);
+    assert_eq!(None, reader.block_at_compressed_offset(empty_block));
+    assert_eq!(None, reader.block_at_compressed_offset(empty_block + 27));
+    assert_eq!(
+      Some(second),
+      reader.block_at_compressed_offset(empty_block + 28)
+    );
+    assert_eq!(5, second.uncompressed_offset);
+  }
+
+  #[test]
+  fn test_compressed_ranges_for() {
+    let content = (0..50_000u32)
+      .map(|i| (i * 31 % 253) as u8)
+      .collect::<Vec<_>>();
+    let path = fixtures::write_temp(
+      "compressed_ranges_for",
+      &fixtures::make_bgzf(&content, 7000),
+    )
+    .unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let index = reader.index().unwrap();
+    for &(ustart, uend) in &[
+      (100, 200),
+      (7000, 14_000),
+      (6999, 35_001),
+      (49_999, 50_000),
+      (0, u64::MAX),
+      (3000, 3000),
+      (50_000, 60_000),
+    ] {
+      let spans = reader.compressed_ranges_for(ustart, uend);
+      assert_eq!(index.compressed_ranges_for(ustart, uend), spans);
+      //lazily opened, the file is indexed up to the end of the range
+      assert_eq!(
+        spans,
+        lazy_reader(&path).compressed_ranges_for(ustart, uend)
+      );
+    }
+    assert_eq!(
+      vec![(0, reader.compressed_length())],
+      reader.compressed_ranges_for(0, 50_000)
+    );
+
+    //the spans alone are enough to read the range
+    let bgzf = fs::read(&path).unwrap();
+    let mut sparse = vec![0; bgzf.len()];
+    for (offset, length) in reader.compressed_ranges_for(13_000, 22_000) {
+      let span = offset as usize..(offset + length) as usize;
+      sparse[span.clone()].copy_from_slice(&bgzf[span]);
+    }
+    let sparse_path = fixtures::write_temp("compressed_ranges_for_sparse", &sparse).unwrap();
+    let from_spans = BgzfReader::open_with_index(sparse_path.clone(), &index).unwrap();
+    let mut data = vec![0; 9000];
+    assert_eq!(9000, from_spans.read_at(13_000, &mut data).unwrap());
+    assert_eq!(&content[13_000..22_000], &data[..]);
+    fs::remove_file(sparse_path).unwrap();
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compressed_ranges_for_splits_at_empty_blocks() {
+    let mut bgzf = fixtures::make_bgzf(b"first", 5);
+    bgzf.truncate(bgzf.len() - EOF_MARKER.len());
+    bgzf.extend_from_slice(&EOF_MARKER);
+    bgzf.extend(fixtures::make_bgzf(b"second", 6));
+    let reader = BgzfReader::from_bytes(bgzf).unwrap();
+    let first = reader.block_metadata(0).unwrap();
+    let second = reader.block_metadata(1).unwrap();
+    assert_eq!(
+      vec![
+        (first.compressed_offset, u64::from(first.block_size)),
+        (second.compressed_offset, u64::from(second.block_size))
+      ],
+      reader.compressed_ranges_for(0, 11)
+    );
+  }
+
+  #[test]
+  fn test_raw_block() {
+    let content = (0..50_000u32)
+      .map(|i| (i * 31 % 253) as u8)
+      .collect::<Vec<_>>();
+    let bgzf = fixtures::make_bgzf(&content, 7000);
+    let reader = BgzfReader::from_bytes(bgzf.clone()).unwrap();
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    assert_eq!(8, blocks.len());
+    let mut uncompressed = Vec::new();
+    for (index, info) in blocks.iter().enumerate() {
+      let raw = reader.raw_block(index).unwrap();
+      let start = info.compressed_offset as usize;
+      assert_eq!(&bgzf[start..start + info.block_size as usize], &raw[..]);
+      let block = read_block(&raw, 0).unwrap();
+      assert_eq!(info.block_size, block.block_size);
+      assert_eq!(info.uncompressed_length, block.input_length);
+      uncompressed.resize(block.input_length as usize, 0);
+      decompress_block_at(
+        &raw,
+        &mut reader.decompressors.acquire(),
+        &block,
+        true,
+        &mut Vec::new(),
+        &mut uncompressed,
+      )
+      .unwrap();
+      let uoffset = info.uncompressed_offset as usize;
+      assert_eq!(
+        &content[uoffset..uoffset + uncompressed.len()],
+        &uncompressed[..]
+      );
+    }
+
+    //all the blocks and the EOF marker are the whole file
+    let mut all = Vec::new();
+    let written = reader.raw_block_range(0, 7, &mut all).unwrap();
+    assert_eq!(written, all.len() as u64);
+    all.extend_from_slice(&EOF_MARKER);
+    assert_eq!(bgzf, all);
+
+    let mut middle = Vec::new();
+    reader.raw_block_range(2, 4, &mut middle).unwrap();
+    let middle = BgzfReader::from_bytes(middle).unwrap();
+    assert_eq!(
+      &content[14_000..35_000],
+      &middle.decompress_all_parallel(1).unwrap()[..]
+    );
+
+    let mut nothing = Vec::new();
+    assert_eq!(0, reader.raw_block_range(3, 2, &mut nothing).unwrap());
+    assert!(nothing.is_empty());
+    assert!(matches!(
+      reader.raw_block_range(6, 8, &mut nothing),
+      Err(BgzfError::OutOfRange { pos: 8, len: 8 })
+    ));
+    assert!(nothing.is_empty());
+    let lazy = BgzfReader::builder()
+      .lazy(true)
+      .open_bytes(bgzf.clone())
+      .unwrap();
+    assert_eq!(reader.raw_block(5).unwrap(), lazy.raw_block(5).unwrap());
+  }
+
+  #[test]
+  fn test_raw_block_of_a_changed_file() {
+    let content = b"data of a file changed after it was opened".repeat(100);
+    let path =
+      fixtures::write_temp("raw_block_changed", &fixtures::make_bgzf(&content, 1000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let second = reader.block_metadata(1).unwrap();
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    //the BSIZE of the second block
+    file
+      .write_all_at(second.compressed_offset + 16, &[0])
+      .unwrap();
+    assert!(reader.raw_block(0).is_ok());
+    match reader.raw_block(1) {
+      Err(BgzfError::IndexMismatch { block }) => {
+        assert_eq!(1, block.index);
+        assert_eq!(second.compressed_offset, block.compressed_offset);
+      }
+      other => panic!("{:?}", other),
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_lookups_of_empty_file() {
+    let path = fixtures::write_temp("block_lookups_empty", &EOF_MARKER).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(None, reader.block_metadata(0));
+    assert_eq!(None, reader.block_containing(0));
+    assert_eq!(None, reader.block_at_compressed_offset(0));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_write_gzi_matches_fixture() {
+    let reader = BgzfReader::new(String::from("tests/data/golden_level_6.bgz")).unwrap();
+    let mut gzi = Vec::new();
+    reader.write_gzi(&mut gzi).unwrap();
+    assert_eq!(fs::read("tests/data/golden_level_6.bgz.gzi").unwrap(), gzi);
+
     let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    let mut gzi = Vec::new();
+    reader.write_gzi(&mut gzi).unwrap();
+    assert_eq!(vec![0; 8], gzi);
+  }
+
+  #[test]
+  fn test_save_gzi_matches_writer_index() {
+    let path = std::env::temp_dir()
+      .join(format!(
+        "bgzf_rust_reader_save_gzi_{}.bgz",
+        std::process::id()
+      ))
+      .to_string_lossy()
+      .into_owned();
+    let gzi_path = format!("{}.gzi", path);
+    let writer_gzi_path = format!("{}.writer.gzi", path);
+    let mut writer = BgzfWriter::builder()
+      .block_size(50)
+      .threads(2)
+      .gzi(writer_gzi_path.clone())
+      .create(path.clone())
+      .unwrap();
+    writer.write_all(TEST_CONTENT).unwrap();
+    writer.finish().unwrap();
+
+    BgzfReader::new(path.clone())
+      .unwrap()
+      .save_gzi(gzi_path.clone())
+      .unwrap();
+    let gzi = fs::read(&gzi_path).unwrap();
+    assert_eq!(fs::read(&writer_gzi_path).unwrap(), gzi);
+    //280 bytes in blocks of 50, the first block is left out
+    assert_eq!(8 + 5 * 16, gzi.len());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(gzi_path).unwrap();
+    fs::remove_file(writer_gzi_path).unwrap();
+  }
+
+  /// Reads the whole content one block at a time
+  fn read_all(reader: &BgzfReader) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut content = Vec::new();
+    for block in reader.block_offsets().collect::<Vec<_>>() {
+      let mut chunk = vec![0; block.uncompressed_length as usize];
+      reader.read_to(&mut chunk)?;
+      content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+  }
+
+  fn gzi_of(path: &str, name: &str) -> String {
+    let gzi_path = format!("{}.{}.gzi", path, name);
+    BgzfReader::new(path.to_string())
+      .unwrap()
+      .save_gzi(gzi_path.clone())
+      .unwrap();
+    gzi_path
+  }
+
+  #[test]
+  fn test_open_with_gzi_reads_the_same() {
+    for &block_size in &[100, 171, 280] {
+      let path = test_fixture("open_with_gzi", block_size);
+      let gzi_path = gzi_of(&path, "index");
+      let scanned = BgzfReader::new(path.clone()).unwrap();
+      let indexed = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+      assert_eq!(
+        scanned.block_offsets().collect::<Vec<_>>(),
+        indexed.block_offsets().collect::<Vec<_>>()
+      );
+      assert_eq!(280, indexed.total_uncompressed_length());
+      assert_eq!(TEST_CONTENT, &read_all(&indexed).unwrap()[..]);
+
+      let indexed = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+      indexed.seek(120);
+      let mut content = vec![0; 20];
+      indexed.read_to(&mut content).unwrap();
+      assert_eq!(&TEST_CONTENT[120..140], &content[..]);
+      fs::remove_file(path).unwrap();
+      fs::remove_file(gzi_path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_open_with_gzi_of_empty_file() {
+    let path = fixtures::write_temp("open_with_gzi_empty", &EOF_MARKER).unwrap();
+    let gzi_path = gzi_of(&path, "index");
+    let reader = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+    assert_eq!(0, reader.total_uncompressed_length());
+    assert_eq!(0, reader.block_offsets().count());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(gzi_path).unwrap();
+  }
+
+  #[test]
+  fn test_open_with_gzi_mismatches() {
+    let path = test_fixture("open_with_gzi_mismatch", 100);
+    let other_path = test_fixture("open_with_gzi_other", 50);
+    let gzi_error = |gzi: &[(u64, u64)]| {
+      let gzi_path = format!("{}.bad.gzi", path);
+      let mut file = File::create(&gzi_path).unwrap();
+      gzi::write_gzi(gzi, &mut file).unwrap();
+      let error = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone())
+        .err()
+        .unwrap();
+      fs::remove_file(gzi_path).unwrap();
+      match error {
+        BgzfError::Gzi(error) => error,
+        error => panic!("{}", error),
+      }
+    };
+    let file_length = fs::metadata(&path).unwrap().len();
+    assert_eq!(
+      GziError::OffsetBeyondEnd {
+        entry: 2,
+        compressed_offset: file_length + 10,
+        file_length
+      },
+      gzi_error(&[(100, 100), (file_length + 10, 200)])
+    );
+    assert_eq!(
+      GziError::NotMonotonic { entry: 2 },
+      gzi_error(&[(200, 100), (100, 200)])
+    );
+    assert_eq!(
+      GziError::InvalidBlock {
+        entry: 1,
+        compressed_offset: 5
+      },
+      gzi_error(&[(5, 100)])
+    );
+
+    //the index of another file only fails when its blocks are read
+    let other_gzi_path = gzi_of(&other_path, "other");
+    let reader = BgzfReader::open_with_gzi(path.clone(), other_gzi_path.clone());
+    assert!(reader.map_or(true, |reader| read_all(&reader).is_err()));
+    fs::remove_file(path).unwrap();
+    fs::remove_file(other_path).unwrap();
+    fs::remove_file(other_gzi_path).unwrap();
+  }
+
+  #[test]
+  fn test_parallel_scan_matches_serial() {
+    let mut content = Vec::new();
+    for i in 0..20_000 {
+      content.extend_from_slice(format!("{}\tparallel scan\n", i).as_bytes());
+    }
+    let path = fixtures::write_temp("parallel_scan", &fixtures::make_bgzf(&content, 97)).unwrap();
+    let serial = BgzfReader::new(path.clone()).unwrap();
+    let file = Source::File(File::open(&path).unwrap());
+    for threads in &[2, 3, 8] {
+      let (blocks, input_length, truncation, anomaly_scan) =
+        scan_blocks_parallel(&file, *threads, ParseMode::Default, &Executor::default()).unwrap();
+      assert_eq!(None, truncation);
+      assert!(anomaly_scan.found.is_empty());
+      assert_eq!(content.len() as u64, input_length);
+      assert_eq!(serial.blocks().len(), blocks.len());
+      for (block, serial_block) in blocks.iter().zip(serial.blocks().iter()) {
+        assert_eq!(serial_block.input_offset, block.input_offset);
+        assert_eq!(serial_block.block_offset, block.block_offset);
+        assert_eq!(serial_block.block_size, block.block_size);
+        assert_eq!(serial_block.input_length, block.input_length);
+      }
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_parallel_scan_stops_like_serial() {
+    //a damaged header in the middle ends both scans at the same block
+    let mut bgzf = fixtures::make_bgzf(&TEST_CONTENT.repeat(20), 50);
+    let path = fixtures::write_temp("parallel_scan_damaged", &bgzf).unwrap();
+    let block_starts = chain_block_starts(&Source::File(File::open(&path).unwrap())).unwrap();
+    //112 data blocks and the EOF marker
+    assert_eq!(113, block_starts.len());
+    bgzf[block_starts[60] as usize + 12] = b'X';
+    fs::write(&path, &bgzf).unwrap();
+    let serial = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    let (blocks, input_length, truncation, _anomaly_scan) = scan_blocks_parallel(
+      &Source::File(File::open(&path).unwrap()),
+      4,
+      ParseMode::Default,
+      &Executor::default(),
+    )
+    .unwrap();
+    assert_eq!(60, blocks.len());
+    assert_eq!(
+      Some(Truncated {
+        block_offset: block_starts[60]
+      }),
+      truncation
+    );
+    assert_eq!(serial.truncation(), truncation);
+    assert_eq!(serial.blocks().len(), blocks.len());
+    assert_eq!(serial.total_uncompressed_length(), input_length);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_open_with_threads() {
+    let mut content = Vec::new();
+    for i in 0..80_000 {
+      content.extend_from_slice(format!("{}\topen with threads\n", i).as_bytes());
+    }
+    let mut writer = BgzfWriter::builder()
+      .compression_level(0)
+      .block_size(1000)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    let bgzf = writer.finish().unwrap();
+    assert!(bgzf.len() as u64 > PARALLEL_SCAN_MIN_LENGTH);
+    let path = fixtures::write_temp("open_with_threads", &bgzf).unwrap();
+
+    let reader = BgzfReader::builder().threads(4).open(path.clone()).unwrap();
+    assert_eq!(None, reader.next_block_offset.get());
+    assert_eq!(content.len() as u64, reader.input_length);
+    assert_eq!(
+      BgzfReader::new(path.clone())
+        .unwrap()
+        .block_offsets()
+        .collect::<Vec<_>>(),
+      reader.block_offsets().collect::<Vec<_>>()
+    );
+    reader.seek(1000);
+    let mut buffer = vec![0; 100];
+    reader.read_to(&mut buffer).unwrap();
+    assert_eq!(&content[1000..1100], &buffer[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  fn lazy_reader(path: &str) -> BgzfReader {
+    BgzfReader::builder()
+      .lazy(true)
+      .open(path.to_string())
+      .unwrap()
+  }
+
+  #[test]
+  fn test_lazy_sequential_read() {
+    let path = test_fixture("lazy_sequential", 40);
+    let reader = lazy_reader(&path);
+    assert_eq!(1, shared_state::read(&reader.blocks).len());
+    assert_eq!(40, reader.input_length);
+
+    let mut content = Vec::new();
+    for blocks_read in 1..=7 {
+      let mut chunk = vec![0; 40];
+      assert_eq!(40, reader.read_to(&mut chunk).unwrap());
+      content.extend_from_slice(&chunk);
+      //each read indexes the one block it needs and no other
+      assert_eq!(blocks_read, shared_state::read(&reader.blocks).len());
+    }
+    assert_eq!(0, reader.read_to(&mut [0; 40]).unwrap());
+    assert_eq!(None, reader.next_block_offset.get());
+    assert_eq!(TEST_CONTENT, &content[..]);
+
+    let eager = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(
+      eager.block_offsets().collect::<Vec<_>>(),
+      reader.block_offsets().collect::<Vec<_>>()
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_lazy_seek_and_full_index() {
+    let path = test_fixture("lazy_seek", 40);
+    let reader = lazy_reader(&path);
+    reader.seek(200);
     let mut content = vec![0; 10];
-    match reader.read(&mut content, 0, 10) {
-      Ok(val) => {
-        assert_eq!(10, val);
+    assert_eq!(10, reader.read_to(&mut content).unwrap());
+    assert_eq!(&TEST_CONTENT[200..210], &content[..]);
+    assert_eq!(
+      vec![0, 40, 80, 120, 160, 200],
+      shared_state::read(&reader.blocks)
+        .iter()
+        .map(|block| block.input_offset)
+        .collect::<Vec<_>>()
+    );
+
+    //going back does not index anything again
+    reader.seek(0);
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[..10], &content[..]);
+    assert_eq!(6, shared_state::read(&reader.blocks).len());
+    assert_eq!(7, lazy_reader(&path).num_blocks());
+
+    assert_eq!(280, reader.total_uncompressed_length());
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    assert_eq!(7, blocks.len());
+    for pair in blocks.windows(2) {
+      assert_eq!(
+        pair[0].compressed_offset + u64::from(pair[0].block_size),
+        pair[1].compressed_offset
+      );
+      assert_eq!(
+        pair[0].uncompressed_offset + u64::from(pair[0].uncompressed_length),
+        pair[1].uncompressed_offset
+      );
+    }
+    assert_eq!(
+      BgzfReader::new(path.clone())
+        .unwrap()
+        .block_offsets()
+        .collect::<Vec<_>>(),
+      blocks
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_lazy_empty_file() {
+    let path = fixtures::write_temp("lazy_empty", &EOF_MARKER).unwrap();
+    let reader = lazy_reader(&path);
+    assert_eq!(0, reader.input_length);
+    assert_eq!(0, reader.num_blocks());
+    assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+    assert_eq!(0, reader.total_uncompressed_length());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_tell_and_seek_virtual() {
+    let path = test_fixture("virtual", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks: Vec<_> = reader.block_offsets().collect();
+    for pos in 0..280 {
+      reader.seek(pos);
+      let voffset = reader.tell_virtual();
+      let block = blocks[pos as usize / 100];
+      assert_eq!(block.compressed_offset, voffset >> 16);
+      assert_eq!(pos % 100, voffset & 0xffff);
+
+      reader.seek(0);
+      reader.seek_virtual(voffset).unwrap();
+      assert_eq!(pos, reader.pos.get());
+    }
+
+    //the end of the data is right after the last block
+    let data_end = (blocks[2].compressed_offset + u64::from(blocks[2].block_size)) << 16;
+    reader.seek(280);
+    assert_eq!(data_end, reader.tell_virtual());
+    reader.seek(0);
+    reader.seek_virtual(data_end).unwrap();
+    assert_eq!(280, reader.pos.get());
+
+    //the end of a block is the start of the next one
+    reader.seek_virtual(100).unwrap();
+    assert_eq!(100, reader.pos.get());
+
+    reader
+      .seek_virtual(blocks[1].compressed_offset << 16 | 30)
+      .unwrap();
+    let mut content = vec![0; 20];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[130..150], &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_seek_virtual_errors() {
+    let path = test_fixture("virtual_errors", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(42);
+    assert!(matches!(
+      reader.seek_virtual(1 << 16),
+      Err(BgzfError::NoBlockAt { offset: 1 })
+    ));
+    assert!(matches!(
+      reader.seek_virtual(101),
+      Err(BgzfError::OutOfRange { pos: 101, len: 100 })
+    ));
+    assert!(reader.seek_virtual(data_end_of(&reader) << 16 | 1).is_err());
+    assert_eq!(42, reader.pos.get());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_offset_conversions() {
+    let path = test_fixture("offset_conversions", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks: Vec<_> = reader.block_offsets().collect();
+    let data_end = data_end_of(&reader);
+    reader.seek(42);
+
+    let pairs = [
+      (0, 0),
+      (99, 99),
+      (100, blocks[1].compressed_offset << 16),
+      (101, blocks[1].compressed_offset << 16 | 1),
+      (199, blocks[1].compressed_offset << 16 | 99),
+      (200, blocks[2].compressed_offset << 16),
+      (279, blocks[2].compressed_offset << 16 | 79),
+      (280, data_end << 16),
+    ];
+    for &(uoffset, voffset) in pairs.iter() {
+      assert_eq!(voffset, reader.uoffset_to_voffset(uoffset).unwrap());
+      assert_eq!(uoffset, reader.voffset_to_uoffset(voffset).unwrap());
+    }
+    //the end of a block is the same offset as the start of the next one
+    assert_eq!(100, reader.voffset_to_uoffset(100).unwrap());
+    assert_eq!(
+      280,
+      reader
+        .voffset_to_uoffset(blocks[2].compressed_offset << 16 | 80)
+        .unwrap()
+    );
+
+    assert!(matches!(
+      reader.uoffset_to_voffset(281),
+      Err(BgzfError::OutOfRange { pos: 281, len: 280 })
+    ));
+    assert!(reader.voffset_to_uoffset(1 << 16).is_err());
+    assert!(reader
+      .voffset_to_uoffset(blocks[2].compressed_offset << 16 | 81)
+      .is_err());
+    assert!(reader.voffset_to_uoffset(data_end << 16 | 1).is_err());
+    assert!(reader.voffset_to_uoffset((data_end + 1) << 16).is_err());
+    assert_eq!(42, reader.pos.get());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_clamp_voffset() {
+    let path = test_fixture("clamp_voffset", 100);
+    let data_end = data_end_of(&BgzfReader::new(path.clone()).unwrap());
+    for lazy in [false, true] {
+      let reader = BgzfReader::builder().lazy(lazy).open(path.clone()).unwrap();
+      let blocks: Vec<_> = reader.block_offsets().collect();
+      let reader = BgzfReader::builder().lazy(lazy).open(path.clone()).unwrap();
+      let second = blocks[1].compressed_offset << 16;
+      let third = blocks[2].compressed_offset << 16;
+      let cases = [
+        //valid offsets stay
+        (0, 0),
+        (99, 99),
+        (100, 100),
+        (second | 5, second | 5),
+        (data_end << 16, data_end << 16),
+        ((data_end + 28) << 16, (data_end + 28) << 16),
+        //past the end of a block, to its end
+        (101, 100),
+        (third | 0xffff, third | 80),
+        //within the header or data of a block, to the start of the next one
+        (1 << 16 | 7, second),
+        ((blocks[1].compressed_offset + 1) << 16, third),
+        //past the last block, to the end of the data
+        ((data_end + 1) << 16, data_end << 16),
+        (u64::MAX, data_end << 16),
+      ];
+      for &(voffset, clamped) in cases.iter() {
+        assert_eq!(clamped, reader.clamp_voffset(voffset), "{:x}", voffset);
+        assert!(reader.is_valid_voffset(clamped));
+        assert_eq!(voffset == clamped, reader.is_valid_voffset(voffset));
+      }
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_offset_conversions_of_empty_file() {
+    let path = fixtures::write_temp("offset_conversions_empty", &EOF_MARKER).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(0, reader.uoffset_to_voffset(0).unwrap());
+    assert_eq!(0, reader.voffset_to_uoffset(0).unwrap());
+    assert!(reader.uoffset_to_voffset(1).is_err());
+    assert!(reader.voffset_to_uoffset(1).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  fn data_end_of(reader: &BgzfReader) -> u64 {
+    let last = reader.block_offsets().last().unwrap();
+    last.compressed_offset + u64::from(last.block_size)
+  }
+
+  #[test]
+  fn test_virtual_offsets_lazily() {
+    let path = test_fixture("virtual_lazy", 100);
+    let full = BgzfReader::new(path.clone()).unwrap();
+    let data_end = data_end_of(&full);
+    let last_block = full.block_offsets().last().unwrap().compressed_offset;
+
+    let reader = lazy_reader(&path);
+    reader.seek_virtual(last_block << 16 | 5).unwrap();
+    assert_eq!(205, reader.pos.get());
+    assert_eq!(last_block << 16 | 5, reader.tell_virtual());
+
+    let reader = lazy_reader(&path);
+    reader.seek(280);
+    assert_eq!(data_end << 16, reader.tell_virtual());
+    let reader = lazy_reader(&path);
+    reader.seek_virtual(data_end << 16).unwrap();
+    assert_eq!(280, reader.pos.get());
+    fs::remove_file(path).unwrap();
+  }
+
+  fn cached_reader(path: &str) -> BgzfReader {
+    BgzfReader::builder()
+      .cache_index(true)
+      .open(String::from(path))
+      .unwrap()
+  }
+
+  fn remove_with_cache(path: &str) {
+    fs::remove_file(path).unwrap();
+    fs::remove_file(index_cache::cache_path(path)).unwrap();
+  }
+
+  #[test]
+  fn test_cache_index_cold_and_warm_open() {
+    let path = test_fixture("index_cache_warm", 100);
+    let cache_path = index_cache::cache_path(&path);
+    let _ = fs::remove_file(&cache_path);
+
+    let reader = cached_reader(&path);
+    let index = reader.index().unwrap();
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(
+      Some(index.clone()),
+      index_cache::load(&cache_path, &metadata)
+    );
+
+    //damage the header of the middle block without changing the length or the
+    //modification time: a scan stops there, the warm open does not read it
+    let modified = metadata.modified().unwrap();
+    let middle = index.blocks()[1].compressed_offset;
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_all_at(middle, &[0]).unwrap();
+    file.set_modified(modified).unwrap();
+    let error = BgzfReader::new(path.clone()).err().unwrap();
+    assert!(matches!(
+      error,
+      BgzfError::Truncated(Truncated { block_offset }) if block_offset == middle
+    ));
+
+    let warm = cached_reader(&path);
+    assert_eq!(index, warm.index().unwrap());
+    assert_eq!(280, warm.total_uncompressed_length());
+    let mut content = vec![0; 100];
+    warm.read_to(&mut content).unwrap();
+    assert_eq!(&TEST_CONTENT[..100], &content[..]);
+    remove_with_cache(&path);
+  }
+
+  #[test]
+  fn test_cache_index_stale_or_corrupt() {
+    let path = test_fixture("index_cache_stale", 100);
+    let cache_path = index_cache::cache_path(&path);
+    cached_reader(&path);
+
+    //a changed data file makes the cache stale
+    fs::write(&path, fixtures::make_bgzf(&TEST_CONTENT[..150], 50)).unwrap();
+    let reader = cached_reader(&path);
+    assert_eq!(3, reader.block_offsets().count());
+    assert_eq!(150, reader.total_uncompressed_length());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(
+      reader.index().ok(),
+      index_cache::load(&cache_path, &metadata)
+    );
+
+    //a cache that can not be loaded is written again
+    fs::write(&cache_path, b"not an index").unwrap();
+    assert_eq!(None, index_cache::load(&cache_path, &metadata));
+    assert_eq!(150, cached_reader(&path).total_uncompressed_length());
+    assert_eq!(
+      reader.index().ok(),
+      index_cache::load(&cache_path, &metadata)
+    );
+
+    //a cache that loads but whose last block is not in the file is not used
+    let mut cached = fs::read(&cache_path).unwrap();
+    let last_length = cached.len() - 4;
+    cached[last_length] += 1;
+    cached[36] += 1;
+    fs::write(&cache_path, &cached).unwrap();
+    assert!(index_cache::load(&cache_path, &metadata).is_some());
+    let reader = cached_reader(&path);
+    assert_eq!(150, reader.total_uncompressed_length());
+    assert_eq!(
+      reader.index().ok(),
+      index_cache::load(&cache_path, &metadata)
+    );
+    remove_with_cache(&path);
+  }
+
+  #[test]
+  fn test_cache() {
+    //the second read ends right at the end of the first block, so it is served
+    //from the cache, and the third one needs the second block
+    let path = test_fixture("cache", 171);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(2, reader.blocks().len());
+    let mut vec = vec![0; 52];
+    let data_read = reader.read_to(&mut vec);
+    assert_eq!(data_read.unwrap(), 52);
+    assert_eq!(
+      "This is just a bgzf test,lets see how it reacts. :).",
+      str::from_utf8(&vec).unwrap()
+    );
+
+    let mut vec2 = vec![0; 119];
+    let data_read_2 = reader.read_to(&mut vec2);
+    assert_eq!(data_read_2.unwrap(), 119);
+    assert_eq!(
+    " I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarante",
+      str::from_utf8(&vec2).unwrap()
+    );
+
+    let mut vec3 = vec![0; 2];
+    let data_read_3 = reader.read_to(&mut vec3);
+    assert_eq!(data_read_3.unwrap(), 2);
+    assert_eq!("e ", str::from_utf8(&vec3).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_cache_bytes() {
+    let data = (0..100_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("cache_bytes", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::builder()
+      .cache_bytes(3500)
+      .open(path.clone())
+      .unwrap();
+    //half blocks, as reads of whole blocks skip the cache
+    let mut half_block = vec![0; 500];
+    for expected in data.chunks(500) {
+      assert_eq!(500, reader.read_to(&mut half_block).unwrap());
+      assert_eq!(expected, &half_block[..]);
+      assert!(reader.cache_stats().bytes <= 3500);
+    }
+    assert_eq!(
+      CacheStats {
+        blocks: 3,
+        bytes: 3000,
+        budget: 3500,
+        hits: 100,
+        misses: 100,
+      },
+      reader.cache_stats()
+    );
+
+    //walking back over the cached blocks decompresses none of them
+    for start in [99_000, 98_000, 97_000] {
+      reader.seek(start);
+      assert_eq!(500, reader.read_to(&mut half_block).unwrap());
+      assert_eq!(&data[start as usize..start as usize + 500], &half_block[..]);
+    }
+    assert_eq!(103, reader.cache_stats().hits);
+    assert_eq!(100, reader.cache_stats().misses);
+
+    //the last block of the scan is now the least recently used one
+    for start in [96_000, 95_000] {
+      reader.seek(start);
+      assert_eq!(500, reader.read_to(&mut half_block).unwrap());
+    }
+    let cached = [95_000u64, 96_000, 97_000, 98_000, 99_000]
+      .iter()
+      .copied()
+      .filter(|&uoffset| lock(&reader.cache).contains(uoffset))
+      .collect::<Vec<_>>();
+    assert_eq!(vec![95_000, 96_000, 97_000], cached);
+    assert_eq!(102, reader.cache_stats().misses);
+
+    //by default only the last block used is kept
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    for _half_block in 0..10 {
+      reader.read_to(&mut half_block).unwrap();
+    }
+    assert_eq!(1, reader.cache_stats().blocks);
+    assert_eq!(1000, reader.cache_stats().bytes);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_parallel_read() {
+    let data = (0..100_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("parallel_read", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    //from the middle of a block to the middle of another one, all the whole
+    //blocks between them are decompressed in parallel
+    for &(start, length) in &[(250, 95_300), (0, 100_000), (999, 9002), (5000, 8000)] {
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      reader.seek(start);
+      let mut buffer = vec![0; length + 10];
+      assert_eq!(length, reader.read(&mut buffer, 3, length).unwrap());
+      assert_eq!(&data[start as usize..][..length], &buffer[3..length + 3]);
+      assert!(buffer[..3]
+        .iter()
+        .chain(&buffer[length + 3..])
+        .all(|&byte| byte == 0));
+      assert_eq!(start + length as u64, reader.pos.get());
+    }
+
+    //a block no worker can decompress fails the read
+    let mut bgzf = fixtures::make_bgzf(&data, 1000);
+    let damaged_block = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(40)
+      .unwrap();
+    bgzf[damaged_block.compressed_offset as usize + 18] = 0xff;
+    fs::write(&path, &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(reader.read_to(&mut vec![0; 100_000]).is_err());
+    let mut block = vec![0; 1000];
+    reader.seek(41_000);
+    assert_eq!(1000, reader.read_to(&mut block).unwrap());
+    assert_eq!(&data[41_000..42_000], &block[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_whole_blocks_read_into_the_buffer() {
+    let data = (0..10_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("whole_blocks", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    //(length of the read, block in the cache after it), only blocks read in
+    //part go to the cache
+    let reads = [
+      (300, 0),
+      (700, 0),
+      //two whole blocks then half of the next one
+      (2500, 3000),
+      (500, 3000),
+      (1000, 3000),
+      (10, 5000),
+      (990, 5000),
+      //from a block start to the end of the data
+      (4000, 5000),
+    ];
+    let mut position = 0;
+    for &(length, cached_block) in &reads {
+      let mut buffer = vec![0; length];
+      assert_eq!(length, reader.read_to(&mut buffer).unwrap());
+      assert_eq!(&data[position..position + length], &buffer[..]);
+      assert_eq!(
+        Some(cached_block),
+        lock(&reader.cache)
+          .most_recent()
+          .map(|(uoffset, _data)| uoffset)
+      );
+      position += length;
+    }
+    assert_eq!(data.len(), position);
+    assert_eq!(10, reader.cache_stats().misses);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_copy_range() {
+    let block = BgzfBlock {
+      input_offset: 5 << 30,
+      block_offset: 0,
+      input_length: 65280,
+      block_size: 100,
+    };
+    //lengths past u32 are not cut to their low bits
+    assert_eq!(
+      (0, 65280),
+      block_copy_range(&block, 5 << 30, (4 << 30) + 10)
+    );
+    assert_eq!(
+      (280, 65000),
+      block_copy_range(&block, (5 << 30) + 280, usize::MAX)
+    );
+    assert_eq!(
+      (65000, 100),
+      block_copy_range(&block, (5 << 30) + 65000, 100)
+    );
+    //nor distances past u32 to theirs
+    assert_eq!((65280, 0), block_copy_range(&block, (9 << 30) + 280, 100));
+  }
+
+  #[test]
+  fn test_offsets_past_4gib() {
+    //the blocks of a small file moved 5GiB into the uncompressed data, like
+    //the last ones of a large file
+    const SHIFT: u64 = 5 << 30;
+    let path = test_fixture("past_4gib", 100);
+    let reader = BgzfReader::builder()
+      .cache_bytes(1000)
+      .open(path.clone())
+      .unwrap();
+    for block in shared_state::write(&reader.blocks).iter_mut() {
+      block.input_offset += SHIFT;
+    }
+    reader.indexed_length.set(SHIFT + 280);
+    let mut content = vec![0; 100];
+    assert_eq!(100, reader.read_at(SHIFT + 150, &mut content).unwrap());
+    assert_eq!(&TEST_CONTENT[150..250], &content[..]);
+    //from the block cached last
+    assert_eq!(20, reader.read_at(SHIFT + 210, &mut content[..20]).unwrap());
+    assert_eq!(&TEST_CONTENT[210..230], &content[..20]);
+    assert_eq!(30, reader.read_at(SHIFT + 250, &mut content).unwrap());
+    assert_eq!(&TEST_CONTENT[250..], &content[..30]);
+    assert_eq!(0, reader.read_at(SHIFT + 280, &mut content).unwrap());
+    assert!(matches!(
+      reader.read_at(100, &mut content),
+      Err(BgzfError::OutOfRange { pos: 100, .. })
+    ));
+    fs::remove_file(path).unwrap();
+
+    //lengths a buffer can not hold on 32-bit targets
+    assert_eq!(280, buffer_length(280).unwrap());
+    let too_long = buffer_length(SHIFT);
+    if cfg!(target_pointer_width = "32") {
+      assert!(matches!(
+        too_long,
+        Err(BgzfError::TooLarge { length: SHIFT, limit }) if limit == u64::from(u32::MAX)
+      ));
+    } else {
+      assert_eq!(SHIFT as usize, too_long.unwrap());
+    }
+  }
+
+  #[test]
+  fn test_read_at_eof() {
+    let path = test_fixture("read_at_eof", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut content = vec![0; 300];
+    assert_eq!(0, reader.read(&mut content, 10, 0).unwrap());
+    assert_eq!(200, reader.read(&mut content, 10, 200).unwrap());
+    assert_eq!(&TEST_CONTENT[..200], &content[10..210]);
+    assert_eq!(80, reader.read(&mut content, 0, 300).unwrap());
+    assert_eq!(&TEST_CONTENT[200..], &content[..80]);
+    assert_eq!(0, reader.read(&mut content, 0, 300).unwrap());
+    assert_eq!(0, reader.read_to(&mut content).unwrap());
+    assert!(reader.read(&mut content, 10, 300).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  /// In-memory file whose reads, once `armed`, wait until `expected` of them
+  /// are being made at once, or give up after a few seconds
+  struct MeetingSource {
+    data: Vec<u8>,
+    armed: Arc<AtomicBool>,
+    readers: (Mutex<usize>, Condvar),
+    expected: usize,
+  }
+
+  impl ReadAt for MeetingSource {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+      if self.armed.load(Ordering::SeqCst) {
+        let (readers, met) = &self.readers;
+        let mut readers = lock(readers);
+        *readers += 1;
+        met.notify_all();
+        let _meeting = met
+          .wait_timeout_while(readers, Duration::from_secs(5), |readers| {
+            *readers < self.expected
+          })
+          .unwrap();
       }
-      Err(e) => {
-        assert!(false);
+      self.data.read_at(pos, buf)
+    }
+  }
+
+  #[test]
+  fn test_read_at_concurrently() {
+    let bgzf = fixtures::make_bgzf(TEST_CONTENT, 10);
+    let armed = Arc::new(AtomicBool::new(false));
+    let source = MeetingSource {
+      data: bgzf.clone(),
+      armed: Arc::clone(&armed),
+      readers: (Mutex::new(0), Condvar::new()),
+      expected: 2,
+    };
+    let reader = BgzfReader::builder()
+      .open_read_at(source, bgzf.len() as u64)
+      .unwrap();
+    armed.store(true, Ordering::SeqCst);
+
+    //each read reads its block from the file while the other one does, the
+    //blocks are past those the opening left in the compressed run
+    let started = Instant::now();
+    let blocks: Vec<Vec<u8>> = thread::scope(|scope| {
+      let workers: Vec<_> = [220, 250]
+        .iter()
+        .map(|&pos| {
+          let reader = &reader;
+          scope.spawn(move || {
+            let mut block = vec![0; 10];
+            reader.read_at(pos, &mut block).unwrap();
+            block
+          })
+        })
+        .collect();
+      workers
+        .into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect()
+    });
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(&TEST_CONTENT[220..230], &blocks[0][..]);
+    assert_eq!(&TEST_CONTENT[250..260], &blocks[1][..]);
+  }
+
+  #[test]
+  fn test_seek_past_the_end() {
+    let path = test_fixture("seek_past_the_end", 50);
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().lazy(true),
+      BgzfReader::builder().background_indexing(true),
+      BgzfReader::builder().threads(3),
+      BgzfReader::builder().prefetch(2),
+      BgzfReader::builder().compressed_cache_bytes(1 << 20),
+    ];
+    let end = TEST_CONTENT.len() as u64;
+    for (number, builder) in IntoIterator::into_iter(builders).enumerate() {
+      let reader = builder.open(path.clone()).unwrap();
+      //and from the whole data loaded
+      if number == 0 {
+        reader.load_all().unwrap();
       }
+      for &pos in &[end, end + 1, end + 70_000, u64::MAX - 1, u64::MAX] {
+        for &length in &[1, 50, 300, 70_000] {
+          reader.seek(pos);
+          let mut content = vec![0; length];
+          //reads ending past u64::MAX fail instead
+          if pos.checked_add(length as u64).is_none() {
+            assert!(matches!(
+              reader.read_to(&mut content),
+              Err(BgzfError::PositionOverflow { .. })
+            ));
+            assert_eq!(
+              io::ErrorKind::InvalidInput,
+              io::Read::read(&mut &reader, &mut content)
+                .unwrap_err()
+                .kind()
+            );
+            assert_eq!(pos, reader.pos.get());
+            continue;
+          }
+          assert_eq!(0, reader.read_to(&mut content).unwrap());
+          assert_eq!(
+            0,
+            reader
+              .read_vectored(&mut [IoSliceMut::new(&mut content)])
+              .unwrap()
+          );
+          assert_eq!(0, io::Read::read(&mut &reader, &mut content).unwrap());
+          assert_eq!(pos, reader.pos.get());
+        }
+        assert_eq!(
+          reader.uoffset_to_voffset(end).unwrap(),
+          reader.tell_virtual()
+        );
+        assert!(reader.uoffset_to_voffset(pos.max(end + 1)).is_err());
+        assert_eq!(None, reader.block_containing(pos));
+        //the data is still there
+        reader.seek(end - 10);
+        let mut content = vec![0; 20];
+        assert_eq!(10, reader.read_to(&mut content).unwrap());
+        assert_eq!(&TEST_CONTENT[end as usize - 10..], &content[..10]);
+      }
+      reader.seek(u64::MAX);
+      let mut data = Vec::new();
+      assert_eq!(
+        io::ErrorKind::InvalidInput,
+        io::Read::read_to_end(&mut reader.into_pipelined(2).unwrap(), &mut data)
+          .unwrap_err()
+          .kind()
+      );
+    }
+    fs::remove_file(path).unwrap();
+
+    //an empty file has no data to seek into
+    let path =
+      fixtures::write_temp("seek_past_the_end_empty", &fixtures::make_bgzf(&[], 50)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(u64::MAX - 10);
+    assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+    assert_eq!(0, reader.tell_virtual());
+    fs::remove_file(path).unwrap();
+
+    //a truncated file fails as it does at the end of its data
+    let path = fixtures::write_temp(
+      "seek_past_the_end_truncated",
+      &fixtures::make_truncated(TEST_CONTENT, 50, 10),
+    )
+    .unwrap();
+    for lazy in [false, true] {
+      let reader = BgzfReader::builder().lazy(lazy).allow_truncated(true);
+      let reader = reader.open(path.clone()).unwrap();
+      reader.seek(u64::MAX - 10);
+      assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+    }
+    let reader = BgzfReader::builder().lazy(true).open(path.clone()).unwrap();
+    reader.seek(u64::MAX - 10);
+    assert!(matches!(
+      reader.read_to(&mut [0; 10]),
+      Err(BgzfError::Truncated(_))
+    ));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_buffer_larger_than_the_data() {
+    //a single block, read from the block then from the cache
+    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    let mut buffer = vec![0xaa; 1000];
+    assert_eq!(280, reader.read_to(&mut buffer).unwrap());
+    assert_eq!(TEST_CONTENT, &buffer[..280]);
+    assert!(buffer[280..].iter().all(|&byte| byte == 0xaa));
+    assert_eq!(0, reader.read_to(&mut buffer).unwrap());
+    reader.seek(30);
+    assert_eq!(250, reader.read(&mut buffer, 500, 500).unwrap());
+    assert_eq!(&TEST_CONTENT[30..], &buffer[500..750]);
+
+    //several blocks, the last one giving less than the buffer has left
+    let path = test_fixture("larger_buffer", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(150);
+    let mut buffer = vec![0; 1000];
+    assert_eq!(130, reader.read(&mut buffer, 10, 990).unwrap());
+    assert_eq!(&TEST_CONTENT[150..], &buffer[10..140]);
+    reader.seek(0);
+    assert_eq!(280, reader.read_to(&mut buffer).unwrap());
+    assert_eq!(TEST_CONTENT, &buffer[..280]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_into_the_middle_of_a_buffer() {
+    let path = test_fixture("middle_of_buffer", 100);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let untouched = |buffer: &[u8]| {
+      buffer[..5]
+        .iter()
+        .chain(&buffer[15..])
+        .all(|&byte| byte == 0xaa)
     };
-    let file_content = str::from_utf8(&content).unwrap();
-    assert_eq!("This is ju", file_content);
+    //decompressing the block, from the block left in the cache, then
+    //straddling two blocks
+    for &start in &[120, 130, 95] {
+      let mut buffer = [0xaa; 32];
+      reader.seek(start);
+      assert_eq!(10, reader.read(&mut buffer, 5, 10).unwrap());
+      assert_eq!(&TEST_CONTENT[start as usize..][..10], &buffer[5..15]);
+      assert!(untouched(&buffer));
+    }
+    //to the end of the data, fewer bytes than len
+    let mut buffer = [0xaa; 32];
+    reader.seek(274);
+    assert_eq!(6, reader.read(&mut buffer, 5, 10).unwrap());
+    assert_eq!(&TEST_CONTENT[274..], &buffer[5..11]);
+    assert!(buffer[..5]
+      .iter()
+      .chain(&buffer[11..])
+      .all(|&byte| byte == 0xaa));
+    fs::remove_file(path).unwrap();
+  }
 
-    reader.seek(20);
-    let mut content_two = vec![0; 32];
-    match reader.read(&mut content_two, 0, 32) {
-      Ok(val) => {
-        assert_eq!(32, val);
+  //needs 2.5GiB of memory and a while to write, run with --ignored
+  #[test]
+  #[ignore]
+  fn test_reads_past_2_gib() {
+    let path = std::env::temp_dir()
+      .join(format!(
+        "bgzf_rust_reader_past_2_gib_{}.bgz",
+        std::process::id()
+      ))
+      .to_string_lossy()
+      .into_owned();
+    let mut writer = BgzfWriter::builder().create(path.clone()).unwrap();
+    let chunk = (0..1 << 20).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let length = (5usize << 30) / 2;
+    for _chunk in 0..length / chunk.len() {
+      writer.write_all(&chunk).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(length as u64, reader.total_uncompressed_length());
+    let mut data = vec![0; length];
+    assert_eq!(length, reader.read(&mut data, 0, length).unwrap());
+    assert!(data.chunks(chunk.len()).all(|read| read == &chunk[..]));
+
+    reader.seek(0);
+    data.fill(0);
+    assert_eq!(length, reader.read_to(&mut data).unwrap());
+    assert_eq!(length as u64, reader.pos.get());
+    assert!(data.chunks(chunk.len()).all(|read| read == &chunk[..]));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_read_into_uninit() {
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    let bgzf = fixtures::make_bgzf(&content, 5000);
+    let open = |builder: BgzfReaderBuilder| builder.open_bytes(bgzf.clone()).unwrap();
+    let loaded = open(BgzfReader::builder());
+    loaded.load_all().unwrap();
+    let readers = [
+      open(BgzfReader::builder()),
+      open(BgzfReader::builder().cache_bytes(1 << 20)),
+      open(BgzfReader::builder().prefetch(4)),
+      loaded,
+    ];
+    for reader in &readers {
+      //reads within a block, across blocks, of whole blocks and of the tail
+      for &chunk in &[1, 999, 5000, 12_345, 100_000, 300_000] {
+        reader.seek(0);
+        let mut data = Vec::with_capacity(content.len() + 1);
+        let mut reads = Vec::new();
+        loop {
+          let spare = data.spare_capacity_mut();
+          let length = min(chunk, spare.len());
+          let read = reader.read_into_uninit(&mut spare[..length]).unwrap();
+          // SAFETY: the first `read` bytes of the spare capacity were
+          // initialized
+          unsafe { data.set_len(data.len() + read) };
+          reads.push(read);
+          if read == 0 {
+            break;
+          }
+        }
+        assert_eq!(content, data, "{}", chunk);
+
+        //the same reads as into zeroed buffers
+        reader.seek(0);
+        let mut safe_reads = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+          let read = reader.read_to(&mut buf).unwrap();
+          safe_reads.push(read);
+          if read == 0 {
+            break;
+          }
+        }
+        assert_eq!(safe_reads, reads, "{}", chunk);
       }
-      Err(_e) => {
-        assert!(false);
+      //the cache serves the block read again
+      reader.seek(7000);
+      let mut spare = Vec::<u8>::with_capacity(10);
+      assert_eq!(
+        10,
+        reader.read_into_uninit(spare.spare_capacity_mut()).unwrap()
+      );
+      assert_eq!(0, reader.read_into_uninit(&mut []).unwrap());
+      assert_eq!(7010, reader.pos.get());
+    }
+  }
+
+  #[test]
+  fn test_read_vectored() {
+    let data = (0..10_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("read_vectored", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    //lengths of the buffers of every read, blocks straddle buffers, some of
+    //which are empty or longer than a block
+    let reads: &[&[usize]] = &[
+      &[300, 0, 1200, 500],
+      &[0, 0],
+      &[2000, 0, 0, 1],
+      &[999, 1000, 2],
+      &[10, 20, 30],
+      //past the end of the data
+      &[3000, 0, 1000],
+    ];
+    let mut position = 0;
+    for lengths in reads {
+      let mut buffers = lengths
+        .iter()
+        .map(|&length| vec![0; length])
+        .collect::<Vec<_>>();
+      let mut bufs = buffers
+        .iter_mut()
+        .map(|buffer| IoSliceMut::new(buffer))
+        .collect::<Vec<_>>();
+      let count = reader.read_vectored(&mut bufs).unwrap();
+      let expected = min(lengths.iter().sum::<usize>(), data.len() - position);
+      assert_eq!(expected, count, "{:?}", lengths);
+      let filled = buffers.concat();
+      assert_eq!(&data[position..position + count], &filled[..count]);
+      assert!(filled[count..].iter().all(|&byte| byte == 0));
+      position += count;
+    }
+    assert_eq!(data.len(), position);
+    let mut buffer = [0; 10];
+    assert_eq!(
+      0,
+      reader
+        .read_vectored(&mut [IoSliceMut::new(&mut buffer)])
+        .unwrap()
+    );
+    assert_eq!(0, reader.read_vectored(&mut []).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_std_read() {
+    use std::io::Read;
+
+    let data = (0..10_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("std_read", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let mut reader = BgzfReader::new(path.clone()).unwrap();
+    let mut read = Vec::new();
+    assert_eq!(data.len(), (&reader).read_to_end(&mut read).unwrap());
+    assert_eq!(data, read);
+    assert_eq!(0, Read::read(&mut reader, &mut [0; 10]).unwrap());
+
+    reader.seek(2500);
+    let (mut first, mut second) = (vec![0; 700], vec![0; 1500]);
+    let count = Read::read_vectored(
+      &mut reader,
+      &mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)],
+    )
+    .unwrap();
+    assert_eq!(2200, count);
+    assert_eq!(&data[2500..3200], &first[..]);
+    assert_eq!(&data[3200..4700], &second[..]);
+    assert_eq!(0, Read::read(&mut reader, &mut []).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compressed_cache() {
+    let data = (0..20_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("compressed_cache", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::builder()
+      .compressed_cache_bytes(1 << 20)
+      .open(path.clone())
+      .unwrap();
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    let region = 2500..9500;
+    let read_region = || {
+      let mut buffer = vec![0; region.len()];
+      reader.seek(region.start as u64);
+      assert_eq!(region.len(), reader.read_to(&mut buffer).unwrap());
+      assert_eq!(&data[region.clone()], &buffer[..]);
+    };
+    read_region();
+    let warm = reader.compressed_cache_stats();
+    //blocks 2 to 9
+    assert_eq!(8, warm.misses);
+    assert_eq!(8, warm.blocks);
+    let compressed_length = blocks[2..10]
+      .iter()
+      .map(|block| u64::from(block.block_size))
+      .sum::<u64>();
+    assert_eq!(compressed_length, warm.bytes);
+
+    //the file is not read again, so damaging it does not change what is read
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    let start = blocks[2].compressed_offset;
+    let length = blocks[10].compressed_offset - start;
+    file.write_all_at(start, &vec![0; length as usize]).unwrap();
+    for _ in 0..3 {
+      read_region();
+    }
+    let stats = reader.compressed_cache_stats();
+    assert_eq!(warm.misses, stats.misses);
+    assert_eq!(warm.hits + 3 * 8, stats.hits);
+    //the decompressed blocks were not kept
+    assert_eq!(4 * 8, reader.cache_stats().misses);
+
+    //blocks not read yet are read from the file
+    reader.seek(500);
+    assert!(reader.read_to(&mut vec![0; 2000]).is_ok());
+    assert_eq!(warm.misses + 2, reader.compressed_cache_stats().misses);
+    assert_eq!(
+      CacheStats::default(),
+      BgzfReader::new(String::from("bgzf_test.bgz"))
+        .unwrap()
+        .compressed_cache_stats()
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_load_all() {
+    let data = (0..30_000u32).map(|n| (n % 241) as u8).collect::<Vec<_>>();
+    let bgzf = fixtures::make_bgzf(&data, 1000);
+    let path = fixtures::write_temp("load_all", &bgzf).unwrap();
+    let limited = BgzfReader::builder()
+      .load_all_limit(data.len() as u64 - 1)
+      .open(path.clone())
+      .unwrap();
+    assert!(limited.load_all().is_err());
+    let reader = BgzfReader::builder()
+      .load_all_limit(data.len() as u64)
+      .open(path.clone())
+      .unwrap();
+    reader.load_all().unwrap();
+    reader.load_all().unwrap();
+
+    //the file is not read any more, so damaging it does not change reads
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_all_at(0, &vec![0; bgzf.len()]).unwrap();
+    let stats = reader.cache_stats();
+    for &(start, length) in &[
+      (0, 10),
+      (999, 2),
+      (12_345, 7_000),
+      (29_990, 10),
+      (0, 30_000),
+    ] {
+      let mut buffer = vec![0; length];
+      reader.seek(start as u64);
+      assert_eq!(length, reader.read_to(&mut buffer).unwrap());
+      assert_eq!(&data[start..start + length], &buffer[..]);
+      assert_eq!((start + length) as u64, reader.pos.get());
+    }
+    let mut buffer = vec![0; 100];
+    reader.seek(29_950);
+    assert_eq!(50, reader.read(&mut buffer, 10, 90).unwrap());
+    assert_eq!(&data[29_950..], &buffer[10..60]);
+    assert_eq!(0, reader.read_to(&mut buffer).unwrap());
+    reader.seek(40_000);
+    assert_eq!(0, reader.read(&mut buffer, 0, 100).unwrap());
+    let (mut first, mut second) = ([0; 3], [0; 5]);
+    reader.seek(100);
+    let mut bufs = [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)];
+    assert_eq!(8, reader.read_vectored(&mut bufs).unwrap());
+    assert_eq!(&data[100..108], &[&first[..], &second[..]].concat()[..]);
+    assert_eq!(stats, reader.cache_stats());
+
+    //unlike a reader that was not loaded
+    assert!(limited.read_to(&mut buffer).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_verify_blocks() {
+    let data = (0..20_000u32).map(|n| (n % 239) as u8).collect::<Vec<_>>();
+    let path = fixtures::write_temp("verify_blocks", &fixtures::make_bgzf(&data, 1000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(reader.verify_blocks(0..20).is_ok());
+    assert!(reader.verify_blocks(5..5).is_ok());
+    assert!(reader.verify_blocks(19..21).is_err());
+    reader.seek(1234);
+    assert!(reader.verify_blocks(0..20).is_ok());
+    assert_eq!(1234, reader.pos.get());
+
+    //the data decompresses fine, only its CRC32 tells it is wrong
+    fs::write(&path, fixtures::make_with_bad_crc(&data, 1000, 12)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(reader.verify_blocks(0..12).is_ok());
+    assert!(reader.verify_blocks(13..20).is_ok());
+    assert!(matches!(
+      reader.verify_blocks(0..20),
+      Err(BgzfError::ChecksumMismatch {
+        block: BlockContext {
+          index: 12,
+          uncompressed_offset: 12_000,
+          operation: BlockOperation::CheckingCrc,
+          ..
+        },
+        ..
+      })
+    ));
+    reader.seek(12_000);
+    assert!(reader.read_to(&mut vec![0; 1000]).is_ok());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_errors_locate_the_block() {
+    let data = (0..20_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let bgzf = fixtures::make_with_bad_crc(&data, 1000, 12);
+    let path = fixtures::write_temp("errors_locate", &bgzf).unwrap();
+    let damaged = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(12)
+      .unwrap();
+    let expected_start = format!(
+      "The CRC32 of block 12 at compressed offset {} (uncompressed offset 12000) is ",
+      damaged.compressed_offset
+    );
+    let located = |error: BgzfError| {
+      let message = error.to_string();
+      assert!(message.starts_with(&expected_start), "{}", message);
+      assert_eq!(
+        Some(BlockOperation::CheckingCrc),
+        error.block_context().map(|block| block.operation)
+      );
+    };
+
+    let open = |builder: BgzfReaderBuilder| builder.verify_crc(true).open(path.clone()).unwrap();
+    let mut all = vec![0; data.len()];
+    located(open(BgzfReader::builder()).read_to(&mut all).err().unwrap());
+    let reader = open(BgzfReader::builder().prefetch(4));
+    let mut block = vec![0; 1000];
+    for _block in 0..12 {
+      assert_eq!(1000, reader.read_to(&mut block).unwrap());
+    }
+    located(reader.read_to(&mut block).err().unwrap());
+    let reader = open(BgzfReader::builder());
+    located(reader.decompress_all_parallel(3).err().unwrap());
+    located(
+      reader
+        .decompress_to_writer_parallel(3, 4, io::sink())
+        .err()
+        .unwrap(),
+    );
+    located(reader.verify_blocks(10..20).err().unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  //timing, run with --release --ignored
+  #[test]
+  #[ignore]
+  fn test_verify_blocks_cost() {
+    let mut state = 1u32;
+    let data = (0..64 << 20)
+      .map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        b"ACGT"[(state >> 30) as usize]
+      })
+      .collect::<Vec<_>>();
+    let path =
+      fixtures::write_temp("verify_blocks_cost", &fixtures::make_bgzf(&data, 65280)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks = reader.blocks().clone();
+    let time = |verify: bool| {
+      let start = std::time::Instant::now();
+      if verify {
+        reader.verify_blocks(0..blocks.len()).unwrap();
+      } else {
+        let mut un_compressor = reader.decompressors.acquire();
+        let (mut block_data, mut uncompressed) = (Vec::new(), Vec::new());
+        for block in &blocks {
+          uncompressed.resize(block.input_length as usize, 0);
+          decompress_block_at(
+            reader.block_source(),
+            &mut un_compressor,
+            block,
+            false,
+            &mut block_data,
+            &mut uncompressed,
+          )
+          .unwrap();
+        }
       }
+      start.elapsed()
     };
-    let file_content_two = str::from_utf8(&content_two).unwrap();
-    assert_eq!("test,lets see how it reacts. :).", file_content_two);
+    let decompressing = (0..3).map(|_| time(false)).min().unwrap();
+    let verifying = (0..3).map(|_| time(true)).min().unwrap();
+    assert!(
+      verifying.as_secs_f64() < decompressing.as_secs_f64() * 1.2,
+      "{:?} verifying, {:?} decompressing",
+      verifying,
+      decompressing
+    );
+    fs::remove_file(path).unwrap();
   }
 
   #[test]
-  fn test_seek_method() {
-    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-    reader.seek(33);
-    assert_eq!(0, reader.current_read_position.get());
-    assert_eq!(33, reader.pos.get());
+  fn test_verify_crc() {
+    let data = (0..40_000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    //stored blocks, a byte of their data changed still inflates
+    let mut writer = BgzfWriter::builder()
+      .compression_level(0)
+      .block_size(1000)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(&data).unwrap();
+    let mut bgzf = writer.finish().unwrap();
+    let path = fixtures::write_temp("verify_crc", &bgzf).unwrap();
+    let damaged = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(30)
+      .unwrap();
+    bgzf[damaged.compressed_offset as usize + 30] ^= 0xff;
+    fs::write(&path, &bgzf).unwrap();
+    let mismatch = |error: BgzfError| match error {
+      BgzfError::ChecksumMismatch {
+        block,
+        expected,
+        actual,
+      } => block.index == 30 && block.uncompressed_offset == 30_000 && expected != actual,
+      _ => false,
+    };
+
+    //the damaged data is read as is by default
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut all = vec![0; data.len()];
+    assert_eq!(data.len(), reader.read_to(&mut all).unwrap());
+    assert_ne!(data, all);
+
+    let open = |builder: BgzfReaderBuilder| builder.verify_crc(true).open(path.clone()).unwrap();
+    //the block read alone, then into a buffer it fits in, by reads that
+    //prefetch, from the compressed cache and with the whole file
+    let reader = open(BgzfReader::builder());
+    reader.seek(30_010);
+    assert!(mismatch(reader.read_to(&mut [0; 10]).err().unwrap()));
+    reader.seek(29_000);
+    assert!(mismatch(reader.read_to(&mut all).err().unwrap()));
+    let reader = open(BgzfReader::builder().prefetch(4));
+    let mut block = vec![0; 1000];
+    for _block in 0..30 {
+      assert_eq!(1000, reader.read_to(&mut block).unwrap());
+    }
+    assert!(mismatch(reader.read_to(&mut block).err().unwrap()));
+    let reader = open(BgzfReader::builder().compressed_cache_bytes(1 << 20));
+    reader.seek(30_000);
+    assert!(mismatch(reader.read_to(&mut block).err().unwrap()));
+    assert!(mismatch(reader.read_to(&mut block).err().unwrap()));
+    let reader = open(BgzfReader::builder());
+    assert!(mismatch(reader.decompress_all_parallel(4).err().unwrap()));
+    assert!(mismatch(reader.load_all().err().unwrap()));
+    assert!(mismatch(
+      reader
+        .decompress_to_writer_parallel(4, 8, io::sink())
+        .err()
+        .unwrap()
+    ));
+    //the other blocks still read fine
+    reader.seek(31_000);
+    assert_eq!(9000, reader.read_to(&mut all).unwrap());
+    assert_eq!(&data[31_000..], &all[..9000]);
+    fs::remove_file(path).unwrap();
   }
 
   #[test]
-  fn test_read_to() {
-    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-    let mut vec = vec![0; 52];
-    let data_read = reader.read_to(&mut vec);
-    assert_eq!(data_read.unwrap(), 52);
+  fn test_empty_files() {
+    let path = fixtures::write_temp("empty_files", &EOF_MARKER).unwrap();
+    //a file of the EOF marker alone, as bgzip writes empty data, and a file
+    //of no bytes at all
+    for bgzf in &[&EOF_MARKER[..], &[]] {
+      fs::write(&path, bgzf).unwrap();
+      #[allow(unused_mut)]
+      let mut builders = vec![
+        BgzfReader::builder(),
+        BgzfReader::builder().lazy(true),
+        BgzfReader::builder().background_indexing(true),
+        BgzfReader::builder()
+          .prefetch(2)
+          .compressed_cache_bytes(1 << 20),
+      ];
+      #[cfg(feature = "mmap")]
+      builders.push(BgzfReader::builder().mmap(true));
+      for builder in builders {
+        let mut reader = builder.open(path.clone()).unwrap();
+        assert_eq!(0, reader.total_uncompressed_length());
+        assert_eq!(0, reader.num_blocks());
+        assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+        reader.seek(0);
+        assert_eq!(0, reader.read(&mut [0; 10], 2, 8).unwrap());
+        reader.seek(100);
+        assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+        reader.seek(0);
+        let mut all = Vec::new();
+        assert_eq!(0, io::Read::read_to_end(&mut reader, &mut all).unwrap());
+        reader.seek(0);
+        assert_eq!(0, io::BufRead::lines(io::BufReader::new(&reader)).count());
+        assert!(reader.verify_blocks(0..0).is_ok());
+        assert!(reader.load_all().is_ok());
+        assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+      }
+    }
+    //unless the EOF marker is required
+    assert!(matches!(
+      BgzfReader::builder()
+        .require_eof_marker(true)
+        .open(path.clone()),
+      Err(BgzfError::MissingEofMarker)
+    ));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_not_bgzf() {
+    let path =
+      fixtures::write_temp("plain_gzip", &fixtures::make_plain_gzip(TEST_CONTENT, 100)).unwrap();
+    let not_bgzf = |looks_like_gzip: bool, error: BgzfError| matches!(error, BgzfError::NotBgzf { looks_like_gzip: gzip } if gzip == looks_like_gzip);
+    //refused rather than opened as an empty, truncated file
+    assert!(not_bgzf(true, BgzfReader::new(path.clone()).err().unwrap()));
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone());
+    assert!(not_bgzf(true, reader.err().unwrap()));
+    let reader = BgzfReader::builder()
+      .open_with_progress(path.clone(), 1, |_progress| ControlFlow::Continue(()));
+    assert!(not_bgzf(true, reader.err().unwrap()));
+
+    fs::write(&path, TEST_CONTENT).unwrap();
+    assert!(not_bgzf(
+      false,
+      BgzfReader::new(path.clone()).err().unwrap()
+    ));
+    //a file cut within the header of its first block is truncated
+    let bgzf = fs::read("bgzf_test.bgz").unwrap();
+    fs::write(&path, &bgzf[..13]).unwrap();
+    assert!(matches!(
+      BgzfReader::new(path.clone()),
+      Err(BgzfError::Truncated(Truncated { block_offset: 0 }))
+    ));
+    fs::write(&path, []).unwrap();
     assert_eq!(
-      "This is just a bgzf test,lets see how it reacts. :).",
-      str::from_utf8(&vec).unwrap()
+      0,
+      BgzfReader::new(path.clone())
+        .unwrap()
+        .total_uncompressed_length()
     );
+    fs::remove_file(path).unwrap();
   }
 
   #[test]
-  fn test_cache() {
-    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-    let mut vec = vec![0; 52];
-    let data_read = reader.read_to(&mut vec);
-    assert_eq!(data_read.unwrap(), 52);
+  fn test_from_bytes() {
+    //bytes that barely compress, for compressed data long enough to be
+    //scanned in parallel
+    let mut state = 1u32;
+    let content = (0..1_200_000)
+      .map(|_i| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 24) as u8
+      })
+      .collect::<Vec<_>>();
+    let bgzf = fixtures::make_bgzf(&content, 1000);
+    assert!(bgzf.len() as u64 >= PARALLEL_SCAN_MIN_LENGTH);
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().lazy(true),
+      BgzfReader::builder().background_indexing(true),
+      BgzfReader::builder().threads(4),
+      BgzfReader::builder().prefetch(2).verify_crc(true),
+    ];
+    for builder in builders {
+      let reader = builder.open_bytes(bgzf.clone()).unwrap();
+      let mut buf = vec![0; 5000];
+      reader.seek(123_456);
+      reader.read_to(&mut buf).unwrap();
+      assert_eq!(&content[123_456..128_456], &buf[..]);
+      assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+      assert!(reader.has_eof_marker());
+    }
+
+    let reader = BgzfReader::from_bytes(fixtures::make_without_eof(TEST_CONTENT, 100)).unwrap();
     assert_eq!(
-      "This is just a bgzf test,lets see how it reacts. :).",
-      str::from_utf8(&vec).unwrap()
+      TEST_CONTENT,
+      &reader.decompress_all_parallel(2).unwrap()[..]
+    );
+    assert!(!reader.has_eof_marker());
+    assert!(matches!(
+      BgzfReader::from_bytes(TEST_CONTENT.to_vec()),
+      Err(BgzfError::NotBgzf {
+        looks_like_gzip: false
+      })
+    ));
+    assert!(matches!(
+      BgzfReader::from_bytes(fixtures::make_truncated(TEST_CONTENT, 100, 5)),
+      Err(BgzfError::Truncated(_))
+    ));
+    assert_eq!(
+      0,
+      BgzfReader::from_bytes(Vec::new())
+        .unwrap()
+        .total_uncompressed_length()
     );
+  }
 
-    let mut vec2 = vec![0; 119];
-    let data_read_2 = reader.read_to(&mut vec2);
-    assert_eq!(data_read_2.unwrap(), 119);
+  #[test]
+  fn test_open_read_at_seek_read_source() {
+    let mut state = 7u32;
+    let content = (0..300_000)
+      .map(|_i| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 28) as u8 + b'a'
+      })
+      .collect::<Vec<_>>();
+    let bgzf = fixtures::make_bgzf(&content, 5000);
+    let length = bgzf.len() as u64;
+    let expected = BgzfReader::from_bytes(bgzf.clone()).unwrap();
+    let path = fixtures::write_temp("seek_read_source", &bgzf).unwrap();
+    let builders = [
+      BgzfReader::builder(),
+      BgzfReader::builder().lazy(true),
+      BgzfReader::builder().threads(4).prefetch(2),
+    ];
+    for builder in builders {
+      let cursor = SeekReadSource::new(io::Cursor::new(bgzf.clone())).unwrap();
+      let file = SeekReadSource::new(File::open(&path).unwrap()).unwrap();
+      for reader in [
+        builder.clone().open_read_at(cursor, length).unwrap(),
+        builder.clone().open_read_at(file, length).unwrap(),
+      ] {
+        let mut buf = vec![0; 7000];
+        reader.seek(123_456);
+        reader.read_to(&mut buf).unwrap();
+        assert_eq!(&content[123_456..130_456], &buf[..]);
+        assert!(expected.block_offsets().eq(reader.block_offsets()));
+        assert_eq!(content, reader.decompress_all_parallel(3).unwrap());
+        assert!(reader.verify(2).unwrap().failures.is_empty());
+      }
+    }
+
+    //two readers hammering one adapter from two threads
+    let reader = BgzfReader::builder()
+      .open_read_at(SeekReadSource::new(io::Cursor::new(bgzf)).unwrap(), length);
+    let reader = reader.unwrap();
+    thread::scope(|scope| {
+      for thread_number in 0..2u64 {
+        let reader = reader.try_clone().unwrap();
+        let content = &content;
+        scope.spawn(move || {
+          let mut buf = vec![0; 3000];
+          let mut pos = thread_number * 54_321;
+          for _ in 0..300 {
+            pos = (pos * 48_271 + 17) % (content.len() as u64 - 3000);
+            reader.seek(pos);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&content[pos as usize..pos as usize + 3000], &buf[..]);
+          }
+        });
+      }
+    });
+
+    //the data ends at the length given, and the bytes a source shorter than
+    //it misses are trailing bytes after the blocks, as in a file
+    let mut with_trailer = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let length = with_trailer.len() as u64;
+    with_trailer.extend_from_slice(b"trailing bytes of a container");
+    let reader = BgzfReader::builder()
+      .open_read_at(with_trailer, length)
+      .unwrap();
     assert_eq!(
-    " I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarante",
-      str::from_utf8(&vec2).unwrap()
+      TEST_CONTENT,
+      &reader.decompress_all_parallel(1).unwrap()[..]
     );
+    assert!(reader.has_eof_marker());
+    let reader = BgzfReader::builder()
+      .open_read_at(fixtures::make_bgzf(TEST_CONTENT, 100), length + 100)
+      .unwrap();
+    assert!(!reader.has_eof_marker());
+    assert_eq!(128, reader.verify(1).unwrap().trailing_bytes);
+    assert!(BgzfReader::builder()
+      .require_eof_marker(true)
+      .open_read_at(fixtures::make_bgzf(TEST_CONTENT, 100), length + 100)
+      .is_err());
+    fs::remove_file(path).unwrap();
+  }
 
-    let mut vec3 = vec![0; 2];
-    let data_read_3 = reader.read_to(&mut vec3);
-    assert_eq!(data_read_3.unwrap(), 2);
-    assert_eq!("e ", str::from_utf8(&vec3).unwrap());
+  #[test]
+  fn test_bc_subfield_anywhere() {
+    let path = test_fixture("bc_subfield_good", 50);
+    let good_sizes: Vec<u32> = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .map(|block| block.block_size)
+      .collect();
+    fs::remove_file(path).unwrap();
+    for &bc_first in &[true, false] {
+      let bgzf = fixtures::make_with_extra_subfield(TEST_CONTENT, 50, bc_first);
+      let path = fixtures::write_temp("bc_subfield", &bgzf).unwrap();
+      let builders = [
+        BgzfReader::builder(),
+        BgzfReader::builder().lazy(true),
+        BgzfReader::builder().threads(3),
+      ];
+      for builder in builders {
+        let reader = builder.open(path.clone()).unwrap();
+        let mut content = vec![0; TEST_CONTENT.len()];
+        assert_eq!(TEST_CONTENT.len(), reader.read_to(&mut content).unwrap());
+        assert_eq!(TEST_CONTENT, &content[..]);
+        //each block 6 bytes longer, the extra field counted in BSIZE
+        let sizes: Vec<u32> = reader
+          .block_offsets()
+          .map(|block| block.block_size - 6)
+          .collect();
+        assert_eq!(good_sizes, sizes);
+      }
+      fs::remove_file(path).unwrap();
+    }
+
+    //a block without BC can not be read
+    let bgzf = fixtures::make_without_bc_subfield(TEST_CONTENT, 50, 1);
+    let second = read_block(&bgzf, 0).unwrap().block_size as u64;
+    assert!(matches!(
+      read_block(&bgzf, second),
+      Err(BgzfError::BadSubfield { offset }) if offset == second
+    ));
+    let path = fixtures::write_temp("bc_subfield_missing", &bgzf).unwrap();
+    //where opening stops, as at any damaged block
+    assert!(matches!(
+      BgzfReader::new(path.clone()).err().unwrap(),
+      BgzfError::Truncated(Truncated { block_offset }) if block_offset == second
+    ));
+    //nor a file whose first block has none
+    let bgzf = fixtures::make_without_bc_subfield(TEST_CONTENT, 50, 0);
+    fs::write(&path, bgzf).unwrap();
+    assert!(matches!(
+      BgzfReader::new(path.clone()).err().unwrap(),
+      BgzfError::NotBgzf {
+        looks_like_gzip: true
+      }
+    ));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_header_fields() {
+    //FHCRC, FNAME and FCOMMENT in each of their combinations
+    for combination in 0..8u8 {
+      let flags = [2, 8, 16]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _flag)| combination & (1 << bit) != 0)
+        .fold(0, |flags, (_bit, flag)| flags | flag);
+      let bgzf = fixtures::make_with_header_fields(TEST_CONTENT, 50, flags);
+      let path = fixtures::write_temp("header_fields", &bgzf).unwrap();
+      let file = File::open(&path).unwrap();
+      let mut starts = vec![0];
+      let (block, anomaly) = read_block_with_anomaly(&file, 0, ParseMode::Default).unwrap();
+      assert_eq!(
+        if flags == 0 {
+          None
+        } else {
+          Some(AnomalyKind::HeaderFlags)
+        },
+        anomaly
+      );
+      assert_eq!(50, block.input_length);
+      while starts.len() < 6 {
+        let block = read_block(&file, *starts.last().unwrap()).unwrap();
+        starts.push(block.block_offset + u64::from(block.block_size));
+      }
+
+      let builders = [
+        BgzfReader::builder(),
+        BgzfReader::builder().lazy(true),
+        BgzfReader::builder().threads(3),
+      ];
+      for builder in builders {
+        let reader = builder.open(path.clone()).unwrap();
+        let mut content = vec![0; TEST_CONTENT.len()];
+        assert_eq!(TEST_CONTENT.len(), reader.read_to(&mut content).unwrap());
+        assert_eq!(TEST_CONTENT, &content[..]);
+        let offsets: Vec<u64> = reader
+          .block_offsets()
+          .map(|block| block.compressed_offset)
+          .collect();
+        assert_eq!(starts, offsets, "flags {}", flags);
+        assert_eq!(
+          TEST_CONTENT,
+          &reader.decompress_all_parallel(2).unwrap()[..]
+        );
+      }
+      fs::remove_file(path).unwrap();
+    }
+
+    assert_eq!(
+      Some(15),
+      header_fields_length(2 | 8 | 16, b"name\0comment\0..data")
+    );
+    assert_eq!(Some(0), header_fields_length(0, b""));
+    //a name that is not zero terminated, or no room for the CRC16
+    assert_eq!(None, header_fields_length(8, b"name"));
+    assert_eq!(None, header_fields_length(8 | 2, b"name\0."));
+  }
+
+  #[test]
+  fn test_error_variants() {
+    let path = fixtures::write_temp(
+      "error_variants",
+      &fixtures::make_without_eof(TEST_CONTENT, 100),
+    )
+    .unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(matches!(
+      reader.read(&mut [0; 10], 5, 10),
+      Err(BgzfError::OutOfRange { pos: 15, len: 10 })
+    ));
+    assert!(matches!(
+      reader.read(&mut [0; 10], 20, 0),
+      Err(BgzfError::OutOfRange { pos: 20, len: 10 })
+    ));
+    assert!(matches!(
+      BgzfReader::builder()
+        .require_eof_marker(true)
+        .open(path.clone()),
+      Err(BgzfError::MissingEofMarker)
+    ));
+    assert!(matches!(
+      BgzfReader::builder()
+        .load_all_limit(279)
+        .open(path.clone())
+        .unwrap()
+        .load_all(),
+      Err(BgzfError::TooLarge {
+        length: 280,
+        limit: 279
+      })
+    ));
+
+    //the second block damaged after indexing, at the magic number, the BC
+    //subfield identifier, BSIZE and the deflate data
+    let block = reader.block_offsets().nth(1).unwrap();
+    let offset = block.compressed_offset;
+    let bgzf = fs::read(&path).unwrap();
+    let damaged_error = |at: usize, byte: u8| {
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+      file.write_all_at(offset + at as u64, &[byte]).unwrap();
+      reader.seek(150);
+      let error = reader.read_to(&mut [0; 10]).err().unwrap();
+      file.write_all_at(0, &bgzf).unwrap();
+      error
+    };
+    let mismatch = |error: BgzfError| match error {
+      BgzfError::IndexMismatch { block } => {
+        block.index == 1 && block.compressed_offset == offset && block.uncompressed_offset == 100
+      }
+      _ => false,
+    };
+    assert!(mismatch(damaged_error(0, 0)));
+    assert!(mismatch(damaged_error(12, b'X')));
+    assert!(mismatch(damaged_error(16, bgzf[offset as usize + 16] ^ 1)));
+    let error = damaged_error(18, 0xff);
+    assert_eq!(
+      format!(
+        "The deflate data of block 1 at compressed offset {} (uncompressed offset 100) is corrupt",
+        offset
+      ),
+      error.to_string()
+    );
+    assert!(matches!(
+      error,
+      BgzfError::CorruptDeflateStream {
+        block: BlockContext {
+          index: 1,
+          uncompressed_offset: 100,
+          operation: BlockOperation::Decompressing,
+          ..
+        },
+        ..
+      }
+    ));
+    //the deflate error is the source, under the block it happened in
+    let source = error.source().unwrap();
+    assert_eq!(
+      io::ErrorKind::InvalidData,
+      source.downcast_ref::<io::Error>().unwrap().kind()
+    );
+    #[cfg(all(feature = "libdeflate", not(feature = "rust-backend")))]
+    assert!(source
+      .downcast_ref::<io::Error>()
+      .and_then(io::Error::get_ref)
+      .unwrap()
+      .is::<libdeflater::DecompressionError>());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_decompression_errors() {
+    let block_offset = |bgzf: &[u8]| read_block(&bgzf, 0).unwrap().block_size as usize;
+    let read_second_block = |bgzf: &[u8], name: &str| {
+      let path = fixtures::write_temp(name, bgzf).unwrap();
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      reader.seek(100);
+      let error = reader.read_to(&mut [0; 10]).unwrap_err();
+      fs::remove_file(path).unwrap();
+      error
+    };
+
+    //footers giving the block less or more data than it holds
+    for (isize, name) in [(60, "isize_less"), (150, "isize_more")] {
+      let bgzf = fixtures::make_with_bad_isize(TEST_CONTENT, 100, 1, isize);
+      let offset = block_offset(&bgzf) as u64;
+      let error = read_second_block(&bgzf, name);
+      match &error {
+        BgzfError::SizeMismatch {
+          block,
+          expected,
+          actual: 100,
+          source,
+        } => {
+          assert_eq!(u64::from(isize), *expected);
+          assert_eq!(
+            (1, offset, 100),
+            (
+              block.index,
+              block.compressed_offset,
+              block.uncompressed_offset
+            )
+          );
+          assert_eq!(io::ErrorKind::InvalidData, source.kind());
+        }
+        other => panic!("{:?}", other),
+      }
+      assert_eq!(
+        format!(
+          "The deflate data of block 1 at compressed offset {} (uncompressed offset 100) decompresses to 100 bytes instead of the {} of its footer",
+          offset, isize
+        ),
+        error.to_string()
+      );
+      assert!(error.source().is_some());
+      assert_eq!(io::ErrorKind::InvalidData, io::Error::from(error).kind());
+    }
+
+    //deflate data that is not
+    let mut bgzf = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let offset = block_offset(&bgzf);
+    bgzf[offset + 18..offset + 28].copy_from_slice(&[0xff; 10]);
+    match read_second_block(&bgzf, "corrupt_deflate") {
+      BgzfError::CorruptDeflateStream { block, source } => {
+        assert_eq!((1, offset as u64), (block.index, block.compressed_offset));
+        assert_eq!(io::ErrorKind::InvalidData, source.kind());
+      }
+      other => panic!("{:?}", other),
+    }
+
+    //a buffer of the wrong length, which the reader never passes
+    let bgzf = fixtures::make_bgzf(TEST_CONTENT, 100);
+    let block = read_block(&bgzf, 0).unwrap();
+    let mut uncompressed = vec![0; 99];
+    let error = inflate_block(
+      &mut Decompressor::new(),
+      &block,
+      true,
+      &bgzf[..block.block_size as usize],
+      &mut uncompressed,
+    )
+    .unwrap_err();
+    assert!(
+      matches!(&error, BgzfError::InternalBufferError { block, .. } if block.compressed_offset == 0),
+      "{:?}",
+      error
+    );
+    assert_eq!(
+      "The buffer for decompressing block 0 at compressed offset 0 (uncompressed offset 0) does not fit its data, this is a bug",
+      error.to_string()
+    );
+    assert_eq!(
+      "99 bytes of buffer for 100 bytes of data",
+      error.source().unwrap().to_string()
+    );
+    assert_eq!(io::ErrorKind::Other, io::Error::from(error).kind());
+    uncompressed.resize(100, 0);
+    inflate_block(
+      &mut Decompressor::new(),
+      &block,
+      true,
+      &bgzf[..block.block_size as usize],
+      &mut uncompressed,
+    )
+    .unwrap();
+    assert_eq!(&TEST_CONTENT[..100], &uncompressed[..]);
+  }
+
+  #[test]
+  fn test_try_clone_after_unlinking() {
+    let path = test_fixture("try_clone_unlinked", 10);
+    let reader = BgzfReader::builder()
+      .cache_bytes(100)
+      .prefetch(2)
+      .open(path.clone())
+      .unwrap();
+    let mut start = vec![0; 50];
+    reader.read_to(&mut start).unwrap();
+    //on unix the open file outlives its path
+    #[cfg(unix)]
+    fs::remove_file(&path).unwrap();
+    let other = reader.try_clone().unwrap();
+    assert_eq!(0, other.pos.get());
+    assert_eq!(100, lock(&other.cache).stats().budget);
+    assert_eq!(0, lock(&other.cache).stats().blocks);
+    assert!(other.prefetcher.is_some());
+
+    //both read on from their own position, in turns and from threads
+    let mut end = vec![0; 100];
+    other.seek(180);
+    other.read_to(&mut end).unwrap();
+    reader.read_to(&mut start).unwrap();
+    assert_eq!(&TEST_CONTENT[180..], &end[..]);
+    assert_eq!(&TEST_CONTENT[50..100], &start[..]);
+    thread::scope(|scope| {
+      for reader in [&reader, &other] {
+        scope.spawn(move || {
+          let mut all = vec![0; 280];
+          assert_eq!(280, reader.read_at(0, &mut all).unwrap());
+          assert_eq!(TEST_CONTENT, &all[..]);
+        });
+      }
+    });
+    assert_eq!(100, reader.pos.get());
+    assert_eq!(280, other.pos.get());
+    #[cfg(not(unix))]
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_try_clone_indexes_the_rest() {
+    let path = test_fixture("try_clone_lazy", 10);
+    for builder in [
+      BgzfReader::builder().lazy(true),
+      BgzfReader::builder().background_indexing(true),
+      BgzfReader::builder().compressed_cache_bytes(1 << 20),
+    ] {
+      let reader = builder.open(path.clone()).unwrap();
+      let mut start = vec![0; 15];
+      reader.read_to(&mut start).unwrap();
+      let other = reader.try_clone().unwrap();
+      let mut all = vec![0; 280];
+      assert_eq!(280, other.read_at(0, &mut all).unwrap());
+      assert_eq!(TEST_CONTENT, &all[..]);
+      assert_eq!(28, other.num_blocks());
+      assert_eq!(reader.index().unwrap(), other.index().unwrap());
+      assert_eq!(
+        reader.compressed_cache.is_some(),
+        other.compressed_cache.is_some()
+      );
+    }
+    fs::remove_file(path).unwrap();
+  }
+  #[test]
+  fn test_open_at_embedded_stream() {
+    //the fixture after a header of 512 bytes, followed by another stream
+    let bgzf = fs::read("bgzf_test.bgz").unwrap();
+    let mut container = b"header".repeat(100);
+    container.resize(512, 0);
+    container.extend_from_slice(&bgzf);
+    container.extend_from_slice(&fixtures::make_bgzf(b"other data", 100));
+    let path = fixtures::write_temp("open_at", &container).unwrap();
+    let whole = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    let bgzf_length = Some(bgzf.len() as u64);
+
+    for builder in [BgzfReader::builder(), BgzfReader::builder().lazy(true)] {
+      let reader = builder.open_at(path.clone(), 512, bgzf_length).unwrap();
+      let mut all = vec![0; 300];
+      assert_eq!(280, reader.read_at(0, &mut all).unwrap());
+      assert_eq!(TEST_CONTENT, &all[..280]);
+      //offsets are those of the stream on its own
+      for uoffset in [0, 41, 100, 279] {
+        let voffset = whole.uoffset_to_voffset(uoffset).unwrap();
+        assert_eq!(voffset, reader.uoffset_to_voffset(uoffset).unwrap());
+        reader.seek_virtual(voffset).unwrap();
+        assert_eq!(voffset, reader.tell_virtual());
+      }
+      assert_eq!(whole.index().unwrap(), reader.index().unwrap());
+      assert_eq!(
+        bgzf.len() as u64,
+        reader.compressed_length_with_eof().unwrap()
+      );
+    }
+
+    //unbounded, the stream goes on with the one after it
+    let reader = BgzfReader::open_at(path.clone(), 512, None).unwrap();
+    assert_eq!(290, reader.total_uncompressed_length());
+
+    assert!(BgzfReader::open_at(path.clone(), 511, bgzf_length).is_err());
+    assert!(BgzfReader::open_at(path.clone(), container.len() as u64, Some(1)).is_err());
+    fs::remove_file(path).unwrap();
+  }
+
+  /// Uncompressed length of the stored blocks of `sparse_large_file`
+  const LARGE_BLOCK_LENGTH: u64 = 65280;
+
+  /// A sparse file of `blocks` stored blocks of `fixtures::offset_pattern`
+  /// data, and the index of all of them, the blocks numbered `written` being
+  /// the only ones actually written, the others reading as zeros
+  fn sparse_large_file(name: &str, blocks: u64, written: &[u64]) -> (String, BgzfIndex) {
+    let stored_block = |number: u64| {
+      let mut data = vec![0; LARGE_BLOCK_LENGTH as usize];
+      fixtures::offset_pattern(number * LARGE_BLOCK_LENGTH, &mut data);
+      let mut writer = BgzfWriter::builder()
+        .compression_level(0)
+        .block_size(LARGE_BLOCK_LENGTH as usize)
+        .build(Vec::new())
+        .unwrap();
+      writer.write_all(&data).unwrap();
+      let mut block = writer.finish().unwrap();
+      block.truncate(block.len() - EOF_MARKER.len());
+      block
+    };
+    let block_size = stored_block(0).len() as u64;
+    let path = fixtures::write_temp(name, b"").unwrap();
+    let file = File::options().write(true).open(&path).unwrap();
+    file
+      .set_len(blocks * block_size + EOF_MARKER.len() as u64)
+      .unwrap();
+    let mut file = file;
+    for &number in written {
+      let block = stored_block(number);
+      assert_eq!(block_size, block.len() as u64);
+      file.write_all_at(number * block_size, &block).unwrap();
+    }
+    file.write_all_at(blocks * block_size, &EOF_MARKER).unwrap();
+    let index = BgzfIndex::new(
+      (0..blocks)
+        .map(|number| BlockInfo {
+          uncompressed_offset: number * LARGE_BLOCK_LENGTH,
+          compressed_offset: number * block_size,
+          block_size: block_size as u32,
+          uncompressed_length: LARGE_BLOCK_LENGTH as u32,
+        })
+        .collect(),
+    )
+    .unwrap();
+    (path, index)
   }
 
+  #[test]
+  fn test_offsets_past_4_gib() {
+    //past 4 GiB compressed and uncompressed, the stored blocks being larger
+    //than their data
+    let blocks = (1 << 32) / LARGE_BLOCK_LENGTH + 3;
+    let block_size = 65311;
+    //around 2^31 and 2^32 in the data and in the file
+    let mut positions = vec![(1 << 31) - 1, 1 << 31, (1 << 32) - 1, 1 << 32];
+    for compressed in [1u64 << 31, 1 << 32] {
+      let number = compressed / block_size + 1;
+      positions.push(number * LARGE_BLOCK_LENGTH);
+    }
+    let mut written = positions
+      .iter()
+      .flat_map(|&uoffset| {
+        let number = uoffset / LARGE_BLOCK_LENGTH;
+        [number - 1, number, number + 1]
+      })
+      .chain([blocks - 1])
+      .collect::<Vec<_>>();
+    written.sort_unstable();
+    written.dedup();
+    let (path, index) = sparse_large_file("past_4_gib", blocks, &written);
+    assert_eq!(
+      blocks * LARGE_BLOCK_LENGTH,
+      index.total_uncompressed_length()
+    );
+    assert!(index.data_end() > 1 << 32);
+
+    let reader = BgzfReader::from_file_with_index(File::open(&path).unwrap(), &index).unwrap();
+    assert_eq!(
+      blocks * LARGE_BLOCK_LENGTH,
+      reader.total_uncompressed_length()
+    );
+    assert_eq!(index.data_end(), reader.compressed_length());
+    let mut expected = vec![0; 100];
+    let mut data = vec![0; 100];
+    for &uoffset in &positions {
+      let start = uoffset - 50;
+      fixtures::offset_pattern(start, &mut expected);
+
+      reader.seek(start);
+      assert_eq!(100, reader.read_to(&mut data).unwrap());
+      assert_eq!(expected, data, "read at {}", start);
+      assert_eq!(start + 100, reader.pos.get());
+      data.fill(0);
+      assert_eq!(100, reader.read_at(start, &mut data).unwrap());
+      assert_eq!(expected, data, "read_at {}", start);
+      data.fill(0);
+      reader.seek(start);
+      assert_eq!(100, reader.read(&mut data, 0, 100).unwrap());
+      assert_eq!(expected, data, "read at {}", start);
+
+      let number = uoffset / LARGE_BLOCK_LENGTH;
+      let within = uoffset % LARGE_BLOCK_LENGTH;
+      let voffset = (number * block_size) << 16 | within;
+      assert_eq!(voffset, reader.uoffset_to_voffset(uoffset).unwrap());
+      assert_eq!(uoffset, reader.voffset_to_uoffset(voffset).unwrap());
+      reader.seek_virtual(voffset).unwrap();
+      assert_eq!(
+        (uoffset, voffset),
+        (reader.pos.get(), reader.tell_virtual())
+      );
+      assert_eq!(number * block_size, reader.compressed_position());
+      let (found, info) = reader.block_containing(uoffset).unwrap();
+      assert_eq!(number, found as u64);
+      assert_eq!(
+        Some(info),
+        reader.block_at_compressed_offset(number * block_size + 1)
+      );
+      let mut raw = Vec::new();
+      reader.raw_block_range(found, found, &mut raw).unwrap();
+      assert_eq!(block_size, raw.len() as u64);
+    }
+
+    //the last byte, and reads stopping at the end of the data
+    let end = blocks * LARGE_BLOCK_LENGTH;
+    fixtures::offset_pattern(end - 10, &mut expected[..10]);
+    assert_eq!(10, reader.read_at(end - 10, &mut data).unwrap());
+    assert_eq!(expected[..10], data[..10]);
+    reader.seek(end);
+    assert_eq!(0, reader.read_to(&mut data).unwrap());
+    assert_eq!(0, reader.read_at(end + 1, &mut data).unwrap());
+
+    //the offsets past 4 GiB survive the index formats
+    let mut saved = Vec::new();
+    index.save(&mut saved).unwrap();
+    assert_eq!(index, BgzfIndex::load(&saved[..]).unwrap());
+    let gzi_path = format!("{}.gzi", path);
+    reader.save_gzi(gzi_path.clone()).unwrap();
+    let reopened = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+    assert_eq!(
+      reader.block_offsets().collect::<Vec<_>>(),
+      reopened.block_offsets().collect::<Vec<_>>()
+    );
+    fixtures::offset_pattern(1 << 32, &mut expected);
+    assert_eq!(100, reopened.read_at(1 << 32, &mut data).unwrap());
+    assert_eq!(expected, data);
+    fs::remove_file(gzi_path).unwrap();
+    fs::remove_file(path).unwrap();
+  }
 }
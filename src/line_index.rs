@@ -0,0 +1,442 @@
+//! Index of the lines of the uncompressed data, to seek to a line without
+//! reading the data before it, see `BgzfReader::build_line_index`.
+//!
+//! Lines are those of `BufRead::lines`: each ends with a '\n', the last one
+//! may end with the data instead, and a '\r' before the '\n' of CRLF endings
+//! is part of the line ending. A trailing newline does not start another,
+//! empty, line.
+//!
+//! The binary format, all integers little-endian:
+//!
+//! magic: the 4 bytes `BGLI`,
+//! version: u32, currently 1,
+//! spacing: u64, number of lines from one checkpoint to the next,
+//! line count: u64,
+//! uncompressed length: u64, of the data the index was built from,
+//! checkpoint count: u64,
+//! then for every checkpoint the uncompressed offset (u64) of line 0,
+//! spacing, 2 * spacing and so on.
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const LINE_INDEX_MAGIC: [u8; 4] = *b"BGLI";
+
+const LINE_INDEX_VERSION: u32 = 1;
+
+/// Number of lines between checkpoints of `BgzfReader::build_line_index`
+pub const DEFAULT_LINE_SPACING: u64 = 1000;
+
+/// Reasons `BgzfReader::seek_line` can not seek to a line
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineIndexError {
+  /// Line `line` (from 0) is not one of the `line_count` lines of the data
+  LineOutOfRange { line: u64, line_count: u64 },
+  /// The index was built from data of `index_length` bytes, not from the
+  /// `data_length` bytes of this file
+  LengthMismatch { index_length: u64, data_length: u64 },
+  /// The line the index has starting at uncompressed offset `offset` does
+  /// not follow a newline, the data not being the one the index was built
+  /// from
+  NotALineStart { offset: u64 },
+}
+
+impl Error for LineIndexError {}
+
+impl fmt::Display for LineIndexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LineIndexError::LineOutOfRange { line, line_count } => write!(
+        f,
+        "Line {} is past the {} lines of the data",
+        line, line_count
+      ),
+      LineIndexError::LengthMismatch {
+        index_length,
+        data_length,
+      } => write!(
+        f,
+        "The line index is of {} bytes of data, not of the {} bytes of the file",
+        index_length, data_length
+      ),
+      LineIndexError::NotALineStart { offset } => write!(
+        f,
+        "The line index has a line starting at uncompressed offset {}, which does not follow a newline",
+        offset
+      ),
+    }
+  }
+}
+
+/// Offsets of every `spacing`-th line of the uncompressed data and the number
+/// of lines, as returned by `BgzfReader::build_line_index`
+///
+/// Fields description:
+///
+/// spacing: number of lines from one checkpoint to the next,
+/// line_count: number of lines of the data,
+/// input_length: length of the uncompressed data the index was built from,
+/// checkpoints: uncompressed offset of lines 0, spacing, 2 * spacing and so on,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineIndex {
+  spacing: u64,
+  line_count: u64,
+  input_length: u64,
+  checkpoints: Vec<u64>,
+}
+
+impl LineIndex {
+  /// Number of lines from one checkpoint to the next
+  pub fn spacing(&self) -> u64 {
+    self.spacing
+  }
+
+  /// Number of lines of the data
+  pub fn line_count(&self) -> u64 {
+    self.line_count
+  }
+
+  /// Length of the uncompressed data the index was built from
+  pub fn total_uncompressed_length(&self) -> u64 {
+    self.input_length
+  }
+
+  /// Uncompressed offset of the checkpoint at or before `line` and the
+  /// number of lines from it to `line`
+  pub(crate) fn checkpoint_before(&self, line: u64) -> Result<(u64, u64), LineIndexError> {
+    if line >= self.line_count {
+      return Err(LineIndexError::LineOutOfRange {
+        line,
+        line_count: self.line_count,
+      });
+    }
+    let checkpoint = self.checkpoints[(line / self.spacing) as usize];
+    Ok((checkpoint, line % self.spacing))
+  }
+
+  /// Writes the index in the binary format described in the module docs
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, LineIndex};
+  ///
+  /// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  /// let mut saved = Vec::new();
+  /// reader.build_line_index().unwrap().save(&mut saved).unwrap();
+  ///
+  /// let index = LineIndex::load(&saved[..]).unwrap();
+  /// reader.seek_line(0, &index).unwrap();
+  /// assert_eq!(0, reader.pos.get());
+  /// ```
+  pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+    let mut saved = Vec::with_capacity(40 + self.checkpoints.len() * 8);
+    saved.extend_from_slice(&LINE_INDEX_MAGIC);
+    saved.extend_from_slice(&LINE_INDEX_VERSION.to_le_bytes());
+    saved.extend_from_slice(&self.spacing.to_le_bytes());
+    saved.extend_from_slice(&self.line_count.to_le_bytes());
+    saved.extend_from_slice(&self.input_length.to_le_bytes());
+    saved.extend_from_slice(&(self.checkpoints.len() as u64).to_le_bytes());
+    for checkpoint in &self.checkpoints {
+      saved.extend_from_slice(&checkpoint.to_le_bytes());
+    }
+    out.write_all(&saved)?;
+    out.flush()
+  }
+
+  /// Reads an index written by `save`. Another magic or version, a
+  /// checkpoint count that does not match the data or the line count, and
+  /// checkpoints that do not increase within the data are `InvalidData`
+  /// errors.
+  pub fn load<R: Read>(mut input: R) -> io::Result<LineIndex> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0u8; 40];
+    input.read_exact(&mut header)?;
+    if header[..4] != LINE_INDEX_MAGIC {
+      return Err(invalid("Not a line index, the magic number is wrong"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != LINE_INDEX_VERSION {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unsupported line index version {}", version),
+      ));
+    }
+    let word = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+    let (spacing, line_count, input_length, checkpoint_count) =
+      (word(8), word(16), word(24), word(32));
+
+    //read to the end rather than allocating for a count that may be garbage
+    let mut records = Vec::new();
+    input.read_to_end(&mut records)?;
+    if records.len() as u64 != checkpoint_count.saturating_mul(8) {
+      return Err(invalid(
+        "Line index checkpoint count does not match its records",
+      ));
+    }
+    if spacing == 0 || checkpoint_count != line_count.div_ceil(spacing) {
+      return Err(invalid(
+        "Line index checkpoint count does not match its lines",
+      ));
+    }
+    let checkpoints = records
+      .chunks(8)
+      .map(|record| u64::from_le_bytes(record.try_into().unwrap()))
+      .collect::<Vec<_>>();
+    let increasing = checkpoints.windows(2).all(|pair| pair[0] < pair[1]);
+    if checkpoints.first().is_some_and(|&first| first != 0)
+      || !increasing
+      || checkpoints.last().is_some_and(|&last| last >= input_length)
+    {
+      return Err(invalid("Line index checkpoints are not lines of its data"));
+    }
+    Ok(LineIndex {
+      spacing,
+      line_count,
+      input_length,
+      checkpoints,
+    })
+  }
+}
+
+/// Builds a `LineIndex` from the data written to it in order
+///
+/// Fields description:
+///
+/// index: the index so far, its line count the lines started,
+/// written: number of bytes written so far,
+/// after_newline: true when the last byte written is a newline, or nothing
+/// was, so that the next byte starts a line,
+pub(crate) struct LineScan {
+  index: LineIndex,
+  written: u64,
+  after_newline: bool,
+}
+
+impl LineScan {
+  /// Returns a scan with a checkpoint every `spacing` lines, at least 1
+  pub(crate) fn new(spacing: u64) -> LineScan {
+    LineScan {
+      index: LineIndex {
+        spacing: spacing.max(1),
+        line_count: 0,
+        input_length: 0,
+        checkpoints: Vec::new(),
+      },
+      written: 0,
+      after_newline: true,
+    }
+  }
+
+  /// Returns the index of the data written
+  pub(crate) fn finish(mut self) -> LineIndex {
+    self.index.input_length = self.written;
+    self.index
+  }
+
+  fn start_line(&mut self, offset: u64) {
+    if self.index.line_count.is_multiple_of(self.index.spacing) {
+      self.index.checkpoints.push(offset);
+    }
+    self.index.line_count += 1;
+  }
+}
+
+impl Write for LineScan {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut from = 0;
+    while from < buf.len() {
+      if self.after_newline {
+        self.start_line(self.written + from as u64);
+      }
+      match buf[from..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => {
+          from += newline + 1;
+          self.after_newline = true;
+        }
+        None => {
+          from = buf.len();
+          self.after_newline = false;
+        }
+      }
+    }
+    self.written += buf.len() as u64;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::make_bgzf;
+  use crate::{BgzfError, BgzfReader};
+  use std::io::BufRead;
+
+  /// 100k lines of 1 to 60 bytes, some ending with CRLF, the last one with
+  /// no newline
+  fn lines_content() -> Vec<u8> {
+    let mut state = 7u32;
+    let mut content = Vec::new();
+    for i in 0..100_000u32 {
+      state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      content.extend_from_slice(format!("{}\t", i).as_bytes());
+      content.extend(std::iter::repeat_n(b'x', (state >> 26) as usize));
+      if i % 5 == 3 {
+        content.push(b'\r');
+      }
+      content.push(b'\n');
+    }
+    content.pop();
+    content
+  }
+
+  fn index_of(pieces: &[&[u8]], spacing: u64) -> LineIndex {
+    let mut scan = LineScan::new(spacing);
+    for piece in pieces {
+      scan.write_all(piece).unwrap();
+    }
+    scan.finish()
+  }
+
+  #[test]
+  fn test_line_ends() {
+    let index = index_of(&[b"a\nbb\r\n", b"", b"c", b"cc\n\nd"], 2);
+    assert_eq!(5, index.line_count());
+    assert_eq!(vec![0, 6, 11], index.checkpoints);
+    assert_eq!(Ok((6, 1)), index.checkpoint_before(3));
+    assert_eq!(
+      Err(LineIndexError::LineOutOfRange {
+        line: 5,
+        line_count: 5
+      }),
+      index.checkpoint_before(5)
+    );
+
+    //a trailing newline starts no line, a lone '\r' ends none
+    assert_eq!(2, index_of(&[b"a\r", b"b\n", b"c\n"], 1).line_count());
+    assert_eq!(1, index_of(&[b"\n"], 1).line_count());
+    assert_eq!(0, index_of(&[], 1).line_count());
+    assert_eq!(vec![0, 1, 2], index_of(&[b"\n\n", b"\n"], 1).checkpoints);
+  }
+
+  #[test]
+  fn test_save_load() {
+    let index = index_of(&[b"one\ntwo\nthree\nfour"], 3);
+    let mut saved = Vec::new();
+    index.save(&mut saved).unwrap();
+    assert_eq!(40 + 2 * 8, saved.len());
+    assert_eq!(index, LineIndex::load(&saved[..]).unwrap());
+
+    let empty = index_of(&[], 1000);
+    let mut saved_empty = Vec::new();
+    empty.save(&mut saved_empty).unwrap();
+    assert_eq!(empty, LineIndex::load(&saved_empty[..]).unwrap());
+
+    let mut bad_magic = saved.clone();
+    bad_magic[0] = b'X';
+    let mut bad_count = saved.clone();
+    bad_count[16] = 7;
+    let mut decreasing = saved.clone();
+    decreasing[48] = 0;
+    let mut past_end = saved.clone();
+    past_end[48] = 200;
+    for bad in [
+      bad_magic,
+      bad_count,
+      decreasing,
+      past_end,
+      saved[..50].to_vec(),
+    ] {
+      assert_eq!(
+        io::ErrorKind::InvalidData,
+        LineIndex::load(&bad[..]).unwrap_err().kind()
+      );
+    }
+    assert_eq!(
+      io::ErrorKind::UnexpectedEof,
+      LineIndex::load(&saved[..20]).unwrap_err().kind()
+    );
+  }
+
+  #[test]
+  fn test_seek_line() {
+    let content = lines_content();
+    //the lines as the in-memory split gives them, with their offsets
+    let lines = content.split(|&byte| byte == b'\n').collect::<Vec<_>>();
+    let mut offsets = vec![0u64];
+    for line in &lines[..lines.len() - 1] {
+      offsets.push(offsets.last().unwrap() + line.len() as u64 + 1);
+    }
+    let bgzf = make_bgzf(&content, 10_000);
+    let readers = vec![
+      BgzfReader::from_bytes(bgzf.clone()).unwrap(),
+      BgzfReader::builder()
+        .lazy(true)
+        .cache_bytes(1 << 20)
+        .open_bytes(bgzf.clone())
+        .unwrap(),
+    ];
+    for reader in readers {
+      for spacing in [1, 7, DEFAULT_LINE_SPACING] {
+        let index = reader.build_line_index_with_spacing(spacing).unwrap();
+        assert_eq!(100_000, index.line_count());
+        assert_eq!(content.len() as u64, index.total_uncompressed_length());
+        let mut state = 1u32;
+        for _ in 0..1000 {
+          state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+          let n = u64::from(state >> 8) % 100_000;
+          reader.seek_line(n, &index).unwrap();
+          assert_eq!(offsets[n as usize], reader.pos.get(), "line {}", n);
+          let line = io::BufReader::new(&reader).lines().next().unwrap().unwrap();
+          let expected = lines[n as usize]
+            .strip_suffix(b"\r")
+            .unwrap_or(lines[n as usize]);
+          assert_eq!(expected, line.as_bytes(), "line {}", n);
+        }
+        reader.seek_line(99_999, &index).unwrap();
+        assert_eq!(offsets[99_999], reader.pos.get());
+        assert!(matches!(
+          reader.seek_line(100_000, &index),
+          Err(BgzfError::LineIndex(LineIndexError::LineOutOfRange {
+            line: 100_000,
+            line_count: 100_000
+          }))
+        ));
+      }
+    }
+  }
+
+  #[test]
+  fn test_seek_line_with_another_index() {
+    let reader = BgzfReader::from_bytes(make_bgzf(b"ab\ncd\nef\n", 4)).unwrap();
+    let other = BgzfReader::from_bytes(make_bgzf(b"a\nbcde\nf\n", 4)).unwrap();
+    let index = other.build_line_index_with_spacing(1).unwrap();
+    reader.seek(5);
+    assert!(matches!(
+      reader.seek_line(1, &index),
+      Err(BgzfError::LineIndex(LineIndexError::NotALineStart {
+        offset: 2
+      }))
+    ));
+    assert_eq!(5, reader.pos.get());
+
+    let shorter = BgzfReader::from_bytes(make_bgzf(b"ab\ncd\n", 4)).unwrap();
+    assert!(matches!(
+      shorter.seek_line(0, &index),
+      Err(BgzfError::LineIndex(LineIndexError::LengthMismatch {
+        index_length: 9,
+        data_length: 6
+      }))
+    ));
+
+    let empty = BgzfReader::from_bytes(make_bgzf(b"", 4)).unwrap();
+    let index = empty.build_line_index().unwrap();
+    assert_eq!(0, index.line_count());
+    assert!(empty.seek_line(0, &index).is_err());
+  }
+}
@@ -0,0 +1,107 @@
+use memmap2::Mmap;
+use positioned_io::ReadAt;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+/// Memory mapping of a bgzf file the blocks are read from, shared with the
+/// prefetching thread. Reads past the end of the mapping read nothing, so a
+/// block beyond it is an `UnexpectedEof` error like with the file.
+#[derive(Clone)]
+pub(crate) struct MappedFile(Arc<Mmap>);
+
+impl MappedFile {
+  pub(crate) fn new(file: &File) -> io::Result<MappedFile> {
+    //Safety: the mapping is only read through slices checked against its
+    //length. The file being truncated or changed by another process while
+    //mapped is not something Rust can guard against, which open_mmap
+    //documents.
+    let mapping = unsafe { Mmap::map(file)? };
+    Ok(MappedFile(Arc::new(mapping)))
+  }
+}
+
+impl ReadAt for MappedFile {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    (&self.0[..]).read_at(pos, buf)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::{BgzfBlock, BgzfError, BgzfReader};
+  use std::fs;
+
+  #[test]
+  fn test_reads_within_the_mapping() {
+    let bgzf = make_bgzf(b"mapped bgzf data", 4);
+    let path = write_temp("mmap_reads", &bgzf).unwrap();
+    let mapping = MappedFile::new(&File::open(&path).unwrap()).unwrap();
+    let mut start = [0u8; 4];
+    mapping.read_exact_at(0, &mut start).unwrap();
+    assert_eq!(&bgzf[..4], &start[..]);
+    let mut past_end = [0u8; 4];
+    let error = mapping
+      .read_exact_at(bgzf.len() as u64 - 2, &mut past_end)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    assert_eq!(0, mapping.read_at(u64::MAX, &mut past_end).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_mapped_reader() {
+    let data = b"This is just a bgzf test,lets see how it reacts. :).".repeat(10);
+    let path = write_temp("mmap_reader", &make_bgzf(&data, 100)).unwrap();
+    for builder in [
+      BgzfReader::builder().mmap(true),
+      BgzfReader::builder().mmap(true).prefetch(2),
+    ] {
+      let reader = builder.open(path.clone()).unwrap();
+      assert!(reader.mapping.is_some());
+      let mut all = vec![0; data.len()];
+      reader.read_exact(&mut all).unwrap();
+      assert_eq!(data, all);
+
+      //a clone reads through the same mapping
+      let clone = reader.try_clone().unwrap();
+      assert!(clone.mapping.is_some());
+      let mut middle = vec![0; 150];
+      clone.read_at(120, &mut middle).unwrap();
+      assert_eq!(&data[120..270], &middle[..]);
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_block_past_the_mapping() {
+    let data = b"This is just a bgzf test,lets see how it reacts. :).".repeat(10);
+    let path = write_temp("mmap_stale", &make_bgzf(&data, 100)).unwrap();
+    let reader = BgzfReader::open_mmap(path.clone()).unwrap();
+    let mut all = vec![0; 100];
+    for expected in data.chunks(100) {
+      reader.read_to(&mut all[..expected.len()]).unwrap();
+      assert_eq!(expected, &all[..expected.len()]);
+    }
+
+    //like a block of a stale index describing a longer file
+    let end = fs::metadata(&path).unwrap().len();
+    crate::shared_state::write(&reader.blocks).push(BgzfBlock {
+      input_offset: data.len() as u64,
+      block_offset: end,
+      input_length: 100,
+      block_size: 200,
+    });
+    reader.indexed_length.set(data.len() as u64 + 100);
+    let error = reader.read_to(&mut all).err().unwrap();
+    assert!(matches!(
+      error,
+      BgzfError::ReadFailed { block, source }
+        if block.compressed_offset == end && source.kind() == io::ErrorKind::UnexpectedEof
+    ));
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,296 @@
+//! Lines of the data iterated in parallel with rayon, see
+//! `BgzfReader::par_lines`.
+//!
+//! The blocks are split into partitions of a few MiB, each one read by a
+//! rayon worker. A line belongs to the partition holding its first byte, but
+//! for a line starting right at a partition's start, which belongs to the
+//! partition before: a worker skips through the first newline from its start,
+//! then yields the lines starting up to its end included, reading on into the
+//! blocks after it for the last one. So every line is read once, whichever
+//! blocks it spans.
+
+use crate::{decompress_block_at, locate_block_in, BgzfBlock, BgzfError, BgzfReader};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Number of blocks in a partition, about 4 MiB of data
+pub(crate) const PARTITION_BLOCKS: usize = 64;
+
+/// Splits `blocks` into runs of `partition_blocks` blocks
+pub(crate) fn partitions(blocks: &[BgzfBlock], partition_blocks: usize) -> Vec<Range<usize>> {
+  (0..blocks.len())
+    .step_by(partition_blocks.max(1))
+    .map(|first| first..(first + partition_blocks.max(1)).min(blocks.len()))
+    .collect()
+}
+
+/// Iterator over the lines of a partition, without their line ending
+///
+/// Fields description:
+///
+/// reader: the reader whose blocks are decompressed, aside from its caches,
+/// blocks: every data block of the file,
+/// next_block: the block to decompress once `data` is used up,
+/// block_data: buffer for the compressed bytes of a block,
+/// data: the data of the block before `next_block`, or nothing,
+/// data_offset: uncompressed offset of `data`,
+/// at: offset in `data` of the next byte to read,
+/// end: uncompressed offset where the partition ends, the lines starting up
+/// to it included are its own,
+/// skip: true until the partial line the partition starts with is skipped,
+/// done: true after the last line or an error,
+pub(crate) struct PartitionLines<'a> {
+  reader: &'a BgzfReader,
+  blocks: Arc<Vec<BgzfBlock>>,
+  next_block: usize,
+  block_data: Vec<u8>,
+  data: Vec<u8>,
+  data_offset: u64,
+  at: usize,
+  end: u64,
+  skip: bool,
+  done: bool,
+}
+
+impl<'a> PartitionLines<'a> {
+  /// Returns the lines of the partition of the blocks numbered `partition`
+  pub(crate) fn new(
+    reader: &'a BgzfReader,
+    blocks: Arc<Vec<BgzfBlock>>,
+    partition: Range<usize>,
+  ) -> PartitionLines<'a> {
+    let last = &blocks[partition.end - 1];
+    let end = last.input_offset + u64::from(last.input_length);
+    PartitionLines {
+      reader,
+      next_block: partition.start,
+      block_data: Vec::new(),
+      data: Vec::new(),
+      data_offset: 0,
+      at: 0,
+      end,
+      skip: partition.start > 0,
+      done: false,
+      blocks,
+    }
+  }
+
+  /// Decompresses the next block into `data`, returns false after the last
+  /// block
+  fn next_data(&mut self) -> Result<bool, BgzfError> {
+    let block = match self.blocks.get(self.next_block) {
+      Some(block) => block,
+      None => return Ok(false),
+    };
+    self.data.resize(block.input_length as usize, 0);
+    decompress_block_at(
+      self.reader.block_source(),
+      &mut self.reader.decompressors.acquire(),
+      block,
+      self.reader.verify_crc,
+      &mut self.block_data,
+      &mut self.data,
+    )
+    .map_err(|error| locate_block_in(&self.blocks, error))?;
+    self.data_offset = block.input_offset;
+    self.at = 0;
+    self.next_block += 1;
+    Ok(true)
+  }
+
+  /// Uncompressed offset of the next byte to read, None at the end of the
+  /// data
+  fn next_offset(&self) -> Option<u64> {
+    if self.at < self.data.len() {
+      return Some(self.data_offset + self.at as u64);
+    }
+    self
+      .blocks
+      .get(self.next_block)
+      .map(|block| block.input_offset)
+  }
+
+  /// Appends the bytes up to the next newline to `line`, and returns whether
+  /// there was one, the newline being read but not appended
+  fn read_line(&mut self, line: &mut Vec<u8>) -> Result<bool, BgzfError> {
+    loop {
+      if self.at == self.data.len() && !self.next_data()? {
+        return Ok(false);
+      }
+      let rest = &self.data[self.at..];
+      match rest.iter().position(|&byte| byte == b'\n') {
+        Some(newline) => {
+          line.extend_from_slice(&rest[..newline]);
+          self.at += newline + 1;
+          return Ok(true);
+        }
+        None => {
+          line.extend_from_slice(rest);
+          self.at = self.data.len();
+        }
+      }
+    }
+  }
+
+  fn next_line(&mut self) -> Result<Option<Vec<u8>>, BgzfError> {
+    if self.skip {
+      self.skip = false;
+      self.read_line(&mut Vec::new())?;
+    }
+    match self.next_offset() {
+      Some(position) if position <= self.end => {}
+      _ => return Ok(None),
+    }
+    let mut line = Vec::new();
+    //a '\r' ends the line only before a newline, as with BufRead::lines
+    if self.read_line(&mut line)? && line.last() == Some(&b'\r') {
+      line.pop();
+    }
+    Ok(Some(line))
+  }
+}
+
+impl Iterator for PartitionLines<'_> {
+  type Item = Result<Vec<u8>, BgzfError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    let line = self.next_line().transpose();
+    self.done = !matches!(line, Some(Ok(_)));
+    line
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_truncated};
+  use rayon::prelude::*;
+
+  /// The lines of `content` as `BufRead::lines` gives them
+  fn expected_lines(content: &[u8]) -> Vec<Vec<u8>> {
+    std::io::BufRead::split(content, b'\n')
+      .map(|line| {
+        let mut line = line.unwrap();
+        if line.last() == Some(&b'\r') {
+          line.pop();
+        }
+        line
+      })
+      .collect()
+  }
+
+  /// The lines of every partition of `partition_blocks` blocks, in order
+  fn partition_lines(reader: &BgzfReader, partition_blocks: usize) -> Vec<Vec<Vec<u8>>> {
+    let blocks = Arc::new(reader.blocks().clone());
+    partitions(&blocks, partition_blocks)
+      .into_iter()
+      .map(|partition| {
+        PartitionLines::new(reader, blocks.clone(), partition)
+          .collect::<Result<Vec<_>, _>>()
+          .unwrap()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_lines_across_blocks() {
+    //blocks of 10 bytes: the line of a's starts right at the start of block
+    //1 and spans two blocks, the line of b's three, and the last block only
+    //holds the end of the last line, no line of its own
+    let mut content = Vec::new();
+    content.extend_from_slice(b"012345678\n");
+    content.extend_from_slice(b"aaaaaaaaaaaaaaa\n");
+    content.extend_from_slice(b"bbbbbbbbbbbbbbb\r\n");
+    content.extend_from_slice(b"ccc\n\n\nlast");
+    let reader = BgzfReader::from_bytes(make_bgzf(&content, 10)).unwrap();
+    let expected = expected_lines(&content);
+    for partition_blocks in 1..8 {
+      let lines = partition_lines(&reader, partition_blocks).concat();
+      assert_eq!(expected, lines, "{} blocks", partition_blocks);
+    }
+    let by_block = partition_lines(&reader, 1)
+      .into_iter()
+      .map(|lines| {
+        lines
+          .into_iter()
+          .map(|line| String::from_utf8(line).unwrap())
+          .collect::<Vec<_>>()
+      })
+      .collect::<Vec<_>>();
+    let empty = Vec::<String>::new();
+    assert_eq!(6, by_block.len());
+    assert_eq!(vec!["012345678", "aaaaaaaaaaaaaaa"], by_block[0]);
+    assert_eq!(empty, by_block[1]);
+    assert_eq!(vec!["bbbbbbbbbbbbbbb"], by_block[2]);
+    assert_eq!(empty, by_block[3]);
+    assert_eq!(vec!["ccc", "", "", "last"], by_block[4]);
+    assert_eq!(empty, by_block[5]);
+
+    let lines = reader.par_lines().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(expected, lines);
+  }
+
+  #[test]
+  fn test_empty_last_partition() {
+    //the second line starts right at the end of the first block, so it is
+    //the first block's, and the last block only holds the newline ending the
+    //data, which starts no line
+    let content = b"first line\nsecond line\n";
+    let reader = BgzfReader::from_bytes(make_bgzf(content, 11)).unwrap();
+    let by_block = partition_lines(&reader, 1);
+    assert_eq!(3, by_block.len());
+    assert_eq!(
+      vec![b"first line".to_vec(), b"second line".to_vec()],
+      by_block[0]
+    );
+    assert!(by_block[1].is_empty());
+    assert!(by_block[2].is_empty());
+
+    let reader = BgzfReader::from_bytes(make_bgzf(b"", 11)).unwrap();
+    assert_eq!(0, reader.par_lines().count());
+  }
+
+  #[test]
+  fn test_many_partitions() {
+    let mut content = Vec::new();
+    for i in 0..200_000u32 {
+      content.extend_from_slice(format!("{}\t{}\n", i, "x".repeat((i % 97) as usize)).as_bytes());
+    }
+    let reader = BgzfReader::from_bytes(make_bgzf(&content, 1000)).unwrap();
+    assert!(reader.blocks().len() > 4 * PARTITION_BLOCKS);
+    let lines = reader.par_lines().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(expected_lines(&content), lines);
+    let long_lines = reader
+      .par_lines()
+      .map(|line| line.map(|line| u64::from(line.len() > 60)))
+      .sum::<Result<u64, _>>()
+      .unwrap();
+    assert_eq!(
+      lines.iter().filter(|line| line.len() > 60).count() as u64,
+      long_lines
+    );
+  }
+
+  #[test]
+  fn test_errors() {
+    let content = b"line\n".repeat(100);
+    let truncated = make_truncated(&content, 50, 30);
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open_bytes(truncated.clone())
+      .unwrap();
+    let lines = reader.par_lines().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(90, lines.len());
+
+    //opening lazily, the truncation is only found by par_lines
+    let reader = BgzfReader::builder()
+      .lazy(true)
+      .open_bytes(truncated)
+      .unwrap();
+    let lines = reader.par_lines().collect::<Vec<_>>();
+    assert!(matches!(lines[..], [Err(BgzfError::Truncated(_))]));
+  }
+}
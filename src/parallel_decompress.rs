@@ -0,0 +1,439 @@
+use crate::decompressor_pool::DecompressorPool;
+use crate::executor::Executor;
+use crate::{decompress_block_at, BgzfBlock, BgzfError};
+use positioned_io::ReadAt;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Decompresses `blocks`, which follow each other from the start of the data,
+/// into `out`, which holds exactly their uncompressed length. Every one of
+/// the `threads` tasks of `executor` decompresses a run of blocks of about
+/// the same length straight into its part of `out`. The error returned is
+/// the one of the first block in the file that could not be decompressed.
+pub(crate) fn decompress_all(
+  source: &(dyn ReadAt + Sync),
+  decompressors: &DecompressorPool,
+  verify_crc: bool,
+  blocks: &[BgzfBlock],
+  executor: &Executor,
+  threads: usize,
+  out: &mut [u8],
+) -> Result<(), BgzfError> {
+  let mut segments = Vec::with_capacity(blocks.len());
+  let mut rest = out;
+  for block in blocks {
+    let (segment, tail) = std::mem::take(&mut rest).split_at_mut(block.input_length as usize);
+    segments.push((block, segment));
+    rest = tail;
+  }
+  let per_thread = segments.len().div_ceil(threads.max(1)).max(1);
+  //every run is only taken by its own task
+  let runs = segments
+    .chunks_mut(per_thread)
+    .map(Mutex::new)
+    .collect::<Vec<_>>();
+  let results = executor.run(runs.len(), |run| {
+    let mut segments = runs[run].lock().unwrap_or_else(PoisonError::into_inner);
+    let mut block_data = Vec::new();
+    for (block, segment) in segments.iter_mut() {
+      let mut un_compressor = decompressors.acquire();
+      decompress_block_at(
+        source,
+        &mut un_compressor,
+        block,
+        verify_crc,
+        &mut block_data,
+        segment,
+      )?;
+    }
+    Ok(())
+  });
+  results
+    .into_iter()
+    .try_for_each(|result| result.expect("Decompressing thread panicked"))
+}
+
+/// Sets `stop` when the thread unwinds, so that a panic of the closure of
+/// `for_each_block` stops the other threads too
+struct StopOnPanic<'a>(&'a AtomicBool);
+
+impl Drop for StopOnPanic<'_> {
+  fn drop(&mut self) {
+    if thread::panicking() {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+}
+
+/// Decompresses `blocks` with `threads` tasks of `executor`, each taking the
+/// next block not taken yet, and calls `f` with the uncompressed offset and
+/// the data of every block, once its data is whole, on the thread that
+/// decompressed it. After an error or a panic of `f` the tasks take no more
+/// blocks. The error returned is the one of the first block in the file that
+/// could not be decompressed, a panic of `f` is resumed on the calling
+/// thread.
+pub(crate) fn for_each_block(
+  source: &(dyn ReadAt + Sync),
+  decompressors: &DecompressorPool,
+  verify_crc: bool,
+  blocks: &[BgzfBlock],
+  executor: &Executor,
+  threads: usize,
+  f: &(dyn Fn(u64, &[u8]) + Sync),
+) -> Result<(), BgzfError> {
+  let next_block = AtomicUsize::new(0);
+  let stop = AtomicBool::new(false);
+  //the failing block of the lowest number, with its error
+  let failed = Mutex::new(None::<(usize, BgzfError)>);
+  let results = executor.run(threads.max(1).min(blocks.len().max(1)), |_task| {
+    let _stop_on_panic = StopOnPanic(&stop);
+    let mut block_data = Vec::new();
+    let mut uncompressed = Vec::new();
+    while !stop.load(Ordering::SeqCst) {
+      let number = next_block.fetch_add(1, Ordering::SeqCst);
+      let block = match blocks.get(number) {
+        Some(block) => block,
+        None => return,
+      };
+      uncompressed.resize(block.input_length as usize, 0);
+      let result = decompress_block_at(
+        source,
+        &mut decompressors.acquire(),
+        block,
+        verify_crc,
+        &mut block_data,
+        &mut uncompressed,
+      );
+      match result {
+        Ok(()) => f(block.input_offset, &uncompressed),
+        Err(error) => {
+          stop.store(true, Ordering::SeqCst);
+          let mut failed = failed.lock().unwrap_or_else(PoisonError::into_inner);
+          if failed.as_ref().is_none_or(|&(first, _)| number < first) {
+            *failed = Some((number, error));
+          }
+        }
+      }
+    }
+  });
+  //every task is done, the panic of the first one is resumed
+  if let Some(Err(payload)) = results.into_iter().find(Result::is_err) {
+    panic::resume_unwind(payload);
+  }
+  match failed.into_inner().unwrap_or_else(PoisonError::into_inner) {
+    Some((_number, error)) => Err(error),
+    None => Ok(()),
+  }
+}
+
+/// Blocks handed out to the threads of `decompress_to_writer`
+///
+/// Fields description:
+///
+/// next_block: number of the next block to decompress,
+/// written: number of blocks written so far,
+/// stop: set when writing stopped before the last block,
+struct Window {
+  next_block: usize,
+  written: usize,
+  stop: bool,
+}
+
+fn lock_window(lock: &Mutex<Window>) -> MutexGuard<'_, Window> {
+  lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Decompresses `blocks` with `threads` tasks of `executor` and writes them
+/// to `out` in file order, on the calling thread. A task only starts a block
+/// when fewer than `in_flight` blocks are decompressed or being decompressed
+/// and not yet written, which bounds the memory held to `in_flight` blocks.
+/// Returns the number of bytes written.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decompress_to_writer<W: Write>(
+  source: &(dyn ReadAt + Sync),
+  decompressors: &DecompressorPool,
+  verify_crc: bool,
+  blocks: &[BgzfBlock],
+  executor: &Executor,
+  threads: usize,
+  in_flight: usize,
+  mut out: W,
+) -> Result<u64, BgzfError> {
+  let in_flight = in_flight.max(1);
+  let window = Mutex::new(Window {
+    next_block: 0,
+    written: 0,
+    stop: false,
+  });
+  let moved = Condvar::new();
+  let (sender, receiver) = mpsc::channel();
+  //every task sends with its own sender, the blocks have all come once the
+  //last one is dropped
+  let senders = (0..threads.max(1))
+    .map(|_task| Mutex::new(Some(sender.clone())))
+    .collect::<Vec<_>>();
+  drop(sender);
+  let (result, _tasks) = executor.run_beside(
+    senders.len(),
+    |task| {
+      let sender = match senders[task]
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+      {
+        Some(sender) => sender,
+        None => return,
+      };
+      let mut block_data = Vec::new();
+      loop {
+        let mut state = moved
+          .wait_while(lock_window(&window), |state| {
+            !state.stop
+              && state.next_block < blocks.len()
+              && state.next_block >= state.written + in_flight
+          })
+          .unwrap_or_else(PoisonError::into_inner);
+        if state.stop || state.next_block == blocks.len() {
+          return;
+        }
+        let number = state.next_block;
+        state.next_block += 1;
+        drop(state);
+
+        let block = &blocks[number];
+        let mut uncompressed = vec![0u8; block.input_length as usize];
+        let result = decompress_block_at(
+          source,
+          &mut decompressors.acquire(),
+          block,
+          verify_crc,
+          &mut block_data,
+          &mut uncompressed,
+        )
+        .map(|()| uncompressed);
+        if sender.send((number, result)).is_err() {
+          return;
+        }
+      }
+    },
+    || {
+      //blocks decompressed ahead of the next one to write
+      let mut pending = BTreeMap::new();
+      let result = (|| {
+        let mut bytes_written = 0;
+        for (number, uncompressed) in receiver.iter() {
+          pending.insert(number, uncompressed);
+          let mut state = lock_window(&window);
+          while let Some(uncompressed) = pending.remove(&state.written) {
+            let uncompressed = uncompressed?;
+            out.write_all(&uncompressed)?;
+            bytes_written += uncompressed.len() as u64;
+            state.written += 1;
+          }
+          drop(state);
+          moved.notify_all();
+        }
+        Ok(bytes_written)
+      })();
+      //the tasks stop after their current block when writing failed
+      lock_window(&window).stop = true;
+      moved.notify_all();
+      result
+    },
+  );
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::BgzfReader;
+  use std::fs;
+  use std::io::{self, Write};
+  use std::panic::{self, AssertUnwindSafe};
+  use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+  use std::sync::Mutex;
+  use std::thread;
+  use std::time::Duration;
+
+  fn content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..20_000 {
+      content.extend_from_slice(format!("{}\tchr{}\tparallel\n", i, i % 7).as_bytes());
+    }
+    content
+  }
+
+  /// The data read block by block with `read_to`
+  fn read_serially(reader: &BgzfReader) -> Vec<u8> {
+    let mut data = Vec::new();
+    for block in reader.block_offsets().collect::<Vec<_>>() {
+      let mut uncompressed = vec![0; block.uncompressed_length as usize];
+      reader.seek(block.uncompressed_offset);
+      reader.read_to(&mut uncompressed).unwrap();
+      data.extend(uncompressed);
+    }
+    data
+  }
+
+  #[test]
+  fn test_decompress_all_parallel() {
+    let content = content();
+    let path = write_temp("decompress_all_parallel", &make_bgzf(&content, 3000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let serial = read_serially(&reader);
+    assert_eq!(content, serial);
+    for &threads in &[0, 1, 3, 8, 1000] {
+      assert_eq!(serial, reader.decompress_all_parallel(threads).unwrap());
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_decompress_to_writer_parallel() {
+    let content = content();
+    let path = write_temp("decompress_to_writer_parallel", &make_bgzf(&content, 3000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    for &(threads, in_flight) in &[(1, 1), (4, 1), (4, 4), (8, 64), (3, 1000)] {
+      let mut out = Vec::new();
+      assert_eq!(
+        content.len() as u64,
+        reader
+          .decompress_to_writer_parallel(threads, in_flight, &mut out)
+          .unwrap()
+      );
+      assert_eq!(content, out);
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_for_each_block_parallel() {
+    let content = content();
+    let path = write_temp("for_each_block_parallel", &make_bgzf(&content, 3000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let serial_sum = read_serially(&reader)
+      .iter()
+      .map(|&byte| u64::from(byte))
+      .sum::<u64>();
+    for &threads in &[0, 1, 3, 8, 1000] {
+      let sum = AtomicU64::new(0);
+      let offsets = Mutex::new(Vec::new());
+      reader
+        .for_each_block_parallel(threads, |offset, data| {
+          assert_eq!(&content[offset as usize..][..data.len()], data);
+          let block_sum = data.iter().map(|&byte| u64::from(byte)).sum::<u64>();
+          sum.fetch_add(block_sum, Ordering::Relaxed);
+          offsets.lock().unwrap().push(offset);
+        })
+        .unwrap();
+      assert_eq!(serial_sum, sum.into_inner());
+      //every block once
+      let mut offsets = offsets.into_inner().unwrap();
+      offsets.sort_unstable();
+      let expected = reader
+        .block_offsets()
+        .map(|block| block.uncompressed_offset)
+        .collect::<Vec<_>>();
+      assert_eq!(expected, offsets);
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_for_each_block_parallel_panic() {
+    let content = content();
+    let path = write_temp("for_each_block_parallel_panic", &make_bgzf(&content, 3000)).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let blocks = reader.block_offsets().count();
+    let calls = AtomicUsize::new(0);
+    let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+      reader.for_each_block_parallel(4, |_offset, _data| {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+          panic!("closure panicked at the first block");
+        }
+        thread::sleep(Duration::from_millis(5));
+      })
+    }))
+    .err()
+    .unwrap();
+    assert_eq!(
+      Some(&"closure panicked at the first block"),
+      panic.downcast_ref::<&str>()
+    );
+    //the other threads stopped long before the last block
+    assert!(calls.into_inner() < blocks / 2);
+
+    //the reader is still usable
+    let sum = AtomicU64::new(0);
+    reader
+      .for_each_block_parallel(4, |_offset, data| {
+        sum.fetch_add(data.len() as u64, Ordering::Relaxed);
+      })
+      .unwrap();
+    assert_eq!(content.len() as u64, sum.into_inner());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_errors() {
+    let content = content();
+    let mut bgzf = make_bgzf(&content, 3000);
+    let path = write_temp("decompress_parallel_errors", &bgzf).unwrap();
+    let damaged = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(50)
+      .unwrap();
+    bgzf[damaged.compressed_offset as usize + 18] = 0xff;
+    fs::write(&path, &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(reader.decompress_all_parallel(4).is_err());
+    let error = reader
+      .for_each_block_parallel(4, |offset, _data| {
+        assert_ne!(
+          damaged.uncompressed_offset, offset,
+          "damaged block passed to f"
+        )
+      })
+      .err()
+      .unwrap();
+    assert_eq!(
+      reader.decompress_all_parallel(1).err().unwrap().to_string(),
+      error.to_string()
+    );
+
+    //the blocks before the damaged one are written
+    let mut out = Vec::new();
+    assert!(reader
+      .decompress_to_writer_parallel(4, 8, &mut out)
+      .is_err());
+    assert_eq!(&content[..damaged.uncompressed_offset as usize], &out[..]);
+
+    //as is the error of the writer
+    struct FailingWriter(usize);
+    impl Write for FailingWriter {
+      fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.0 == 0 {
+          return Err(io::Error::other("disk full"));
+        }
+        self.0 -= 1;
+        Ok(buf.len())
+      }
+      fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+      }
+    }
+    let error = reader
+      .decompress_to_writer_parallel(4, 8, FailingWriter(10))
+      .err()
+      .unwrap();
+    assert_eq!("disk full", error.to_string());
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,147 @@
+//! What the data of a file is, see `BgzfReader::payload_kind`.
+
+/// Most uncompressed bytes `BgzfReader::payload_kind` looks at, and gives
+/// back with `PayloadKind::Unknown`
+pub(crate) const HEAD_LENGTH: usize = 512;
+
+/// What the uncompressed data of a bgzf file is, told by the signature it
+/// starts with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadKind {
+  /// A BAM file, starting with the magic "BAM\1"
+  Bam,
+  /// A VCF file, starting with its "##fileformat=VCF" line
+  Vcf,
+  /// A FASTA file, starting with the '>' of its first sequence name
+  Fasta,
+  /// Anything else, with the first bytes of the data, up to 512 of them,
+  /// for the caller to look at
+  Unknown(Vec<u8>),
+}
+
+impl PayloadKind {
+  /// Returns the kind of the data starting with `head`
+  pub(crate) fn of(head: &[u8]) -> PayloadKind {
+    if head.starts_with(b"BAM\x01") {
+      PayloadKind::Bam
+    } else if head.starts_with(b"##fileformat=VCF") {
+      PayloadKind::Vcf
+    } else if head.starts_with(b">") {
+      PayloadKind::Fasta
+    } else {
+      PayloadKind::Unknown(head[..head.len().min(HEAD_LENGTH)].to_vec())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bam_header, make_bgzf, make_truncated, make_vcf};
+  use crate::{BgzfError, BgzfReader};
+
+  #[test]
+  fn test_signatures() {
+    assert_eq!(PayloadKind::Bam, PayloadKind::of(b"BAM\x01\x00\x00"));
+    assert_eq!(PayloadKind::Vcf, PayloadKind::of(b"##fileformat=VCFv4.2\n"));
+    assert_eq!(PayloadKind::Fasta, PayloadKind::of(b">chr1\nACGT\n"));
+    //close but not quite
+    assert_eq!(
+      PayloadKind::Unknown(b"BAM\x02".to_vec()),
+      PayloadKind::of(b"BAM\x02")
+    );
+    assert_eq!(
+      PayloadKind::Unknown(b"##fileformat=VC".to_vec()),
+      PayloadKind::of(b"##fileformat=VC")
+    );
+    assert_eq!(PayloadKind::Unknown(Vec::new()), PayloadKind::of(b""));
+    assert_eq!(
+      PayloadKind::Unknown(vec![b'@'; HEAD_LENGTH]),
+      PayloadKind::of(&[b'@'; 2 * HEAD_LENGTH])
+    );
+  }
+
+  #[test]
+  fn test_payload_kinds_of_files() {
+    let fasta = b">chr1 first\nACGTACGTAC\n>chr2\nGGCC\n";
+    let text = b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:100\n";
+    let cases = [
+      (
+        make_bam_header(&[("chr1", 248_956_422), ("chr2", 242_193_529)], 65280),
+        PayloadKind::Bam,
+      ),
+      (make_vcf(10, 65280), PayloadKind::Vcf),
+      (make_bgzf(fasta, 100), PayloadKind::Fasta),
+      (make_bgzf(text, 100), PayloadKind::Unknown(text.to_vec())),
+      (make_bgzf(b"", 100), PayloadKind::Unknown(Vec::new())),
+      //signatures across blocks
+      (make_bam_header(&[("chr1", 1000)], 3), PayloadKind::Bam),
+      (make_vcf(1, 5), PayloadKind::Vcf),
+    ];
+    for (bgzf, kind) in cases.iter() {
+      let reader = BgzfReader::from_bytes(bgzf.clone()).unwrap();
+      assert_eq!(*kind, reader.payload_kind().unwrap());
+      let lazy = BgzfReader::builder()
+        .lazy(true)
+        .open_bytes(bgzf.clone())
+        .unwrap();
+      assert_eq!(*kind, lazy.payload_kind().unwrap());
+    }
+
+    let unknown = (0..2000u32)
+      .map(|i| (i % 200) as u8 + 1)
+      .collect::<Vec<_>>();
+    let reader = BgzfReader::from_bytes(make_bgzf(&unknown, 100)).unwrap();
+    assert_eq!(
+      PayloadKind::Unknown(unknown[..HEAD_LENGTH].to_vec()),
+      reader.payload_kind().unwrap()
+    );
+  }
+
+  #[test]
+  fn test_leaves_the_reader_as_it_was() {
+    let bgzf = make_vcf(2000, 1000);
+    let reader = BgzfReader::builder()
+      .cache_bytes(1 << 20)
+      .compressed_cache_bytes(1 << 20)
+      .open_bytes(bgzf.clone())
+      .unwrap();
+    let data = BgzfReader::from_bytes(bgzf)
+      .unwrap()
+      .decompress_all_parallel(1)
+      .unwrap();
+    reader.seek(5000);
+    let mut buf = vec![0; 10];
+    reader.read_to(&mut buf).unwrap();
+    let stats = reader.cache_stats();
+    let compressed_stats = reader.compressed_cache_stats();
+    assert_eq!(PayloadKind::Vcf, reader.payload_kind().unwrap());
+    assert_eq!(5010, reader.pos.get());
+    assert_eq!(stats, reader.cache_stats());
+    assert_eq!(compressed_stats, reader.compressed_cache_stats());
+    reader.read_to(&mut buf).unwrap();
+    assert_eq!(&data[5010..5020], &buf[..]);
+  }
+
+  #[test]
+  fn test_truncated_file() {
+    let fasta = b">chr1\n"
+      .iter()
+      .chain([b'A'; 294].iter())
+      .copied()
+      .collect::<Vec<_>>();
+    let bgzf = make_truncated(&fasta, 100, 10);
+    //opened lazily, the truncation is only found looking past the first block
+    let reader = BgzfReader::builder()
+      .lazy(true)
+      .open_bytes(bgzf.clone())
+      .unwrap();
+    let error = reader.payload_kind().unwrap_err();
+    assert!(matches!(error, BgzfError::Truncated { .. }), "{:?}", error);
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open_bytes(bgzf)
+      .unwrap();
+    assert_eq!(PayloadKind::Fasta, reader.payload_kind().unwrap());
+  }
+}
@@ -0,0 +1,234 @@
+use crate::BgzfReader;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Uncompressed bytes the thread reads at once, the most a block holds
+const CHUNK_LENGTH: usize = 64 << 10;
+
+/// Reader of the uncompressed data of a `BgzfReader` from its position on,
+/// whose next chunks are decompressed by a thread while the caller reads the
+/// current one, see `BgzfReader::into_pipelined`. Dropping it stops the
+/// thread after the chunk it is decompressing.
+///
+/// Fields description:
+///
+/// chunks: chunks decompressed by the thread and not read yet, or the error
+/// that stopped it, dropped before joining the thread so that it stops
+/// waiting for room,
+/// front: the chunk being read,
+/// front_pos: how much of front was read,
+/// failed: kind of the error that stopped the thread, once returned, the
+/// reads after it fail too,
+/// thread: handle of the thread, joined on drop,
+pub struct PipelinedReader {
+  chunks: Option<Receiver<io::Result<Vec<u8>>>>,
+  front: Vec<u8>,
+  front_pos: usize,
+  failed: Option<io::ErrorKind>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl PipelinedReader {
+  /// Starts a thread reading `reader` from its position, keeping at most
+  /// `depth` chunks ahead of the caller, at least one
+  pub(crate) fn spawn(reader: BgzfReader, depth: usize) -> io::Result<PipelinedReader> {
+    let (sender, chunks) = mpsc::sync_channel(depth.max(1));
+    let thread = thread::Builder::new()
+      .name(String::from("bgzf-pipeline"))
+      .spawn(move || decompress_chunks(&reader, &sender))?;
+    Ok(PipelinedReader {
+      chunks: Some(chunks),
+      front: Vec::new(),
+      front_pos: 0,
+      failed: None,
+      thread: Some(thread),
+    })
+  }
+
+  /// Makes the next chunk the front one, returns false at the end of the
+  /// data
+  fn next_chunk(&mut self) -> io::Result<bool> {
+    if let Some(kind) = self.failed {
+      return Err(io::Error::new(
+        kind,
+        "An earlier read of the pipelined reader failed",
+      ));
+    }
+    let chunks = match &self.chunks {
+      Some(chunks) => chunks,
+      None => return Ok(false),
+    };
+    match chunks.recv() {
+      Ok(Ok(chunk)) => {
+        self.front = chunk;
+        self.front_pos = 0;
+        Ok(true)
+      }
+      Ok(Err(e)) => {
+        self.failed = Some(e.kind());
+        Err(e)
+      }
+      //the thread only stops without an error at the end of the data,
+      //unless it panicked
+      Err(_) => {
+        self.chunks = None;
+        match self.thread.take().map(JoinHandle::join) {
+          Some(Err(_)) => {
+            self.failed = Some(io::ErrorKind::Other);
+            Err(io::Error::other("Pipelined decompressing thread panicked"))
+          }
+          _ => Ok(false),
+        }
+      }
+    }
+  }
+}
+
+impl Read for PipelinedReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    while self.front_pos == self.front.len() {
+      if !self.next_chunk()? {
+        return Ok(0);
+      }
+    }
+    let length = buf.len().min(self.front.len() - self.front_pos);
+    buf[..length].copy_from_slice(&self.front[self.front_pos..self.front_pos + length]);
+    self.front_pos += length;
+    Ok(length)
+  }
+}
+
+impl Drop for PipelinedReader {
+  fn drop(&mut self) {
+    self.chunks = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Sends the chunks of `reader` one after the other until the end of the
+/// data, the first error or the receiver being dropped
+fn decompress_chunks(reader: &BgzfReader, sender: &SyncSender<io::Result<Vec<u8>>>) {
+  loop {
+    let mut chunk = vec![0; CHUNK_LENGTH];
+    let chunk = match reader.read(&mut chunk, 0, CHUNK_LENGTH) {
+      Ok(0) => return,
+      Ok(read) => {
+        chunk.truncate(read);
+        Ok(chunk)
+      }
+      Err(e) => Err(io::Error::from(e)),
+    };
+    let failed = chunk.is_err();
+    if sender.send(chunk).is_err() || failed {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::BgzfReader;
+  use std::fs;
+  use std::io::{self, Read};
+
+  fn content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..30_000 {
+      content.extend_from_slice(format!("{}\tchr{}\tpipelined\n", i, i % 5).as_bytes());
+    }
+    content
+  }
+
+  #[test]
+  fn test_reads_like_the_reader() {
+    let content = content();
+    let path = write_temp("pipelined_reads", &make_bgzf(&content, 5000)).unwrap();
+    for &depth in &[0, 1, 4, 1000] {
+      let mut out = Vec::new();
+      let mut pipelined = BgzfReader::new(path.clone())
+        .unwrap()
+        .into_pipelined(depth)
+        .unwrap();
+      assert_eq!(
+        content.len() as u64,
+        io::copy(&mut pipelined, &mut out).unwrap()
+      );
+      assert_eq!(content, out);
+      assert_eq!(0, pipelined.read(&mut [0; 10]).unwrap());
+    }
+
+    //from the position of the reader, in reads of any length
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(12_345);
+    let mut pipelined = reader.into_pipelined(2).unwrap();
+    let mut out = Vec::new();
+    let mut buffer = vec![0; 7777];
+    loop {
+      let read = pipelined.read(&mut buffer).unwrap();
+      if read == 0 {
+        break;
+      }
+      out.extend_from_slice(&buffer[..read]);
+    }
+    assert_eq!(&content[12_345..], &out[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_error_when_reached() {
+    let content = content();
+    let mut bgzf = make_bgzf(&content, 5000);
+    let path = write_temp("pipelined_error", &bgzf).unwrap();
+    let damaged = BgzfReader::new(path.clone())
+      .unwrap()
+      .block_offsets()
+      .nth(40)
+      .unwrap();
+    bgzf[damaged.compressed_offset as usize + 18] = 0xff;
+    fs::write(&path, &bgzf).unwrap();
+
+    let mut pipelined = BgzfReader::new(path.clone())
+      .unwrap()
+      .into_pipelined(4)
+      .unwrap();
+    let mut out = Vec::new();
+    let error = io::copy(&mut pipelined, &mut out).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    //the chunks before the damaged block are all read first
+    assert!(out.len() as u64 <= damaged.uncompressed_offset);
+    assert!(out.len() as u64 + 64 * 1024 > damaged.uncompressed_offset);
+    assert_eq!(&content[..out.len()], &out[..]);
+    let error = pipelined.read(&mut [0; 10]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_drop_stops_the_thread() {
+    let content = content();
+    let path = write_temp("pipelined_drop", &make_bgzf(&content, 5000)).unwrap();
+    //the thread waits for room when dropped, or has not started yet
+    let mut pipelined = BgzfReader::new(path.clone())
+      .unwrap()
+      .into_pipelined(1)
+      .unwrap();
+    let mut start = [0; 10];
+    pipelined.read_exact(&mut start).unwrap();
+    assert_eq!(&content[..10], &start);
+    drop(pipelined);
+    drop(
+      BgzfReader::new(path.clone())
+        .unwrap()
+        .into_pipelined(3)
+        .unwrap(),
+    );
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,381 @@
+use crate::decompressor_pool::DecompressorPool;
+use crate::{decompress_block_at, BgzfBlock, BgzfError};
+use positioned_io::ReadAt;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// Blocks asked for and decompressed
+///
+/// Fields description:
+///
+/// queue: blocks to decompress, in the order the reader will need them,
+/// working: uncompressed offset of the block the thread is decompressing,
+/// ready: decompressed blocks, or why they could not be, by uncompressed
+/// offset,
+/// stop: asks the thread to stop,
+/// done: whether the thread has stopped,
+#[derive(Default)]
+struct PrefetchState {
+  queue: VecDeque<BgzfBlock>,
+  working: Option<u64>,
+  ready: HashMap<u64, Result<Vec<u8>, BgzfError>>,
+  stop: bool,
+  done: bool,
+}
+
+impl PrefetchState {
+  /// Whether the block starting at `uoffset` is ready or will be
+  fn has(&self, uoffset: u64) -> bool {
+    self.working == Some(uoffset)
+      || self.ready.contains_key(&uoffset)
+      || self.queue.iter().any(|block| block.input_offset == uoffset)
+  }
+}
+
+/// State shared with the thread, and the condition variable signalled on
+/// every change to it
+type Shared = Arc<(Mutex<PrefetchState>, Condvar)>;
+
+/// Thread reading and decompressing the blocks a reader is about to need.
+/// Dropping it stops the thread after the block it is decompressing.
+///
+/// Fields description:
+///
+/// shared: blocks asked for and decompressed,
+/// thread: handle of the thread, joined on drop,
+pub(crate) struct Prefetcher {
+  shared: Shared,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Prefetcher {
+  /// Starts a thread decompressing the blocks of `source` asked for with
+  /// `request`, with decompressors taken from `decompressors`, checking
+  /// their CRC32 with `verify_crc`
+  pub(crate) fn spawn<R: ReadAt + Send + 'static>(
+    source: R,
+    decompressors: Arc<DecompressorPool>,
+    verify_crc: bool,
+  ) -> io::Result<Prefetcher> {
+    let shared: Shared = Arc::new((Mutex::new(PrefetchState::default()), Condvar::new()));
+    let mut work = PrefetchWork {
+      source,
+      decompressors,
+      verify_crc,
+      shared: shared.clone(),
+    };
+    let thread = thread::Builder::new()
+      .name(String::from("bgzf-prefetch"))
+      .spawn(move || work.run())?;
+    Ok(Prefetcher {
+      shared,
+      thread: Some(thread),
+    })
+  }
+
+  /// Makes `blocks` the blocks to decompress next, in this order. Blocks
+  /// ready or being decompressed are not decompressed again, and ready blocks
+  /// not in `blocks` are dropped, so an empty `blocks` stops prefetching.
+  pub(crate) fn request(&self, blocks: &[BgzfBlock]) {
+    let (lock, changed) = &*self.shared;
+    let mut state = lock_state(lock);
+    state
+      .ready
+      .retain(|uoffset, _block| blocks.iter().any(|block| block.input_offset == *uoffset));
+    state.queue.clear();
+    for &block in blocks {
+      if !state.has(block.input_offset) {
+        state.queue.push_back(block);
+      }
+    }
+    drop(state);
+    changed.notify_all();
+  }
+
+  /// Returns the block starting at `uoffset`, waiting for the thread when it
+  /// is decompressing or about to decompress it, or None when it was not
+  /// asked for. The error of a block that could not be decompressed is the
+  /// one a read decompressing it would get.
+  pub(crate) fn take(&self, uoffset: u64) -> Option<Result<Vec<u8>, BgzfError>> {
+    let (lock, changed) = &*self.shared;
+    let mut state = changed
+      .wait_while(lock_state(lock), |state| {
+        !state.done && !state.ready.contains_key(&uoffset) && state.has(uoffset)
+      })
+      .unwrap_or_else(PoisonError::into_inner);
+    state.ready.remove(&uoffset)
+  }
+}
+
+impl Drop for Prefetcher {
+  fn drop(&mut self) {
+    let (lock, changed) = &*self.shared;
+    lock_state(lock).stop = true;
+    changed.notify_all();
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// The state stays consistent even when the other side panicked, every
+/// change to it is made in one go
+fn lock_state(lock: &Mutex<PrefetchState>) -> MutexGuard<'_, PrefetchState> {
+  lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Work of the prefetching thread
+///
+/// Fields description:
+///
+/// source: where the blocks are read from,
+/// decompressors: where the decompressor of every block is taken from,
+/// verify_crc: whether the CRC32 of the blocks is checked,
+/// shared: blocks asked for and decompressed,
+struct PrefetchWork<R> {
+  source: R,
+  decompressors: Arc<DecompressorPool>,
+  verify_crc: bool,
+  shared: Shared,
+}
+
+impl<R: ReadAt> PrefetchWork<R> {
+  /// Decompresses the blocks of the queue one after the other until stopped
+  fn run(&mut self) {
+    let mut block_data = Vec::new();
+    let (lock, changed) = &*self.shared;
+    loop {
+      let mut state = changed
+        .wait_while(lock_state(lock), |state| {
+          !state.stop && state.queue.is_empty()
+        })
+        .unwrap_or_else(PoisonError::into_inner);
+      let block = match state.queue.pop_front() {
+        Some(block) if !state.stop => block,
+        _ => return,
+      };
+      state.working = Some(block.input_offset);
+      drop(state);
+
+      let mut uncompressed = vec![0u8; block.input_length as usize];
+      let result = decompress_block_at(
+        &self.source,
+        &mut self.decompressors.acquire(),
+        &block,
+        self.verify_crc,
+        &mut block_data,
+        &mut uncompressed,
+      );
+
+      let mut state = lock_state(lock);
+      state.working = None;
+      state
+        .ready
+        .insert(block.input_offset, result.map(|()| uncompressed));
+      drop(state);
+      changed.notify_all();
+    }
+  }
+}
+
+impl<R> Drop for PrefetchWork<R> {
+  //also reached when decompressing a block panics, so that a reader waiting
+  //for a block never waits forever
+  fn drop(&mut self) {
+    let (lock, changed) = &*self.shared;
+    let mut state = lock_state(lock);
+    state.working = None;
+    state.done = true;
+    drop(state);
+    changed.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, write_temp};
+  use crate::BgzfReader;
+  use std::fs;
+  use std::time::{Duration, Instant};
+
+  const BLOCK_LENGTH: usize = 1000;
+
+  fn numbers(count: u32) -> Vec<u8> {
+    (0..count).flat_map(|n| n.to_le_bytes()).collect()
+  }
+
+  fn pool() -> Arc<DecompressorPool> {
+    Arc::new(DecompressorPool::new(1))
+  }
+
+  /// In-memory file taking `delay` for every read
+  struct SlowSource {
+    data: Vec<u8>,
+    delay: Duration,
+  }
+
+  impl ReadAt for SlowSource {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+      thread::sleep(self.delay);
+      self.data.read_at(pos, buf)
+    }
+  }
+
+  fn blocks_of(bgzf: &[u8]) -> Vec<BgzfBlock> {
+    let path = write_temp("prefetch_blocks", bgzf).unwrap();
+    let blocks = BgzfReader::new(path.clone()).unwrap().blocks().clone();
+    fs::remove_file(path).unwrap();
+    blocks
+  }
+
+  #[test]
+  fn test_reading_overlaps_processing() {
+    let data = numbers(5000);
+    let bgzf = make_bgzf(&data, BLOCK_LENGTH);
+    let blocks = blocks_of(&bgzf);
+    let delay = Duration::from_millis(20);
+    let prefetcher = Prefetcher::spawn(SlowSource { data: bgzf, delay }, pool(), false).unwrap();
+
+    //every block takes `delay` to read then `delay` to process, one after the
+    //other that is twice the delay per block
+    let start = Instant::now();
+    for (number, block) in blocks.iter().enumerate() {
+      prefetcher.request(&blocks[number..blocks.len().min(number + 2)]);
+      let uncompressed = prefetcher.take(block.input_offset).unwrap().unwrap();
+      assert_eq!(
+        &data[block.input_offset as usize..][..BLOCK_LENGTH],
+        &uncompressed[..]
+      );
+      thread::sleep(delay);
+    }
+    let elapsed = start.elapsed();
+    assert!(
+      elapsed < delay * 2 * blocks.len() as u32,
+      "{:?} for {} blocks",
+      elapsed,
+      blocks.len()
+    );
+  }
+
+  #[test]
+  fn test_blocks_not_asked_for() {
+    let bgzf = make_bgzf(&numbers(1000), BLOCK_LENGTH);
+    let blocks = blocks_of(&bgzf);
+    let prefetcher = Prefetcher::spawn(bgzf, pool(), false).unwrap();
+    assert!(prefetcher.take(0).is_none());
+    prefetcher.request(&blocks[1..3]);
+    assert!(prefetcher.take(blocks[1].input_offset).is_some());
+    //taken blocks are gone
+    assert!(prefetcher.take(blocks[1].input_offset).is_none());
+
+    //standing down drops what was prefetched
+    prefetcher.request(&[]);
+    assert!(prefetcher.take(blocks[2].input_offset).is_none());
+  }
+
+  #[test]
+  fn test_errors_come_with_the_block() {
+    let mut bgzf = make_bgzf(&numbers(1000), BLOCK_LENGTH);
+    let blocks = blocks_of(&bgzf);
+    //the deflate data of the second block can not be decompressed
+    bgzf[blocks[1].block_offset as usize + 18] = 0xff;
+    let prefetcher = Prefetcher::spawn(bgzf, pool(), false).unwrap();
+    prefetcher.request(&blocks);
+    assert!(prefetcher.take(blocks[0].input_offset).unwrap().is_ok());
+    assert!(prefetcher.take(blocks[1].input_offset).unwrap().is_err());
+    assert!(prefetcher.take(blocks[2].input_offset).unwrap().is_ok());
+  }
+
+  fn prefetching_reader(path: &str) -> BgzfReader {
+    BgzfReader::builder()
+      .prefetch(4)
+      .open(String::from(path))
+      .unwrap()
+  }
+
+  fn is_idle(reader: &BgzfReader) -> bool {
+    let state = lock_state(&reader.prefetcher.as_ref().unwrap().shared.0);
+    state.queue.is_empty() && state.working.is_none() && state.ready.is_empty()
+  }
+
+  #[test]
+  fn test_sequential_reads() {
+    let data = numbers(24_750);
+    let path = write_temp("prefetch_sequential", &make_bgzf(&data, BLOCK_LENGTH)).unwrap();
+    let reader = prefetching_reader(&path);
+    //parts of blocks, the end of a block then two whole blocks
+    let mut position = 0;
+    for &length in [300, 700, 2000].iter().cycle().take(99) {
+      let mut buffer = vec![0; length];
+      assert_eq!(length, reader.read_to(&mut buffer).unwrap());
+      assert_eq!(&data[position..position + length], &buffer[..]);
+      position += length;
+    }
+    assert_eq!(data.len(), position);
+    assert_eq!(0, reader.read_to(&mut [0; 10]).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_random_reads_stand_down() {
+    let data = numbers(25_000);
+    let path = write_temp("prefetch_random", &make_bgzf(&data, BLOCK_LENGTH)).unwrap();
+    let reader = prefetching_reader(&path);
+    let mut buffer = vec![0; 500];
+    reader.read_to(&mut buffer).unwrap();
+    //backwards, as seeking forward past the cached block is not supported
+    for block in (1..100).rev().step_by(7) {
+      let start = block * 1000 + 250;
+      reader.seek(start);
+      assert_eq!(500, reader.read_to(&mut buffer).unwrap());
+      assert_eq!(&data[start as usize..start as usize + 500], &buffer[..]);
+      //only a read continuing this one prefetches again
+      assert!(is_idle(&reader));
+    }
+    //reading on from the last position into the next block prefetches the
+    //blocks after it again
+    reader.read_to(&mut buffer[..250]).unwrap();
+    reader.read_to(&mut buffer).unwrap();
+    assert!(!is_idle(&reader));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_errors_fail_the_read_needing_the_block() {
+    let data = numbers(2500);
+    let mut bgzf = make_bgzf(&data, BLOCK_LENGTH);
+    let blocks = blocks_of(&bgzf);
+    bgzf[blocks[3].block_offset as usize + 18] = 0xff;
+    let path = write_temp("prefetch_error", &bgzf).unwrap();
+    let serial = BgzfReader::new(path.clone()).unwrap();
+    let reader = prefetching_reader(&path);
+    let mut buffer = vec![0; BLOCK_LENGTH];
+    for expected in data.chunks(BLOCK_LENGTH).take(3) {
+      assert_eq!(1000, reader.read_to(&mut buffer).unwrap());
+      assert_eq!(expected, &buffer[..]);
+      serial.read_to(&mut buffer).unwrap();
+    }
+    let error = reader.read_to(&mut buffer).err().unwrap();
+    assert_eq!(
+      serial.read_to(&mut buffer).err().unwrap().to_string(),
+      error.to_string()
+    );
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_drop_stops_the_thread() {
+    let bgzf = make_bgzf(&numbers(5000), BLOCK_LENGTH);
+    let blocks = blocks_of(&bgzf);
+    let delay = Duration::from_millis(20);
+    let prefetcher = Prefetcher::spawn(SlowSource { data: bgzf, delay }, pool(), false).unwrap();
+    prefetcher.request(&blocks);
+    let start = Instant::now();
+    drop(prefetcher);
+    //only the block being decompressed is finished
+    assert!(start.elapsed() < delay * 5);
+  }
+}
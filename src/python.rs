@@ -0,0 +1,354 @@
+//! Python module `bgzf_rust_reader`, with the `python` feature, built into a
+//! wheel by maturin (see pyproject.toml).
+//!
+//! Its `BgzfReader` class is a read-only binary file: it implements the
+//! methods of `io.RawIOBase` and is registered as one, so `io.BufferedReader`
+//! and `io.TextIOWrapper` can wrap it and pandas can read from it. Failures
+//! of the file raise the `OSError` of their kind (`FileNotFoundError`,
+//! `PermissionError`, ...), data that is not valid BGZF raises `OSError`
+//! like a bad gzip file, and invalid arguments or reads of a closed reader
+//! raise `ValueError`.
+
+use crate::backend::{Decompressor, Inflate};
+use crate::{decompress_block_at, read_block, BgzfError, BgzfReader};
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyOSError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+/// Bytes read at once looking for the end of a line
+const LINE_READ_LENGTH: usize = 8 * 1024;
+
+/// Where the data of a `PyBgzfReader` comes from
+enum Source {
+  /// A bgzf file
+  File(Box<BgzfReader>),
+  /// Bytes of bgzf data given to the constructor, decompressed
+  Memory(Vec<u8>),
+}
+
+impl Source {
+  /// Same as `BgzfReader::read_at`
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> Result<usize, BgzfError> {
+    match self {
+      Source::File(reader) => reader.read_at(pos, buf),
+      Source::Memory(data) => {
+        let start = pos.min(data.len() as u64) as usize;
+        let read = buf.len().min(data.len() - start);
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        Ok(read)
+      }
+    }
+  }
+
+  fn len(&self) -> u64 {
+    match self {
+      Source::File(reader) => reader.total_uncompressed_length(),
+      Source::Memory(data) => data.len() as u64,
+    }
+  }
+}
+
+/// Decompresses the blocks of the bgzf data `data`
+fn decompress_bytes(data: &[u8]) -> Result<Vec<u8>, BgzfError> {
+  let mut decompressor = Decompressor::new();
+  let mut block_data = Vec::new();
+  let mut uncompressed = Vec::new();
+  let mut offset = 0;
+  while offset < data.len() as u64 {
+    let mut block = read_block(&data, offset)?;
+    block.input_offset = uncompressed.len() as u64;
+    let start = uncompressed.len();
+    uncompressed.resize(start + block.input_length as usize, 0);
+    decompress_block_at(
+      &data,
+      &mut decompressor,
+      &block,
+      true,
+      &mut block_data,
+      &mut uncompressed[start..],
+    )?;
+    offset += u64::from(block.block_size);
+  }
+  Ok(uncompressed)
+}
+
+/// Returns the Python exception of `error`
+fn python_error(error: BgzfError) -> PyErr {
+  match error {
+    BgzfError::Io(error) => PyErr::from(error),
+    error => PyOSError::new_err(error.to_string()),
+  }
+}
+
+fn closed_error() -> PyErr {
+  PyValueError::new_err("I/O operation on closed file.")
+}
+
+/// Reader of bgzf data with random access, from the path of a file or from
+/// bytes, which behaves like a binary file opened for reading
+///
+/// Fields description:
+///
+/// source: the data, None once closed,
+/// pos: uncompressed offset the next read starts at,
+#[pyclass(name = "BgzfReader", module = "bgzf_rust_reader", unsendable)]
+pub struct PyBgzfReader {
+  source: Option<Source>,
+  pos: u64,
+}
+
+impl PyBgzfReader {
+  fn source(&self) -> PyResult<&Source> {
+    self.source.as_ref().ok_or_else(closed_error)
+  }
+
+  /// Reads up to `size` bytes from the position, all of them to the end of
+  /// the data for a negative `size`, and moves the position past them
+  fn read_vec(&mut self, size: i64) -> PyResult<Vec<u8>> {
+    let source = self.source()?;
+    let available = source.len().saturating_sub(self.pos);
+    let length = match u64::try_from(size) {
+      Ok(size) => size.min(available),
+      Err(_negative) => available,
+    };
+    let mut data = vec![0; length as usize];
+    let mut read = 0;
+    while read < data.len() {
+      match source
+        .read_at(self.pos + read as u64, &mut data[read..])
+        .map_err(python_error)?
+      {
+        0 => break,
+        bytes => read += bytes,
+      }
+    }
+    data.truncate(read);
+    self.pos += read as u64;
+    Ok(data)
+  }
+
+  /// Reads a line from the position, its newline included, of at most
+  /// `size` bytes unless `size` is negative
+  fn read_line(&mut self, size: i64) -> PyResult<Vec<u8>> {
+    let source = self.source()?;
+    let limit = usize::try_from(size).unwrap_or(usize::MAX);
+    let mut line = Vec::new();
+    let mut chunk = vec![0; LINE_READ_LENGTH];
+    while line.len() < limit {
+      let wanted = chunk.len().min(limit - line.len());
+      let read = source
+        .read_at(self.pos + line.len() as u64, &mut chunk[..wanted])
+        .map_err(python_error)?;
+      if read == 0 {
+        break;
+      }
+      match chunk[..read].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => {
+          line.extend_from_slice(&chunk[..=newline]);
+          break;
+        }
+        None => line.extend_from_slice(&chunk[..read]),
+      }
+    }
+    self.pos += line.len() as u64;
+    Ok(line)
+  }
+}
+
+#[pymethods]
+impl PyBgzfReader {
+  /// Opens `source`, the path of a bgzf file (str or os.PathLike) or bgzf
+  /// data (bytes, bytearray or anything with the buffer protocol)
+  #[new]
+  fn new(source: &Bound<'_, PyAny>) -> PyResult<PyBgzfReader> {
+    let source = if let Ok(path) = source.extract::<PathBuf>() {
+      let path = path
+        .into_os_string()
+        .into_string()
+        .map_err(|_path| PyValueError::new_err("The path is not valid UTF-8"))?;
+      Source::File(Box::new(BgzfReader::new(path).map_err(python_error)?))
+    } else if let Ok(buffer) = PyBuffer::<u8>::get(source) {
+      let data = buffer.to_vec(source.py())?;
+      Source::Memory(decompress_bytes(&data).map_err(python_error)?)
+    } else {
+      return Err(PyTypeError::new_err(
+        "BgzfReader takes a path or bytes of bgzf data",
+      ));
+    };
+    Ok(PyBgzfReader {
+      source: Some(source),
+      pos: 0,
+    })
+  }
+
+  /// Reads up to `size` bytes, to the end of the data when it is negative or
+  /// None, and returns them, b"" at the end of the data
+  #[pyo3(signature = (size = -1))]
+  fn read<'py>(&mut self, py: Python<'py>, size: Option<i64>) -> PyResult<Bound<'py, PyBytes>> {
+    let data = self.read_vec(size.unwrap_or(-1))?;
+    Ok(PyBytes::new(py, &data))
+  }
+
+  /// Same as `read`, for `io.BufferedReader` and `io.TextIOWrapper`
+  #[pyo3(signature = (size = -1))]
+  fn read1<'py>(&mut self, py: Python<'py>, size: Option<i64>) -> PyResult<Bound<'py, PyBytes>> {
+    self.read(py, size)
+  }
+
+  /// Reads to the end of the data
+  fn readall<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+    self.read(py, None)
+  }
+
+  /// Reads into the writable buffer `buffer`, returns the number of bytes
+  /// read, 0 at the end of the data
+  fn readinto(&mut self, py: Python<'_>, buffer: &Bound<'_, PyAny>) -> PyResult<usize> {
+    let buffer = PyBuffer::<u8>::get(buffer)?;
+    let target = buffer
+      .as_mut_slice(py)
+      .ok_or_else(|| PyTypeError::new_err("readinto needs a writable contiguous buffer"))?;
+    let data = self.read_vec(target.len() as i64)?;
+    for (cell, &byte) in target.iter().zip(&data) {
+      cell.set(byte);
+    }
+    Ok(data.len())
+  }
+
+  /// Reads a line, its newline included, of at most `size` bytes unless it
+  /// is negative or None, b"" at the end of the data
+  #[pyo3(signature = (size = -1))]
+  fn readline<'py>(&mut self, py: Python<'py>, size: Option<i64>) -> PyResult<Bound<'py, PyBytes>> {
+    let line = self.read_line(size.unwrap_or(-1))?;
+    Ok(PyBytes::new(py, &line))
+  }
+
+  /// Reads the lines to the end of the data, stopping once their total length
+  /// reaches `hint` unless it is negative or None
+  #[pyo3(signature = (hint = -1))]
+  fn readlines<'py>(&mut self, py: Python<'py>, hint: Option<i64>) -> PyResult<Bound<'py, PyList>> {
+    let hint = u64::try_from(hint.unwrap_or(-1))
+      .ok()
+      .filter(|&hint| hint > 0);
+    let lines = PyList::empty(py);
+    let mut total = 0;
+    loop {
+      let line = self.read_line(-1)?;
+      if line.is_empty() {
+        break;
+      }
+      total += line.len() as u64;
+      lines.append(PyBytes::new(py, &line))?;
+      if hint.is_some_and(|hint| total >= hint) {
+        break;
+      }
+    }
+    Ok(lines)
+  }
+
+  /// Moves the position to `offset` from the start (`whence` 0), from the
+  /// position (1) or from the end of the data (2), returns the new position.
+  /// Positions past the end are allowed, reads from them returning b"".
+  #[pyo3(signature = (offset, whence = 0))]
+  fn seek(&mut self, offset: i64, whence: i32) -> PyResult<u64> {
+    let base = match whence {
+      0 => 0,
+      1 => self.pos,
+      2 => self.source()?.len(),
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "invalid whence ({})",
+          whence
+        )))
+      }
+    };
+    self.source()?;
+    let pos = i128::from(base) + i128::from(offset);
+    self.pos = u64::try_from(pos)
+      .map_err(|_error| PyValueError::new_err(format!("negative seek position {}", pos)))?;
+    Ok(self.pos)
+  }
+
+  /// Returns the position
+  fn tell(&self) -> PyResult<u64> {
+    self.source()?;
+    Ok(self.pos)
+  }
+
+  /// Returns the uncompressed length of the data
+  fn __len__(&self) -> PyResult<usize> {
+    Ok(self.source()?.len() as usize)
+  }
+
+  fn readable(&self) -> PyResult<bool> {
+    self.source().map(|_source| true)
+  }
+
+  fn seekable(&self) -> PyResult<bool> {
+    self.source().map(|_source| true)
+  }
+
+  fn writable(&self) -> PyResult<bool> {
+    self.source().map(|_source| false)
+  }
+
+  fn isatty(&self) -> PyResult<bool> {
+    self.source().map(|_source| false)
+  }
+
+  /// Raises `io.UnsupportedOperation`, the data not being a file descriptor
+  fn fileno(&self, py: Python<'_>) -> PyResult<i32> {
+    let unsupported = py.import("io")?.getattr("UnsupportedOperation")?;
+    Err(PyErr::from_value(unsupported.call1(("fileno",))?))
+  }
+
+  fn flush(&self) -> PyResult<()> {
+    self.source().map(|_source| ())
+  }
+
+  /// Closes the reader, which can be done more than once
+  fn close(&mut self) {
+    self.source = None;
+  }
+
+  #[getter]
+  fn closed(&self) -> bool {
+    self.source.is_none()
+  }
+
+  fn __enter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+    slf.source()?;
+    Ok(slf)
+  }
+
+  #[pyo3(signature = (*_args))]
+  fn __exit__(&mut self, _args: &Bound<'_, pyo3::types::PyTuple>) -> bool {
+    self.close();
+    false
+  }
+
+  fn __iter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+    slf.source()?;
+    Ok(slf)
+  }
+
+  fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyBytes>>> {
+    let line = self.read_line(-1)?;
+    Ok(Some(PyBytes::new(py, &line)).filter(|_line| !line.is_empty()))
+  }
+}
+
+/// Python bindings of the bgzf reader
+#[pymodule]
+fn bgzf_rust_reader(module: &Bound<'_, PyModule>) -> PyResult<()> {
+  module.add_class::<PyBgzfReader>()?;
+  let reader_class = module.getattr("BgzfReader")?;
+  module
+    .py()
+    .import("io")?
+    .getattr("RawIOBase")?
+    .call_method1("register", (reader_class,))?;
+  Ok(())
+}
@@ -0,0 +1,196 @@
+//! Recovery of the blocks following damage in the middle of a file, see
+//! `BgzfReaderBuilder::recover`.
+
+use crate::read_block;
+use positioned_io::ReadAt;
+use std::cmp::min;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Length of the chunks of the file searched for the next block
+const SEARCH_CHUNK: usize = 1 << 16;
+
+/// Part of a file skipped over because it could not be read:
+///
+/// compressed_range: the bytes of the file skipped, from the first damaged
+/// block to the next block that could be read,
+/// uoffset_range: where the data of the skipped blocks was in the
+/// uncompressed data, which is empty for blocks whose headers were damaged,
+/// their length being unknown, and the data of the block otherwise,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataGap {
+  pub compressed_range: Range<u64>,
+  pub uoffset_range: Range<u64>,
+}
+
+impl DataGap {
+  /// Tells whether reading `len` bytes from `pos` reads across the gap, for
+  /// an empty one whether the read starts before it and ends after it
+  pub(crate) fn touched_by(&self, pos: u64, len: usize) -> bool {
+    pos < self.uoffset_range.end && pos.saturating_add(len as u64) > self.uoffset_range.start
+  }
+}
+
+impl Error for DataGap {}
+
+impl fmt::Display for DataGap {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "The data at {}..{} is lost, the bytes {}..{} of the file are damaged",
+      self.uoffset_range.start,
+      self.uoffset_range.end,
+      self.compressed_range.start,
+      self.compressed_range.end
+    )
+  }
+}
+
+/// Returns the offset of the first block after `from` in `file`, which is
+/// `file_length` bytes long, that can be read and is followed by another
+/// one or by the end of the file, or None when no block is left
+pub(crate) fn resync<R: ReadAt + ?Sized>(file: &R, from: u64, file_length: u64) -> Option<u64> {
+  let mut chunk = vec![0u8; SEARCH_CHUNK];
+  let mut start = from + 1;
+  while start < file_length {
+    let length = min(SEARCH_CHUNK as u64, file_length - start) as usize;
+    file.read_exact_at(start, &mut chunk[..length]).ok()?;
+    //the gzip magic and deflate method, the header is checked by reading it
+    let candidates = chunk[..length]
+      .iter()
+      .enumerate()
+      .filter(|&(position, &byte)| {
+        byte == 31 && chunk.get(position + 1).is_none_or(|&byte| byte == 139)
+      })
+      .map(|(position, _byte)| start + position as u64);
+    for offset in candidates {
+      if chains(file, offset, file_length) {
+        return Some(offset);
+      }
+    }
+    start += length as u64;
+  }
+  None
+}
+
+/// Tells whether a block can be read at `offset` of `file`, and the file
+/// ends after it or another block follows it
+fn chains<R: ReadAt + ?Sized>(file: &R, offset: u64, file_length: u64) -> bool {
+  match read_block(file, offset) {
+    Ok(block) => {
+      let next = offset + u64::from(block.block_size);
+      next == file_length || read_block(file, next).is_ok()
+    }
+    Err(_) => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_with_garbage, write_temp};
+  use crate::{BgzfError, BgzfReader, Truncated};
+  use std::fs;
+
+  /// Data that does not compress, for blocks about as long as their data
+  fn data() -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..20_000)
+      .map(|_n| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+      })
+      .collect()
+  }
+
+  /// Offset of every block of `bgzf` from its headers
+  fn block_offsets(bgzf: &[u8]) -> Vec<u64> {
+    let mut offsets = vec![0];
+    while let Ok(block) = read_block(&bgzf.to_vec(), *offsets.last().unwrap()) {
+      offsets.push(offsets.last().unwrap() + u64::from(block.block_size));
+    }
+    offsets
+  }
+
+  #[test]
+  fn test_resync() {
+    let bgzf = make_bgzf(&data(), 1000);
+    let offsets = block_offsets(&bgzf);
+    let length = bgzf.len() as u64;
+    //from any byte of a block, the next one
+    for &from in &[0, 1, offsets[3] - 1, offsets[3]] {
+      let next = offsets.iter().find(|&&offset| offset > from).copied();
+      assert_eq!(next, resync(&bgzf, from, length));
+    }
+    //the EOF marker ends the file
+    assert_eq!(Some(length - 28), resync(&bgzf, length - 29, length));
+    assert_eq!(None, resync(&bgzf, length - 28, length));
+  }
+
+  #[test]
+  fn test_recover_skips_garbage() {
+    let data = data();
+    let offsets = block_offsets(&make_bgzf(&data, 1000));
+    //300 bytes of garbage over the start of the ninth block, and some over
+    //the start of the deflate data of the fourth, right after its header
+    let mut bgzf = make_with_garbage(&data, 1000, 8, 300);
+    let deflate_data = offsets[3] as usize + 18;
+    bgzf[deflate_data..deflate_data + 50].copy_from_slice(&[0xff; 50]);
+    let path = write_temp("recover_garbage", &bgzf).unwrap();
+    assert!(matches!(
+      BgzfReader::new(path.clone()).err().unwrap(),
+      BgzfError::Truncated(Truncated { block_offset }) if block_offset == offsets[8]
+    ));
+
+    for lazy in [false, true] {
+      let reader = BgzfReader::builder()
+        .recover(true)
+        .lazy(lazy)
+        .open(path.clone())
+        .unwrap();
+      //the data of the ninth block is lost
+      assert_eq!(19_000, reader.total_uncompressed_length());
+      let header_gap = DataGap {
+        compressed_range: offsets[8]..offsets[9],
+        uoffset_range: 8000..8000,
+      };
+      assert_eq!(vec![header_gap.clone()], reader.gaps());
+
+      //reads on either side of the gaps are as they were
+      let mut content = vec![0; 4000];
+      reader.seek(4000);
+      assert_eq!(4000, reader.read_to(&mut content).unwrap());
+      assert_eq!(&data[4000..8000], &content[..]);
+      assert_eq!(4000, reader.read_to(&mut content).unwrap());
+      assert_eq!(&data[9000..13_000], &content[..]);
+      //and reads across them fail
+      reader.seek(7500);
+      match reader.read_to(&mut content) {
+        Err(BgzfError::DataGap(gap)) => assert_eq!(header_gap, gap),
+        other => panic!("{:?}", other),
+      }
+      reader.seek(2500);
+      let block_gap = DataGap {
+        compressed_range: offsets[3]..offsets[4],
+        uoffset_range: 3000..4000,
+      };
+      match reader.read_to(&mut content) {
+        Err(BgzfError::DataGap(gap)) => assert_eq!(block_gap, gap),
+        other => panic!("{:?}", other),
+      }
+      assert_eq!(vec![block_gap.clone(), header_gap.clone()], reader.gaps());
+      reader.seek(3999);
+      assert!(matches!(
+        reader.read_to(&mut content[..1]),
+        Err(BgzfError::DataGap(_))
+      ));
+      reader.seek(2000);
+      assert_eq!(1000, reader.read_to(&mut content[..1000]).unwrap());
+      assert_eq!(&data[2000..3000], &content[..1000]);
+    }
+    fs::remove_file(path).unwrap();
+  }
+}
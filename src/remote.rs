@@ -0,0 +1,165 @@
+use crate::block_cache::{BlockCache, CacheStats};
+use crate::shared_state::lock;
+use positioned_io::ReadAt;
+use std::cmp::min;
+use std::io;
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Default of `BgzfReaderBuilder::fetch_size`
+pub(crate) const DEFAULT_FETCH_SIZE: u64 = 256 * 1024;
+
+/// Default of `BgzfReaderBuilder::remote_cache_bytes`
+pub(crate) const DEFAULT_CACHE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Fetches byte ranges of a remote file, the transport of a reader opened
+/// with `BgzfReaderBuilder::open_remote`. With the `http` feature
+/// `HttpTransport` makes HTTP range requests, with the `object-store` feature
+/// `ObjectStoreTransport` calls `get_range` of an object store, other
+/// transports can wrap another client or another protocol.
+pub trait RangeTransport: Send + Sync {
+  /// Returns the length of the file
+  fn length(&self) -> io::Result<u64>;
+
+  /// Returns the bytes of `range`, within the file
+  fn fetch(&self, range: Range<u64>) -> io::Result<Vec<u8>>;
+}
+
+/// A remote file read through a `RangeTransport`, fetching aligned chunks of
+/// `fetch_size` bytes so that the reads of neighbouring blocks, like those of
+/// indexing, make a request together, and keeping the chunks fetched in a
+/// cache so that reading a block again does not fetch it again
+///
+/// Fields description:
+///
+/// transport: where the chunks are fetched from,
+/// length: length of the file,
+/// fetch_size: length of the chunks, the least a request fetches,
+/// chunks: chunks fetched, by offset in the file,
+pub(crate) struct RemoteSource {
+  transport: Box<dyn RangeTransport>,
+  length: u64,
+  fetch_size: u64,
+  chunks: Mutex<BlockCache>,
+}
+
+impl RemoteSource {
+  /// Returns a source of the file of `transport`, asking it its length
+  pub(crate) fn new(
+    transport: Box<dyn RangeTransport>,
+    fetch_size: u64,
+    cache_bytes: u64,
+  ) -> io::Result<RemoteSource> {
+    Ok(RemoteSource {
+      length: transport.length()?,
+      transport,
+      fetch_size: fetch_size.max(1),
+      chunks: Mutex::new(BlockCache::new("chunks", cache_bytes)),
+    })
+  }
+
+  /// Returns the length of the file
+  pub(crate) fn len(&self) -> u64 {
+    self.length
+  }
+
+  /// Returns the use of the chunk cache, whose misses are the requests made
+  pub(crate) fn stats(&self) -> CacheStats {
+    lock(&self.chunks).stats()
+  }
+}
+
+/// Reads from one chunk at a time, at most up to its end
+impl ReadAt for RemoteSource {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    if pos >= self.length || buf.is_empty() {
+      return Ok(0);
+    }
+    let chunk_start = pos - pos % self.fetch_size;
+    //within a chunk, the distance fits in a usize
+    let copy_start = (pos - chunk_start) as usize;
+    let copy = |chunk: &[u8], buf: &mut [u8]| {
+      let length = min(chunk.len() - copy_start, buf.len());
+      buf[..length].copy_from_slice(&chunk[copy_start..copy_start + length]);
+      length
+    };
+    if let Some(chunk) = lock(&self.chunks).get(chunk_start) {
+      return Ok(copy(chunk, buf));
+    }
+    //fetched without holding the lock, the reads of cached chunks go on
+    let chunk_end = min(chunk_start + self.fetch_size, self.length);
+    let chunk = self.transport.fetch(chunk_start..chunk_end)?;
+    if chunk.len() as u64 != chunk_end - chunk_start {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+          "{} bytes fetched at offset {} instead of {}",
+          chunk.len(),
+          chunk_start,
+          chunk_end - chunk_start
+        ),
+      ));
+    }
+    let mut chunks = lock(&self.chunks);
+    let chunk = chunks.insert(chunk_start, chunk);
+    Ok(copy(chunk, buf))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicU64, Ordering};
+  use std::sync::Arc;
+
+  /// A file in memory, counting the requests made
+  struct Counting {
+    data: Vec<u8>,
+    requests: Arc<AtomicU64>,
+  }
+
+  impl RangeTransport for Counting {
+    fn length(&self) -> io::Result<u64> {
+      Ok(self.data.len() as u64)
+    }
+
+    fn fetch(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+      self.requests.fetch_add(1, Ordering::SeqCst);
+      Ok(self.data[range.start as usize..range.end as usize].to_vec())
+    }
+  }
+
+  #[test]
+  fn test_reads_fetch_chunks() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let requests = Arc::new(AtomicU64::new(0));
+    let transport = Counting {
+      data: data.clone(),
+      requests: Arc::clone(&requests),
+    };
+    let source = RemoteSource::new(Box::new(transport), 100, 300).unwrap();
+    assert_eq!(1000, source.len());
+
+    //a read across two chunks fetches both, reads within them none more
+    let mut buf = vec![0; 50];
+    source.read_exact_at(180, &mut buf).unwrap();
+    assert_eq!(&data[180..230], &buf[..]);
+    source.read_exact_at(110, &mut buf).unwrap();
+    assert_eq!(&data[110..160], &buf[..]);
+    assert_eq!(2, requests.load(Ordering::SeqCst));
+
+    //the last chunk is shorter, reads past the end return nothing
+    let mut tail = vec![0; 20];
+    assert_eq!(10, source.read_at(990, &mut tail).unwrap());
+    assert_eq!(&data[990..], &tail[..10]);
+    assert_eq!(0, source.read_at(1000, &mut tail).unwrap());
+    assert_eq!(3, requests.load(Ordering::SeqCst));
+
+    //the cache holds 3 chunks, the least recently used is fetched again
+    source.read_exact_at(0, &mut buf).unwrap();
+    source.read_exact_at(180, &mut buf).unwrap();
+    assert_eq!(5, requests.load(Ordering::SeqCst));
+    let stats = source.stats();
+    assert_eq!((3, 300, 5), (stats.blocks, stats.budget, stats.misses));
+  }
+}
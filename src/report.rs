@@ -0,0 +1,275 @@
+//! Compression report of the blocks of a file, see
+//! `BgzfReader::compression_report`.
+//!
+//! With the `serde` feature `CompressionReport` and `HistogramBucket`
+//! implement `Serialize`, as maps named after their fields.
+
+use crate::{BgzfBlock, BlockInfo};
+
+/// Compressed block sizes where the buckets of `size_histogram` start
+const SIZE_BUCKETS: [u32; 7] = [0, 1024, 2048, 4096, 8192, 16384, 32768];
+
+/// Compression ratios where the buckets of `ratio_histogram` start
+const RATIO_BUCKETS: [f64; 8] = [0.0, 1.0, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0];
+
+/// Thresholds of `BgzfReader::compression_report`:
+///
+/// small_block_size: compressed size under which a block counts as small,
+/// 1024 by default,
+/// poor_ratio: compression ratio under which a block counts as poorly
+/// compressed, 1.1 by default,
+/// worst_blocks: number of blocks with the lowest ratios to list, 10 by
+/// default,
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReportOptions {
+  pub small_block_size: u32,
+  pub poor_ratio: f64,
+  pub worst_blocks: usize,
+}
+
+impl Default for ReportOptions {
+  fn default() -> ReportOptions {
+    ReportOptions {
+      small_block_size: 1024,
+      poor_ratio: 1.1,
+      worst_blocks: 10,
+    }
+  }
+}
+
+/// Bucket of a histogram, holding the values from `from` up to the `from` of
+/// the next bucket, the last one holding everything above its `from`:
+///
+/// from: smallest value in the bucket,
+/// blocks: number of blocks in the bucket,
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HistogramBucket<T> {
+  pub from: T,
+  pub blocks: u64,
+}
+
+/// How well the data blocks of a file are compressed, the ratio of a block
+/// being its uncompressed length over its size in the file:
+///
+/// blocks: number of data blocks, the EOF marker not included,
+/// size_histogram: blocks by compressed size, from 0 then powers of two from
+/// 1024 to 32768,
+/// ratio_histogram: blocks by compression ratio, from 0, 1, 1.5, 2, 3, 4, 6
+/// and 8,
+/// small_blocks: number of blocks smaller than `small_block_size`,
+/// poorly_compressed_blocks: number of blocks with a ratio under `poor_ratio`,
+/// worst_blocks: the `worst_blocks` blocks with the lowest ratios, lowest
+/// first, with their number in the file,
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressionReport {
+  pub blocks: u64,
+  pub size_histogram: Vec<HistogramBucket<u32>>,
+  pub ratio_histogram: Vec<HistogramBucket<f64>>,
+  pub small_blocks: u64,
+  pub poorly_compressed_blocks: u64,
+  pub worst_blocks: Vec<(usize, BlockInfo)>,
+}
+
+fn ratio(block: &BgzfBlock) -> f64 {
+  f64::from(block.input_length) / f64::from(block.block_size)
+}
+
+/// Adds one block of `value` to the histogram bucket holding it
+fn count<T: PartialOrd + Copy>(histogram: &mut [HistogramBucket<T>], value: T) {
+  let bucket = histogram.partition_point(|bucket| bucket.from <= value);
+  histogram[bucket.saturating_sub(1)].blocks += 1;
+}
+
+/// Builds the report of `blocks` from their sizes only
+pub(crate) fn compression_report(
+  blocks: &[BgzfBlock],
+  options: ReportOptions,
+) -> CompressionReport {
+  let mut size_histogram = SIZE_BUCKETS
+    .iter()
+    .map(|&from| HistogramBucket { from, blocks: 0 })
+    .collect::<Vec<_>>();
+  let mut ratio_histogram = RATIO_BUCKETS
+    .iter()
+    .map(|&from| HistogramBucket { from, blocks: 0 })
+    .collect::<Vec<_>>();
+  let mut small_blocks = 0;
+  let mut poorly_compressed_blocks = 0;
+  for block in blocks {
+    count(&mut size_histogram, block.block_size);
+    count(&mut ratio_histogram, ratio(block));
+    if block.block_size < options.small_block_size {
+      small_blocks += 1;
+    }
+    if ratio(block) < options.poor_ratio {
+      poorly_compressed_blocks += 1;
+    }
+  }
+
+  let mut by_ratio = (0..blocks.len()).collect::<Vec<_>>();
+  //stable, so blocks of the same ratio stay in file order
+  by_ratio.sort_by(|&a, &b| ratio(&blocks[a]).total_cmp(&ratio(&blocks[b])));
+  let worst_blocks = by_ratio
+    .into_iter()
+    .take(options.worst_blocks)
+    .map(|index| (index, blocks[index].info()))
+    .collect();
+
+  CompressionReport {
+    blocks: blocks.len() as u64,
+    size_histogram,
+    ratio_histogram,
+    small_blocks,
+    poorly_compressed_blocks,
+    worst_blocks,
+  }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+  use super::{CompressionReport, HistogramBucket};
+  use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+  impl<T: Serialize> Serialize for HistogramBucket<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+      let mut bucket = serializer.serialize_struct("HistogramBucket", 2)?;
+      bucket.serialize_field("from", &self.from)?;
+      bucket.serialize_field("blocks", &self.blocks)?;
+      bucket.end()
+    }
+  }
+
+  impl Serialize for CompressionReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+      let mut report = serializer.serialize_struct("CompressionReport", 6)?;
+      report.serialize_field("blocks", &self.blocks)?;
+      report.serialize_field("size_histogram", &self.size_histogram)?;
+      report.serialize_field("ratio_histogram", &self.ratio_histogram)?;
+      report.serialize_field("small_blocks", &self.small_blocks)?;
+      report.serialize_field("poorly_compressed_blocks", &self.poorly_compressed_blocks)?;
+      report.serialize_field("worst_blocks", &self.worst_blocks)?;
+      report.end()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{make_bgzf, make_without_eof, write_temp};
+  use crate::writer::EOF_MARKER;
+  use crate::BgzfReader;
+  use std::fs;
+
+  /// Bytes deflate can not compress
+  fn noise(length: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..length)
+      .map(|_n| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+      })
+      .collect()
+  }
+
+  fn mixed_file() -> String {
+    //3 blocks of repeated bytes then 2 blocks of noise
+    let mut bgzf = make_without_eof(&[b'a'; 3000], 1000);
+    bgzf.extend(make_bgzf(&noise(10_000), 5000));
+    write_temp("report_mixed", &bgzf).unwrap()
+  }
+
+  #[test]
+  fn test_compression_report() {
+    let path = mixed_file();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let report = reader.compression_report(ReportOptions {
+      worst_blocks: 2,
+      ..ReportOptions::default()
+    });
+    assert_eq!(5, report.blocks);
+    assert_eq!(
+      vec![3, 0, 0, 2, 0, 0, 0],
+      report
+        .size_histogram
+        .iter()
+        .map(|bucket| bucket.blocks)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+      SIZE_BUCKETS.to_vec(),
+      report
+        .size_histogram
+        .iter()
+        .map(|bucket| bucket.from)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+      vec![2, 0, 0, 0, 0, 0, 0, 3],
+      report
+        .ratio_histogram
+        .iter()
+        .map(|bucket| bucket.blocks)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(3, report.small_blocks);
+    assert_eq!(2, report.poorly_compressed_blocks);
+    let blocks = reader.block_offsets().collect::<Vec<_>>();
+    let mut worst = report.worst_blocks.clone();
+    worst.sort_by_key(|(index, _block)| *index);
+    assert_eq!(vec![(3, blocks[3]), (4, blocks[4])], worst);
+    let indexed_blocks = reader.blocks();
+    assert!(
+      ratio(&indexed_blocks[report.worst_blocks[0].0])
+        <= ratio(&indexed_blocks[report.worst_blocks[1].0])
+    );
+    drop(indexed_blocks);
+
+    //other thresholds
+    let report = reader.compression_report(ReportOptions {
+      small_block_size: 1,
+      poor_ratio: 100.0,
+      worst_blocks: 100,
+    });
+    assert_eq!(0, report.small_blocks);
+    assert_eq!(5, report.poorly_compressed_blocks);
+    assert_eq!(5, report.worst_blocks.len());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compression_report_of_empty_file() {
+    let path = write_temp("report_empty", &EOF_MARKER).unwrap();
+    let report = BgzfReader::new(path.clone())
+      .unwrap()
+      .compression_report(ReportOptions::default());
+    assert_eq!(0, report.blocks);
+    assert!(report
+      .size_histogram
+      .iter()
+      .all(|bucket| bucket.blocks == 0));
+    assert!(report
+      .ratio_histogram
+      .iter()
+      .all(|bucket| bucket.blocks == 0));
+    assert!(report.worst_blocks.is_empty());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serialize_report() {
+    let path = mixed_file();
+    let report = BgzfReader::new(path.clone())
+      .unwrap()
+      .compression_report(ReportOptions::default());
+    let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+    assert_eq!(5, json["blocks"]);
+    assert_eq!(1024, json["size_histogram"][1]["from"]);
+    assert_eq!(3, json["ratio_histogram"][7]["blocks"]);
+    assert_eq!(5, json["worst_blocks"].as_array().unwrap().len());
+    fs::remove_file(path).unwrap();
+  }
+}
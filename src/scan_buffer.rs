@@ -0,0 +1,129 @@
+use positioned_io::ReadAt;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::io;
+
+/// Bytes read by the first read of a scan, enough for the largest block
+const FIRST_FILL_LENGTH: usize = 64 << 10;
+
+/// Most bytes read at once, every read reads twice as many as the one before
+/// up to this
+const MAX_FILL_LENGTH: usize = 4 << 20;
+
+/// Source read through a buffer, for scanning the blocks of a file one after
+/// the other: the headers and footers `read_block` reads are then mostly in
+/// the buffer, and the file is only read again once a block goes past it.
+/// The first read is small so that indexing a few blocks does not read much
+/// more, the next ones grow for long scans.
+pub(crate) struct ScanBuffer<'a, R: ?Sized> {
+  source: &'a R,
+  buffered: RefCell<Buffered>,
+}
+
+/// start: offset in the source of the first byte held,
+/// data: bytes held from start, shorter than asked for at the end of the
+/// source,
+/// fill_length: number of bytes the next read asks for,
+struct Buffered {
+  start: u64,
+  data: Vec<u8>,
+  fill_length: usize,
+}
+
+impl<'a, R: ReadAt + ?Sized> ScanBuffer<'a, R> {
+  /// Nothing is read, nor allocated, before the first read
+  pub(crate) fn new(source: &'a R) -> ScanBuffer<'a, R> {
+    ScanBuffer {
+      source,
+      buffered: RefCell::new(Buffered {
+        start: 0,
+        data: Vec::new(),
+        fill_length: FIRST_FILL_LENGTH,
+      }),
+    }
+  }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for ScanBuffer<'_, R> {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let mut buffered = self.buffered.borrow_mut();
+    if pos < buffered.start || pos >= buffered.start + buffered.data.len() as u64 {
+      buffered.fill(self.source, pos)?;
+    }
+    let start = (pos - buffered.start) as usize;
+    let length = min(buf.len(), buffered.data.len() - start);
+    buf[..length].copy_from_slice(&buffered.data[start..start + length]);
+    Ok(length)
+  }
+}
+
+impl Buffered {
+  /// Reads the bytes of `source` from `pos`, as many as it has up to
+  /// `fill_length`
+  fn fill<R: ReadAt + ?Sized>(&mut self, source: &R, pos: u64) -> io::Result<()> {
+    self.data.resize(self.fill_length, 0);
+    let mut filled = 0;
+    while filled < self.data.len() {
+      match source.read_at(pos + filled as u64, &mut self.data[filled..]) {
+        Ok(0) => break,
+        Ok(read) => filled += read,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => {
+          self.data.clear();
+          return Err(e);
+        }
+      }
+    }
+    self.data.truncate(filled);
+    self.start = pos;
+    self.fill_length = min(self.fill_length * 2, MAX_FILL_LENGTH);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compressed_run::tests::CountingSource;
+  use crate::fixtures::make_bgzf;
+  use crate::{read_block, BgzfError};
+
+  /// Reads the blocks of `source` one after the other with `read_block`,
+  /// returns their offset, size and uncompressed length
+  fn scan<R: ReadAt>(source: &R, length: u64) -> Vec<(u64, u32, u32)> {
+    let mut blocks = Vec::new();
+    let mut block_offset = 0;
+    while block_offset < length {
+      let block = read_block(source, block_offset).unwrap();
+      block_offset += u64::from(block.block_size);
+      blocks.push((block.block_offset, block.block_size, block.input_length));
+    }
+    blocks
+  }
+
+  #[test]
+  fn test_reads_within_the_buffer() {
+    let data = (0..200_000u32)
+      .flat_map(|n| n.to_le_bytes())
+      .collect::<Vec<_>>();
+    let source = CountingSource::new(make_bgzf(&data, 1000));
+    let length = source.data.len() as u64;
+    let blocks = scan(&source, length);
+    //the data blocks and the EOF marker
+    assert_eq!(801, blocks.len());
+    assert_eq!(3 * blocks.len(), source.reads.get());
+
+    //reads of 64KiB, 128KiB and so on
+    source.reads.set(0);
+    let buffered = ScanBuffer::new(&source);
+    assert_eq!(blocks, scan(&buffered, length));
+    assert!(source.reads.get() < 10, "{} reads", source.reads.get());
+
+    //past the end of the source, like reading it
+    let error = read_block(&buffered, length).err().unwrap();
+    assert!(matches!(error, BgzfError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    let mut tail = [0; 8];
+    assert_eq!(4, buffered.read_at(length - 4, &mut tail).unwrap());
+    assert_eq!(&source.data[length as usize - 4..], &tail[..4]);
+  }
+}
@@ -0,0 +1,183 @@
+use crate::shared_state::lock;
+use positioned_io::ReadAt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+/// Reads at an offset from a source that can only `Read` and `Seek`, such as
+/// an entry of a container format or a stream of a FUSE file system, so that
+/// `BgzfReaderBuilder::open_read_at` can read it at random without copying
+/// it to a file first
+///
+/// Every read locks the source, seeks to the offset, reads, and seeks back
+/// to where the source was when wrapped: a lock and two seeks per read on
+/// top of the read itself. The reads of a reader are whole blocks, or
+/// chunks of many blocks while indexing, so this costs little for a source
+/// whose seeks are cheap, but the reads of all the threads of the reader
+/// and of its clones wait for each other, which the parallel methods and
+/// prefetching do not get past, and a source that seeks by reading again
+/// from the start is read again for every block.
+///
+/// Fields description:
+///
+/// inner: the source and the position it is put back at after every read,
+/// length: length of the source, from its start,
+pub struct SeekReadSource<T: Read + Seek> {
+  inner: Mutex<(T, u64)>,
+  length: u64,
+}
+
+impl<T: Read + Seek> SeekReadSource<T> {
+  /// Wraps `inner`, seeking to its end to know its length and then back to
+  /// its current position, where it is put back after every read
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, SeekReadSource};
+  /// use std::io::Cursor;
+  ///
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let source = SeekReadSource::new(Cursor::new(data)).unwrap();
+  /// let length = source.len();
+  /// let reader = BgzfReader::builder().open_read_at(source, length).unwrap();
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// ```
+  pub fn new(mut inner: T) -> io::Result<SeekReadSource<T>> {
+    let position = inner.stream_position()?;
+    let length = inner.seek(SeekFrom::End(0))?;
+    inner.seek(SeekFrom::Start(position))?;
+    Ok(SeekReadSource {
+      inner: Mutex::new((inner, position)),
+      length,
+    })
+  }
+
+  /// Returns the length of the source
+  pub fn len(&self) -> u64 {
+    self.length
+  }
+
+  /// Returns true when the source is empty
+  pub fn is_empty(&self) -> bool {
+    self.length == 0
+  }
+
+  /// Returns the source, at the position it had when wrapped
+  pub fn into_inner(self) -> T {
+    self
+      .inner
+      .into_inner()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .0
+  }
+}
+
+/// Seeks to `pos`, reads, and seeks back, the source being put back even
+/// when the read fails
+impl<T: Read + Seek> ReadAt for SeekReadSource<T> {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    if pos >= self.length || buf.is_empty() {
+      return Ok(0);
+    }
+    let mut inner = lock(&self.inner);
+    let (source, position) = &mut *inner;
+    let result = source
+      .seek(SeekFrom::Start(pos))
+      .and_then(|_| source.read(buf));
+    source.seek(SeekFrom::Start(*position))?;
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+  use std::sync::Arc;
+  use std::thread;
+
+  fn data() -> Vec<u8> {
+    (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn test_reads_and_puts_the_source_back() {
+    let data = data();
+    let mut cursor = Cursor::new(data.clone());
+    cursor.set_position(1234);
+    let source = SeekReadSource::new(cursor).unwrap();
+    assert_eq!(100_000, source.len());
+    let mut buf = vec![0; 5000];
+    for &pos in &[0, 99_000, 50_000, 1] {
+      let read = source.read_at(pos, &mut buf).unwrap();
+      assert_eq!(read, 5000.min(100_000 - pos as usize));
+      assert_eq!(&data[pos as usize..pos as usize + read], &buf[..read]);
+    }
+    assert_eq!(0, source.read_at(100_000, &mut buf).unwrap());
+    assert_eq!(0, source.read_at(u64::MAX, &mut buf).unwrap());
+    assert_eq!(1234, source.into_inner().position());
+    assert!(SeekReadSource::new(Cursor::new(Vec::new()))
+      .unwrap()
+      .is_empty());
+  }
+
+  /// A source failing its reads, counting its seeks
+  struct Failing {
+    seeks: Vec<SeekFrom>,
+  }
+
+  impl Read for Failing {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+      Err(io::Error::other("read failed"))
+    }
+  }
+
+  impl Seek for Failing {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+      self.seeks.push(pos);
+      Ok(match pos {
+        SeekFrom::End(_) => 1000,
+        SeekFrom::Start(offset) => offset,
+        SeekFrom::Current(_) => 7,
+      })
+    }
+  }
+
+  #[test]
+  fn test_failed_read_puts_the_source_back() {
+    let source = SeekReadSource::new(Failing { seeks: Vec::new() }).unwrap();
+    let mut buf = [0; 10];
+    assert_eq!(
+      "read failed",
+      source.read_at(500, &mut buf).unwrap_err().to_string()
+    );
+    let seeks = source.into_inner().seeks;
+    assert_eq!(
+      &[SeekFrom::Start(500), SeekFrom::Start(7)],
+      &seeks[seeks.len() - 2..]
+    );
+  }
+
+  #[test]
+  fn test_reads_from_threads() {
+    let data = Arc::new(data());
+    let source = Arc::new(SeekReadSource::new(Cursor::new(data.to_vec())).unwrap());
+    let readers = (0..2u64)
+      .map(|thread_number| {
+        let source = Arc::clone(&source);
+        let data = Arc::clone(&data);
+        thread::spawn(move || {
+          let mut buf = vec![0; 777];
+          let mut pos = thread_number * 12_345;
+          for _ in 0..2000 {
+            pos = (pos * 48_271 + 11) % (100_000 - 777);
+            source.read_exact_at(pos, &mut buf).unwrap();
+            assert_eq!(&data[pos as usize..pos as usize + 777], &buf[..]);
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for reader in readers {
+      reader.join().unwrap();
+    }
+  }
+}
@@ -0,0 +1,64 @@
+//! State of a `BgzfReader` its `&self` methods update, shareable between
+//! threads.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A position in a file, which can be read and set from any thread
+#[derive(Default)]
+pub struct Position(AtomicU64);
+
+impl Position {
+  pub fn new(position: u64) -> Position {
+    Position(AtomicU64::new(position))
+  }
+
+  /// Returns the position
+  pub fn get(&self) -> u64 {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  /// Sets the position
+  pub fn set(&self, position: u64) {
+    self.0.store(position, Ordering::SeqCst)
+  }
+}
+
+impl fmt::Debug for Position {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fmt::Debug::fmt(&self.get(), f)
+  }
+}
+
+/// Value read and set as a whole, like a `Cell` shareable between threads
+pub(crate) struct SharedCell<T: Copy>(Mutex<T>);
+
+impl<T: Copy> SharedCell<T> {
+  pub(crate) fn new(value: T) -> SharedCell<T> {
+    SharedCell(Mutex::new(value))
+  }
+
+  pub(crate) fn get(&self) -> T {
+    *lock(&self.0)
+  }
+
+  pub(crate) fn set(&self, value: T) {
+    *lock(&self.0) = value;
+  }
+}
+
+/// Locks `mutex`, a thread having panicked holding it leaving it as it was
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Same as `lock` for reading `lock`
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+  lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Same as `lock` for writing `lock`
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+  lock.write().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,199 @@
+#[cfg(feature = "crypt4gh")]
+use crate::crypt4gh_source::Crypt4ghSource;
+#[cfg(feature = "remote")]
+use crate::remote::RemoteSource;
+use positioned_io::ReadAt;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+/// The compressed data a `BgzfReader` reads its blocks from: a file, a
+/// stream embedded in a file, read from `start` for `length` bytes as if it
+/// were a file of its own, the bytes given to `BgzfReader::from_bytes`,
+/// which need no filesystem and so also work on wasm32, with the `remote`
+/// feature a remote file, with the `crypt4gh` feature the plaintext of an
+/// encrypted file, or the `length` bytes of any source read at an offset.
+pub(crate) enum Source {
+  File(File),
+  Embedded {
+    file: File,
+    start: u64,
+    length: u64,
+  },
+  Bytes(Arc<Vec<u8>>),
+  #[cfg(feature = "remote")]
+  Remote(Arc<RemoteSource>),
+  #[cfg(feature = "crypt4gh")]
+  Crypt4gh(Arc<Crypt4ghSource<Source>>),
+  Custom {
+    source: Arc<dyn ReadAt + Send + Sync>,
+    length: u64,
+  },
+}
+
+impl Source {
+  /// Returns the source of the stream of `length` bytes at `start` in
+  /// `file`, up to the end of the file when None, failing when the stream
+  /// goes past the end of the file
+  pub(crate) fn embedded(file: File, start: u64, length: Option<u64>) -> io::Result<Source> {
+    let file_length = file.metadata()?.len();
+    let length = length.unwrap_or_else(|| file_length.saturating_sub(start));
+    match start.checked_add(length) {
+      Some(end) if end <= file_length => Ok(Source::Embedded {
+        file,
+        start,
+        length,
+      }),
+      _ => Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+          "A stream of {} bytes at offset {} goes past the end of the file of {} bytes",
+          length, start, file_length
+        ),
+      )),
+    }
+  }
+
+  /// Returns the length of the compressed data
+  pub(crate) fn len(&self) -> io::Result<u64> {
+    match self {
+      Source::File(file) => Ok(file.metadata()?.len()),
+      Source::Embedded { length, .. } => Ok(*length),
+      Source::Bytes(bytes) => Ok(bytes.len() as u64),
+      #[cfg(feature = "remote")]
+      Source::Remote(remote) => Ok(remote.len()),
+      #[cfg(feature = "crypt4gh")]
+      Source::Crypt4gh(encrypted) => Ok(encrypted.len()),
+      Source::Custom { length, .. } => Ok(*length),
+    }
+  }
+
+  /// Returns another source of the same data, for a thread to read it, the
+  /// bytes, the fetched chunks of a remote file, the decrypted segments and
+  /// other sources being shared rather than copied
+  pub(crate) fn try_clone(&self) -> io::Result<Source> {
+    match self {
+      Source::File(file) => Ok(Source::File(file.try_clone()?)),
+      Source::Embedded {
+        file,
+        start,
+        length,
+      } => Ok(Source::Embedded {
+        file: file.try_clone()?,
+        start: *start,
+        length: *length,
+      }),
+      Source::Bytes(bytes) => Ok(Source::Bytes(Arc::clone(bytes))),
+      #[cfg(feature = "remote")]
+      Source::Remote(remote) => Ok(Source::Remote(Arc::clone(remote))),
+      #[cfg(feature = "crypt4gh")]
+      Source::Crypt4gh(encrypted) => Ok(Source::Crypt4gh(Arc::clone(encrypted))),
+      Source::Custom { source, length } => Ok(Source::Custom {
+        source: Arc::clone(source),
+        length: *length,
+      }),
+    }
+  }
+
+  /// Returns the whole file, None for embedded streams, bytes, remote,
+  /// encrypted files and other sources
+  #[cfg(feature = "mmap")]
+  pub(crate) fn file(&self) -> Option<&File> {
+    match self {
+      Source::File(file) => Some(file),
+      _ => None,
+    }
+  }
+}
+
+impl ReadAt for Source {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(offset = pos, length = buf.len(), "source read");
+    match self {
+      #[cfg(any(unix, windows))]
+      Source::File(file) => file.read_at(pos, buf),
+      //the stream ends where the container goes on
+      #[cfg(any(unix, windows))]
+      Source::Embedded {
+        file,
+        start,
+        length,
+      } => {
+        let buf_length = (buf.len() as u64).min(length.saturating_sub(pos)) as usize;
+        match buf_length {
+          0 => Ok(0),
+          _ => file.read_at(start + pos, &mut buf[..buf_length]),
+        }
+      }
+      //positioned-io only reads files at an offset on unix and windows,
+      //and elsewhere files can not be opened anyway
+      #[cfg(not(any(unix, windows)))]
+      Source::File(_) | Source::Embedded { .. } => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Files can not be read at an offset on this platform",
+      )),
+      Source::Bytes(bytes) => (&bytes[..]).read_at(pos, buf),
+      #[cfg(feature = "remote")]
+      Source::Remote(remote) => remote.read_at(pos, buf),
+      #[cfg(feature = "crypt4gh")]
+      Source::Crypt4gh(encrypted) => encrypted.read_at(pos, buf),
+      //the data ends at its length whatever the source holds after it
+      Source::Custom { source, length } => {
+        let buf_length = (buf.len() as u64).min(length.saturating_sub(pos)) as usize;
+        match buf_length {
+          0 => Ok(0),
+          _ => source.read_at(pos, &mut buf[..buf_length]),
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_bytes_source() {
+    let source = Source::Bytes(Arc::new(b"bgzf data".to_vec()));
+    assert_eq!(9, source.len().unwrap());
+    let mut buf = [0; 4];
+    assert_eq!(4, source.try_clone().unwrap().read_at(5, &mut buf).unwrap());
+    assert_eq!(b"data", &buf);
+    assert_eq!(0, source.read_at(9, &mut buf).unwrap());
+    assert_eq!(0, source.read_at(u64::MAX, &mut buf).unwrap());
+  }
+  #[test]
+  fn test_custom_source() {
+    let source = Source::Custom {
+      source: Arc::new(b"bgzf data and more".to_vec()),
+      length: 9,
+    };
+    assert_eq!(9, source.len().unwrap());
+    let mut buf = [0; 8];
+    assert_eq!(4, source.try_clone().unwrap().read_at(5, &mut buf).unwrap());
+    assert_eq!(b"data", &buf[..4]);
+    assert_eq!(0, source.read_at(9, &mut buf).unwrap());
+    assert_eq!(0, source.read_at(u64::MAX, &mut buf).unwrap());
+  }
+
+  #[test]
+  fn test_embedded_source() {
+    let path = crate::fixtures::write_temp("embedded_source", b"headerbgzf datatrailer").unwrap();
+    let source = Source::embedded(File::open(&path).unwrap(), 6, Some(9)).unwrap();
+    assert_eq!(9, source.len().unwrap());
+    let mut buf = [0; 8];
+    assert_eq!(4, source.try_clone().unwrap().read_at(5, &mut buf).unwrap());
+    assert_eq!(b"data", &buf[..4]);
+    assert_eq!(0, source.read_at(9, &mut buf).unwrap());
+    assert_eq!(0, source.read_at(u64::MAX, &mut buf).unwrap());
+
+    let rest = Source::embedded(File::open(&path).unwrap(), 6, None).unwrap();
+    assert_eq!(16, rest.len().unwrap());
+    assert!(Source::embedded(File::open(&path).unwrap(), 6, Some(17)).is_err());
+    assert!(Source::embedded(File::open(&path).unwrap(), 23, None).is_err());
+    assert!(Source::embedded(File::open(&path).unwrap(), u64::MAX, Some(2)).is_err());
+    std::fs::remove_file(&path).unwrap();
+  }
+}
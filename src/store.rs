@@ -0,0 +1,88 @@
+use crate::RangeTransport;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Where the transport runs the futures of the store: the tokio runtime it
+/// was made in, or a runtime of its own when made outside of any
+enum Bridge {
+  Handle(Handle),
+  Runtime(Runtime),
+}
+
+/// Transport reading an object of an `object_store::ObjectStore`, like S3,
+/// GCS or Azure, with its `get_range`, so that the credentials, retries and
+/// instrumentation of the store apply to the reads of the blocks
+///
+/// Fields description:
+///
+/// store: the store holding the object,
+/// location: path of the object in the store,
+/// bridge: where the futures of the store run, the reader waiting for them,
+pub struct ObjectStoreTransport {
+  store: Arc<dyn ObjectStore>,
+  location: Path,
+  bridge: Bridge,
+}
+
+impl ObjectStoreTransport {
+  /// Returns a transport for the object at `location` in `store`. Made
+  /// within a tokio runtime, its requests run on that runtime, and the
+  /// reader must then be used on threads allowed to block, like those of
+  /// `tokio::task::spawn_blocking`, the sync reads waiting for them. Made
+  /// elsewhere, it starts a runtime of its own.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, ObjectStoreTransport};
+  /// use object_store::memory::InMemory;
+  /// use object_store::path::Path;
+  /// use object_store::ObjectStore;
+  /// use std::sync::Arc;
+  ///
+  /// let store = Arc::new(InMemory::new());
+  /// let location = Path::from("data/bgzf_test.bgz");
+  /// let data = std::fs::read("bgzf_test.bgz").unwrap();
+  /// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  /// runtime.block_on(store.put(&location, data.into())).unwrap();
+  ///
+  /// let transport = ObjectStoreTransport::new(store, location).unwrap();
+  /// let reader = BgzfReader::builder().open_remote(transport).unwrap();
+  /// assert_eq!(280, reader.total_uncompressed_length());
+  /// ```
+  pub fn new(store: Arc<dyn ObjectStore>, location: Path) -> io::Result<ObjectStoreTransport> {
+    let bridge = match Handle::try_current() {
+      Ok(handle) => Bridge::Handle(handle),
+      Err(_error) => Bridge::Runtime(Builder::new_current_thread().enable_all().build()?),
+    };
+    Ok(ObjectStoreTransport {
+      store,
+      location,
+      bridge,
+    })
+  }
+
+  /// Waits for `future`, run on the runtime of the bridge
+  fn block_on<F: Future>(&self, future: F) -> F::Output {
+    match &self.bridge {
+      Bridge::Handle(handle) => handle.block_on(future),
+      Bridge::Runtime(runtime) => runtime.block_on(future),
+    }
+  }
+}
+
+impl RangeTransport for ObjectStoreTransport {
+  fn length(&self) -> io::Result<u64> {
+    let meta = self.block_on(self.store.head(&self.location))?;
+    Ok(meta.size)
+  }
+
+  fn fetch(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let bytes = self.block_on(self.store.get_range(&self.location, range))?;
+    Ok(bytes.to_vec())
+  }
+}
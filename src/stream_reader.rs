@@ -0,0 +1,372 @@
+use crate::backend::{Decompressor, Inflate};
+use crate::writer::{EOF_MARKER, MAX_BLOCK_SIZE};
+use crate::{check_bgzf_start, inflate_block, read_block, BgzfBlock, BgzfError, Truncated};
+use positioned_io::ReadAt;
+use std::io::{self, BufRead, Read};
+
+/// Compressed bytes read from a source, for `read_block` to find the blocks
+/// in them at their offsets in the source:
+///
+/// start: offset in the source of the first byte,
+/// data: the bytes read,
+pub(crate) struct Window<'a> {
+  pub(crate) start: u64,
+  pub(crate) data: &'a [u8],
+}
+
+impl ReadAt for Window<'_> {
+  fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    //only the blocks from the start are looked for
+    self.data.read_at(pos - self.start, buf)
+  }
+}
+
+/// Reader of the bgzf data of a source that can only be read through once,
+/// like a pipe or stdin, decompressing its blocks one after the other as
+/// they come, with no index and no seeking
+///
+/// Fields description:
+///
+/// inner: the source of the compressed data,
+/// decompressor: de-compresses the blocks,
+/// pending: compressed bytes read from inner and not decompressed yet, the
+/// first filled of them, from the compressed offset block_offset, a whole
+/// block being at most its length,
+/// inner_end: whether inner returned its last byte,
+/// block_offset: compressed offset of the next block,
+/// block: data of the block being read,
+/// block_pos: how much of block was read,
+/// uncompressed_offset: uncompressed offset of the next byte read,
+/// after_eof_marker: whether the last block read was an EOF marker,
+/// done: set at the end of the data,
+/// failed: kind of the error that stopped the reader, once returned, the
+/// reads after it fail too,
+pub struct BgzfStreamReader<R: Read> {
+  inner: R,
+  decompressor: Decompressor,
+  pending: Vec<u8>,
+  filled: usize,
+  inner_end: bool,
+  block_offset: u64,
+  block: Vec<u8>,
+  block_pos: usize,
+  uncompressed_offset: u64,
+  after_eof_marker: bool,
+  done: bool,
+  failed: Option<io::ErrorKind>,
+}
+
+/// Below are the steps to read bgzf data arriving on a pipe or stdin, wrap
+/// the source with `BgzfStreamReader::new` and read it with `std::io::Read`
+/// or `BufRead`, which hands out the data a block at a time.
+///
+/// The blocks are read as the source gives them and decompressed when whole,
+/// the CRC32 of each is checked since it can not be read again. An EOF marker
+/// followed by more blocks, like that of files joined with `cat`, is gone
+/// over, and anything after an EOF marker that is not a block is ignored, as
+/// by `BgzfReader`. A source ending within a block fails the read with
+/// `UnexpectedEof`, as do the reads after it.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::BgzfStreamReader;
+/// use std::io::{BufRead, Cursor};
+///
+/// let data = std::fs::read("bgzf_test.bgz").unwrap();
+/// let mut reader = BgzfStreamReader::new(Cursor::new(data));
+/// let mut first_line = String::new();
+/// reader.read_line(&mut first_line).unwrap();
+/// assert!(first_line.starts_with("This is just a bgzf test"));
+/// assert_eq!(first_line.len() as u64, reader.uncompressed_offset());
+/// ```
+impl<R: Read> BgzfStreamReader<R> {
+  /// Returns a reader of the bgzf data of `inner`, from its current position
+  pub fn new(inner: R) -> BgzfStreamReader<R> {
+    BgzfStreamReader {
+      inner,
+      decompressor: Decompressor::new(),
+      pending: vec![0; MAX_BLOCK_SIZE],
+      filled: 0,
+      inner_end: false,
+      block_offset: 0,
+      block: Vec::new(),
+      block_pos: 0,
+      uncompressed_offset: 0,
+      after_eof_marker: false,
+      done: false,
+      failed: None,
+    }
+  }
+
+  /// Returns the uncompressed offset of the next byte read, the number of
+  /// bytes read so far
+  pub fn uncompressed_offset(&self) -> u64 {
+    self.uncompressed_offset
+  }
+
+  /// Returns whether the last block read was an EOF marker. Once reads return
+  /// no more bytes, tells whether the data ended with one.
+  pub fn has_eof_marker(&self) -> bool {
+    self.after_eof_marker
+  }
+
+  /// Returns a reference to the source
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Returns the source, which may have been read past the blocks
+  /// decompressed so far
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+
+  /// Reads from `inner` once, after the bytes pending
+  fn fill(&mut self) -> io::Result<()> {
+    loop {
+      match self.inner.read(&mut self.pending[self.filled..]) {
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+        Err(error) => return Err(error),
+        Ok(0) => {
+          self.inner_end = true;
+          return Ok(());
+        }
+        Ok(read) => {
+          self.filled += read;
+          return Ok(());
+        }
+      }
+    }
+  }
+
+  /// Decompresses the next block holding data into `block`, reading from
+  /// `inner` until it is whole, returns false at the end of the data
+  fn next_block(&mut self) -> Result<bool, BgzfError> {
+    loop {
+      let window = Window {
+        start: self.block_offset,
+        data: &self.pending[..self.filled],
+      };
+      if self.block_offset == 0 {
+        check_bgzf_start(&window)?;
+      }
+      if self.filled == 0 && self.inner_end {
+        return Ok(false);
+      }
+      let block = match read_block(&window, self.block_offset) {
+        Ok(block) => block,
+        Err(BgzfError::Io(error))
+          if error.kind() == io::ErrorKind::UnexpectedEof && !self.inner_end =>
+        {
+          self.fill()?;
+          continue;
+        }
+        Err(_error) if self.after_eof_marker => return Ok(false),
+        Err(BgzfError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof => {
+          return Err(
+            Truncated {
+              block_offset: self.block_offset,
+            }
+            .into(),
+          )
+        }
+        Err(error) => return Err(error),
+      };
+      let block_size = block.block_size as usize;
+      let block_data = &self.pending[..block_size];
+      self.after_eof_marker = block_data == EOF_MARKER;
+      let has_data = block.input_length > 0;
+      if has_data {
+        let block = BgzfBlock {
+          input_offset: self.uncompressed_offset,
+          ..block
+        };
+        self.block.resize(block.input_length as usize, 0);
+        inflate_block(
+          &mut self.decompressor,
+          &block,
+          true,
+          block_data,
+          &mut self.block,
+        )?;
+        self.block_pos = 0;
+      }
+      self.pending.copy_within(block_size..self.filled, 0);
+      self.filled -= block_size;
+      self.block_offset += u64::from(block.block_size);
+      if has_data {
+        return Ok(true);
+      }
+    }
+  }
+}
+
+impl<R: Read> BufRead for BgzfStreamReader<R> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if let Some(kind) = self.failed {
+      return Err(io::Error::new(
+        kind,
+        "An earlier read of the stream reader failed",
+      ));
+    }
+    while self.block_pos == self.block.len() && !self.done {
+      match self.next_block() {
+        Ok(true) => {}
+        Ok(false) => self.done = true,
+        Err(error) => {
+          let error = io::Error::from(error);
+          self.failed = Some(error.kind());
+          return Err(error);
+        }
+      }
+    }
+    Ok(&self.block[self.block_pos..])
+  }
+
+  fn consume(&mut self, amount: usize) {
+    let amount = amount.min(self.block.len() - self.block_pos);
+    self.block_pos += amount;
+    self.uncompressed_offset += amount as u64;
+  }
+}
+
+impl<R: Read> Read for BgzfStreamReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let length = available.len().min(buf.len());
+    buf[..length].copy_from_slice(&available[..length]);
+    self.consume(length);
+    Ok(length)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{
+    make_bgzf, make_plain_gzip, make_truncated, make_with_bad_crc, make_with_header_fields,
+    make_without_eof,
+  };
+  use std::io::Cursor;
+
+  fn content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..5_000 {
+      content.extend_from_slice(format!("{}\tchr{}\tstreamed\n", i, i % 7).as_bytes());
+    }
+    content
+  }
+
+  /// A pipe giving at most `piece` bytes a read
+  struct Pipe {
+    data: Cursor<Vec<u8>>,
+    piece: usize,
+  }
+
+  impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let length = buf.len().min(self.piece);
+      self.data.read(&mut buf[..length])
+    }
+  }
+
+  fn stream(bgzf: Vec<u8>, piece: usize) -> BgzfStreamReader<Pipe> {
+    BgzfStreamReader::new(Pipe {
+      data: Cursor::new(bgzf),
+      piece,
+    })
+  }
+
+  #[test]
+  fn test_reads_in_small_pieces() {
+    let content = content();
+    let bgzf = make_bgzf(&content, 3000);
+    for &piece in &[1, 7, 100, 4096, 1 << 20] {
+      let mut reader = stream(bgzf.clone(), piece);
+      let mut data = Vec::new();
+      reader.read_to_end(&mut data).unwrap();
+      assert_eq!(content, data, "{}", piece);
+      assert_eq!(content.len() as u64, reader.uncompressed_offset());
+      assert!(reader.has_eof_marker());
+      assert_eq!(0, reader.read(&mut [0; 10]).unwrap());
+    }
+
+    //lines a block at a time, the offset counting what was consumed
+    let mut reader = stream(bgzf, 333);
+    let mut line = String::new();
+    for i in 0..5_000 {
+      line.clear();
+      reader.read_line(&mut line).unwrap();
+      assert_eq!(format!("{}\tchr{}\tstreamed\n", i, i % 7), line);
+    }
+    assert_eq!(content.len() as u64, reader.uncompressed_offset());
+    assert_eq!(0, reader.read_line(&mut line).unwrap());
+
+    //header fields of other gzip flags
+    let bgzf = make_with_header_fields(&content, 3000, 2 | 8 | 16);
+    let mut data = Vec::new();
+    stream(bgzf, 50).read_to_end(&mut data).unwrap();
+    assert_eq!(content, data);
+  }
+
+  #[test]
+  fn test_concatenated_streams() {
+    let content = content();
+    let (first, second) = content.split_at(40_000);
+    let mut bgzf = make_bgzf(first, 3000);
+    bgzf.extend(make_bgzf(second, 5000));
+    let mut reader = stream(bgzf.clone(), 1000);
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    assert_eq!(content, data);
+    assert!(reader.has_eof_marker());
+
+    //what follows the last EOF marker and is not a block is ignored
+    bgzf.extend_from_slice(b"trailing bytes, not bgzf");
+    let mut data = Vec::new();
+    stream(bgzf, 7).read_to_end(&mut data).unwrap();
+    assert_eq!(content, data);
+
+    //the data may end without one
+    let mut reader = stream(make_without_eof(&content, 3000), 100);
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    assert_eq!(content, data);
+    assert!(!reader.has_eof_marker());
+  }
+
+  #[test]
+  fn test_errors() {
+    let content = content();
+    //the blocks before the one cut are read
+    let mut reader = stream(make_truncated(&content, 3000, 10), 100);
+    let mut data = Vec::new();
+    let error = reader.read_to_end(&mut data).err().unwrap();
+    assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    assert_eq!(&content[..data.len()], &data[..]);
+    assert_eq!(content.len() - content.len() % 3000, data.len());
+    assert_eq!(
+      io::ErrorKind::UnexpectedEof,
+      reader.read(&mut [0; 10]).err().unwrap().kind()
+    );
+
+    let mut data = Vec::new();
+    let error = stream(make_with_bad_crc(&content, 3000, 2), 100)
+      .read_to_end(&mut data)
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    assert_eq!(&content[..6000], &data[..]);
+
+    let error = stream(make_plain_gzip(&content, 3000), 100)
+      .read(&mut [0; 10])
+      .err()
+      .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+    //no data at all is no error
+    let mut reader = stream(Vec::new(), 100);
+    assert_eq!(0, reader.read(&mut [0; 10]).unwrap());
+    assert!(!reader.has_eof_marker());
+  }
+}
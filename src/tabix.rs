@@ -0,0 +1,818 @@
+//! Region queries of bgzipped text files, like VCF and BED, with their tabix
+//! index, see `TabixReader`.
+//!
+//! The .tbi file is itself bgzf, read with a `BgzfReader`. Its format, all
+//! integers little-endian:
+//!
+//! magic: the 4 bytes `TBI\1`,
+//! reference count, format, sequence column, begin column, end column, meta
+//! character and number of lines to skip: i32 each,
+//! names: the length as i32 then the reference names, each ending with a NUL,
+//! then for every reference its bins, an i32 count then for each bin its
+//! number (u32), an i32 count of chunks and the (begin, end) virtual offsets
+//! (u64) of each, and its linear index, an i32 count then the virtual offset
+//! (u64) of the first line of every 16kb window,
+//! optionally, the number of lines without coordinates as u64.
+
+use crate::{BgzfError, BgzfReader};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::error::Error;
+use std::fmt;
+
+const TABIX_MAGIC: [u8; 4] = *b"TBI\x01";
+
+/// Bin holding the metadata of a reference rather than chunks
+const METADATA_BIN: u32 = 37450;
+
+/// Width of a window of the linear index, and of the smallest bins
+const MIN_SHIFT: u32 = 14;
+
+/// Coordinates a tabix index covers, 2^29
+const MAX_COORDINATE: u64 = 1 << 29;
+
+/// Format of the data in `TabixHeader::format`, the other bits being flags
+const FORMAT_GENERIC: u32 = 0;
+const FORMAT_SAM: u32 = 1;
+const FORMAT_VCF: u32 = 2;
+
+/// Flag of `TabixHeader::format` for 0-based, half-open coordinates (like
+/// BED) rather than 1-based, inclusive ones
+const FLAG_UCSC: u32 = 0x10000;
+
+/// Uncompressed bytes read at once looking for the end of a line
+const LINE_READ_LENGTH: usize = 64 * 1024;
+
+/// Reasons a tabix index or a query of it can not be used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TabixError {
+  /// The data does not start with the tabix magic `TBI\1`
+  NotTabix,
+  /// The index is cut off or has a count or name that does not fit at
+  /// `offset` of its uncompressed data
+  Malformed { offset: u64 },
+  /// The index has no line on the reference
+  UnknownReference { name: String },
+  /// The region is not `name`, `name:start` nor `name:start-end`
+  InvalidRegion { region: String },
+  /// The line at `offset` of the uncompressed data has no valid coordinates
+  /// in the columns of the index header
+  InvalidRecord { offset: u64 },
+}
+
+impl Error for TabixError {}
+
+impl fmt::Display for TabixError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TabixError::NotTabix => write!(f, "The file is not a tabix index"),
+      TabixError::Malformed { offset } => write!(
+        f,
+        "The tabix index is cut off or malformed at offset {}",
+        offset
+      ),
+      TabixError::UnknownReference { name } => {
+        write!(f, "The tabix index has no line on reference {}", name)
+      }
+      TabixError::InvalidRegion { region } => write!(f, "Invalid region {}", region),
+      TabixError::InvalidRecord { offset } => write!(
+        f,
+        "The line at offset {} has no valid coordinates in the columns of the tabix index",
+        offset
+      ),
+    }
+  }
+}
+
+/// Columns of the lines, from the header of a tabix index
+///
+/// Fields description:
+///
+/// format: 0 for generic tab-separated lines, 1 for SAM and 2 for VCF, plus
+/// 0x10000 when the coordinates are 0-based and half-open (like BED) rather
+/// than 1-based and inclusive,
+/// sequence_column: 1-based column of the reference name,
+/// begin_column: 1-based column of the start,
+/// end_column: 1-based column of the end, 0 for lines one base long, unused
+/// for SAM and VCF, whose end is given by the CIGAR and the REF (or END),
+/// meta: lines starting with this byte are headers, not indexed,
+/// skip: number of lines at the start of the file which are headers too,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TabixHeader {
+  pub format: u32,
+  pub sequence_column: usize,
+  pub begin_column: usize,
+  pub end_column: usize,
+  pub meta: u8,
+  pub skip: u32,
+}
+
+/// Bins and linear index of one reference
+#[derive(Clone, Debug, Default)]
+struct ReferenceIndex {
+  bins: HashMap<u32, Vec<(u64, u64)>>,
+  intervals: Vec<u64>,
+}
+
+/// A tabix (.tbi) index, giving the parts of the data file holding the lines
+/// of a region
+#[derive(Clone, Debug)]
+pub struct TabixIndex {
+  header: TabixHeader,
+  names: Vec<String>,
+  references: Vec<ReferenceIndex>,
+}
+
+/// Little-endian integers read one after the other from the index data
+struct Fields<'a> {
+  data: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Fields<'a> {
+  fn bytes(&mut self, length: usize) -> Result<&'a [u8], TabixError> {
+    let malformed = TabixError::Malformed {
+      offset: self.offset as u64,
+    };
+    let end = self.offset.checked_add(length).ok_or(malformed.clone())?;
+    let bytes = self.data.get(self.offset..end).ok_or(malformed)?;
+    self.offset = end;
+    Ok(bytes)
+  }
+
+  fn u32(&mut self) -> Result<u32, TabixError> {
+    Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, TabixError> {
+    Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+  }
+
+  /// Reads an i32 which can not be negative, like a count or a column
+  fn count(&mut self) -> Result<usize, TabixError> {
+    let offset = self.offset as u64;
+    let value = i32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+    usize::try_from(value).map_err(|_error| TabixError::Malformed { offset })
+  }
+}
+
+impl TabixIndex {
+  /// Opens the tabix index at `tbi_path`, read with a `BgzfReader`
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::TabixIndex;
+  ///
+  /// let index = TabixIndex::open(String::from("tests/data/variants.vcf.gz.tbi")).unwrap();
+  /// assert_eq!(["chr1", "chr2"], index.reference_names());
+  /// ```
+  pub fn open(tbi_path: String) -> Result<TabixIndex, BgzfError> {
+    let data = BgzfReader::new(tbi_path)?.decompress_all_parallel(1)?;
+    Ok(TabixIndex::from_bytes(&data)?)
+  }
+
+  /// Parses the uncompressed data of a tabix index
+  pub fn from_bytes(data: &[u8]) -> Result<TabixIndex, TabixError> {
+    let mut fields = Fields { data, offset: 0 };
+    if fields.bytes(4).ok() != Some(&TABIX_MAGIC[..]) {
+      return Err(TabixError::NotTabix);
+    }
+    let reference_count = fields.count()?;
+    let header = TabixHeader {
+      format: fields.u32()?,
+      sequence_column: fields.count()?,
+      begin_column: fields.count()?,
+      end_column: fields.count()?,
+      meta: fields.u32()? as u8,
+      skip: fields.u32()?,
+    };
+    let names_offset = fields.offset as u64;
+    let names_length = fields.count()?;
+    let names_data = fields.bytes(names_length)?;
+    let names = names_data
+      .split(|&byte| byte == 0)
+      .take(reference_count)
+      .map(|name| String::from_utf8_lossy(name).into_owned())
+      .collect::<Vec<_>>();
+    if names.len() != reference_count || reference_count > 0 && names_data.last() != Some(&0) {
+      return Err(TabixError::Malformed {
+        offset: names_offset,
+      });
+    }
+
+    let mut references = Vec::with_capacity(reference_count.min(data.len()));
+    for _ in 0..reference_count {
+      let mut reference = ReferenceIndex::default();
+      for _ in 0..fields.count()? {
+        let bin = fields.u32()?;
+        let chunk_count = fields.count()?;
+        let mut chunks = Vec::with_capacity(chunk_count.min(data.len() / 16));
+        for _ in 0..chunk_count {
+          chunks.push((fields.u64()?, fields.u64()?));
+        }
+        if bin != METADATA_BIN {
+          reference.bins.insert(bin, chunks);
+        }
+      }
+      for _ in 0..fields.count()? {
+        reference.intervals.push(fields.u64()?);
+      }
+      references.push(reference);
+    }
+    Ok(TabixIndex {
+      header,
+      names,
+      references,
+    })
+  }
+
+  /// Returns the columns of the lines indexed
+  pub fn header(&self) -> &TabixHeader {
+    &self.header
+  }
+
+  /// Returns the names of the references with lines, in file order
+  pub fn reference_names(&self) -> &[String] {
+    &self.names
+  }
+
+  /// Returns the (begin, end) virtual offsets of the parts of the data file
+  /// which can hold lines of `reference` overlapping the 0-based, half-open
+  /// `start..end`, in file order. The chunks of the bins overlapping the
+  /// region which end before the first line of its 16kb window (the linear
+  /// index) are left out, and the others merged when they overlap or share
+  /// a block.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::TabixIndex;
+  ///
+  /// let index = TabixIndex::open(String::from("tests/data/variants.vcf.gz.tbi")).unwrap();
+  /// let chunks = index.chunks("chr2", 0, 500_000).unwrap();
+  /// assert_eq!(1, chunks.len());
+  /// assert!(index.chunks("chrX", 0, 100).is_err());
+  /// ```
+  pub fn chunks(
+    &self,
+    reference: &str,
+    start: u64,
+    end: u64,
+  ) -> Result<Vec<(u64, u64)>, TabixError> {
+    let id = self
+      .names
+      .iter()
+      .position(|name| name == reference)
+      .ok_or_else(|| TabixError::UnknownReference {
+        name: reference.to_string(),
+      })?;
+    let index = &self.references[id];
+    let end = end.min(MAX_COORDINATE);
+    if start >= end {
+      return Ok(Vec::new());
+    }
+
+    let window = (start >> MIN_SHIFT) as usize;
+    let min_offset = match index.intervals.get(window) {
+      Some(&offset) => offset,
+      None => index.intervals.last().copied().unwrap_or(0),
+    };
+    let mut chunks = overlapping_bins(start, end)
+      .filter_map(|bin| index.bins.get(&bin))
+      .flatten()
+      .filter(|&&(_, chunk_end)| chunk_end > min_offset)
+      .copied()
+      .collect::<Vec<_>>();
+    chunks.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(chunks.len());
+    for (chunk_start, chunk_end) in chunks {
+      match merged.last_mut() {
+        Some(last) if chunk_start <= last.1 || chunk_start >> 16 == last.1 >> 16 => {
+          last.1 = last.1.max(chunk_end);
+        }
+        _ => merged.push((chunk_start, chunk_end)),
+      }
+    }
+    Ok(merged)
+  }
+}
+
+/// Returns the bins which can hold a line overlapping the 0-based, half-open
+/// `start..end`, from the one bin covering 2^29 bases down to those of 16kb
+fn overlapping_bins(start: u64, end: u64) -> impl Iterator<Item = u32> {
+  let last = end - 1;
+  [(0, 29), (1, 26), (9, 23), (73, 20), (585, 17), (4681, 14)]
+    .iter()
+    .flat_map(move |&(first_bin, shift)| {
+      (first_bin + (start >> shift) as u32)..=(first_bin + (last >> shift) as u32)
+    })
+}
+
+/// A bgzipped text file with its tabix index, to read the lines of a region
+///
+/// Fields description:
+///
+/// reader: reader of the data file,
+/// index: tabix index of the data file,
+pub struct TabixReader {
+  reader: BgzfReader,
+  index: TabixIndex,
+}
+
+/// Below are the steps to query a tabix indexed file, open it with `open`,
+/// which finds the index at `<file>.tbi`, or `open_with_index`, then iterate
+/// over the lines `query` or `query_region` returns. Lines are given in file
+/// order without their newline.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::TabixReader;
+///
+/// let reader = TabixReader::open(String::from("tests/data/variants.vcf.gz")).unwrap();
+/// for line in reader.query_region("chr1:100,000-120,000").unwrap() {
+///   let line = line.unwrap();
+///   assert!(line.starts_with("chr1\t"));
+/// }
+/// ```
+impl TabixReader {
+  /// Opens the bgzipped file at `file_path` with the tabix index at
+  /// `<file_path>.tbi`
+  pub fn open(file_path: String) -> Result<TabixReader, BgzfError> {
+    let tbi_path = format!("{}.tbi", file_path);
+    TabixReader::open_with_index(file_path, tbi_path)
+  }
+
+  /// Opens the bgzipped file at `file_path` with the tabix index at
+  /// `tbi_path`
+  pub fn open_with_index(file_path: String, tbi_path: String) -> Result<TabixReader, BgzfError> {
+    let index = TabixIndex::open(tbi_path)?;
+    Ok(TabixReader {
+      reader: BgzfReader::new(file_path)?,
+      index,
+    })
+  }
+
+  /// Returns the tabix index
+  pub fn index(&self) -> &TabixIndex {
+    &self.index
+  }
+
+  /// Returns the lines of `reference` overlapping the 0-based, half-open
+  /// `start..end`, as the coordinates of the columns the index header gives
+  /// tell. The end of a VCF line is given by its REF, or by END in its INFO
+  /// when there is one, and the end of a SAM line by its CIGAR. A reference
+  /// without lines in the index fails with `TabixError::UnknownReference`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::TabixReader;
+  ///
+  /// let reader = TabixReader::open(String::from("tests/data/variants.vcf.gz")).unwrap();
+  /// let lines = reader.query("chr2", 0, 500_000).unwrap();
+  /// assert_eq!(200, lines.count());
+  /// ```
+  pub fn query(&self, reference: &str, start: u64, end: u64) -> Result<TabixLines<'_>, BgzfError> {
+    //chunks can end at the EOF marker, or past it
+    let data_end = (self.reader).uoffset_to_voffset(self.reader.total_uncompressed_length())?;
+    let mut ranges = Vec::new();
+    for (chunk_start, chunk_end) in self.index.chunks(reference, start, end)? {
+      ranges.push((
+        self.reader.voffset_to_uoffset(chunk_start.min(data_end))?,
+        self.reader.voffset_to_uoffset(chunk_end.min(data_end))?,
+      ));
+    }
+    ranges.reverse();
+    Ok(TabixLines {
+      reader: &self.reader,
+      header: self.index.header,
+      reference: reference.to_string(),
+      start,
+      end,
+      ranges,
+      buffer: Vec::new(),
+      buffer_offset: 0,
+      cursor: 0,
+    })
+  }
+
+  /// Same as `query` with a region as samtools and tabix take it: `name`
+  /// for the whole reference, `name:start` from 1-based `start` to the end,
+  /// or `name:start-end` for 1-based, inclusive `start` to `end`. Commas in
+  /// the numbers are left out.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::TabixReader;
+  ///
+  /// let reader = TabixReader::open(String::from("tests/data/variants.vcf.gz")).unwrap();
+  /// let region = reader.query_region("chr1:1,001-2,000").unwrap();
+  /// let by_coordinates = reader.query("chr1", 1000, 2000).unwrap();
+  /// assert_eq!(
+  ///   region.map(Result::unwrap).collect::<Vec<_>>(),
+  ///   by_coordinates.map(Result::unwrap).collect::<Vec<_>>()
+  /// );
+  /// ```
+  pub fn query_region(&self, region: &str) -> Result<TabixLines<'_>, BgzfError> {
+    //a reference name can have colons
+    if self.index.names.iter().any(|name| name == region) {
+      return self.query(region, 0, MAX_COORDINATE);
+    }
+    let (reference, start, end) = parse_region(region)?;
+    self.query(reference, start, end)
+  }
+}
+
+/// Splits `region` into its reference and 0-based, half-open coordinates
+fn parse_region(region: &str) -> Result<(&str, u64, u64), TabixError> {
+  let invalid = || TabixError::InvalidRegion {
+    region: region.to_string(),
+  };
+  let number = |text: &str| {
+    text
+      .replace(',', "")
+      .parse::<u64>()
+      .map_err(|_error| invalid())
+  };
+  let (reference, range) = match region.rfind(':') {
+    Some(colon) => (&region[..colon], Some(&region[colon + 1..])),
+    None => (region, None),
+  };
+  let (start, end) = match range.map(|range| range.split_once('-')) {
+    None => (0, MAX_COORDINATE),
+    Some(None) => (number(range.unwrap())?.saturating_sub(1), MAX_COORDINATE),
+    Some(Some((start, end))) => (number(start)?.saturating_sub(1), number(end)?),
+  };
+  if reference.is_empty() || range.is_some() && start >= end {
+    return Err(invalid());
+  }
+  Ok((reference, start, end))
+}
+
+/// Iterator over the lines of a region, returned by `TabixReader::query`
+///
+/// Fields description:
+///
+/// reader: reader of the data file,
+/// header: columns of the lines,
+/// reference, start, end: the region, 0-based and half-open,
+/// ranges: uncompressed (start, end) of the chunks left to read, the next
+/// one last,
+/// buffer: data read from `buffer_offset`,
+/// cursor: index in `buffer` of the start of the next line,
+pub struct TabixLines<'a> {
+  reader: &'a BgzfReader,
+  header: TabixHeader,
+  reference: String,
+  start: u64,
+  end: u64,
+  ranges: Vec<(u64, u64)>,
+  buffer: Vec<u8>,
+  buffer_offset: u64,
+  cursor: usize,
+}
+
+impl TabixLines<'_> {
+  /// Returns the next line of the chunks with its offset, None after the
+  /// last chunk
+  fn next_line(&mut self) -> Result<Option<(u64, Vec<u8>)>, BgzfError> {
+    loop {
+      let &(range_start, range_end) = match self.ranges.last() {
+        Some(range) => range,
+        None => return Ok(None),
+      };
+      let position = self.buffer_offset + self.cursor as u64;
+      if position < range_start {
+        self.buffer.clear();
+        self.buffer_offset = range_start;
+        self.cursor = 0;
+        continue;
+      }
+      if position >= range_end {
+        self.ranges.pop();
+        continue;
+      }
+      if let Some(length) = self.buffer[self.cursor..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+      {
+        let line = self.buffer[self.cursor..self.cursor + length].to_vec();
+        self.cursor += length + 1;
+        return Ok(Some((position, line)));
+      }
+
+      //keeps the start of the line and reads what follows it
+      self.buffer.drain(..self.cursor);
+      self.buffer_offset = position;
+      self.cursor = 0;
+      let length = self.buffer.len();
+      self.buffer.resize(length + LINE_READ_LENGTH, 0);
+      let read = self
+        .reader
+        .read_at(position + length as u64, &mut self.buffer[length..])?;
+      self.buffer.truncate(length + read);
+      if read == 0 {
+        //the end of the data, the last line having no newline
+        self.ranges.clear();
+        let line = std::mem::take(&mut self.buffer);
+        return Ok(Some((position, line)).filter(|(_, line)| !line.is_empty()));
+      }
+    }
+  }
+}
+
+impl Iterator for TabixLines<'_> {
+  type Item = Result<String, BgzfError>;
+
+  fn next(&mut self) -> Option<Result<String, BgzfError>> {
+    loop {
+      let (offset, line) = match self.next_line() {
+        Ok(Some(line)) => line,
+        Ok(None) => return None,
+        Err(error) => {
+          self.ranges.clear();
+          return Some(Err(error));
+        }
+      };
+      if line.is_empty() || line[0] == self.header.meta {
+        continue;
+      }
+      let line = match String::from_utf8(line) {
+        Ok(line) => line,
+        Err(_error) => return Some(Err(self.invalid_record(offset))),
+      };
+      let (reference, start, end) = match coordinates(&self.header, &line) {
+        Some(coordinates) => coordinates,
+        None => return Some(Err(self.invalid_record(offset))),
+      };
+      if reference != self.reference || end <= self.start {
+        continue;
+      }
+      if start >= self.end {
+        //the lines are sorted, no later one can overlap the region
+        self.ranges.clear();
+        return None;
+      }
+      return Some(Ok(line));
+    }
+  }
+}
+
+impl TabixLines<'_> {
+  /// Ends the iteration with an `InvalidRecord` error
+  fn invalid_record(&mut self, offset: u64) -> BgzfError {
+    self.ranges.clear();
+    BgzfError::from(TabixError::InvalidRecord { offset })
+  }
+}
+
+/// Returns the reference and the 0-based, half-open coordinates of `line`
+/// from the columns of `header`, None when they are missing or not numbers
+fn coordinates<'a>(header: &TabixHeader, line: &'a str) -> Option<(&'a str, u64, u64)> {
+  let columns = line.split('\t').collect::<Vec<_>>();
+  let column = |number: usize| columns.get(number.checked_sub(1)?).copied();
+  let position = |number: usize| column(number)?.parse::<u64>().ok();
+  let reference = column(header.sequence_column)?;
+  let begin = position(header.begin_column)?;
+  let ucsc = header.format & FLAG_UCSC != 0;
+  let start = if ucsc { begin } else { begin.saturating_sub(1) };
+
+  let end = match header.format & 0xffff {
+    FORMAT_SAM => start + cigar_reference_length(column(6)?)?,
+    FORMAT_VCF => {
+      let info_end = column(8)?
+        .split(';')
+        .find_map(|field| field.strip_prefix("END="))
+        .and_then(|end| end.parse::<u64>().ok());
+      match info_end {
+        Some(end) if end > start => end,
+        _ => start + column(4)?.len() as u64,
+      }
+    }
+    FORMAT_GENERIC if header.end_column != 0 => position(header.end_column)?,
+    _ => start + 1,
+  };
+  Some((reference, start, end.max(start + 1)))
+}
+
+/// Returns the number of reference bases a CIGAR covers, 0 for `*`
+fn cigar_reference_length(cigar: &str) -> Option<u64> {
+  if cigar == "*" {
+    return Some(0);
+  }
+  let mut length = 0;
+  let mut count = 0u64;
+  for byte in cigar.bytes() {
+    match byte {
+      b'0'..=b'9' => count = count.checked_mul(10)?.checked_add(u64::from(byte - b'0'))?,
+      b'M' | b'D' | b'N' | b'=' | b'X' => {
+        length += count;
+        count = 0;
+      }
+      b'I' | b'S' | b'H' | b'P' => count = 0,
+      _ => return None,
+    }
+  }
+  Some(length)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A tabix index of one reference, `chr1`, with the given bins and
+  /// linear index
+  fn index_data(bins: &[(u32, &[(u64, u64)])], intervals: &[u64]) -> Vec<u8> {
+    let mut data = TABIX_MAGIC.to_vec();
+    for value in [1, 2, 1, 2, 0, i32::from(b'#'), 0, 5] {
+      data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(b"chr1\0");
+    data.extend_from_slice(&(bins.len() as i32).to_le_bytes());
+    for (bin, chunks) in bins {
+      data.extend_from_slice(&bin.to_le_bytes());
+      data.extend_from_slice(&(chunks.len() as i32).to_le_bytes());
+      for (start, end) in chunks.iter() {
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&end.to_le_bytes());
+      }
+    }
+    data.extend_from_slice(&(intervals.len() as i32).to_le_bytes());
+    for interval in intervals {
+      data.extend_from_slice(&interval.to_le_bytes());
+    }
+    data
+  }
+
+  #[test]
+  fn test_from_bytes() {
+    let data = index_data(
+      &[(4681, &[(100, 200)]), (METADATA_BIN, &[(1, 2), (3, 4)])],
+      &[100],
+    );
+    let index = TabixIndex::from_bytes(&data).unwrap();
+    assert_eq!(["chr1"], index.reference_names());
+    assert_eq!(
+      TabixHeader {
+        format: 2,
+        sequence_column: 1,
+        begin_column: 2,
+        end_column: 0,
+        meta: b'#',
+        skip: 0,
+      },
+      *index.header()
+    );
+    assert_eq!(vec![(100, 200)], index.chunks("chr1", 0, 10).unwrap());
+
+    assert_eq!(
+      TabixError::NotTabix,
+      TabixIndex::from_bytes(b"BAI\x01").unwrap_err()
+    );
+    assert_eq!(
+      TabixError::Malformed { offset: 36 },
+      TabixIndex::from_bytes(&data[..38]).unwrap_err()
+    );
+    assert!(TabixIndex::from_bytes(&data[..data.len() - 1]).is_err());
+    let mut negative_count = data.clone();
+    negative_count[4..8].copy_from_slice(&(-1i32).to_le_bytes());
+    assert_eq!(
+      TabixError::Malformed { offset: 4 },
+      TabixIndex::from_bytes(&negative_count).unwrap_err()
+    );
+  }
+
+  #[test]
+  fn test_chunks() {
+    let data = index_data(
+      &[
+        (0, &[(10 << 16, 11 << 16)]),
+        (4681, &[(1 << 16, 2 << 16), (3 << 16, 4 << 16 | 5)]),
+        (
+          4682,
+          &[(4 << 16 | 5, 5 << 16 | 10), (5 << 16 | 20, 7 << 16)],
+        ),
+        (4683, &[(8 << 16, 9 << 16)]),
+      ],
+      &[1 << 16, 6 << 16, 8 << 16],
+    );
+    let index = TabixIndex::from_bytes(&data).unwrap();
+    //merged when overlapping or sharing a block
+    assert_eq!(
+      vec![(1 << 16, 2 << 16), (3 << 16, 7 << 16), (10 << 16, 11 << 16)],
+      index.chunks("chr1", 0, 1 << 15).unwrap()
+    );
+    //the linear index leaves out the chunks before the window
+    assert_eq!(
+      vec![(5 << 16 | 20, 7 << 16), (10 << 16, 11 << 16)],
+      index.chunks("chr1", 1 << 14, 1 << 15).unwrap()
+    );
+    assert_eq!(
+      vec![(8 << 16, 9 << 16), (10 << 16, 11 << 16)],
+      index.chunks("chr1", 1 << 15, 1 << 16).unwrap()
+    );
+    assert_eq!(
+      Vec::<(u64, u64)>::new(),
+      index.chunks("chr1", 5, 5).unwrap()
+    );
+    assert_eq!(
+      TabixError::UnknownReference {
+        name: String::from("chr2")
+      },
+      index.chunks("chr2", 0, 1).unwrap_err()
+    );
+  }
+
+  #[test]
+  fn test_overlapping_bins() {
+    assert_eq!(
+      vec![0, 1, 9, 73, 585, 4681],
+      overlapping_bins(0, 1).collect::<Vec<_>>()
+    );
+    assert_eq!(
+      vec![0, 1, 9, 73, 585, 4681, 4682],
+      overlapping_bins(16383, 16385).collect::<Vec<_>>()
+    );
+    let bins = overlapping_bins(0, MAX_COORDINATE).collect::<Vec<_>>();
+    assert_eq!(37449, bins.len());
+    assert_eq!(Some(&37448), bins.last());
+  }
+
+  #[test]
+  fn test_parse_region() {
+    assert_eq!(Ok(("chr1", 0, MAX_COORDINATE)), parse_region("chr1"));
+    assert_eq!(Ok(("chr1", 99, MAX_COORDINATE)), parse_region("chr1:100"));
+    assert_eq!(
+      Ok(("chr7", 139_999_999, 141_000_000)),
+      parse_region("chr7:140,000,000-141,000,000")
+    );
+    assert_eq!(Ok(("HLA-A*01:01", 0, 10)), parse_region("HLA-A*01:01:1-10"));
+    for region in ["", ":1-10", "chr1:x", "chr1:10-5", "chr1:1-"] {
+      assert_eq!(
+        Err(TabixError::InvalidRegion {
+          region: region.to_string()
+        }),
+        parse_region(region)
+      );
+    }
+  }
+
+  #[test]
+  fn test_coordinates() {
+    let vcf = TabixHeader {
+      format: FORMAT_VCF,
+      sequence_column: 1,
+      begin_column: 2,
+      end_column: 0,
+      meta: b'#',
+      skip: 0,
+    };
+    assert_eq!(
+      Some(("chr1", 9, 12)),
+      coordinates(&vcf, "chr1\t10\t.\tACG\tA\t.\t.\t.")
+    );
+    assert_eq!(
+      Some(("chr1", 9, 500)),
+      coordinates(&vcf, "chr1\t10\t.\tN\t<DEL>\t.\t.\tSVTYPE=DEL;END=500")
+    );
+    assert_eq!(None, coordinates(&vcf, "chr1\tten\t.\tA\tC\t.\t.\t."));
+    assert_eq!(None, coordinates(&vcf, "chr1\t10"));
+
+    let bed = TabixHeader {
+      format: FORMAT_GENERIC | FLAG_UCSC,
+      begin_column: 2,
+      end_column: 3,
+      ..vcf
+    };
+    assert_eq!(
+      Some(("chr2", 10, 20)),
+      coordinates(&bed, "chr2\t10\t20\tname")
+    );
+    let gff = TabixHeader {
+      format: FORMAT_GENERIC,
+      begin_column: 4,
+      end_column: 5,
+      ..vcf
+    };
+    assert_eq!(
+      Some(("chr3", 9, 20)),
+      coordinates(&gff, "chr3\tsource\tgene\t10\t20\t.\t+\t.\t.")
+    );
+    let sam = TabixHeader {
+      format: FORMAT_SAM,
+      sequence_column: 3,
+      begin_column: 4,
+      ..vcf
+    };
+    assert_eq!(
+      Some(("chr1", 99, 112)),
+      coordinates(
+        &sam,
+        "read\t0\tchr1\t100\t60\t2S5M3I4D2N2M\t*\t0\t0\tACGT\t*"
+      )
+    );
+    assert_eq!(
+      Some(("chr1", 99, 100)),
+      coordinates(&sam, "read\t4\tchr1\t100\t0\t*\t*\t0\t0\tACGT\t*")
+    );
+  }
+}
@@ -0,0 +1,561 @@
+use crate::ends_with_eof_marker;
+use crate::floor_block;
+use crate::read_block;
+use crate::scan_block_chain;
+use crate::source::Source;
+use crate::writer::EOF_MARKER;
+use crate::BgzfError;
+use crate::BgzfReader;
+use crate::BgzfWriter;
+use positioned_io::ReadAt;
+use std::cmp::min;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Summary of a `transcode` call
+///
+/// Fields description:
+///
+/// uncompressed_bytes: number of uncompressed bytes moved to the writer,
+/// source_compressed_bytes: total size of the source blocks,
+/// output_compressed_bytes: total size of the blocks written by the writer,
+/// source_blocks: number of blocks read from the source,
+/// output_blocks: number of blocks written by the writer,
+///
+/// Neither compressed size counts the EOF marker.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TranscodeStats {
+  pub uncompressed_bytes: u64,
+  pub source_compressed_bytes: u64,
+  pub output_compressed_bytes: u64,
+  pub source_blocks: u64,
+  pub output_blocks: u64,
+}
+
+/// This function recompresses all the data of `reader` into `writer`, one
+/// source block at a time, so only a couple of blocks are held in memory.
+/// The writer uses its own compression level and block size, and its last
+/// block is flushed at the end so the output sizes are complete, but the
+/// writer is not finished.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{transcode, BgzfReader, BgzfWriter};
+///
+/// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+/// let mut writer = BgzfWriter::builder()
+///   .compression_level(12)
+///   .build(Vec::new())
+///   .unwrap();
+/// let stats = transcode(&reader, &mut writer).unwrap();
+/// assert_eq!(280, stats.uncompressed_bytes);
+/// assert_eq!(211, stats.source_compressed_bytes);
+/// writer.finish().unwrap();
+/// ```
+pub fn transcode<W: Write>(
+  reader: &BgzfReader,
+  writer: &mut BgzfWriter<W>,
+) -> Result<TranscodeStats, BgzfError> {
+  let mut stats = TranscodeStats::default();
+  let compressed_position = writer.flush_block()? >> 16;
+  let blocks_written = writer.blocks_written;
+
+  for block in reader.blocks().iter() {
+    let uncompressed = reader.decompress_block(block)?;
+    writer.write_all(&uncompressed)?;
+    stats.uncompressed_bytes += uncompressed.len() as u64;
+    stats.source_compressed_bytes += u64::from(block.block_size);
+    stats.source_blocks += 1;
+  }
+
+  stats.output_compressed_bytes = (writer.flush_block()? >> 16) - compressed_position;
+  stats.output_blocks = writer.blocks_written - blocks_written;
+  Ok(stats)
+}
+
+/// Block counts and sizes on one side of a `defragment` call
+///
+/// Fields description:
+///
+/// blocks: number of data blocks,
+/// compressed_bytes: total size of the data blocks, without the EOF marker,
+/// uncompressed_bytes: total uncompressed length of the data blocks,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockStats {
+  pub blocks: u64,
+  pub compressed_bytes: u64,
+  pub uncompressed_bytes: u64,
+}
+
+impl BlockStats {
+  /// Average size of a block in the file, 0 when there is no block
+  pub fn average_block_size(&self) -> u64 {
+    self.compressed_bytes.checked_div(self.blocks).unwrap_or(0)
+  }
+
+  /// Average number of uncompressed bytes in a block, 0 when there is no block
+  pub fn average_uncompressed_length(&self) -> u64 {
+    self
+      .uncompressed_bytes
+      .checked_div(self.blocks)
+      .unwrap_or(0)
+  }
+}
+
+/// Summary of a `defragment` call, the blocks of the source before and the
+/// blocks written for them after
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DefragmentStats {
+  pub before: BlockStats,
+  pub after: BlockStats,
+}
+
+/// This function rewrites the data of `reader` into `writer` in blocks of
+/// `target_block_size` uncompressed bytes (at most 65280), merging the many
+/// tiny blocks left by producers that flush after every record. It works like
+/// `transcode`, the writer goes back to its own block size afterwards and is
+/// not finished.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+/// use bgzf_rust_reader::{defragment, BgzfReader, BgzfWriter};
+///
+/// let path = write_temp("defragment_doc", &make_bgzf(&[b'a'; 1000], 10)).unwrap();
+/// let reader = BgzfReader::new(path.clone()).unwrap();
+/// let mut writer = BgzfWriter::new(Vec::new());
+/// let stats = defragment(&reader, &mut writer, 65280).unwrap();
+/// assert_eq!(100, stats.before.blocks);
+/// assert_eq!(1, stats.after.blocks);
+/// writer.finish().unwrap();
+/// std::fs::remove_file(path).unwrap();
+/// ```
+pub fn defragment<W: Write>(
+  reader: &BgzfReader,
+  writer: &mut BgzfWriter<W>,
+  target_block_size: usize,
+) -> Result<DefragmentStats, BgzfError> {
+  writer.block_format.check_block_size(target_block_size)?;
+  writer.flush_block()?;
+  let block_size = writer.block_size;
+  writer.block_size = target_block_size;
+  let result = transcode(reader, writer);
+  writer.block_size = block_size;
+  let stats = result?;
+  Ok(DefragmentStats {
+    before: BlockStats {
+      blocks: stats.source_blocks,
+      compressed_bytes: stats.source_compressed_bytes,
+      uncompressed_bytes: stats.uncompressed_bytes,
+    },
+    after: BlockStats {
+      blocks: stats.output_blocks,
+      compressed_bytes: stats.output_compressed_bytes,
+      uncompressed_bytes: stats.uncompressed_bytes,
+    },
+  })
+}
+
+/// This function concatenates bgzf files into `output` without recompressing
+/// them: the data blocks of every input are copied as they are, in the given
+/// order, and a single EOF marker is written at the very end. It returns the
+/// number of bytes written.
+///
+/// Every input is checked to be a valid chain of blocks before anything of
+/// it is copied. Inputs without an EOF marker are accepted since their data
+/// blocks are complete, and empty (zero-length) inputs add nothing.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::cat;
+///
+/// let mut output = Vec::new();
+/// let input = String::from("bgzf_test.bgz");
+/// let written = cat(&[input.clone(), input], &mut output).unwrap();
+/// assert_eq!(2 * 211 + 28, written);
+/// ```
+pub fn cat<W: Write>(input_paths: &[String], output: W) -> io::Result<u64> {
+  let mut writer = BgzfWriter::new(output);
+  let mut raw = Vec::new();
+  for input_path in input_paths {
+    let input = Source::File(File::open(input_path)?);
+    let block_chain = scan_block_chain(&input)
+      .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input_path, e)))?;
+    let mut position = 0;
+    while position < block_chain.data_end {
+      let block = read_block(&input, position)?;
+      raw.resize(block.block_size as usize, 0);
+      input.read_exact_at(position, &mut raw)?;
+      writer.write_raw_block(&raw)?;
+      position += u64::from(block.block_size);
+    }
+  }
+  writer.try_finish()?;
+  Ok(writer.stats().compressed_bytes)
+}
+
+/// Returns true when the file at `file_path` ends with the 28 byte BGZF EOF
+/// marker. Only the tail of the file is read, the blocks are not checked.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::has_eof_marker;
+///
+/// assert!(has_eof_marker(String::from("bgzf_test.bgz")).unwrap());
+/// ```
+pub fn has_eof_marker(file_path: String) -> io::Result<bool> {
+  ends_with_eof_marker(&Source::File(File::open(file_path)?))
+}
+
+/// Appends the EOF marker to the file at `file_path` if it is missing, and
+/// returns whether the file was changed. Every block is checked first, a file
+/// whose last block is cut short or damaged is truncated rather than just
+/// missing its marker, so it is left alone and an `InvalidData` error is
+/// returned. Calling it on a repaired file does nothing.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{has_eof_marker, repair_eof};
+/// use bgzf_rust_reader::fixtures::{make_without_eof, write_temp};
+///
+/// let path = write_temp("repair_eof_doc", &make_without_eof(b"bgzf", 4)).unwrap();
+/// assert!(!has_eof_marker(path.clone()).unwrap());
+/// assert!(repair_eof(path.clone()).unwrap());
+/// assert!(has_eof_marker(path.clone()).unwrap());
+/// assert!(!repair_eof(path.clone()).unwrap());
+/// std::fs::remove_file(path).unwrap();
+/// ```
+pub fn repair_eof(file_path: String) -> io::Result<bool> {
+  let mut file = OpenOptions::new().read(true).write(true).open(file_path)?;
+  let block_chain = scan_block_chain(&Source::File(file.try_clone()?))?;
+  if block_chain.has_eof_marker {
+    return Ok(false);
+  }
+  file.seek(SeekFrom::Start(block_chain.data_end))?;
+  file.write_all(&EOF_MARKER)?;
+  file.sync_all()?;
+  Ok(true)
+}
+
+/// Summary of a `slice_blocks` call
+///
+/// Fields description:
+///
+/// leading_bytes: uncompressed bytes before the requested range in the slice,
+/// trailing_bytes: uncompressed bytes after the requested range in the slice,
+/// blocks: number of blocks copied,
+/// compressed_bytes: number of bytes written, including the EOF marker,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SliceInfo {
+  pub leading_bytes: u64,
+  pub trailing_bytes: u64,
+  pub blocks: u64,
+  pub compressed_bytes: u64,
+}
+
+/// This function writes to `out` a bgzf file made of the blocks of `reader`
+/// covering the uncompressed range `ustart..uend`, copied without any
+/// de-compression, followed by the EOF marker. Since whole blocks are copied
+/// the slice starts `leading_bytes` before the requested range and ends
+/// `trailing_bytes` after it. `uend` is capped to the uncompressed length and
+/// an empty range writes just the EOF marker.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{slice_blocks, BgzfReader};
+///
+/// let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+/// let mut slice = Vec::new();
+/// let info = slice_blocks(&reader, 29, 49, &mut slice).unwrap();
+/// assert_eq!(29, info.leading_bytes);
+/// assert_eq!(280 - 49, info.trailing_bytes);
+/// assert_eq!(1, info.blocks);
+/// ```
+pub fn slice_blocks<W: Write>(
+  reader: &BgzfReader,
+  ustart: u64,
+  uend: u64,
+  out: &mut W,
+) -> Result<SliceInfo, BgzfError> {
+  let mut info = SliceInfo::default();
+  let uend = min(uend, reader.total_uncompressed_length());
+  let mut slice_blocks = 0..0;
+  if ustart < uend {
+    let blocks = reader.blocks();
+    let first_block = floor_block(&blocks, ustart).unwrap_or(0);
+    slice_blocks = first_block..first_block;
+    for block in blocks[first_block..]
+      .iter()
+      .take_while(|block| block.input_offset < uend)
+    {
+      if info.blocks == 0 {
+        info.leading_bytes = ustart - block.input_offset;
+      }
+      info.trailing_bytes =
+        (block.input_offset + u64::from(block.input_length)).saturating_sub(uend);
+      slice_blocks.end += 1;
+      info.blocks += 1;
+    }
+  }
+  let mut writer = BgzfWriter::new(out);
+  for block_index in slice_blocks {
+    writer.write_raw_block(&reader.raw_block(block_index)?)?;
+  }
+  writer.try_finish()?;
+  info.compressed_bytes = writer.stats().compressed_bytes;
+  Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures;
+  use crate::writer::MAX_BLOCK_INPUT_SIZE;
+  use std::fs;
+
+  fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+      "bgzf_rust_reader_tools_{}_{}.bgz",
+      name,
+      std::process::id()
+    ));
+    path.to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn test_transcode_round_trip() {
+    let mut content = Vec::new();
+    for i in 0..20_000 {
+      content.extend_from_slice(
+        format!("{}\tchr{}\tsome fairly repetitive text\n", i, i % 23).as_bytes(),
+      );
+    }
+    let source_path = temp_path("transcode_source");
+    let mut source = BgzfWriter::builder()
+      .compression_level(1)
+      .create(source_path.clone())
+      .unwrap();
+    source.write_all(&content).unwrap();
+    source.finish().unwrap();
+
+    let output_path = temp_path("transcode_output");
+    let reader = BgzfReader::new(source_path.clone()).unwrap();
+    let mut writer = BgzfWriter::builder()
+      .compression_level(9)
+      .block_size(60_000)
+      .create(output_path.clone())
+      .unwrap();
+    let stats = transcode(&reader, &mut writer).unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(content.len() as u64, stats.uncompressed_bytes);
+    assert_eq!(reader.blocks().len() as u64, stats.source_blocks);
+    assert_eq!(content.len().div_ceil(60_000) as u64, stats.output_blocks);
+    assert!(stats.output_compressed_bytes < stats.source_compressed_bytes);
+    assert_eq!(
+      fs::metadata(&output_path).unwrap().len(),
+      stats.output_compressed_bytes + 28
+    );
+
+    let transcoded = BgzfReader::new(output_path.clone()).unwrap();
+    assert_eq!(content.len() as u64, transcoded.total_uncompressed_length());
+    let mut transcoded_content = Vec::new();
+    for block in transcoded.blocks().iter() {
+      transcoded_content.extend(transcoded.decompress_block(block).unwrap());
+    }
+    assert_eq!(content, transcoded_content);
+
+    fs::remove_file(source_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+  }
+
+  fn write_bgzf(path: &str, content: &[u8]) {
+    let mut writer = BgzfWriter::create(path.to_string()).unwrap();
+    writer.write_all(content).unwrap();
+    writer.finish().unwrap();
+  }
+
+  #[test]
+  fn test_defragment_many_small_blocks() {
+    let mut content = Vec::new();
+    for i in 0..3000 {
+      content.extend_from_slice(format!("record {}\tchr{}\n", i, i % 5).as_bytes());
+    }
+    //one block per record, like a producer flushing after each of them
+    let mut source = BgzfWriter::new(Vec::new());
+    for record in content.split_inclusive(|&byte| byte == b'\n') {
+      source.write_all(record).unwrap();
+      source.flush_block().unwrap();
+    }
+    let source_path = fixtures::write_temp("defragment_source", &source.finish().unwrap()).unwrap();
+
+    let reader = BgzfReader::new(source_path.clone()).unwrap();
+    let mut writer = BgzfWriter::builder()
+      .block_size(1000)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(b"header\n").unwrap();
+    let stats = defragment(&reader, &mut writer, 20_000).unwrap();
+    writer.write_all(b"trailer\n").unwrap();
+    let output = writer.finish().unwrap();
+
+    assert_eq!(3000, stats.before.blocks);
+    assert_eq!(content.len().div_ceil(20_000) as u64, stats.after.blocks);
+    assert_eq!(content.len() as u64, stats.before.uncompressed_bytes);
+    assert_eq!(content.len() as u64, stats.after.uncompressed_bytes);
+    assert!(stats.after.compressed_bytes * 10 < stats.before.compressed_bytes);
+    assert!(stats.after.average_block_size() > stats.before.average_block_size());
+
+    let output_path = fixtures::write_temp("defragment_output", &output).unwrap();
+    let defragmented = BgzfReader::new(output_path.clone()).unwrap();
+    let blocks = defragmented.block_offsets().collect::<Vec<_>>();
+    //header and trailer in blocks of their own, everything else re-blocked
+    assert_eq!(stats.after.blocks + 2, blocks.len() as u64);
+    assert_eq!(20_000, blocks[1].uncompressed_length);
+    let mut defragmented_content = Vec::new();
+    for block in defragmented.blocks().iter() {
+      defragmented_content.extend(defragmented.decompress_block(block).unwrap());
+    }
+    let mut expected = b"header\n".to_vec();
+    expected.extend_from_slice(&content);
+    expected.extend_from_slice(b"trailer\n");
+    assert_eq!(expected, defragmented_content);
+
+    fs::remove_file(source_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+  }
+
+  #[test]
+  fn test_defragment_invalid_block_size() {
+    let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+    let mut writer = BgzfWriter::new(Vec::new());
+    assert!(defragment(&reader, &mut writer, 0).is_err());
+    assert!(defragment(&reader, &mut writer, MAX_BLOCK_INPUT_SIZE + 1).is_err());
+    assert_eq!(EOF_MARKER.len(), writer.finish().unwrap().len());
+  }
+
+  #[test]
+  fn test_cat_inputs() {
+    let first_path = temp_path("cat_first");
+    let second_path = temp_path("cat_second");
+    let empty_path = temp_path("cat_empty");
+    let output_path = temp_path("cat_output");
+    write_bgzf(&first_path, &vec![b'a'; 70_000]);
+    write_bgzf(&second_path, b"second file");
+    fs::write(&empty_path, b"").unwrap();
+    //second input without its EOF marker
+    let second = fs::read(&second_path).unwrap();
+    fs::write(&second_path, &second[..second.len() - 28]).unwrap();
+
+    let inputs = [first_path.clone(), empty_path.clone(), second_path.clone()];
+    let written = cat(&inputs, File::create(&output_path).unwrap()).unwrap();
+    assert_eq!(fs::metadata(&output_path).unwrap().len(), written);
+
+    let reader = BgzfReader::new(output_path.clone()).unwrap();
+    assert_eq!(70_011, reader.total_uncompressed_length());
+    assert_eq!(3, reader.blocks().len());
+    reader.seek(70_000);
+    let mut content = vec![0; 11];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(b"second file", &content[..]);
+
+    for path in &[first_path, second_path, empty_path, output_path] {
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_cat_rejects_invalid_input() {
+    let invalid_path = temp_path("cat_invalid");
+    fs::write(&invalid_path, b"not a bgzf file").unwrap();
+    let mut output = Vec::new();
+    let inputs = [String::from("bgzf_test.bgz"), invalid_path.clone()];
+    match cat(&inputs, &mut output) {
+      Ok(_written) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    fs::remove_file(invalid_path).unwrap();
+  }
+
+  #[test]
+  fn test_repair_eof() {
+    let content = b"This is just a bgzf test,lets see how it reacts. :).".repeat(20);
+    let complete = fixtures::make_bgzf(&content, 100);
+    let path =
+      fixtures::write_temp("repair_eof", &fixtures::make_without_eof(&content, 100)).unwrap();
+    assert!(!has_eof_marker(path.clone()).unwrap());
+    assert!(repair_eof(path.clone()).unwrap());
+    assert_eq!(complete, fs::read(&path).unwrap());
+    //a second call finds the marker and changes nothing
+    assert!(!repair_eof(path.clone()).unwrap());
+    assert_eq!(complete, fs::read(&path).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_repair_eof_refuses_truncated_file() {
+    let content = b"This is just a bgzf test,lets see how it reacts. :).".repeat(20);
+    let truncated = fixtures::make_truncated(&content, 100, 10);
+    let path = fixtures::write_temp("repair_truncated", &truncated).unwrap();
+    match repair_eof(path.clone()) {
+      Ok(_changed) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    assert_eq!(truncated, fs::read(&path).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_has_eof_marker_short_file() {
+    let path = temp_path("short");
+    fs::write(&path, b"bgzf").unwrap();
+    assert!(!has_eof_marker(path.clone()).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_slice_blocks() {
+    let mut content = Vec::new();
+    for i in 0..30_000 {
+      content.extend_from_slice(format!("line {}\n", i).as_bytes());
+    }
+    let source_path = temp_path("slice_source");
+    let slice_path = temp_path("slice_output");
+    let mut writer = BgzfWriter::builder()
+      .block_size(10_000)
+      .create(source_path.clone())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    writer.finish().unwrap();
+
+    let reader = BgzfReader::new(source_path.clone()).unwrap();
+    let mut slice = Vec::new();
+    let info = slice_blocks(&reader, 25_000, 41_000, &mut slice).unwrap();
+    assert_eq!(
+      SliceInfo {
+        leading_bytes: 5_000,
+        trailing_bytes: 9_000,
+        blocks: 3,
+        compressed_bytes: slice.len() as u64,
+      },
+      info
+    );
+    assert_eq!(&EOF_MARKER, &slice[slice.len() - 28..]);
+
+    fs::write(&slice_path, &slice).unwrap();
+    let sliced = BgzfReader::new(slice_path.clone()).unwrap();
+    assert_eq!(30_000, sliced.total_uncompressed_length());
+    sliced.seek(info.leading_bytes);
+    let mut requested = vec![0; 5_000];
+    sliced.read_to(&mut requested).unwrap();
+    assert_eq!(&content[25_000..30_000], &requested[..]);
+
+    let mut empty = Vec::new();
+    let info = slice_blocks(&reader, 41_000, 41_000, &mut empty).unwrap();
+    assert_eq!(0, info.blocks);
+    assert_eq!(EOF_MARKER.to_vec(), empty);
+
+    fs::remove_file(source_path).unwrap();
+    fs::remove_file(slice_path).unwrap();
+  }
+}
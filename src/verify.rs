@@ -0,0 +1,185 @@
+//! Integrity check of a whole file, see `BgzfReader::verify`.
+
+use crate::decompressor_pool::DecompressorPool;
+use crate::executor::Executor;
+use crate::{decompress_block_at, BgzfBlock, BgzfError, Truncated};
+use positioned_io::ReadAt;
+
+/// What `BgzfReader::verify` found checking a whole file:
+///
+/// blocks: number of data blocks checked, the EOF marker not included,
+/// compressed_bytes: length of the file,
+/// uncompressed_bytes: total uncompressed length of the blocks checked,
+/// failures: error of every block that could not be read, decompressed to
+/// the length in its footer or whose data does not match the CRC32 in its
+/// footer, in file order, `BgzfError::block_context` telling which block,
+/// has_eof_marker: whether the file ends with the EOF marker,
+/// truncation: the block the blocks stop at, when the file is cut off or
+/// damaged,
+/// trailing_bytes: bytes of the file after the last data block other than
+/// the EOF marker ending it,
+#[derive(Debug)]
+pub struct VerifyReport {
+  pub blocks: u64,
+  pub compressed_bytes: u64,
+  pub uncompressed_bytes: u64,
+  pub failures: Vec<BgzfError>,
+  pub has_eof_marker: bool,
+  pub truncation: Option<Truncated>,
+  pub trailing_bytes: u64,
+}
+
+impl VerifyReport {
+  /// Tells whether the file is completely sound: every block checked out,
+  /// they chain from the start of the file to the EOF marker ending it, and
+  /// nothing follows the marker
+  pub fn is_sound(&self) -> bool {
+    self.failures.is_empty()
+      && self.has_eof_marker
+      && self.truncation.is_none()
+      && self.trailing_bytes == 0
+  }
+}
+
+/// Decompresses `blocks` with `threads` tasks of `executor`, each checking a
+/// run of them against their footers, and returns the error of every block
+/// that failed, in file order
+pub(crate) fn check_blocks(
+  source: &(dyn ReadAt + Sync),
+  decompressors: &DecompressorPool,
+  blocks: &[BgzfBlock],
+  executor: &Executor,
+  threads: usize,
+) -> Vec<BgzfError> {
+  let per_thread = blocks.len().div_ceil(threads.max(1)).max(1);
+  let runs = blocks.chunks(per_thread).collect::<Vec<_>>();
+  let results = executor.run(runs.len(), |run| {
+    let (mut block_data, mut uncompressed) = (Vec::new(), Vec::new());
+    let mut failures = Vec::new();
+    for block in runs[run] {
+      uncompressed.resize(block.input_length as usize, 0);
+      let mut un_compressor = decompressors.acquire();
+      if let Err(error) = decompress_block_at(
+        source,
+        &mut un_compressor,
+        block,
+        true,
+        &mut block_data,
+        &mut uncompressed,
+      ) {
+        failures.push(error);
+      }
+    }
+    failures
+  });
+  results
+    .into_iter()
+    .flat_map(|result| result.expect("Verifying thread panicked"))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::fixtures::{
+    make_bgzf, make_truncated, make_with_anomaly, make_with_bad_crc, write_temp,
+  };
+  use crate::{AnomalyKind, BgzfError, BgzfReader, BlockOperation, Truncated};
+  use std::fs;
+
+  fn data() -> Vec<u8> {
+    (0..5000u32).map(|n| (n * 7 % 253) as u8).collect()
+  }
+
+  #[test]
+  fn test_verify_pristine_file() {
+    let bgzf = make_bgzf(&data(), 500);
+    let path = write_temp("verify_pristine", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    for threads in 1..5 {
+      let report = reader.verify(threads).unwrap();
+      assert!(report.is_sound(), "{:?}", report);
+      assert_eq!(10, report.blocks);
+      assert_eq!(bgzf.len() as u64, report.compressed_bytes);
+      assert_eq!(5000, report.uncompressed_bytes);
+    }
+    //lazily, the rest of the file is indexed first
+    let lazy = BgzfReader::builder().lazy(true).open(path.clone()).unwrap();
+    assert!(lazy.verify(2).unwrap().is_sound());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_verify_reports_every_bad_block() {
+    let mut bgzf = make_with_bad_crc(&data(), 500, 2);
+    //and the CRC32 of the eighth block
+    let bad_blocks = make_with_bad_crc(&data(), 500, 7);
+    let good = make_bgzf(&data(), 500);
+    for (index, byte) in bad_blocks.iter().enumerate() {
+      if *byte != good[index] {
+        bgzf[index] = *byte;
+      }
+    }
+    let path = write_temp("verify_bad_crc", &bgzf).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    for threads in [1, 3, 16] {
+      let report = reader.verify(threads).unwrap();
+      assert!(!report.is_sound());
+      assert_eq!(10, report.blocks);
+      assert!(report.has_eof_marker);
+      let failed: Vec<(u64, u64, BlockOperation)> = report
+        .failures
+        .iter()
+        .map(|failure| {
+          let block = failure.block_context().unwrap();
+          (block.index, block.uncompressed_offset, block.operation)
+        })
+        .collect();
+      assert_eq!(
+        vec![
+          (2, 1000, BlockOperation::CheckingCrc),
+          (7, 3500, BlockOperation::CheckingCrc)
+        ],
+        failed
+      );
+      assert!(matches!(
+        report.failures[0],
+        BgzfError::ChecksumMismatch { .. }
+      ));
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_verify_truncated_file() {
+    let bgzf = make_truncated(&data(), 500, 100);
+    let path = write_temp("verify_truncated", &bgzf).unwrap();
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(path.clone())
+      .unwrap();
+    let report = reader.verify(2).unwrap();
+    assert!(!report.is_sound());
+    assert!(report.failures.is_empty());
+    assert!(!report.has_eof_marker);
+    assert_eq!(9, report.blocks);
+    let data_end = make_bgzf(&data()[..4500], 500).len() as u64 - 28;
+    assert_eq!(
+      Some(Truncated {
+        block_offset: data_end
+      }),
+      report.truncation
+    );
+    assert_eq!(bgzf.len() as u64 - data_end, report.trailing_bytes);
+    fs::remove_file(path).unwrap();
+
+    //data after the EOF marker
+    let bgzf = make_with_anomaly(&data(), 500, AnomalyKind::TrailingData);
+    let path = write_temp("verify_trailing", &bgzf).unwrap();
+    let report = BgzfReader::new(path.clone()).unwrap().verify(1).unwrap();
+    assert!(!report.is_sound());
+    assert!(report.failures.is_empty());
+    assert_eq!(None, report.truncation);
+    assert_eq!(28 + 14, report.trailing_bytes);
+    fs::remove_file(path).unwrap();
+  }
+}
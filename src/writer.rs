@@ -0,0 +1,2440 @@
+#[cfg(feature = "async-tokio")]
+use crate::async_writer::AsyncBgzfWriter;
+use crate::backend::{crc32, Compressor, Deflate, DeflateLevel};
+use crate::compression_pool::CompressionPool;
+use crate::executor::Executor;
+use crate::gzi::write_gzi;
+use crate::source::Source;
+use crate::BgzfReader;
+use crate::ExtraSubfield;
+use crate::{read_block, scan_block_chain};
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum number of uncompressed bytes stored in one block, same as htslib
+pub(crate) const MAX_BLOCK_INPUT_SIZE: usize = 0xff00;
+
+/// Maximum size of a whole block (header + compressed data + footer)
+pub(crate) const MAX_BLOCK_SIZE: usize = 0x10000;
+
+pub(crate) const BLOCK_HEADER_LENGTH: usize = 18;
+
+pub(crate) const BLOCK_FOOTER_LENGTH: usize = 8;
+
+/// Empty block written at the end of every BGZF file
+pub(crate) const EOF_MARKER: [u8; 28] = [
+  0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+  0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Compression level used when none is given, matches bgzip's default (zlib level 6)
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 6;
+
+/// Struct to write bgzf data to any `std::io::Write` sink
+///
+/// Fields description:
+///
+/// inner: sink the compressed blocks are written to, taken out by finish,
+/// block_format: compression level and extra subfields of every block,
+/// block_size: number of uncompressed bytes put in each block,
+/// uncompressed_data: data buffered for the block currently being filled,
+/// compressor: deflate state used when compressing inline,
+/// compressed_block: output buffer of the inline compression, reused for every block,
+/// compressed_position: number of compressed bytes written to the sink,
+/// uncompressed_position: number of uncompressed bytes in the written blocks,
+/// queued_uncompressed_length: number of uncompressed bytes in the pool's blocks,
+/// blocks_written: number of data blocks written to the sink,
+/// gzi_path: where to save the .gzi index once finished, None for no index,
+/// block_offsets: (compressed, uncompressed) start of every block, kept for the index,
+/// pool: worker threads compressing the blocks, None when compressing inline,
+/// stats: totals of the blocks written by this writer,
+/// on_block: callback told of every block written, see `on_block`,
+/// finished: true once the EOF marker is written or finishing returned an error,
+pub struct BgzfWriter<W: Write> {
+  inner: Option<W>,
+  pub(crate) block_format: BlockFormat,
+  pub(crate) block_size: usize,
+  uncompressed_data: Vec<u8>,
+  compressor: BlockCompressor,
+  compressed_block: Vec<u8>,
+  pub(crate) compressed_position: u64,
+  uncompressed_position: u64,
+  queued_uncompressed_length: u64,
+  pub(crate) blocks_written: u64,
+  gzi_path: Option<String>,
+  block_offsets: Vec<(u64, u64)>,
+  pool: Option<CompressionPool>,
+  stats: WriterStats,
+  on_block: Option<Box<dyn FnMut(BlockFlushInfo) + Send>>,
+  finished: bool,
+}
+
+/// Summary of a `compress_from_reader` call
+///
+/// Fields description:
+///
+/// bytes_in: number of uncompressed bytes read from the source,
+/// bytes_out: number of compressed bytes written, including the EOF marker,
+/// blocks_written: number of data blocks written, not counting the EOF marker,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressStats {
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  pub blocks_written: u64,
+}
+
+/// Where a block the writer just wrote is, in the output and in the data,
+/// given to the callback of `BgzfWriter::on_block`:
+///
+/// compressed_offset: offset of the block header in the output,
+/// block_size: length of the whole block in the output (header and footer
+/// included),
+/// uncompressed_offset: offset of the block's first byte in the uncompressed
+/// data,
+/// uncompressed_length: number of uncompressed bytes in the block,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockFlushInfo {
+  pub compressed_offset: u64,
+  pub block_size: u32,
+  pub uncompressed_offset: u64,
+  pub uncompressed_length: u32,
+}
+
+/// Totals of what a writer produced, returned by `BgzfWriter::stats`. Only
+/// the blocks written by this writer are counted, not the ones already in a
+/// file it appends to.
+///
+/// Fields description:
+///
+/// uncompressed_bytes: number of uncompressed bytes in the written blocks,
+/// compressed_bytes: number of bytes written to the sink, headers, footers
+/// and the EOF marker included,
+/// blocks: number of data blocks written, not counting the EOF marker,
+/// min_block_size: size of the smallest data block, 0 when there is none,
+/// max_block_size: size of the largest data block, 0 when there is none,
+/// stored_blocks: number of data blocks holding their data uncompressed,
+/// eof_marker: true once the EOF marker is written,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriterStats {
+  pub uncompressed_bytes: u64,
+  pub compressed_bytes: u64,
+  pub blocks: u64,
+  pub min_block_size: u64,
+  pub max_block_size: u64,
+  pub stored_blocks: u64,
+  pub eof_marker: bool,
+}
+
+impl WriterStats {
+  /// Average size of a data block, 0 when there is no block
+  pub fn average_block_size(&self) -> u64 {
+    self
+      .data_block_bytes()
+      .checked_div(self.blocks)
+      .unwrap_or(0)
+  }
+
+  /// Number of uncompressed bytes per compressed byte written, 0 when nothing
+  /// was written
+  pub fn compression_ratio(&self) -> f64 {
+    if self.compressed_bytes == 0 {
+      return 0.0;
+    }
+    self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+  }
+
+  fn data_block_bytes(&self) -> u64 {
+    if self.eof_marker {
+      self.compressed_bytes - EOF_MARKER.len() as u64
+    } else {
+      self.compressed_bytes
+    }
+  }
+
+  fn add_block(&mut self, block_size: u64, uncompressed_length: u64, stored: bool) {
+    if self.blocks == 0 || block_size < self.min_block_size {
+      self.min_block_size = block_size;
+    }
+    self.max_block_size = max(self.max_block_size, block_size);
+    self.blocks += 1;
+    self.compressed_bytes += block_size;
+    self.uncompressed_bytes += uncompressed_length;
+    if stored {
+      self.stored_blocks += 1;
+    }
+  }
+}
+
+/// Builder to configure a bgzf Writer before creating it
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::BgzfWriter;
+/// use std::io::Write;
+///
+/// let mut writer = BgzfWriter::builder()
+///   .compression_level(1)
+///   .threads(4)
+///   .build(Vec::new())
+///   .unwrap();
+/// writer.write_all(b"This is just a bgzf test").unwrap();
+/// let compressed = writer.finish().unwrap();
+/// assert_eq!(&[31, 139, 8, 4], &compressed[..4]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BgzfWriterBuilder {
+  compression_level: i32,
+  block_size: usize,
+  threads: usize,
+  allow_missing_eof_marker: bool,
+  gzi_path: Option<String>,
+  extra_subfields: Vec<ExtraSubfield>,
+  deterministic: bool,
+  executor: Executor,
+  #[cfg(feature = "async-tokio")]
+  spawn_blocking: bool,
+}
+
+impl Default for BgzfWriterBuilder {
+  fn default() -> BgzfWriterBuilder {
+    BgzfWriterBuilder {
+      compression_level: DEFAULT_COMPRESSION_LEVEL,
+      block_size: MAX_BLOCK_INPUT_SIZE,
+      threads: 1,
+      allow_missing_eof_marker: false,
+      gzi_path: None,
+      extra_subfields: Vec::new(),
+      deterministic: true,
+      executor: Executor::default(),
+      #[cfg(feature = "async-tokio")]
+      spawn_blocking: false,
+    }
+  }
+}
+
+impl BgzfWriterBuilder {
+  /// Sets the compression level, 0 (no compression) up to 12 (best). At level
+  /// 0 every block is written in deflate stored mode, useful when the data is
+  /// already compressed or encrypted. At the other levels a block is stored
+  /// too whenever deflate would make it larger than the raw data.
+  pub fn compression_level(mut self, level: i32) -> BgzfWriterBuilder {
+    self.compression_level = level;
+    self
+  }
+
+  /// Sets the number of uncompressed bytes put in each block, between 1 and
+  /// the BGZF limit of 65280 bytes which is also the default
+  pub fn block_size(mut self, block_size: usize) -> BgzfWriterBuilder {
+    self.block_size = block_size;
+    self
+  }
+
+  /// Sets the number of threads compressing blocks, with more than one thread
+  /// full blocks are compressed by a pool of workers and written in order.
+  /// The default is 1, compressing on the writing thread.
+  pub fn threads(mut self, threads: usize) -> BgzfWriterBuilder {
+    self.threads = threads;
+    self
+  }
+
+  /// Compresses the blocks on the threads of `pool` rather than on `threads`
+  /// threads of the writer's own, `threads` still telling how many blocks
+  /// are compressed at once, and the pool running as many as it has threads.
+  /// Writing from a thread of the pool, the writer runs blocks waiting in the
+  /// pool while it waits for the next one to write. Only used with more than
+  /// one thread.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  /// use std::io::Write;
+  /// use std::sync::Arc;
+  ///
+  /// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+  /// let mut writer = BgzfWriter::builder()
+  ///   .threads(4)
+  ///   .thread_pool(Arc::new(pool))
+  ///   .build(Vec::new())
+  ///   .unwrap();
+  /// writer.write_all(b"This is just a bgzf test").unwrap();
+  /// let compressed = writer.finish().unwrap();
+  /// assert_eq!(&[31, 139, 8, 4], &compressed[..4]);
+  /// ```
+  #[cfg(feature = "rayon")]
+  pub fn thread_pool(mut self, pool: std::sync::Arc<rayon::ThreadPool>) -> BgzfWriterBuilder {
+    self.executor = Executor::Pool(pool);
+    self
+  }
+
+  /// When appending, accept an existing file that does not end with the EOF
+  /// marker instead of returning an error. Off by default since a missing
+  /// marker usually means the file was truncated.
+  pub fn allow_missing_eof_marker(mut self, allow: bool) -> BgzfWriterBuilder {
+    self.allow_missing_eof_marker = allow;
+    self
+  }
+
+  /// Makes the writer save a .gzi index of its blocks to `gzi_path` when it
+  /// is finished, in the same format as `bgzip -r`. When appending, the index
+  /// covers the blocks already in the file too.
+  pub fn gzi(mut self, gzi_path: String) -> BgzfWriterBuilder {
+    self.gzi_path = Some(gzi_path);
+    self
+  }
+
+  /// Adds a subfield to the gzip extra field of every data block, after the
+  /// BC subfield holding the block size. It can be called several times, the
+  /// subfields are written in the same order. The header of a block can not
+  /// grow past 64 KiB with the data, so creating the writer fails when the
+  /// subfields leave no room for blocks of the chosen block size.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  ///
+  /// let writer = BgzfWriter::builder()
+  ///   .extra_subfield(*b"PV", b"run-42".to_vec())
+  ///   .build(Vec::new())
+  ///   .unwrap();
+  /// writer.finish().unwrap();
+  /// ```
+  pub fn extra_subfield(mut self, id: [u8; 2], data: Vec<u8>) -> BgzfWriterBuilder {
+    self.extra_subfields.push(ExtraSubfield { id, data });
+    self
+  }
+
+  /// With `true`, the default, the output only depends on the input bytes and
+  /// the options, like bgzip's: every header has MTIME 0, XFL 0 and OS 255,
+  /// and blocks are cut at the same places whatever the number of threads or
+  /// the size of the writes. With `false` the time the writer is created is
+  /// recorded as MTIME, like gzip does.
+  pub fn deterministic(mut self, deterministic: bool) -> BgzfWriterBuilder {
+    self.deterministic = deterministic;
+    self
+  }
+
+  /// Makes the async writer compress full blocks on tokio's blocking thread
+  /// pool with `spawn_blocking` instead of inline in `poll_write`, which keeps
+  /// the runtime's worker threads free at high compression levels. Only used
+  /// by `build_async`.
+  #[cfg(feature = "async-tokio")]
+  pub fn spawn_blocking(mut self, spawn_blocking: bool) -> BgzfWriterBuilder {
+    self.spawn_blocking = spawn_blocking;
+    self
+  }
+
+  /// Returns the writer compressing into `inner`
+  pub fn build<W: Write>(self, inner: W) -> io::Result<BgzfWriter<W>> {
+    self.into_writer(inner, 0, 0, Vec::new())
+  }
+
+  /// Returns an async writer compressing into `inner`. The compression level,
+  /// block size, extra subfields and deterministic options apply, blocks are
+  /// compressed one at a time so `threads` is not used, and neither is the
+  /// .gzi index.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  ///
+  /// let writer = BgzfWriter::builder()
+  ///   .compression_level(9)
+  ///   .spawn_blocking(true)
+  ///   .build_async(Vec::new())
+  ///   .unwrap();
+  /// ```
+  #[cfg(feature = "async-tokio")]
+  pub fn build_async<W: tokio::io::AsyncWrite + Unpin>(
+    self,
+    inner: W,
+  ) -> io::Result<AsyncBgzfWriter<W>> {
+    let block_format = self.block_format()?;
+    block_format.check_block_size(self.block_size)?;
+    Ok(AsyncBgzfWriter::from_parts(
+      inner,
+      block_format,
+      self.block_size,
+      self.spawn_blocking,
+    ))
+  }
+
+  /// Creates (or truncates) the file at `file_path` and returns the writer
+  pub fn create(self, file_path: String) -> io::Result<BgzfWriter<File>> {
+    let bgzf_file = File::create(file_path)?;
+    self.into_writer(bgzf_file, 0, 0, Vec::new())
+  }
+
+  /// Opens the existing bgzf file at `file_path` and returns a writer adding
+  /// new blocks after its last data block. Every block of the file is checked,
+  /// the EOF marker is removed and a fresh one is written by `finish`.
+  pub fn append(self, file_path: String) -> io::Result<BgzfWriter<File>> {
+    let mut bgzf_file = OpenOptions::new().read(true).write(true).open(file_path)?;
+    let block_chain = scan_block_chain(&Source::File(bgzf_file.try_clone()?))?;
+    if !block_chain.has_eof_marker && !self.allow_missing_eof_marker {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "File does not end with the BGZF EOF marker",
+      ));
+    }
+    bgzf_file.set_len(block_chain.data_end)?;
+    bgzf_file.seek(SeekFrom::Start(block_chain.data_end))?;
+    self.into_writer(
+      bgzf_file,
+      block_chain.data_end,
+      block_chain.uncompressed_length,
+      block_chain.block_offsets,
+    )
+  }
+
+  /// Opens the bgzf file at `file_path` left by a writer that did not
+  /// finish, like one killed while writing, and returns a writer adding new
+  /// blocks after its last whole block, with the uncompressed offset writing
+  /// resumes at. The data from that offset on has to be written again. The
+  /// file is indexed as `BgzfReader` does and every block is checked, CRC32
+  /// included, then the block the writer was cut off in, and the EOF marker
+  /// of a file that was finished, are removed. Fails when a block before the
+  /// cut off one is damaged, or when more than a block's worth of bytes
+  /// follows the last whole block, which is damage rather than a cut off
+  /// write. With the .gzi index option, the index covers the blocks kept.
+  pub fn resume(self, file_path: String) -> io::Result<(BgzfWriter<File>, u64)> {
+    let reader = BgzfReader::builder()
+      .allow_truncated(true)
+      .open(file_path.clone())?;
+    let report = reader.verify(self.threads)?;
+    if let Some(failure) = report.failures.into_iter().next() {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, failure));
+    }
+    if let Some(truncation) = report.truncation {
+      if report.compressed_bytes - truncation.block_offset >= MAX_BLOCK_SIZE as u64 {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!(
+            "{} bytes follow the last whole block, more than a cut off block",
+            report.compressed_bytes - truncation.block_offset
+          ),
+        ));
+      }
+    }
+    let blocks = reader.blocks();
+    let data_end = blocks
+      .last()
+      .map_or(0, |block| block.block_offset + u64::from(block.block_size));
+    let block_offsets = blocks
+      .iter()
+      .map(|block| (block.block_offset, block.input_offset))
+      .collect::<Vec<_>>();
+    drop(blocks);
+    let resume_offset = reader.total_uncompressed_length();
+    //the reader may map the file, which is not to be cut under it
+    drop(reader);
+    let mut bgzf_file = OpenOptions::new().write(true).open(file_path)?;
+    bgzf_file.set_len(data_end)?;
+    bgzf_file.seek(SeekFrom::Start(data_end))?;
+    let writer = self.into_writer(bgzf_file, data_end, resume_offset, block_offsets)?;
+    Ok((writer, resume_offset))
+  }
+
+  fn block_format(&self) -> io::Result<BlockFormat> {
+    let mtime = if self.deterministic {
+      0
+    } else {
+      SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() as u32)
+    };
+    Ok(BlockFormat {
+      level: compression_level_from(self.compression_level)?,
+      mtime,
+      extra_subfields: serialize_extra_subfields(&self.extra_subfields)?,
+    })
+  }
+
+  fn into_writer<W: Write>(
+    self,
+    inner: W,
+    compressed_position: u64,
+    uncompressed_position: u64,
+    block_offsets: Vec<(u64, u64)>,
+  ) -> io::Result<BgzfWriter<W>> {
+    let block_format = self.block_format()?;
+    block_format.check_block_size(self.block_size)?;
+    let pool = if self.threads > 1 {
+      Some(CompressionPool::new(
+        self.threads,
+        block_format.clone(),
+        BlockCompressor::compress_to_vec,
+        &self.executor,
+      ))
+    } else {
+      None
+    };
+    let mut compressor = BlockCompressor::new(block_format.clone());
+    let compressed_block = Vec::with_capacity(compressor.max_block_length(self.block_size));
+    Ok(BgzfWriter {
+      inner: Some(inner),
+      block_format,
+      block_size: self.block_size,
+      uncompressed_data: Vec::with_capacity(self.block_size),
+      compressor,
+      compressed_block,
+      compressed_position,
+      uncompressed_position,
+      queued_uncompressed_length: 0,
+      blocks_written: 0,
+      gzi_path: self.gzi_path,
+      block_offsets,
+      pool,
+      stats: WriterStats::default(),
+      on_block: None,
+      finished: false,
+    })
+  }
+}
+
+/// Below are the steps to use the bgzf Writer,
+/// 1st step is to create a BGZF writer instance over any `std::io::Write`
+/// sink with a new function (or `create` for a file path),
+/// after that data can be written with the `std::io::Write` methods
+/// and `finish` must be called to write the last block and the EOF marker.
+///
+/// A writer dropped without being finished tries to finish itself, but any
+/// error doing so is lost (it only triggers a debug assertion), so `finish`
+/// should always be called explicitly.
+///
+/// # Example
+/// ```
+/// use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+/// use std::io::Write;
+///
+/// let path = std::env::temp_dir().join("bgzf_writer_doc_example.bgz");
+/// let path = path.to_str().unwrap().to_string();
+/// let mut writer = BgzfWriter::create(path.clone()).unwrap();
+/// writer.write_all(b"This is just a bgzf test").unwrap();
+/// writer.finish().unwrap();
+///
+/// let reader = BgzfReader::new(path).unwrap();
+/// let mut vec = vec![0; 24];
+/// reader.read_to(&mut vec).unwrap();
+/// assert_eq!(b"This is just a bgzf test", &vec[..]);
+/// ```
+impl BgzfWriter<File> {
+  /// Returns a builder to set options like the compression level and threads,
+  /// the builder can create writers over any sink
+  pub fn builder() -> BgzfWriterBuilder {
+    BgzfWriterBuilder::default()
+  }
+
+  /// Creates (or truncates) the file at `file_path` and writes to it with
+  /// the default compression level
+  pub fn create(file_path: String) -> io::Result<BgzfWriter<File>> {
+    BgzfWriter::builder().create(file_path)
+  }
+
+  /// Opens the existing bgzf file at `file_path` to append new blocks to it,
+  /// the file must be valid BGZF ending with the EOF marker.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+  /// use std::io::Write;
+  ///
+  /// let path = std::env::temp_dir().join("bgzf_append_doc_example.bgz");
+  /// let path = path.to_str().unwrap().to_string();
+  /// let mut writer = BgzfWriter::create(path.clone()).unwrap();
+  /// writer.write_all(b"This is just ").unwrap();
+  /// writer.finish().unwrap();
+  ///
+  /// let mut writer = BgzfWriter::append(path.clone()).unwrap();
+  /// writer.write_all(b"a bgzf test").unwrap();
+  /// writer.finish().unwrap();
+  ///
+  /// let reader = BgzfReader::new(path).unwrap();
+  /// assert_eq!(24, reader.total_uncompressed_length());
+  /// ```
+  pub fn append(file_path: String) -> io::Result<BgzfWriter<File>> {
+    BgzfWriter::builder().append(file_path)
+  }
+
+  /// Opens the bgzf file at `file_path` left by a writer that did not
+  /// finish to write on after its last whole block, returning the writer and
+  /// the uncompressed offset to write the data again from, see
+  /// `BgzfWriterBuilder::resume`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+  /// use std::io::Write;
+  ///
+  /// let path = std::env::temp_dir().join("bgzf_resume_doc_example.bgz");
+  /// let path = path.to_str().unwrap().to_string();
+  /// let data = b"This is just a bgzf test".repeat(10_000);
+  /// let mut writer = BgzfWriter::create(path.clone()).unwrap();
+  /// writer.write_all(&data).unwrap();
+  /// writer.finish().unwrap();
+  /// //cut off in the middle of the second block
+  /// let second = BgzfReader::new(path.clone()).unwrap().block_metadata(1).unwrap();
+  /// let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+  /// file.set_len(second.compressed_offset + 10).unwrap();
+  ///
+  /// let (mut writer, offset) = BgzfWriter::resume(path.clone()).unwrap();
+  /// assert_eq!(65280, offset);
+  /// writer.write_all(&data[offset as usize..]).unwrap();
+  /// writer.finish().unwrap();
+  ///
+  /// let reader = BgzfReader::new(path).unwrap();
+  /// assert_eq!(data, reader.decompress_all_parallel(2).unwrap());
+  /// ```
+  pub fn resume(file_path: String) -> io::Result<(BgzfWriter<File>, u64)> {
+    BgzfWriter::builder().resume(file_path)
+  }
+}
+
+impl<W: Write> BgzfWriter<W> {
+  /// Returns a writer compressing into `inner` with the default compression
+  /// level and full sized blocks
+  pub fn new(inner: W) -> BgzfWriter<W> {
+    BgzfWriter::with_capacity(MAX_BLOCK_INPUT_SIZE, inner)
+  }
+
+  /// Returns a writer compressing into `inner` with `capacity` uncompressed
+  /// bytes per block, values outside of 1 to 65280 are brought into that range
+  pub fn with_capacity(capacity: usize, inner: W) -> BgzfWriter<W> {
+    BgzfWriter::builder()
+      .block_size(capacity.clamp(1, MAX_BLOCK_INPUT_SIZE))
+      .build(inner)
+      .expect("Default compression level and clamped block size are valid")
+  }
+
+  /// Compresses everything `src` returns into `dst` as bgzf, including the EOF
+  /// marker, using the writer configured by `options`. The source is read
+  /// straight into the block buffer in block sized chunks, short reads are
+  /// fine, and an empty source produces just the EOF marker.
+  ///
+  /// `dst` is consumed, pass `&mut` of a sink to keep using it afterwards.
+  /// If reading or writing fails the EOF marker is not written.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  ///
+  /// let mut compressed = Vec::new();
+  /// let stats = BgzfWriter::compress_from_reader(
+  ///   &mut &b"This is just a bgzf test"[..],
+  ///   &mut compressed,
+  ///   BgzfWriter::builder(),
+  /// )
+  /// .unwrap();
+  /// assert_eq!(24, stats.bytes_in);
+  /// assert_eq!(1, stats.blocks_written);
+  /// assert_eq!(compressed.len() as u64, stats.bytes_out);
+  /// ```
+  pub fn compress_from_reader<R: Read>(
+    src: &mut R,
+    dst: W,
+    options: BgzfWriterBuilder,
+  ) -> io::Result<CompressStats> {
+    let mut writer = options.build(dst)?;
+    let result = writer.copy_from(src);
+    if result.is_err() {
+      //keeps drop from making the partial output look complete
+      writer.finished = true;
+    }
+    let bytes_in = result?;
+    writer.try_finish()?;
+    Ok(CompressStats {
+      bytes_in,
+      bytes_out: writer.compressed_position,
+      blocks_written: writer.blocks_written,
+    })
+  }
+
+  fn copy_from<R: Read>(&mut self, src: &mut R) -> io::Result<u64> {
+    let mut bytes_in = 0;
+    let mut filled = self.uncompressed_data.len();
+    self.uncompressed_data.resize(self.block_size, 0);
+    loop {
+      if filled == self.block_size {
+        self.write_block()?;
+        filled = 0;
+        self.uncompressed_data.resize(self.block_size, 0);
+      }
+      match src.read(&mut self.uncompressed_data[filled..]) {
+        Ok(0) => break,
+        Ok(bytes_read) => {
+          filled += bytes_read;
+          bytes_in += bytes_read as u64;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => {
+          self.uncompressed_data.truncate(filled);
+          return Err(e);
+        }
+      }
+    }
+    self.uncompressed_data.truncate(filled);
+    Ok(bytes_in)
+  }
+
+  /// This method returns the compression level used for the blocks
+  pub fn compression_level(&self) -> i32 {
+    self.block_format.level.map_or(0, DeflateLevel::get)
+  }
+
+  /// Returns a reference to the underlying writer
+  pub fn get_ref(&self) -> &W {
+    self
+      .inner
+      .as_ref()
+      .expect("Inner writer is only taken by finish")
+  }
+
+  /// Returns a mutable reference to the underlying writer, writing to it
+  /// directly corrupts the bgzf output
+  pub fn get_mut(&mut self) -> &mut W {
+    self
+      .inner
+      .as_mut()
+      .expect("Inner writer is only taken by finish")
+  }
+
+  /// Finishes the bgzf data (see `finish`) and returns the underlying writer
+  pub fn into_inner(self) -> io::Result<W> {
+    self.finish()
+  }
+
+  /// Returns the offset in the uncompressed data where the next written byte
+  /// lands, counting the bytes still buffered or being compressed. When
+  /// appending it includes the data already in the file.
+  pub fn uncompressed_position(&self) -> u64 {
+    self.uncompressed_position
+      + self.queued_uncompressed_length
+      + self.uncompressed_data.len() as u64
+  }
+
+  /// Returns the virtual offset where the next written byte lands, with the
+  /// same meaning as htslib's `bgzf_tell`: the compressed offset of the block
+  /// being filled shifted left by 16, ored with the number of bytes already
+  /// buffered for that block. Only blocks that are fully compressed have a
+  /// known size, so a full buffer is written out first and with multiple
+  /// threads the blocks still being compressed are waited for, which is why
+  /// this needs `&mut self`. Nothing is written when the buffer has room.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  /// use std::io::Write;
+  ///
+  /// let mut writer = BgzfWriter::new(Vec::new());
+  /// writer.write_all(b"first record\n").unwrap();
+  /// assert_eq!(13, writer.virtual_position().unwrap());
+  /// assert_eq!(13, writer.uncompressed_position());
+  /// let block_end = writer.flush_block().unwrap();
+  /// assert_eq!(block_end, writer.virtual_position().unwrap());
+  /// writer.finish().unwrap();
+  /// ```
+  pub fn virtual_position(&mut self) -> io::Result<u64> {
+    if self.uncompressed_data.len() == self.block_size {
+      self.write_block()?;
+    }
+    self.write_compressed_blocks(true)?;
+    Ok((self.compressed_position << 16) | self.uncompressed_data.len() as u64)
+  }
+
+  /// Returns the totals of the blocks written so far. Data still buffered or
+  /// being compressed is not counted yet, after `try_finish` the stats cover
+  /// the whole output including the EOF marker.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  /// use std::io::Write;
+  ///
+  /// let mut writer = BgzfWriter::new(Vec::new());
+  /// writer.write_all(b"This is just a bgzf test").unwrap();
+  /// writer.try_finish().unwrap();
+  /// let stats = writer.stats();
+  /// assert_eq!(1, stats.blocks);
+  /// assert_eq!(24, stats.uncompressed_bytes);
+  /// assert_eq!(writer.get_ref().len() as u64, stats.compressed_bytes);
+  /// ```
+  pub fn stats(&self) -> WriterStats {
+    self.stats
+  }
+
+  /// Calls `callback` every time a data block is written to the sink, the
+  /// last one written by `finish` included, with where the block is. It is
+  /// called once the whole block was handed to the sink, in the order of
+  /// the output whatever the number of threads, so a record index can take
+  /// the virtual offset of a record from the block holding its start and
+  /// its `uncompressed_position` when it was written. When appending, the
+  /// blocks already in the file are not reported. A panic of the callback
+  /// leaves the output whole: the block is written, the writer still
+  /// finishes the file, when dropped too, and the callback is not called
+  /// again.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  /// use std::io::Write;
+  /// use std::sync::mpsc;
+  ///
+  /// let (sender, blocks) = mpsc::channel();
+  /// let mut writer = BgzfWriter::with_capacity(10, Vec::new());
+  /// writer.on_block(move |block| sender.send(block).unwrap());
+  /// writer.write_all(b"This is just a bgzf test").unwrap();
+  /// let output = writer.finish().unwrap();
+  /// let blocks = blocks.iter().collect::<Vec<_>>();
+  /// assert_eq!(3, blocks.len());
+  /// assert_eq!(20, blocks[2].uncompressed_offset);
+  /// assert_eq!(4, blocks[2].uncompressed_length);
+  /// let last = blocks[2].compressed_offset + u64::from(blocks[2].block_size);
+  /// //the EOF marker follows
+  /// assert_eq!(output.len() as u64 - 28, last);
+  /// ```
+  pub fn on_block(&mut self, callback: impl FnMut(BlockFlushInfo) + Send + 'static) {
+    self.on_block = Some(Box::new(callback));
+  }
+
+  /// This method compresses whatever is buffered into a block, even if it is
+  /// small, so that the next write starts at a block boundary. It returns the
+  /// virtual offset (compressed offset << 16) at which the next write begins,
+  /// when nothing is buffered no block is written and the current position is
+  /// returned.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::BgzfWriter;
+  /// use std::io::Write;
+  ///
+  /// let mut writer = BgzfWriter::new(Vec::new());
+  /// assert_eq!(0, writer.flush_block().unwrap());
+  /// writer.write_all(b"first record\n").unwrap();
+  /// let second_record = writer.flush_block().unwrap();
+  /// assert_eq!(0, second_record & 0xffff);
+  /// assert_eq!(second_record, writer.flush_block().unwrap());
+  /// writer.finish().unwrap();
+  /// ```
+  pub fn flush_block(&mut self) -> io::Result<u64> {
+    if !self.uncompressed_data.is_empty() {
+      self.write_block()?;
+    }
+    self.write_compressed_blocks(true)?;
+    Ok(self.compressed_position << 16)
+  }
+
+  /// Writes `raw`, an already compressed block such as one from
+  /// `BgzfReader::raw_block`, to the output unchanged, and returns where it
+  /// went. The data buffered or being compressed is written first, as a
+  /// block of its own, so that the positions stay right when raw blocks and
+  /// normal writes are mixed. `raw` has to be a single whole BGZF block,
+  /// with the gzip header, the BC subfield giving its exact length and the
+  /// footer, otherwise an `InvalidData` error is returned and nothing is
+  /// written. Its deflate data and CRC32 are passed on unchecked, and it
+  /// moves the uncompressed position by its ISIZE. This is how blocks are
+  /// moved between files without decompressing them, see `cat`.
+  ///
+  /// # Example
+  /// ```
+  /// use bgzf_rust_reader::fixtures::make_bgzf;
+  /// use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+  /// use std::io::Write;
+  ///
+  /// let reader = BgzfReader::from_bytes(make_bgzf(b"0123456789", 4)).unwrap();
+  /// let mut writer = BgzfWriter::new(Vec::new());
+  /// writer.write_all(b"ab").unwrap();
+  /// let block = writer.write_raw_block(&reader.raw_block(2).unwrap()).unwrap();
+  /// assert_eq!(2, block.uncompressed_offset);
+  /// assert_eq!(2, block.uncompressed_length);
+  /// assert_eq!(4, writer.uncompressed_position());
+  /// let output = BgzfReader::from_bytes(writer.finish().unwrap()).unwrap();
+  /// assert_eq!(b"ab89".to_vec(), output.decompress_all_parallel(1).unwrap());
+  /// ```
+  pub fn write_raw_block(&mut self, raw: &[u8]) -> io::Result<BlockFlushInfo> {
+    let block = read_block(&raw, 0).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if block.block_size as usize != raw.len() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+          "The raw block holds {} bytes but its header gives a size of {}",
+          raw.len(),
+          block.block_size
+        ),
+      ));
+    }
+    self.sink()?;
+    if !self.uncompressed_data.is_empty() {
+      self.write_block()?;
+    }
+    self.write_compressed_blocks(true)?;
+    self.sink()?.write_all(raw)?;
+    Ok(self.block_written(raw))
+  }
+
+  /// This method writes the buffered data as a last block followed by the
+  /// EOF marker, and returns the underlying writer. With multiple threads it
+  /// first waits for all queued blocks to be compressed and written. When a
+  /// .gzi path was given to the builder the index is saved last.
+  ///
+  /// Since the writer is consumed it can not be finished twice, if finishing
+  /// fails the error is returned here and dropping the writer does nothing.
+  pub fn finish(mut self) -> io::Result<W> {
+    if let Err(e) = self.try_finish() {
+      self.finished = true;
+      return Err(e);
+    }
+    Ok(
+      self
+        .inner
+        .take()
+        .expect("Inner writer is only taken by finish"),
+    )
+  }
+
+  /// This method does the same as `finish` without giving up the writer,
+  /// calling it again once it succeeded does nothing. Writing after it
+  /// returns an error.
+  pub fn try_finish(&mut self) -> io::Result<()> {
+    if self.finished {
+      return Ok(());
+    }
+    if !self.uncompressed_data.is_empty() {
+      self.write_block()?;
+    }
+    self.write_compressed_blocks(true)?;
+    let inner = self.sink()?;
+    inner.write_all(&EOF_MARKER)?;
+    inner.flush()?;
+    self.compressed_position += EOF_MARKER.len() as u64;
+    self.stats.compressed_bytes += EOF_MARKER.len() as u64;
+    self.stats.eof_marker = true;
+    self.finished = true;
+    if let Some(gzi_path) = self.gzi_path.as_ref() {
+      write_gzi(&self.block_offsets, File::create(gzi_path)?)?;
+    }
+    Ok(())
+  }
+
+  fn sink(&mut self) -> io::Result<&mut W> {
+    match self.inner.as_mut() {
+      Some(inner) if !self.finished => Ok(inner),
+      _ => Err(io::Error::other("Writer is already finished")),
+    }
+  }
+
+  fn write_block(&mut self) -> io::Result<()> {
+    match self.pool.as_mut() {
+      Some(pool) => {
+        let uncompressed_data = mem::replace(
+          &mut self.uncompressed_data,
+          Vec::with_capacity(self.block_size),
+        );
+        let queued_length = uncompressed_data.len() as u64;
+        pool.submit(uncompressed_data)?;
+        self.queued_uncompressed_length += queued_length;
+        self.write_compressed_blocks(false)
+      }
+      None => {
+        //taken out while written so the positions can be updated from it
+        let mut block = mem::take(&mut self.compressed_block);
+        let result = self
+          .compressor
+          .compress(&self.uncompressed_data, &mut block)
+          .and_then(|()| self.sink()?.write_all(&block));
+        if result.is_ok() {
+          self.uncompressed_data.clear();
+          self.block_written(&block);
+        }
+        self.compressed_block = block;
+        result
+      }
+    }
+  }
+
+  /// Writes the blocks the pool has finished compressing in submission order,
+  /// waiting for all of them when `drain` is true, or else only while too many
+  /// blocks are in flight
+  fn write_compressed_blocks(&mut self, drain: bool) -> io::Result<()> {
+    while let Some(pool) = self.pool.as_mut() {
+      let block = match pool.next_block(drain || pool.is_full())? {
+        Some(block) => block,
+        None => break,
+      };
+      self.sink()?.write_all(&block)?;
+      self.queued_uncompressed_length -= block_input_length(&block);
+      self.block_written(&block);
+    }
+    Ok(())
+  }
+
+  /// Moves the positions past a block just written to the sink, then tells
+  /// the callback, last so that a panic of it leaves the writer as it should
+  /// be after the block, and returns where the block is
+  fn block_written(&mut self, block: &[u8]) -> BlockFlushInfo {
+    if self.gzi_path.is_some() {
+      self
+        .block_offsets
+        .push((self.compressed_position, self.uncompressed_position));
+    }
+    let input_length = block_input_length(block);
+    let info = BlockFlushInfo {
+      compressed_offset: self.compressed_position,
+      block_size: block.len() as u32,
+      uncompressed_offset: self.uncompressed_position,
+      uncompressed_length: input_length as u32,
+    };
+    //a stored block starts with BFINAL set and BTYPE 00 right after the
+    //header, whose extra field may differ from ours in a raw block, which
+    //may also have other header fields, never stored then for the stats
+    let header_length = 12 + usize::from(u16::from_le_bytes([block[10], block[11]]));
+    let stored = block[3] == 4 && block[header_length] & 0x07 == 0x01;
+    self
+      .stats
+      .add_block(block.len() as u64, input_length, stored);
+    self.compressed_position += block.len() as u64;
+    self.uncompressed_position += input_length;
+    self.blocks_written += 1;
+    //taken out while called, a callback that panicked is not called again
+    if let Some(mut on_block) = self.on_block.take() {
+      on_block(info);
+      self.on_block = Some(on_block);
+    }
+    info
+  }
+}
+
+/// Reads ISIZE, the uncompressed length, which is the last field of a block
+fn block_input_length(block: &[u8]) -> u64 {
+  let mut input_length = [0u8; 4];
+  input_length.copy_from_slice(&block[block.len() - 4..]);
+  u64::from(u32::from_le_bytes(input_length))
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+  fn drop(&mut self) {
+    if self.inner.is_some() && !self.finished {
+      let result = self.try_finish();
+      debug_assert!(
+        result.is_ok() || thread::panicking(),
+        "BgzfWriter dropped without finish and finishing failed: {:?}",
+        result
+      );
+    }
+  }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.sink()?;
+    if self.uncompressed_data.len() == self.block_size {
+      self.write_block()?;
+    }
+    let copy_length = min(self.block_size - self.uncompressed_data.len(), buf.len());
+    self
+      .uncompressed_data
+      .extend_from_slice(&buf[..copy_length]);
+    Ok(copy_length)
+  }
+
+  /// Writes the buffered block if it is full and every block queued for
+  /// compression, a partially filled block stays buffered and the EOF marker
+  /// is not written
+  fn flush(&mut self) -> io::Result<()> {
+    if self.uncompressed_data.len() == self.block_size {
+      self.write_block()?;
+    }
+    self.write_compressed_blocks(true)?;
+    self.sink()?.flush()
+  }
+}
+
+/// Level 0 gives None, meaning stored blocks, since libdeflate starts at 1
+pub(crate) fn compression_level_from(level: i32) -> io::Result<Option<DeflateLevel>> {
+  if level == 0 {
+    return Ok(None);
+  }
+  DeflateLevel::new(level).map(Some).ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!(
+        "Compression level {} is out of the supported range 0-12",
+        level
+      ),
+    )
+  })
+}
+
+/// How the writer encodes every data block
+///
+/// Fields description:
+///
+/// level: deflate level, None for stored blocks,
+/// mtime: modification time put in the gzip header,
+/// extra_subfields: FEXTRA subfields written after BC, already serialized,
+#[derive(Clone, Debug)]
+pub(crate) struct BlockFormat {
+  pub(crate) level: Option<DeflateLevel>,
+  pub(crate) mtime: u32,
+  pub(crate) extra_subfields: Vec<u8>,
+}
+
+impl BlockFormat {
+  fn header_length(&self) -> usize {
+    BLOCK_HEADER_LENGTH + self.extra_subfields.len()
+  }
+
+  /// Checks that blocks of `block_size` uncompressed bytes always fit in the
+  /// 64 KiB limit, even stored and with the extra subfields in their header
+  pub(crate) fn check_block_size(&self, block_size: usize) -> io::Result<()> {
+    let room = MAX_BLOCK_SIZE - self.header_length() - STORED_HEADER_LENGTH - BLOCK_FOOTER_LENGTH;
+    let max_block_size = min(room, MAX_BLOCK_INPUT_SIZE);
+    if block_size == 0 || block_size > max_block_size {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "Block size {} is out of the supported range 1-{}",
+          block_size, max_block_size
+        ),
+      ));
+    }
+    Ok(())
+  }
+}
+
+/// Serializes the subfields as they appear in the gzip extra field: id, u16
+/// little-endian length and data for each of them
+fn serialize_extra_subfields(extra_subfields: &[ExtraSubfield]) -> io::Result<Vec<u8>> {
+  let mut serialized = Vec::new();
+  for subfield in extra_subfields {
+    if &subfield.id == b"BC" {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The BC subfield is written by the writer itself",
+      ));
+    }
+    let length = u16::try_from(subfield.data.len()).map_err(|_e| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Extra subfield data is longer than 65535 bytes",
+      )
+    })?;
+    serialized.extend_from_slice(&subfield.id);
+    serialized.extend_from_slice(&length.to_le_bytes());
+    serialized.extend_from_slice(&subfield.data);
+  }
+  //XLEN, the whole extra field with the 6 bytes of BC, is a u16 too, and the
+  //header has to leave room for the data in the 64 KiB of the block
+  if serialized.len() + 6 + BLOCK_HEADER_LENGTH + BLOCK_FOOTER_LENGTH > MAX_BLOCK_SIZE {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "Extra subfields do not fit in a BGZF block header",
+    ));
+  }
+  Ok(serialized)
+}
+
+/// Compresses blocks one after the other, keeping the deflate state from
+/// block to block so that compressing does not allocate once the output
+/// buffer is large enough
+///
+/// Fields description:
+///
+/// format: compression level and extra subfields of every block,
+/// compressor: deflate state, None when the format has no level,
+pub(crate) struct BlockCompressor {
+  format: BlockFormat,
+  compressor: Option<Compressor>,
+}
+
+impl BlockCompressor {
+  pub(crate) fn new(format: BlockFormat) -> BlockCompressor {
+    let compressor = format.level.map(Compressor::new);
+    BlockCompressor { format, compressor }
+  }
+
+  /// Returns the size of the largest block `data_length` bytes of data can
+  /// take, enough room for `compress` not to grow its output
+  pub(crate) fn max_block_length(&mut self, data_length: usize) -> usize {
+    let stored_length = STORED_HEADER_LENGTH + data_length;
+    let deflate_length = match self.compressor.as_mut() {
+      Some(compressor) => compressor.compress_bound(data_length),
+      None => 0,
+    };
+    self.format.header_length() + max(stored_length, deflate_length) + BLOCK_FOOTER_LENGTH
+  }
+
+  /// Compresses `data` into a single complete BGZF block replacing the content
+  /// of `block`. A format without level stores the data as is, and so does
+  /// any level where deflate does not make it smaller.
+  pub(crate) fn compress(&mut self, data: &[u8], block: &mut Vec<u8>) -> io::Result<()> {
+    let header_length = self.format.header_length();
+    let stored_length = header_length + STORED_HEADER_LENGTH + data.len() + BLOCK_FOOTER_LENGTH;
+    block.clear();
+    block.resize(header_length, 0);
+    let deflated = match self.compressor.as_mut() {
+      Some(compressor) => {
+        compressor.deflate(data, block)?;
+        block.len() + BLOCK_FOOTER_LENGTH <= stored_length
+      }
+      None => false,
+    };
+    if !deflated {
+      block.truncate(header_length);
+      push_stored_data(data, block)?;
+    }
+    block.resize(block.len() + BLOCK_FOOTER_LENGTH, 0);
+    let block_size = block.len();
+    if block_size > MAX_BLOCK_SIZE {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Compressed block is larger than the BGZF block size limit",
+      ));
+    }
+
+    //gzip header with the BC extra subfield holding BSIZE (block size - 1),
+    //followed by the other subfields
+    let xlen = (header_length - 12) as u16;
+    block[..BLOCK_HEADER_LENGTH].copy_from_slice(&EOF_MARKER[..BLOCK_HEADER_LENGTH]);
+    block[4..8].copy_from_slice(&self.format.mtime.to_le_bytes());
+    block[10..12].copy_from_slice(&xlen.to_le_bytes());
+    block[16..18].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+    block[BLOCK_HEADER_LENGTH..header_length].copy_from_slice(&self.format.extra_subfields);
+
+    let footer = block_size - BLOCK_FOOTER_LENGTH;
+    block[footer..footer + 4].copy_from_slice(&crc32(data).to_le_bytes());
+    block[footer + 4..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(())
+  }
+
+  /// Same as `compress` into a new vector, for blocks handed over to another
+  /// thread
+  pub(crate) fn compress_to_vec(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut block = Vec::new();
+    self.compress(data, &mut block)?;
+    Ok(block)
+  }
+}
+
+/// Length of the header of a deflate stored block (BFINAL/BTYPE, LEN, NLEN)
+const STORED_HEADER_LENGTH: usize = 5;
+
+/// Appends `data` to `block` as a single final deflate stored block
+fn push_stored_data(data: &[u8], block: &mut Vec<u8>) -> io::Result<()> {
+  let length = u16::try_from(data.len()).map_err(|_e| {
+    io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Data is too long for a single stored block",
+    )
+  })?;
+  //BFINAL set and BTYPE 00, then LEN and its one's complement NLEN
+  block.push(0x01);
+  block.extend_from_slice(&length.to_le_bytes());
+  block.extend_from_slice(&(!length).to_le_bytes());
+  block.extend_from_slice(data);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::BgzfReader;
+  use std::fs;
+  use std::panic::{self, AssertUnwindSafe};
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::{Arc, Mutex};
+
+  fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+      "bgzf_rust_reader_{}_{}.bgz",
+      name,
+      std::process::id()
+    ));
+    path.to_str().unwrap().to_string()
+  }
+
+  fn test_content() -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..6000 {
+      content.extend_from_slice(
+        format!("{}\tchr{}\tsome fairly repetitive text\n", i, i % 23).as_bytes(),
+      );
+    }
+    content
+  }
+
+  fn write_with_level(path: &str, level: i32, content: &[u8]) {
+    let mut writer = BgzfWriter::builder()
+      .compression_level(level)
+      .create(path.to_string())
+      .unwrap();
+    writer.write_all(content).unwrap();
+    writer.finish().unwrap();
+  }
+
+  fn read_back(path: &str) -> Vec<u8> {
+    let reader = BgzfReader::new(path.to_string()).unwrap();
+    let mut content = Vec::new();
+    let mut remaining = reader.total_uncompressed_length() as usize;
+    while remaining > 0 {
+      let mut chunk = vec![0; min(remaining, MAX_BLOCK_INPUT_SIZE)];
+      reader.read_to(&mut chunk).unwrap();
+      remaining -= chunk.len();
+      content.extend_from_slice(&chunk);
+    }
+    content
+  }
+
+  /// Compresses a block with a new compressor and buffer, as if nothing was
+  /// reused
+  fn compress_block(format: &BlockFormat, data: &[u8]) -> io::Result<Vec<u8>> {
+    BlockCompressor::new(format.clone()).compress_to_vec(data)
+  }
+
+  fn format(level: i32) -> BlockFormat {
+    BlockFormat {
+      level: compression_level_from(level).unwrap(),
+      mtime: 0,
+      extra_subfields: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn test_compress_block_header_and_footer() {
+    let block = compress_block(&format(DEFAULT_COMPRESSION_LEVEL), b"bgzf").unwrap();
+    assert_eq!(&block[..4], &[31, 139, 8, 4]);
+    assert_eq!(&block[12..14], b"BC");
+    let bsize = u16::from_le_bytes([block[16], block[17]]);
+    assert_eq!(usize::from(bsize) + 1, block.len());
+    assert_eq!(&block[block.len() - 4..], &4u32.to_le_bytes());
+  }
+
+  /// Bytes from a xorshift generator, deflate can not make them smaller
+  fn random_bytes(length: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..length)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 24) as u8
+      })
+      .collect()
+  }
+
+  fn bsize(block: &[u8]) -> usize {
+    usize::from(u16::from_le_bytes([block[16], block[17]])) + 1
+  }
+
+  #[test]
+  fn test_random_block_is_stored() {
+    let data = random_bytes(MAX_BLOCK_INPUT_SIZE);
+    for level in &[0, DEFAULT_COMPRESSION_LEVEL, 12] {
+      let block = compress_block(&format(*level), &data).unwrap();
+      assert_eq!(block.len(), bsize(&block));
+      assert!(block.len() <= MAX_BLOCK_SIZE);
+      assert_eq!(
+        BLOCK_HEADER_LENGTH + STORED_HEADER_LENGTH + data.len() + BLOCK_FOOTER_LENGTH,
+        block.len()
+      );
+      assert_eq!(0x01, block[BLOCK_HEADER_LENGTH]);
+    }
+  }
+
+  #[test]
+  fn test_stored_blocks_round_trip() {
+    let mut content = random_bytes(MAX_BLOCK_INPUT_SIZE * 2 + 1000);
+    content.extend_from_slice(&test_content());
+    let stored_path = temp_path("level_0");
+    let mixed_path = temp_path("level_6_random");
+    write_with_level(&stored_path, 0, &content);
+    write_with_level(&mixed_path, 6, &content);
+
+    assert_eq!(content, read_back(&stored_path));
+    assert_eq!(content, read_back(&mixed_path));
+    //the text compresses at level 6 but not at level 0
+    assert!(fs::metadata(&mixed_path).unwrap().len() < fs::metadata(&stored_path).unwrap().len());
+
+    fs::remove_file(stored_path).unwrap();
+    fs::remove_file(mixed_path).unwrap();
+  }
+
+  #[test]
+  fn test_stored_empty_block() {
+    let block = compress_block(&format(0), b"").unwrap();
+    assert_eq!(&[0x01, 0x00, 0x00, 0xff, 0xff], &block[18..23]);
+    assert_eq!(block.len(), bsize(&block));
+
+    let writer = BgzfWriter::builder()
+      .compression_level(0)
+      .build(Vec::new())
+      .unwrap();
+    assert_eq!(0, writer.compression_level());
+    assert_eq!(EOF_MARKER.len(), writer.finish().unwrap().len());
+  }
+
+  #[test]
+  fn test_compression_levels() {
+    let content = test_content();
+    let fast_path = temp_path("level_1");
+    let best_path = temp_path("level_9");
+    write_with_level(&fast_path, 1, &content);
+    write_with_level(&best_path, 9, &content);
+
+    assert_eq!(content, read_back(&fast_path));
+    assert_eq!(content, read_back(&best_path));
+    assert!(fs::metadata(&best_path).unwrap().len() < fs::metadata(&fast_path).unwrap().len());
+
+    fs::remove_file(fast_path).unwrap();
+    fs::remove_file(best_path).unwrap();
+  }
+
+  #[test]
+  fn test_default_compression_level() {
+    let path = temp_path("default_level");
+    let writer = BgzfWriter::create(path.clone()).unwrap();
+    assert_eq!(DEFAULT_COMPRESSION_LEVEL, writer.compression_level());
+    writer.finish().unwrap();
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_flush_block_record_boundaries() {
+    let path = temp_path("flush_block");
+    let records: [&[u8]; 3] = [b"first record\n", b"second record\n", b"third\n"];
+    let mut writer = BgzfWriter::create(path.clone()).unwrap();
+    let mut virtual_offsets = Vec::new();
+    for record in records.iter() {
+      virtual_offsets.push(writer.flush_block().unwrap());
+      writer.write_all(record).unwrap();
+    }
+    writer.flush_block().unwrap();
+    writer.finish().unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(3, reader.blocks().len());
+    for (record, virtual_offset) in records.iter().zip(virtual_offsets) {
+      assert_eq!(0, virtual_offset & 0xffff);
+      let block_start = virtual_offset >> 16;
+      let uoffset = reader
+        .blocks()
+        .iter()
+        .find(|block| block.block_offset == block_start)
+        .unwrap()
+        .input_offset;
+      reader.seek(uoffset);
+      let mut content = vec![0; record.len()];
+      reader.read_to(&mut content).unwrap();
+      assert_eq!(record, &&content[..]);
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  fn compress_failing_on_x(compressor: &mut BlockCompressor, data: &[u8]) -> io::Result<Vec<u8>> {
+    if data[0] == b'x' {
+      return Err(io::Error::other("failing worker"));
+    }
+    compressor.compress_to_vec(data)
+  }
+
+  #[test]
+  fn test_threaded_compression_keeps_block_order() {
+    let mut content = Vec::new();
+    for i in 0..200_000u32 {
+      content.extend_from_slice(format!("{}:{}\n", i, i.wrapping_mul(2_654_435_761)).as_bytes());
+    }
+    let serial_path = temp_path("serial");
+    let threaded_path = temp_path("threaded");
+    write_with_level(&serial_path, 6, &content);
+    let mut writer = BgzfWriter::builder()
+      .threads(4)
+      .create(threaded_path.clone())
+      .unwrap();
+    for chunk in content.chunks(1000) {
+      writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap();
+
+    assert_eq!(
+      fs::read(&serial_path).unwrap(),
+      fs::read(&threaded_path).unwrap()
+    );
+    assert_eq!(content, read_back(&threaded_path));
+    fs::remove_file(serial_path).unwrap();
+    fs::remove_file(threaded_path).unwrap();
+  }
+
+  #[test]
+  fn test_threaded_compression_worker_error() {
+    let path = temp_path("failing_worker");
+    let mut writer = BgzfWriter::builder()
+      .threads(2)
+      .create(path.clone())
+      .unwrap();
+    writer.pool = Some(CompressionPool::new(
+      2,
+      format(DEFAULT_COMPRESSION_LEVEL),
+      compress_failing_on_x,
+      &Executor::default(),
+    ));
+    writer
+      .write_all(&vec![b'a'; MAX_BLOCK_INPUT_SIZE * 2])
+      .unwrap();
+    writer.write_all(&vec![b'x'; MAX_BLOCK_INPUT_SIZE]).unwrap();
+    match writer.finish() {
+      Ok(_file) => panic!(),
+      Err(e) => assert_eq!("failing worker", e.to_string()),
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_append_keeps_existing_content() {
+    let path = temp_path("append");
+    let content = test_content();
+    let (first, second) = content.split_at(100_000);
+    write_with_level(&path, 6, first);
+    let original = fs::read(&path).unwrap();
+
+    let mut writer = BgzfWriter::append(path.clone()).unwrap();
+    writer.write_all(second).unwrap();
+    writer.finish().unwrap();
+
+    let appended = fs::read(&path).unwrap();
+    assert_eq!(
+      &original[..original.len() - EOF_MARKER.len()],
+      &appended[..original.len() - EOF_MARKER.len()]
+    );
+    assert_eq!(&EOF_MARKER, &appended[appended.len() - EOF_MARKER.len()..]);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+    let mut head = vec![0; MAX_BLOCK_INPUT_SIZE];
+    reader.read_to(&mut head).unwrap();
+    assert_eq!(&content[..MAX_BLOCK_INPUT_SIZE], &head[..]);
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    reader.seek(100_000);
+    let mut tail = vec![0; 1000];
+    reader.read_to(&mut tail).unwrap();
+    assert_eq!(&content[100_000..101_000], &tail[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_append_missing_eof_marker() {
+    let path = temp_path("append_no_eof");
+    write_with_level(&path, 6, b"This is just ");
+    let file_length = fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_len(file_length - EOF_MARKER.len() as u64)
+      .unwrap();
+
+    match BgzfWriter::append(path.clone()) {
+      Ok(_writer) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    let mut writer = BgzfWriter::builder()
+      .allow_missing_eof_marker(true)
+      .append(path.clone())
+      .unwrap();
+    writer.write_all(b"a bgzf test").unwrap();
+    writer.finish().unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut content = vec![0; 13];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(b"This is just ", &content[..]);
+    let mut content = vec![0; 11];
+    reader.read_to(&mut content).unwrap();
+    assert_eq!(b"a bgzf test", &content[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_append_rejects_non_bgzf_file() {
+    let path = temp_path("append_invalid");
+    let mut content = b"not a bgzf file at all".to_vec();
+    content.extend_from_slice(&EOF_MARKER);
+    fs::write(&path, &content).unwrap();
+    match BgzfWriter::append(path.clone()) {
+      Ok(_writer) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    assert_eq!(content, fs::read(&path).unwrap());
+    fs::remove_file(path).unwrap();
+  }
+
+  /// File whose writes stop reaching the disk after `kept` bytes, like a
+  /// writer killed at that point, the rest being lost without an error
+  struct KilledAt {
+    file: File,
+    kept: u64,
+  }
+
+  impl Write for KilledAt {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      let length = min(buf.len() as u64, self.kept) as usize;
+      self.file.write_all(&buf[..length])?;
+      self.kept -= length as u64;
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      self.file.flush()
+    }
+  }
+
+  #[test]
+  fn test_resume_after_killed_write() {
+    let content = test_content();
+    for threads in [1, 3] {
+      let builder = || BgzfWriter::builder().block_size(10_000).threads(threads);
+      let mut writer = builder().build(Vec::new()).unwrap();
+      writer.write_all(&content).unwrap();
+      let complete = writer.finish().unwrap();
+      let blocks = BgzfReader::from_bytes(complete.clone())
+        .unwrap()
+        .block_offsets()
+        .collect::<Vec<_>>();
+      assert!(blocks.len() > 5);
+      let cuts = [
+        0,
+        10,
+        blocks[3].compressed_offset,
+        blocks[3].compressed_offset + 100,
+        complete.len() as u64 - EOF_MARKER.len() as u64,
+        complete.len() as u64 - 5,
+        complete.len() as u64,
+      ];
+      for &cut in &cuts {
+        let path = temp_path("resume_killed");
+        let mut writer = builder()
+          .build(KilledAt {
+            file: File::create(&path).unwrap(),
+            kept: cut,
+          })
+          .unwrap();
+        writer.write_all(&content).unwrap();
+        //dropped without finish, the data past the cut never got written
+        drop(writer);
+        assert_eq!(cut, fs::metadata(&path).unwrap().len());
+
+        let (mut writer, offset) = builder().resume(path.clone()).unwrap();
+        let whole_blocks = blocks
+          .iter()
+          .filter(|block| block.compressed_offset + u64::from(block.block_size) <= cut);
+        let expected_offset = whole_blocks
+          .map(|block| u64::from(block.uncompressed_length))
+          .sum::<u64>();
+        assert_eq!(expected_offset, offset, "cut at {}", cut);
+        writer.write_all(&content[offset as usize..]).unwrap();
+        writer.finish().unwrap();
+        //blocks are cut at the same places, so the file is the same
+        assert_eq!(complete, fs::read(&path).unwrap(), "cut at {}", cut);
+        assert_eq!(content, read_back(&path));
+        fs::remove_file(path).unwrap();
+      }
+    }
+  }
+
+  #[test]
+  fn test_resume_rejects_damaged_files() {
+    let content = test_content();
+    let mut writer = BgzfWriter::builder()
+      .block_size(10_000)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    let complete = writer.finish().unwrap();
+    let blocks = BgzfReader::from_bytes(complete.clone())
+      .unwrap()
+      .block_offsets()
+      .collect::<Vec<_>>();
+    let path = temp_path("resume_damaged");
+
+    //the CRC32 of a whole block does not match its data
+    let mut damaged = complete[..blocks[5].compressed_offset as usize + 100].to_vec();
+    let footer = (blocks[1].compressed_offset + u64::from(blocks[1].block_size)) as usize - 8;
+    damaged[footer] ^= 0xff;
+    fs::write(&path, &damaged).unwrap();
+    match BgzfWriter::resume(path.clone()) {
+      Ok(_writer) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    assert_eq!(damaged, fs::read(&path).unwrap());
+
+    //a whole block's worth of bytes follows the last whole block
+    let mut damaged = complete[..blocks[5].compressed_offset as usize].to_vec();
+    damaged.extend_from_slice(&random_bytes(MAX_BLOCK_SIZE));
+    fs::write(&path, &damaged).unwrap();
+    match BgzfWriter::resume(path.clone()) {
+      Ok(_writer) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    assert_eq!(damaged, fs::read(&path).unwrap());
+
+    fs::write(&path, b"not a bgzf file at all").unwrap();
+    match BgzfWriter::resume(path.clone()) {
+      Ok(_writer) => panic!(),
+      Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+    }
+    fs::remove_file(path).unwrap();
+  }
+
+  /// .gzi `bgzip -r -l 0` gives for 3000 bytes in blocks of 1000 bytes, each
+  /// stored block being 18 + 5 + 1000 + 8 = 1031 bytes long: 2 entries,
+  /// (1031, 1000) and (2062, 2000)
+  const THREE_BLOCKS_GZI: [u8; 40] = [
+    2, 0, 0, 0, 0, 0, 0, 0, 7, 4, 0, 0, 0, 0, 0, 0, 232, 3, 0, 0, 0, 0, 0, 0, 14, 8, 0, 0, 0, 0, 0,
+    0, 208, 7, 0, 0, 0, 0, 0, 0,
+  ];
+
+  #[test]
+  fn test_gzi_matches_fixture() {
+    let path = temp_path("gzi_fixture");
+    let gzi_path = format!("{}.gzi", path);
+    let mut writer = BgzfWriter::builder()
+      .compression_level(0)
+      .block_size(1000)
+      .gzi(gzi_path.clone())
+      .create(path.clone())
+      .unwrap();
+    writer.write_all(&[b'a'; 3000]).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(
+      3 * 1031 + EOF_MARKER.len() as u64,
+      fs::metadata(&path).unwrap().len()
+    );
+    assert_eq!(&THREE_BLOCKS_GZI[..], &fs::read(&gzi_path).unwrap()[..]);
+    fs::remove_file(path).unwrap();
+    fs::remove_file(gzi_path).unwrap();
+  }
+
+  #[test]
+  fn test_gzi_matches_blocks_when_threaded_and_appending() {
+    let path = temp_path("gzi_append");
+    let gzi_path = format!("{}.gzi", path);
+    let content = test_content();
+    let (first, second) = content.split_at(100_000);
+    write_with_level(&path, 6, first);
+    let mut writer = BgzfWriter::builder()
+      .threads(3)
+      .gzi(gzi_path.clone())
+      .append(path.clone())
+      .unwrap();
+    writer.write_all(second).unwrap();
+    writer.finish().unwrap();
+
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&(reader.blocks().len() as u64 - 1).to_le_bytes());
+    for block in reader.blocks().iter().skip(1) {
+      expected.extend_from_slice(&block.block_offset.to_le_bytes());
+      expected.extend_from_slice(&block.input_offset.to_le_bytes());
+    }
+    assert_eq!(expected, fs::read(&gzi_path).unwrap());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(gzi_path).unwrap();
+  }
+
+  #[test]
+  fn test_on_block_rebuilds_gzi() {
+    let content = test_content();
+    for &threads in &[1, 4] {
+      let path = temp_path(&format!("on_block_{}", threads));
+      let gzi_path = format!("{}.gzi", path);
+      let mut writer = BgzfWriter::builder()
+        .threads(threads)
+        .block_size(10_000)
+        .gzi(gzi_path.clone())
+        .create(path.clone())
+        .unwrap();
+      let blocks = Arc::new(Mutex::new(Vec::new()));
+      let reported = Arc::clone(&blocks);
+      writer.on_block(move |block| reported.lock().unwrap().push(block));
+      let (first, second) = content.split_at(123_456);
+      writer.write_all(first).unwrap();
+      //a record starting mid-block, its virtual offset from the block
+      let record_start = writer.uncompressed_position();
+      writer.flush_block().unwrap();
+      writer.write_all(second).unwrap();
+      writer.finish().unwrap();
+
+      let blocks = blocks.lock().unwrap().clone();
+      let mut gzi = Vec::new();
+      gzi.extend_from_slice(&(blocks.len() as u64 - 1).to_le_bytes());
+      for block in &blocks[1..] {
+        gzi.extend_from_slice(&block.compressed_offset.to_le_bytes());
+        gzi.extend_from_slice(&block.uncompressed_offset.to_le_bytes());
+      }
+      assert_eq!(fs::read(&gzi_path).unwrap(), gzi);
+
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      let expected = reader
+        .block_offsets()
+        .map(|block| BlockFlushInfo {
+          compressed_offset: block.compressed_offset,
+          block_size: block.block_size,
+          uncompressed_offset: block.uncompressed_offset,
+          uncompressed_length: block.uncompressed_length,
+        })
+        .collect::<Vec<_>>();
+      assert_eq!(expected, blocks);
+      //the partial block of flush_block, the last one of finish
+      assert_eq!(3456, blocks[12].uncompressed_length);
+      assert!(blocks.last().unwrap().uncompressed_length < 10_000);
+
+      let block = blocks
+        .iter()
+        .find(|block| block.uncompressed_offset + 10_000 > record_start)
+        .unwrap();
+      let voffset = block.compressed_offset << 16 | (record_start - block.uncompressed_offset);
+      assert_eq!(record_start, reader.voffset_to_uoffset(voffset).unwrap());
+      fs::remove_file(path).unwrap();
+      fs::remove_file(gzi_path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_on_block_panic_keeps_output_whole() {
+    let content = test_content();
+    for &threads in &[1, 4] {
+      let mut output = Vec::new();
+      let calls = Arc::new(AtomicUsize::new(0));
+      let counted = Arc::clone(&calls);
+      let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut writer = BgzfWriter::builder()
+          .threads(threads)
+          .block_size(10_000)
+          .build(&mut output)
+          .unwrap();
+        writer.on_block(move |_block| {
+          if counted.fetch_add(1, Ordering::SeqCst) == 1 {
+            panic!("indexer failed");
+          }
+        });
+        writer.write_all(&content).unwrap();
+        writer.finish().unwrap();
+      }));
+      assert!(panic.is_err());
+      //the callback is not called again while the dropped writer finishes
+      assert_eq!(2, calls.load(Ordering::SeqCst));
+
+      //the data accepted before the panic, all of it in whole blocks
+      assert_eq!(&EOF_MARKER, &output[output.len() - EOF_MARKER.len()..]);
+      let reader = BgzfReader::from_bytes(output).unwrap();
+      let data = reader.decompress_all_parallel(1).unwrap();
+      assert!(data.len() >= 20_000);
+      assert_eq!(&content[..data.len()], &data[..]);
+    }
+  }
+
+  /// The blocks of `reader` as `BlockFlushInfo`, to compare with the ones a
+  /// writer reported
+  fn flush_infos(reader: &BgzfReader) -> Vec<BlockFlushInfo> {
+    reader
+      .block_offsets()
+      .map(|block| BlockFlushInfo {
+        compressed_offset: block.compressed_offset,
+        block_size: block.block_size,
+        uncompressed_offset: block.uncompressed_offset,
+        uncompressed_length: block.uncompressed_length,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_write_raw_blocks_round_trip() {
+    let content = test_content();
+    let mut source = Vec::new();
+    let mut writer = BgzfWriter::builder()
+      .block_size(10_000)
+      .build(&mut source)
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    writer.finish().unwrap();
+    let source = BgzfReader::from_bytes(source).unwrap();
+    for &threads in &[1, 3] {
+      //every block moved over as is, the same file
+      let mut output = Vec::new();
+      let mut writer = BgzfWriter::builder()
+        .threads(threads)
+        .build(&mut output)
+        .unwrap();
+      let blocks = (0..source.num_blocks())
+        .map(|block_index| {
+          writer
+            .write_raw_block(&source.raw_block(block_index).unwrap())
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+      assert_eq!(content.len() as u64, writer.uncompressed_position());
+      writer.finish().unwrap();
+      assert_eq!(flush_infos(&source), blocks);
+      let reader = BgzfReader::from_bytes(output).unwrap();
+      assert_eq!(flush_infos(&source), flush_infos(&reader));
+      assert_eq!(content, reader.decompress_all_parallel(1).unwrap());
+
+      //the blocks in reverse order between normal writes, each one after
+      //the data written before it
+      let mut output = Vec::new();
+      let mut writer = BgzfWriter::builder()
+        .threads(threads)
+        .block_size(3000)
+        .build(&mut output)
+        .unwrap();
+      let reported = Arc::new(Mutex::new(Vec::new()));
+      let on_block = Arc::clone(&reported);
+      writer.on_block(move |block| on_block.lock().unwrap().push(block));
+      let mut expected = Vec::new();
+      let mut raw_blocks = Vec::new();
+      for block_index in (0..source.num_blocks()).rev() {
+        let written = &content[..block_index * 500];
+        writer.write_all(written).unwrap();
+        expected.extend_from_slice(written);
+        raw_blocks.push(
+          writer
+            .write_raw_block(&source.raw_block(block_index).unwrap())
+            .unwrap(),
+        );
+        let info = source.block_metadata(block_index).unwrap();
+        let start = info.uncompressed_offset as usize;
+        expected.extend_from_slice(&content[start..start + info.uncompressed_length as usize]);
+        assert_eq!(expected.len() as u64, writer.uncompressed_position());
+      }
+      writer.write_all(b"after the last raw block").unwrap();
+      expected.extend_from_slice(b"after the last raw block");
+      writer.finish().unwrap();
+
+      let reader = BgzfReader::from_bytes(output).unwrap();
+      assert_eq!(expected, reader.decompress_all_parallel(1).unwrap());
+      let blocks = flush_infos(&reader);
+      assert_eq!(*reported.lock().unwrap(), blocks);
+      for raw_block in raw_blocks {
+        assert!(blocks.contains(&raw_block));
+      }
+    }
+  }
+
+  #[test]
+  fn test_write_raw_block_keeps_stats_and_gzi() {
+    //a stored block with another extra field than the writer's
+    let mut source = Vec::new();
+    let mut writer = BgzfWriter::builder()
+      .compression_level(0)
+      .extra_subfield(*b"XY", b"more".to_vec())
+      .build(&mut source)
+      .unwrap();
+    writer.write_all(&random_bytes(5000)).unwrap();
+    writer.finish().unwrap();
+    let source = BgzfReader::from_bytes(source).unwrap();
+    let raw = source.raw_block(0).unwrap();
+
+    let path = temp_path("raw_block_gzi");
+    let gzi_path = format!("{}.gzi", path);
+    let mut writer = BgzfWriter::builder()
+      .gzi(gzi_path.clone())
+      .create(path.clone())
+      .unwrap();
+    writer.write_all(&test_content()[..1000]).unwrap();
+    writer.write_raw_block(&raw).unwrap();
+    writer.write_raw_block(&raw).unwrap();
+    writer.try_finish().unwrap();
+    let stats = writer.stats();
+    assert_eq!(3, stats.blocks);
+    assert_eq!(2, stats.stored_blocks);
+    assert_eq!(11_000, stats.uncompressed_bytes);
+    assert_eq!(fs::metadata(&path).unwrap().len(), stats.compressed_bytes);
+    drop(writer);
+
+    let indexed = BgzfReader::new(path.clone()).unwrap();
+    let from_gzi = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+    assert!(indexed.block_offsets().eq(from_gzi.block_offsets()));
+    assert_eq!(11_000, from_gzi.total_uncompressed_length());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(gzi_path).unwrap();
+  }
+
+  #[test]
+  fn test_write_raw_block_rejects_invalid_blocks() {
+    let source = BgzfReader::from_bytes(crate::fixtures::make_bgzf(b"0123456789", 4)).unwrap();
+    let raw = source.raw_block(1).unwrap();
+    let mut two_blocks = raw.clone();
+    two_blocks.extend_from_slice(&source.raw_block(2).unwrap());
+    let mut bad_magic = raw.clone();
+    bad_magic[1] = 0;
+    let mut no_bc = raw.clone();
+    no_bc[12] = b'X';
+    let mut bsize_too_large = raw.clone();
+    bsize_too_large[16] += 1;
+    let invalid: Vec<(&str, &[u8])> = vec![
+      ("empty", &[]),
+      ("header only", &raw[..18]),
+      ("footer cut", &raw[..raw.len() - 1]),
+      ("two blocks", &two_blocks),
+      ("bad magic", &bad_magic),
+      ("no BC subfield", &no_bc),
+      ("BSIZE past the end", &bsize_too_large),
+      ("not bgzf", b"not a bgzf block, just some text"),
+    ];
+
+    let mut output = Vec::new();
+    let mut writer = BgzfWriter::new(&mut output);
+    writer.write_all(b"ab").unwrap();
+    for (name, block) in invalid {
+      let error = writer.write_raw_block(block).unwrap_err();
+      assert_eq!(io::ErrorKind::InvalidData, error.kind(), "{}", name);
+    }
+    //nothing was written, the buffered data included
+    assert_eq!(2, writer.uncompressed_position());
+    assert_eq!(0, writer.stats().blocks);
+    writer.write_raw_block(&raw).unwrap();
+    writer.finish().unwrap();
+    let output = BgzfReader::from_bytes(output).unwrap();
+    assert_eq!(
+      b"ab4567".to_vec(),
+      output.decompress_all_parallel(1).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_extra_subfields_round_trip() {
+    let path = temp_path("extra_subfields");
+    let content = test_content();
+    let mut writer = BgzfWriter::builder()
+      .extra_subfield(*b"PV", b"run-42".to_vec())
+      .extra_subfield(*b"XY", vec![1, 2, 3, 4, 5, 6, 7, 8])
+      .threads(2)
+      .create(path.clone())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(content, read_back(&path));
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert!(reader.blocks().len() > 1);
+    for block in reader.block_offsets() {
+      let header = reader.block_header(&block).unwrap();
+      let ids = header
+        .extra_subfields
+        .iter()
+        .map(|subfield| subfield.id)
+        .collect::<Vec<_>>();
+      assert_eq!(vec![*b"BC", *b"PV", *b"XY"], ids);
+      assert_eq!(
+        ((block.block_size - 1) as u16).to_le_bytes().to_vec(),
+        header.extra_subfields[0].data
+      );
+      assert_eq!(b"run-42".to_vec(), header.extra_subfields[1].data);
+      assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], header.extra_subfields[2].data);
+    }
+    //the EOF marker stays the standard one
+    let written = fs::read(&path).unwrap();
+    assert_eq!(&EOF_MARKER, &written[written.len() - EOF_MARKER.len()..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_extra_subfields_limits() {
+    let invalid = vec![
+      BgzfWriter::builder().extra_subfield(*b"BC", vec![0, 0]),
+      BgzfWriter::builder().extra_subfield(*b"AB", vec![0; 0x10000]),
+      //fits in XLEN but leaves no room for full sized blocks
+      BgzfWriter::builder().extra_subfield(*b"AB", vec![0; 1000]),
+    ];
+    for options in invalid {
+      match options.build(Vec::new()) {
+        Ok(_writer) => panic!(),
+        Err(e) => assert_eq!(io::ErrorKind::InvalidInput, e.kind()),
+      }
+    }
+
+    //random data is stored, so this is the largest header room left
+    let data = random_bytes(60_000);
+    let room =
+      MAX_BLOCK_SIZE - BLOCK_HEADER_LENGTH - 4 - STORED_HEADER_LENGTH - BLOCK_FOOTER_LENGTH;
+    let mut writer = BgzfWriter::builder()
+      .extra_subfield(*b"AB", vec![7; room - data.len()])
+      .block_size(data.len())
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(&data).unwrap();
+    let written = writer.finish().unwrap();
+    assert_eq!(MAX_BLOCK_SIZE + EOF_MARKER.len(), written.len());
+    assert_eq!(MAX_BLOCK_SIZE, bsize(&written));
+  }
+
+  fn write_deterministic(content: &[u8], threads: usize, chunk_size: usize) -> Vec<u8> {
+    let mut writer = BgzfWriter::builder()
+      .deterministic(true)
+      .threads(threads)
+      .build(Vec::new())
+      .unwrap();
+    for chunk in content.chunks(chunk_size) {
+      writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap()
+  }
+
+  #[test]
+  fn test_deterministic_output() {
+    let content = test_content();
+    let expected = write_deterministic(&content, 1, content.len());
+    assert_eq!(expected, write_deterministic(&content, 1, content.len()));
+    assert_eq!(expected, write_deterministic(&content, 1, 777));
+    assert_eq!(expected, write_deterministic(&content, 4, 777));
+    assert_eq!(expected, write_deterministic(&content, 3, 100_000));
+    //MTIME 0, XFL 0 and OS 255 in the first header
+    assert_eq!(&[0, 0, 0, 0, 0, 0xff], &expected[4..10]);
+  }
+
+  #[test]
+  fn test_non_deterministic_mtime() {
+    let path = temp_path("mtime");
+    let mut writer = BgzfWriter::builder()
+      .deterministic(false)
+      .create(path.clone())
+      .unwrap();
+    writer.write_all(b"bgzf").unwrap();
+    writer.finish().unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    let block = reader.block_offsets().next().unwrap();
+    assert!(reader.block_header(&block).unwrap().mtime > 0);
+    fs::remove_file(path).unwrap();
+  }
+
+  /// The golden file was written by this writer at level 6 with the
+  /// libdeflate backend, miniz_oxide compresses differently
+  #[cfg(not(feature = "rust-backend"))]
+  #[test]
+  fn test_deterministic_golden_file() {
+    let mut content = Vec::new();
+    for i in 0..300 {
+      content.extend_from_slice(format!("{}\tchr{}\tgolden record\n", i, i % 3).as_bytes());
+    }
+    let mut writer = BgzfWriter::builder()
+      .compression_level(6)
+      .block_size(2000)
+      .build(Vec::new())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    let golden = fs::read("tests/data/golden_level_6.bgz").unwrap();
+    assert_eq!(golden, writer.finish().unwrap());
+  }
+
+  #[test]
+  fn test_positions_while_writing() {
+    let content = test_content();
+    let records = content
+      .split_inclusive(|&byte| byte == b'\n')
+      .collect::<Vec<_>>();
+    for threads in &[1, 3] {
+      let path = temp_path(&format!("positions_{}", threads));
+      let mut writer = BgzfWriter::builder()
+        .threads(*threads)
+        .block_size(10_000)
+        .create(path.clone())
+        .unwrap();
+      let mut positions = Vec::new();
+      for (number, record) in records.iter().enumerate() {
+        if number % 97 == 0 {
+          positions.push((
+            number,
+            writer.uncompressed_position(),
+            writer.virtual_position().unwrap(),
+          ));
+        }
+        writer.write_all(record).unwrap();
+      }
+      assert_eq!(content.len() as u64, writer.uncompressed_position());
+      writer.finish().unwrap();
+
+      let reader = BgzfReader::new(path.clone()).unwrap();
+      let blocks = reader.block_offsets().collect::<Vec<_>>();
+      for (number, uncompressed_position, virtual_position) in positions {
+        let compressed_offset = virtual_position >> 16;
+        let within_block = virtual_position & 0xffff;
+        let block = blocks
+          .iter()
+          .find(|block| block.compressed_offset == compressed_offset)
+          .unwrap();
+        assert!(within_block < u64::from(block.uncompressed_length));
+        assert_eq!(
+          uncompressed_position,
+          block.uncompressed_offset + within_block
+        );
+        let record_start = uncompressed_position as usize;
+        assert_eq!(
+          records[number],
+          &content[record_start..record_start + records[number].len()]
+        );
+      }
+      fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_reused_compressor_matches_fresh_one() {
+    let mut content = test_content();
+    content.extend_from_slice(&random_bytes(20_000));
+    content.extend_from_slice(&test_content());
+    for level in &[0, 1, 6, 12] {
+      let mut writer = BgzfWriter::builder()
+        .compression_level(*level)
+        .block_size(7000)
+        .build(Vec::new())
+        .unwrap();
+      writer.write_all(&content).unwrap();
+      let written = writer.finish().unwrap();
+
+      let mut expected = Vec::new();
+      for chunk in content.chunks(7000) {
+        expected.extend_from_slice(&compress_block(&format(*level), chunk).unwrap());
+      }
+      expected.extend_from_slice(&EOF_MARKER);
+      assert_eq!(expected, written);
+    }
+  }
+
+  #[test]
+  fn test_max_block_length() {
+    for level in &[0, 1, 6, 12] {
+      let mut compressor = BlockCompressor::new(format(*level));
+      let data = random_bytes(MAX_BLOCK_INPUT_SIZE);
+      let mut block = Vec::with_capacity(compressor.max_block_length(data.len()));
+      let capacity = block.capacity();
+      compressor.compress(&data, &mut block).unwrap();
+      compressor
+        .compress(&test_content()[..1000], &mut block)
+        .unwrap();
+      assert_eq!(capacity, block.capacity());
+    }
+  }
+
+  #[test]
+  fn test_stats_match_output() {
+    let mut content = test_content();
+    content.extend_from_slice(&random_bytes(5000));
+    for &(level, threads) in &[(6, 1), (6, 3), (0, 1), (0, 3)] {
+      let mut writer = BgzfWriter::builder()
+        .compression_level(level)
+        .threads(threads)
+        .block_size(7000)
+        .build(Vec::new())
+        .unwrap();
+      writer.write_all(&content).unwrap();
+      writer.try_finish().unwrap();
+      let stats = writer.stats();
+      let written = writer.finish().unwrap();
+
+      let mut block_sizes = Vec::new();
+      let mut offset = 0;
+      while offset < written.len() - EOF_MARKER.len() {
+        block_sizes.push(bsize(&written[offset..]) as u64);
+        offset += bsize(&written[offset..]);
+      }
+      assert_eq!(written.len() as u64, stats.compressed_bytes);
+      assert_eq!(content.len() as u64, stats.uncompressed_bytes);
+      assert_eq!(block_sizes.len() as u64, stats.blocks);
+      assert_eq!(*block_sizes.iter().min().unwrap(), stats.min_block_size);
+      assert_eq!(*block_sizes.iter().max().unwrap(), stats.max_block_size);
+      assert_eq!(
+        block_sizes.iter().sum::<u64>() / block_sizes.len() as u64,
+        stats.average_block_size()
+      );
+      assert!(stats.eof_marker);
+      if level == 0 {
+        assert_eq!(stats.blocks, stats.stored_blocks);
+      } else {
+        //only the blocks of random bytes do not shrink
+        assert!(stats.stored_blocks > 0 && stats.stored_blocks < stats.blocks);
+        assert!(stats.compression_ratio() > 1.0);
+      }
+    }
+  }
+
+  #[test]
+  fn test_stats_before_finish() {
+    let mut writer = BgzfWriter::new(Vec::new());
+    assert_eq!(WriterStats::default(), writer.stats());
+    assert_eq!(0.0, writer.stats().compression_ratio());
+    writer.write_all(b"buffered").unwrap();
+    assert_eq!(0, writer.stats().blocks);
+    writer.flush_block().unwrap();
+    let stats = writer.stats();
+    assert_eq!(1, stats.blocks);
+    assert_eq!(8, stats.uncompressed_bytes);
+    assert!(!stats.eof_marker);
+    assert_eq!(writer.get_ref().len() as u64, stats.compressed_bytes);
+    assert_eq!(stats.compressed_bytes, stats.average_block_size());
+  }
+
+  #[test]
+  fn test_drop_without_finish() {
+    let path = temp_path("drop_without_finish");
+    let content = test_content();
+    {
+      let mut writer = BgzfWriter::builder()
+        .threads(2)
+        .create(path.clone())
+        .unwrap();
+      writer.write_all(&content).unwrap();
+    }
+    let written = fs::read(&path).unwrap();
+    assert_eq!(&EOF_MARKER, &written[written.len() - EOF_MARKER.len()..]);
+    assert_eq!(content, read_back(&path));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_flush_keeps_partial_block_and_eof_marker() {
+    let path = temp_path("flush");
+    let mut writer = BgzfWriter::create(path.clone()).unwrap();
+    writer
+      .write_all(&vec![b'a'; MAX_BLOCK_INPUT_SIZE + 10])
+      .unwrap();
+    writer.flush().unwrap();
+    let flushed = fs::read(&path).unwrap();
+    assert!(!flushed.is_empty());
+    assert_ne!(&EOF_MARKER, &flushed[flushed.len() - EOF_MARKER.len()..]);
+    assert_eq!(10, writer.uncompressed_data.len());
+    writer.finish().unwrap();
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_try_finish_twice() {
+    let path = temp_path("finish_twice");
+    let mut writer = BgzfWriter::create(path.clone()).unwrap();
+    writer.write_all(b"This is just a bgzf test").unwrap();
+    writer.try_finish().unwrap();
+    let finished_length = fs::metadata(&path).unwrap().len();
+    writer.try_finish().unwrap();
+    assert!(writer.write_all(b"more").is_err());
+    writer.finish().unwrap();
+    assert_eq!(finished_length, fs::metadata(&path).unwrap().len());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_write_into_vec() {
+    let content = test_content();
+    let mut writer = BgzfWriter::with_capacity(10_000, Vec::new());
+    writer.write_all(&content).unwrap();
+    assert!(!writer.get_ref().is_empty());
+    let compressed = writer.into_inner().unwrap();
+    assert_eq!(
+      &EOF_MARKER,
+      &compressed[compressed.len() - EOF_MARKER.len()..]
+    );
+
+    let path = temp_path("vec");
+    fs::write(&path, &compressed).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(content.len().div_ceil(10_000), reader.blocks().len());
+    assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+    let mut head = vec![0; 10_000];
+    reader.read_to(&mut head).unwrap();
+    assert_eq!(&content[..10_000], &head[..]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_invalid_block_size() {
+    for block_size in &[0, MAX_BLOCK_INPUT_SIZE + 1] {
+      match BgzfWriter::builder()
+        .block_size(*block_size)
+        .build(Vec::new())
+      {
+        Ok(_writer) => panic!(),
+        Err(e) => assert_eq!(io::ErrorKind::InvalidInput, e.kind()),
+      }
+    }
+  }
+
+  /// Source handing out at most 7 bytes per read, with an interruption in between
+  struct ShortReads<'a> {
+    data: &'a [u8],
+    interrupt: bool,
+  }
+
+  impl<'a> Read for ShortReads<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      self.interrupt = !self.interrupt;
+      if self.interrupt {
+        return Err(io::Error::from(io::ErrorKind::Interrupted));
+      }
+      let length = min(min(7, buf.len()), self.data.len());
+      buf[..length].copy_from_slice(&self.data[..length]);
+      self.data = &self.data[length..];
+      Ok(length)
+    }
+  }
+
+  #[test]
+  fn test_compress_from_reader_short_reads() {
+    let content = test_content();
+    let mut compressed = Vec::new();
+    let stats = BgzfWriter::compress_from_reader(
+      &mut ShortReads {
+        data: &content,
+        interrupt: false,
+      },
+      &mut compressed,
+      BgzfWriter::builder(),
+    )
+    .unwrap();
+    assert_eq!(content.len() as u64, stats.bytes_in);
+    assert_eq!(compressed.len() as u64, stats.bytes_out);
+    assert_eq!(
+      content.len().div_ceil(MAX_BLOCK_INPUT_SIZE) as u64,
+      stats.blocks_written
+    );
+
+    let path = temp_path("compress_from_reader");
+    fs::write(&path, &compressed).unwrap();
+    assert_eq!(content, read_back(&path));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_compress_from_empty_reader() {
+    let mut compressed = Vec::new();
+    let stats =
+      BgzfWriter::compress_from_reader(&mut io::empty(), &mut compressed, BgzfWriter::builder())
+        .unwrap();
+    assert_eq!(EOF_MARKER.to_vec(), compressed);
+    assert_eq!(
+      CompressStats {
+        bytes_in: 0,
+        bytes_out: 28,
+        blocks_written: 0
+      },
+      stats
+    );
+
+    let path = temp_path("compress_from_empty_reader");
+    fs::write(&path, &compressed).unwrap();
+    let reader = BgzfReader::new(path.clone()).unwrap();
+    assert_eq!(0, reader.total_uncompressed_length());
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_invalid_compression_level() {
+    for level in &[-1, 13] {
+      match BgzfWriter::builder()
+        .compression_level(*level)
+        .build(Vec::new())
+      {
+        Ok(_writer) => panic!(),
+        Err(e) => assert_eq!(io::ErrorKind::InvalidInput, e.kind()),
+      }
+    }
+  }
+}
@@ -0,0 +1,138 @@
+//Counts the allocations made by the writer once it is warmed up, which should
+//be none when compressing inline, and by the reader for every block it reads
+
+use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::io::{self, Write};
+
+struct CountingAllocator;
+
+thread_local! {
+  //only the test's own thread is counted, not the harness' threads
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+  ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn test_no_allocation_per_block() {
+  let mut content = Vec::new();
+  for i in 0..20_000 {
+    content.extend_from_slice(format!("{}\tchr{}\tallocation test\n", i, i % 7).as_bytes());
+  }
+  for level in &[0, 1, 6] {
+    let mut writer = BgzfWriter::builder()
+      .compression_level(*level)
+      .build(io::sink())
+      .unwrap();
+    writer.write_all(&content).unwrap();
+    writer.flush().unwrap();
+
+    let before = allocations();
+    for _ in 0..10 {
+      writer.write_all(&content).unwrap();
+    }
+    writer.flush().unwrap();
+    assert_eq!(0, allocations() - before, "level {}", level);
+    writer.finish().unwrap();
+  }
+}
+
+#[test]
+fn test_allocations_per_block_read() {
+  let mut content = Vec::new();
+  for i in 0..20_000 {
+    content.extend_from_slice(format!("{}\tchr{}\tallocation test\n", i, i % 7).as_bytes());
+  }
+  let path = write_temp("allocations_read", &make_bgzf(&content, 1000)).unwrap();
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  let blocks = reader.block_offsets().collect::<Vec<_>>();
+  let mut block = vec![0; 1000];
+
+  //whole blocks are decompressed right into the buffer, the decompressor is
+  //made with the first block and reused, and the buffer for the compressed
+  //blocks only grows with the first blocks
+  let before = allocations();
+  for info in &blocks {
+    let length = info.uncompressed_length as usize;
+    assert_eq!(length, reader.read_to(&mut block[..length]).unwrap());
+    assert_eq!(
+      &content[info.uncompressed_offset as usize..][..length],
+      &block[..length]
+    );
+  }
+  assert!(
+    allocations() - before <= 4,
+    "{} allocations for {} blocks",
+    allocations() - before,
+    blocks.len()
+  );
+
+  //blocks read in halves go through the cache: the uncompressed block, which
+  //moves to the cache, and the cache tables and decompressor with the first
+  //block
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  let before = allocations();
+  for info in &blocks {
+    let length = info.uncompressed_length as usize;
+    let half = length / 2;
+    assert_eq!(half, reader.read_to(&mut block[..half]).unwrap());
+    assert_eq!(
+      length - half,
+      reader.read_to(&mut block[half..length]).unwrap()
+    );
+    assert_eq!(
+      &content[info.uncompressed_offset as usize..][..length],
+      &block[..length]
+    );
+  }
+  assert!(
+    allocations() - before <= blocks.len() + 6,
+    "{} allocations for {} blocks",
+    allocations() - before,
+    blocks.len()
+  );
+
+  //reads straddling two blocks, the first one still in the cache from the
+  //read before, allocate no more than the block they decompress
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  reader.seek(500);
+  let before = allocations();
+  let mut pos = 500;
+  for _ in 1..blocks.len() {
+    assert_eq!(1000, reader.read_to(&mut block).unwrap());
+    assert_eq!(&content[pos..pos + 1000], &block[..]);
+    pos += 1000;
+  }
+  assert!(
+    allocations() - before <= blocks.len() + 6,
+    "{} allocations for {} blocks",
+    allocations() - before,
+    blocks.len()
+  );
+  fs::remove_file(path).unwrap();
+}
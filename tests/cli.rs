@@ -0,0 +1,138 @@
+//! End-to-end tests running the bgzf binary, only built with `--features cli`
+#![cfg(feature = "cli")]
+
+use bgzf_rust_reader::fixtures::{
+  make_bgzf, make_truncated, make_with_bad_crc, make_without_eof, write_temp,
+};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn test_content() -> Vec<u8> {
+  let mut content = Vec::new();
+  for i in 0..5000 {
+    content.extend_from_slice(format!("{}\tchr{}\tline for the cli tests\n", i, i % 7).as_bytes());
+  }
+  content
+}
+
+fn bgzf(args: &[&str], stdin: &[u8]) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_bgzf"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  child.stdin.take().unwrap().write_all(stdin).unwrap();
+  child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_compress_decompress_through_pipes() {
+  let content = test_content();
+  let compressed = bgzf(&["compress", "--level", "9", "-@", "2"], &content);
+  assert!(compressed.status.success());
+  assert_eq!(&[31, 139, 8, 4], &compressed.stdout[..4]);
+
+  let decompressed = bgzf(&["decompress"], &compressed.stdout);
+  assert!(decompressed.status.success());
+  assert_eq!(content, decompressed.stdout);
+
+  //stdin is read as a stream, a stream ending within a block fails
+  let truncated = bgzf(&["decompress"], &make_truncated(&content, 10_000, 100));
+  assert!(!truncated.status.success());
+}
+
+#[test]
+fn test_compress_decompress_files() {
+  let content = test_content();
+  let input = write_temp("cli_plain_input", &content).unwrap();
+  let compressed = format!("{}.compressed", input);
+  let decompressed = format!("{}.decompressed", input);
+
+  let output = bgzf(&["compress", &input, "-o", &compressed, "--index"], b"");
+  assert!(output.status.success());
+  assert!(fs::metadata(format!("{}.gzi", compressed)).is_ok());
+  let output = bgzf(&["decompress", &compressed, "-o", &decompressed], b"");
+  assert!(output.status.success());
+  assert_eq!(content, fs::read(&decompressed).unwrap());
+
+  //the index subcommand gives the same .gzi as the writer
+  let gzi = format!("{}.index.gzi", input);
+  let output = bgzf(&["index", &compressed, "-o", &gzi], b"");
+  assert!(output.status.success());
+  assert_eq!(
+    fs::read(format!("{}.gzi", compressed)).unwrap(),
+    fs::read(&gzi).unwrap()
+  );
+
+  for path in &[
+    input.clone(),
+    compressed.clone(),
+    format!("{}.gzi", compressed),
+    decompressed,
+    gzi,
+  ] {
+    fs::remove_file(path).unwrap();
+  }
+}
+
+#[test]
+fn test_inspect() {
+  let path = write_temp("cli_inspect", &make_bgzf(&test_content(), 60_000)).unwrap();
+  let output = bgzf(&["inspect", &path], b"");
+  assert!(output.status.success());
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  let lines = stdout.lines().collect::<Vec<_>>();
+  assert_eq!(
+    "block\tcompressed_offset\tuncompressed_offset\tblock_size\tuncompressed_length",
+    lines[0]
+  );
+  let content_length = test_content().len();
+  assert_eq!(1 + content_length.div_ceil(60_000), lines.len());
+  let second_block = lines[2].split('\t').collect::<Vec<_>>();
+  let first_block_size = lines[1].split('\t').nth(3).unwrap();
+  assert_eq!(
+    vec!["1", first_block_size, "60000", second_block[3], "60000"],
+    second_block
+  );
+  assert!(lines[1].starts_with("0\t0\t0\t"));
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_test_subcommand() {
+  let content = test_content();
+  let cases = vec![
+    ("cli_test_good", make_bgzf(&content, 10_000), None),
+    (
+      "cli_test_bad_crc",
+      make_with_bad_crc(&content, 10_000, 3),
+      Some("CRC32 mismatch"),
+    ),
+    (
+      "cli_test_without_eof",
+      make_without_eof(&content, 10_000),
+      Some("EOF marker"),
+    ),
+    (
+      "cli_test_truncated",
+      make_truncated(&content, 10_000, 100),
+      Some("truncated"),
+    ),
+  ];
+  for (name, bgzf_data, expected_error) in cases {
+    let path = write_temp(name, &bgzf_data).unwrap();
+    let output = bgzf(&["test", &path], b"");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    match expected_error {
+      None => assert!(output.status.success(), "{}: {}", name, stderr),
+      Some(expected_error) => {
+        assert!(!output.status.success(), "{}", name);
+        assert!(stderr.contains(expected_error), "{}: {}", name, stderr);
+      }
+    }
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,51 @@
+//Checks the library builds for 32-bit targets, where usize is narrower than
+//the u64 offsets into the data, for those of them installed with rustup, the
+//others being skipped. wasm32 has no files, the data is read from memory
+//there, see tests/wasm.rs.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+const TARGETS: [&str; 3] = [
+  "armv7-unknown-linux-gnueabihf",
+  "i686-unknown-linux-gnu",
+  "wasm32-unknown-unknown",
+];
+
+/// Tells whether the standard library of `target` is installed
+fn installed(target: &str) -> bool {
+  let rustc = env::var("RUSTC").unwrap_or_else(|_error| String::from("rustc"));
+  Command::new(rustc)
+    .args(["--print", "target-libdir", "--target", target])
+    .output()
+    .ok()
+    .filter(|output| output.status.success())
+    .is_some_and(|output| Path::new(String::from_utf8_lossy(&output.stdout).trim()).is_dir())
+}
+
+#[test]
+fn test_builds_for_32_bit_targets() {
+  for target in TARGETS {
+    if !installed(target) {
+      eprintln!("skipping {}, which is not installed", target);
+      continue;
+    }
+    //the pure Rust backend, libdeflate would need a C cross compiler
+    let status = Command::new(env!("CARGO"))
+      .args(["check", "--lib", "--no-default-features", "--features"])
+      .args(["rust-backend", "--target", target])
+      .current_dir(env!("CARGO_MANIFEST_DIR"))
+      .env(
+        "CARGO_TARGET_DIR",
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join("cross"),
+      )
+      .status()
+      .unwrap();
+    assert!(
+      status.success(),
+      "the library does not build for {}",
+      target
+    );
+  }
+}
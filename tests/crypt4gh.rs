@@ -0,0 +1,85 @@
+//Reads bgzf data encrypted with Crypt4GH: tests/data/htslib.vcf.gz.c4gh is
+//tests/data/htslib.vcf.gz encrypted by the crypt4gh command line tool of the
+//EGA (crypt4gh-rust 0.4.1) for tests/data/crypt4gh_recipient.pub, whose
+//secret key is protected by the passphrase "bgzf test":
+//crypt4gh encrypt --recipient_pk crypt4gh_recipient.pub < htslib.vcf.gz > htslib.vcf.gz.c4gh
+#![cfg(feature = "crypt4gh")]
+
+use bgzf_rust_reader::{crypt4gh_secret_key, BgzfError, BgzfReader};
+use std::io;
+
+const ENCRYPTED: &str = "tests/data/htslib.vcf.gz.c4gh";
+
+fn secret_key() -> Vec<u8> {
+  crypt4gh_secret_key(
+    String::from("tests/data/crypt4gh_recipient.sec"),
+    "bgzf test",
+  )
+  .unwrap()
+}
+
+#[test]
+fn test_reads_like_the_plaintext() {
+  let plain = BgzfReader::new(String::from("tests/data/htslib.vcf.gz")).unwrap();
+  let reader = BgzfReader::builder()
+    .open_crypt4gh(String::from(ENCRYPTED), &secret_key())
+    .unwrap();
+  assert!(plain.block_offsets().eq(reader.block_offsets()));
+  let content = plain.decompress_all_parallel(1).unwrap();
+  assert_eq!(content, reader.decompress_all_parallel(2).unwrap());
+  let report = reader.verify(2).unwrap();
+  assert!(report.failures.is_empty());
+  assert!(report.has_eof_marker);
+
+  //the block across the end of the first 64 KiB segment, and reads at
+  //random
+  let (block_index, block) = reader
+    .block_offsets()
+    .enumerate()
+    .find(|(_, block)| {
+      block.compressed_offset < 65536
+        && block.compressed_offset + u64::from(block.block_size) > 65536
+    })
+    .unwrap();
+  let mut data = vec![0; 1000];
+  for &pos in &[
+    block.uncompressed_offset + 10,
+    content.len() as u64 - 1000,
+    0,
+    block.uncompressed_offset - 500,
+  ] {
+    assert_eq!(1000, reader.read_at(pos, &mut data).unwrap());
+    assert_eq!(&content[pos as usize..pos as usize + 1000], &data[..]);
+  }
+  assert_eq!(
+    plain.raw_block(block_index).unwrap(),
+    reader.raw_block(block_index).unwrap()
+  );
+  let voffset = plain
+    .uoffset_to_voffset(block.uncompressed_offset + 77)
+    .unwrap();
+  reader.seek_virtual(voffset).unwrap();
+  reader.read_exact(&mut data).unwrap();
+  let start = block.uncompressed_offset as usize + 77;
+  assert_eq!(&content[start..start + 1000], &data[..]);
+}
+
+#[test]
+fn test_rejects_other_keys() {
+  //the key of a wrong passphrase is just another key
+  let wrong = crypt4gh_secret_key(
+    String::from("tests/data/crypt4gh_recipient.sec"),
+    "not the passphrase",
+  )
+  .unwrap();
+  for key in [wrong, vec![1; 32]] {
+    match BgzfReader::builder().open_crypt4gh(String::from(ENCRYPTED), &key) {
+      Err(BgzfError::Io(error)) => assert_eq!(io::ErrorKind::InvalidData, error.kind()),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+  assert!(BgzfReader::builder()
+    .open_crypt4gh(String::from("tests/data/htslib.vcf.gz"), &secret_key())
+    .is_err());
+  assert!(crypt4gh_secret_key(String::from("tests/data/crypt4gh_recipient.pub"), "").is_err());
+}
@@ -0,0 +1,119 @@
+//Region fetches of tests/data/sequences.fa.gz, a FASTA of 60 bases per line
+//in blocks of 100 bytes: chr1 of 250 bases, its last line of 10, chr2 of two
+//full lines, mito of a single short line and an empty chrUn_1. Its .fai is
+//the one `samtools faidx` writes for the uncompressed file.
+
+use bgzf_rust_reader::{BgzfError, BgzfReader, FaiError, FaiReader};
+use std::collections::HashMap;
+
+fn open() -> FaiReader {
+  let reader = BgzfReader::new(String::from("tests/data/sequences.fa.gz")).unwrap();
+  FaiReader::new(reader, String::from("tests/data/sequences.fa.gz.fai")).unwrap()
+}
+
+/// Returns the sequences of the fixture by name, read from the whole file
+fn sequences() -> HashMap<String, Vec<u8>> {
+  let data = BgzfReader::new(String::from("tests/data/sequences.fa.gz"))
+    .unwrap()
+    .decompress_all_parallel(1)
+    .unwrap();
+  let mut sequences = HashMap::new();
+  let mut name = String::new();
+  for line in String::from_utf8(data).unwrap().lines() {
+    match line.strip_prefix('>') {
+      Some(header) => {
+        name = header.split(' ').next().unwrap().to_string();
+        sequences.insert(name.clone(), Vec::new());
+      }
+      None => sequences
+        .get_mut(&name)
+        .unwrap()
+        .extend_from_slice(line.as_bytes()),
+    }
+  }
+  sequences
+}
+
+#[test]
+fn test_fetch_matches_sequences() {
+  let fasta = open();
+  let sequences = sequences();
+  assert_eq!(["chr1", "chr2", "mito", "chrUn_1"], fasta.sequence_names());
+  for name in ["chr1", "chr2", "mito"] {
+    let sequence = &sequences[name];
+    let length = sequence.len() as u64;
+    assert_eq!(Some(length), fasta.sequence_length(name));
+    for start in 1..=length {
+      for end in (start..=length).step_by(7).chain([length]) {
+        assert_eq!(
+          &sequence[start as usize - 1..end as usize],
+          &fasta.fetch(name, start, end).unwrap()[..],
+          "{}:{}-{}",
+          name,
+          start,
+          end
+        );
+      }
+    }
+  }
+}
+
+#[test]
+fn test_fetch_around_lines() {
+  let fasta = open();
+  let chr1 = &sequences()["chr1"];
+  //the last base of a line, the first of the next and both
+  assert_eq!(&chr1[59..60], &fasta.fetch("chr1", 60, 60).unwrap()[..]);
+  assert_eq!(&chr1[60..61], &fasta.fetch("chr1", 61, 61).unwrap()[..]);
+  assert_eq!(&chr1[59..61], &fasta.fetch("chr1", 60, 61).unwrap()[..]);
+  //the last, partial line, alone and with the line before
+  assert_eq!(&chr1[240..], &fasta.fetch("chr1", 241, 250).unwrap()[..]);
+  assert_eq!(&chr1[200..], &fasta.fetch("chr1", 201, 250).unwrap()[..]);
+  assert_eq!(&chr1[..], &fasta.fetch("chr1", 1, 250).unwrap()[..]);
+}
+
+#[test]
+fn test_fetch_errors() {
+  let fasta = open();
+  let out_of_range = |name: &str, start, end, length| {
+    Err::<Vec<u8>, _>(BgzfError::Fai(FaiError::OutOfRange {
+      name: name.to_string(),
+      start,
+      end,
+      length,
+    }))
+  };
+  for (start, end) in [(0, 10), (20, 10), (1, 251), (251, 251)] {
+    assert_eq!(
+      format!("{:?}", out_of_range("chr1", start, end, 250)),
+      format!("{:?}", fasta.fetch("chr1", start, end))
+    );
+  }
+  assert_eq!(
+    format!("{:?}", out_of_range("chrUn_1", 1, 1, 0)),
+    format!("{:?}", fasta.fetch("chrUn_1", 1, 1))
+  );
+  assert!(matches!(
+    fasta.fetch("chr3", 1, 1),
+    Err(BgzfError::Fai(FaiError::UnknownSequence { name })) if name == "chr3"
+  ));
+  assert_eq!(
+    "chr1:1-251 is not within the 250 bases of chr1",
+    fasta.fetch("chr1", 1, 251).unwrap_err().to_string()
+  );
+
+  //an index whose offsets are not the file's
+  let fai_path = std::env::temp_dir().join("bgzf_test_wrong.fai");
+  std::fs::write(&fai_path, "chr1\t250\t5\t60\t61\nchr2\t400\t267\t60\t61\n").unwrap();
+  let reader = BgzfReader::new(String::from("tests/data/sequences.fa.gz")).unwrap();
+  let wrong = FaiReader::new(reader, fai_path.to_string_lossy().into_owned()).unwrap();
+  std::fs::remove_file(fai_path).unwrap();
+  assert!(matches!(
+    wrong.fetch("chr1", 1, 250),
+    Err(BgzfError::Fai(FaiError::LineMismatch { .. }))
+  ));
+  assert!(matches!(
+    wrong.fetch("chr2", 390, 400),
+    Err(BgzfError::UnexpectedEof { .. })
+  ));
+}
@@ -0,0 +1,77 @@
+//Builds the library with the `ffi` feature, compiles tests/ffi/read.c
+//against include/bgzf_rs.h and the cdylib, and runs it. Skipped when no C
+//compiler is found, like the cross compilation test without its targets.
+#![cfg(feature = "ffi")]
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_c_program_reads_through_the_api() {
+  let compiler = env::var("CC").unwrap_or_else(|_error| String::from("cc"));
+  if Command::new(&compiler).arg("--version").output().is_err() {
+    eprintln!("skipping, no C compiler {}", compiler);
+    return;
+  }
+  let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+  let status = Command::new(env!("CARGO"))
+    .args(["build", "--lib", "--features", "ffi"])
+    .current_dir(manifest_dir)
+    .env("CARGO_TARGET_DIR", &target_dir)
+    .status()
+    .unwrap();
+  assert!(status.success(), "the library does not build");
+
+  let library_dir = target_dir.join("debug");
+  let program = target_dir.join("read");
+  let status = Command::new(&compiler)
+    .arg(manifest_dir.join("tests/ffi/read.c"))
+    .arg("-I")
+    .arg(manifest_dir.join("include"))
+    .arg("-L")
+    .arg(&library_dir)
+    .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+    .args(["-lbgzf_rust_reader", "-o"])
+    .arg(&program)
+    .status()
+    .unwrap();
+  assert!(status.success(), "read.c does not compile");
+
+  //cargo test puts target/debug first in the library path, where a build
+  //with other features can have left a library of the same name
+  let output = Command::new(&program)
+    .arg(manifest_dir.join("bgzf_test.bgz"))
+    .env("LD_LIBRARY_PATH", &library_dir)
+    .env("DYLD_LIBRARY_PATH", &library_dir)
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+}
+
+#[test]
+fn test_header_matches_the_api() {
+  let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let output = match Command::new("cbindgen")
+    .arg("--quiet")
+    .current_dir(manifest_dir)
+    .output()
+  {
+    Ok(output) => output,
+    Err(_error) => {
+      eprintln!("skipping, cbindgen is not installed");
+      return;
+    }
+  };
+  assert!(output.status.success());
+  let header = std::fs::read(manifest_dir.join("include/bgzf_rs.h")).unwrap();
+  assert!(
+    header == output.stdout,
+    "include/bgzf_rs.h is out of date, regenerate it with cbindgen"
+  );
+}
@@ -0,0 +1,44 @@
+/* Reads bgzf_test.bgz through the C API, run by tests/ffi.rs with the path
+ * of the file as its argument. Exits with 1 on the first check failing. */
+#include <stdio.h>
+#include <string.h>
+
+#include "bgzf_rs.h"
+
+#define CHECK(condition)                                     \
+  if (!(condition)) {                                        \
+    fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #condition); \
+    return 1;                                                \
+  }
+
+int main(int argc, char **argv) {
+  char buf[300];
+  CHECK(argc == 2);
+
+  BgzfHandle *missing = bgzf_rs_open("/nonexistent/file.bgz");
+  CHECK(missing == NULL);
+  CHECK(bgzf_rs_last_error(NULL) != NULL);
+
+  BgzfHandle *handle = bgzf_rs_open(argv[1]);
+  CHECK(handle != NULL);
+  CHECK(bgzf_rs_last_error(handle) == NULL);
+  CHECK(bgzf_rs_length(handle) == 280);
+
+  CHECK(bgzf_rs_read(handle, (uint8_t *)buf, 4) == 4);
+  CHECK(memcmp(buf, "This", 4) == 0);
+  CHECK(bgzf_rs_seek(handle, 8) == 0);
+  CHECK(bgzf_rs_read(handle, (uint8_t *)buf, 4) == 4);
+  CHECK(memcmp(buf, "just", 4) == 0);
+  CHECK(bgzf_rs_read(handle, (uint8_t *)buf, sizeof buf) == 268);
+  CHECK(bgzf_rs_read(handle, (uint8_t *)buf, sizeof buf) == 0);
+
+  CHECK(bgzf_rs_read(handle, NULL, 10) == -1);
+  const char *error = bgzf_rs_last_error(handle);
+  CHECK(error != NULL && strcmp(error, "The buffer is NULL") == 0);
+  CHECK(bgzf_rs_seek(handle, 0) == 0);
+  CHECK(bgzf_rs_last_error(handle) == NULL);
+
+  bgzf_rs_close(handle);
+  bgzf_rs_close(NULL);
+  return 0;
+}
@@ -0,0 +1,126 @@
+//Random access to tests/data/multi_member.txt.gz, 12000 lines in four
+//members laid out the way pigz writes them and `cat` joins them: zlib
+//deflate at level 6 with a sync flush every 32 KiB of data, the first
+//member naming the file, the second one empty. It was written by Python's
+//zlib, pigz not being available where it was made, and reads back with
+//gzip.
+#![cfg(feature = "gzip")]
+
+use bgzf_rust_reader::GzipRandomReader;
+use std::io::Read;
+
+const DATA_PATH: &str = "tests/data/multi_member.txt.gz";
+
+/// The lines of the fixture
+fn content() -> Vec<u8> {
+  (0..12000u64)
+    .flat_map(|i| {
+      format!(
+        "{}\tchr{}\t{}\tmulti-member gzip\n",
+        i,
+        i % 23,
+        i * 7919 % 100003
+      )
+      .into_bytes()
+    })
+    .collect()
+}
+
+fn open(spacing: u64) -> GzipRandomReader {
+  GzipRandomReader::builder()
+    .checkpoint_spacing(spacing)
+    .open(String::from(DATA_PATH))
+    .unwrap()
+}
+
+#[test]
+fn test_checkpoints_of_members_and_flush_points() {
+  let reader = open(40_000);
+  let checkpoints = reader.index().checkpoints();
+  let members = checkpoints
+    .iter()
+    .filter(|checkpoint| checkpoint.member_start)
+    .map(|checkpoint| checkpoint.uncompressed_offset)
+    .collect::<Vec<_>>();
+  assert_eq!(vec![0, 100000, 100000, 230000], members);
+  //the first flush point at least 40000 bytes after the checkpoint before
+  for pair in checkpoints.windows(2).filter(|pair| !pair[1].member_start) {
+    let distance = pair[1].uncompressed_offset - pair[0].uncompressed_offset;
+    assert!((40_000..40_000 + 32768).contains(&distance), "{}", distance);
+    assert_eq!(32768, pair[1].window_length());
+  }
+  assert!(checkpoints.len() > 4 + 3);
+  assert_eq!(content().len() as u64, reader.total_uncompressed_length());
+
+  //every deflate block boundary: the sync flushes every 32 KiB of each
+  //member, the only ones Python's zlib made at this size
+  let offsets = open(1)
+    .index()
+    .checkpoints()
+    .iter()
+    .map(|checkpoint| checkpoint.uncompressed_offset)
+    .collect::<Vec<_>>();
+  let mut expected = Vec::new();
+  for (start, end) in [
+    (0, 100000),
+    (100000, 100000),
+    (100000, 230000),
+    (230000, 414339),
+  ] {
+    expected.extend((start..end.max(start + 1)).step_by(32768));
+  }
+  assert_eq!(expected, offsets);
+}
+
+#[test]
+fn test_seeks_around_checkpoints() {
+  let content = content();
+  for spacing in [1, 40_000, 1 << 20] {
+    let reader = open(spacing);
+    let mut offsets = Vec::new();
+    for checkpoint in reader.index().checkpoints() {
+      let at = checkpoint.uncompressed_offset;
+      offsets.extend([
+        at.saturating_sub(1000),
+        at.saturating_sub(1),
+        at,
+        at + 1,
+        at + 1000,
+      ]);
+    }
+    //backwards, then forwards, so that reads restart from a checkpoint as
+    //well as go on from the read before
+    let forwards = offsets.clone();
+    offsets.reverse();
+    offsets.extend(forwards);
+    let mut buf = vec![0; 3000];
+    for pos in offsets {
+      let pos = pos.min(content.len() as u64);
+      reader.seek(pos);
+      let read = (&reader).read(&mut buf).unwrap();
+      let expected = &content[pos as usize..(pos as usize + 3000).min(content.len())];
+      assert_eq!(expected, &buf[..read], "{} with spacing {}", pos, spacing);
+      assert_eq!(pos + read as u64, reader.position());
+    }
+  }
+}
+
+#[test]
+fn test_sequential_reads_cross_members() {
+  let content = content();
+  let reader = open(40_000);
+  let mut all = Vec::new();
+  let mut buf = [0; 777];
+  loop {
+    match (&reader).read(&mut buf).unwrap() {
+      0 => break,
+      read => all.extend_from_slice(&buf[..read]),
+    }
+  }
+  assert_eq!(content, all);
+
+  //a read across the end of the first member and the empty one
+  let mut across = vec![0; 200];
+  assert_eq!(200, reader.read_at(99900, &mut across).unwrap());
+  assert_eq!(&content[99900..100100], &across[..]);
+}
@@ -0,0 +1,144 @@
+//Virtual offsets of tests/data/htslib.vcf.gz, a VCF of 5000 lines on chr1
+//and chr2 laid out the way bgzip of htslib built with zlib writes it: blocks
+//of 0xff00 uncompressed bytes at zlib's default level, lines crossing the
+//block boundaries. Its .gzi is the one `bgzip -i` writes, its .tbi was
+//written by the tabix indexer of noodles-vcf 0.94 (`vcf::fs::index`), which
+//ends the chunk of the last line right after the EOF marker, where htslib
+//stops reading too. The offsets htslib computes are derived from the .gzi
+//the way bgzf_useek does, and must round-trip through the reader.
+
+use bgzf_rust_reader::BgzfReader;
+use std::convert::TryInto;
+use std::fs;
+
+const DATA_PATH: &str = "tests/data/htslib.vcf.gz";
+
+/// The (compressed, uncompressed) offsets of the blocks, from the .gzi and
+/// the first block, which it leaves out
+fn gzi_blocks() -> Vec<(u64, u64)> {
+  let gzi = fs::read("tests/data/htslib.vcf.gz.gzi").unwrap();
+  let word = |i: usize| u64::from_le_bytes(gzi[i * 8..i * 8 + 8].try_into().unwrap());
+  let mut blocks = vec![(0, 0)];
+  blocks.extend((0..word(0) as usize).map(|entry| (word(1 + 2 * entry), word(2 + 2 * entry))));
+  blocks
+}
+
+/// The virtual offset htslib gives `uoffset`, the block holding it being the
+/// last one starting at or before it, as bgzf_useek finds it
+fn htslib_voffset(blocks: &[(u64, u64)], uoffset: u64) -> u64 {
+  let (compressed, uncompressed) =
+    blocks[blocks.partition_point(|&(_, start)| start <= uoffset) - 1];
+  compressed << 16 | (uoffset - uncompressed)
+}
+
+fn content() -> Vec<u8> {
+  BgzfReader::new(String::from(DATA_PATH))
+    .unwrap()
+    .decompress_all_parallel(1)
+    .unwrap()
+}
+
+/// Offsets around every block boundary, the start of every 97th line, and
+/// the first and last bytes
+fn probes(content: &[u8], blocks: &[(u64, u64)]) -> Vec<u64> {
+  let mut probes = vec![0, 1, content.len() as u64 - 1];
+  for &(_, start) in &blocks[1..] {
+    probes.extend([start - 1, start, start + 1]);
+  }
+  let line_starts = content
+    .iter()
+    .enumerate()
+    .filter(|&(_, &byte)| byte == b'\n')
+    .map(|(i, _)| i as u64 + 1);
+  probes.extend(
+    line_starts
+      .step_by(97)
+      .filter(|&start| start < content.len() as u64),
+  );
+  probes
+}
+
+#[test]
+fn test_gzi_matches_bgzip() {
+  let reader = BgzfReader::new(String::from(DATA_PATH)).unwrap();
+  let mut gzi = Vec::new();
+  reader.write_gzi(&mut gzi).unwrap();
+  assert_eq!(fs::read("tests/data/htslib.vcf.gz.gzi").unwrap(), gzi);
+  let blocks = reader
+    .block_offsets()
+    .map(|block| (block.compressed_offset, block.uncompressed_offset))
+    .collect::<Vec<_>>();
+  assert_eq!(gzi_blocks(), blocks);
+  assert!(reader
+    .block_offsets()
+    .take(blocks.len() - 1)
+    .all(|block| block.uncompressed_length == 0xff00));
+}
+
+#[test]
+fn test_offsets_round_trip_with_htslib() {
+  let content = content();
+  let blocks = gzi_blocks();
+  let reader = BgzfReader::new(String::from(DATA_PATH)).unwrap();
+  let mut line = vec![0; 40];
+  for uoffset in probes(&content, &blocks) {
+    let voffset = htslib_voffset(&blocks, uoffset);
+    assert_eq!(voffset, reader.uoffset_to_voffset(uoffset).unwrap());
+    assert_eq!(uoffset, reader.voffset_to_uoffset(voffset).unwrap());
+    assert!(reader.is_valid_voffset(voffset));
+    assert_eq!(voffset, reader.clamp_voffset(voffset));
+
+    reader.seek_virtual(voffset).unwrap();
+    let length = reader.read_to(&mut line).unwrap();
+    assert_eq!(&content[uoffset as usize..][..length], &line[..length]);
+    let end = uoffset + length as u64;
+    if end < content.len() as u64 {
+      assert_eq!(htslib_voffset(&blocks, end), reader.tell_virtual());
+    }
+  }
+
+  //htslib also gives the start of a block as the end of the one before it
+  for pair in blocks.windows(2) {
+    let ((previous, previous_start), (_, start)) = (pair[0], pair[1]);
+    let voffset = previous << 16 | (start - previous_start);
+    assert!(reader.is_valid_voffset(voffset));
+    assert_eq!(start, reader.voffset_to_uoffset(voffset).unwrap());
+    assert!(!reader.is_valid_voffset(voffset + 1));
+    assert_eq!(voffset, reader.clamp_voffset(voffset + 1));
+  }
+}
+
+#[test]
+#[cfg(feature = "tabix")]
+fn test_tabix_offsets_start_lines() {
+  use bgzf_rust_reader::TabixIndex;
+
+  let content = content();
+  let reader = BgzfReader::new(String::from(DATA_PATH)).unwrap();
+  let index = TabixIndex::open(String::from("tests/data/htslib.vcf.gz.tbi")).unwrap();
+  let mut chunk_ends = Vec::new();
+  for reference in ["chr1", "chr2"] {
+    for start in (0..5_000_000).step_by(250_000) {
+      for (chunk_start, chunk_end) in index.chunks(reference, start, start + 250_000).unwrap() {
+        for voffset in [chunk_start, chunk_end] {
+          assert!(reader.is_valid_voffset(voffset), "{:x}", voffset);
+          assert_eq!(voffset, reader.clamp_voffset(voffset));
+          let uoffset = reader.voffset_to_uoffset(voffset).unwrap() as usize;
+          assert!(uoffset == content.len() || content[uoffset - 1] == b'\n');
+        }
+        chunk_ends.push(chunk_end);
+        reader.seek_virtual(chunk_start).unwrap();
+        let mut chunk = vec![0; 9];
+        reader.read_to(&mut chunk).unwrap();
+        assert!(chunk.starts_with(reference.as_bytes()));
+      }
+    }
+  }
+  //the chunk of the last line ends right after the EOF marker
+  let eof_end = reader.compressed_length_with_eof().unwrap() << 16;
+  assert_eq!(Some(&eof_end), chunk_ends.iter().max());
+  assert_eq!(
+    content.len() as u64,
+    reader.voffset_to_uoffset(eof_end).unwrap()
+  );
+}
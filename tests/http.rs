@@ -0,0 +1,204 @@
+//Reads of bgzf files served by a local HTTP server supporting range
+//requests, like object stores do, made of a thread answering one request
+//per connection and counting them.
+#![cfg(feature = "http")]
+
+use bgzf_rust_reader::fixtures::make_bgzf;
+use bgzf_rust_reader::{BgzfError, BgzfReader, HttpTransport};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// The server: where it listens and how many requests it answered
+struct Server {
+  url: String,
+  requests: Arc<AtomicU64>,
+}
+
+impl Server {
+  /// Serves `data` at /data.bgz with range requests and at /plain.bgz
+  /// without, answering any other path with a 404
+  fn start(data: Vec<u8>) -> Server {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicU64::new(0));
+    let counted = Arc::clone(&requests);
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        counted.fetch_add(1, Ordering::SeqCst);
+        //a client closing early is not the server's problem
+        let _ = answer(stream.unwrap(), &data);
+      }
+    });
+    Server { url, requests }
+  }
+
+  fn requests(&self) -> u64 {
+    self.requests.load(Ordering::SeqCst)
+  }
+}
+
+/// Answers the request read from `stream`
+fn answer(mut stream: TcpStream, data: &[u8]) -> io::Result<()> {
+  let mut lines = BufReader::new(stream.try_clone()?).lines();
+  let request_line = lines.next().unwrap_or(Ok(String::new()))?;
+  let path = request_line.split(' ').nth(1).unwrap_or("");
+  let mut range = None;
+  for line in lines {
+    let line = line?;
+    if line.is_empty() {
+      break;
+    }
+    if let Some(bytes) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+      let (first, last) = bytes.split_once('-').unwrap();
+      range = Some((first.parse::<u64>().unwrap(), last.parse::<u64>().unwrap()));
+    }
+  }
+  let length = data.len() as u64;
+  let (status, headers, body) = match (path, range) {
+    ("/data.bgz", Some((first, _last))) if first >= length => (
+      "416 Range Not Satisfiable",
+      format!("Content-Range: bytes */{}\r\n", length),
+      &[][..],
+    ),
+    ("/data.bgz", Some((first, last))) => {
+      let last = last.min(length - 1);
+      (
+        "206 Partial Content",
+        format!("Content-Range: bytes {}-{}/{}\r\n", first, last, length),
+        &data[first as usize..=last as usize],
+      )
+    }
+    ("/data.bgz", None) | ("/plain.bgz", _) => ("200 OK", String::new(), data),
+    _ => ("404 Not Found", String::new(), &[][..]),
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+    status,
+    headers,
+    body.len()
+  )?;
+  stream.write_all(body)
+}
+
+/// About 1.7 MB of lines, which in blocks of 10000 bytes compress to a
+/// dozen chunks of 64 KiB
+fn content() -> Vec<u8> {
+  let mut content = Vec::new();
+  let mut state: u32 = 1;
+  for i in 0..80000 {
+    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+    content.extend_from_slice(format!("{}\tchr{}\t{:08x}\n", i, i % 23, state).as_bytes());
+  }
+  content
+}
+
+fn read_region(reader: &BgzfReader, start: u64, length: usize) -> Vec<u8> {
+  let mut buf = vec![0; length];
+  reader.read_at(start, &mut buf).unwrap();
+  buf
+}
+
+#[test]
+fn test_reads_remote_file() {
+  let content = content();
+  let server = Server::start(make_bgzf(&content, 10000));
+  let reader = BgzfReader::builder()
+    .fetch_size(64 * 1024)
+    .open_url(&format!("{}/data.bgz", server.url))
+    .unwrap();
+  assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+  assert_eq!(
+    &content[123456..133456],
+    &read_region(&reader, 123456, 10000)[..]
+  );
+  assert_eq!(content, reader.decompress_all_parallel(2).unwrap());
+
+  //the chunks are cached, reading blocks again fetches nothing
+  let requests = server.requests();
+  assert_eq!(
+    &content[500000..500100],
+    &read_region(&reader, 500000, 100)[..]
+  );
+  assert_eq!(requests, server.requests());
+  let stats = reader.fetch_stats();
+  assert!(stats.hits > 0);
+  //the length, then a request per chunk of the whole file
+  assert_eq!(requests, stats.misses + 1);
+}
+
+#[test]
+fn test_opens_with_index_without_reading_the_whole_file() {
+  let content = content();
+  let bgzf = make_bgzf(&content, 10000);
+  let local = BgzfReader::from_bytes(bgzf.clone()).unwrap();
+  let mut gzi = Vec::new();
+  local.write_gzi(&mut gzi).unwrap();
+  let server = Server::start(bgzf.clone());
+  let url = format!("{}/data.bgz", server.url);
+
+  let builder = BgzfReader::builder().fetch_size(64 * 1024);
+  let indexed = builder
+    .clone()
+    .open_remote_with_index(HttpTransport::new(&url), &local.index().unwrap())
+    .unwrap();
+  let from_gzi = builder
+    .clone()
+    .open_remote_with_gzi(HttpTransport::new(&url), &gzi)
+    .unwrap();
+  let lazy = builder.lazy(true).open_url(&url).unwrap();
+  for reader in [indexed, from_gzi, lazy] {
+    let before = server.requests();
+    assert_eq!(
+      &content[20000..20100],
+      &read_region(&reader, 20000, 100)[..]
+    );
+    //the first chunk, the last one and the one read, of more than ten
+    assert!(
+      reader.fetch_stats().misses <= 3,
+      "{:?}",
+      reader.fetch_stats()
+    );
+    assert!(server.requests() - before <= 2);
+  }
+  assert!(bgzf.len() > 10 * 64 * 1024);
+}
+
+#[test]
+fn test_remote_errors() {
+  let server = Server::start(make_bgzf(b"some data", 100));
+  let error = BgzfReader::builder()
+    .open_url(&format!("{}/missing.bgz", server.url))
+    .err()
+    .unwrap();
+  assert!(
+    matches!(&error, BgzfError::Io(error) if error.kind() == io::ErrorKind::NotFound),
+    "{:?}",
+    error
+  );
+  let error = BgzfReader::builder()
+    .open_url(&format!("{}/plain.bgz", server.url))
+    .err()
+    .unwrap();
+  assert!(
+    matches!(&error, BgzfError::Io(error) if error.kind() == io::ErrorKind::Unsupported),
+    "{:?}",
+    error
+  );
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let closed = format!("http://{}/data.bgz", listener.local_addr().unwrap());
+  drop(listener);
+  assert!(matches!(
+    BgzfReader::builder().open_url(&closed),
+    Err(BgzfError::Io(_))
+  ));
+
+  let empty = Server::start(Vec::new());
+  let reader = BgzfReader::builder()
+    .open_url(&format!("{}/data.bgz", empty.url))
+    .unwrap();
+  assert_eq!(0, reader.total_uncompressed_length());
+}
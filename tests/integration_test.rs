@@ -1,28 +1,170 @@
+use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
 use bgzf_rust_reader::BgzfReader;
+use std::collections::HashMap;
+use std::fs;
 use std::str;
+use std::sync::Arc;
+use std::thread;
+
+const TEST_CONTENT: &str = "This is just a bgzf test,lets see how it reacts. :). I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarantee that it will work. So I am just trying to test bgzf with this text file. Have a great day software lovers.\n";
 
 #[test]
 fn test_total_uncompressed_length() {
-  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-  let test_content = "This is just a bgzf test,lets see how it reacts. :). I think it will work fine, but who knows this is still a software. Unless you have tested it 100% there is no guarantee that it will work. So I am just trying to test bgzf with this text file. Have a great day software lovers. ";
+  let path = write_temp("total_length", &make_bgzf(TEST_CONTENT.as_bytes(), 64)).unwrap();
+  let reader = BgzfReader::new(path.clone()).unwrap();
   assert_eq!(
     reader.total_uncompressed_length(),
-    test_content.len() as u64
-  )
+    TEST_CONTENT.len() as u64
+  );
+  fs::remove_file(path).unwrap();
 }
 
 #[test]
 fn test_random_access() {
-  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
-  //In file bgzf_test (the uncompressed version) the 29th position in the file,
+  let path = write_temp("random_access", &make_bgzf(TEST_CONTENT.as_bytes(), 64)).unwrap();
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  //In the uncompressed content the 29th position in the file,
   //"This is just a bgzf test,lets" is upto this point
   reader.seek(29);
   let mut test_buffer = vec![0; 20];
   //reading 20 bytes to the vector test_buffer
-  reader.read_to(&mut test_buffer);
+  reader.read_to(&mut test_buffer).unwrap();
   //the 20 bytes after 29th position in file is " see how it reacts. "
   assert_eq!(
     " see how it reacts. ",
     str::from_utf8(&test_buffer).unwrap()
   );
+
+  //the same from the 4th block, on a fresh reader
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  reader.seek(197);
+  let mut test_buffer = vec![0; 20];
+  reader.read_to(&mut test_buffer).unwrap();
+  assert_eq!(
+    "am just trying to te",
+    str::from_utf8(&test_buffer).unwrap()
+  );
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_checked_in_file_matches_fixture() {
+  let reader = BgzfReader::new(String::from("bgzf_test.bgz")).unwrap();
+  let mut content = vec![0; TEST_CONTENT.len()];
+  reader.read_to(&mut content).unwrap();
+  assert_eq!(TEST_CONTENT, str::from_utf8(&content).unwrap());
+}
+
+/// Data that does not repeat, every chunk of it being found once
+fn shared_data() -> Vec<u8> {
+  let mut state = 0x9e37_79b9_u32;
+  (0..100_000)
+    .map(|_n| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      b'a' + (state % 16) as u8
+    })
+    .collect()
+}
+
+#[test]
+fn test_reader_shared_between_threads() {
+  fn shared<T: Send + Sync>(_reader: &T) {}
+  let data = shared_data();
+  let path = write_temp("shared_reader", &make_bgzf(&data, 1000)).unwrap();
+  //where every thread reads, and what a single thread reads there
+  let reads: Vec<Vec<(u64, usize)>> = (0..8u64)
+    .map(|thread| {
+      (0..200u64)
+        .map(|n| {
+          (
+            (thread * 7919 + n * 104_729) % 100_500,
+            1 + (n * 37 % 2500) as usize,
+          )
+        })
+        .collect()
+    })
+    .collect();
+  let serial = BgzfReader::new(path.clone()).unwrap();
+  let baseline: Vec<Vec<Vec<u8>>> = reads
+    .iter()
+    .map(|reads| {
+      reads
+        .iter()
+        .map(|&(pos, len)| {
+          let mut content = vec![0; len];
+          let read = serial.read_at(pos, &mut content).unwrap();
+          content.truncate(read);
+          content
+        })
+        .collect()
+    })
+    .collect();
+  for (reads, contents) in reads.iter().zip(&baseline) {
+    for (&(pos, len), content) in reads.iter().zip(contents) {
+      let start = (pos as usize).min(data.len());
+      assert_eq!(&data[start..(start + len).min(data.len())], &content[..]);
+    }
+  }
+
+  let builders = vec![
+    BgzfReader::builder(),
+    BgzfReader::builder().lazy(true),
+    BgzfReader::builder().lazy(true).background_indexing(true),
+    BgzfReader::builder().cache_bytes(8000),
+    BgzfReader::builder().cache_bytes(8000).prefetch(4),
+    BgzfReader::builder().compressed_cache_bytes(8000),
+  ];
+  for builder in builders {
+    let reader = Arc::new(builder.open(path.clone()).unwrap());
+    shared(&reader);
+    let threads = reads
+      .iter()
+      .cloned()
+      .map(|reads| {
+        let reader = Arc::clone(&reader);
+        thread::spawn(move || {
+          reads
+            .into_iter()
+            .map(|(pos, len)| {
+              let mut content = vec![0; len];
+              let read = reader.read_at(pos, &mut content).unwrap();
+              content.truncate(read);
+              content
+            })
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect::<Vec<_>>();
+    for (thread, expected) in threads.into_iter().zip(&baseline) {
+      assert_eq!(expected, &thread.join().unwrap());
+    }
+    //reads at positions leave the reader's alone
+    assert_eq!(0, reader.pos.get());
+
+    //sequential reads from threads each get the next chunk of the data
+    let chunks: HashMap<&[u8], usize> = data.chunks(100).zip(0..).collect();
+    let threads = (0..4)
+      .map(|_thread| {
+        let reader = Arc::clone(&reader);
+        thread::spawn(move || {
+          let mut chunks = Vec::new();
+          let mut chunk = vec![0; 100];
+          while reader.read_to(&mut chunk).unwrap() > 0 {
+            chunks.push(chunk.clone());
+          }
+          chunks
+        })
+      })
+      .collect::<Vec<_>>();
+    let mut read: Vec<usize> = threads
+      .into_iter()
+      .flat_map(|thread| thread.join().unwrap())
+      .map(|chunk| chunks[&chunk[..]])
+      .collect();
+    read.sort_unstable();
+    assert_eq!((0..1000).collect::<Vec<_>>(), read);
+  }
+  fs::remove_file(path).unwrap();
 }
@@ -0,0 +1,89 @@
+//Writes a bgzf file larger than 4 GiB, compressed and uncompressed, and
+//reads it around the offsets where 32 bit arithmetic would break. It takes
+//minutes and 5 GiB of disk, so it only runs on demand:
+//cargo test --release --test large_file -- --ignored
+
+use bgzf_rust_reader::fixtures::{offset_pattern, write_temp};
+use bgzf_rust_reader::{BgzfReader, BgzfWriter};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+/// Uncompressed length of the file, past 2^32 by half a GiB
+const LENGTH: u64 = (1 << 32) + (1 << 29);
+
+/// Writes `LENGTH` bytes of `offset_pattern` data as stored blocks, which
+/// are written without compressing and are larger than their data
+fn write_large_file() -> String {
+  let path = write_temp("large_file", b"").unwrap();
+  let mut writer = BgzfWriter::builder()
+    .compression_level(0)
+    .threads(4)
+    .build(BufWriter::new(File::create(&path).unwrap()))
+    .unwrap();
+  let mut chunk = vec![0; 1 << 24];
+  let mut offset = 0;
+  while offset < LENGTH {
+    let length = chunk.len().min((LENGTH - offset) as usize);
+    offset_pattern(offset, &mut chunk[..length]);
+    writer.write_all(&chunk[..length]).unwrap();
+    offset += length as u64;
+  }
+  writer.finish().unwrap().flush().unwrap();
+  path
+}
+
+#[test]
+#[ignore]
+fn test_reads_past_4_gib() {
+  let path = write_large_file();
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  assert_eq!(LENGTH, reader.total_uncompressed_length());
+  assert!(reader.compressed_length() > LENGTH);
+
+  //around 2^31 and 2^32 in the data, and in the file
+  let mut positions = vec![(1 << 31) - 1, 1 << 31, (1 << 32) - 1, 1 << 32];
+  for compressed in [1u64 << 31, 1 << 32] {
+    let voffset = reader
+      .block_at_compressed_offset(compressed)
+      .map(|info| info.compressed_offset << 16)
+      .unwrap();
+    positions.push(reader.voffset_to_uoffset(voffset).unwrap());
+  }
+  let mut expected = vec![0; 200_000];
+  let mut data = vec![0; 200_000];
+  for &uoffset in &positions {
+    let start = uoffset - 100_000;
+    offset_pattern(start, &mut expected);
+    reader.seek(start);
+    reader.read_exact(&mut data).unwrap();
+    assert_eq!(expected, data, "read at {}", start);
+    data.fill(0);
+    assert_eq!(data.len(), reader.read_at(start, &mut data).unwrap());
+    assert_eq!(expected, data, "read_at {}", start);
+
+    let voffset = reader.uoffset_to_voffset(uoffset).unwrap();
+    assert_eq!(uoffset, reader.voffset_to_uoffset(voffset).unwrap());
+    reader.seek_virtual(voffset).unwrap();
+    assert_eq!(voffset, reader.tell_virtual());
+    reader.read_exact(&mut data[..8]).unwrap();
+    offset_pattern(uoffset, &mut expected[..8]);
+    assert_eq!(expected[..8], data[..8]);
+  }
+
+  //the index of the whole file, through a gzi file
+  let gzi_path = format!("{}.gzi", path);
+  reader.save_gzi(gzi_path.clone()).unwrap();
+  let reopened = BgzfReader::open_with_gzi(path.clone(), gzi_path.clone()).unwrap();
+  assert!(reader.block_offsets().eq(reopened.block_offsets()));
+  offset_pattern(LENGTH - 100, &mut expected[..100]);
+  assert_eq!(100, reopened.read_at(LENGTH - 100, &mut data).unwrap());
+  assert_eq!(expected[..100], data[..100]);
+
+  let report = reader.verify(4).unwrap();
+  assert!(report.failures.is_empty());
+  assert!(report.has_eof_marker);
+  assert_eq!(LENGTH, report.uncompressed_bytes);
+  drop((reader, reopened));
+  fs::remove_file(gzi_path).unwrap();
+  fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,139 @@
+//Feeds damaged copies of a bgzf file to the reader, which has to fail with
+//an error on them, never panic
+
+use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+use bgzf_rust_reader::BgzfReader;
+use std::fs;
+use std::io::Read;
+
+fn content() -> Vec<u8> {
+  let mut content = Vec::new();
+  for i in 0..400 {
+    content.extend_from_slice(format!("{}\tchr{}\tmalformed input\n", i, i % 3).as_bytes());
+  }
+  content
+}
+
+/// xorshift64, so that every run damages the file the same way
+struct Random(u64);
+
+impl Random {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  fn below(&mut self, bound: usize) -> usize {
+    (self.next() % bound as u64) as usize
+  }
+}
+
+/// Opens `bgzf` every way and reads it whole, from the middle and through
+/// `Read`, the results are ignored, only panics fail
+fn read_everything(path: &str, bgzf: &[u8], random: &mut Random) {
+  fs::write(path, bgzf).unwrap();
+  let readers = vec![
+    BgzfReader::new(String::from(path)),
+    BgzfReader::builder()
+      .allow_truncated(true)
+      .open(String::from(path)),
+  ];
+  for mut reader in readers.into_iter().flatten() {
+    let mut buffer = vec![0; 1 + random.below(5000)];
+    while let Ok(read) = reader.read_to(&mut buffer) {
+      if read == 0 {
+        break;
+      }
+    }
+    let length = reader.total_uncompressed_length();
+    reader.seek(random.next() % (length + 10));
+    let rest = buffer.len() - 1;
+    let _ = reader.read(&mut buffer, 1, rest);
+    let _ = reader.verify_blocks(0..reader.num_blocks());
+    reader.seek(0);
+    let _ = reader.read_to_end(&mut Vec::new());
+  }
+}
+
+#[test]
+fn test_truncated_at_every_boundary() {
+  let bgzf = make_bgzf(&content(), 1000);
+  let path = write_temp("malformed_truncated", &bgzf).unwrap();
+  let mut random = Random(0x2545_f491_4f6c_dd1d);
+  //inside the first block and its header byte by byte, then every 7 bytes
+  for length in (0..64).chain((64..bgzf.len()).step_by(7)) {
+    read_everything(&path, &bgzf[..length], &mut random);
+  }
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_bad_extra_fields() {
+  let bgzf = make_bgzf(&content(), 1000);
+  let path = write_temp("malformed_extra", &bgzf).unwrap();
+  let mut random = Random(0x9e37_79b9_7f4a_7c15);
+  //XLEN of 0, 1 and longer than the block, then BSIZE and the subfield
+  //length too small or too large
+  for &(at, value) in &[
+    (10, 0u16),
+    (10, 1),
+    (10, 5),
+    (10, u16::MAX),
+    (16, 0),
+    (16, 24),
+    (16, u16::MAX),
+    (14, 0),
+    (14, u16::MAX),
+  ] {
+    let mut damaged = bgzf.clone();
+    damaged[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    read_everything(&path, &damaged, &mut random);
+  }
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_garbage_after_the_first_block() {
+  let bgzf = make_bgzf(&content(), 1000);
+  let path = write_temp("malformed_garbage", &bgzf).unwrap();
+  let mut random = Random(0xdead_beef_cafe_f00d);
+  let first_block = usize::from(u16::from_le_bytes([bgzf[16], bgzf[17]])) + 1;
+  for garbage_length in &[1, 17, 18, 100, 70_000] {
+    let mut damaged = bgzf[..first_block].to_vec();
+    damaged.extend((0..*garbage_length).map(|_| random.next() as u8));
+    read_everything(&path, &damaged, &mut random);
+    //a gzip header start among the garbage
+    if *garbage_length >= 4 {
+      damaged[first_block..first_block + 4].copy_from_slice(&[31, 139, 8, 4]);
+      read_everything(&path, &damaged, &mut random);
+    }
+  }
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_random_mutations() {
+  let bgzf = make_bgzf(&content(), 1000);
+  let path = write_temp("malformed_mutations", &bgzf).unwrap();
+  let mut random = Random(0x0123_4567_89ab_cdef);
+  for _mutation in 0..1500 {
+    let mut damaged = bgzf.clone();
+    //a few bytes overwritten, mostly in the header and footer of a block
+    for _byte in 0..1 + random.below(4) {
+      let at = if random.below(2) == 0 {
+        random.below(damaged.len())
+      } else {
+        let block = random.below(damaged.len() / 100) * 100;
+        (block + random.below(26)).min(damaged.len() - 1)
+      };
+      damaged[at] = random.next() as u8;
+    }
+    if random.below(4) == 0 {
+      damaged.truncate(random.below(damaged.len()));
+    }
+    read_everything(&path, &damaged, &mut random);
+  }
+  fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,58 @@
+//Round trips of virtual positions between this crate and noodles-bgzf, both
+//reading the same file.
+#![cfg(feature = "noodles")]
+
+use bgzf_rust_reader::fixtures::{make_bgzf, write_temp};
+use bgzf_rust_reader::BgzfReader;
+use noodles_bgzf::VirtualPosition;
+use std::fs::{self, File};
+use std::io::Read;
+
+/// Offsets at the start, in the middle and at the end of blocks of 100 bytes
+const OFFSETS: [u64; 7] = [0, 1, 99, 150, 250, 401, 499];
+
+fn data() -> Vec<u8> {
+  (0..500u32).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[test]
+fn test_noodles_positions_drive_reads() {
+  let data = data();
+  let path = write_temp("noodles_to_reader", &make_bgzf(&data, 100)).unwrap();
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  for &offset in OFFSETS.iter() {
+    let mut noodles_reader = noodles_bgzf::io::Reader::new(File::open(&path).unwrap());
+    let mut skipped = vec![0; offset as usize];
+    noodles_reader.read_exact(&mut skipped).unwrap();
+    let position = noodles_reader.virtual_position();
+
+    reader.seek_virtual_position(position).unwrap();
+    assert_eq!(offset, reader.pos.get());
+    let mut content = vec![0; 10.min(data.len() - offset as usize)];
+    reader.read_to(&mut content).unwrap();
+    let mut expected = vec![0; content.len()];
+    noodles_reader.read_exact(&mut expected).unwrap();
+    assert_eq!(expected, content);
+  }
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_reader_positions_drive_noodles() {
+  let data = data();
+  let path = write_temp("reader_to_noodles", &make_bgzf(&data, 100)).unwrap();
+  let reader = BgzfReader::new(path.clone()).unwrap();
+  let mut noodles_reader = noodles_bgzf::io::Reader::new(File::open(&path).unwrap());
+  for &offset in OFFSETS.iter() {
+    reader.seek(offset);
+    let position = reader.tell_virtual_position();
+    assert_eq!(u64::from(position), reader.tell_virtual());
+    assert_eq!(position, VirtualPosition::from(reader.tell_virtual()));
+
+    assert_eq!(position, noodles_reader.seek(position).unwrap());
+    let mut content = vec![0; 10.min(data.len() - offset as usize)];
+    noodles_reader.read_exact(&mut content).unwrap();
+    assert_eq!(&data[offset as usize..][..content.len()], &content[..]);
+  }
+  fs::remove_file(path).unwrap();
+}
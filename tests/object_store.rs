@@ -0,0 +1,115 @@
+//Reads of bgzf files held by the in-memory implementation of object_store,
+//through its get_range, the way they are read from S3, GCS or Azure.
+#![cfg(feature = "object-store")]
+
+use bgzf_rust_reader::fixtures::make_bgzf;
+use bgzf_rust_reader::{BgzfError, BgzfReader, ObjectStoreTransport};
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::io;
+use std::sync::Arc;
+
+/// About 1.7 MB of lines, which in blocks of 10000 bytes compress to a
+/// dozen chunks of 64 KiB
+fn content() -> Vec<u8> {
+  let mut content = Vec::new();
+  let mut state: u32 = 1;
+  for i in 0..80000 {
+    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+    content.extend_from_slice(format!("{}\tchr{}\t{:08x}\n", i, i % 23, state).as_bytes());
+  }
+  content
+}
+
+/// Returns a store holding `data` at `location`
+fn store_of(location: &Path, data: Vec<u8>) -> Arc<InMemory> {
+  let store = Arc::new(InMemory::new());
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .build()
+    .unwrap();
+  runtime.block_on(store.put(location, data.into())).unwrap();
+  store
+}
+
+fn read_region(reader: &BgzfReader, start: u64, length: usize) -> Vec<u8> {
+  let mut buf = vec![0; length];
+  reader.read_at(start, &mut buf).unwrap();
+  buf
+}
+
+#[test]
+fn test_opens_with_gzi_and_reads_regions() {
+  let content = content();
+  let bgzf = make_bgzf(&content, 10000);
+  let mut gzi = Vec::new();
+  BgzfReader::from_bytes(bgzf.clone())
+    .unwrap()
+    .write_gzi(&mut gzi)
+    .unwrap();
+  let location = Path::from("reads/data.bgz");
+  let store = store_of(&location, bgzf.clone());
+
+  let transport = ObjectStoreTransport::new(store, location).unwrap();
+  let reader = BgzfReader::builder()
+    .fetch_size(64 * 1024)
+    .open_remote_with_gzi(transport, &gzi)
+    .unwrap();
+  assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+  assert_eq!(
+    &content[20000..20100],
+    &read_region(&reader, 20000, 100)[..]
+  );
+  //the first chunk, the last one and the one read, of more than ten
+  assert!(
+    reader.fetch_stats().misses <= 3,
+    "{:?}",
+    reader.fetch_stats()
+  );
+  assert!(bgzf.len() > 10 * 64 * 1024);
+
+  //a region across blocks and chunks, then all of it
+  assert_eq!(
+    &content[123456..223456],
+    &read_region(&reader, 123456, 100000)[..]
+  );
+  assert_eq!(content, reader.decompress_all_parallel(2).unwrap());
+}
+
+#[test]
+fn test_reads_from_blocking_threads_of_a_runtime() {
+  let content = content();
+  let location = Path::from("data.bgz");
+  let store = store_of(&location, make_bgzf(&content, 10000));
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .build()
+    .unwrap();
+  let read = runtime.block_on(async move {
+    //made within the runtime, its requests run there
+    let transport = ObjectStoreTransport::new(store, location).unwrap();
+    tokio::task::spawn_blocking(move || {
+      let reader = BgzfReader::builder().open_remote(transport).unwrap();
+      read_region(&reader, 500000, 1000)
+    })
+    .await
+    .unwrap()
+  });
+  assert_eq!(&content[500000..501000], &read[..]);
+}
+
+#[test]
+fn test_object_store_errors() {
+  let location = Path::from("data.bgz");
+  let store = store_of(&location, make_bgzf(b"some data", 100));
+  let transport = ObjectStoreTransport::new(store.clone(), Path::from("missing.bgz")).unwrap();
+  let error = BgzfReader::builder().open_remote(transport).err().unwrap();
+  assert!(
+    matches!(&error, BgzfError::Io(error) if error.kind() == io::ErrorKind::NotFound),
+    "{:?}",
+    error
+  );
+
+  let transport = ObjectStoreTransport::new(store, location).unwrap();
+  let reader = BgzfReader::builder().open_remote(transport).unwrap();
+  assert_eq!(b"some data", &read_region(&reader, 0, 9)[..]);
+}
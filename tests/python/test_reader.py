@@ -0,0 +1,127 @@
+"""Tests of the Python module, run with pytest after `maturin develop`:
+
+    maturin develop --features python
+    pytest tests/python
+"""
+
+import io
+import os
+import pathlib
+
+import pytest
+
+from bgzf_rust_reader import BgzfReader
+
+ROOT = pathlib.Path(__file__).resolve().parents[2]
+TEST_FILE = ROOT / "bgzf_test.bgz"
+VCF_FILE = ROOT / "tests" / "data" / "variants.vcf.gz"
+CONTENT = (ROOT / "bgzf_test").read_bytes()
+
+
+def test_read_seek_tell():
+    with BgzfReader(str(TEST_FILE)) as reader:
+        assert len(reader) == len(CONTENT) == 280
+        assert reader.read(4) == b"This"
+        assert reader.tell() == 4
+        assert reader.seek(8) == 8
+        assert reader.read(4) == b"just"
+        assert reader.seek(-4, io.SEEK_CUR) == 8
+        assert reader.seek(-10, io.SEEK_END) == 270
+        assert reader.read() == CONTENT[270:]
+        assert reader.read(10) == b""
+        assert reader.seek(1000) == 1000
+        assert reader.read() == b""
+
+
+def test_constructors():
+    from_path = BgzfReader(TEST_FILE)
+    from_str = BgzfReader(os.fspath(TEST_FILE))
+    from_bytes = BgzfReader(TEST_FILE.read_bytes())
+    from_bytearray = BgzfReader(bytearray(TEST_FILE.read_bytes()))
+    for reader in [from_path, from_str, from_bytes, from_bytearray]:
+        assert reader.read() == CONTENT
+        reader.seek(5)
+        assert reader.read(2) == b"is"
+        reader.close()
+
+
+def test_readline_and_iteration():
+    with BgzfReader(VCF_FILE) as reader:
+        lines = list(reader)
+    with BgzfReader(VCF_FILE) as reader:
+        data = reader.read()
+    assert lines == data.splitlines(keepends=True)
+    assert lines[0] == b"##fileformat=VCFv4.3\n"
+
+    with BgzfReader(VCF_FILE) as reader:
+        assert reader.readline() == lines[0]
+        assert reader.readline(5) == lines[1][:5]
+        assert reader.readline() == lines[1][5:]
+        assert reader.readlines() == lines[2:]
+        assert reader.readline() == b""
+
+
+def test_readinto():
+    with BgzfReader(TEST_FILE) as reader:
+        buffer = bytearray(10)
+        assert reader.readinto(buffer) == 10
+        assert buffer == CONTENT[:10]
+        view = memoryview(bytearray(300))
+        assert reader.readinto(view) == 270
+        assert view[:270] == CONTENT[10:]
+        assert reader.readinto(buffer) == 0
+
+
+def test_raw_io_protocol():
+    reader = BgzfReader(TEST_FILE)
+    assert isinstance(reader, io.RawIOBase)
+    assert reader.readable() and reader.seekable() and not reader.writable()
+    with pytest.raises(io.UnsupportedOperation):
+        reader.fileno()
+    buffered = io.BufferedReader(reader, buffer_size=16)
+    assert buffered.read(4) == b"This"
+    assert buffered.peek(1)[:1] == b" "
+    text = io.TextIOWrapper(io.BufferedReader(BgzfReader(VCF_FILE)), encoding="utf-8")
+    assert text.readline() == "##fileformat=VCFv4.3\n"
+    assert sum(1 for line in text if not line.startswith("#")) == 700
+    text.close()
+
+
+def test_text_io_wrapper_directly():
+    text = io.TextIOWrapper(BgzfReader(TEST_FILE), encoding="utf-8")
+    assert text.read() == CONTENT.decode()
+
+
+def test_pandas_read_csv():
+    pandas = pytest.importorskip("pandas")
+    with BgzfReader(VCF_FILE) as reader:
+        frame = pandas.read_csv(reader, sep="\t", comment="#", header=None)
+    assert frame.shape == (700, 8)
+    assert set(frame[0]) == {"chr1", "chr2"}
+
+
+def test_close():
+    reader = BgzfReader(TEST_FILE)
+    with reader:
+        pass
+    assert reader.closed
+    for call in [reader.read, reader.tell, reader.readline, lambda: len(reader)]:
+        with pytest.raises(ValueError):
+            call()
+    reader.close()
+
+
+def test_errors():
+    with pytest.raises(FileNotFoundError):
+        BgzfReader(ROOT / "no_such_file.bgz")
+    with pytest.raises(OSError):
+        BgzfReader(ROOT / "bgzf_test")
+    with pytest.raises(OSError):
+        BgzfReader(b"not bgzf data")
+    with pytest.raises(TypeError):
+        BgzfReader(42)
+    with BgzfReader(TEST_FILE) as reader:
+        with pytest.raises(ValueError):
+            reader.seek(-1)
+        with pytest.raises(ValueError):
+            reader.seek(0, 3)
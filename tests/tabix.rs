@@ -0,0 +1,138 @@
+//Region queries of tests/data/variants.vcf.gz, a VCF of 700 lines on chr1
+//and chr2 with short variants and deletions up to 60kb long given by END,
+//written in blocks of 4096 bytes. Its .tbi was written by the tabix indexer
+//of noodles-vcf 0.94 (`vcf::fs::index`), the lines each query returns are
+//checked against a scan of the whole file.
+#![cfg(feature = "tabix")]
+
+use bgzf_rust_reader::{BgzfError, BgzfReader, TabixError, TabixReader};
+
+const DATA_PATH: &str = "tests/data/variants.vcf.gz";
+
+/// Returns the lines of the fixture overlapping the 0-based, half-open
+/// `start..end` of `reference`, found by reading every line
+fn scan(reference: &str, start: u64, end: u64) -> Vec<String> {
+  let data = BgzfReader::new(String::from(DATA_PATH))
+    .unwrap()
+    .decompress_all_parallel(1)
+    .unwrap();
+  String::from_utf8(data)
+    .unwrap()
+    .lines()
+    .filter(|line| !line.starts_with('#'))
+    .filter(|line| {
+      let columns: Vec<_> = line.split('\t').collect();
+      let line_start = columns[1].parse::<u64>().unwrap() - 1;
+      let line_end = match columns[7].strip_prefix("SVTYPE=DEL;END=") {
+        Some(end) => end.parse::<u64>().unwrap(),
+        None => line_start + columns[3].len() as u64,
+      };
+      columns[0] == reference && line_start < end && line_end > start
+    })
+    .map(String::from)
+    .collect()
+}
+
+fn query(reader: &TabixReader, reference: &str, start: u64, end: u64) -> Vec<String> {
+  reader
+    .query(reference, start, end)
+    .unwrap()
+    .collect::<Result<_, _>>()
+    .unwrap()
+}
+
+#[test]
+fn test_queries_match_scan() {
+  let reader = TabixReader::open(String::from(DATA_PATH)).unwrap();
+  let mut regions = vec![
+    ("chr1", 0, 1 << 29),
+    ("chr2", 0, 1 << 29),
+    ("chr1", 0, 1),
+    ("chr1", 16383, 16385),
+    ("chr2", 499_000, 600_000),
+    ("chr1", 2_100_000, 3_000_000),
+  ];
+  //windows of every size along both references, from a base to 256kb
+  for shift in [0, 10, 14, 17, 18] {
+    for i in 0..40u64 {
+      let start = i * 52_361 % 2_050_000;
+      regions.push(("chr1", start, start + (1 << shift)));
+      regions.push(("chr2", start / 4, start / 4 + (1 << shift)));
+    }
+  }
+  let mut found = 0;
+  for (reference, start, end) in regions {
+    let expected = scan(reference, start, end);
+    assert_eq!(
+      expected,
+      query(&reader, reference, start, end),
+      "{}:{}-{}",
+      reference,
+      start,
+      end
+    );
+    found += expected.len();
+  }
+  assert!(found > 1000);
+}
+
+#[test]
+fn test_long_deletions_overlap_later_windows() {
+  let reader = TabixReader::open(String::from(DATA_PATH)).unwrap();
+  let deletions = scan("chr1", 0, 1 << 29)
+    .into_iter()
+    .filter(|line| line.contains("END="))
+    .collect::<Vec<_>>();
+  assert!(!deletions.is_empty());
+  for deletion in deletions {
+    let columns: Vec<_> = deletion.split('\t').collect();
+    let end = columns[7]["SVTYPE=DEL;END=".len()..]
+      .parse::<u64>()
+      .unwrap();
+    //the last base of the deletion only
+    assert!(query(&reader, "chr1", end - 1, end).contains(&deletion));
+    assert!(!query(&reader, "chr1", end, end + 1).contains(&deletion));
+  }
+}
+
+#[test]
+fn test_query_region() {
+  let reader = TabixReader::open(String::from(DATA_PATH)).unwrap();
+  let lines = |region: &str| {
+    reader
+      .query_region(region)
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap()
+  };
+  assert_eq!(scan("chr1", 99_999, 200_000), lines("chr1:100,000-200,000"));
+  assert_eq!(scan("chr2", 249_999, 1 << 29), lines("chr2:250000"));
+  assert_eq!(scan("chr2", 0, 1 << 29), lines("chr2"));
+  assert!(matches!(
+    reader.query_region("chr1:200-100"),
+    Err(BgzfError::Tabix(TabixError::InvalidRegion { .. }))
+  ));
+  assert!(matches!(
+    reader.query_region("chr3:1-100"),
+    Err(BgzfError::Tabix(TabixError::UnknownReference { .. }))
+  ));
+}
+
+#[test]
+fn test_index_header() {
+  let reader = TabixReader::open(String::from(DATA_PATH)).unwrap();
+  let header = reader.index().header();
+  assert_eq!(
+    (2, 1, 2, b'#'),
+    (
+      header.format,
+      header.sequence_column,
+      header.begin_column,
+      header.meta
+    )
+  );
+  assert!(matches!(
+    TabixReader::open_with_index(String::from(DATA_PATH), String::from(DATA_PATH)),
+    Err(BgzfError::Tabix(TabixError::NotTabix))
+  ));
+}
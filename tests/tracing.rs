@@ -0,0 +1,203 @@
+//The tracing spans and events of a scripted read pattern, collected by a
+//subscriber recording them as text, keeping the instrumentation from
+//rotting.
+#![cfg(feature = "tracing")]
+
+use bgzf_rust_reader::fixtures::make_bgzf;
+use bgzf_rust_reader::BgzfReader;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Fields of a span or an event, in the order they were recorded
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl Visit for Fields {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    self.0.push((field.name().to_string(), value.to_string()));
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+    self
+      .0
+      .push((field.name().to_string(), format!("{:?}", value)));
+  }
+}
+
+impl Fields {
+  /// The fields as "name=value" separated by spaces, the message first and
+  /// without its name, the durations, which vary, left out
+  fn line(&self) -> String {
+    self
+      .0
+      .iter()
+      .filter(|(name, _value)| name != "duration_us")
+      .map(|(name, value)| match name.as_str() {
+        "message" => value.clone(),
+        _ => format!("{}={}", name, value),
+      })
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  fn get(&self, name: &str) -> Option<&str> {
+    self
+      .0
+      .iter()
+      .find(|(field, _value)| field == name)
+      .map(|(_field, value)| value.as_str())
+  }
+}
+
+/// What the subscriber saw: the spans by id, with their name, and the events
+#[derive(Default)]
+struct Collected {
+  spans: Vec<(&'static str, Fields)>,
+  events: Vec<Fields>,
+}
+
+#[derive(Clone, Default)]
+struct Collector(Arc<Mutex<Collected>>);
+
+impl Subscriber for Collector {
+  fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+    true
+  }
+
+  fn new_span(&self, span: &Attributes<'_>) -> Id {
+    let mut fields = Fields::default();
+    span.record(&mut fields);
+    let mut collected = self.0.lock().unwrap();
+    collected.spans.push((span.metadata().name(), fields));
+    Id::from_u64(collected.spans.len() as u64)
+  }
+
+  fn record(&self, span: &Id, values: &Record<'_>) {
+    let mut collected = self.0.lock().unwrap();
+    values.record(&mut collected.spans[span.into_u64() as usize - 1].1);
+  }
+
+  fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+  fn event(&self, event: &Event<'_>) {
+    let mut fields = Fields::default();
+    event.record(&mut fields);
+    self.0.lock().unwrap().events.push(fields);
+  }
+
+  fn enter(&self, _span: &Id) {}
+
+  fn exit(&self, _span: &Id) {}
+}
+
+/// Runs `f` with the collector as the subscriber of the thread, and returns
+/// what it collected
+fn collect(f: impl FnOnce()) -> Collected {
+  let collector = Collector::default();
+  tracing::subscriber::with_default(collector.clone(), f);
+  let collected = std::mem::take(&mut *collector.0.lock().unwrap());
+  collected
+}
+
+#[test]
+fn test_events_of_a_read_pattern() {
+  let content: Vec<u8> = (0..300u32).map(|i| (i * 7 % 251) as u8).collect();
+  let bgzf = make_bgzf(&content, 100);
+  let mut reader = None;
+  let opening = collect(|| reader = Some(BgzfReader::from_bytes(bgzf.clone()).unwrap()));
+  let reader = reader.unwrap();
+  assert_eq!(1, opening.spans.len());
+  assert_eq!("index_scan", opening.spans[0].0);
+  assert_eq!(Some("3"), opening.spans[0].1.get("blocks"));
+  assert!(opening
+    .events
+    .iter()
+    .all(|event| event.line().starts_with("source read")));
+  assert!(!opening.events.is_empty());
+
+  let blocks = reader.index().unwrap().blocks().to_vec();
+  let mut buf = vec![0; 100];
+  let reading = collect(|| {
+    //the first block, again from the cache, the last one, then the whole
+    //first one again, which the cache no longer holds, straight into buf
+    reader.read_at(10, &mut buf[..20]).unwrap();
+    reader.read_at(50, &mut buf[..20]).unwrap();
+    reader.read_at(250, &mut buf[..10]).unwrap();
+    reader.read_at(0, &mut buf).unwrap();
+  });
+  assert_eq!(&content[..100], &buf[..]);
+  assert!(reading.spans.is_empty());
+
+  let decompressed = |block: usize| {
+    format!(
+      "block decompressed block_offset={} compressed_size={} uncompressed_size=100",
+      blocks[block].compressed_offset, blocks[block].block_size
+    )
+  };
+  let source_read = |block: usize| {
+    format!(
+      "source read offset={} length={}",
+      blocks[block].compressed_offset, blocks[block].block_size
+    )
+  };
+  let lines = reading.events.iter().map(Fields::line).collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      source_read(0),
+      decompressed(0),
+      "cache miss cache=blocks offset=0".to_string(),
+      "cache hit cache=blocks offset=0".to_string(),
+      source_read(2),
+      decompressed(2),
+      "cache miss cache=blocks offset=200".to_string(),
+      "cache miss cache=blocks offset=0".to_string(),
+      source_read(0),
+      decompressed(0),
+    ],
+    lines
+  );
+  assert!(reading
+    .events
+    .iter()
+    .filter(|event| event.get("message") == Some("block decompressed"))
+    .all(|event| event.get("duration_us").is_some()));
+}
+
+#[test]
+fn test_events_of_the_compressed_cache() {
+  let content: Vec<u8> = (0..300u32).map(|i| (i * 7 % 251) as u8).collect();
+  let reader = BgzfReader::builder()
+    .compressed_cache_bytes(1 << 20)
+    .open_bytes(make_bgzf(&content, 100))
+    .unwrap();
+  let blocks = reader.index().unwrap().blocks().to_vec();
+  let mut buf = vec![0; 100];
+  let reading = collect(|| {
+    reader.read_at(0, &mut buf).unwrap();
+    reader.read_at(100, &mut buf).unwrap();
+    reader.read_at(0, &mut buf).unwrap();
+  });
+  let caches = reading
+    .events
+    .iter()
+    .filter(|event| event.get("cache") == Some("compressed"))
+    .map(Fields::line)
+    .collect::<Vec<_>>();
+  let compressed = |hit: &str, block: usize| {
+    format!(
+      "cache {} cache=compressed offset={}",
+      hit, blocks[block].compressed_offset
+    )
+  };
+  assert_eq!(
+    vec![
+      compressed("miss", 0),
+      compressed("miss", 1),
+      compressed("hit", 0)
+    ],
+    caches
+  );
+}
@@ -0,0 +1,63 @@
+//Reads bgzf data held in memory under wasm32-unknown-unknown, where there
+//is no filesystem, like in a browser. Run with the wasm-bindgen test runner
+//(`cargo install wasm-bindgen-cli`):
+//
+//CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner cargo test
+//--target wasm32-unknown-unknown --no-default-features --features rust-backend
+//--test wasm
+#![cfg(target_arch = "wasm32")]
+
+use bgzf_rust_reader::fixtures::make_bgzf;
+use bgzf_rust_reader::{BgzfError, BgzfReader};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// A VCF of 700 lines in blocks of 4096 bytes, as fetched by a browser
+const VARIANTS: &[u8] = include_bytes!("data/variants.vcf.gz");
+
+#[wasm_bindgen_test]
+fn test_index_and_read_a_region() {
+  let content = (0..20_000u32)
+    .flat_map(|i| format!("{}\tregion\n", i).into_bytes())
+    .collect::<Vec<_>>();
+  let reader = BgzfReader::from_bytes(make_bgzf(&content, 1000)).unwrap();
+  assert_eq!(content.len() as u64, reader.total_uncompressed_length());
+  assert_eq!(content.len().div_ceil(1000), reader.num_blocks());
+
+  //a region across blocks
+  let mut region = vec![0; 2500];
+  reader.seek(150_000);
+  assert_eq!(2500, reader.read_to(&mut region).unwrap());
+  assert_eq!(&content[150_000..152_500], &region[..]);
+  let voffset = reader.uoffset_to_voffset(150_000).unwrap();
+  assert_eq!(150_000, reader.voffset_to_uoffset(voffset).unwrap());
+}
+
+#[wasm_bindgen_test]
+fn test_read_a_fixture() {
+  let reader = BgzfReader::builder()
+    .verify_crc(true)
+    .open_bytes(VARIANTS.to_vec())
+    .unwrap();
+  assert!(reader.num_blocks() > 1);
+  let mut all = vec![0; reader.total_uncompressed_length() as usize];
+  assert_eq!(all.len(), reader.read_to(&mut all).unwrap());
+  assert!(all.starts_with(b"##fileformat=VCF"));
+
+  let mut line = vec![0; 100];
+  reader.seek(5000);
+  reader.read_to(&mut line).unwrap();
+  assert_eq!(&all[5000..5100], &line[..]);
+}
+
+#[wasm_bindgen_test]
+fn test_no_files() {
+  //opening a path fails rather than panicking
+  assert!(matches!(
+    BgzfReader::new(String::from("bgzf_test.bgz")),
+    Err(BgzfError::Io(_))
+  ));
+  assert!(matches!(
+    BgzfReader::from_bytes(b"not bgzf".to_vec()),
+    Err(BgzfError::NotBgzf { .. })
+  ));
+}